@@ -0,0 +1,211 @@
+//! Throughput of reading, writing, position compression, movelist coding and
+//! move generation.
+//!
+//! ```text
+//! cargo bench --bench binpack -- --save-baseline main
+//! cargo bench --bench binpack -- --baseline main [FILTER]
+//! ```
+//!
+//! The data is generated from seeded random games, so every run and every
+//! machine works on the same entries.
+
+mod harness;
+
+use std::{hint::black_box, io::Cursor, process::ExitCode};
+
+use harness::Runner;
+use sfbinpack::{
+    chess::{attacks, position::Position},
+    CompressedPosition, CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    TrainingDataEntry, WriterOptions,
+};
+
+/// Xorshift, enough to pick moves and scores
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+/// Random games of up to `max_plies` plies, every position followed by the
+/// move played from it
+fn games(count: usize, max_plies: usize, seed: u64) -> Vec<TrainingDataEntry> {
+    let mut rng = Rng(seed);
+    let mut entries = Vec::new();
+
+    for _ in 0..count {
+        let mut pos = Position::new();
+        let mut result = rng.below(3) as i16 - 1;
+
+        for ply in 0..max_plies {
+            let moves = attacks::legal_moves(&pos);
+            if moves.is_empty() {
+                break;
+            }
+
+            let mv = moves[rng.below(moves.len())];
+            entries.push(TrainingDataEntry {
+                pos,
+                mv,
+                score: rng.below(601) as i16 - 300,
+                ply: ply as u16,
+                result,
+            });
+
+            pos.do_move(mv);
+            result = -result;
+        }
+    }
+
+    entries
+}
+
+fn write(entries: &[TrainingDataEntry]) -> Vec<u8> {
+    write_with(entries, WriterOptions::default())
+}
+
+fn write_with(entries: &[TrainingDataEntry], options: WriterOptions) -> Vec<u8> {
+    let mut writer =
+        CompressedTrainingDataEntryWriter::with_options(Cursor::new(Vec::new()), options).unwrap();
+    for entry in entries {
+        writer.write_entry(entry).unwrap();
+    }
+    writer.flush_and_end();
+    writer.into_inner().unwrap().into_inner()
+}
+
+fn read(data: &[u8]) -> u64 {
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+    let mut score = 0i64;
+    while reader.has_next() {
+        score += reader.next().score as i64;
+    }
+    score as u64
+}
+
+fn read_into(data: &[u8]) -> u64 {
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+    let mut entry = TrainingDataEntry::default();
+    let mut score = 0i64;
+    while reader.has_next() {
+        reader.next_into(&mut entry);
+        score += entry.score as i64;
+    }
+    score as u64
+}
+
+fn read_batch(data: &[u8]) -> u64 {
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+    let mut batch = Vec::new();
+    let mut score = 0i64;
+    while reader.next_batch(&mut batch, 1024) > 0 {
+        score += batch.iter().map(|entry| entry.score as i64).sum::<i64>();
+    }
+    score as u64
+}
+
+fn scan_scores(data: &[u8]) -> u64 {
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+    let mut score = 0i64;
+    reader.scan_scores(|record| score += record.score as i64);
+    score as u64
+}
+
+fn scan_metadata(data: &[u8]) -> u64 {
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+    let mut plies = 0u64;
+    reader.scan_metadata(|chain| plies += 1 + chain.plies as u64);
+    plies
+}
+
+fn perft(pos: &Position, depth: u32) -> u64 {
+    let moves = attacks::legal_moves(pos);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    moves
+        .iter()
+        .map(|&mv| perft(&pos.after_move(mv), depth - 1))
+        .sum()
+}
+
+fn main() -> ExitCode {
+    let mut runner = Runner::from_args();
+
+    // short games with many chunks of small movelists, and long games where
+    // the movelists make up most of the data
+    let entries = games(2000, 100, 0x2545_F491_4F6C_DD1D);
+    let long_games = games(100, 400, 0x9E37_79B9_7F4A_7C15);
+    let data = write(&entries);
+    let long_data = write(&long_games);
+    let count = entries.len() as u64;
+
+    runner.bench("read/next", count, || read(&data));
+    runner.bench("read/next_into", count, || read_into(&data));
+    runner.bench("read/next_batch", count, || read_batch(&data));
+    runner.bench("read/scan_scores", count, || scan_scores(&data));
+    runner.bench("read/scan_metadata", count, || scan_metadata(&data));
+    runner.bench("write", count, || write(&entries));
+
+    // every other position dropped, all stems
+    let sparse: Vec<_> = entries.iter().step_by(2).copied().collect();
+    let sparse_data = write(&sparse);
+    let sparse_count = sparse.len() as u64;
+    runner.bench("read/sparse/next", sparse_count, || read(&sparse_data));
+    runner.bench("read/sparse/scan_scores", sparse_count, || {
+        scan_scores(&sparse_data)
+    });
+
+    // the games one position at a time, as after shuffling, so every entry
+    // is a stem and, when counted, is checked for a broken chain
+    let stems: Vec<_> = (0..entries.len())
+        .map(|idx| entries[idx * 7919 % entries.len()])
+        .collect();
+    let counting = WriterOptions {
+        count_broken_chains: true,
+        ..WriterOptions::default()
+    };
+    runner.bench("write/stems", count, || write(&stems));
+    runner.bench("write/stems/count_broken_chains", count, || {
+        write_with(&stems, counting)
+    });
+
+    let positions: Vec<Position> = entries.iter().take(10_000).map(|entry| entry.pos).collect();
+    let compressed: Vec<CompressedPosition> =
+        positions.iter().map(CompressedPosition::compress).collect();
+    let batch = positions.len() as u64;
+
+    runner.bench("position/compress", batch, || {
+        for pos in &positions {
+            black_box(CompressedPosition::compress(pos));
+        }
+    });
+    runner.bench("position/decompress", batch, || {
+        for pos in &compressed {
+            black_box(pos.decompress());
+        }
+    });
+
+    let moves = long_games.len() as u64;
+    runner.bench("movelist/encode", moves, || write(&long_games));
+    runner.bench("movelist/decode", moves, || read_into(&long_data));
+
+    let startpos = Position::new();
+    let kiwipete =
+        Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    runner.bench("perft/startpos/4", 197_281, || perft(&startpos, 4));
+    runner.bench("perft/kiwipete/3", 97_862, || perft(&kiwipete, 3));
+
+    runner.finish()
+}
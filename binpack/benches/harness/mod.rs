@@ -0,0 +1,201 @@
+//! A small benchmark runner in the spirit of criterion, without its
+//! dependencies.
+//!
+//! Every benchmark is warmed up, then timed in a number of samples and
+//! reported with the median time per iteration and its throughput. The
+//! arguments after `cargo bench --` select and compare runs:
+//!
+//! - `FILTER` runs only the benchmarks whose name contains it
+//! - `--save-baseline NAME` stores the results under NAME
+//! - `--baseline NAME` compares against the results stored under NAME and
+//!   exits with status 1 if a benchmark got slower by more than the threshold
+//! - `--threshold PERCENT` sets that threshold, 10 by default
+//!
+//! Baselines are kept in the target directory of the build.
+
+use std::{
+    collections::HashMap,
+    fs,
+    hint::black_box,
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+const WARM_UP: Duration = Duration::from_millis(500);
+const MEASUREMENT: Duration = Duration::from_secs(2);
+const SAMPLES: u32 = 20;
+
+#[derive(Debug)]
+pub struct Runner {
+    filter: Option<String>,
+    save: Option<String>,
+    baseline: Option<(String, HashMap<String, f64>)>,
+    threshold: f64,
+    results: Vec<(String, f64)>,
+    regressions: Vec<String>,
+}
+
+impl Runner {
+    pub fn from_args() -> Self {
+        let mut runner = Self {
+            filter: None,
+            save: None,
+            baseline: None,
+            threshold: 10.0,
+            results: Vec::new(),
+            regressions: Vec::new(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save-baseline" => runner.save = args.next(),
+                "--baseline" => {
+                    let name = args.next().expect("--baseline needs a name");
+                    let results = load_baseline(&name);
+                    runner.baseline = Some((name, results));
+                }
+                "--threshold" => {
+                    runner.threshold = args
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .expect("--threshold needs a percentage");
+                }
+                // passed by cargo bench
+                "--bench" => {}
+                _ if arg.starts_with("--") => panic!("unknown argument {}", arg),
+                _ => runner.filter = Some(arg),
+            }
+        }
+
+        runner
+    }
+
+    /// Times `f`, which processes `elements` entries, positions or nodes
+    /// per call
+    pub fn bench<R>(&mut self, name: &str, elements: u64, mut f: impl FnMut() -> R) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            return;
+        }
+
+        // the warm up also tells how many iterations fit in a sample
+        let start = Instant::now();
+        let mut iterations = 0u64;
+        while start.elapsed() < WARM_UP {
+            black_box(f());
+            iterations += 1;
+        }
+        let per_iteration = start.elapsed().as_secs_f64() / iterations as f64;
+        let per_sample = MEASUREMENT.as_secs_f64() / SAMPLES as f64;
+        let sample_iterations = ((per_sample / per_iteration) as u64).max(1);
+
+        let mut samples: Vec<f64> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..sample_iterations {
+                    black_box(f());
+                }
+                start.elapsed().as_nanos() as f64 / sample_iterations as f64
+            })
+            .collect();
+        samples.sort_by(f64::total_cmp);
+        let median = samples[samples.len() / 2];
+
+        let mut line = format!(
+            "{:<32} time: {:>12}   thrpt: {:>14}",
+            name,
+            format_time(median),
+            format_throughput(elements, median)
+        );
+
+        if let Some(before) = self
+            .baseline
+            .as_ref()
+            .and_then(|(_, results)| results.get(name))
+        {
+            let change = (median - before) / before * 100.0;
+            line += &format!("   {:+.1}%", change);
+
+            if change > self.threshold {
+                line += " (regressed)";
+                self.regressions.push(name.to_string());
+            }
+        }
+
+        println!("{}", line);
+        self.results.push((name.to_string(), median));
+    }
+
+    /// Saves the results if asked to, fails if a benchmark regressed
+    pub fn finish(self) -> ExitCode {
+        if let Some(name) = &self.save {
+            let path = baseline_path(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+            let contents: String = self
+                .results
+                .iter()
+                .map(|(bench, time)| format!("{}\t{}\n", bench, time))
+                .collect();
+            fs::write(&path, contents).unwrap();
+            println!("saved baseline {} to {}", name, path.display());
+        }
+
+        if self.regressions.is_empty() {
+            return ExitCode::SUCCESS;
+        }
+
+        let (name, _) = self.baseline.as_ref().unwrap();
+        println!(
+            "{} benchmarks regressed by more than {}% against {}: {}",
+            self.regressions.len(),
+            self.threshold,
+            name,
+            self.regressions.join(", ")
+        );
+        ExitCode::FAILURE
+    }
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("baselines")
+        .join(format!("{}.tsv", name))
+}
+
+fn load_baseline(name: &str) -> HashMap<String, f64> {
+    let path = baseline_path(name);
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("cannot read baseline {}: {}", path.display(), e));
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (bench, time) = line.split_once('\t')?;
+            Some((bench.to_string(), time.parse().ok()?))
+        })
+        .collect()
+}
+
+fn format_time(nanos: f64) -> String {
+    match nanos {
+        n if n < 1e3 => format!("{:.2} ns", n),
+        n if n < 1e6 => format!("{:.2} µs", n / 1e3),
+        n if n < 1e9 => format!("{:.2} ms", n / 1e6),
+        n => format!("{:.2} s", n / 1e9),
+    }
+}
+
+fn format_throughput(elements: u64, nanos: f64) -> String {
+    match elements as f64 / (nanos / 1e9) {
+        n if n < 1e3 => format!("{:.2} elem/s", n),
+        n if n < 1e6 => format!("{:.2} Kelem/s", n / 1e3),
+        n if n < 1e9 => format!("{:.2} Melem/s", n / 1e6),
+        n => format!("{:.2} Gelem/s", n / 1e9),
+    }
+}
@@ -1,7 +1,7 @@
 use std::fs::{OpenOptions, read_dir};
 use std::path::{Path, PathBuf};
 use std::env;
-use sfbinpack::{CompressedTrainingDataEntryReader, CompressedReaderError};
+use sfbinpack::CompressedTrainingDataEntryReader;
 
 fn collect_binpack_files(root: &Path, out: &mut Vec<PathBuf>) {
     if root.is_dir() {
@@ -58,14 +58,7 @@ fn main() {
                         total_count += count;
                     }
                     Err(e) => {
-                        match e {
-                            CompressedReaderError::EndOfFile => {
-                                println!("No chunks in file {} (Empty)", path.display());
-                            }
-                            other => {
-                                println!("Could not read {}: {}", path.display(), other);
-                            }
-                        }
+                        println!("Could not read {}: {}", path.display(), e);
                     }
                 }
             }
@@ -0,0 +1,16 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use sfbinpack::CompressedTrainingDataEntryReader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut reader) = CompressedTrainingDataEntryReader::new(Cursor::new(data)) else {
+        return;
+    };
+
+    while reader.has_next() {
+        let _ = reader.try_next();
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sfbinpack::fuzzing::{self, PackedTrainingDataEntry};
+
+fuzz_target!(|data: [u8; 32]| {
+    let entry = PackedTrainingDataEntry { data }.unpack_entry();
+    let _ = entry.to_string();
+
+    if fuzzing::check_position(&entry.pos).is_ok() && fuzzing::is_legal(&entry.pos, entry.mv) {
+        let _ = entry.pos.after_move(entry.mv);
+    }
+});
@@ -0,0 +1,29 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sfbinpack::fuzzing::{self, BitReader, PackedMoveScoreListReader, PackedTrainingDataEntry};
+
+fuzz_target!(|input: ([u8; 32], u8, &[u8])| {
+    let (stem, num_plies, movetext) = input;
+
+    let mut bits = BitReader::new();
+    while bits.num_read_bytes() <= movetext.len() {
+        bits.extract_bits_le8(movetext, 7);
+        bits.extract_vle16(movetext, 4);
+    }
+
+    // the chain the way the checked reader walks it: the stem and every
+    // move are checked before the next entry is decoded from them
+    let stem = PackedTrainingDataEntry { data: stem }.unpack_entry();
+    if fuzzing::check_position(&stem.pos).is_err() || !fuzzing::is_legal(&stem.pos, stem.mv) {
+        return;
+    }
+
+    let mut reader = PackedMoveScoreListReader::new(stem, num_plies as u16);
+    while reader.has_next() {
+        let entry = reader.next_entry(movetext);
+        if reader.is_corrupt() || !fuzzing::is_legal(&entry.pos, entry.mv) {
+            break;
+        }
+    }
+});
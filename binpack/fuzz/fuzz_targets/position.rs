@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sfbinpack::{chess::attacks, fuzzing, CompressedPosition};
+
+fuzz_target!(|data: [u8; 24]| {
+    let compressed = CompressedPosition::read_from_big_endian(&data);
+    let pos = compressed.decompress();
+    let _ = pos.fen();
+
+    // the checked decode only adds errors, never changes the position
+    if let Ok(checked) = compressed.try_decompress() {
+        assert_eq!(checked, pos);
+    }
+
+    // positions the checked reader accepts must be safe to play on
+    if fuzzing::check_position(&pos).is_ok() {
+        for mv in attacks::legal_moves(&pos) {
+            let next = pos.after_move(mv);
+            assert_eq!(fuzzing::check_position(&next), Ok(()));
+        }
+    }
+});
@@ -1,7 +1,11 @@
 use numpy::{ndarray::Array2, IntoPyArray, PyArray1};
-use pyo3::{prelude::*, types::PyTuple};
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyTuple},
+};
 use sfbinpack::{
-    chess::{color::Color, coords::Square, piece::Piece, piecetype::PieceType},
+    chess::color::Color,
+    features::{self, halfka::HalfKAv2Hm, halfkp::HalfKP},
     TrainingDataEntry,
 };
 
@@ -10,21 +14,78 @@ use crate::error::LoaderError;
 #[derive(Clone, Copy)]
 pub enum FeatureSet {
     HalfKP,
+    /// HalfKP followed by the virtual K and P factor features
+    HalfKPFactorized,
+    HalfKAv2Hm,
+    /// HalfKAv2_hm followed by the virtual A factor features
+    HalfKAv2HmFactorized,
 }
 
 impl FeatureSet {
     pub fn try_from_name(name: &str) -> Result<Self, LoaderError> {
         match name {
             "HalfKP" => Ok(FeatureSet::HalfKP),
+            "HalfKP^" => Ok(FeatureSet::HalfKPFactorized),
+            "HalfKAv2_hm" => Ok(FeatureSet::HalfKAv2Hm),
+            "HalfKAv2_hm^" => Ok(FeatureSet::HalfKAv2HmFactorized),
             other => Err(LoaderError::UnsupportedFeatureSet(other.to_string())),
         }
     }
 
-    pub fn max_active_features(&self) -> usize {
+    /// The core feature set that computes the indices
+    fn features(&self) -> &'static dyn features::FeatureSet {
+        static HALFKP: HalfKP = HalfKP::new();
+        static HALFKP_FACTORIZED: HalfKP = HalfKP::new().factorized();
+        static HALFKA: HalfKAv2Hm = HalfKAv2Hm::new();
+        static HALFKA_FACTORIZED: HalfKAv2Hm = HalfKAv2Hm::new().factorized();
+
         match self {
-            FeatureSet::HalfKP => HalfKPSparse::MAX_ACTIVE_FEATURES,
+            FeatureSet::HalfKP => &HALFKP,
+            FeatureSet::HalfKPFactorized => &HALFKP_FACTORIZED,
+            FeatureSet::HalfKAv2Hm => &HALFKA,
+            FeatureSet::HalfKAv2HmFactorized => &HALFKA_FACTORIZED,
         }
     }
+
+    pub fn max_active_features(&self) -> usize {
+        self.features().max_active_features()
+    }
+
+    /// Number of real input features, virtual features are indexed after these
+    pub fn num_real_features(&self) -> usize {
+        self.features().num_features()
+    }
+
+    /// Number of virtual factor features, zero for unfactorized sets
+    pub fn num_virtual_features(&self) -> usize {
+        self.features().num_virtual_features()
+    }
+
+    fn fill_features(
+        &self,
+        entry: &TrainingDataEntry,
+        color: Color,
+        indices: &mut [i32],
+        values: &mut [f32],
+    ) {
+        self.features()
+            .fill_features(&entry.pos, color, indices, values);
+    }
+}
+
+/// Sizes of a feature set: `{"max_active_features", "num_real_features", "num_virtual_features"}`.
+///
+/// Virtual (factor) features are indexed after the real ones in the same
+/// index arrays, a trainer coalesces them into the real weights when
+/// exporting the network.
+#[pyfunction]
+pub fn feature_set_info(py: Python<'_>, name: &str) -> PyResult<PyObject> {
+    let feature_set = FeatureSet::try_from_name(name)?;
+    let info = PyDict::new(py);
+    info.set_item("max_active_features", feature_set.max_active_features())?;
+    info.set_item("num_real_features", feature_set.num_real_features())?;
+    info.set_item("num_virtual_features", feature_set.num_virtual_features())?;
+    Ok(info.into())
 }
 
 pub struct SparseBatchData {
@@ -39,10 +100,16 @@ pub struct SparseBatchData {
     black_values: Vec<f32>,
     psqt_indices: Vec<i32>,
     layer_stack_indices: Vec<i32>,
+    weight: Option<Vec<f32>>,
 }
 
 impl SparseBatchData {
-    pub fn from_entries(entries: Vec<TrainingDataEntry>, feature_set: FeatureSet) -> Self {
+    /// `weight` has an entry per entry of `entries` when weighting is on
+    pub fn from_entries(
+        entries: Vec<TrainingDataEntry>,
+        feature_set: FeatureSet,
+        weight: Option<Vec<f32>>,
+    ) -> Self {
         let size = entries.len();
         let max_active_features = feature_set.max_active_features();
 
@@ -65,7 +132,7 @@ impl SparseBatchData {
             score[i] = entry.score as f32;
 
             let piece_count = pos.occupied().count() as i32;
-            let bucket = ((piece_count - 1).max(0) / 4) as i32;
+            let bucket = (piece_count - 1).max(0) / 4;
             psqt_indices[i] = bucket;
             layer_stack_indices[i] = bucket;
 
@@ -80,22 +147,8 @@ impl SparseBatchData {
             black_slice.fill(-1);
             black_values_slice.fill(0.0);
 
-            match feature_set {
-                FeatureSet::HalfKP => {
-                    HalfKPSparse::fill_features(
-                        entry,
-                        Color::White,
-                        white_slice,
-                        white_values_slice,
-                    );
-                    HalfKPSparse::fill_features(
-                        entry,
-                        Color::Black,
-                        black_slice,
-                        black_values_slice,
-                    );
-                }
-            }
+            feature_set.fill_features(entry, Color::White, white_slice, white_values_slice);
+            feature_set.fill_features(entry, Color::Black, black_slice, black_values_slice);
         }
 
         Self {
@@ -110,6 +163,7 @@ impl SparseBatchData {
             black_values,
             psqt_indices,
             layer_stack_indices,
+            weight,
         }
     }
 
@@ -126,6 +180,7 @@ impl SparseBatchData {
             black_values,
             psqt_indices,
             layer_stack_indices,
+            weight,
         } = self;
 
         let them: Vec<f32> = is_white.iter().map(|v| 1.0 - *v).collect();
@@ -157,80 +212,25 @@ impl SparseBatchData {
         let psqt_tensor = PyArray1::from_vec(py, psqt_indices);
         let layer_stack_tensor = PyArray1::from_vec(py, layer_stack_indices);
 
-        let tuple = PyTuple::new(
-            py,
-            [
-                us_tensor.to_object(py),
-                them_tensor.to_object(py),
-                white_idx_tensor.to_object(py),
-                white_val_tensor.to_object(py),
-                black_idx_tensor.to_object(py),
-                black_val_tensor.to_object(py),
-                outcome_tensor.to_object(py),
-                score_tensor.to_object(py),
-                psqt_tensor.to_object(py),
-                layer_stack_tensor.to_object(py),
-            ],
-        );
-
-        Ok(tuple.into())
-    }
-}
-
-struct HalfKPSparse;
-
-impl HalfKPSparse {
-    pub const MAX_ACTIVE_FEATURES: usize = 32;
-
-    fn fill_features(
-        entry: &TrainingDataEntry,
-        color: Color,
-        indices: &mut [i32],
-        values: &mut [f32],
-    ) {
-        let pos = entry.pos;
-        let king_sq = pos.king_sq(color);
-        let king_bucket = Self::orient_square(color, king_sq);
-        let mut pieces = pos.occupied().bits() & !pos.pieces_bb_type(PieceType::King).bits();
-        let mut count = 0usize;
-
-        while pieces != 0 && count < indices.len() {
-            let sq_idx = pieces.trailing_zeros() as u32;
-            pieces &= pieces - 1;
-            let square = Square::new(sq_idx);
-            let piece = pos.piece_at(square);
-            if piece == Piece::none() {
-                continue;
-            }
-
-            let piece_type_idx = match piece.piece_type() {
-                PieceType::Pawn => 0,
-                PieceType::Knight => 1,
-                PieceType::Bishop => 2,
-                PieceType::Rook => 3,
-                PieceType::Queen => 4,
-                _ => continue,
-            };
-
-            let is_enemy = usize::from(piece.color() != color);
-            let square_idx = Self::orient_square(color, square);
-
-            let feature = king_bucket * 640
-                + is_enemy * 320
-                + piece_type_idx * 64
-                + square_idx;
-
-            indices[count] = feature as i32;
-            values[count] = 1.0;
-            count += 1;
+        let mut tensors = vec![
+            us_tensor.to_object(py),
+            them_tensor.to_object(py),
+            white_idx_tensor.to_object(py),
+            white_val_tensor.to_object(py),
+            black_idx_tensor.to_object(py),
+            black_val_tensor.to_object(py),
+            outcome_tensor.to_object(py),
+            score_tensor.to_object(py),
+            psqt_tensor.to_object(py),
+            layer_stack_tensor.to_object(py),
+        ];
+        if let Some(weight) = weight {
+            let weight_tensor = Array2::from_shape_vec((size, 1), weight)
+                .expect("invalid weight shape")
+                .into_pyarray(py);
+            tensors.push(weight_tensor.to_object(py));
         }
-    }
 
-    fn orient_square(color: Color, square: Square) -> usize {
-        if color == Color::White {
-            square.index() as usize
-        } else {
-            (square.index() ^ 56) as usize
-        }
+        Ok(PyTuple::new(py, tensors).into())
     }
 }
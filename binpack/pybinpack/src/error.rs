@@ -12,6 +12,36 @@ pub enum LoaderError {
     NoFiles,
     #[error("unsupported feature set '{0}'")]
     UnsupportedFeatureSet(String),
+    #[error("invalid mixing weights: {0}")]
+    InvalidWeights(String),
+    #[error("the '{0}' feature was not enabled when building binpack_loader")]
+    FeatureDisabled(&'static str),
+    #[error("{0}: compressed input support was not compiled in")]
+    UnsupportedCompression(String),
+    #[error("{path}: corrupt binpack data: {message}")]
+    CorruptInput { path: String, message: String },
+    #[error("on_error must be 'raise' or 'skip', got '{0}'")]
+    InvalidErrorPolicy(String),
+}
+
+/// What the loader does when a file cannot be opened or decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Propagate the error, ending the iteration
+    Raise,
+    /// Log the error and continue, dropping the damaged chunk of a file that
+    /// fails to decode or a file that fails to open
+    Skip,
+}
+
+impl ErrorPolicy {
+    pub fn try_from_name(name: &str) -> Result<Self, LoaderError> {
+        match name {
+            "raise" => Ok(ErrorPolicy::Raise),
+            "skip" => Ok(ErrorPolicy::Skip),
+            other => Err(LoaderError::InvalidErrorPolicy(other.to_string())),
+        }
+    }
 }
 
 impl From<LoaderError> for PyErr {
@@ -19,9 +49,13 @@ impl From<LoaderError> for PyErr {
         match err {
             LoaderError::Io(e) => PyIOError::new_err(e.to_string()),
             LoaderError::Reader(e) => PyRuntimeError::new_err(e.to_string()),
-            LoaderError::NoFiles | LoaderError::UnsupportedFeatureSet(_) => {
-                PyValueError::new_err(err.to_string())
-            }
+            LoaderError::CorruptInput { .. } => PyRuntimeError::new_err(err.to_string()),
+            LoaderError::NoFiles
+            | LoaderError::UnsupportedFeatureSet(_)
+            | LoaderError::InvalidWeights(_)
+            | LoaderError::FeatureDisabled(_)
+            | LoaderError::UnsupportedCompression(_)
+            | LoaderError::InvalidErrorPolicy(_) => PyValueError::new_err(err.to_string()),
         }
     }
 }
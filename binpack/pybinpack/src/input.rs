@@ -0,0 +1,189 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::error::LoaderError;
+
+/// Compression applied on top of the binpack, derived from the file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            Some("gz") => Compression::Gzip,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// A binpack input, either a plain file, seekable zstd or a decompressing
+/// stream over one.
+pub enum InputFile {
+    Plain(File),
+    #[cfg(feature = "zstd")]
+    Seekable(sfbinpack::seekable::SeekableReader<File>),
+    Decoded(DecodedStream),
+}
+
+impl InputFile {
+    pub fn open(path: &Path) -> Result<Self, LoaderError> {
+        let file = File::open(path).map_err(|err| {
+            LoaderError::Io(io::Error::new(
+                err.kind(),
+                format!("{}: {}", path.display(), err),
+            ))
+        })?;
+
+        match Compression::from_path(path) {
+            Compression::None => Ok(InputFile::Plain(file)),
+            Compression::Zstd => zstd_input(file, path),
+            Compression::Gzip => Ok(InputFile::Decoded(DecodedStream::new(gzip_decoder(
+                file, path,
+            )?))),
+        }
+    }
+}
+
+/// Seekable zstd is read frame by frame with random access, any other zstd
+/// as a stream
+#[cfg(feature = "zstd")]
+fn zstd_input(mut file: File, _path: &Path) -> Result<InputFile, LoaderError> {
+    use sfbinpack::seekable::{is_seekable, SeekableReader};
+
+    if is_seekable(&mut file)? {
+        return Ok(InputFile::Seekable(SeekableReader::new(file)?));
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    Ok(InputFile::Decoded(DecodedStream::new(Box::new(
+        zstd::stream::read::Decoder::new(file)?,
+    ))))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_input(_file: File, path: &Path) -> Result<InputFile, LoaderError> {
+    Err(LoaderError::UnsupportedCompression(
+        path.display().to_string(),
+    ))
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_decoder(file: File, _path: &Path) -> Result<Box<dyn Read>, LoaderError> {
+    Ok(Box::new(flate2::read::MultiGzDecoder::new(
+        io::BufReader::new(file),
+    )))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_decoder(_file: File, path: &Path) -> Result<Box<dyn Read>, LoaderError> {
+    Err(LoaderError::UnsupportedCompression(
+        path.display().to_string(),
+    ))
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            InputFile::Plain(file) => file.read(buf),
+            #[cfg(feature = "zstd")]
+            InputFile::Seekable(file) => file.read(buf),
+            InputFile::Decoded(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Seek for InputFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            InputFile::Plain(file) => file.seek(pos),
+            #[cfg(feature = "zstd")]
+            InputFile::Seekable(file) => file.seek(pos),
+            InputFile::Decoded(stream) => stream.seek(pos),
+        }
+    }
+}
+
+/// Sequential stream which answers the few seeks the binpack reader performs.
+///
+/// The reader only asks for the current position and for the end of the
+/// stream to find out whether another chunk follows. The end is reported by
+/// peeking a single byte: one past the current position if there is more
+/// data, the current position otherwise. Any real repositioning fails.
+pub struct DecodedStream {
+    inner: Box<dyn Read>,
+    peeked: Option<u8>,
+    position: u64,
+}
+
+impl DecodedStream {
+    fn new(inner: Box<dyn Read>) -> Self {
+        Self {
+            inner,
+            peeked: None,
+            position: 0,
+        }
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        if self.peeked.is_none() {
+            let mut byte = [0u8; 1];
+            loop {
+                match self.inner.read(&mut byte) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        self.peeked = Some(byte[0]);
+                        break;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(self.peeked)
+    }
+}
+
+impl Read for DecodedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let read = match self.peeked.take() {
+            Some(byte) => {
+                buf[0] = byte;
+                1 + self.inner.read(&mut buf[1..])?
+            }
+            None => self.inner.read(buf)?,
+        };
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for DecodedStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            SeekFrom::Start(offset) if offset == self.position => Ok(self.position),
+            SeekFrom::End(0) => {
+                let more = self.peek()?.is_some();
+                Ok(self.position + more as u64)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compressed binpack inputs can only be read sequentially",
+            )),
+        }
+    }
+}
@@ -2,8 +2,14 @@
 
 mod batch;
 mod error;
+mod filter;
+mod input;
+mod prefetch;
 mod skip;
+mod source;
+mod stats;
 mod stream;
+mod weight;
 
 use pyo3::prelude::*;
 use stream::PySparseBatchStream;
@@ -11,5 +17,7 @@ use stream::PySparseBatchStream;
 #[pymodule]
 fn binpack_loader(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<PySparseBatchStream>()?;
+    m.add_function(wrap_pyfunction!(stream::scan, m)?)?;
+    m.add_function(wrap_pyfunction!(batch::feature_set_info, m)?)?;
     Ok(())
 }
@@ -0,0 +1,162 @@
+use pyo3::{
+    prelude::*,
+    types::{IntoPyDict, PyTuple},
+};
+
+/// Batches converted to torch tensors in pinned memory and copied to a CUDA
+/// device ahead of time.
+///
+/// One batch is kept in flight: while the caller trains on batch `n`, the
+/// host to device copy of batch `n + 1` has already been queued with
+/// `non_blocking=True` on the configured CUDA stream. Before a batch is
+/// handed out the current stream waits on the copy stream, so the tensors
+/// are safe to use without further synchronization.
+///
+/// Everything goes through the caller's `torch` installation, the extension
+/// itself does not link against CUDA. Requires the `cuda` cargo feature.
+pub struct DevicePrefetch {
+    torch: PyObject,
+    device: Option<PyObject>,
+    stream: Option<PyObject>,
+    pin_memory: bool,
+    pending: Option<Pending>,
+}
+
+/// A transferred batch, or the end of the epoch seen while prefetching
+pub enum Pending {
+    Batch(PyObject),
+    End,
+}
+
+impl DevicePrefetch {
+    #[cfg(feature = "cuda")]
+    pub fn new(
+        py: Python<'_>,
+        device: Option<PyObject>,
+        stream: Option<PyObject>,
+        pin_memory: bool,
+    ) -> PyResult<Self> {
+        let torch = py.import("torch")?;
+        let device = device
+            .map(|device| torch.call_method1("device", (device,)).map(PyObject::from))
+            .transpose()?;
+
+        if stream.is_some() && device.is_none() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "cuda_stream requires a device",
+            ));
+        }
+
+        Ok(Self {
+            torch: torch.into(),
+            device,
+            stream,
+            pin_memory,
+            pending: None,
+        })
+    }
+
+    #[cfg(not(feature = "cuda"))]
+    pub fn new(
+        _py: Python<'_>,
+        _device: Option<PyObject>,
+        _stream: Option<PyObject>,
+        _pin_memory: bool,
+    ) -> PyResult<Self> {
+        Err(crate::error::LoaderError::FeatureDisabled("cuda").into())
+    }
+
+    /// Returns the prefetched batch and queues the transfer of the next one.
+    ///
+    /// `produce` yields the next host batch as the tuple of numpy arrays, or
+    /// `None` at the end of an epoch.
+    pub fn next(
+        &mut self,
+        py: Python<'_>,
+        mut produce: impl FnMut(Python<'_>) -> PyResult<Option<PyObject>>,
+    ) -> PyResult<Option<PyObject>> {
+        let current = match self.pending.take() {
+            Some(pending) => pending,
+            None => self.fetch(py, &mut produce)?,
+        };
+
+        match current {
+            Pending::Batch(batch) => {
+                // wait before queueing the next copy so only this batch is awaited
+                self.wait_for_copy(py)?;
+                self.pending = Some(self.fetch(py, &mut produce)?);
+                Ok(Some(batch))
+            }
+            Pending::End => Ok(None),
+        }
+    }
+
+    fn fetch(
+        &self,
+        py: Python<'_>,
+        produce: &mut impl FnMut(Python<'_>) -> PyResult<Option<PyObject>>,
+    ) -> PyResult<Pending> {
+        match produce(py)? {
+            Some(batch) => self.transfer(py, batch).map(Pending::Batch),
+            None => Ok(Pending::End),
+        }
+    }
+
+    fn transfer(&self, py: Python<'_>, batch: PyObject) -> PyResult<PyObject> {
+        let torch = self.torch.as_ref(py);
+        let arrays = batch.as_ref(py).downcast::<PyTuple>()?;
+
+        let context = match &self.stream {
+            Some(stream) => {
+                let context = torch
+                    .getattr("cuda")?
+                    .call_method1("stream", (stream.as_ref(py),))?;
+                context.call_method0("__enter__")?;
+                Some(context)
+            }
+            None => None,
+        };
+
+        let tensors = (|| {
+            let mut tensors = Vec::with_capacity(arrays.len());
+            for array in arrays.iter() {
+                let mut tensor = torch.call_method1("from_numpy", (array,))?;
+                if self.pin_memory {
+                    tensor = tensor.call_method0("pin_memory")?;
+                }
+                if let Some(device) = &self.device {
+                    let kwargs = [("non_blocking", true)].into_py_dict(py);
+                    tensor = tensor.call_method("to", (device.as_ref(py),), Some(kwargs))?;
+                }
+                tensors.push(tensor);
+            }
+            PyResult::Ok(tensors)
+        })();
+
+        // restore the caller's stream even if a copy failed, the copy's error
+        // takes precedence over one from leaving the context
+        let exited = match context {
+            Some(context) => context
+                .call_method1("__exit__", (py.None(), py.None(), py.None()))
+                .map(drop),
+            None => Ok(()),
+        };
+        let tensors = tensors?;
+        exited?;
+
+        Ok(PyTuple::new(py, tensors).into())
+    }
+
+    fn wait_for_copy(&self, py: Python<'_>) -> PyResult<()> {
+        if let (Some(stream), Some(device)) = (&self.stream, &self.device) {
+            let current = self
+                .torch
+                .as_ref(py)
+                .getattr("cuda")?
+                .call_method1("current_stream", (device.as_ref(py),))?;
+            current.call_method1("wait_stream", (stream.as_ref(py),))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use std::path::{Path, PathBuf};
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, SeedableRng};
+use sfbinpack::{CompressedTrainingDataEntryReader, TrainingDataEntry};
+
+use crate::{
+    error::{ErrorPolicy, LoaderError},
+    input::InputFile,
+};
+
+/// Samples entries from several file groups with fixed probabilities.
+///
+/// Each entry is drawn from group `i` with probability `weights[i] / sum(weights)`.
+/// Exhausted groups (only possible without `cyclic`) drop out and the
+/// remaining weights are renormalized, the mix ends once every group is done.
+pub struct MixedSource {
+    groups: Vec<EntrySource>,
+    weights: Vec<f64>,
+    sampler: Option<WeightedIndex<f64>>,
+    rng: StdRng,
+}
+
+impl MixedSource {
+    pub fn new(
+        groups: Vec<Vec<PathBuf>>,
+        weights: Vec<f64>,
+        cyclic: bool,
+        on_error: ErrorPolicy,
+        seed: Option<u64>,
+    ) -> Result<Self, LoaderError> {
+        if groups.is_empty() {
+            return Err(LoaderError::NoFiles);
+        }
+
+        if groups.len() != weights.len() {
+            return Err(LoaderError::InvalidWeights(format!(
+                "expected {} weights, got {}",
+                groups.len(),
+                weights.len()
+            )));
+        }
+
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().all(|w| *w == 0.0) {
+            return Err(LoaderError::InvalidWeights(
+                "weights must be non-negative and not all zero".to_string(),
+            ));
+        }
+
+        let groups = groups
+            .into_iter()
+            .map(|files| EntrySource::new(files, cyclic, on_error))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut source = Self {
+            groups,
+            weights,
+            sampler: None,
+            rng: seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+        };
+        source.rebuild_sampler();
+        Ok(source)
+    }
+
+    pub fn next_entry(&mut self) -> Result<Option<TrainingDataEntry>, LoaderError> {
+        loop {
+            let idx = match &self.sampler {
+                Some(sampler) => sampler.sample(&mut self.rng),
+                None if self.groups.len() == 1 && self.weights[0] > 0.0 => 0,
+                None => return Ok(None),
+            };
+
+            match self.groups[idx].next_entry()? {
+                Some(entry) => return Ok(Some(entry)),
+                None => {
+                    self.weights[idx] = 0.0;
+                    if self.weights.iter().all(|w| *w == 0.0) {
+                        return Ok(None);
+                    }
+                    self.rebuild_sampler();
+                }
+            }
+        }
+    }
+
+    /// Counts the entries of a single pass over all groups
+    pub fn count_entries(&self) -> Result<u64, LoaderError> {
+        let mut total = 0;
+        for group in &self.groups {
+            total += group.count_entries()?;
+        }
+        Ok(total)
+    }
+
+    /// Drains the errors skipped by all groups since the last call
+    pub fn take_errors(&mut self) -> Vec<SkippedError> {
+        self.groups
+            .iter_mut()
+            .flat_map(|group| group.take_errors())
+            .collect()
+    }
+
+    fn rebuild_sampler(&mut self) {
+        // a single group needs no sampling
+        self.sampler = if self.groups.len() > 1 {
+            WeightedIndex::new(&self.weights).ok()
+        } else {
+            None
+        };
+    }
+}
+
+/// A file that failed to open, or a chunk that failed to decode, skipped under
+/// `ErrorPolicy::Skip`
+#[derive(Debug, Clone)]
+pub struct SkippedError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+pub struct EntrySource {
+    files: Vec<PathBuf>,
+    reader: Option<CompressedTrainingDataEntryReader<InputFile>>,
+    file_idx: usize,
+    cyclic: bool,
+    on_error: ErrorPolicy,
+    errors: Vec<SkippedError>,
+    /// An entry was returned since the files were last started over
+    yielded: bool,
+}
+
+impl EntrySource {
+    pub fn new(
+        files: Vec<PathBuf>,
+        cyclic: bool,
+        on_error: ErrorPolicy,
+    ) -> Result<Self, LoaderError> {
+        if files.is_empty() {
+            return Err(LoaderError::NoFiles);
+        }
+
+        Ok(Self {
+            files,
+            reader: None,
+            file_idx: 0,
+            cyclic,
+            on_error,
+            errors: Vec::new(),
+            yielded: false,
+        })
+    }
+
+    pub fn next_entry(&mut self) -> Result<Option<TrainingDataEntry>, LoaderError> {
+        loop {
+            if self.reader.is_none() && !self.advance_reader()? {
+                return Ok(None);
+            }
+
+            if let Some(reader) = self.reader.as_mut() {
+                if !reader.has_next() {
+                    self.reader = None;
+                    continue;
+                }
+
+                let path = &self.files[self.file_idx - 1];
+                match read_next(reader, path) {
+                    Ok(entry) => {
+                        self.yielded = true;
+                        return Ok(Some(entry));
+                    }
+                    // the reader skipped the damaged chunk and goes on with the next
+                    Err(err) => self.handle_error(self.file_idx - 1, err, "a chunk of")?,
+                }
+            }
+        }
+    }
+
+    /// Counts the entries of a single pass over all files
+    ///
+    /// With `ErrorPolicy::Skip` the damaged chunks of a corrupt file are left
+    /// out.
+    pub fn count_entries(&self) -> Result<u64, LoaderError> {
+        let mut total = 0u64;
+
+        for path in &self.files {
+            let mut reader = match open_reader(path) {
+                Ok(reader) => reader,
+                Err(_) if self.on_error == ErrorPolicy::Skip => continue,
+                Err(err) => return Err(err),
+            };
+
+            while reader.has_next() {
+                match read_next(&mut reader, path) {
+                    Ok(_) => total += 1,
+                    Err(_) if self.on_error == ErrorPolicy::Skip => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(total)
+    }
+
+    pub fn take_errors(&mut self) -> Vec<SkippedError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn advance_reader(&mut self) -> Result<bool, LoaderError> {
+        let total_files = self.files.len();
+        let mut attempts = 0;
+
+        while attempts < total_files {
+            if self.file_idx >= self.files.len() {
+                // a pass over the files without a single entry, e.g. when
+                // every chunk is corrupt, would repeat forever
+                if self.cyclic && std::mem::take(&mut self.yielded) {
+                    self.file_idx = 0;
+                } else {
+                    break;
+                }
+            }
+
+            let idx = self.file_idx;
+            self.file_idx += 1;
+            attempts += 1;
+
+            match open_reader(&self.files[idx]) {
+                // an empty file has nothing to switch to
+                Ok(reader) if reader.has_next() => {
+                    self.reader = Some(reader);
+                    return Ok(true);
+                }
+                Ok(_) => continue,
+                Err(err) => self.handle_error(idx, err, "the rest of")?,
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the error under `ErrorPolicy::Raise`, logs and records it otherwise,
+    /// `skipped` says what part of the file is dropped
+    fn handle_error(
+        &mut self,
+        file_idx: usize,
+        err: LoaderError,
+        skipped: &str,
+    ) -> Result<(), LoaderError> {
+        match self.on_error {
+            ErrorPolicy::Raise => Err(err),
+            ErrorPolicy::Skip => {
+                let path = self.files[file_idx].clone();
+                eprintln!(
+                    "binpack_loader: skipping {} {}: {}",
+                    skipped,
+                    path.display(),
+                    err
+                );
+                self.errors.push(SkippedError {
+                    path,
+                    message: err.to_string(),
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Decodes the next entry, checking the data. After an error the reader has
+/// skipped the rest of the damaged chunk.
+fn read_next(
+    reader: &mut CompressedTrainingDataEntryReader<InputFile>,
+    path: &Path,
+) -> Result<TrainingDataEntry, LoaderError> {
+    reader.try_next().map_err(|err| LoaderError::CorruptInput {
+        path: path.display().to_string(),
+        message: err.to_string(),
+    })
+}
+
+fn open_reader(path: &Path) -> Result<CompressedTrainingDataEntryReader<InputFile>, LoaderError> {
+    let file = InputFile::open(path)?;
+    Ok(CompressedTrainingDataEntryReader::new(file)?)
+}
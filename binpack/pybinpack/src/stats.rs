@@ -0,0 +1,93 @@
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyList},
+};
+use sfbinpack::TrainingDataEntry;
+
+use crate::{skip::SkipReason, source::SkippedError};
+
+const PIECE_COUNT_BUCKETS: usize = 33;
+
+/// Counters describing what the loader read and what it discarded.
+#[derive(Debug, Clone)]
+pub struct LoaderStats {
+    entries_read: u64,
+    entries_kept: u64,
+    skipped: [u64; SkipReason::COUNT],
+    filter_rejected: u64,
+    piece_count_read: [u64; PIECE_COUNT_BUCKETS],
+    piece_count_kept: [u64; PIECE_COUNT_BUCKETS],
+    errors: Vec<SkippedError>,
+}
+
+impl Default for LoaderStats {
+    fn default() -> Self {
+        Self {
+            entries_read: 0,
+            entries_kept: 0,
+            skipped: [0; SkipReason::COUNT],
+            filter_rejected: 0,
+            piece_count_read: [0; PIECE_COUNT_BUCKETS],
+            piece_count_kept: [0; PIECE_COUNT_BUCKETS],
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl LoaderStats {
+    pub fn record_read(&mut self, entry: &TrainingDataEntry) {
+        self.entries_read += 1;
+        self.piece_count_read[piece_count(entry)] += 1;
+    }
+
+    pub fn record_skip(&mut self, reason: SkipReason) {
+        self.skipped[reason as usize] += 1;
+    }
+
+    pub fn record_filter_rejected(&mut self, count: usize) {
+        self.filter_rejected += count as u64;
+    }
+
+    pub fn record_kept(&mut self, entry: &TrainingDataEntry) {
+        self.entries_kept += 1;
+        self.piece_count_kept[piece_count(entry)] += 1;
+    }
+
+    pub fn record_error(&mut self, error: &SkippedError) {
+        self.errors.push(error.clone());
+    }
+
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let skipped = PyDict::new(py);
+        for reason in SkipReason::ALL {
+            skipped.set_item(reason.name(), self.skipped[reason as usize])?;
+        }
+        skipped.set_item("filter", self.filter_rejected)?;
+
+        let histograms = PyDict::new(py);
+        histograms.set_item("read", self.piece_count_read.to_vec())?;
+        histograms.set_item("kept", self.piece_count_kept.to_vec())?;
+
+        let error_files = PyList::empty(py);
+        for error in &self.errors {
+            let details = PyDict::new(py);
+            details.set_item("path", error.path.display().to_string())?;
+            details.set_item("message", &error.message)?;
+            error_files.append(details)?;
+        }
+
+        let dict = PyDict::new(py);
+        dict.set_item("entries_read", self.entries_read)?;
+        dict.set_item("entries_kept", self.entries_kept)?;
+        dict.set_item("skipped", skipped)?;
+        dict.set_item("piece_count", histograms)?;
+        dict.set_item("errors", self.errors.len())?;
+        dict.set_item("error_files", error_files)?;
+
+        Ok(dict.into())
+    }
+}
+
+fn piece_count(entry: &TrainingDataEntry) -> usize {
+    usize::min(entry.pos.occupied().count() as usize, PIECE_COUNT_BUCKETS - 1)
+}
@@ -1,36 +1,87 @@
-use std::{
-    fs::File,
-    path::{Path, PathBuf},
-};
+use std::path::PathBuf;
 
-use pyo3::{prelude::*, types::PyDict};
-use sfbinpack::{CompressedReaderError, CompressedTrainingDataEntryReader, TrainingDataEntry};
+use pyo3::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sfbinpack::TrainingDataEntry;
 
 use crate::{
     batch::{FeatureSet, SparseBatchData},
-    error::LoaderError,
+    error::{ErrorPolicy, LoaderError},
+    filter::PyEntryFilter,
+    prefetch::DevicePrefetch,
     skip::{SkipConfig, SkipState},
+    source::{EntrySource, MixedSource},
+    stats::LoaderStats,
+    weight::{WeightConfig, WeightState},
 };
 
 #[pyclass(name = "SparseBatchStream", unsendable)]
 pub struct PySparseBatchStream {
     feature_set: FeatureSet,
     batch_size: usize,
-    source: EntrySource,
+    source: MixedSource,
     skip_state: Option<SkipState>,
+    weight_state: Option<WeightState>,
+    filter: Option<PyEntryFilter>,
+    stats: LoaderStats,
+    epoch: EpochLimit,
+    total_entries: Option<u64>,
+    prefetch: Option<DevicePrefetch>,
 }
 
 #[pymethods]
 impl PySparseBatchStream {
     #[new]
-    #[pyo3(signature = (feature_set, files, batch_size, skip_config=None, cyclic=false, num_workers=1))]
+    #[pyo3(signature = (
+        feature_set,
+        files,
+        batch_size,
+        cyclic=false,
+        num_workers=1,
+        *,
+        filtered=false,
+        random_fen_skipping=0,
+        wld_filtered=false,
+        early_fen_skipping=-1,
+        simple_eval_skipping=-1,
+        param_index=0,
+        wld_weighted=false,
+        phase_weights=None,
+        filter=None,
+        entries_per_epoch=None,
+        batches_per_epoch=None,
+        weights=None,
+        device=None,
+        pin_memory=false,
+        cuda_stream=None,
+        on_error="raise",
+        seed=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
+        py: Python<'_>,
         feature_set: &str,
-        files: Vec<String>,
+        files: &PyAny,
         batch_size: usize,
-        skip_config: Option<&PyDict>,
         cyclic: bool,
         num_workers: usize,
+        filtered: bool,
+        random_fen_skipping: i32,
+        wld_filtered: bool,
+        early_fen_skipping: i32,
+        simple_eval_skipping: i32,
+        param_index: i32,
+        wld_weighted: bool,
+        phase_weights: Option<(f32, f32, f32)>,
+        filter: Option<PyObject>,
+        entries_per_epoch: Option<usize>,
+        batches_per_epoch: Option<usize>,
+        weights: Option<Vec<f64>>,
+        device: Option<PyObject>,
+        pin_memory: bool,
+        cuda_stream: Option<PyObject>,
+        on_error: &str,
+        seed: Option<u64>,
     ) -> PyResult<Self> {
         if batch_size == 0 {
             return Err(pyo3::exceptions::PyValueError::new_err(
@@ -38,11 +89,41 @@ impl PySparseBatchStream {
             ));
         }
 
+        if entries_per_epoch == Some(0) || batches_per_epoch == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "entries_per_epoch and batches_per_epoch must be greater than zero",
+            ));
+        }
+
         let feature_set = FeatureSet::try_from_name(feature_set)?;
-        let paths = files.into_iter().map(PathBuf::from).collect::<Vec<_>>();
-        let source = EntrySource::new(paths, cyclic)?;
-        let skip_cfg = parse_skip_config(skip_config)?;
+        let groups = parse_file_groups(files)?;
+        let weights = weights.unwrap_or_else(|| vec![1.0; groups.len()]);
+        let on_error = ErrorPolicy::try_from_name(on_error)?;
+        let (source_seed, skip_seed) = split_seed(seed);
+        let source = MixedSource::new(groups, weights, cyclic, on_error, source_seed)?;
+        let skip_cfg = SkipConfig {
+            filtered,
+            random_fen_skipping,
+            wld_filtered,
+            early_fen_skipping,
+            simple_eval_skipping,
+            param_index,
+            seed: skip_seed,
+        };
         let skip_state = SkipState::maybe_new(skip_cfg);
+        let weight_state = WeightState::maybe_new(WeightConfig {
+            wld_weighted,
+            phase_weights,
+        });
+        let filter = filter
+            .map(|callable| PyEntryFilter::new(py, callable))
+            .transpose()?;
+
+        let prefetch = if device.is_some() || pin_memory || cuda_stream.is_some() {
+            Some(DevicePrefetch::new(py, device, cuda_stream, pin_memory)?)
+        } else {
+            None
+        };
 
         // currently single-threaded but we keep the parameter for API parity
         let _ = num_workers;
@@ -52,6 +133,17 @@ impl PySparseBatchStream {
             batch_size,
             source,
             skip_state,
+            weight_state,
+            filter,
+            stats: LoaderStats::default(),
+            epoch: EpochLimit {
+                entries_per_epoch,
+                batches_per_epoch,
+                entries: 0,
+                batches: 0,
+            },
+            total_entries: None,
+            prefetch,
         })
     }
 
@@ -60,112 +152,199 @@ impl PySparseBatchStream {
     }
 
     fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
-        match self.next_batch_data() {
-            Ok(Some(batch)) => batch.into_py(py).map(Some),
-            Ok(None) => Ok(None),
-            Err(err) => Err(err.into()),
-        }
+        self.next_output(py)
     }
 
     pub fn next_batch(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
-        match self.next_batch_data() {
-            Ok(Some(batch)) => batch.into_py(py).map(Some),
-            Ok(None) => Ok(None),
-            Err(err) => Err(err.into()),
+        self.next_output(py)
+    }
+
+    /// Number of batches in one epoch.
+    ///
+    /// Without `batches_per_epoch`/`entries_per_epoch` this needs one pass
+    /// over all files to count the entries, the result is cached. Entries
+    /// dropped by the skip settings are not accounted for, so the value is an
+    /// upper bound when skipping is enabled.
+    fn __len__(&mut self) -> PyResult<usize> {
+        if let Some(batches) = self.epoch.batches_per_epoch {
+            return Ok(batches);
         }
+
+        let entries = match self.epoch.entries_per_epoch {
+            Some(entries) => entries as u64,
+            None => match self.total_entries {
+                Some(total) => total,
+                None => {
+                    let total = self.source.count_entries()?;
+                    self.total_entries = Some(total);
+                    total
+                }
+            },
+        };
+
+        Ok(entries.div_ceil(self.batch_size as u64) as usize)
+    }
+
+    /// Counts of read, kept and skipped entries (by reason) since the stream was created
+    pub fn stats(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.stats.to_dict(py)
     }
 }
 
-impl PySparseBatchStream {
-    fn next_batch_data(&mut self) -> Result<Option<SparseBatchData>, LoaderError> {
-        let mut buffer = Vec::with_capacity(self.batch_size);
-        while buffer.len() < self.batch_size {
-            match self.source.next_entry()? {
-                Some(entry) => {
-                    if let Some(skip) = &mut self.skip_state {
-                        if !skip.should_keep(&entry) {
-                            continue;
-                        }
-                    }
-                    buffer.push(entry);
-                }
-                None => break,
-            }
-        }
+/// Reads all files once with the given skip settings and returns the same
+/// statistics as `SparseBatchStream.stats()`, without building any batches.
+#[pyfunction]
+#[pyo3(signature = (
+    files,
+    *,
+    filtered=false,
+    random_fen_skipping=0,
+    wld_filtered=false,
+    early_fen_skipping=-1,
+    simple_eval_skipping=-1,
+    param_index=0,
+    on_error="raise",
+    seed=None,
+))]
+#[allow(clippy::too_many_arguments)]
+pub fn scan(
+    py: Python<'_>,
+    files: Vec<String>,
+    filtered: bool,
+    random_fen_skipping: i32,
+    wld_filtered: bool,
+    early_fen_skipping: i32,
+    simple_eval_skipping: i32,
+    param_index: i32,
+    on_error: &str,
+    seed: Option<u64>,
+) -> PyResult<PyObject> {
+    let on_error = ErrorPolicy::try_from_name(on_error)?;
+    let paths = files.into_iter().map(PathBuf::from).collect::<Vec<_>>();
+    let mut source = EntrySource::new(paths, false, on_error)?;
+    let mut skip_state = SkipState::maybe_new(SkipConfig {
+        filtered,
+        random_fen_skipping,
+        wld_filtered,
+        early_fen_skipping,
+        simple_eval_skipping,
+        param_index,
+        seed: split_seed(seed).1,
+    });
+    let mut stats = LoaderStats::default();
 
-        if buffer.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(SparseBatchData::from_entries(
-                buffer,
-                self.feature_set,
-            )))
+    while let Some(entry) = source.next_entry()? {
+        stats.record_read(&entry);
+
+        match skip_state
+            .as_mut()
+            .and_then(|skip| skip.skip_reason(&entry))
+        {
+            Some(reason) => stats.record_skip(reason),
+            None => stats.record_kept(&entry),
         }
     }
-}
 
-struct EntrySource {
-    files: Vec<PathBuf>,
-    reader: Option<CompressedTrainingDataEntryReader<File>>,
-    file_idx: usize,
-    cyclic: bool,
+    for error in source.take_errors() {
+        stats.record_error(&error);
+    }
+
+    stats.to_dict(py)
 }
 
-impl EntrySource {
-    fn new(files: Vec<PathBuf>, cyclic: bool) -> Result<Self, LoaderError> {
-        if files.is_empty() {
-            return Err(LoaderError::NoFiles);
+impl PySparseBatchStream {
+    fn next_output(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        match self.prefetch.take() {
+            Some(mut prefetch) => {
+                let batch = prefetch.next(py, |py| self.next_host_batch(py));
+                self.prefetch = Some(prefetch);
+                batch
+            }
+            None => self.next_host_batch(py),
         }
+    }
 
-        Ok(Self {
-            files,
-            reader: None,
-            file_idx: 0,
-            cyclic,
-        })
+    fn next_host_batch(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        match self.next_batch_data(py)? {
+            Some(batch) => batch.into_py(py).map(Some),
+            None => Ok(None),
+        }
     }
 
-    fn next_entry(&mut self) -> Result<Option<TrainingDataEntry>, LoaderError> {
-        loop {
-            if self.reader.is_none() && !self.advance_reader()? {
+    fn next_batch_data(&mut self, py: Python<'_>) -> PyResult<Option<SparseBatchData>> {
+        let batch_size = match self.epoch.next_batch_size(self.batch_size) {
+            Some(size) => size,
+            None => {
+                // the epoch is over, the next call continues with a fresh one
+                self.epoch.reset();
                 return Ok(None);
             }
+        };
 
-            if let Some(reader) = self.reader.as_mut() {
-                if reader.has_next() {
-                    let entry = reader.next();
-                    return Ok(Some(entry));
-                } else {
-                    self.reader = None;
-                }
+        let mut buffer = Vec::with_capacity(batch_size);
+        let mut candidates = Vec::with_capacity(batch_size);
+
+        while buffer.len() < batch_size {
+            let exhausted = self.fill_candidates(&mut candidates, batch_size - buffer.len())?;
+
+            if let Some(filter) = &self.filter {
+                let before = candidates.len();
+                filter.retain(py, &mut candidates)?;
+                self.stats.record_filter_rejected(before - candidates.len());
+            }
+            for entry in &candidates {
+                self.stats.record_kept(entry);
+            }
+            buffer.append(&mut candidates);
+
+            if exhausted {
+                break;
             }
         }
-    }
 
-    fn advance_reader(&mut self) -> Result<bool, LoaderError> {
-        let total_files = self.files.len();
-        let mut attempts = 0;
+        if buffer.is_empty() {
+            self.epoch.reset();
+            Ok(None)
+        } else {
+            self.epoch.record_batch(buffer.len());
+            let weight = self
+                .weight_state
+                .as_mut()
+                .map(|weights| weights.weights(&buffer));
+            Ok(Some(SparseBatchData::from_entries(
+                buffer,
+                self.feature_set,
+                weight,
+            )))
+        }
+    }
 
-        while attempts < total_files {
-            if self.file_idx >= self.files.len() {
-                if self.cyclic {
-                    self.file_idx = 0;
-                } else {
-                    break;
-                }
+    /// Reads up to `wanted` entries passing the skip config into `candidates`,
+    /// returns true once the source is exhausted.
+    fn fill_candidates(
+        &mut self,
+        candidates: &mut Vec<TrainingDataEntry>,
+        wanted: usize,
+    ) -> Result<bool, LoaderError> {
+        while candidates.len() < wanted {
+            let next = self.source.next_entry()?;
+            for error in self.source.take_errors() {
+                self.stats.record_error(&error);
             }
 
-            let path = self.files[self.file_idx].clone();
-            self.file_idx += 1;
-            attempts += 1;
+            match next {
+                Some(entry) => {
+                    self.stats.record_read(&entry);
 
-            match open_reader(&path) {
-                Ok(Some(reader)) => {
-                    self.reader = Some(reader);
-                    return Ok(true);
+                    if let Some(skip) = &mut self.skip_state {
+                        if let Some(reason) = skip.skip_reason(&entry) {
+                            self.stats.record_skip(reason);
+                            continue;
+                        }
+                    }
+                    candidates.push(entry);
                 }
-                Ok(None) => continue,
-                Err(err) => return Err(err),
+                None => return Ok(true),
             }
         }
 
@@ -173,45 +352,58 @@ impl EntrySource {
     }
 }
 
-fn open_reader(
-    path: &Path,
-) -> Result<Option<CompressedTrainingDataEntryReader<File>>, LoaderError> {
-    let file = File::open(path).map_err(|err| {
-        LoaderError::Io(std::io::Error::new(
-            err.kind(),
-            format!("{}: {}", path.display(), err),
-        ))
-    })?;
-
-    match CompressedTrainingDataEntryReader::new(file) {
-        Ok(reader) => Ok(Some(reader)),
-        Err(CompressedReaderError::EndOfFile) => Ok(None),
-        Err(err) => Err(LoaderError::from(err)),
+/// Seeds of the mixing of file groups and of the skipping, which draw from
+/// generators of their own. `scan` uses the same skip seed as a stream, so
+/// both skip the same entries of a single group for the same seed.
+fn split_seed(seed: Option<u64>) -> (Option<u64>, Option<u64>) {
+    let mut seeds = seed.map(StdRng::seed_from_u64);
+    let mut next = || seeds.as_mut().map(|seeds| seeds.gen());
+    (next(), next())
+}
+
+/// Accepts either a flat list of files or a list of file groups for mixing
+fn parse_file_groups(files: &PyAny) -> PyResult<Vec<Vec<PathBuf>>> {
+    let to_paths = |group: Vec<String>| group.into_iter().map(PathBuf::from).collect();
+
+    if let Ok(flat) = files.extract::<Vec<String>>() {
+        return Ok(vec![to_paths(flat)]);
     }
+
+    let groups = files.extract::<Vec<Vec<String>>>()?;
+    Ok(groups.into_iter().map(to_paths).collect())
 }
 
-fn parse_skip_config(dict: Option<&PyDict>) -> PyResult<SkipConfig> {
-    let mut cfg = SkipConfig::default();
-    if let Some(d) = dict {
-        if let Some(value) = d.get_item("filtered")? {
-            cfg.filtered = value.extract::<bool>()?;
-        }
-        if let Some(value) = d.get_item("random_fen_skipping")? {
-            cfg.random_fen_skipping = value.extract::<i32>()?;
-        }
-        if let Some(value) = d.get_item("wld_filtered")? {
-            cfg.wld_filtered = value.extract::<bool>()?;
-        }
-        if let Some(value) = d.get_item("early_fen_skipping")? {
-            cfg.early_fen_skipping = value.extract::<i32>()?;
-        }
-        if let Some(value) = d.get_item("simple_eval_skipping")? {
-            cfg.simple_eval_skipping = value.extract::<i32>()?;
+/// Tracks progress through the current epoch when its size is fixed.
+struct EpochLimit {
+    entries_per_epoch: Option<usize>,
+    batches_per_epoch: Option<usize>,
+    entries: usize,
+    batches: usize,
+}
+
+impl EpochLimit {
+    /// Size of the next batch, or `None` once the epoch is complete
+    fn next_batch_size(&self, batch_size: usize) -> Option<usize> {
+        if let Some(limit) = self.batches_per_epoch {
+            if self.batches >= limit {
+                return None;
+            }
         }
-        if let Some(value) = d.get_item("param_index")? {
-            cfg.param_index = value.extract::<i32>()?;
+
+        match self.entries_per_epoch {
+            Some(limit) if self.entries >= limit => None,
+            Some(limit) => Some(batch_size.min(limit - self.entries)),
+            None => Some(batch_size),
         }
     }
 
-    Ok(cfg)
+    fn record_batch(&mut self, entries: usize) {
+        self.entries += entries;
+        self.batches += 1;
+    }
+
+    fn reset(&mut self) {
+        self.entries = 0;
+        self.batches = 0;
+    }
 }
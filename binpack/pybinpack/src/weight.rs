@@ -0,0 +1,54 @@
+use sfbinpack::{
+    filter::{weight_fn, EntryFilter, EntryWeight, PhaseWeight, SkipWld},
+    TrainingDataEntry,
+};
+
+/// Weighting selected through the stream keywords, applied to the entries
+/// that pass the skip config.
+#[derive(Debug, Clone, Default)]
+pub struct WeightConfig {
+    /// Weigh by the confidence of the WLD model instead of skipping
+    pub wld_weighted: bool,
+    /// Weights of the opening, middlegame and endgame
+    pub phase_weights: Option<(f32, f32, f32)>,
+}
+
+impl WeightConfig {
+    pub fn is_active(&self) -> bool {
+        self.wld_weighted || self.phase_weights.is_some()
+    }
+}
+
+pub struct WeightState {
+    weight: Box<dyn EntryWeight>,
+}
+
+impl WeightState {
+    pub fn maybe_new(config: WeightConfig) -> Option<Self> {
+        if !config.is_active() {
+            return None;
+        }
+
+        let mut weight: Box<dyn EntryWeight> = Box::new(weight_fn(|_| 1.0));
+        if config.wld_weighted {
+            weight = Box::new(weight.times(SkipWld::new().weighted()));
+        }
+        if let Some((opening, middlegame, endgame)) = config.phase_weights {
+            weight = Box::new(weight.times(PhaseWeight {
+                opening,
+                middlegame,
+                endgame,
+            }));
+        }
+
+        Some(Self { weight })
+    }
+
+    /// The weight of every entry of `entries`, in order
+    pub fn weights(&mut self, entries: &[TrainingDataEntry]) -> Vec<f32> {
+        entries
+            .iter()
+            .map(|entry| self.weight.weight(entry))
+            .collect()
+    }
+}
@@ -0,0 +1,308 @@
+//! Where the bytes of a binpack go.
+//!
+//! [`CompressionStats::analyze`] walks the chunks of a file and splits its
+//! size into chunk headers, checksum trailers, stems, movetext, annotations
+//! and the unused tails of chunks, measures the bits spent per move and per score
+//! and counts chains by length. A stem costs 34 bytes while a continuation
+//! costs a byte or two, so a generator that writes few continuations, e.g.
+//! because it skips positions within games, shows up as short chains and a
+//! large stem share:
+//!
+//! ```
+//! use std::fs::File;
+//! use sfbinpack::analysis::CompressionStats;
+//!
+//! let stats = CompressionStats::analyze(File::open("test/ep1.binpack").unwrap()).unwrap();
+//! assert_eq!(stats.file_bytes, 46);
+//! assert_eq!(stats.chains, 1);
+//! assert_eq!(stats.chain_lengths[&2], 1);
+//! println!("{stats}");
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Seek},
+};
+
+use crate::{
+    checksum,
+    common::move_score_list_reader::PackedMoveScoreListReader,
+    common::{
+        arithmetic::{signed_to_unsigned, used_bits_safe},
+        compressed_training_file_reader::CompressedTrainingDataFileReader,
+        compressed_training_file_writer,
+        entry::PackedTrainingDataEntry,
+    },
+    CompressedReaderError,
+};
+
+const CHUNK_HEADER_SIZE: u64 = compressed_training_file_writer::CHUNK_HEADER_SIZE as u64;
+
+/// Bytes of the ply count after a stem
+const COUNT_SIZE: usize = 2;
+
+const SCORE_VLE_BLOCK_SIZE: usize = 4;
+
+/// Byte and bit counts of a binpack
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub file_bytes: u64,
+    pub chunks: u64,
+    /// Magic and size in front of every chunk
+    pub header_bytes: u64,
+    /// CRC32 trailers, see [`crate::checksum`]
+    pub trailer_bytes: u64,
+    /// Stems and the ply counts after them
+    pub stem_bytes: u64,
+    pub movetext_bytes: u64,
+    /// Ends of chunks too short for another chain
+    pub padding_bytes: u64,
+    /// Extension chunks of annotations, headers included, see
+    /// [`crate::annotation`]
+    pub annotation_bytes: u64,
+    pub entries: u64,
+    pub chains: u64,
+    /// Bits of the moves in movetext, piece and destination indices
+    pub move_bits: u64,
+    /// Bits of the score deltas in movetext
+    pub score_bits: u64,
+    /// Chains by length in entries, stem included, in buckets of powers of
+    /// two: `n` counts the chains of `n..2n` entries
+    pub chain_lengths: BTreeMap<u64, u64>,
+}
+
+impl CompressionStats {
+    /// Reads the binpack in `file` from its current position to the end
+    pub fn analyze<T: Read + Seek>(file: T) -> Result<Self, CompressedReaderError> {
+        let mut input = CompressedTrainingDataFileReader::new(file, None)?;
+        let mut stats = Self::default();
+        let mut chunk = Vec::new();
+
+        while input.has_next_chunk() {
+            input.read_next_chunk_into(&mut chunk)?;
+            if let Some(annotations) = input.annotations() {
+                stats.annotation_bytes += CHUNK_HEADER_SIZE + annotations.len() as u64;
+            }
+            stats.add_chunk(&chunk)?;
+        }
+
+        stats.file_bytes = input.read_bytes();
+        Ok(stats)
+    }
+
+    /// Adds the chunk `chunk`, its data and trailer without the header
+    fn add_chunk(&mut self, chunk: &[u8]) -> Result<(), CompressedReaderError> {
+        let (_, data_len) = checksum::check(chunk);
+        let data = &chunk[..data_len];
+        let stem_size = PackedTrainingDataEntry::byte_size() + COUNT_SIZE;
+
+        self.chunks += 1;
+        self.header_bytes += CHUNK_HEADER_SIZE;
+        self.trailer_bytes += (chunk.len() - data_len) as u64;
+
+        let mut offset = 0;
+        while offset + stem_size <= data.len() {
+            let stem =
+                PackedTrainingDataEntry::from_slice(&data[offset..offset + stem_size - COUNT_SIZE])
+                    .unpack_entry();
+            let plies =
+                u16::from_be_bytes([data[offset + stem_size - 2], data[offset + stem_size - 1]]);
+            offset += stem_size;
+            self.stem_bytes += stem_size as u64;
+
+            let movetext = &data[offset..];
+            let mut reader = PackedMoveScoreListReader::new(stem, plies);
+            let mut last_score = stem.score.wrapping_neg();
+            while reader.has_next() {
+                let bits = reader.num_read_bits();
+                let entry = reader.next_entry(movetext);
+                if reader.is_corrupt() || reader.num_read_bytes() > movetext.len() {
+                    return Err(CompressedReaderError::InvalidFormat(format!(
+                        "corrupt movetext in chunk {}",
+                        self.chunks - 1
+                    )));
+                }
+
+                let score_bits = score_bits(entry.score.wrapping_sub(last_score));
+                last_score = entry.score.wrapping_neg();
+                self.score_bits += score_bits;
+                self.move_bits += (reader.num_read_bits() - bits) as u64 - score_bits;
+            }
+
+            let movetext_bytes = reader.num_read_bytes();
+            offset += movetext_bytes;
+            self.movetext_bytes += movetext_bytes as u64;
+
+            let length = 1 + plies as u64;
+            self.entries += length;
+            self.chains += 1;
+            *self.chain_lengths.entry(1 << length.ilog2()).or_default() += 1;
+        }
+
+        self.padding_bytes += (data.len() - offset) as u64;
+        Ok(())
+    }
+
+    /// Entries stored as continuations, in movetext
+    pub fn continuations(&self) -> u64 {
+        self.entries - self.chains
+    }
+
+    pub fn bytes_per_entry(&self) -> f64 {
+        ratio(self.file_bytes as f64, self.entries)
+    }
+
+    pub fn bits_per_move(&self) -> f64 {
+        ratio(self.move_bits as f64, self.continuations())
+    }
+
+    pub fn bits_per_score(&self) -> f64 {
+        ratio(self.score_bits as f64, self.continuations())
+    }
+
+    pub fn average_chain_length(&self) -> f64 {
+        ratio(self.entries as f64, self.chains)
+    }
+
+    /// Fraction of the file taken by stems
+    pub fn stem_share(&self) -> f64 {
+        ratio(self.stem_bytes as f64, self.file_bytes)
+    }
+}
+
+/// Bits of a score delta: blocks of 4 bits and a continuation bit, at least
+/// one block
+fn score_bits(delta: i16) -> u64 {
+    let bits = used_bits_safe(signed_to_unsigned(delta) as u64 + 1);
+    let blocks = bits.div_ceil(SCORE_VLE_BLOCK_SIZE).max(1);
+    (blocks * (SCORE_VLE_BLOCK_SIZE + 1)) as u64
+}
+
+fn ratio(value: f64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        value / count as f64
+    }
+}
+
+impl fmt::Display for CompressionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = |bytes: u64| 100.0 * ratio(bytes as f64, self.file_bytes);
+
+        writeln!(f, "file:        {} bytes", self.file_bytes)?;
+        for (name, bytes) in [
+            ("headers:", self.header_bytes),
+            ("trailers:", self.trailer_bytes),
+            ("stems:", self.stem_bytes),
+            ("movetext:", self.movetext_bytes),
+            ("padding:", self.padding_bytes),
+            ("annotations:", self.annotation_bytes),
+        ] {
+            writeln!(f, "{:<12} {} bytes ({:.1}%)", name, bytes, share(bytes))?;
+        }
+
+        writeln!(f, "entries:     {}", self.entries)?;
+        writeln!(f, "chains:      {}", self.chains)?;
+        writeln!(f, "bytes/entry: {:.3}", self.bytes_per_entry())?;
+        writeln!(f, "bits/move:   {:.2}", self.bits_per_move())?;
+        writeln!(f, "bits/score:  {:.2}", self.bits_per_score())?;
+        write!(f, "chain lengths:")?;
+        for (length, count) in &self.chain_lengths {
+            write!(f, "\n  {:>6}..{:<6} {}", length, length * 2, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        testing::{random_game, GameOptions, Rng},
+        CompressedTrainingDataEntryWriter, WriterOptions,
+    };
+
+    fn write(games: &[Vec<crate::TrainingDataEntry>], options: WriterOptions) -> Vec<u8> {
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Cursor::new(Vec::new()), options)
+                .unwrap();
+        for game in games {
+            for entry in game {
+                writer.write_entry(entry).unwrap();
+            }
+        }
+        writer.flush_and_end();
+        writer.into_inner().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_score_bits() {
+        assert_eq!(score_bits(0), 5);
+        // zigzag: -8 and 7 are 15 and 14, one block
+        assert_eq!(score_bits(-8), 5);
+        assert_eq!(score_bits(7), 5);
+        assert_eq!(score_bits(8), 10);
+        assert_eq!(score_bits(i16::MIN), 20);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut rng = Rng::new(3);
+        let games = (0..20)
+            .map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+        let entries = games.iter().map(Vec::len).sum::<usize>() as u64;
+
+        let data = write(&games, WriterOptions::default());
+        let stats = CompressionStats::analyze(Cursor::new(&data)).unwrap();
+        assert_eq!(stats.file_bytes, data.len() as u64);
+        assert_eq!(
+            stats.header_bytes
+                + stats.trailer_bytes
+                + stats.stem_bytes
+                + stats.movetext_bytes
+                + stats.padding_bytes
+                + stats.annotation_bytes,
+            stats.file_bytes
+        );
+        assert_eq!(stats.entries, entries);
+        assert_eq!(stats.chain_lengths.values().sum::<u64>(), stats.chains);
+        // movetext ends on a byte boundary, less than a byte per chain is
+        // left unused
+        let bits = stats.move_bits + stats.score_bits;
+        assert!(bits <= stats.movetext_bytes * 8);
+        assert!(stats.movetext_bytes * 8 - bits < 8 * stats.chains);
+        assert_eq!(stats.trailer_bytes, 0);
+
+        let checked = write(
+            &games,
+            WriterOptions {
+                checksums: true,
+                ..Default::default()
+            },
+        );
+        let stats_checked = CompressionStats::analyze(Cursor::new(&checked)).unwrap();
+        assert_eq!(
+            stats_checked.trailer_bytes,
+            (checksum::TRAILER_SIZE as u64) * stats_checked.chunks
+        );
+        assert_eq!(stats_checked.movetext_bytes, stats.movetext_bytes);
+
+        // every other position dropped: all stems, no movetext
+        let sparse = games
+            .iter()
+            .map(|game| game.iter().step_by(2).copied().collect())
+            .collect::<Vec<_>>();
+        let data = write(&sparse, WriterOptions::default());
+        let sparse = CompressionStats::analyze(Cursor::new(&data)).unwrap();
+        assert_eq!(sparse.chains, sparse.entries);
+        assert_eq!(sparse.movetext_bytes, 0);
+        assert!(sparse.stem_share() > stats.stem_share());
+        assert!(sparse.bytes_per_entry() > stats.bytes_per_entry());
+        assert!(format!("{sparse}").contains("bits/move:   0.00"));
+    }
+}
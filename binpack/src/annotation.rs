@@ -0,0 +1,508 @@
+//! Extra values stored with entries, such as the depth and node count of
+//! the search that scored them.
+//!
+//! The format has no room for them in a chain, so the annotations of the
+//! entries of a chunk go into an extension chunk of their own right in
+//! front of it. It is framed like any chunk, with [`MAGIC`] instead of
+//! `"BINP"`:
+//!
+//! ```text
+//! Annotations = Count Annotation*         (* one per entry of the chunk *)
+//! Annotation  = Fields Depth? SelDepth? Nodes? PvLength? (Len Extra)?
+//!               ScoreSource? ResultSource?
+//! Fields      = UINT8                     (* bit set per value present *)
+//! ScoreSource = Kind SourceDepth?         (* no depth for tablebases *)
+//! Kind        = LEB128                    (* 0 search, 1 rescore, 2 tablebase *)
+//! SourceDepth = LEB128                    (* depth + 1, 0 if unknown *)
+//! ResultSource = LEB128                   (* 0 played, 1 adjudicated *)
+//! Count, Depth, SelDepth, Nodes, PvLength, Len = LEB128
+//! ```
+//!
+//! [`ScoreSource`] and [`ResultSource`] are the provenance of an entry, the
+//! same tags summarize a whole dataset in its
+//! [`DatasetMeta`](crate::meta::DatasetMeta) sidecar, so a dataset mixed
+//! from several sources stays auditable.
+//!
+//! Entries without an annotation get an empty one, a single zero byte, and
+//! chunks without any annotated entry get no extension chunk, so a file
+//! written without annotations is byte for byte what it always was. A
+//! checksum trailer, see [`crate::checksum`], covers the extension chunk
+//! like any other.
+//!
+//! This crate's reader collects the annotations of every chunk and returns
+//! them with [`next_annotated`](crate::CompressedTrainingDataEntryReader::next_annotated),
+//! everything else reading chunks, [`CompressionStats`](crate::analysis::CompressionStats)
+//! included, passes over them. Readers that only know `"BINP"` chunks,
+//! Stockfish's and versions of this crate before annotations, stop at the
+//! first extension chunk with an invalid magic error. Writing the entries
+//! again with [`write_entry`](crate::CompressedTrainingDataEntryWriter::write_entry),
+//! or copying chunks in a [`Pipeline`](crate::pipeline::Pipeline), drops
+//! the annotations and gives a file every reader takes.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use sfbinpack::{
+//!     annotation::Annotation, CompressedTrainingDataEntryReader,
+//!     CompressedTrainingDataEntryWriter, TrainingDataEntry,
+//! };
+//!
+//! let mut writer = CompressedTrainingDataEntryWriter::new(Cursor::new(Vec::new())).unwrap();
+//! let annotation = Annotation {
+//!     depth: Some(12),
+//!     nodes: Some(5000),
+//!     ..Default::default()
+//! };
+//! writer
+//!     .write_annotated(&TrainingDataEntry::default(), &annotation)
+//!     .unwrap();
+//! writer.flush_and_end();
+//!
+//! let data = writer.into_inner().unwrap().into_inner();
+//! let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+//! let (entry, read) = reader.next_annotated();
+//! assert_eq!(entry, TrainingDataEntry::default());
+//! assert_eq!(read, annotation);
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+/// Magic of an extension chunk holding the annotations of the chunk after
+/// it
+pub const MAGIC: &[u8; 4] = b"BINA";
+
+const DEPTH: u8 = 1 << 0;
+const SELDEPTH: u8 = 1 << 1;
+const NODES: u8 = 1 << 2;
+const PV_LENGTH: u8 = 1 << 3;
+const EXTRA: u8 = 1 << 4;
+const SCORE_SOURCE: u8 = 1 << 5;
+const RESULT_SOURCE: u8 = 1 << 6;
+
+/// Why the data of an extension chunk cannot be read
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AnnotationError {
+    #[error("annotations end in the middle of a value")]
+    Truncated,
+    #[error("a value does not fit its field")]
+    Overflow,
+    #[error("unknown annotation fields {0:#04x}")]
+    UnknownFields(u8),
+    #[error("{0} bytes after the last annotation")]
+    TrailingBytes(usize),
+    #[error("unknown provenance tag '{0}'")]
+    UnknownTag(String),
+}
+
+/// Where the score of an entry comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScoreSource {
+    /// The search of the game that generated the entry, to `depth` if
+    /// known; tagged `search` or e.g. `search:d12`
+    Search { depth: Option<u32> },
+    /// A later search over the finished data, e.g. `binpack-tools
+    /// rescore`; tagged `rescore` or e.g. `rescore:d8`
+    Rescore { depth: Option<u32> },
+    /// A tablebase probe, tagged `tablebase`
+    Tablebase,
+}
+
+/// How the result of the game of an entry was decided
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResultSource {
+    /// Played out to the end, tagged `played`
+    Played,
+    /// Adjudicated before the end, by score, tablebase or move limit,
+    /// tagged `adjudicated`
+    Adjudicated,
+}
+
+impl fmt::Display for ScoreSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, depth) = match *self {
+            Self::Search { depth } => ("search", depth),
+            Self::Rescore { depth } => ("rescore", depth),
+            Self::Tablebase => ("tablebase", None),
+        };
+
+        match depth {
+            Some(depth) => write!(f, "{name}:d{depth}"),
+            None => f.write_str(name),
+        }
+    }
+}
+
+impl FromStr for ScoreSource {
+    type Err = AnnotationError;
+
+    fn from_str(tag: &str) -> Result<Self, AnnotationError> {
+        let unknown = || AnnotationError::UnknownTag(tag.to_string());
+
+        let (name, depth) = match tag.split_once(":d") {
+            Some((name, depth)) => (name, Some(depth.parse().map_err(|_| unknown())?)),
+            None => (tag, None),
+        };
+
+        match (name, depth) {
+            ("search", depth) => Ok(Self::Search { depth }),
+            ("rescore", depth) => Ok(Self::Rescore { depth }),
+            ("tablebase", None) => Ok(Self::Tablebase),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl fmt::Display for ResultSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Played => "played",
+            Self::Adjudicated => "adjudicated",
+        })
+    }
+}
+
+impl FromStr for ResultSource {
+    type Err = AnnotationError;
+
+    fn from_str(tag: &str) -> Result<Self, AnnotationError> {
+        match tag {
+            "played" => Ok(Self::Played),
+            "adjudicated" => Ok(Self::Adjudicated),
+            _ => Err(AnnotationError::UnknownTag(tag.to_string())),
+        }
+    }
+}
+
+/// Values stored with an entry, each optional
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotation {
+    /// Depth of the search that gave the score
+    pub depth: Option<u32>,
+    /// Selective depth of that search
+    pub seldepth: Option<u32>,
+    /// Nodes the search visited
+    pub nodes: Option<u64>,
+    /// Moves in its principal variation
+    pub pv_length: Option<u32>,
+    /// Anything else, stored as it is
+    pub extra: Vec<u8>,
+    pub score_source: Option<ScoreSource>,
+    pub result_source: Option<ResultSource>,
+}
+
+impl Annotation {
+    /// Whether no value is set, the annotation of an entry written without
+    /// one
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        let mut fields = 0;
+        for (present, field) in [
+            (self.depth.is_some(), DEPTH),
+            (self.seldepth.is_some(), SELDEPTH),
+            (self.nodes.is_some(), NODES),
+            (self.pv_length.is_some(), PV_LENGTH),
+            (!self.extra.is_empty(), EXTRA),
+            (self.score_source.is_some(), SCORE_SOURCE),
+            (self.result_source.is_some(), RESULT_SOURCE),
+        ] {
+            if present {
+                fields |= field;
+            }
+        }
+        out.push(fields);
+
+        for value in [
+            self.depth.map(u64::from),
+            self.seldepth.map(u64::from),
+            self.nodes,
+            self.pv_length.map(u64::from),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            write_varint(out, value);
+        }
+
+        if !self.extra.is_empty() {
+            write_varint(out, self.extra.len() as u64);
+            out.extend_from_slice(&self.extra);
+        }
+
+        if let Some(source) = self.score_source {
+            let (kind, depth) = match source {
+                ScoreSource::Search { depth } => (0, Some(depth)),
+                ScoreSource::Rescore { depth } => (1, Some(depth)),
+                ScoreSource::Tablebase => (2, None),
+            };
+            write_varint(out, kind);
+            if let Some(depth) = depth {
+                write_varint(out, depth.map_or(0, |depth| u64::from(depth) + 1));
+            }
+        }
+
+        if let Some(source) = self.result_source {
+            write_varint(out, source as u64);
+        }
+    }
+
+    fn decode(data: &mut &[u8]) -> Result<Self, AnnotationError> {
+        let (&fields, rest) = data.split_first().ok_or(AnnotationError::Truncated)?;
+        *data = rest;
+
+        let known = DEPTH | SELDEPTH | NODES | PV_LENGTH | EXTRA | SCORE_SOURCE | RESULT_SOURCE;
+        let unknown = fields & !known;
+        if unknown != 0 {
+            return Err(AnnotationError::UnknownFields(unknown));
+        }
+
+        let value = |data: &mut &[u8], field| -> Result<Option<u64>, AnnotationError> {
+            (fields & field != 0).then(|| read_varint(data)).transpose()
+        };
+        let small = |value: Option<u64>| {
+            value
+                .map(|value| u32::try_from(value).map_err(|_| AnnotationError::Overflow))
+                .transpose()
+        };
+
+        let depth = small(value(data, DEPTH)?)?;
+        let seldepth = small(value(data, SELDEPTH)?)?;
+        let nodes = value(data, NODES)?;
+        let pv_length = small(value(data, PV_LENGTH)?)?;
+        let extra = match value(data, EXTRA)? {
+            Some(len) => {
+                let len = usize::try_from(len).map_err(|_| AnnotationError::Overflow)?;
+                if len > data.len() {
+                    return Err(AnnotationError::Truncated);
+                }
+                let (extra, rest) = data.split_at(len);
+                *data = rest;
+                extra.to_vec()
+            }
+            None => Vec::new(),
+        };
+
+        let score_source = match value(data, SCORE_SOURCE)? {
+            Some(kind) => {
+                let depth = |data: &mut &[u8]| match read_varint(data)? {
+                    0 => Ok(None),
+                    depth => small(Some(depth - 1)),
+                };
+                Some(match kind {
+                    0 => ScoreSource::Search {
+                        depth: depth(data)?,
+                    },
+                    1 => ScoreSource::Rescore {
+                        depth: depth(data)?,
+                    },
+                    2 => ScoreSource::Tablebase,
+                    _ => return Err(AnnotationError::UnknownTag(format!("score source {kind}"))),
+                })
+            }
+            None => None,
+        };
+        let result_source = match value(data, RESULT_SOURCE)? {
+            Some(0) => Some(ResultSource::Played),
+            Some(1) => Some(ResultSource::Adjudicated),
+            Some(kind) => return Err(AnnotationError::UnknownTag(format!("result source {kind}"))),
+            None => None,
+        };
+
+        Ok(Self {
+            depth,
+            seldepth,
+            nodes,
+            pv_length,
+            extra,
+            score_source,
+            result_source,
+        })
+    }
+}
+
+/// The data of an extension chunk for `count` entries whose annotations
+/// are encoded in `annotations`
+pub(crate) fn encode_chunk(count: u64, annotations: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(annotations.len() + 10);
+    write_varint(&mut data, count);
+    data.extend_from_slice(annotations);
+    data
+}
+
+/// The annotations in the data of an extension chunk, trailer excluded
+pub(crate) fn decode_chunk(mut data: &[u8]) -> Result<Vec<Annotation>, AnnotationError> {
+    let count = read_varint(&mut data)?;
+
+    // every annotation takes at least a byte
+    if count > data.len() as u64 {
+        return Err(AnnotationError::Truncated);
+    }
+
+    let annotations = (0..count)
+        .map(|_| Annotation::decode(&mut data))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !data.is_empty() {
+        return Err(AnnotationError::TrailingBytes(data.len()));
+    }
+
+    Ok(annotations)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, AnnotationError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or(AnnotationError::Truncated)?;
+        *data = rest;
+
+        let bits = u64::from(byte & 0x7F);
+        if shift == 63 && bits > 1 {
+            return Err(AnnotationError::Overflow);
+        }
+        value |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(AnnotationError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(annotations: &[Annotation]) -> Vec<Annotation> {
+        let mut encoded = Vec::new();
+        for annotation in annotations {
+            annotation.encode(&mut encoded);
+        }
+        decode_chunk(&encode_chunk(annotations.len() as u64, &encoded)).unwrap()
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            let mut slice = &data[..];
+            assert_eq!(read_varint(&mut slice), Ok(value));
+            assert!(slice.is_empty());
+        }
+
+        assert_eq!(
+            read_varint(&mut &[0x80][..]),
+            Err(AnnotationError::Truncated)
+        );
+        assert_eq!(
+            read_varint(&mut &[0xFF; 10][..]),
+            Err(AnnotationError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_annotations_roundtrip() {
+        let annotations = [
+            Annotation::default(),
+            Annotation {
+                depth: Some(20),
+                seldepth: Some(31),
+                nodes: Some(u64::MAX),
+                pv_length: Some(7),
+                extra: b"multipv 2".to_vec(),
+                score_source: Some(ScoreSource::Rescore { depth: Some(0) }),
+                result_source: Some(ResultSource::Adjudicated),
+            },
+            Annotation {
+                score_source: Some(ScoreSource::Search { depth: None }),
+                ..Default::default()
+            },
+            Annotation {
+                score_source: Some(ScoreSource::Tablebase),
+                result_source: Some(ResultSource::Played),
+                ..Default::default()
+            },
+            Annotation {
+                nodes: Some(1),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(roundtrip(&annotations), annotations);
+        assert!(roundtrip(&[])[..].is_empty());
+
+        // an empty annotation is a single byte
+        let mut encoded = Vec::new();
+        Annotation::default().encode(&mut encoded);
+        assert_eq!(encoded, [0]);
+    }
+
+    #[test]
+    fn test_provenance_tags() {
+        for tag in [
+            "search",
+            "search:d12",
+            "rescore:d0",
+            "tablebase",
+            "played",
+            "adjudicated",
+        ] {
+            let round_trip = match tag.parse::<ScoreSource>() {
+                Ok(source) => source.to_string(),
+                Err(_) => tag.parse::<ResultSource>().unwrap().to_string(),
+            };
+            assert_eq!(round_trip, tag);
+        }
+
+        for tag in [
+            "",
+            "search:",
+            "search:12",
+            "tablebase:d3",
+            "Search",
+            "rescore:d-1",
+        ] {
+            assert!(tag.parse::<ScoreSource>().is_err(), "{tag}");
+        }
+        assert!("draw".parse::<ResultSource>().is_err());
+    }
+
+    #[test]
+    fn test_malformed_annotations() {
+        assert_eq!(decode_chunk(&[]), Err(AnnotationError::Truncated));
+        // more annotations than bytes
+        assert_eq!(decode_chunk(&[3, 0]), Err(AnnotationError::Truncated));
+        assert_eq!(
+            decode_chunk(&[1, 0x80]),
+            Err(AnnotationError::UnknownFields(0x80))
+        );
+        assert!(matches!(
+            decode_chunk(&[1, SCORE_SOURCE, 3]),
+            Err(AnnotationError::UnknownTag(_))
+        ));
+        // a depth past u32
+        assert_eq!(
+            decode_chunk(&[1, DEPTH, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]),
+            Err(AnnotationError::Overflow)
+        );
+        // extra longer than the chunk
+        assert_eq!(
+            decode_chunk(&[1, EXTRA, 5, 1]),
+            Err(AnnotationError::Truncated)
+        );
+        assert_eq!(
+            decode_chunk(&[1, 0, 0]),
+            Err(AnnotationError::TrailingBytes(1))
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Training batches of sparse features, without Python.
+//!
+//! A [`SparseBatch`] holds the active features of each entry for both
+//! perspectives as CSR matrices, a row per entry, next to the targets a
+//! trainer needs. It is plain vectors, so tch-rs, candle, burn or any other
+//! framework can turn it into tensors: [`CsrMatrix::coo_indices`] gives the
+//! indices of a sparse COO tensor and [`CsrMatrix::padded`] the dense
+//! `[rows, width]` layout of frameworks without sparse tensors.
+//!
+//! The crate has no `tch` or `candle` feature and no conversions to
+//! `tch::Tensor` or `candle_core::Tensor`: neither is a dependency, and
+//! adding them would tie the crate to libtorch and to their release
+//! cycles. The conversion is left to the trainer and is a call to their
+//! constructor from a slice and a shape, e.g. a sparse tensor in tch:
+//!
+//! ```ignore
+//! let nnz = batch.white.indices.len() as i64;
+//! let white = Tensor::sparse_coo_tensor_indices_size(
+//!     &Tensor::from_slice(&batch.white.coo_indices()).view([2, nnz]),
+//!     &Tensor::from_slice(&batch.white.values),
+//!     [batch.len() as i64, batch.num_features as i64],
+//!     (Kind::Float, Device::Cpu),
+//!     false,
+//! );
+//! let score = Tensor::from_slice(&batch.score).view([-1, 1]);
+//! ```
+//!
+//! or, candle having no sparse tensors, the padded layout for an embedding
+//! bag:
+//!
+//! ```ignore
+//! let (indices, values) = batch.white.padded(32);
+//! let shape = (batch.len(), 32);
+//! let white = Tensor::from_vec(indices, shape, &device)?;
+//! let white_values = Tensor::from_vec(values, shape, &device)?;
+//! ```
+//!
+//! Reading a file into batches:
+//!
+//! ```
+//! use std::fs::File;
+//! use sfbinpack::{
+//!     batch::SparseBatchBuilder, features::halfkp::HalfKP, CompressedTrainingDataEntryReader,
+//! };
+//!
+//! let file = File::open("test/ep1.binpack").unwrap();
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//! let mut builder = SparseBatchBuilder::new(HalfKP::new().factorized());
+//!
+//! while reader.has_next() {
+//!     builder.push(&reader.next());
+//!     if builder.len() == 1024 {
+//!         let _batch = builder.build();
+//!         // train on the batch
+//!     }
+//! }
+//!
+//! let batch = builder.build();
+//! assert_eq!(batch.len(), 3);
+//! assert_eq!(batch.white.indptr.len(), 4);
+//! ```
+
+use crate::{chess::color::Color, features::FeatureSet, TrainingDataEntry};
+
+/// Features of a batch seen from one side, in compressed sparse row form:
+/// the features of row `i` are `indices[indptr[i]..indptr[i + 1]]` with the
+/// values at the same places
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrMatrix {
+    pub indptr: Vec<i32>,
+    pub indices: Vec<i32>,
+    pub values: Vec<f32>,
+}
+
+impl Default for CsrMatrix {
+    fn default() -> Self {
+        Self {
+            indptr: vec![0],
+            indices: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl CsrMatrix {
+    pub fn rows(&self) -> usize {
+        self.indptr.len() - 1
+    }
+
+    /// Row and column of every value, the `[2, nnz]` indices of a COO sparse
+    /// tensor, rows first: what `tch::Tensor::sparse_coo_tensor_indices_size`
+    /// takes with `values`
+    pub fn coo_indices(&self) -> Vec<i64> {
+        let mut coo = Vec::with_capacity(2 * self.indices.len());
+        for (row, range) in self.indptr.windows(2).enumerate() {
+            coo.extend(std::iter::repeat_n(
+                row as i64,
+                (range[1] - range[0]) as usize,
+            ));
+        }
+        coo.extend(self.indices.iter().map(|&i| i as i64));
+        coo
+    }
+
+    /// Indices and values of every row padded to `width` with -1 and 0,
+    /// row-major `[rows, width]`, for frameworks without sparse tensors such
+    /// as candle, which gather the weights of the indices instead. Features
+    /// past `width` are dropped.
+    pub fn padded(&self, width: usize) -> (Vec<i64>, Vec<f32>) {
+        let mut indices = vec![-1; self.rows() * width];
+        let mut values = vec![0.0; self.rows() * width];
+        for row in 0..self.rows() {
+            let (row_indices, row_values) = self.row(row);
+            let count = row_indices.len().min(width);
+            let offset = row * width;
+            for i in 0..count {
+                indices[offset + i] = row_indices[i] as i64;
+                values[offset + i] = row_values[i];
+            }
+        }
+        (indices, values)
+    }
+
+    /// The indices and values of row `i`
+    pub fn row(&self, i: usize) -> (&[i32], &[f32]) {
+        let range = self.indptr[i] as usize..self.indptr[i + 1] as usize;
+        (&self.indices[range.clone()], &self.values[range])
+    }
+
+    /// Appends the active features of `entry` for `perspective` as a row,
+    /// using `scratch` to collect them
+    fn push_row<F: FeatureSet>(
+        &mut self,
+        features: &F,
+        entry: &TrainingDataEntry,
+        perspective: Color,
+        scratch: &mut (Vec<i32>, Vec<f32>),
+    ) {
+        let (indices, values) = scratch;
+        let count = features.fill_features(&entry.pos, perspective, indices, values);
+        self.indices.extend_from_slice(&indices[..count]);
+        self.values.extend_from_slice(&values[..count]);
+        self.indptr.push(self.indices.len() as i32);
+    }
+}
+
+/// Sparse features and targets of a number of entries, a row per entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseBatch {
+    /// Columns of the feature matrices, real and virtual features
+    pub num_features: usize,
+    pub white: CsrMatrix,
+    pub black: CsrMatrix,
+    /// 1 if white is to move, 0 if black is
+    pub stm: Vec<f32>,
+    /// Game result for the side to move: 0 for a loss, 0.5 for a draw and 1
+    /// for a win
+    pub outcome: Vec<f32>,
+    /// Score for the side to move
+    pub score: Vec<f32>,
+    /// Bucket of the PSQT and layer stack, `(pieces - 1) / 4`
+    pub bucket: Vec<i32>,
+    /// Weight of every entry in the loss, 1 unless pushed with
+    /// [`SparseBatchBuilder::push_weighted`]
+    pub weight: Vec<f32>,
+}
+
+impl SparseBatch {
+    /// A batch of `entries` with the features of `features`
+    pub fn from_entries<'a, F: FeatureSet>(
+        features: F,
+        entries: impl IntoIterator<Item = &'a TrainingDataEntry>,
+    ) -> Self {
+        let mut builder = SparseBatchBuilder::new(features);
+        for entry in entries {
+            builder.push(entry);
+        }
+        builder.build()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stm.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stm.is_empty()
+    }
+}
+
+/// Collects entries into [`SparseBatch`]es with the features of a
+/// [`FeatureSet`]
+#[derive(Debug)]
+pub struct SparseBatchBuilder<F> {
+    features: F,
+    batch: SparseBatch,
+    scratch: (Vec<i32>, Vec<f32>),
+}
+
+impl<F: FeatureSet> SparseBatchBuilder<F> {
+    pub fn new(features: F) -> Self {
+        let max_active = features.max_active_features();
+        let num_features = features.num_features() + features.num_virtual_features();
+
+        Self {
+            features,
+            batch: SparseBatch {
+                num_features,
+                white: CsrMatrix::default(),
+                black: CsrMatrix::default(),
+                stm: Vec::new(),
+                outcome: Vec::new(),
+                score: Vec::new(),
+                bucket: Vec::new(),
+                weight: Vec::new(),
+            },
+            scratch: (vec![-1; max_active], vec![0.0; max_active]),
+        }
+    }
+
+    pub fn features(&self) -> &F {
+        &self.features
+    }
+
+    /// Adds `entry` as the next row, with a weight of 1
+    pub fn push(&mut self, entry: &TrainingDataEntry) {
+        self.push_weighted(entry, 1.0);
+    }
+
+    /// Adds `entry` as the next row with `weight`, e.g. from an
+    /// [`EntryWeight`](crate::filter::EntryWeight)
+    pub fn push_weighted(&mut self, entry: &TrainingDataEntry, weight: f32) {
+        let batch = &mut self.batch;
+        let features = &self.features;
+
+        batch
+            .white
+            .push_row(features, entry, Color::White, &mut self.scratch);
+        batch
+            .black
+            .push_row(features, entry, Color::Black, &mut self.scratch);
+
+        batch
+            .stm
+            .push((entry.pos.side_to_move() == Color::White) as u8 as f32);
+        batch.outcome.push((entry.result as f32 + 1.0) * 0.5);
+        batch.score.push(entry.score as f32);
+
+        let pieces = entry.pos.occupied().count() as i32;
+        batch.bucket.push((pieces - 1).max(0) / 4);
+        batch.weight.push(weight);
+    }
+
+    /// Entries pushed since the last [`build`](Self::build)
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    /// The batch of the entries pushed so far, the builder starts over
+    /// empty
+    pub fn build(&mut self) -> SparseBatch {
+        let empty = SparseBatch {
+            num_features: self.batch.num_features,
+            white: CsrMatrix::default(),
+            black: CsrMatrix::default(),
+            stm: Vec::with_capacity(self.len()),
+            outcome: Vec::with_capacity(self.len()),
+            score: Vec::with_capacity(self.len()),
+            bucket: Vec::with_capacity(self.len()),
+            weight: Vec::with_capacity(self.len()),
+        };
+        std::mem::replace(&mut self.batch, empty)
+    }
+}
+
+impl<'a, F: FeatureSet> Extend<&'a TrainingDataEntry> for SparseBatchBuilder<F> {
+    fn extend<T: IntoIterator<Item = &'a TrainingDataEntry>>(&mut self, entries: T) {
+        for entry in entries {
+            self.push(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        features::{halfka::HalfKAv2Hm, halfkp::HalfKP},
+        testing::entry_from_fen,
+    };
+
+    #[test]
+    fn test_sparse_batch() {
+        let entries = [
+            entry_from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1", "", 120, 1),
+            entry_from_fen(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1",
+                "",
+                -20,
+                0,
+            ),
+            entry_from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "", 0, -1),
+        ];
+
+        let features = HalfKP::new().factorized();
+        let batch = SparseBatch::from_entries(features, &entries);
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.num_features, 40960 + 704);
+
+        // real feature, K and P; 30 real, K and 30 P; K only
+        assert_eq!(batch.white.indptr, [0, 3, 64, 65]);
+        assert_eq!(batch.black.indptr, batch.white.indptr);
+        assert_eq!(
+            batch.white.row(0).0,
+            [4 * 640 + 8, 40960 + 4, 40960 + 64 + 8]
+        );
+        assert_eq!(batch.black.row(2).0, [40960 + 4]);
+        assert!(batch.white.values.iter().all(|&v| v == 1.0));
+
+        let mut indices = [-1; 64];
+        let mut values = [0.0; 64];
+        let count =
+            features.fill_features(&entries[1].pos, Color::Black, &mut indices, &mut values);
+        assert_eq!(batch.black.row(1).0, &indices[..count]);
+
+        assert_eq!(batch.stm, [1.0, 0.0, 1.0]);
+        assert_eq!(batch.outcome, [1.0, 0.5, 0.0]);
+        assert_eq!(batch.score, [120.0, -20.0, 0.0]);
+        assert_eq!(batch.bucket, [0, 7, 0]);
+        assert_eq!(batch.weight, [1.0; 3]);
+    }
+
+    #[test]
+    fn test_tensor_layouts() {
+        let matrix = CsrMatrix {
+            indptr: vec![0, 2, 2, 3],
+            indices: vec![5, 9, 1],
+            values: vec![1.0, 1.0, 0.5],
+        };
+        assert_eq!(matrix.rows(), 3);
+        assert_eq!(matrix.coo_indices(), [0, 0, 2, 5, 9, 1]);
+
+        let (indices, values) = matrix.padded(2);
+        assert_eq!(indices, [5, 9, -1, -1, 1, -1]);
+        assert_eq!(values, [1.0, 1.0, 0.0, 0.0, 0.5, 0.0]);
+        assert_eq!(matrix.padded(1).0, [5, -1, 1]);
+    }
+
+    #[test]
+    fn test_builder_starts_over() {
+        let entries = [
+            entry_from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1", "", 0, 0),
+            entry_from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "", 0, 0),
+        ];
+
+        let mut builder = SparseBatchBuilder::new(HalfKAv2Hm::new());
+        builder.extend(&entries);
+        let first = builder.build();
+        assert!(builder.is_empty());
+
+        builder.push_weighted(&entries[1], 0.25);
+        let second = builder.build();
+        assert_eq!(second.weight, [0.25]);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second.white.row(0), first.white.row(1));
+        assert_eq!(second.white.indptr, [0, 2]);
+
+        let empty = builder.build();
+        assert!(empty.is_empty());
+        assert_eq!(empty.white.indptr, [0]);
+    }
+}
@@ -0,0 +1,156 @@
+use std::str::FromStr;
+
+use crate::error::CliError;
+
+/// Minimal command line parser.
+///
+/// Options are consumed by name (`--lines 10`, `--lines=10` or `-n 10`)
+/// and whatever is left over afterwards are the positional arguments.
+/// Everything after a literal `--` is positional.
+#[derive(Debug)]
+pub struct Args {
+    args: Vec<String>,
+    positional_only: Vec<String>,
+}
+
+impl Args {
+    pub fn new(args: impl IntoIterator<Item = String>) -> Self {
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        let positional_only = match args.iter().position(|arg| arg == "--") {
+            Some(idx) => {
+                let rest = args.split_off(idx + 1);
+                args.pop();
+                rest
+            }
+            None => Vec::new(),
+        };
+
+        Self {
+            args,
+            positional_only,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(std::env::args().skip(1))
+    }
+
+    /// Removes and returns the first argument if it is not an option
+    pub fn subcommand(&mut self) -> Option<String> {
+        match self.args.first() {
+            Some(arg) if !arg.starts_with('-') => Some(self.args.remove(0)),
+            _ => None,
+        }
+    }
+
+    /// Removes every occurrence of a boolean flag, returns whether it was given
+    pub fn flag(&mut self, names: &[&str]) -> bool {
+        let before = self.args.len();
+        self.args.retain(|arg| !names.contains(&arg.as_str()));
+        self.args.len() != before
+    }
+
+    /// Removes an option with a value and parses it, the last occurrence wins
+    pub fn value<T: FromStr>(&mut self, names: &[&str]) -> Result<Option<T>, CliError> {
+        let mut found = None;
+        let mut idx = 0;
+
+        while idx < self.args.len() {
+            let arg = &self.args[idx];
+
+            if names.contains(&arg.as_str()) {
+                if idx + 1 >= self.args.len() {
+                    return Err(CliError::MissingValue(arg.clone()));
+                }
+                let value = self.args.remove(idx + 1);
+                let name = self.args.remove(idx);
+                found = Some((name, value));
+                continue;
+            }
+
+            if let Some((name, value)) = arg.split_once('=') {
+                if names.contains(&name) {
+                    found = Some((name.to_string(), value.to_string()));
+                    self.args.remove(idx);
+                    continue;
+                }
+            }
+
+            idx += 1;
+        }
+
+        match found {
+            Some((name, value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| CliError::InvalidValue { name, value }),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the remaining positional arguments, failing on unknown options
+    pub fn finish(self) -> Result<Vec<String>, CliError> {
+        if let Some(option) = self
+            .args
+            .iter()
+            .find(|arg| arg.starts_with('-') && arg.len() > 1)
+        {
+            return Err(CliError::UnknownOption(option.clone()));
+        }
+
+        let mut positional = self.args;
+        positional.extend(self.positional_only);
+        Ok(positional)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Args {
+        Args::new(list.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_subcommand_and_positionals() {
+        let mut args = args(&["head", "-n", "5", "a.binpack"]);
+
+        assert_eq!(args.subcommand().as_deref(), Some("head"));
+        assert_eq!(args.value::<usize>(&["-n", "--lines"]).unwrap(), Some(5));
+        assert_eq!(args.finish().unwrap(), vec!["a.binpack"]);
+    }
+
+    #[test]
+    fn test_value_forms() {
+        let mut args = args(&["--lines=3", "x", "--lines", "7"]);
+
+        assert_eq!(args.value::<usize>(&["--lines"]).unwrap(), Some(7));
+        assert_eq!(args.finish().unwrap(), vec!["x"]);
+    }
+
+    #[test]
+    fn test_flag_and_separator() {
+        let mut args = args(&["--json", "a", "--", "--not-an-option"]);
+
+        assert!(args.flag(&["--json"]));
+        assert!(!args.flag(&["--quiet"]));
+        assert_eq!(args.finish().unwrap(), vec!["a", "--not-an-option"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            args(&["-n"]).value::<usize>(&["-n"]),
+            Err(CliError::MissingValue(_))
+        ));
+        assert!(matches!(
+            args(&["-n", "x"]).value::<usize>(&["-n"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            args(&["--bogus"]).finish(),
+            Err(CliError::UnknownOption(_))
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use sfbinpack::stats::{Comparison, DatasetStats, Divergence};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::{open_reader, total_size};
+
+pub const USAGE: &str = "binpack-tools compare [--max-divergence D] A B
+
+Compares the score, piece count and result distributions and the positions
+at ply 12 of two binpacks, e.g. a dataset before and after filtering, and
+prints the Jensen-Shannon divergence and total variation distance of each.
+
+Options:
+  --max-divergence D    exit with status 1 if any Jensen-Shannon divergence
+                        is above D, from 0 to 1";
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let max_divergence = match args.value::<f64>(&["--max-divergence"])? {
+        Some(max) if !(0.0..=1.0).contains(&max) => {
+            return Err(CliError::InvalidValue {
+                name: "--max-divergence".to_string(),
+                value: max.to_string(),
+            })
+        }
+        max => max,
+    };
+    let files = args.finish()?;
+
+    let [a, b] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    let (a, b) = (Path::new(a), Path::new(b));
+
+    let mut progress = out.progress(total_size(&[a, b])?);
+    let stats_a = collect(a, &mut progress)?;
+    progress.set_offset(total_size(&[a])?);
+    let stats_b = collect(b, &mut progress)?;
+    progress.finish();
+
+    let comparison = stats_a.compare(&stats_b);
+    out.report(|| to_json(&comparison), || print!("{}", comparison));
+
+    match max_divergence {
+        Some(max) if comparison.max_divergence() > max => {
+            Err(CliError::Diverged(comparison.max_divergence()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn collect(path: &Path, progress: &mut Progress) -> Result<DatasetStats, CliError> {
+    let mut reader = open_reader(path)?;
+    let mut stats = DatasetStats::new();
+
+    while reader.has_next() {
+        stats.add(&reader.next());
+        progress.entry(reader.read_bytes());
+    }
+
+    Ok(stats)
+}
+
+fn divergence_json(divergence: &Divergence) -> Value {
+    object([
+        ("jensen_shannon", divergence.jensen_shannon.into()),
+        ("total_variation", divergence.total_variation.into()),
+    ])
+}
+
+fn to_json(comparison: &Comparison) -> Value {
+    let side = |idx: usize| {
+        let [loss, draw, win] = comparison.result_balance[idx];
+        object([
+            ("entries", comparison.entries[idx].into()),
+            (
+                "results",
+                object([
+                    ("win", win.into()),
+                    ("draw", draw.into()),
+                    ("loss", loss.into()),
+                ]),
+            ),
+            (
+                "distinct_openings",
+                comparison.distinct_openings[idx].into(),
+            ),
+            ("opening_entropy", comparison.opening_entropy[idx].into()),
+        ])
+    };
+
+    object([
+        ("a", side(0)),
+        ("b", side(1)),
+        ("shared_openings", comparison.shared_openings.into()),
+        (
+            "divergence",
+            object([
+                ("score", divergence_json(&comparison.scores)),
+                ("pieces", divergence_json(&comparison.piece_counts)),
+                ("result", divergence_json(&comparison.results)),
+                ("openings", divergence_json(&comparison.openings)),
+            ]),
+        ),
+        ("max_divergence", comparison.max_divergence().into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_ep1_with_itself() {
+        let mut progress = Output::default().progress(0);
+        let path = Path::new("./test/ep1.binpack");
+        let a = collect(path, &mut progress).unwrap();
+        let b = collect(path, &mut progress).unwrap();
+
+        let comparison = a.compare(&b);
+        assert_eq!(comparison.entries, [3, 3]);
+        assert_eq!(comparison.max_divergence(), 0.0);
+
+        let json = to_json(&comparison).to_string();
+        assert!(json.starts_with("{\"a\":{\"entries\":3,"));
+        assert!(json.ends_with(",\"max_divergence\":0}"));
+    }
+}
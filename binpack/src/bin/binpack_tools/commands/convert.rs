@@ -0,0 +1,590 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use sfbinpack::{
+    formats::{
+        bin::{BinReader, BinWriter},
+        epd::{EpdReader, EpdWriter},
+        jsonl::{JsonlReader, JsonlWriter},
+        pgn::{PgnOptions, PgnReader, PgnWriter},
+        plain::{PlainReader, PlainWriter},
+        policy::ScorePolicy,
+        scored::{Column, ScoredOptions, ScoredReader},
+        EntryWrite, FormatError,
+    },
+    pipeline::{
+        perspective::{self, FlipPerspective, Perspective},
+        Pipeline,
+    },
+    CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter, TrainingDataEntry,
+};
+
+use crate::{args::Args, error::CliError, json::object, output::Output};
+
+use super::{score_policy, ScoreOptions};
+
+pub const USAGE: &str = "binpack-tools convert [--from FORMAT] [--to FORMAT] [OPTIONS] IN OUT
+
+Converts training data between formats. Formats are binpack, plain, bin, pgn,
+jsonl and epd, when --from or --to is missing it is taken from the file
+extension (.binpack, .plain/.txt, .bin, .pgn, .jsonl, .epd). The scored
+format, lines of `fen | bestmove | score | result` as rescoring scripts
+write them, can only be read.
+
+Options:
+  --from FORMAT            format of IN
+  --to FORMAT              format of OUT
+  --columns LIST           columns of the scored format, a comma separated
+                           list of fen, move, score, result and skip,
+                           default fen,move,score,result
+  --delimiter CHAR         between the columns of the scored format, default |
+  --white-relative         scores and results of IN are white's, not the side
+                           to move's; not for pgn and epd, which always are
+  --variations DEPTH       also read the variations of pgn games nested up to
+                           DEPTH deep, each as a chain of its own
+  --scored-variations      only the variations with a score comment
+  --threads N              threads parsing the games of pgn input, in the
+                           same order (default: number of cpus)
+  --check-perspective      fail unless scores and results of the first entries,
+                           after --white-relative, follow the material balance
+                           of the side to move rather than of white
+  --cap-mates CAP          replace mate scores with +-CAP
+  --scale-score FROM:TO    convert scores from FROM units per pawn to TO, e.g.
+                           208:100 from Stockfish's internal units to
+                           centipawns, mate scores are left alone
+  --clamp-score MIN:MAX    clamp scores to MIN..=MAX
+  --mates ENCODING         how mate scores and scores beyond them are read
+                           and written: offset, a mate in N plies is
+                           32000 - N (default); clamp:CAP, +-CAP; or none,
+                           no score
+  --score-units N          units per pawn of the scores of the entries, for
+                           the pawns of pgn comments (default 100)
+
+The score options are applied in the order above, after --white-relative.
+--mates applies to both IN and OUT.";
+
+/// Entries checked by --check-perspective before anything is written
+const PERSPECTIVE_SAMPLE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Binpack,
+    Plain,
+    Bin,
+    Pgn,
+    Jsonl,
+    Epd,
+    Scored,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "binpack" => Ok(Format::Binpack),
+            "plain" | "txt" => Ok(Format::Plain),
+            "bin" => Ok(Format::Bin),
+            "pgn" => Ok(Format::Pgn),
+            "jsonl" => Ok(Format::Jsonl),
+            "epd" => Ok(Format::Epd),
+            "scored" => Ok(Format::Scored),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Format {
+    fn from_extension(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+type Entries = Box<dyn Iterator<Item = Result<TrainingDataEntry, FormatError>>>;
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let from = args.value::<String>(&["--from"])?;
+    let to = args.value::<String>(&["--to"])?;
+    let mut scored = scored_options(&mut args)?;
+    let pgn = PgnOptions {
+        variation_depth: args.value::<usize>(&["--variations"])?.unwrap_or(0),
+        scored_variations_only: args.flag(&["--scored-variations"]),
+    };
+    let threads = args
+        .value::<usize>(&["--threads"])?
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let white_relative = args.flag(&["--white-relative"]);
+    let check_perspective = args.flag(&["--check-perspective"]);
+    let scores = ScoreOptions::parse(&mut args)?;
+    let policy = score_policy(&mut args)?;
+    let files = args.finish()?;
+
+    let [input, output] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    let (input, output) = (Path::new(input), Path::new(output));
+
+    let from = format_of(from, "--from", input)?;
+    let to = format_of(to, "--to", output)?;
+
+    // binpacks store scores as they are, the policy applies here
+    let mut pipeline = Pipeline::new();
+    if from == Format::Binpack {
+        pipeline = pipeline.map_score(move |entry| policy.score(entry.score.into()));
+    }
+    if white_relative {
+        match from {
+            Format::Pgn | Format::Epd => {
+                return Err(CliError::Usage(
+                    "--white-relative does not apply to pgn and epd".to_string(),
+                ))
+            }
+            Format::Scored => scored.white_relative = true,
+            _ => pipeline = pipeline.transform(FlipPerspective::new()),
+        }
+    }
+    let mut pipeline = scores.add_to(pipeline);
+    if to == Format::Binpack {
+        pipeline = pipeline.map_score(move |entry| policy.score(entry.score.into()));
+    }
+
+    let total = input.metadata().map_err(CliError::io(input))?.len();
+    let file = File::open(input).map_err(CliError::io(input))?;
+    let file = CountingReader::new(file);
+    let read = file.count.clone();
+    let mut progress = out.progress(total);
+
+    let entries = open_entries(from, input, file, scored, pgn, policy, threads)?;
+    let mut writer = create_writer(to, output, policy)?;
+
+    let format_error = |path: &Path| {
+        let path = path.display().to_string();
+        move |source| CliError::Format { path, source }
+    };
+
+    // held back until the sample is checked
+    let mut pending = Vec::new();
+    let mut checked = !check_perspective;
+    let mut converted: u64 = 0;
+    for entry in entries {
+        let entry = entry.map_err(format_error(input))?;
+        pending.extend(pipeline.apply(entry));
+        converted += 1;
+        progress.entry(read.load(Ordering::Relaxed));
+
+        if !checked {
+            if pending.len() < PERSPECTIVE_SAMPLE {
+                continue;
+            }
+            check_sample(input, &pending)?;
+            checked = true;
+        }
+
+        for entry in pending.drain(..) {
+            writer.write_entry(&entry).map_err(format_error(output))?;
+        }
+    }
+
+    if !checked {
+        check_sample(input, &pending)?;
+    }
+    for entry in &pending {
+        writer.write_entry(entry).map_err(format_error(output))?;
+    }
+
+    writer.finish().map_err(format_error(output))?;
+    progress.finish();
+
+    out.report(
+        || object([("entries", converted.into())]),
+        || println!("converted {} entries", converted),
+    );
+
+    Ok(())
+}
+
+fn format_of(name: Option<String>, option: &str, path: &Path) -> Result<Format, CliError> {
+    match name {
+        Some(name) => name.parse().map_err(|_| CliError::InvalidValue {
+            name: option.to_string(),
+            value: name,
+        }),
+        None => Format::from_extension(path).ok_or_else(|| {
+            CliError::Usage(format!(
+                "cannot tell the format of {} from its extension, use {}",
+                path.display(),
+                option
+            ))
+        }),
+    }
+}
+
+/// Fails if the scores or the results of `sample` come out for white, see
+/// [`perspective::detect`]
+fn check_sample(path: &Path, sample: &[TrainingDataEntry]) -> Result<(), CliError> {
+    let report = perspective::detect(sample);
+
+    for (what, votes) in [("scores", report.scores), ("results", report.results)] {
+        if votes.perspective() == Some(Perspective::White) {
+            return Err(CliError::Perspective {
+                path: path.display().to_string(),
+                what,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// The layout of the scored format from `--columns` and `--delimiter`
+fn scored_options(args: &mut Args) -> Result<ScoredOptions, CliError> {
+    let mut options = ScoredOptions::default();
+
+    if let Some(list) = args.value::<String>(&["--columns"])? {
+        let invalid = || CliError::InvalidValue {
+            name: "--columns".to_string(),
+            value: list.clone(),
+        };
+        options.columns = list
+            .split(',')
+            .map(|name| name.trim().parse::<Column>().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        if !options.columns.contains(&Column::Fen) {
+            return Err(invalid());
+        }
+    }
+
+    if let Some(delimiter) = args.value::<String>(&["--delimiter"])? {
+        let mut chars = delimiter.chars();
+        options.delimiter = match (chars.next(), chars.next()) {
+            (Some(c), None) if c != ' ' => c,
+            _ => {
+                return Err(CliError::InvalidValue {
+                    name: "--delimiter".to_string(),
+                    value: delimiter,
+                })
+            }
+        };
+    }
+
+    Ok(options)
+}
+
+fn open_entries(
+    format: Format,
+    path: &Path,
+    file: CountingReader<File>,
+    scored: ScoredOptions,
+    pgn: PgnOptions,
+    policy: ScorePolicy,
+    threads: usize,
+) -> Result<Entries, CliError> {
+    Ok(match format {
+        Format::Binpack => match CompressedTrainingDataEntryReader::new(file) {
+            Ok(reader) => Box::new(BinpackEntries(reader)),
+            Err(source) => {
+                return Err(CliError::Reader {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        },
+        Format::Plain => Box::new(PlainReader::new(BufReader::new(file)).with_score_policy(policy)),
+        Format::Bin => Box::new(BinReader::new(BufReader::new(file)).with_score_policy(policy)),
+        Format::Pgn => {
+            let reader =
+                PgnReader::with_options(BufReader::new(file), pgn).with_score_policy(policy);
+            if threads > 1 {
+                Box::new(reader.parallel(threads))
+            } else {
+                Box::new(reader)
+            }
+        }
+        Format::Jsonl => Box::new(JsonlReader::new(BufReader::new(file)).with_score_policy(policy)),
+        Format::Epd => Box::new(EpdReader::new(BufReader::new(file)).with_score_policy(policy)),
+        Format::Scored => Box::new(
+            ScoredReader::with_options(BufReader::new(file), scored).with_score_policy(policy),
+        ),
+    })
+}
+
+fn create_writer(
+    format: Format,
+    path: &Path,
+    policy: ScorePolicy,
+) -> Result<Box<dyn EntryWrite>, CliError> {
+    if format == Format::Scored {
+        return Err(CliError::Usage(
+            "the scored format can only be read".to_string(),
+        ));
+    }
+
+    let file = File::create(path).map_err(CliError::io(path))?;
+
+    Ok(match format {
+        Format::Binpack => {
+            let writer = CompressedTrainingDataEntryWriter::new(file).map_err(|source| {
+                CliError::Format {
+                    path: path.display().to_string(),
+                    source: source.into(),
+                }
+            })?;
+            Box::new(writer)
+        }
+        Format::Plain => Box::new(PlainWriter::new(BufWriter::new(file)).with_score_policy(policy)),
+        Format::Bin => Box::new(BinWriter::new(BufWriter::new(file)).with_score_policy(policy)),
+        Format::Pgn => Box::new(PgnWriter::new(BufWriter::new(file)).with_score_policy(policy)),
+        Format::Jsonl => Box::new(JsonlWriter::new(BufWriter::new(file)).with_score_policy(policy)),
+        Format::Epd => Box::new(EpdWriter::new(BufWriter::new(file)).with_score_policy(policy)),
+        Format::Scored => unreachable!(),
+    })
+}
+
+struct BinpackEntries<T: Read + Seek>(CompressedTrainingDataEntryReader<T>);
+
+impl<T: Read + Seek> Iterator for BinpackEntries<T> {
+    type Item = Result<TrainingDataEntry, FormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.has_next().then(|| Ok(self.0.next()))
+    }
+}
+
+/// Keeps track of the position in the input for the progress bar, also
+/// when the input is read on another thread
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = self.inner.seek(pos)?;
+        self.count.store(offset, Ordering::Relaxed);
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_convert_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = Path::new("test/ep1.binpack");
+
+        let mut previous = input.to_path_buf();
+        for ext in ["plain", "bin", "jsonl", "epd", "pgn", "binpack"] {
+            let next = dir.path().join(format!("data.{}", ext));
+            let args = Args::new([previous.display().to_string(), next.display().to_string()]);
+            run(args, &Output::default()).unwrap();
+            previous = next;
+        }
+
+        assert_eq!(fs::read(input).unwrap(), fs::read(previous).unwrap());
+    }
+
+    #[test]
+    fn test_convert_scored() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("data.txt");
+        let output = dir.path().join("data.epd");
+        fs::write(
+            &input,
+            "35;rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1;e2e4;1-0\n",
+        )
+        .unwrap();
+
+        let args = Args::new(
+            [
+                "--from",
+                "scored",
+                "--columns",
+                "score,fen,bestmove,result",
+                "--delimiter",
+                ";",
+                input.to_str().unwrap(),
+                output.to_str().unwrap(),
+            ]
+            .map(String::from),
+        );
+        run(args, &Output::default()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - hmvc 0; fmvn 1; sm e4; ce 35; c9 \"1-0\";\n"
+        );
+
+        for (option, value) in [("--columns", "move,score"), ("--delimiter", "||")] {
+            let args = Args::new(
+                [
+                    option,
+                    value,
+                    "--from",
+                    "scored",
+                    input.to_str().unwrap(),
+                    "out.epd",
+                ]
+                .map(String::from),
+            );
+            assert!(matches!(
+                run(args, &Output::default()),
+                Err(CliError::InvalidValue { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_convert_white_relative() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("white.plain");
+        let output = dir.path().join("out.plain");
+        // black to move a rook down, scored and decided for white
+        let entry =
+            "fen 3k4/8/8/8/8/8/8/R3K3 b - - 0 1\nmove d8d7\nscore 400\nply 1\nresult 1\ne\n";
+        fs::write(&input, entry.repeat(20)).unwrap();
+
+        let convert = |options: &[&str]| {
+            let mut args = options.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            args.push(input.display().to_string());
+            args.push(output.display().to_string());
+            run(Args::new(args), &Output::default())
+        };
+
+        assert!(matches!(
+            convert(&["--check-perspective"]),
+            Err(CliError::Perspective { what: "scores", .. })
+        ));
+
+        convert(&["--white-relative", "--check-perspective"]).unwrap();
+        let text = fs::read_to_string(&output).unwrap();
+        assert_eq!(text.matches("score -400\n").count(), 20);
+        assert_eq!(text.matches("result -1\n").count(), 20);
+
+        assert!(matches!(
+            convert(&["--white-relative", "--from", "epd"]),
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_convert_mates() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("mates.plain");
+        let output = dir.path().join("out.jsonl");
+        let entry = |score: i32| {
+            format!("fen 4k3/8/8/8/8/8/8/R3K3 w - - 0 1\nmove a1a8\nscore {score}\nresult 1\ne\n")
+        };
+        fs::write(&input, entry(31999) + &entry(40000) + &entry(-120)).unwrap();
+
+        let convert = |options: &[&str]| {
+            let mut args = options.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            args.push(input.display().to_string());
+            args.push(output.display().to_string());
+            run(Args::new(args), &Output::default())?;
+            let text = fs::read_to_string(&output).unwrap();
+            Ok::<_, CliError>(
+                text.lines()
+                    .map(|line| {
+                        line.split("\"score\":")
+                            .nth(1)
+                            .unwrap()
+                            .split(',')
+                            .next()
+                            .unwrap()
+                            .to_string()
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(convert(&[]).unwrap(), ["31999", "31753", "-120"]);
+        assert_eq!(
+            convert(&["--mates", "clamp:2000"]).unwrap(),
+            ["2000", "2000", "-120"]
+        );
+        assert_eq!(
+            convert(&["--mates", "none"]).unwrap(),
+            ["32002", "32002", "-120"]
+        );
+        assert!(matches!(
+            convert(&["--mates", "clamp"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            convert(&["--score-units", "0"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_convert_pgn_variations() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("game.pgn");
+        let output = dir.path().join("game.plain");
+        fs::write(
+            &input,
+            "1. e4 {+0.30} e5 (1... c5 {+0.40} 2. Nf3) 2. Nf3 *\n",
+        )
+        .unwrap();
+
+        let convert = |options: &[&str]| {
+            let mut args = options.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            args.push(input.display().to_string());
+            args.push(output.display().to_string());
+            run(Args::new(args), &Output::default()).unwrap();
+            fs::read_to_string(&output)
+                .unwrap()
+                .matches("\ne\n")
+                .count()
+        };
+
+        assert_eq!(convert(&[]), 3);
+        assert_eq!(convert(&["--threads", "1"]), 3);
+        assert_eq!(convert(&["--variations", "1", "--threads", "3"]), 5);
+    }
+
+    #[test]
+    fn test_convert_unknown_format() {
+        let args = Args::new(["--to", "csv", "test/ep1.binpack", "out.csv"].map(String::from));
+        assert!(matches!(
+            run(args, &Output::default()),
+            Err(CliError::InvalidValue { .. })
+        ));
+
+        let args = Args::new(["test/ep1.binpack", "out.csv"].map(String::from));
+        assert!(matches!(
+            run(args, &Output::default()),
+            Err(CliError::Usage(_))
+        ));
+    }
+}
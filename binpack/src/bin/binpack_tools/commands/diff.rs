@@ -0,0 +1,453 @@
+use std::{collections::HashMap, fs::File, path::Path};
+
+use sfbinpack::{CompressedTrainingDataEntryReader, PackedTrainingDataEntry, TrainingDataEntry};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::Output,
+};
+
+use super::{entry_json, format_entry, open_reader};
+
+pub const USAGE: &str = "binpack-tools diff [--by-position] A B
+
+Compares two binpacks and prints the first difference and summary counts.
+Exits with status 1 if the files differ.
+
+By default the entries are compared one by one in file order. With
+--by-position the order does not matter: every entry of B is matched to an
+equal entry of A, and only the entries left over are paired by position and
+reported as different, or as only in one file. A position that occurs in
+many games, like the start position, is matched without regard to which
+game its entries came from.
+
+Options:
+  --by-position    match entries by position instead of by index";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    entries_a: u64,
+    entries_b: u64,
+    equal: u64,
+    different: u64,
+    only_a: u64,
+    only_b: u64,
+}
+
+impl Summary {
+    fn is_identical(&self) -> bool {
+        self.different == 0 && self.only_a == 0 && self.only_b == 0
+    }
+}
+
+#[derive(Debug)]
+struct Difference {
+    /// Index of the entry in A, or in B if it is missing from A
+    index: u64,
+    a: Option<TrainingDataEntry>,
+    b: Option<TrainingDataEntry>,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let by_position = args.flag(&["--by-position"]);
+    let files = args.finish()?;
+
+    let [a, b] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    let (a, b) = (Path::new(a), Path::new(b));
+
+    let (summary, first) = if by_position {
+        diff_by_position(a, b)?
+    } else {
+        diff_exact(a, b)?
+    };
+
+    out.report(
+        || report_json(&summary, first.as_ref()),
+        || {
+            if let Some(first) = &first {
+                print_difference(first);
+                println!();
+            }
+
+            println!("entries in a:    {}", summary.entries_a);
+            println!("entries in b:    {}", summary.entries_b);
+            println!("equal:           {}", summary.equal);
+            println!("different:       {}", summary.different);
+            println!("only in a:       {}", summary.only_a);
+            println!("only in b:       {}", summary.only_b);
+        },
+    );
+
+    if !summary.is_identical() {
+        return Err(CliError::FilesDiffer);
+    }
+
+    Ok(())
+}
+
+fn differing_fields(a: &TrainingDataEntry, b: &TrainingDataEntry) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+
+    if a.pos != b.pos {
+        fields.push("position");
+    }
+    if a.mv != b.mv {
+        fields.push("move");
+    }
+    if a.score != b.score {
+        fields.push("score");
+    }
+    if a.ply != b.ply {
+        fields.push("ply");
+    }
+    if a.result != b.result {
+        fields.push("result");
+    }
+
+    fields
+}
+
+fn print_difference(difference: &Difference) {
+    println!("first difference at entry {}:", difference.index);
+
+    let format = |entry: &Option<TrainingDataEntry>| {
+        entry
+            .as_ref()
+            .map_or_else(|| "<missing>".to_string(), format_entry)
+    };
+    println!("  a: {}", format(&difference.a));
+    println!("  b: {}", format(&difference.b));
+
+    if let (Some(a), Some(b)) = (&difference.a, &difference.b) {
+        println!("  differs in: {}", differing_fields(a, b).join(", "));
+    }
+}
+
+fn report_json(summary: &Summary, first: Option<&Difference>) -> Value {
+    let first = first.map(|difference| {
+        let differs_in = match (&difference.a, &difference.b) {
+            (Some(a), Some(b)) => differing_fields(a, b).into(),
+            _ => Value::Null,
+        };
+
+        object([
+            ("index", difference.index.into()),
+            ("a", difference.a.as_ref().map(entry_json).into()),
+            ("b", difference.b.as_ref().map(entry_json).into()),
+            ("differs_in", differs_in),
+        ])
+    });
+
+    object([
+        ("identical", summary.is_identical().into()),
+        ("entries_a", summary.entries_a.into()),
+        ("entries_b", summary.entries_b.into()),
+        ("equal", summary.equal.into()),
+        ("different", summary.different.into()),
+        ("only_a", summary.only_a.into()),
+        ("only_b", summary.only_b.into()),
+        ("first_difference", first.into()),
+    ])
+}
+
+fn next_entry(reader: &mut CompressedTrainingDataEntryReader<File>) -> Option<TrainingDataEntry> {
+    reader.has_next().then(|| reader.next())
+}
+
+fn diff_exact(a: &Path, b: &Path) -> Result<(Summary, Option<Difference>), CliError> {
+    let mut reader_a = open_reader(a)?;
+    let mut reader_b = open_reader(b)?;
+
+    let mut summary = Summary::default();
+    let mut first = None;
+    let mut index = 0;
+
+    loop {
+        let entry_a = next_entry(&mut reader_a);
+        let entry_b = next_entry(&mut reader_b);
+
+        match (&entry_a, &entry_b) {
+            (None, None) => break,
+            (Some(a), Some(b)) => {
+                summary.entries_a += 1;
+                summary.entries_b += 1;
+
+                if a == b {
+                    summary.equal += 1;
+                } else {
+                    summary.different += 1;
+                }
+            }
+            (Some(_), None) => {
+                summary.entries_a += 1;
+                summary.only_a += 1;
+            }
+            (None, Some(_)) => {
+                summary.entries_b += 1;
+                summary.only_b += 1;
+            }
+        }
+
+        if first.is_none() && entry_a != entry_b {
+            first = Some(Difference {
+                index,
+                a: entry_a,
+                b: entry_b,
+            });
+        }
+        index += 1;
+    }
+
+    Ok((summary, first))
+}
+
+fn diff_by_position(a: &Path, b: &Path) -> Result<(Summary, Option<Difference>), CliError> {
+    let mut summary = Summary::default();
+
+    // the indices of every distinct entry of A, equal entries in B use them
+    // up in any order, so a position that repeats in several games does not
+    // pair entries of different games
+    let mut entries_a: HashMap<[u8; 32], (TrainingDataEntry, Vec<u64>)> = HashMap::new();
+    let mut reader_a = open_reader(a)?;
+
+    while let Some(entry) = next_entry(&mut reader_a) {
+        entries_a
+            .entry(PackedTrainingDataEntry::from_entry(&entry).data)
+            .or_insert_with(|| (entry, Vec::new()))
+            .1
+            .push(summary.entries_a);
+        summary.entries_a += 1;
+    }
+    for (_, indices) in entries_a.values_mut() {
+        indices.reverse();
+    }
+
+    // entries of B without an equal one in A, by position
+    let mut left_b: HashMap<u64, Vec<(u64, TrainingDataEntry)>> = HashMap::new();
+    let mut reader_b = open_reader(b)?;
+
+    while let Some(entry) = next_entry(&mut reader_b) {
+        let matched = entries_a
+            .get_mut(&PackedTrainingDataEntry::from_entry(&entry).data)
+            .and_then(|(_, indices)| indices.pop());
+
+        match matched {
+            Some(_) => summary.equal += 1,
+            None => left_b
+                .entry(entry.pos.zobrist_key())
+                .or_default()
+                .push((summary.entries_b, entry)),
+        }
+        summary.entries_b += 1;
+    }
+
+    // what is left of A, by position, paired in order with what is left of B
+    let mut left_a: HashMap<u64, Vec<(u64, TrainingDataEntry)>> = HashMap::new();
+    for (entry, indices) in entries_a.into_values() {
+        let left = left_a.entry(entry.pos.zobrist_key()).or_default();
+        left.extend(indices.into_iter().map(|index| (index, entry)));
+    }
+
+    let mut first: Option<Difference> = None;
+    let mut note = |difference: Difference| {
+        if first
+            .as_ref()
+            .is_none_or(|first| difference.index < first.index)
+        {
+            first = Some(difference);
+        }
+    };
+
+    for (key, mut left) in left_a {
+        left.sort_unstable_by_key(|(index, _)| *index);
+        let mut others = left_b.remove(&key).unwrap_or_default().into_iter();
+
+        for (index, entry_a) in left {
+            match others.next() {
+                Some((_, entry_b)) => {
+                    summary.different += 1;
+                    note(Difference {
+                        index,
+                        a: Some(entry_a),
+                        b: Some(entry_b),
+                    });
+                }
+                None => {
+                    summary.only_a += 1;
+                    note(Difference {
+                        index,
+                        a: Some(entry_a),
+                        b: None,
+                    });
+                }
+            }
+        }
+
+        left_b.insert(key, others.collect());
+    }
+
+    for (index, entry) in left_b.into_values().flatten() {
+        summary.only_b += 1;
+        note(Difference {
+            index,
+            a: None,
+            b: Some(entry),
+        });
+    }
+
+    Ok((summary, first))
+}
+
+#[cfg(test)]
+mod tests {
+    use sfbinpack::{
+        chess::{position::Position, r#move::Move},
+        CompressedTrainingDataEntryWriter,
+    };
+
+    use super::*;
+
+    /// A game from the start position, every entry with the same score
+    fn game(moves: &[&str], score: i16) -> Vec<TrainingDataEntry> {
+        let mut pos = Position::new();
+        let mut entries = Vec::new();
+
+        for (ply, uci) in moves.iter().enumerate() {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            entries.push(TrainingDataEntry {
+                pos,
+                mv,
+                score,
+                ply: ply as u16,
+                result: 0,
+            });
+            pos.do_move(mv);
+        }
+        entries
+    }
+
+    fn ep1() -> Vec<TrainingDataEntry> {
+        let mut reader = open_reader(Path::new("./test/ep1.binpack")).unwrap();
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    fn write(path: &Path, entries: &[TrainingDataEntry]) {
+        let mut writer =
+            CompressedTrainingDataEntryWriter::new(File::create(path).unwrap()).unwrap();
+        for entry in entries {
+            writer.write_entry(entry).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let ep1 = Path::new("./test/ep1.binpack");
+
+        for (summary, first) in [
+            diff_exact(ep1, ep1).unwrap(),
+            diff_by_position(ep1, ep1).unwrap(),
+        ] {
+            assert!(summary.is_identical());
+            assert_eq!(summary.equal, 3);
+            assert!(first.is_none());
+        }
+    }
+
+    #[test]
+    fn test_diff_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.binpack");
+        let b = dir.path().join("b.binpack");
+
+        let entries = ep1();
+        write(&a, &entries);
+
+        // B: the last entry rescored and the first one dropped
+        let mut changed = entries[1..].to_vec();
+        changed[1].score += 1;
+        write(&b, &changed);
+
+        let (summary, first) = diff_exact(&a, &b).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                entries_a: 3,
+                entries_b: 2,
+                equal: 0,
+                different: 2,
+                only_a: 1,
+                only_b: 0,
+            }
+        );
+        assert_eq!(first.unwrap().index, 0);
+
+        let (summary, first) = diff_by_position(&a, &b).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                entries_a: 3,
+                entries_b: 2,
+                equal: 1,
+                different: 1,
+                only_a: 1,
+                only_b: 0,
+            }
+        );
+
+        let first = first.unwrap();
+        assert_eq!(first.index, 0);
+        assert!(first.b.is_none());
+    }
+
+    #[test]
+    fn test_diff_by_position_reordered_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.binpack");
+        let b = dir.path().join("b.binpack");
+
+        // both games start with 1. e4 from the start position, scored
+        // differently, and share the position after 1. e4 e5 2. Nf3 Nc6
+        let open = game(&["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"], 10);
+        let other = game(&["e2e4", "b8c6", "g1f3", "e7e5", "f1c4"], 20);
+        let again = game(&["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"], 10);
+
+        write(&a, &[open.clone(), other.clone(), again.clone()].concat());
+        write(&b, &[other.clone(), again, open.clone()].concat());
+
+        let (summary, first) = diff_by_position(&a, &b).unwrap();
+        assert!(summary.is_identical(), "{summary:?}");
+        assert_eq!(summary.equal, 15);
+        assert!(first.is_none());
+
+        // a changed entry is the only difference, whatever the order
+        let mut changed = other.clone();
+        changed[0].score = 30;
+        write(&b, &[changed, open.clone(), open].concat());
+
+        let (summary, first) = diff_by_position(&a, &b).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                entries_a: 15,
+                entries_b: 15,
+                equal: 14,
+                different: 1,
+                only_a: 0,
+                only_b: 0,
+            }
+        );
+
+        let first = first.unwrap();
+        assert_eq!(first.index, 5);
+        assert_eq!(first.a.unwrap().score, 20);
+        assert_eq!(first.b.unwrap().score, 30);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use sfbinpack::{
+    filter::{from_fn, EntryFilter, ExcludeError, ExcludePositions},
+    pipeline::{Pipeline, PipelineError},
+    CompressedTrainingDataEntryWriter,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    expr::Expr,
+    json::object,
+    output::{Output, Progress},
+};
+
+use super::{open_reader, total_size, write_meta, ScoreOptions};
+
+pub const USAGE: &str = "binpack-tools filter [--where EXPR] [OPTIONS] IN OUT
+
+Writes the entries of IN for which EXPR is true to a new binpack OUT, e.g.
+
+  binpack-tools filter --where \"abs(score) < 1000 && ply > 20 && !in_check\" in.binpack out.binpack
+
+Fields: score, ply, result, pieces, phase, rule50 (integers) and white,
+in_check, is_capture, is_promotion, is_castle, gives_check (booleans). Functions: abs,
+min and max. Operators: || && == != < <= > >= + - * / % ! and parentheses,
+`and` and `or` can be used in place of && and ||.
+
+Options:
+  --exclude FILE           skip the positions of FILE, a binpack or a file of
+                           FEN or EPD lines, e.g. to keep a test set out
+  --cap-mates CAP          replace mate scores with +-CAP
+  --scale-score FROM:TO    convert scores from FROM units per pawn to TO, e.g.
+                           208:100 from Stockfish's internal units to
+                           centipawns, mate scores are left alone
+  --clamp-score MIN:MAX    clamp scores to MIN..=MAX
+
+The score options are applied in the order above, to the entries written.
+EXPR sees the scores of IN.";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Counts {
+    read: u64,
+    kept: u64,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let text = args.value::<String>(&["-w", "--where"])?;
+    let exclude = args.value::<String>(&["--exclude"])?;
+    let scores = ScoreOptions::parse(&mut args)?;
+    let files = args.finish()?;
+
+    let [input, output] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    if text.is_none() && exclude.is_none() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    // what the sidecar of OUT says the entries were filtered by
+    let description = [
+        text.as_ref().map(|text| format!("--where '{}'", text)),
+        exclude.as_ref().map(|path| format!("--exclude {}", path)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ");
+
+    let expr = match text {
+        Some(text) => Expr::parse(&text).map_err(|source| CliError::Expression { text, source })?,
+        None => Expr::parse("true").unwrap(),
+    };
+    let exclude = match exclude {
+        Some(path) => load_exclude(Path::new(&path))?,
+        None => ExcludePositions::new(),
+    };
+
+    let mut progress = out.progress(total_size(&[input])?);
+    let counts = filter(
+        &expr,
+        exclude,
+        &scores,
+        &description,
+        Path::new(input),
+        Path::new(output),
+        &mut progress,
+    )?;
+    progress.finish();
+
+    out.report(
+        || object([("read", counts.read.into()), ("kept", counts.kept.into())]),
+        || println!("kept {} of {} entries", counts.kept, counts.read),
+    );
+
+    Ok(())
+}
+
+/// Loads the positions of a binpack, or of a file of FEN or EPD lines
+fn load_exclude(path: &Path) -> Result<ExcludePositions, CliError> {
+    let mut exclude = ExcludePositions::new();
+
+    let loaded = if path.extension().is_some_and(|ext| ext == "binpack") {
+        exclude.load_binpack(&mut open_reader(path)?)
+    } else {
+        let file = File::open(path).map_err(CliError::io(path))?;
+        exclude.load_fens(BufReader::new(file))
+    };
+
+    let name = path.display().to_string();
+    match loaded {
+        Ok(_) => Ok(exclude),
+        Err(ExcludeError::Io(source)) => Err(CliError::Io { path: name, source }),
+        Err(ExcludeError::Read(source)) => Err(CliError::Reader { path: name, source }),
+        Err(ExcludeError::InvalidFen { line, fen }) => Err(CliError::Fen {
+            path: name,
+            line,
+            fen,
+        }),
+    }
+}
+
+fn filter(
+    expr: &Expr,
+    exclude: ExcludePositions,
+    scores: &ScoreOptions,
+    description: &str,
+    input: &Path,
+    output: &Path,
+    progress: &mut Progress,
+) -> Result<Counts, CliError> {
+    let mut reader = open_reader(input)?;
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error)?;
+
+    let mut reported = 0;
+    let pipeline = Pipeline::new().filter(exclude.and(from_fn(|entry| expr.matches(entry))));
+    let stats = scores
+        .add_to(pipeline)
+        // chunks nothing is dropped from are copied as they are
+        .copy_chunks(true)
+        .progress(|stats, position| {
+            progress.add(stats.read - reported, position);
+            reported = stats.read;
+        })
+        .run(&mut reader, &mut writer)
+        .map_err(|err| match err {
+            PipelineError::Write(source) => writer_error(source),
+            PipelineError::Read(_) => unreachable!("the pipeline is not checked"),
+        })?;
+
+    let counts = Counts {
+        read: stats.read,
+        kept: stats.written,
+    };
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), Some(description.to_string()))?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use sfbinpack::meta::DatasetMeta;
+
+    use super::*;
+
+    #[test]
+    fn test_filter_ep1() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.binpack");
+
+        let expr = Expr::parse("score < 0 && !in_check").unwrap();
+        let scores = ScoreOptions::default();
+        let mut progress = Output::default().progress(0);
+        let input = Path::new("./test/ep1.binpack");
+        let counts = filter(
+            &expr,
+            ExcludePositions::new(),
+            &scores,
+            "--where 'score < 0 && !in_check'",
+            input,
+            &output,
+            &mut progress,
+        )
+        .unwrap();
+        assert_eq!(counts, Counts { read: 3, kept: 2 });
+
+        let meta = DatasetMeta::read(&output).unwrap().unwrap();
+        assert_eq!((meta.entries, meta.games), (2, 2));
+        assert_eq!(
+            meta.filter.as_deref(),
+            Some("--where 'score < 0 && !in_check'")
+        );
+
+        let expr = Expr::parse("true").unwrap();
+        let copy = dir.path().join("copy.binpack");
+        let counts = filter(
+            &expr,
+            ExcludePositions::new(),
+            &scores,
+            "",
+            &output,
+            &copy,
+            &mut progress,
+        )
+        .unwrap();
+        assert_eq!(counts, Counts { read: 2, kept: 2 });
+    }
+
+    #[test]
+    fn test_filter_score_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.binpack");
+
+        let mut args = Args::new(["--clamp-score", "-1:1"].map(String::from));
+        let scores = ScoreOptions::parse(&mut args).unwrap();
+        let expr = Expr::parse("score < 0").unwrap();
+        let mut progress = Output::default().progress(0);
+        let input = Path::new("./test/ep1.binpack");
+        filter(
+            &expr,
+            ExcludePositions::new(),
+            &scores,
+            "",
+            input,
+            &output,
+            &mut progress,
+        )
+        .unwrap();
+
+        let mut reader = open_reader(&output).unwrap();
+        let mut count = 0;
+        while reader.has_next() {
+            assert_eq!(reader.next().score, -1);
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn test_filter_exclude() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.binpack");
+        let input = Path::new("./test/ep1.binpack");
+
+        let expr = Expr::parse("true").unwrap();
+        let scores = ScoreOptions::default();
+        let mut progress = Output::default().progress(0);
+        let exclude = load_exclude(input).unwrap();
+        let counts = filter(&expr, exclude, &scores, "", input, &output, &mut progress).unwrap();
+        assert_eq!(counts, Counts { read: 3, kept: 0 });
+
+        let fens = dir.path().join("test.epd");
+        std::fs::write(&fens, "not a fen\n").unwrap();
+        assert!(matches!(
+            load_exclude(&fens),
+            Err(CliError::Fen { line: 1, .. })
+        ));
+    }
+}
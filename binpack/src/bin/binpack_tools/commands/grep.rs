@@ -0,0 +1,261 @@
+use std::path::Path;
+
+use sfbinpack::{chess::position::Position, TrainingDataEntry};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::{entry_json, format_entry, open_reader, total_size};
+
+pub const USAGE: &str = "binpack-tools grep --fen FEN [--ignore-counters] [-C N] FILE...
+
+Finds the entries whose position is FEN and prints the file, chunk and entry
+index of each, together with up to N entries (default 2) before and after it
+in the same game. Exits with status 1 if the position was not found.
+
+Positions are compared by their Zobrist key first and then square by square.
+
+Options:
+  --fen FEN            the position to look for; a FEN without the move
+                       counters implies --ignore-counters
+  --ignore-counters    match the position regardless of the halfmove clock
+                       and fullmove number
+  -C, --context N      entries of game context to print around a match";
+
+/// An entry matching the position, with the entries around it in its game
+#[derive(Debug)]
+struct Match {
+    chunk: u64,
+    index: u64,
+    context: Vec<TrainingDataEntry>,
+    /// Index of the matching entry in `context`
+    at: usize,
+}
+
+/// The position looked for, compared by key before anything else
+struct Needle {
+    pos: Position,
+    key: u64,
+    ignore_counters: bool,
+}
+
+impl Needle {
+    fn new(pos: Position, ignore_counters: bool) -> Self {
+        Self {
+            pos,
+            key: pos.zobrist_key(),
+            ignore_counters,
+        }
+    }
+
+    fn matches(&self, pos: &Position) -> bool {
+        if pos.zobrist_key() != self.key {
+            return false;
+        }
+
+        if self.ignore_counters {
+            without_counters(*pos) == without_counters(self.pos)
+        } else {
+            *pos == self.pos
+        }
+    }
+}
+
+fn without_counters(mut pos: Position) -> Position {
+    pos.set_rule50_counter(0);
+    pos.set_ply(0);
+    pos
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let fen = args.value::<String>(&["--fen"])?;
+    let mut ignore_counters = args.flag(&["--ignore-counters"]);
+    let context = args.value::<usize>(&["-C", "--context"])?.unwrap_or(2);
+    let files = args.finish()?;
+
+    let Some(fen) = fen.filter(|_| !files.is_empty()) else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+
+    let full_fen = match fen.split_whitespace().count() {
+        4 => {
+            ignore_counters = true;
+            format!("{} 0 1", fen.trim())
+        }
+        _ => fen.clone(),
+    };
+    let pos = Position::from_fen(&full_fen).map_err(|_| CliError::InvalidValue {
+        name: "--fen".to_string(),
+        value: fen,
+    })?;
+
+    let needle = Needle::new(pos, ignore_counters);
+    let mut progress = out.progress(total_size(&files)?);
+    let mut done = 0;
+    let mut found = 0;
+    let mut matches = Vec::new();
+
+    for file in &files {
+        let path = Path::new(file);
+        progress.set_offset(done);
+
+        for found_match in grep(path, &needle, context, &mut progress)? {
+            // the matches are the data asked for, --quiet does not hide them
+            if out.json {
+                matches.push(match_json(file, &found_match));
+            } else {
+                if found > 0 {
+                    println!();
+                }
+                print_match(path, &found_match);
+            }
+            found += 1;
+        }
+
+        done += total_size(&[path])?;
+    }
+    progress.finish();
+
+    if out.json {
+        println!("{}", object([("matches", Value::Array(matches))]));
+    }
+
+    if found == 0 {
+        return Err(CliError::NotFound);
+    }
+
+    out.info(format!("{} matching entries", found));
+    Ok(())
+}
+
+fn grep(
+    path: &Path,
+    needle: &Needle,
+    context: usize,
+    progress: &mut Progress,
+) -> Result<Vec<Match>, CliError> {
+    let mut matches = Vec::new();
+
+    let mut reader = open_reader(path)?;
+
+    // the entries of the current game with the chunk each was read from
+    let mut game: Vec<(TrainingDataEntry, u64)> = Vec::new();
+    let mut index = 0;
+
+    while reader.has_next() {
+        game.clear();
+        loop {
+            let entry = reader.next();
+            game.push((entry, reader.chain_location().chunk));
+
+            if !(reader.has_next() && reader.is_next_entry_continuation()) {
+                break;
+            }
+        }
+
+        for (offset, (entry, chunk)) in game.iter().enumerate() {
+            if !needle.matches(&entry.pos) {
+                continue;
+            }
+
+            let from = offset.saturating_sub(context);
+            let to = (offset + context + 1).min(game.len());
+
+            matches.push(Match {
+                chunk: *chunk,
+                index: index + offset as u64,
+                context: game[from..to].iter().map(|(entry, _)| *entry).collect(),
+                at: offset - from,
+            });
+        }
+
+        index += game.len() as u64;
+        progress.add(game.len() as u64, reader.read_bytes());
+    }
+
+    Ok(matches)
+}
+
+fn match_json(file: &str, found: &Match) -> Value {
+    object([
+        ("file", file.into()),
+        ("chunk", found.chunk.into()),
+        ("entry", found.index.into()),
+        ("at", found.at.into()),
+        (
+            "context",
+            Value::Array(found.context.iter().map(entry_json).collect()),
+        ),
+    ])
+}
+
+fn print_match(path: &Path, found: &Match) {
+    println!(
+        "{}: chunk {}, entry {}",
+        path.display(),
+        found.chunk,
+        found.index
+    );
+
+    for (idx, entry) in found.context.iter().enumerate() {
+        let marker = if idx == found.at { '>' } else { ' ' };
+        println!("{} {}", marker, format_entry(entry));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress() -> Progress {
+        Output::default().progress(0)
+    }
+
+    fn ep1() -> Vec<TrainingDataEntry> {
+        let mut reader = open_reader(Path::new("./test/ep1.binpack")).unwrap();
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    #[test]
+    fn test_grep() {
+        let entries = ep1();
+        let path = Path::new("./test/ep1.binpack");
+
+        let needle = Needle::new(entries[1].pos, false);
+        let matches = grep(path, &needle, 1, &mut progress()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].chunk, 0);
+        assert_eq!(matches[0].index, 1);
+        assert_eq!(matches[0].context, entries);
+        assert_eq!(matches[0].at, 1);
+
+        let matches = grep(path, &needle, 0, &mut progress()).unwrap();
+        assert_eq!(matches[0].context, [entries[1]]);
+        assert_eq!(matches[0].at, 0);
+    }
+
+    #[test]
+    fn test_grep_counters() {
+        let path = Path::new("./test/ep1.binpack");
+
+        let mut pos = ep1()[2].pos;
+        pos.set_rule50_counter(pos.rule50_counter() + 1);
+
+        assert!(grep(path, &Needle::new(pos, false), 2, &mut progress())
+            .unwrap()
+            .is_empty());
+
+        let matches = grep(path, &Needle::new(pos, true), 2, &mut progress()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].index, 2);
+        assert_eq!(matches[0].at, 2);
+    }
+}
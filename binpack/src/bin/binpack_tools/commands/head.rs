@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::Output,
+};
+
+use super::{entry_json, format_entry, open_reader};
+
+pub const USAGE: &str = "binpack-tools head [-n N] FILE
+
+Prints the first N entries (default 10) as `fen | move | score | ply | result`,
+with --json as a list of objects with these fields.";
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let count = args.value::<u64>(&["-n", "--lines"])?.unwrap_or(10);
+    let files = args.finish()?;
+
+    let [file] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+
+    let path = Path::new(file);
+    let mut entries = Vec::new();
+
+    let mut reader = open_reader(path)?;
+    let mut printed = 0;
+    while printed < count && reader.has_next() {
+        let entry = reader.next();
+        printed += 1;
+
+        // the entries are the data asked for, --quiet does not hide them
+        if out.json {
+            entries.push(entry_json(&entry));
+        } else {
+            println!("{}", format_entry(&entry));
+        }
+    }
+
+    if out.json {
+        println!("{}", object([("entries", Value::Array(entries))]));
+    }
+
+    Ok(())
+}
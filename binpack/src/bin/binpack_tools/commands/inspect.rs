@@ -0,0 +1,326 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use sfbinpack::{analysis::CompressionStats, meta::DatasetMeta};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::{open_reader, total_size};
+
+pub const USAGE: &str = "binpack-tools inspect [--scan] [--compression] FILE...
+
+Prints chunk, entry and game counts and the byte sizes of each file. The
+counts come from the file's .binpack.meta sidecar if it has an up to date
+one, which also tells what wrote the file, and from reading all entries
+otherwise.
+
+Options:
+  --scan           read all entries even if there is a sidecar
+  --compression    also break the size down into chunk headers, stems,
+                   movetext and padding, with the bits per move and score
+                   and the chain lengths
+
+A file with annotation chunks (BINA) or alignment padding chunks (BINZ)
+can only be read by this crate and its tools, Stockfish and nnue-pytorch
+stop at the first such chunk. --compression shows the bytes of both,
+rewriting the file with convert drops them.";
+
+#[derive(Debug, Default)]
+struct Summary {
+    file_size: u64,
+    chunks: u64,
+    entries: u64,
+    games: u64,
+    /// The sidecar the counts were taken from
+    meta: Option<DatasetMeta>,
+    compression: Option<CompressionStats>,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let scan = args.flag(&["--scan"]);
+    let compression = args.flag(&["--compression"]);
+    let files = args.finish()?;
+    if files.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let mut progress = out.progress(total_size(&files)?);
+    let mut summaries = Vec::with_capacity(files.len());
+    let mut done = 0;
+
+    for file in &files {
+        progress.set_offset(done);
+        let mut summary = inspect(Path::new(file), scan, &mut progress)?;
+        if compression {
+            summary.compression = Some(analyze(Path::new(file))?);
+        }
+        done += summary.file_size;
+        summaries.push(summary);
+    }
+    progress.finish();
+
+    out.report(
+        || {
+            let files = files
+                .iter()
+                .zip(&summaries)
+                .map(|(file, summary)| summary_json(file, summary))
+                .collect();
+            object([("files", Value::Array(files))])
+        },
+        || {
+            for (idx, (file, summary)) in files.iter().zip(&summaries).enumerate() {
+                if idx > 0 {
+                    println!();
+                }
+                print_summary(Path::new(file), summary);
+            }
+        },
+    );
+
+    Ok(())
+}
+
+fn inspect(path: &Path, scan: bool, progress: &mut Progress) -> Result<Summary, CliError> {
+    let file_size = path.metadata().map_err(CliError::io(path))?.len();
+    let mut summary = Summary {
+        file_size,
+        ..Summary::default()
+    };
+
+    if !scan {
+        let meta = DatasetMeta::read(path).map_err(|source| CliError::Meta {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        if let Some(meta) = meta {
+            summary.chunks = count_chunks(path)?;
+            summary.entries = meta.entries;
+            summary.games = meta.games;
+            summary.meta = Some(meta);
+            return Ok(summary);
+        }
+    }
+
+    let mut reader = open_reader(path)?;
+
+    while reader.has_next() {
+        // a game starts whenever the next entry is a stem
+        if !reader.is_next_entry_continuation() {
+            summary.games += 1;
+        }
+
+        reader.next();
+        summary.entries += 1;
+        progress.entry(reader.read_bytes());
+    }
+
+    summary.chunks = reader.chunks_read();
+    Ok(summary)
+}
+
+fn analyze(path: &Path) -> Result<CompressionStats, CliError> {
+    let file = File::open(path).map_err(CliError::io(path))?;
+    CompressionStats::analyze(file).map_err(|source| CliError::Reader {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Counts chunks by their headers alone, seeking over their data and not
+/// counting the padding in front of aligned chunks
+fn count_chunks(path: &Path) -> Result<u64, CliError> {
+    let mut file = BufReader::new(File::open(path).map_err(CliError::io(path))?);
+    let mut header = [0u8; 8];
+    let mut chunks = 0;
+
+    loop {
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(chunks),
+            Err(err) => return Err(CliError::io(path)(err)),
+        }
+
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap());
+        file.seek(SeekFrom::Current(size as i64))
+            .map_err(CliError::io(path))?;
+        if &header[..4] != b"BINZ" {
+            chunks += 1;
+        }
+    }
+}
+
+fn print_summary(path: &Path, summary: &Summary) {
+    println!("file:            {}", path.display());
+    println!("size:            {} bytes", summary.file_size);
+    println!("chunks:          {}", summary.chunks);
+    println!("entries:         {}", summary.entries);
+    println!("games:           {}", summary.games);
+
+    if summary.chunks > 0 {
+        println!(
+            "bytes/chunk:     {:.1}",
+            summary.file_size as f64 / summary.chunks as f64
+        );
+    }
+
+    if summary.entries > 0 {
+        println!(
+            "bytes/entry:     {:.3}",
+            summary.file_size as f64 / summary.entries as f64
+        );
+        println!(
+            "entries/game:    {:.1}",
+            summary.entries as f64 / summary.games as f64
+        );
+    }
+
+    if let Some(meta) = &summary.meta {
+        if let Some(generator) = &meta.generator {
+            let version = meta.generator_version.as_deref().unwrap_or("");
+            println!("generator:       {} {}", generator, version);
+        }
+        if let Some(created) = meta.created {
+            println!("created:         {} (unix time)", created);
+        }
+        if let Some(filter) = &meta.filter {
+            println!("filter:          {}", filter);
+        }
+        println!("(counts from the .binpack.meta sidecar)");
+    }
+
+    if let Some(compression) = &summary.compression {
+        println!();
+        println!("{}", compression);
+    }
+}
+
+fn summary_json(file: &str, summary: &Summary) -> Value {
+    object([
+        ("file", file.into()),
+        ("size", summary.file_size.into()),
+        ("chunks", summary.chunks.into()),
+        ("entries", summary.entries.into()),
+        ("games", summary.games.into()),
+        ("from_meta", summary.meta.is_some().into()),
+        (
+            "generator",
+            summary
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.generator.clone())
+                .into(),
+        ),
+        (
+            "generator_version",
+            summary
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.generator_version.clone())
+                .into(),
+        ),
+        (
+            "created",
+            summary.meta.as_ref().and_then(|meta| meta.created).into(),
+        ),
+        (
+            "filter",
+            summary
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.filter.clone())
+                .into(),
+        ),
+        (
+            "compression",
+            summary
+                .compression
+                .as_ref()
+                .map_or(Value::Null, compression_json),
+        ),
+    ])
+}
+
+fn compression_json(stats: &CompressionStats) -> Value {
+    let chain_lengths = stats
+        .chain_lengths
+        .iter()
+        .map(|(&length, &count)| object([("min", length.into()), ("chains", count.into())]))
+        .collect();
+
+    object([
+        ("header_bytes", stats.header_bytes.into()),
+        ("trailer_bytes", stats.trailer_bytes.into()),
+        ("stem_bytes", stats.stem_bytes.into()),
+        ("movetext_bytes", stats.movetext_bytes.into()),
+        ("padding_bytes", stats.padding_bytes.into()),
+        ("annotation_bytes", stats.annotation_bytes.into()),
+        ("move_bits", stats.move_bits.into()),
+        ("score_bits", stats.score_bits.into()),
+        ("bits_per_move", stats.bits_per_move().into()),
+        ("bits_per_score", stats.bits_per_score().into()),
+        ("chain_lengths", Value::Array(chain_lengths)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_ep1() {
+        let mut progress = Output::default().progress(0);
+        let summary = inspect(Path::new("./test/ep1.binpack"), false, &mut progress).unwrap();
+
+        assert_eq!(summary.chunks, 1);
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.games, 1);
+        assert_eq!(summary.file_size, 46);
+        assert!(summary.meta.is_none());
+    }
+
+    #[test]
+    fn test_inspect_meta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ep1.binpack");
+        std::fs::copy("./test/ep1.binpack", &path).unwrap();
+
+        // counts the sidecar makes up are taken over, unless scanning
+        let mut meta = DatasetMeta::new(30, 10).with_generator("gen", "1.0");
+        meta.created = Some(1760486400);
+        meta.write(&path).unwrap();
+
+        let mut progress = Output::default().progress(0);
+        let summary = inspect(&path, false, &mut progress).unwrap();
+        assert_eq!(
+            (summary.chunks, summary.entries, summary.games),
+            (1, 30, 10)
+        );
+
+        let json = summary_json("ep1.binpack", &summary).to_string();
+        assert!(json.contains("\"from_meta\":true,\"generator\":\"gen\""));
+
+        let summary = inspect(&path, true, &mut progress).unwrap();
+        assert_eq!((summary.chunks, summary.entries, summary.games), (1, 3, 1));
+    }
+
+    #[test]
+    fn test_inspect_compression() {
+        let path = Path::new("./test/ep1.binpack");
+        let mut summary = inspect(path, false, &mut Output::default().progress(0)).unwrap();
+        summary.compression = Some(analyze(path).unwrap());
+
+        let json = summary_json("ep1.binpack", &summary).to_string();
+        assert!(json.contains("\"header_bytes\":8,\"trailer_bytes\":0,\"stem_bytes\":34"));
+        assert!(json.contains("\"chain_lengths\":[{\"min\":2,\"chains\":1}]"));
+    }
+}
@@ -0,0 +1,302 @@
+use std::{fs::File, path::Path, str::FromStr};
+
+use sfbinpack::CompressedTrainingDataEntryWriter;
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+    rng::{self, Rng},
+};
+
+use super::{next_game, open_reader, total_size, write_meta};
+
+pub const USAGE: &str = "binpack-tools interleave [--weights W,...] [--seed S] IN... OUT
+
+Mixes the games of several binpacks into one. For every game written a source
+is drawn with probability proportional to its weight, so OUT is a random
+mixture in which the games of each source keep their order.
+
+Options:
+  --weights W,...       one non-negative weight per input (default: equal),
+                        a source with weight 0 is never read
+  --stop-on-exhausted   stop as soon as one source runs out, so the mixture
+                        keeps its proportions to the end; by default the
+                        remaining sources are drained with their weights
+                        renormalized
+  --seed S              seed for a reproducible mixture, random by default";
+
+/// Comma separated list of sampling weights
+#[derive(Debug, Clone, PartialEq)]
+struct Weights(Vec<f64>);
+
+impl FromStr for Weights {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let weights = text
+            .split(',')
+            .map(|weight| weight.trim().parse::<f64>().map_err(|_| ()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if weights
+            .iter()
+            .any(|weight| !(*weight >= 0.0 && weight.is_finite()))
+        {
+            return Err(());
+        }
+
+        Ok(Weights(weights))
+    }
+}
+
+/// Games and entries taken from each source
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Taken {
+    games: u64,
+    entries: u64,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let weights = args.value::<Weights>(&["--weights"])?;
+    let stop_on_exhausted = args.flag(&["--stop-on-exhausted"]);
+    let seed = args.value::<u64>(&["--seed"])?;
+    let mut files = args.finish()?;
+
+    if files.len() < 2 {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+    let output = files.pop().unwrap();
+
+    let weights = match weights {
+        Some(Weights(weights)) if weights.len() == files.len() => weights,
+        Some(Weights(weights)) => {
+            return Err(CliError::Usage(format!(
+                "got {} weights for {} inputs",
+                weights.len(),
+                files.len()
+            )))
+        }
+        None => vec![1.0; files.len()],
+    };
+
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rng::seed_from_time();
+        out.info(format!("seed: {}", seed));
+        seed
+    });
+
+    let inputs = files.iter().map(Path::new).collect::<Vec<_>>();
+    let mut progress = out.progress(total_size(&inputs)?);
+    let taken = interleave(
+        &inputs,
+        &weights,
+        Path::new(&output),
+        stop_on_exhausted,
+        seed,
+        &mut progress,
+    )?;
+    progress.finish();
+
+    out.report(
+        || {
+            let sources = files.iter().zip(&taken).map(|(file, taken)| {
+                object([
+                    ("file", file.as_str().into()),
+                    ("games", taken.games.into()),
+                    ("entries", taken.entries.into()),
+                ])
+            });
+            object([
+                ("seed", seed.into()),
+                ("sources", Value::Array(sources.collect())),
+            ])
+        },
+        || {
+            for (file, taken) in files.iter().zip(&taken) {
+                println!("{}: {} games, {} entries", file, taken.games, taken.entries);
+            }
+        },
+    );
+
+    Ok(())
+}
+
+fn interleave(
+    inputs: &[&Path],
+    weights: &[f64],
+    output: &Path,
+    stop_on_exhausted: bool,
+    seed: u64,
+    progress: &mut Progress,
+) -> Result<Vec<Taken>, CliError> {
+    let mut rng = Rng::new(seed);
+
+    let mut readers = Vec::with_capacity(inputs.len());
+    for (input, &weight) in inputs.iter().zip(weights) {
+        // a reader that is never drawn from might as well be exhausted, an
+        // empty input is one from the start
+        let reader = if weight > 0.0 {
+            Some(open_reader(input)?).filter(|reader| reader.has_next())
+        } else {
+            None
+        };
+        readers.push(reader);
+    }
+
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error)?;
+
+    let mut taken = vec![Taken::default(); inputs.len()];
+    // bytes read from each source, the progress is their sum
+    let mut read = vec![0; inputs.len()];
+    let mut game = Vec::new();
+
+    loop {
+        let live = readers.iter().map(Option::is_some);
+        let total = weights
+            .iter()
+            .zip(live)
+            .filter_map(|(&weight, live)| live.then_some(weight))
+            .sum::<f64>();
+        if total <= 0.0 {
+            break;
+        }
+
+        let source = pick(&mut rng, weights, &readers, total);
+        let reader = readers[source].as_mut().expect("picked a live source");
+
+        if next_game(reader, &mut game) {
+            for entry in &game {
+                writer.write_entry(entry).map_err(writer_error)?;
+            }
+            taken[source].games += 1;
+            taken[source].entries += game.len() as u64;
+
+            read[source] = reader.read_bytes();
+            progress.add(game.len() as u64, read.iter().sum());
+        }
+
+        if !reader.has_next() {
+            readers[source] = None;
+            if stop_on_exhausted {
+                break;
+            }
+        }
+    }
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+    Ok(taken)
+}
+
+/// Draws the index of a live source, `total` is the sum of their weights
+fn pick<T>(rng: &mut Rng, weights: &[f64], sources: &[Option<T>], total: f64) -> usize {
+    let mut target = rng.next_f64() * total;
+    let mut last = 0;
+
+    for (idx, (&weight, source)) in weights.iter().zip(sources).enumerate() {
+        if source.is_none() || weight <= 0.0 {
+            continue;
+        }
+        if target < weight {
+            return idx;
+        }
+        target -= weight;
+        last = idx;
+    }
+
+    // rounding can leave `target` just above the last weight
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use sfbinpack::TrainingDataEntry;
+
+    use super::*;
+
+    /// Writes `games` copies of the game in ep1 with `offset` added to the scores
+    fn write_source(path: &Path, games: usize, offset: i16) {
+        let mut source = open_reader(Path::new("./test/ep1.binpack")).unwrap();
+        let mut game = Vec::new();
+        next_game(&mut source, &mut game);
+
+        let mut writer =
+            CompressedTrainingDataEntryWriter::new(File::create(path).unwrap()).unwrap();
+        for _ in 0..games {
+            for entry in &game {
+                let entry = TrainingDataEntry {
+                    score: entry.score + offset,
+                    ..*entry
+                };
+                writer.write_entry(&entry).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_weights() {
+        assert_eq!("0.7,0.3".parse(), Ok(Weights(vec![0.7, 0.3])));
+        assert_eq!("1, 2 ,0".parse(), Ok(Weights(vec![1.0, 2.0, 0.0])));
+        assert!("0.5,".parse::<Weights>().is_err());
+        assert!("-1,1".parse::<Weights>().is_err());
+        assert!("inf".parse::<Weights>().is_err());
+    }
+
+    #[test]
+    fn test_interleave() {
+        let mut progress = Output::default().progress(0);
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.binpack");
+        let b = dir.path().join("b.binpack");
+        let output = dir.path().join("out.binpack");
+        write_source(&a, 10, 0);
+        write_source(&b, 5, 1000);
+
+        let inputs = [a.as_path(), b.as_path()];
+
+        let taken = interleave(&inputs, &[0.7, 0.3], &output, false, 3, &mut progress).unwrap();
+        assert_eq!(
+            taken,
+            [
+                Taken {
+                    games: 10,
+                    entries: 30
+                },
+                Taken {
+                    games: 5,
+                    entries: 15
+                }
+            ]
+        );
+
+        // games stay whole and both sources show up
+        let mut reader = open_reader(&output).unwrap();
+        let mut game = Vec::new();
+        let mut from_b = 0;
+        while next_game(&mut reader, &mut game) {
+            assert_eq!(game.len(), 3);
+            if game[0].score > 500 {
+                from_b += 1;
+            }
+        }
+        assert_eq!(from_b, 5);
+
+        let again = interleave(&inputs, &[0.7, 0.3], &output, false, 3, &mut progress).unwrap();
+        assert_eq!(taken, again);
+
+        let taken = interleave(&inputs, &[1.0, 0.0], &output, false, 3, &mut progress).unwrap();
+        assert_eq!(taken[1], Taken::default());
+        assert_eq!(taken[0].games, 10);
+
+        let taken = interleave(&inputs, &[0.5, 0.5], &output, true, 3, &mut progress).unwrap();
+        assert!(taken[0].games == 10 || taken[1].games == 5);
+        assert!(taken[0].games + taken[1].games < 15);
+    }
+}
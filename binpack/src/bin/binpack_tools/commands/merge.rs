@@ -0,0 +1,127 @@
+use std::{fs::File, path::Path};
+
+use sfbinpack::{shard, CompressedTrainingDataEntryWriter};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::{open_reader, total_size, write_meta};
+
+pub const USAGE: &str = "binpack-tools merge OUT IN...
+
+Concatenates the binpacks IN into OUT, games stay intact.";
+
+pub fn run(args: Args, out: &Output) -> Result<(), CliError> {
+    let files = args.finish()?;
+
+    let [output, inputs @ ..] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    if inputs.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let output = Path::new(output);
+    let mut progress = out.progress(total_size(inputs)?);
+    let counts = merge(output, inputs, &mut progress)?;
+    progress.finish();
+
+    let size = total_size(&[output])?;
+    let total = counts.iter().sum::<u64>();
+
+    out.report(
+        || {
+            let inputs = inputs
+                .iter()
+                .zip(&counts)
+                .map(|(input, entries)| {
+                    object([
+                        ("file", input.as_str().into()),
+                        ("entries", (*entries).into()),
+                    ])
+                })
+                .collect();
+
+            object([
+                ("inputs", Value::Array(inputs)),
+                (
+                    "output",
+                    object([
+                        ("file", output.display().to_string().into()),
+                        ("entries", total.into()),
+                        ("bytes", size.into()),
+                    ]),
+                ),
+            ])
+        },
+        || {
+            for (input, entries) in inputs.iter().zip(&counts) {
+                println!("{}: {} entries", input, entries);
+            }
+            println!("{}: {} entries, {} bytes", output.display(), total, size);
+        },
+    );
+
+    Ok(())
+}
+
+/// Returns the number of entries taken from each input
+fn merge(output: &Path, inputs: &[String], progress: &mut Progress) -> Result<Vec<u64>, CliError> {
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error)?;
+
+    let mut counts = Vec::with_capacity(inputs.len());
+    let mut done = 0;
+
+    for input in inputs {
+        let reader = open_reader(Path::new(input))?;
+        let entries = shard::merge([reader], &mut writer).map_err(writer_error)?;
+        counts.push(entries);
+
+        // the entries are copied in one go, so progress is per input
+        done += total_size(&[input])?;
+        progress.add(entries, done);
+    }
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_ep1() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("merged.binpack");
+        let empty = dir.path().join("empty.binpack");
+        File::create(&empty).unwrap();
+
+        let inputs = [
+            "./test/ep1.binpack".to_string(),
+            empty.display().to_string(),
+            "./test/ep1.binpack".to_string(),
+        ];
+        let mut progress = Output::default().progress(0);
+        assert_eq!(merge(&output, &inputs, &mut progress).unwrap(), [3, 0, 3]);
+
+        let mut reader = open_reader(&output).unwrap();
+        let mut entries = 0;
+        while reader.has_next() {
+            reader.next();
+            entries += 1;
+        }
+        assert_eq!(entries, 6);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use sfbinpack::{
+    formats::policy::{MateEncoding, ScorePolicy},
+    meta::DatasetMeta,
+    pipeline::{
+        score::{CapMateScores, ClampScore, ScaleScore},
+        Pipeline,
+    },
+    BufferPool, CompressedTrainingDataEntryReader, ReaderOptions, TrainingDataEntry, WriterStats,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+};
+
+pub mod compare;
+pub mod convert;
+pub mod diff;
+pub mod filter;
+pub mod grep;
+pub mod head;
+pub mod inspect;
+pub mod interleave;
+pub mod merge;
+pub mod repair;
+pub mod rescore;
+pub mod sample;
+pub mod serve;
+pub mod shuffle;
+pub mod split;
+pub mod stats;
+pub mod validate;
+pub mod verify;
+
+/// Opens a binpack for reading. Readers share their chunk buffers, commands
+/// going through many files reuse them.
+pub fn open_reader(path: &Path) -> Result<CompressedTrainingDataEntryReader<File>, CliError> {
+    let file = File::open(path).map_err(CliError::io(path))?;
+
+    CompressedTrainingDataEntryReader::with_options(file, reader_options()).map_err(|source| {
+        CliError::Reader {
+            path: path.display().to_string(),
+            source,
+        }
+    })
+}
+
+/// Options of the readers of open_reader(), for readers opened otherwise
+pub fn reader_options() -> ReaderOptions {
+    static POOL: OnceLock<BufferPool> = OnceLock::new();
+
+    ReaderOptions {
+        buffer_pool: Some(POOL.get_or_init(BufferPool::new).clone()),
+        ..Default::default()
+    }
+}
+
+/// Writes the `.binpack.meta` sidecar of a binpack the command wrote,
+/// `filter` describes what the command kept
+pub fn write_meta(
+    path: &Path,
+    stats: &WriterStats,
+    filter: Option<String>,
+) -> Result<(), CliError> {
+    let mut meta = dataset_meta(stats);
+    meta.filter = filter;
+    save_meta(path, &meta)
+}
+
+/// The sidecar of a binpack the command wrote, for commands that add more
+/// to it than write_meta() does
+pub fn dataset_meta(stats: &WriterStats) -> DatasetMeta {
+    DatasetMeta::from_stats(stats).with_generator(env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+pub fn save_meta(path: &Path, meta: &DatasetMeta) -> Result<(), CliError> {
+    meta.write(path).map_err(|source| CliError::Meta {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Combined size of the files, the total of a progress bar over all of them
+pub fn total_size<P: AsRef<Path>>(paths: &[P]) -> Result<u64, CliError> {
+    paths.iter().try_fold(0, |total, path| {
+        let path = path.as_ref();
+        Ok(total + path.metadata().map_err(CliError::io(path))?.len())
+    })
+}
+
+/// Reads the entries of the next game into `game`, returns false once the
+/// reader is exhausted
+pub fn next_game<T: Read + Seek>(
+    reader: &mut CompressedTrainingDataEntryReader<T>,
+    game: &mut Vec<TrainingDataEntry>,
+) -> bool {
+    game.clear();
+
+    if !reader.has_next() {
+        return false;
+    }
+
+    game.push(reader.next());
+    while reader.has_next() && reader.is_next_entry_continuation() {
+        game.push(reader.next());
+    }
+
+    true
+}
+
+/// One line per entry: `fen | move | score | ply | result`
+pub fn format_entry(entry: &TrainingDataEntry) -> String {
+    let fen = entry
+        .pos
+        .fen()
+        .unwrap_or_else(|_| "<invalid position>".to_string());
+
+    format!(
+        "{} | {} | {} | {} | {}",
+        fen,
+        entry.mv.as_uci(),
+        entry.score,
+        entry.ply,
+        entry.result
+    )
+}
+
+/// The fields of `format_entry` as a JSON object
+pub fn entry_json(entry: &TrainingDataEntry) -> Value {
+    object([
+        ("fen", entry.pos.fen().ok().into()),
+        ("move", entry.mv.as_uci().into()),
+        ("score", entry.score.into()),
+        ("ply", entry.ply.into()),
+        ("result", entry.result.into()),
+    ])
+}
+
+/// Score transforms asked for with `--cap-mates`, `--scale-score` and
+/// `--clamp-score`, applied in that order
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScoreOptions {
+    cap_mates: Option<CapMateScores>,
+    scale: Option<ScaleScore>,
+    clamp: Option<ClampScore>,
+}
+
+impl ScoreOptions {
+    pub fn parse(args: &mut Args) -> Result<Self, CliError> {
+        let invalid = |name: &str, value: String| CliError::InvalidValue {
+            name: name.to_string(),
+            value,
+        };
+
+        let cap_mates = match args.value::<i16>(&["--cap-mates"])? {
+            Some(cap) if cap < 0 => return Err(invalid("--cap-mates", cap.to_string())),
+            cap => cap.map(CapMateScores::new),
+        };
+
+        let scale = match args.value::<Pair<i32>>(&["--scale-score"])? {
+            Some(Pair(from, to)) if from <= 0 || to < 0 => {
+                return Err(invalid("--scale-score", format!("{from}:{to}")))
+            }
+            scale => scale.map(|Pair(from, to)| ScaleScore::new(from, to)),
+        };
+
+        let clamp = match args.value::<Pair<i16>>(&["--clamp-score"])? {
+            Some(Pair(min, max)) if min > max => {
+                return Err(invalid("--clamp-score", format!("{min}:{max}")))
+            }
+            clamp => clamp.map(|Pair(min, max)| ClampScore::new(min, max)),
+        };
+
+        Ok(Self {
+            cap_mates,
+            scale,
+            clamp,
+        })
+    }
+
+    /// Appends the transforms to `pipeline`
+    pub fn add_to<'a>(&self, mut pipeline: Pipeline<'a>) -> Pipeline<'a> {
+        if let Some(cap_mates) = self.cap_mates {
+            pipeline = pipeline.transform(cap_mates);
+        }
+        if let Some(scale) = self.scale {
+            pipeline = pipeline.transform(scale);
+        }
+        if let Some(clamp) = self.clamp {
+            pipeline = pipeline.transform(clamp);
+        }
+        pipeline
+    }
+}
+
+/// How scores are read and written, from `--mates` and `--score-units`
+pub fn score_policy(args: &mut Args) -> Result<ScorePolicy, CliError> {
+    let mut policy = ScorePolicy::default();
+
+    if let Some(mates) = args.value::<MateEncoding>(&["--mates"])? {
+        policy.mates = mates;
+    }
+
+    match args.value::<i32>(&["--score-units"])? {
+        Some(units) if units <= 0 => {
+            return Err(CliError::InvalidValue {
+                name: "--score-units".to_string(),
+                value: units.to_string(),
+            })
+        }
+        Some(units) => policy.units = units,
+        None => {}
+    }
+
+    Ok(policy)
+}
+
+/// `A:B`
+struct Pair<T>(T, T);
+
+impl<T: FromStr> FromStr for Pair<T> {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (a, b) = text.split_once(':').ok_or(())?;
+        Ok(Pair(a.parse().map_err(|_| ())?, b.parse().map_err(|_| ())?))
+    }
+}
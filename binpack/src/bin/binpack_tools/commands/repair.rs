@@ -0,0 +1,310 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use sfbinpack::{
+    CompressedReaderError, CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    TrainingDataEntry,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::object,
+    output::{Output, Progress},
+};
+
+use super::{total_size, write_meta};
+
+pub const USAGE: &str = "binpack-tools repair IN OUT
+
+Salvages what can be read from a damaged binpack, typically one truncated by
+a crashed generator. Every chunk is decoded on its own and the chains that
+decode completely are written to OUT. Data that is not a chunk is skipped up
+to the next chunk header, and a chain cut off by the end of the file or of a
+damaged chunk is dropped whole.
+
+Prints how many chunks were intact, how many entries were recovered and how
+many decoded entries were dropped with incomplete chains. Entries in data
+that could not be decoded at all are not counted.";
+
+const HEADER_SIZE: u64 = 8;
+const MAGIC: &[u8; 4] = b"BINP";
+const MAX_CHUNK_SIZE: u64 = 100 * 1024 * 1024;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    intact_chunks: u64,
+    damaged_chunks: u64,
+    recovered: u64,
+    lost: u64,
+    skipped_bytes: u64,
+}
+
+/// The complete chains of one chunk
+#[derive(Debug)]
+struct Salvage {
+    entries: Vec<TrainingDataEntry>,
+    /// Entries decoded from a chain that turned out to be incomplete
+    lost: u64,
+    /// The whole chunk decoded without errors
+    clean: bool,
+}
+
+pub fn run(args: Args, out: &Output) -> Result<(), CliError> {
+    let files = args.finish()?;
+    let [input, output] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+
+    let mut progress = out.progress(total_size(&[input])?);
+    let summary = repair(Path::new(input), Path::new(output), &mut progress)?;
+    progress.finish();
+
+    out.report(
+        || {
+            object([
+                ("intact_chunks", summary.intact_chunks.into()),
+                ("damaged_chunks", summary.damaged_chunks.into()),
+                ("recovered", summary.recovered.into()),
+                ("lost", summary.lost.into()),
+                ("skipped_bytes", summary.skipped_bytes.into()),
+            ])
+        },
+        || {
+            println!(
+                "chunks:          {} intact, {} damaged",
+                summary.intact_chunks, summary.damaged_chunks
+            );
+            println!("recovered:       {} entries", summary.recovered);
+            println!("lost:            {} entries", summary.lost);
+            println!("skipped:         {} bytes", summary.skipped_bytes);
+        },
+    );
+
+    Ok(())
+}
+
+fn repair(input: &Path, output: &Path, progress: &mut Progress) -> Result<Summary, CliError> {
+    let file = File::open(input).map_err(CliError::io(input))?;
+    let len = file.metadata().map_err(CliError::io(input))?.len();
+    let mut file = BufReader::new(file);
+
+    let out = File::create(output).map_err(CliError::io(output))?;
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+    let mut writer = CompressedTrainingDataEntryWriter::new(out).map_err(writer_error)?;
+
+    let mut summary = Summary::default();
+    let mut data = Vec::new();
+    let mut pos = 0;
+
+    while pos < len {
+        let header = read_header(&mut file, pos, len).map_err(CliError::io(input))?;
+
+        let salvage = match header {
+            Some(size) => {
+                let available = size.min(len - pos - HEADER_SIZE);
+                data.resize(available as usize, 0);
+                file.read_exact(&mut data).map_err(CliError::io(input))?;
+
+                let salvage = salvage_chunk(&data);
+                summary.lost += salvage.lost;
+
+                let complete = salvage.clean && available == size;
+                (complete || !salvage.entries.is_empty()).then_some((salvage, complete))
+            }
+            None => None,
+        };
+
+        // without a single good chain the magic was most likely part of
+        // damaged data rather than a chunk header
+        let Some((salvage, complete)) = salvage else {
+            let next = find_magic(&mut file, pos + 1, len).map_err(CliError::io(input))?;
+            summary.skipped_bytes += next - pos;
+            pos = next;
+            continue;
+        };
+
+        for entry in &salvage.entries {
+            writer.write_entry(entry).map_err(writer_error)?;
+        }
+
+        summary.recovered += salvage.entries.len() as u64;
+        if complete {
+            summary.intact_chunks += 1;
+        } else {
+            summary.damaged_chunks += 1;
+        }
+
+        pos += HEADER_SIZE + data.len() as u64;
+        progress.add(salvage.entries.len() as u64, pos);
+    }
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+    Ok(summary)
+}
+
+/// Returns the size of the chunk starting at `pos`, or None if there is no
+/// plausible chunk header
+fn read_header(file: &mut (impl Read + Seek), pos: u64, len: u64) -> io::Result<Option<u64>> {
+    if len - pos < HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let mut header = [0u8; HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(pos))?;
+    file.read_exact(&mut header)?;
+
+    let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+    if &header[..4] != MAGIC || size > MAX_CHUNK_SIZE {
+        return Ok(None);
+    }
+
+    Ok(Some(size))
+}
+
+/// Position of the next chunk magic at or after `from`, `len` if there is none
+fn find_magic(file: &mut (impl Read + Seek), from: u64, len: u64) -> io::Result<u64> {
+    const BLOCK_SIZE: usize = 1 << 20;
+
+    let mut block = vec![0u8; BLOCK_SIZE];
+    let mut start = from;
+
+    while start + MAGIC.len() as u64 <= len {
+        let size = BLOCK_SIZE.min((len - start) as usize);
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut block[..size])?;
+
+        if let Some(idx) = block[..size]
+            .windows(MAGIC.len())
+            .position(|window| window == MAGIC)
+        {
+            return Ok(start + idx as u64);
+        }
+
+        // the magic may straddle two blocks
+        start += (size - MAGIC.len() + 1) as u64;
+    }
+
+    Ok(len)
+}
+
+/// Decodes the chunk `data` and keeps the chains that decode completely
+fn salvage_chunk(data: &[u8]) -> Salvage {
+    let mut salvage = Salvage {
+        entries: Vec::new(),
+        lost: 0,
+        clean: true,
+    };
+
+    let mut buffer = Vec::with_capacity(HEADER_SIZE as usize + data.len());
+    buffer.extend_from_slice(MAGIC);
+    buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(data);
+
+    let mut reader = match CompressedTrainingDataEntryReader::new(Cursor::new(buffer)) {
+        Ok(reader) => reader,
+        Err(_) => {
+            salvage.clean = false;
+            return salvage;
+        }
+    };
+
+    let mut chain = Vec::new();
+
+    while reader.has_next() {
+        let continuation = reader.is_next_entry_continuation();
+
+        match panic::catch_unwind(AssertUnwindSafe(|| reader.try_next())) {
+            Ok(Ok(entry)) => {
+                if !continuation {
+                    salvage.entries.append(&mut chain);
+                }
+                chain.push(entry);
+            }
+            Ok(Err(CompressedReaderError::EndOfFile)) => break,
+            _ => {
+                // an error at a stem means the chain before it was complete
+                if continuation {
+                    salvage.lost += chain.len() as u64;
+                    chain.clear();
+                }
+                salvage.clean = false;
+                break;
+            }
+        }
+    }
+
+    salvage.entries.append(&mut chain);
+    salvage
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::commands::open_reader;
+
+    fn read_all(path: &Path) -> Vec<TrainingDataEntry> {
+        let mut entries = Vec::new();
+        let mut reader = open_reader(path).unwrap();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    #[test]
+    fn test_repair_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.binpack");
+        let input = Path::new("./test/ep1.binpack");
+
+        let summary = repair(input, &output, &mut Output::default().progress(0)).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                intact_chunks: 1,
+                recovered: 3,
+                ..Summary::default()
+            }
+        );
+        assert_eq!(read_all(&output), read_all(input));
+    }
+
+    #[test]
+    fn test_repair_damaged() {
+        let ep1 = fs::read("./test/ep1.binpack").unwrap();
+
+        // a good chunk, garbage, another good chunk and a truncated one
+        let mut data = ep1.clone();
+        data.extend_from_slice(b"garbage BIN");
+        data.extend_from_slice(&ep1);
+        data.extend_from_slice(&ep1[..ep1.len() - 2]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("damaged.binpack");
+        let output = dir.path().join("out.binpack");
+        fs::write(&input, data).unwrap();
+
+        let summary = repair(&input, &output, &mut Output::default().progress(0)).unwrap();
+        assert_eq!(summary.intact_chunks, 2);
+        assert_eq!(summary.damaged_chunks, 0);
+        assert_eq!(summary.recovered, 6);
+        assert!(summary.lost > 0);
+        assert_eq!(summary.skipped_bytes, 11 + ep1.len() as u64 - 2);
+
+        assert_eq!(
+            read_all(&output),
+            read_all(Path::new("./test/ep1.binpack")).repeat(2)
+        );
+    }
+}
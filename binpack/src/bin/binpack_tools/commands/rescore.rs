@@ -0,0 +1,304 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use sfbinpack::{
+    annotation::ScoreSource,
+    engine::{EngineOptions, EnginePool, Limit, BATCH_PER_ENGINE},
+    formats::policy::ScorePolicy,
+    resume::{Resume, ResumeError},
+    CompressedTrainingDataEntryWriter,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::object,
+    output::{Output, Progress},
+};
+
+use super::{dataset_meta, open_reader, reader_options, save_meta, score_policy, total_size};
+
+pub const USAGE: &str = "binpack-tools rescore --engine PATH [options] IN OUT
+
+Analyses every position of IN with a pool of UCI engines and writes the
+entries with the engine's scores to OUT. Scores are from the side to move's
+point of view, a mate in N plies is stored as 32000 - N unless --mates says
+otherwise.
+
+Options:
+  --engine PATH    UCI engine executable
+  --depth N        search depth (default 8)
+  --nodes N        search N nodes per position instead of to a depth
+  --threads N      number of engine processes, each searching with a single
+                   thread (default: number of cpus)
+  --hash MB        hash size of each engine in MiB (default 16)
+  --best-move      also replace the move with the engine's best move; games
+                   whose moves change are no longer chained, so the output
+                   gets larger
+  --resume         record the progress in OUT.progress after every chunk of
+                   IN, and go on from there if the file exists
+  --mates ENCODING how mate scores are stored: offset (default), clamp:CAP
+                   for +-CAP or none for no score
+  --score-units N  units per pawn of the stored scores (default 100)";
+
+#[derive(Debug, Clone)]
+struct Options {
+    engine: PathBuf,
+    limit: Limit,
+    threads: usize,
+    hash: u64,
+    best_move: bool,
+    resume: bool,
+    score_policy: ScorePolicy,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let engine = args.value::<PathBuf>(&["--engine"])?;
+    let depth = args.value::<u32>(&["--depth"])?;
+    let nodes = args.value::<u64>(&["--nodes"])?;
+    let threads = args.value::<usize>(&["--threads"])?;
+    let hash = args.value::<u64>(&["--hash"])?.unwrap_or(16);
+    let best_move = args.flag(&["--best-move"]);
+    let resume = args.flag(&["--resume"]);
+    let score_policy = score_policy(&mut args)?;
+    let files = args.finish()?;
+
+    let ([input, output], Some(engine)) = (files.as_slice(), engine) else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+
+    let limit = match (depth, nodes) {
+        (Some(_), Some(_)) => return Err(CliError::Usage(USAGE.to_string())),
+        (_, Some(nodes)) => Limit::Nodes(nodes),
+        (depth, None) => Limit::Depth(depth.unwrap_or(8)),
+    };
+
+    let threads = threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .max(1);
+
+    let options = Options {
+        engine,
+        limit,
+        threads,
+        hash,
+        best_move,
+        resume,
+        score_policy,
+    };
+
+    let mut progress = out.progress(total_size(&[input])?);
+    let rescored = rescore(Path::new(input), Path::new(output), &options, &mut progress)?;
+    progress.finish();
+
+    out.report(
+        || {
+            object([
+                ("entries", rescored.into()),
+                ("engines", options.threads.into()),
+            ])
+        },
+        || {
+            println!(
+                "rescored {} entries with {} engines",
+                rescored, options.threads
+            )
+        },
+    );
+
+    Ok(())
+}
+
+fn rescore(
+    input: &Path,
+    output: &Path,
+    options: &Options,
+    progress: &mut Progress,
+) -> Result<u64, CliError> {
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+    let resume_error = |source: ResumeError| CliError::Resume {
+        path: output.display().to_string(),
+        source,
+    };
+
+    let (mut resume, mut reader, file) = if options.resume {
+        let (resume, reader, file) =
+            Resume::open(input, output, reader_options()).map_err(resume_error)?;
+        (Some(resume), reader, file)
+    } else {
+        let file = File::create(output).map_err(CliError::io(output))?;
+        (None, open_reader(input)?, file)
+    };
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error)?;
+
+    // bytes of the input converted before the job went on
+    let start = resume
+        .as_ref()
+        .map_or(0, |resume| resume.last_checkpoint().input_offset);
+
+    if !reader.has_next() {
+        writer.flush_and_end();
+        return finish(resume, &writer, output, options.limit);
+    }
+
+    let engine_options = EngineOptions {
+        hash: options.hash,
+        score_policy: options.score_policy,
+        ..Default::default()
+    };
+    let mut pool = EnginePool::spawn(&options.engine, options.threads, &engine_options)
+        .map_err(CliError::io(&options.engine))?
+        .with_best_move(options.best_move);
+
+    let batch_size = options.threads * BATCH_PER_ENGINE;
+    let mut batch = Vec::with_capacity(batch_size);
+
+    while reader.has_next() {
+        match resume.as_mut() {
+            // batches end with the chunks of the input
+            Some(resume) => {
+                resume.read_batch(&mut reader, &mut batch, batch_size);
+            }
+            None => {
+                batch.clear();
+                while batch.len() < batch_size && reader.has_next() {
+                    batch.push(reader.next());
+                }
+            }
+        }
+
+        pool.score_batch(&mut batch, options.limit)
+            .map_err(CliError::io(&options.engine))?;
+
+        for entry in &batch {
+            writer.write_entry(entry).map_err(writer_error)?;
+        }
+        if let Some(resume) = resume.as_mut() {
+            resume.checkpoint(&mut writer).map_err(resume_error)?;
+        }
+        progress.add(batch.len() as u64, start + reader.read_bytes());
+    }
+
+    writer.flush_and_end();
+    finish(resume, &writer, output, options.limit)
+}
+
+/// Writes the sidecar of the output, with every score tagged as rescored,
+/// and removes its progress file, returns the entries in the output
+fn finish(
+    resume: Option<Resume>,
+    writer: &CompressedTrainingDataEntryWriter<File>,
+    output: &Path,
+    limit: Limit,
+) -> Result<u64, CliError> {
+    let stats = match &resume {
+        Some(resume) => resume.stats(&writer.stats()),
+        None => writer.stats(),
+    };
+    let depth = match limit {
+        Limit::Depth(depth) => Some(depth),
+        Limit::Nodes(_) => None,
+    };
+    let meta =
+        dataset_meta(&stats).with_score_source(ScoreSource::Rescore { depth }, stats.entries);
+    save_meta(output, &meta)?;
+
+    if let Some(resume) = resume {
+        resume.finish().map_err(|source| CliError::Resume {
+            path: output.display().to_string(),
+            source,
+        })?;
+    }
+
+    Ok(stats.entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use sfbinpack::TrainingDataEntry;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rescore_with_fake_engine() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = dir.path().join("engine.sh");
+        fs::write(
+            &engine,
+            "#!/bin/sh
+while read -r command rest; do
+  case \"$command\" in
+    uci) echo 'id name fake'; echo uciok ;;
+    isready) echo readyok ;;
+    go) echo 'info depth 1 score cp 10'; echo 'info depth 2 score cp 42'; echo 'bestmove 0000' ;;
+    quit) exit 0 ;;
+  esac
+done
+",
+        )
+        .unwrap();
+        fs::set_permissions(&engine, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let output = dir.path().join("out.binpack");
+        let options = Options {
+            engine,
+            limit: Limit::Depth(2),
+            threads: 2,
+            hash: 1,
+            best_move: true,
+            resume: false,
+            score_policy: ScorePolicy::default(),
+        };
+
+        let input = Path::new("./test/ep1.binpack");
+        let mut progress = Output::default().progress(0);
+        assert_eq!(rescore(input, &output, &options, &mut progress).unwrap(), 3);
+
+        let mut original = open_reader(input).unwrap();
+        let mut rescored = open_reader(&output).unwrap();
+        while original.has_next() {
+            let before = original.next();
+            let after = rescored.next();
+
+            // `0000` is not a legal move, so the move is kept
+            assert_eq!(
+                TrainingDataEntry {
+                    score: 42,
+                    ..before
+                },
+                after
+            );
+        }
+        assert!(!rescored.has_next());
+
+        let meta = sfbinpack::meta::DatasetMeta::read(&output)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            meta.score_sources,
+            [(ScoreSource::Rescore { depth: Some(2) }, 3)].into()
+        );
+
+        // the same with checkpoints, whose progress file is gone at the end
+        let resumable = dir.path().join("resumable.binpack");
+        let options = Options {
+            resume: true,
+            ..options
+        };
+        assert_eq!(
+            rescore(input, &resumable, &options, &mut progress).unwrap(),
+            3
+        );
+        assert_eq!(fs::read(&resumable).unwrap(), fs::read(&output).unwrap());
+        assert!(!sfbinpack::resume::progress_path(&resumable).exists());
+    }
+}
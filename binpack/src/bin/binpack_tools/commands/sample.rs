@@ -0,0 +1,390 @@
+use std::{fs::File, path::Path, str::FromStr};
+
+use rand::{rngs::StdRng, SeedableRng};
+use sfbinpack::{
+    sample::{Bucketing, StratifiedSampler},
+    CompressedTrainingDataEntryWriter,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::object,
+    output::{Output, Progress},
+    rng::{self, Rng},
+};
+
+use super::{next_game, open_reader, total_size, write_meta};
+
+pub const USAGE: &str =
+    "binpack-tools sample (--rate R | --count N | --per-bucket N) [--seed S] IN OUT
+
+Writes a random subset of the games of IN to OUT, games are kept whole.
+
+Options:
+  --rate R          keep each game with probability R
+  --count N         keep about N entries, accepts k, M and G suffixes (1M);
+                    the input is read twice and the last game picked may
+                    overshoot N
+  --per-bucket N    keep up to N entries, not games, of each bucket of
+                    positions, for balanced evaluation sets
+  --by KEYS         what --per-bucket buckets by, a comma separated list of
+                    phase, material and king; all three by default
+  --seed S          seed for a reproducible sample, random by default";
+
+/// The value of `--by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct By(Bucketing);
+
+impl FromStr for By {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut bucketing = Bucketing::default();
+        for key in text.split(',') {
+            match key.trim() {
+                "phase" => bucketing.phase = true,
+                "material" => bucketing.material = true,
+                "king" => bucketing.king_safety = true,
+                _ => return Err(()),
+            }
+        }
+        Ok(By(bucketing))
+    }
+}
+
+/// A count with an optional `k`, `M` or `G` suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Count(u64);
+
+impl FromStr for Count {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (number, scale) = match text.char_indices().last().ok_or(())? {
+            (idx, 'k' | 'K') => (&text[..idx], 1e3),
+            (idx, 'm' | 'M') => (&text[..idx], 1e6),
+            (idx, 'g' | 'G') => (&text[..idx], 1e9),
+            _ => return text.parse().map(Count).map_err(|_| ()),
+        };
+
+        let value = number.parse::<f64>().map_err(|_| ())? * scale;
+        if !(value >= 0.0 && value.is_finite()) {
+            return Err(());
+        }
+
+        Ok(Count(value.round() as u64))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Rate(f64),
+    Count(u64),
+    PerBucket(u64, Bucketing),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Sampled {
+    /// Games written whole, or buckets with --per-bucket
+    games: u64,
+    entries: u64,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let rate = args.value::<f64>(&["--rate"])?;
+    let count = args.value::<Count>(&["--count"])?;
+    let per_bucket = args.value::<Count>(&["--per-bucket"])?;
+    let by = args.value::<By>(&["--by"])?;
+    let seed = args.value::<u64>(&["--seed"])?;
+    let files = args.finish()?;
+
+    let [input, output] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+
+    let mode = match (rate, count, per_bucket) {
+        (Some(rate), None, None) if (0.0..=1.0).contains(&rate) => Mode::Rate(rate),
+        (Some(rate), None, None) => {
+            return Err(CliError::InvalidValue {
+                name: "--rate".to_string(),
+                value: rate.to_string(),
+            })
+        }
+        (None, Some(Count(count)), None) => Mode::Count(count),
+        (None, None, Some(Count(count))) => {
+            Mode::PerBucket(count, by.map_or(Bucketing::all(), |By(by)| by))
+        }
+        _ => return Err(CliError::Usage(USAGE.to_string())),
+    };
+    if by.is_some() && !matches!(mode, Mode::PerBucket(..)) {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rng::seed_from_time();
+        out.info(format!("seed: {}", seed));
+        seed
+    });
+
+    let mut progress = out.progress(total_size(&[input])?);
+    let sampled = sample(
+        Path::new(input),
+        Path::new(output),
+        mode,
+        seed,
+        &mut progress,
+    )?;
+    progress.finish();
+
+    out.report(
+        || {
+            let games = match mode {
+                Mode::PerBucket(..) => "buckets",
+                _ => "games",
+            };
+            object([
+                (games, sampled.games.into()),
+                ("entries", sampled.entries.into()),
+                ("seed", seed.into()),
+            ])
+        },
+        || match mode {
+            Mode::PerBucket(..) => println!(
+                "sampled {} entries from {} buckets",
+                sampled.entries, sampled.games
+            ),
+            _ => println!(
+                "sampled {} games, {} entries",
+                sampled.games, sampled.entries
+            ),
+        },
+    );
+
+    Ok(())
+}
+
+/// `progress` follows the pass writing the sample
+fn sample(
+    input: &Path,
+    output: &Path,
+    mode: Mode,
+    seed: u64,
+    progress: &mut Progress,
+) -> Result<Sampled, CliError> {
+    let mut rng = Rng::new(seed);
+
+    let selected = match mode {
+        Mode::Rate(_) => None,
+        Mode::Count(count) => Some(select_games(input, count, &mut rng)?),
+        Mode::PerBucket(per_bucket, bucketing) => {
+            return sample_buckets(input, output, per_bucket, bucketing, seed, progress)
+        }
+    };
+
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error)?;
+    let mut sampled = Sampled::default();
+
+    let mut reader = open_reader(input)?;
+    let mut game = Vec::new();
+    let mut idx = 0;
+
+    while next_game(&mut reader, &mut game) {
+        let keep = match (&selected, mode) {
+            (Some(selected), _) => selected[idx],
+            (None, Mode::Rate(rate)) => rng.next_f64() < rate,
+            (None, Mode::Count(_) | Mode::PerBucket(..)) => unreachable!(),
+        };
+        idx += 1;
+        progress.add(game.len() as u64, reader.read_bytes());
+
+        if keep {
+            for entry in &game {
+                writer.write_entry(entry).map_err(writer_error)?;
+            }
+            sampled.games += 1;
+            sampled.entries += game.len() as u64;
+        }
+    }
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+    Ok(sampled)
+}
+
+fn sample_buckets(
+    input: &Path,
+    output: &Path,
+    per_bucket: u64,
+    bucketing: Bucketing,
+    seed: u64,
+    progress: &mut Progress,
+) -> Result<Sampled, CliError> {
+    let per_bucket = usize::try_from(per_bucket).unwrap_or(usize::MAX);
+    let mut sampler =
+        StratifiedSampler::with_rng(bucketing, per_bucket, StdRng::seed_from_u64(seed));
+
+    let mut reader = open_reader(input)?;
+    while reader.has_next() {
+        sampler.add(reader.next());
+        progress.entry(reader.read_bytes());
+    }
+
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let writer_error = |source| CliError::Writer {
+        path: output.display().to_string(),
+        source,
+    };
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error)?;
+
+    let buckets = sampler.strata().count() as u64;
+    let entries = sampler.into_entries();
+    for entry in &entries {
+        writer.write_entry(entry).map_err(writer_error)?;
+    }
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+
+    Ok(Sampled {
+        games: buckets,
+        entries: entries.len() as u64,
+    })
+}
+
+/// Picks games in random order until they hold at least `count` entries,
+/// returns whether each game of the input was picked
+fn select_games(input: &Path, count: u64, rng: &mut Rng) -> Result<Vec<bool>, CliError> {
+    let mut lengths = Vec::new();
+
+    let mut reader = open_reader(input)?;
+    let mut game = Vec::new();
+    while next_game(&mut reader, &mut game) {
+        lengths.push(game.len() as u64);
+    }
+
+    let mut order = (0..lengths.len()).collect::<Vec<_>>();
+    rng.shuffle(&mut order);
+
+    let mut selected = vec![false; lengths.len()];
+    let mut entries = 0;
+
+    for idx in order {
+        if entries >= count {
+            break;
+        }
+        selected[idx] = true;
+        entries += lengths[idx];
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!("1M".parse(), Ok(Count(1_000_000)));
+        assert_eq!("2.5k".parse(), Ok(Count(2_500)));
+        assert_eq!("3G".parse(), Ok(Count(3_000_000_000)));
+        assert_eq!("42".parse(), Ok(Count(42)));
+        assert!("".parse::<Count>().is_err());
+        assert!("-1k".parse::<Count>().is_err());
+        assert!("1T".parse::<Count>().is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.binpack");
+
+        // 20 games of 3 entries
+        {
+            let mut source = open_reader(Path::new("./test/ep1.binpack")).unwrap();
+            let mut game = Vec::new();
+            next_game(&mut source, &mut game);
+
+            let mut writer =
+                CompressedTrainingDataEntryWriter::new(File::create(&input).unwrap()).unwrap();
+            for _ in 0..20 {
+                for entry in &game {
+                    writer.write_entry(entry).unwrap();
+                }
+            }
+        }
+
+        let output = dir.path().join("out.binpack");
+        let mut progress = Output::default().progress(0);
+
+        let sampled = sample(&input, &output, Mode::Count(10), 1, &mut progress).unwrap();
+        assert_eq!(
+            sampled,
+            Sampled {
+                games: 4,
+                entries: 12
+            }
+        );
+
+        let sampled = sample(&input, &output, Mode::Rate(1.0), 1, &mut progress).unwrap();
+        assert_eq!(
+            sampled,
+            Sampled {
+                games: 20,
+                entries: 60
+            }
+        );
+
+        let sampled = sample(&input, &output, Mode::Rate(0.0), 1, &mut progress).unwrap();
+        assert_eq!(sampled, Sampled::default());
+
+        let first = sample(&input, &output, Mode::Rate(0.5), 7, &mut progress).unwrap();
+        let second = sample(&input, &output, Mode::Rate(0.5), 7, &mut progress).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_sample_per_bucket() {
+        assert_eq!(
+            "phase,king".parse(),
+            Ok(By(Bucketing {
+                phase: true,
+                material: false,
+                king_safety: true
+            }))
+        );
+        assert!("phase,queen".parse::<By>().is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.binpack");
+        let input = Path::new("./test/ep1.binpack");
+        let mut progress = Output::default().progress(0);
+
+        let by_phase = Bucketing {
+            phase: true,
+            ..Default::default()
+        };
+        let sampled = sample(
+            input,
+            &output,
+            Mode::PerBucket(1, by_phase),
+            1,
+            &mut progress,
+        )
+        .unwrap();
+        assert_eq!(sampled.entries, sampled.games);
+
+        let mut reader = open_reader(&output).unwrap();
+        let mut count = 0;
+        while reader.has_next() {
+            reader.next();
+            count += 1;
+        }
+        assert_eq!(count, sampled.entries);
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    io::{self, BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use sfbinpack::{
+    filter::from_fn, formats::bin::pack_record, pipeline::Pipeline, TrainingDataEntry,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    expr::Expr,
+    json::object,
+    output::Output,
+    rng::{self, Rng},
+};
+
+use super::{open_reader, ScoreOptions};
+
+pub const USAGE: &str = "binpack-tools serve [--bind ADDR] [--where EXPR] [--shuffle-buffer N]
+                     [--seed S] [--epochs N] [--max-clients N] FILE...
+
+Serves the entries of FILE... to trainers over TCP, so the machines that
+prepare data and the ones that train need not be the same. Every client that
+connects gets its own stream: the entries of all files, in order, that pass
+EXPR (see `filter`) and the score options, shuffled through a buffer and
+repeated for the given number of epochs. Entries are sent as the 40 byte
+records of the bin format (PackedSfenValue), read them with
+`sfbinpack::formats::bin::BinReader`; the connection is closed after the
+last epoch.
+
+Options:
+  --bind ADDR            address to listen on (default 127.0.0.1:9470)
+  --where EXPR           only send the entries for which EXPR is true
+  --shuffle-buffer N     entries shuffled at a time (default 1000000), 0 or
+                         1 sends them in file order
+  --seed S               seed of the first client's shuffle, the n-th client
+                         gets S + n, random by default
+  --epochs N             passes over the files per client, 0 repeats them
+                         until the client disconnects (default 1)
+  --max-clients N        stop after serving N clients, 0 for no limit
+                         (default 0)
+  --cap-mates CAP, --scale-score FROM:TO, --clamp-score MIN:MAX
+                         rewrite the scores sent, as in `filter`";
+
+const DEFAULT_BIND: &str = "127.0.0.1:9470";
+
+/// What every client is sent
+#[derive(Debug)]
+struct Stream {
+    files: Vec<PathBuf>,
+    expr: Option<Expr>,
+    scores: ScoreOptions,
+    shuffle_buffer: usize,
+    epochs: u64,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let bind = args
+        .value::<String>(&["--bind"])?
+        .unwrap_or_else(|| DEFAULT_BIND.to_string());
+    let text = args.value::<String>(&["-w", "--where"])?;
+    let shuffle_buffer = args
+        .value::<usize>(&["--shuffle-buffer"])?
+        .unwrap_or(1_000_000);
+    let seed = args.value::<u64>(&["--seed"])?;
+    let epochs = args.value::<u64>(&["--epochs"])?.unwrap_or(1);
+    let max_clients = args.value::<u64>(&["--max-clients"])?.unwrap_or(0);
+    let scores = ScoreOptions::parse(&mut args)?;
+    let files = args.finish()?;
+
+    if files.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let expr = match text {
+        Some(text) => {
+            Some(Expr::parse(&text).map_err(|source| CliError::Expression { text, source })?)
+        }
+        None => None,
+    };
+
+    // every file is opened once up front, so a typo fails now and not
+    // when the first client connects
+    for file in &files {
+        open_reader(Path::new(file))?;
+    }
+
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rng::seed_from_time();
+        out.info(format!("seed: {}", seed));
+        seed
+    });
+
+    let stream = Stream {
+        files: files.iter().map(PathBuf::from).collect(),
+        expr,
+        scores,
+        shuffle_buffer,
+        epochs,
+    };
+
+    let listener = TcpListener::bind(&bind).map_err(CliError::io(&bind))?;
+    let addr = listener.local_addr().map_err(CliError::io(&bind))?;
+    out.info(format!("listening on {}", addr));
+
+    let (clients, sent) = serve(&listener, &stream, seed, max_clients, out)?;
+
+    out.report(
+        || object([("clients", clients.into()), ("entries", sent.into())]),
+        || println!("sent {} entries to {} clients", sent, clients),
+    );
+
+    Ok(())
+}
+
+/// Accepts clients until `max_clients` were served, each on its own thread,
+/// and returns the number of clients and the entries sent to them
+fn serve(
+    listener: &TcpListener,
+    stream: &Stream,
+    seed: u64,
+    max_clients: u64,
+    out: &Output,
+) -> Result<(u64, u64), CliError> {
+    thread::scope(|scope| {
+        let mut clients = Vec::new();
+
+        for (idx, connection) in listener.incoming().enumerate() {
+            let connection = match connection {
+                Ok(connection) => connection,
+                Err(err) => {
+                    out.info(format!("accepting a client failed: {}", err));
+                    continue;
+                }
+            };
+
+            let seed = seed.wrapping_add(idx as u64);
+            clients.push(scope.spawn(move || serve_client(connection, stream, seed, out)));
+
+            if max_clients > 0 && clients.len() as u64 >= max_clients {
+                break;
+            }
+        }
+
+        let served = clients.len() as u64;
+        let mut sent = 0;
+        for client in clients {
+            sent += client.join().expect("client thread panicked")?;
+        }
+
+        Ok((served, sent))
+    })
+}
+
+fn serve_client(
+    connection: TcpStream,
+    stream: &Stream,
+    seed: u64,
+    out: &Output,
+) -> Result<u64, CliError> {
+    let peer = connection
+        .peer_addr()
+        .map_or_else(|_| "client".to_string(), |addr| addr.to_string());
+    out.info(format!("{}: connected", peer));
+
+    let mut output = BufWriter::new(connection);
+    let mut sent = 0;
+    let result = send(stream, seed, &mut output, &mut sent).and_then(|()| {
+        output.flush().map_err(|source| CliError::Io {
+            path: peer.clone(),
+            source,
+        })
+    });
+
+    match result {
+        Ok(()) => out.info(format!("{}: sent {} entries", peer, sent)),
+        // the trainer went away, which is how endless streams end
+        Err(CliError::Io { source, .. }) if is_disconnect(&source) => {
+            out.info(format!("{}: disconnected after {} entries", peer, sent))
+        }
+        Err(err) => return Err(err),
+    }
+
+    Ok(sent)
+}
+
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Writes the records of every epoch to `output`, counting them in `sent`
+fn send(
+    stream: &Stream,
+    seed: u64,
+    output: &mut impl Write,
+    sent: &mut u64,
+) -> Result<(), CliError> {
+    let mut rng = Rng::new(seed);
+    let mut buffer = Vec::with_capacity(stream.shuffle_buffer.min(1 << 20));
+    let mut epoch = 0;
+
+    let mut emit = |entry: &TrainingDataEntry| -> Result<(), CliError> {
+        output
+            .write_all(&pack_record(entry))
+            .map_err(|source| CliError::Io {
+                path: "client".to_string(),
+                source,
+            })?;
+        *sent += 1;
+        Ok(())
+    };
+
+    while stream.epochs == 0 || epoch < stream.epochs {
+        // EXPR sees the scores of the files, like with `filter`
+        let mut pipeline = Pipeline::new();
+        if let Some(expr) = &stream.expr {
+            pipeline = pipeline.filter(from_fn(|entry| expr.matches(entry)));
+        }
+        let mut pipeline = stream.scores.add_to(pipeline);
+
+        let mut passed = 0;
+        for file in &stream.files {
+            let mut reader = open_reader(file)?;
+            while reader.has_next() {
+                for entry in pipeline.apply(reader.next()) {
+                    passed += 1;
+                    shuffle_into(
+                        &mut buffer,
+                        entry,
+                        stream.shuffle_buffer,
+                        &mut rng,
+                        &mut emit,
+                    )?;
+                }
+            }
+        }
+        for entry in pipeline.finish() {
+            passed += 1;
+            shuffle_into(
+                &mut buffer,
+                entry,
+                stream.shuffle_buffer,
+                &mut rng,
+                &mut emit,
+            )?;
+        }
+
+        // nothing passes the filter, repeating would spin forever
+        if passed == 0 {
+            break;
+        }
+        epoch += 1;
+    }
+
+    rng.shuffle(&mut buffer);
+    for entry in &buffer {
+        emit(entry)?;
+    }
+
+    Ok(())
+}
+
+/// Adds `entry` to the shuffle buffer and, once it is full, emits a random
+/// entry of it to make room
+fn shuffle_into(
+    buffer: &mut Vec<TrainingDataEntry>,
+    entry: TrainingDataEntry,
+    size: usize,
+    rng: &mut Rng,
+    emit: &mut impl FnMut(&TrainingDataEntry) -> Result<(), CliError>,
+) -> Result<(), CliError> {
+    if size <= 1 {
+        return emit(&entry);
+    }
+
+    if buffer.len() < size {
+        buffer.push(entry);
+        return Ok(());
+    }
+
+    let idx = rng.below(size as u64) as usize;
+    emit(&buffer[idx])?;
+    buffer[idx] = entry;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::Shutdown};
+
+    use sfbinpack::formats::bin::{BinReader, RECORD_SIZE};
+
+    use super::*;
+
+    fn ep1(shuffle_buffer: usize, epochs: u64, expr: Option<&str>) -> Stream {
+        Stream {
+            files: vec![PathBuf::from("./test/ep1.binpack")],
+            expr: expr.map(|text| Expr::parse(text).unwrap()),
+            scores: ScoreOptions::default(),
+            shuffle_buffer,
+            epochs,
+        }
+    }
+
+    fn entries(data: &[u8]) -> Vec<TrainingDataEntry> {
+        BinReader::new(data).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_send() {
+        let mut reader = open_reader(Path::new("./test/ep1.binpack")).unwrap();
+        let mut expected = Vec::new();
+        while reader.has_next() {
+            expected.push(reader.next());
+        }
+
+        let mut data = Vec::new();
+        let mut sent = 0;
+        send(&ep1(0, 2, None), 1, &mut data, &mut sent).unwrap();
+        assert_eq!(sent, 6);
+        assert_eq!(
+            entries(&data),
+            [expected.clone(), expected.clone()].concat()
+        );
+
+        // the same entries, shuffled, and the same shuffle for the same seed
+        let mut shuffled = Vec::new();
+        send(&ep1(4, 2, None), 1, &mut shuffled, &mut 0).unwrap();
+        let mut again = Vec::new();
+        send(&ep1(4, 2, None), 1, &mut again, &mut 0).unwrap();
+        assert_eq!(shuffled, again);
+
+        let mut shuffled = entries(&shuffled);
+        let mut both = [expected.clone(), expected.clone()].concat();
+        shuffled.sort_by_key(|entry| (entry.ply, entry.pos.zobrist_key()));
+        both.sort_by_key(|entry| (entry.ply, entry.pos.zobrist_key()));
+        assert_eq!(shuffled, both);
+
+        let mut data = Vec::new();
+        send(&ep1(0, 1, Some("score < 0")), 1, &mut data, &mut 0).unwrap();
+        assert!(entries(&data).iter().all(|entry| entry.score < 0));
+
+        // endless, but nothing passes
+        send(&ep1(0, 0, Some("false")), 1, &mut Vec::new(), &mut 0).unwrap();
+    }
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let out = Output::default();
+
+        let client = thread::spawn(move || {
+            let mut connection = TcpStream::connect(addr).unwrap();
+            connection.shutdown(Shutdown::Write).unwrap();
+            let mut data = Vec::new();
+            connection.read_to_end(&mut data).unwrap();
+            data
+        });
+
+        let (clients, sent) = serve(&listener, &ep1(2, 3, None), 7, 1, &out).unwrap();
+        assert_eq!((clients, sent), (1, 9));
+
+        let data = client.join().unwrap();
+        assert_eq!(data.len(), 9 * RECORD_SIZE);
+    }
+}
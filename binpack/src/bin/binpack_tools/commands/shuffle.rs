@@ -0,0 +1,303 @@
+use std::{
+    fs::{self, File},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use sfbinpack::{CompressedTrainingDataEntryWriter, TrainingDataEntry};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::object,
+    output::{Output, Progress},
+    rng::{self, Rng},
+};
+
+use super::{next_game, open_reader, total_size, write_meta};
+
+pub const USAGE: &str = "binpack-tools shuffle [--buffer-gb G] [--seed S] [--tmp-dir DIR] IN OUT
+
+Shuffles the games of IN into OUT, the entries of a game stay together and
+in order. Inputs that do not fit into the memory budget are first scattered
+over temporary binpacks at random, each of which is then shuffled in memory.
+
+Options:
+  --buffer-gb G    memory budget in GB (default 1)
+  --seed S         seed for a reproducible shuffle, random by default
+  --tmp-dir DIR    directory for the temporary files (default: system temp)";
+
+/// Binpacks rarely get below this many bytes per entry, used to estimate how
+/// many entries the input holds before reading it
+const MIN_BYTES_PER_ENTRY: u64 = 2;
+
+/// Each bucket keeps a writer with a chunk buffer of about a MiB
+const MAX_BUCKETS: u64 = 1024;
+
+#[derive(Debug)]
+struct Options {
+    buffer_bytes: u64,
+    seed: u64,
+    tmp_dir: PathBuf,
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let buffer_gb = args.value::<f64>(&["--buffer-gb"])?.unwrap_or(1.0);
+    let seed = args.value::<u64>(&["--seed"])?;
+    let tmp_dir = args.value::<PathBuf>(&["--tmp-dir"])?;
+    let files = args.finish()?;
+
+    let [input, output] = files.as_slice() else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+
+    if !(buffer_gb > 0.0 && buffer_gb.is_finite()) {
+        return Err(CliError::InvalidValue {
+            name: "--buffer-gb".to_string(),
+            value: buffer_gb.to_string(),
+        });
+    }
+
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rng::seed_from_time();
+        out.info(format!("seed: {}", seed));
+        seed
+    });
+
+    let options = Options {
+        buffer_bytes: (buffer_gb * 1e9) as u64,
+        seed,
+        tmp_dir: tmp_dir.unwrap_or_else(std::env::temp_dir),
+    };
+
+    let mut progress = out.progress(total_size(&[input])?);
+    let games = shuffle(Path::new(input), Path::new(output), &options, &mut progress)?;
+    progress.finish();
+
+    out.report(
+        || object([("games", games.into()), ("seed", seed.into())]),
+        || println!("shuffled {} games", games),
+    );
+
+    Ok(())
+}
+
+/// Number of buckets so that each one fits into the memory budget
+fn bucket_count(input_size: u64, buffer_bytes: u64) -> u64 {
+    let entries = input_size / MIN_BYTES_PER_ENTRY;
+    let bytes = entries.saturating_mul(mem::size_of::<TrainingDataEntry>() as u64);
+
+    bytes.div_ceil(buffer_bytes.max(1)).clamp(1, MAX_BUCKETS)
+}
+
+/// `progress` follows the first pass over the input, which reads it into
+/// memory or scatters it over the buckets
+fn shuffle(
+    input: &Path,
+    output: &Path,
+    options: &Options,
+    progress: &mut Progress,
+) -> Result<u64, CliError> {
+    let mut rng = Rng::new(options.seed);
+    let input_size = input.metadata().map_err(CliError::io(input))?.len();
+    let buckets = bucket_count(input_size, options.buffer_bytes);
+
+    let mut reader = open_reader(input)?;
+    if !reader.has_next() {
+        File::create(output).map_err(CliError::io(output))?;
+        return Ok(0);
+    }
+
+    let mut games = Vec::new();
+
+    if buckets == 1 {
+        let mut game = Vec::new();
+        while next_game(&mut reader, &mut game) {
+            progress.add(game.len() as u64, reader.read_bytes());
+            games.push(mem::take(&mut game));
+        }
+        return write_shuffled(games, output, &mut rng);
+    }
+
+    let tmp = TempDir::create(&options.tmp_dir, options.seed)?;
+    let paths = (0..buckets)
+        .map(|idx| tmp.path.join(format!("bucket-{}.binpack", idx)))
+        .collect::<Vec<_>>();
+
+    // scatter, every game goes to a random bucket
+    {
+        let mut writers = paths
+            .iter()
+            .map(|path| {
+                let file = File::create(path).map_err(CliError::io(path))?;
+                CompressedTrainingDataEntryWriter::new(file).map_err(|source| CliError::Writer {
+                    path: path.display().to_string(),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut game = Vec::new();
+        while next_game(&mut reader, &mut game) {
+            let bucket = rng.below(buckets) as usize;
+            for entry in &game {
+                writers[bucket]
+                    .write_entry(entry)
+                    .map_err(|source| CliError::Writer {
+                        path: paths[bucket].display().to_string(),
+                        source,
+                    })?;
+            }
+            progress.add(game.len() as u64, reader.read_bytes());
+        }
+
+        for writer in &mut writers {
+            writer.flush_and_end();
+        }
+    }
+
+    // the buckets are in random order already, shuffle each one in memory
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error(output))?;
+    let mut total = 0;
+
+    for path in &paths {
+        let mut reader = open_reader(path)?;
+        let mut game = Vec::new();
+        while next_game(&mut reader, &mut game) {
+            games.push(mem::take(&mut game));
+        }
+
+        rng.shuffle(&mut games);
+        total += games.len() as u64;
+
+        for entry in games.drain(..).flatten() {
+            writer.write_entry(&entry).map_err(writer_error(output))?;
+        }
+
+        fs::remove_file(path).map_err(CliError::io(path))?;
+    }
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+    Ok(total)
+}
+
+fn write_shuffled(
+    mut games: Vec<Vec<TrainingDataEntry>>,
+    output: &Path,
+    rng: &mut Rng,
+) -> Result<u64, CliError> {
+    rng.shuffle(&mut games);
+
+    let file = File::create(output).map_err(CliError::io(output))?;
+    let mut writer = CompressedTrainingDataEntryWriter::new(file).map_err(writer_error(output))?;
+
+    for entry in games.iter().flatten() {
+        writer.write_entry(entry).map_err(writer_error(output))?;
+    }
+
+    writer.flush_and_end();
+    write_meta(output, &writer.stats(), None)?;
+    Ok(games.len() as u64)
+}
+
+fn writer_error(path: &Path) -> impl Fn(sfbinpack::CompressedWriterError) -> CliError + '_ {
+    move |source| CliError::Writer {
+        path: path.display().to_string(),
+        source,
+    }
+}
+
+/// Directory for the buckets, removed again when dropped
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn create(parent: &Path, seed: u64) -> Result<Self, CliError> {
+        let path = parent.join(format!("binpack-shuffle-{}-{:x}", std::process::id(), seed));
+        fs::create_dir_all(&path).map_err(CliError::io(&path))?;
+
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_games(path: &Path) -> Vec<Vec<TrainingDataEntry>> {
+        let mut reader = open_reader(path).unwrap();
+        let mut games = Vec::new();
+        let mut game = Vec::new();
+        while next_game(&mut reader, &mut game) {
+            games.push(game.clone());
+        }
+        games
+    }
+
+    #[test]
+    fn test_bucket_count() {
+        assert_eq!(bucket_count(0, 1_000_000_000), 1);
+        assert_eq!(bucket_count(1_000_000, 1_000_000_000), 1);
+        assert!(bucket_count(1_000_000_000, 1_000_000_000) > 1);
+        assert_eq!(bucket_count(u64::MAX, 1), MAX_BUCKETS);
+    }
+
+    #[test]
+    fn test_shuffle_spills_and_keeps_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.binpack");
+        let ep1 = read_games(Path::new("./test/ep1.binpack")).remove(0);
+
+        // distinct games by giving each a different score
+        let mut expected = Vec::new();
+        {
+            let mut writer =
+                CompressedTrainingDataEntryWriter::new(File::create(&input).unwrap()).unwrap();
+            for idx in 0..50 {
+                let mut game = ep1.clone();
+                game[0].score = idx;
+                for entry in &game {
+                    writer.write_entry(entry).unwrap();
+                }
+                expected.push(game);
+            }
+        }
+
+        for buffer_bytes in [1 << 30, 1] {
+            let output = dir.path().join(format!("out-{}.binpack", buffer_bytes));
+            let options = Options {
+                buffer_bytes,
+                seed: 42,
+                tmp_dir: dir.path().to_path_buf(),
+            };
+
+            let mut progress = Output::default().progress(0);
+            assert_eq!(
+                shuffle(&input, &output, &options, &mut progress).unwrap(),
+                50
+            );
+
+            let mut games = read_games(&output);
+            assert_ne!(games, expected);
+
+            games.sort_by_key(|game| game[0].score);
+            assert_eq!(games, expected);
+        }
+
+        // the temporary directory is cleaned up
+        let leftovers = fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+}
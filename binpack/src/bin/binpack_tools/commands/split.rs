@@ -0,0 +1,139 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use sfbinpack::shard::{ShardInfo, ShardWriter};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::{open_reader, total_size};
+
+pub const USAGE: &str = "binpack-tools split --entries-per-file N IN OUT_PREFIX
+
+Splits IN into OUT_PREFIX0000.binpack, OUT_PREFIX0001.binpack, ... with about
+N entries each. A file is only closed between two games, so files can be
+slightly larger than N entries.
+
+Options:
+  -n, --entries-per-file N    entries per output file";
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let entries_per_file = args.value::<u64>(&["-n", "--entries-per-file"])?;
+    let files = args.finish()?;
+
+    let (Some(entries_per_file), [input, prefix]) = (entries_per_file, files.as_slice()) else {
+        return Err(CliError::Usage(USAGE.to_string()));
+    };
+    if entries_per_file == 0 {
+        return Err(CliError::InvalidValue {
+            name: "--entries-per-file".to_string(),
+            value: "0".to_string(),
+        });
+    }
+
+    let mut progress = out.progress(total_size(&[input])?);
+    let shards = split(Path::new(input), prefix, entries_per_file, &mut progress)?;
+    progress.finish();
+
+    let sizes = shards
+        .iter()
+        .map(|(path, _)| total_size(&[path]))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    out.report(
+        || {
+            let files = shards
+                .iter()
+                .zip(&sizes)
+                .map(|((path, info), size)| {
+                    object([
+                        ("file", path.display().to_string().into()),
+                        ("entries", info.entries.into()),
+                        ("games", info.games.into()),
+                        ("bytes", (*size).into()),
+                    ])
+                })
+                .collect();
+            object([("files", Value::Array(files))])
+        },
+        || {
+            for ((path, info), size) in shards.iter().zip(&sizes) {
+                println!(
+                    "{}: {} entries, {} games, {} bytes",
+                    path.display(),
+                    info.entries,
+                    info.games,
+                    size
+                );
+            }
+        },
+    );
+
+    Ok(())
+}
+
+fn shard_path(prefix: &str, idx: usize) -> PathBuf {
+    PathBuf::from(format!("{}{:04}.binpack", prefix, idx))
+}
+
+fn split(
+    input: &Path,
+    prefix: &str,
+    entries_per_file: u64,
+    progress: &mut Progress,
+) -> Result<Vec<(PathBuf, ShardInfo)>, CliError> {
+    let mut reader = open_reader(input)?;
+
+    let mut writer = ShardWriter::new(entries_per_file, |idx| {
+        File::create(shard_path(prefix, idx))
+    });
+
+    while reader.has_next() {
+        let entry = reader.next();
+        writer
+            .write_entry(&entry)
+            .map_err(|source| CliError::Writer {
+                path: prefix.to_string(),
+                source,
+            })?;
+        progress.entry(reader.read_bytes());
+    }
+
+    let shards = writer.finish();
+    Ok(shards
+        .into_iter()
+        .enumerate()
+        .map(|(idx, info)| (shard_path(prefix, idx), info))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_keeps_game() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("part-").display().to_string();
+
+        let mut progress = Output::default().progress(0);
+        let shards = split(Path::new("./test/ep1.binpack"), &prefix, 1, &mut progress).unwrap();
+
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards[0].0, shard_path(&prefix, 0));
+        assert_eq!(
+            shards[0].1,
+            ShardInfo {
+                entries: 3,
+                games: 1
+            }
+        );
+        assert!(shards[0].0.exists());
+    }
+}
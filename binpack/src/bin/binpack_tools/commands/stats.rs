@@ -0,0 +1,276 @@
+use std::{collections::HashMap, path::Path};
+
+use sfbinpack::{
+    chess::{color::Color, piecetype::PieceType, position::MAX_GAME_PHASE, r#move::MoveType},
+    stats::Histogram,
+    TrainingDataEntry,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::{open_reader, total_size};
+
+pub const USAGE: &str = "binpack-tools stats [--json] FILE...
+
+Prints the score, ply, piece count and game phase distributions, the result
+balance, the fraction of captures and positions in check and an estimate of
+the fraction of duplicate positions over all given files.";
+
+/// Only positions whose hash is a multiple of this are remembered for the
+/// duplicate estimate, since every occurrence of a position has the same
+/// hash the sample still contains all of its duplicates
+const DUPLICATE_SAMPLE_RATE: u64 = 16;
+
+#[derive(Debug)]
+struct Stats {
+    entries: u64,
+    scores: Histogram,
+    plies: Histogram,
+    piece_counts: Histogram,
+    phases: Histogram,
+    /// Loss, draw and win from the side to move's point of view
+    results: [u64; 3],
+    /// Black wins, draws and white wins
+    white_results: [u64; 3],
+    captures: u64,
+    in_check: u64,
+    sampled: u64,
+    sampled_positions: HashMap<u64, u64>,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            entries: 0,
+            scores: Histogram::new(100, -3000, 3000),
+            plies: Histogram::new(20, 0, 400),
+            piece_counts: Histogram::new(1, 2, 32),
+            phases: Histogram::new(1, 0, MAX_GAME_PHASE as i64),
+            results: [0; 3],
+            white_results: [0; 3],
+            captures: 0,
+            in_check: 0,
+            sampled: 0,
+            sampled_positions: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, entry: &TrainingDataEntry) {
+        let pos = &entry.pos;
+        self.entries += 1;
+
+        self.scores.add(entry.score as i64);
+        self.plies.add(entry.ply as i64);
+        self.piece_counts.add(pos.occupied().count() as i64);
+        self.phases.add(pos.game_phase() as i64);
+
+        let result = entry.result.clamp(-1, 1);
+        let white_result = match pos.side_to_move() {
+            Color::White => result,
+            Color::Black => -result,
+        };
+        self.results[(result + 1) as usize] += 1;
+        self.white_results[(white_result + 1) as usize] += 1;
+
+        let captured = pos.piece_at(entry.mv.to());
+        if entry.mv.mtype() == MoveType::EnPassant
+            || (entry.mv.mtype() != MoveType::Castle
+                && captured.piece_type() != PieceType::None
+                && captured.color() != pos.side_to_move())
+        {
+            self.captures += 1;
+        }
+
+        if pos.is_checked(pos.side_to_move()) {
+            self.in_check += 1;
+        }
+
+        let hash = pos.canonical_key();
+        if hash.is_multiple_of(DUPLICATE_SAMPLE_RATE) {
+            self.sampled += 1;
+            *self.sampled_positions.entry(hash).or_default() += 1;
+        }
+    }
+
+    /// Estimated fraction of entries whose position already occurred earlier
+    fn duplicate_fraction(&self) -> f64 {
+        if self.sampled == 0 {
+            return 0.0;
+        }
+
+        1.0 - self.sampled_positions.len() as f64 / self.sampled as f64
+    }
+}
+
+pub fn run(args: Args, out: &Output) -> Result<(), CliError> {
+    let files = args.finish()?;
+    if files.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let mut progress = out.progress(total_size(&files)?);
+    let mut stats = Stats::new();
+    let mut done = 0;
+
+    for file in &files {
+        let path = Path::new(file);
+        progress.set_offset(done);
+        collect(path, &mut stats, &mut progress)?;
+        done += total_size(&[path])?;
+    }
+    progress.finish();
+
+    out.report(|| to_json(&stats), || print_stats(&stats));
+
+    Ok(())
+}
+
+fn collect(path: &Path, stats: &mut Stats, progress: &mut Progress) -> Result<(), CliError> {
+    let mut reader = open_reader(path)?;
+
+    while reader.has_next() {
+        stats.add(&reader.next());
+        progress.entry(reader.read_bytes());
+    }
+
+    Ok(())
+}
+
+fn fraction(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+fn print_stats(stats: &Stats) {
+    let total = stats.entries;
+
+    println!("entries:         {}", total);
+    println!(
+        "results (stm):   {} wins, {} draws, {} losses",
+        stats.results[2], stats.results[1], stats.results[0]
+    );
+    println!(
+        "results (white): {} white wins, {} draws, {} black wins",
+        stats.white_results[2], stats.white_results[1], stats.white_results[0]
+    );
+    println!(
+        "captures:        {:.2}%",
+        100.0 * fraction(stats.captures, total)
+    );
+    println!(
+        "in check:        {:.2}%",
+        100.0 * fraction(stats.in_check, total)
+    );
+    println!(
+        "duplicates:      {:.2}% (estimated from {} positions)",
+        100.0 * stats.duplicate_fraction(),
+        stats.sampled
+    );
+
+    for (title, histogram) in [
+        ("score", &stats.scores),
+        ("ply", &stats.plies),
+        ("pieces", &stats.piece_counts),
+        ("phase", &stats.phases),
+    ] {
+        println!();
+        println!("{}:", title);
+        print_histogram(histogram, total);
+    }
+}
+
+fn print_histogram(histogram: &Histogram, total: u64) {
+    const BAR_WIDTH: u64 = 40;
+
+    let max = histogram.counts().values().copied().max().unwrap_or(0);
+
+    for (&bucket, &count) in histogram.counts() {
+        let bar = (count * BAR_WIDTH).checked_div(max).unwrap_or(0);
+        println!(
+            "  {:>12} {:>12} {:>6.2}% {}",
+            histogram.label(bucket),
+            count,
+            100.0 * fraction(count, total),
+            "#".repeat(bar as usize)
+        );
+    }
+}
+
+fn histogram_json(histogram: &Histogram) -> Value {
+    let buckets = histogram
+        .counts()
+        .iter()
+        .map(|(bucket, count)| {
+            object([
+                ("label", histogram.label(*bucket).into()),
+                ("count", (*count).into()),
+            ])
+        })
+        .collect();
+
+    Value::Array(buckets)
+}
+
+fn to_json(stats: &Stats) -> Value {
+    let total = stats.entries;
+
+    object([
+        ("entries", total.into()),
+        (
+            "results",
+            object([
+                ("win", stats.results[2].into()),
+                ("draw", stats.results[1].into()),
+                ("loss", stats.results[0].into()),
+            ]),
+        ),
+        (
+            "white_results",
+            object([
+                ("white", stats.white_results[2].into()),
+                ("draw", stats.white_results[1].into()),
+                ("black", stats.white_results[0].into()),
+            ]),
+        ),
+        ("capture_fraction", fraction(stats.captures, total).into()),
+        ("in_check_fraction", fraction(stats.in_check, total).into()),
+        ("duplicate_fraction", stats.duplicate_fraction().into()),
+        ("score", histogram_json(&stats.scores)),
+        ("ply", histogram_json(&stats.plies)),
+        ("pieces", histogram_json(&stats.piece_counts)),
+        ("phase", histogram_json(&stats.phases)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_ep1() {
+        let mut stats = Stats::new();
+        let mut progress = Output::default().progress(0);
+        collect(Path::new("./test/ep1.binpack"), &mut stats, &mut progress).unwrap();
+
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.results, [0, 3, 0]);
+        // Bxb7 captures, and c2c4 uncovers a check from the queen on b1
+        assert_eq!(stats.captures, 1);
+        assert_eq!(stats.in_check, 1);
+        assert_eq!(stats.scores.total(), 3);
+        assert_eq!(stats.piece_counts.counts().get(&19), Some(&3));
+        assert_eq!(stats.phases.total(), 3);
+
+        let json = to_json(&stats).to_string();
+        assert!(json.starts_with("{\"entries\":3,"));
+        assert!(json.contains("\"label\":\"-300..-200\",\"count\":2"));
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    fs::File,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use sfbinpack::{
+    chess::{attacks, color::Color, piecetype::PieceType, position::Position},
+    ChainLocation, CompressedReaderError, CompressedTrainingDataEntryReader, TrainingDataEntry,
+};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::total_size;
+
+pub const USAGE: &str = "binpack-tools validate [-k N] FILE...
+
+Decodes every entry with bounds checks and verifies that positions are sane,
+moves are legal and continuations are consistent. Prints the first N errors
+(default 20) of each file with their chunk index and the byte offset of the
+chain they belong to. Exits with status 1 if any file has errors.
+
+Options:
+  -k, --max-errors N    number of errors to print per file";
+
+#[derive(Debug)]
+struct ValidationError {
+    location: ChainLocation,
+    entry: u64,
+    message: String,
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    entries: u64,
+    chunks: u64,
+    errors: Vec<ValidationError>,
+    total_errors: u64,
+    /// Decoding stopped before the end of the file
+    aborted: bool,
+}
+
+impl Report {
+    fn error(&mut self, max_errors: usize, location: ChainLocation, message: String) {
+        self.total_errors += 1;
+
+        if self.errors.len() < max_errors {
+            self.errors.push(ValidationError {
+                location,
+                entry: self.entries,
+                message,
+            });
+        }
+    }
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let max_errors = args.value::<usize>(&["-k", "--max-errors"])?.unwrap_or(20);
+    let files = args.finish()?;
+    if files.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let mut progress = out.progress(total_size(&files)?);
+    let mut reports = Vec::with_capacity(files.len());
+    let mut done = 0;
+
+    for file in &files {
+        let path = Path::new(file);
+        progress.set_offset(done);
+        reports.push(validate(path, max_errors, &mut progress)?);
+        done += total_size(&[path])?;
+    }
+    progress.finish();
+
+    out.report(
+        || {
+            let files = files
+                .iter()
+                .zip(&reports)
+                .map(|(file, report)| report_json(file, report))
+                .collect();
+            object([("files", Value::Array(files))])
+        },
+        || {
+            for (idx, (file, report)) in files.iter().zip(&reports).enumerate() {
+                if idx > 0 {
+                    println!();
+                }
+                print_report(Path::new(file), report);
+            }
+        },
+    );
+
+    let failed = reports
+        .iter()
+        .filter(|report| report.total_errors > 0)
+        .count();
+    if failed > 0 {
+        return Err(CliError::ValidationFailed(failed));
+    }
+
+    Ok(())
+}
+
+fn validate(path: &Path, max_errors: usize, progress: &mut Progress) -> Result<Report, CliError> {
+    let mut report = Report::default();
+
+    let file = File::open(path).map_err(CliError::io(path))?;
+    let mut reader = match CompressedTrainingDataEntryReader::new(file) {
+        Ok(reader) => reader,
+        Err(err) => {
+            report.error(max_errors, ChainLocation::default(), err.to_string());
+            report.aborted = true;
+            return Ok(report);
+        }
+    };
+
+    let mut last: Option<TrainingDataEntry> = None;
+
+    while reader.has_next() {
+        let continuation = reader.is_next_entry_continuation();
+
+        // the bounds checks catch truncated data, but garbage can still trip
+        // the assertions of the position decoder
+        let entry = match panic::catch_unwind(AssertUnwindSafe(|| reader.try_next())) {
+            Ok(Ok(entry)) => entry,
+            Ok(Err(CompressedReaderError::EndOfFile)) => break,
+            Ok(Err(err)) => {
+                report.error(max_errors, reader.chain_location(), err.to_string());
+                last = None;
+                continue;
+            }
+            Err(_) => {
+                let message = "decoder panicked, cannot continue with this file".to_string();
+                report.error(max_errors, reader.chain_location(), message);
+                report.aborted = true;
+                break;
+            }
+        };
+
+        for message in check_entry(&entry, last.as_ref().filter(|_| continuation)) {
+            report.error(max_errors, reader.chain_location(), message);
+        }
+
+        report.entries += 1;
+        last = Some(entry);
+        progress.entry(reader.read_bytes());
+    }
+
+    report.chunks = reader.chunks_read();
+    Ok(report)
+}
+
+/// Returns everything that is wrong with an entry, `previous` is the entry
+/// before it in the same chain
+fn check_entry(entry: &TrainingDataEntry, previous: Option<&TrainingDataEntry>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let pos = &entry.pos;
+
+    if let Some(message) = check_position(pos) {
+        errors.push(message);
+    } else if !attacks::legal_moves(pos).contains(&entry.mv) {
+        errors.push(format!("illegal move {}", entry.mv.as_uci()));
+    }
+
+    if !(-1..=1).contains(&entry.result) {
+        errors.push(format!("invalid result {}", entry.result));
+    }
+
+    if let Some(previous) = previous {
+        if !previous.is_continuation(entry) {
+            errors.push(format!(
+                "entry is not a continuation of the previous one: {}",
+                broken_continuation(previous, entry)
+            ));
+        }
+    }
+
+    errors
+}
+
+/// How `entry` differs from what the move of `previous` leads to
+fn broken_continuation(previous: &TrainingDataEntry, entry: &TrainingDataEntry) -> String {
+    if previous.result != -entry.result {
+        return format!("result {} after {}", entry.result, previous.result);
+    }
+    if previous.ply.wrapping_add(1) != entry.ply {
+        return format!("ply {} after {}", entry.ply, previous.ply);
+    }
+    if check_position(&previous.pos).is_some()
+        || !attacks::legal_moves(&previous.pos).contains(&previous.mv)
+    {
+        return format!("previous move {} is not legal", previous.mv.as_uci());
+    }
+
+    let expected = previous.pos.after_move(previous.mv);
+    format!("position differs by {}", expected.diff(&entry.pos))
+}
+
+fn check_position(pos: &Position) -> Option<String> {
+    for color in [Color::White, Color::Black] {
+        let kings = pos.pieces_bb_color(color, PieceType::King).count();
+        if kings != 1 {
+            return Some(format!("{:?} has {} kings", color, kings));
+        }
+    }
+
+    if pos.is_checked(!pos.side_to_move()) {
+        return Some("the side not to move is in check".to_string());
+    }
+
+    None
+}
+
+fn print_report(path: &Path, report: &Report) {
+    println!("file:            {}", path.display());
+    println!("chunks:          {}", report.chunks);
+    println!("entries:         {}", report.entries);
+    println!("errors:          {}", report.total_errors);
+
+    for error in &report.errors {
+        println!(
+            "  chunk {} at byte {} (entry {}): {}",
+            error.location.chunk, error.location.offset, error.entry, error.message
+        );
+    }
+
+    if report.total_errors > report.errors.len() as u64 {
+        println!(
+            "  ... {} more",
+            report.total_errors - report.errors.len() as u64
+        );
+    }
+
+    if report.aborted {
+        println!("decoding stopped early, the rest of the file was not checked");
+    }
+}
+
+fn report_json(file: &str, report: &Report) -> Value {
+    let errors = report
+        .errors
+        .iter()
+        .map(|error| {
+            object([
+                ("chunk", error.location.chunk.into()),
+                ("offset", error.location.offset.into()),
+                ("entry", error.entry.into()),
+                ("message", error.message.as_str().into()),
+            ])
+        })
+        .collect();
+
+    object([
+        ("file", file.into()),
+        ("chunks", report.chunks.into()),
+        ("entries", report.entries.into()),
+        ("total_errors", report.total_errors.into()),
+        ("errors", Value::Array(errors)),
+        ("aborted", report.aborted.into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn open_reader(path: &str) -> CompressedTrainingDataEntryReader<File> {
+        CompressedTrainingDataEntryReader::new(File::open(path).unwrap()).unwrap()
+    }
+
+    fn progress() -> Progress {
+        Output::default().progress(0)
+    }
+
+    #[test]
+    fn test_validate_ep1() {
+        let report = validate(Path::new("./test/ep1.binpack"), 20, &mut progress()).unwrap();
+
+        assert_eq!(report.chunks, 1);
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.total_errors, 0);
+    }
+
+    #[test]
+    fn test_broken_continuation() {
+        let mut reader = open_reader("./test/ep1.binpack");
+        let first = reader.next();
+        let second = reader.next();
+        assert!(check_entry(&second, Some(&first)).is_empty());
+
+        let mut moved = second;
+        moved.pos.set_rule50_counter(7);
+        assert_eq!(
+            check_entry(&moved, Some(&first)),
+            [format!(
+                "entry is not a continuation of the previous one: position differs by rule50 {}->7",
+                second.pos.rule50_counter()
+            )]
+        );
+
+        let replayed = TrainingDataEntry {
+            ply: first.ply,
+            ..second
+        };
+        assert!(check_entry(&replayed, Some(&first))[0]
+            .ends_with(&format!("ply {} after {}", first.ply, first.ply)));
+    }
+
+    #[test]
+    fn test_validate_truncated() {
+        let data = fs::read("./test/ep1.binpack").unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("truncated.binpack");
+        let mut truncated = data[..data.len() - 2].to_vec();
+        truncated[4..8].copy_from_slice(&((data.len() - 10) as u32).to_le_bytes());
+        truncated.extend_from_slice(&data);
+        truncated.extend_from_slice(b"garbage");
+        fs::write(&path, truncated).unwrap();
+
+        let report = validate(&path, 1, &mut progress()).unwrap();
+
+        assert_eq!(report.total_errors, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].location.chunk, 0);
+        assert_eq!(report.errors[0].location.offset, 8);
+        assert!(!report.aborted);
+    }
+}
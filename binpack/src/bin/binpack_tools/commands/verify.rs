@@ -0,0 +1,238 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use sfbinpack::checksum::{self, ChunkChecksum};
+
+use crate::{
+    args::Args,
+    error::CliError,
+    json::{object, Value},
+    output::{Output, Progress},
+};
+
+use super::total_size;
+
+pub const USAGE: &str = "binpack-tools verify [--require] FILE...
+
+Checks the CRC32 trailers of the chunks, as written with checksums enabled,
+without decoding any entries. Prints the chunks with a matching, a wrong and
+no checksum and the chunk index and byte offset of every mismatch. Exits with
+status 1 if a checksum is wrong or the chunk framing is broken.
+
+Options:
+  --require    also fail if a chunk has no checksum";
+
+const HEADER_SIZE: usize = 8;
+const MAGIC: &[u8; 4] = b"BINP";
+const PADDING_MAGIC: &[u8; 4] = b"BINZ";
+
+#[derive(Debug, Default)]
+struct Report {
+    chunks: u64,
+    valid: u64,
+    missing: u64,
+    /// Chunk index and byte offset of the header of each chunk with a wrong
+    /// checksum
+    mismatches: Vec<(u64, u64)>,
+    /// Why reading stopped before the end of the file
+    broken: Option<String>,
+}
+
+impl Report {
+    fn failed(&self, require: bool) -> bool {
+        !self.mismatches.is_empty() || self.broken.is_some() || (require && self.missing > 0)
+    }
+}
+
+pub fn run(mut args: Args, out: &Output) -> Result<(), CliError> {
+    let require = args.flag(&["--require"]);
+    let files = args.finish()?;
+    if files.is_empty() {
+        return Err(CliError::Usage(USAGE.to_string()));
+    }
+
+    let mut progress = out.progress(total_size(&files)?);
+    let mut reports = Vec::with_capacity(files.len());
+    let mut done = 0;
+
+    for file in &files {
+        let path = Path::new(file);
+        progress.set_offset(done);
+        reports.push(verify(path, &mut progress)?);
+        done += total_size(&[path])?;
+    }
+    progress.finish();
+
+    out.report(
+        || {
+            let files = files
+                .iter()
+                .zip(&reports)
+                .map(|(file, report)| report_json(file, report))
+                .collect();
+            object([("files", Value::Array(files))])
+        },
+        || {
+            for (idx, (file, report)) in files.iter().zip(&reports).enumerate() {
+                if idx > 0 {
+                    println!();
+                }
+                print_report(Path::new(file), report);
+            }
+        },
+    );
+
+    let failed = reports
+        .iter()
+        .filter(|report| report.failed(require))
+        .count();
+    if failed > 0 {
+        return Err(CliError::ValidationFailed(failed));
+    }
+
+    Ok(())
+}
+
+fn verify(path: &Path, progress: &mut Progress) -> Result<Report, CliError> {
+    let file = File::open(path).map_err(CliError::io(path))?;
+    let len = file.metadata().map_err(CliError::io(path))?.len();
+    let mut file = BufReader::new(file);
+
+    let mut report = Report::default();
+    let mut chunk = Vec::new();
+    let mut pos = 0;
+
+    while pos < len {
+        let mut header = [0u8; HEADER_SIZE];
+        if len - pos < HEADER_SIZE as u64 {
+            report.broken = Some(format!("truncated chunk header at byte {}", pos));
+            break;
+        }
+        file.read_exact(&mut header).map_err(CliError::io(path))?;
+
+        if &header[..4] != MAGIC && &header[..4] != PADDING_MAGIC {
+            report.broken = Some(format!("invalid chunk header at byte {}", pos));
+            break;
+        }
+
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as u64;
+        if size > len - pos - HEADER_SIZE as u64 {
+            report.broken = Some(format!("truncated chunk at byte {}", pos));
+            break;
+        }
+
+        chunk.resize(size as usize, 0);
+        file.read_exact(&mut chunk).map_err(CliError::io(path))?;
+
+        // padding in front of an aligned chunk has no checksum
+        if &header[..4] == PADDING_MAGIC {
+            pos += (HEADER_SIZE + chunk.len()) as u64;
+            progress.add(0, pos);
+            continue;
+        }
+
+        match checksum::check(&chunk).0 {
+            ChunkChecksum::Valid => report.valid += 1,
+            ChunkChecksum::Missing => report.missing += 1,
+            ChunkChecksum::Mismatch { .. } => report.mismatches.push((report.chunks, pos)),
+        }
+
+        report.chunks += 1;
+        pos += (HEADER_SIZE + chunk.len()) as u64;
+        progress.add(0, pos);
+    }
+
+    Ok(report)
+}
+
+fn print_report(path: &Path, report: &Report) {
+    println!("file:            {}", path.display());
+    println!("chunks:          {}", report.chunks);
+    println!("valid:           {}", report.valid);
+    println!("mismatched:      {}", report.mismatches.len());
+    println!("no checksum:     {}", report.missing);
+
+    for (chunk, offset) in &report.mismatches {
+        println!("  chunk {} at byte {}: checksum mismatch", chunk, offset);
+    }
+
+    if let Some(broken) = &report.broken {
+        println!("{}, the rest of the file was not checked", broken);
+    }
+}
+
+fn report_json(file: &str, report: &Report) -> Value {
+    let mismatches = report
+        .mismatches
+        .iter()
+        .map(|(chunk, offset)| object([("chunk", (*chunk).into()), ("offset", (*offset).into())]))
+        .collect();
+
+    object([
+        ("file", file.into()),
+        ("chunks", report.chunks.into()),
+        ("valid", report.valid.into()),
+        ("missing", report.missing.into()),
+        ("mismatches", Value::Array(mismatches)),
+        ("broken", report.broken.clone().into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use sfbinpack::{CompressedTrainingDataEntryWriter, WriterOptions};
+
+    use super::*;
+
+    fn progress() -> Progress {
+        Output::default().progress(0)
+    }
+
+    #[test]
+    fn test_verify_without_checksums() {
+        let report = verify(Path::new("./test/ep1.binpack"), &mut progress()).unwrap();
+
+        assert_eq!((report.chunks, report.valid, report.missing), (1, 0, 1));
+        assert!(!report.failed(false));
+        assert!(report.failed(true));
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let mut reader = super::super::open_reader(Path::new("./test/ep1.binpack")).unwrap();
+        let options = WriterOptions {
+            checksums: true,
+            ..Default::default()
+        };
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+        while reader.has_next() {
+            writer.write_entry(&reader.next()).unwrap();
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checked.binpack");
+        fs::write(&path, [data.clone(), data.clone()].concat()).unwrap();
+
+        let report = verify(&path, &mut progress()).unwrap();
+        assert_eq!((report.chunks, report.valid, report.missing), (2, 2, 0));
+        assert!(!report.failed(true));
+
+        // a flipped bit in the second chunk
+        let mut damaged = [data.clone(), data.clone()].concat();
+        damaged[data.len() + 20] ^= 0x04;
+        fs::write(&path, damaged).unwrap();
+
+        let report = verify(&path, &mut progress()).unwrap();
+        assert_eq!(report.valid, 1);
+        assert_eq!(report.mismatches, [(1, data.len() as u64)]);
+        assert!(report.failed(false));
+    }
+}
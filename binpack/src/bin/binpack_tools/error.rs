@@ -0,0 +1,123 @@
+use std::io;
+
+use sfbinpack::{
+    formats::FormatError, meta::MetaError, resume::ResumeError, BinpackError,
+    CompressedReaderError, CompressedWriterError,
+};
+use thiserror::Error;
+
+use crate::expr::ExprError;
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("unknown command '{0}'")]
+    UnknownCommand(String),
+    #[error("unknown option '{0}'")]
+    UnknownOption(String),
+    #[error("option '{0}' requires a value")]
+    MissingValue(String),
+    #[error("invalid value '{value}' for option '{name}'")]
+    InvalidValue { name: String, value: String },
+    #[error("{path}: {source}")]
+    Io { path: String, source: io::Error },
+    #[error("{path}: {source}")]
+    Reader {
+        path: String,
+        source: CompressedReaderError,
+    },
+    #[error("{path}: {source}")]
+    Writer {
+        path: String,
+        source: CompressedWriterError,
+    },
+    #[error("{path}: {source}")]
+    Format { path: String, source: FormatError },
+    #[error("{path}: {source}")]
+    Meta { path: String, source: MetaError },
+    #[error("{path}: {source}")]
+    Resume { path: String, source: ResumeError },
+    #[error("{path}: the {what} of the first entries come out for white, not the side to move, check --white-relative")]
+    Perspective { path: String, what: &'static str },
+    #[error("{path}:{line}: invalid FEN '{fen}'")]
+    Fen {
+        path: String,
+        line: usize,
+        fen: String,
+    },
+    #[error("invalid expression '{text}': {source}")]
+    Expression { text: String, source: ExprError },
+    #[error("{0} file(s) failed validation")]
+    ValidationFailed(usize),
+    #[error("files differ")]
+    FilesDiffer,
+    #[error("position not found")]
+    NotFound,
+    #[error("datasets diverge by {0:.4}")]
+    Diverged(f64),
+}
+
+impl CliError {
+    /// Exit status of the process: 1 when a check did not pass (validation
+    /// errors, differing or diverging files, nothing found), 2 for usage errors, 3 for I/O
+    /// errors and 4 for input that is not valid training data
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::ValidationFailed(_)
+            | CliError::FilesDiffer
+            | CliError::NotFound
+            | CliError::Diverged(_) => 1,
+            CliError::Usage(_)
+            | CliError::UnknownCommand(_)
+            | CliError::UnknownOption(_)
+            | CliError::MissingValue(_)
+            | CliError::InvalidValue { .. }
+            | CliError::Expression { .. } => 2,
+            CliError::Writer {
+                source:
+                    CompressedWriterError::InvalidEntry(_) | CompressedWriterError::BrokenChain(_),
+                ..
+            }
+            | CliError::Format {
+                source:
+                    FormatError::Writer(
+                        CompressedWriterError::InvalidEntry(_)
+                        | CompressedWriterError::BrokenChain(_),
+                    ),
+                ..
+            } => 4,
+            CliError::Io { .. }
+            | CliError::Reader {
+                source:
+                    CompressedReaderError::Io(_)
+                    | CompressedReaderError::BinpackError(BinpackError::Io(_)),
+                ..
+            }
+            | CliError::Writer { .. }
+            | CliError::Format {
+                source: FormatError::Io(_) | FormatError::Writer(_),
+                ..
+            }
+            | CliError::Meta {
+                source: MetaError::Io(_),
+                ..
+            }
+            | CliError::Resume {
+                source: ResumeError::Io(_) | ResumeError::Write(_) | ResumeError::Changed(_),
+                ..
+            } => 3,
+            CliError::Reader { .. }
+            | CliError::Format { .. }
+            | CliError::Meta { .. }
+            | CliError::Resume { .. }
+            | CliError::Perspective { .. }
+            | CliError::Fen { .. } => 4,
+        }
+    }
+
+    pub fn io(path: impl AsRef<std::path::Path>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().display().to_string();
+        move |source| CliError::Io { path, source }
+    }
+}
@@ -0,0 +1,607 @@
+//! A small expression language for selecting entries.
+//!
+//! ```text
+//! abs(score) < 1000 && ply > 20 && !in_check
+//! ```
+//!
+//! Integers and booleans are the only types. Expressions are type checked
+//! when they are parsed, evaluating a parsed expression cannot fail.
+//!
+//! | Name | Type | Meaning |
+//! |------|------|---------|
+//! | `score` | int | score from the side to move's point of view |
+//! | `ply` | int | ply of the position |
+//! | `result` | int | game result from the side to move's point of view, -1, 0 or 1 |
+//! | `pieces` | int | number of pieces on the board, kings included |
+//! | `phase` | int | game phase from 24 at the start to 0 with only kings and pawns |
+//! | `rule50` | int | halfmove clock |
+//! | `white` | bool | white is to move |
+//! | `in_check` | bool | the side to move is in check |
+//! | `is_capture` | bool | the move captures a piece |
+//! | `is_promotion` | bool | the move promotes a pawn |
+//! | `is_castle` | bool | the move castles |
+//! | `gives_check` | bool | the move gives check |
+//!
+//! Functions are `abs(x)`, `min(x, y)` and `max(x, y)`. The operators are,
+//! from loosest to tightest binding: `||` (or `or`), `&&` (or `and`),
+//! `== !=`, `< <= > >=`, `+ -`, `* / %` and the prefix operators `!` and `-`.
+
+use std::fmt;
+
+use sfbinpack::{
+    chess::{color::Color, piecetype::PieceType, r#move::MoveType},
+    TrainingDataEntry,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    /// Zero based character offset into the expression
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column + 1, self.message)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Int,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "an integer"),
+            Type::Bool => write!(f, "a boolean"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Score,
+    Ply,
+    Result,
+    Pieces,
+    Phase,
+    Rule50,
+    White,
+    InCheck,
+    IsCapture,
+    IsPromotion,
+    IsCastle,
+    GivesCheck,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "score" => Field::Score,
+            "ply" => Field::Ply,
+            "result" => Field::Result,
+            "pieces" => Field::Pieces,
+            "phase" => Field::Phase,
+            "rule50" => Field::Rule50,
+            "white" => Field::White,
+            "in_check" => Field::InCheck,
+            "is_capture" => Field::IsCapture,
+            "is_promotion" => Field::IsPromotion,
+            "is_castle" => Field::IsCastle,
+            "gives_check" => Field::GivesCheck,
+            _ => return None,
+        })
+    }
+
+    fn ty(self) -> Type {
+        match self {
+            Field::Score
+            | Field::Ply
+            | Field::Result
+            | Field::Pieces
+            | Field::Phase
+            | Field::Rule50 => Type::Int,
+            _ => Type::Bool,
+        }
+    }
+
+    fn eval(self, entry: &TrainingDataEntry) -> i64 {
+        let pos = &entry.pos;
+        let stm = pos.side_to_move();
+
+        match self {
+            Field::Score => entry.score as i64,
+            Field::Ply => entry.ply as i64,
+            Field::Result => entry.result as i64,
+            Field::Pieces => pos.occupied().count() as i64,
+            Field::Phase => pos.game_phase() as i64,
+            Field::Rule50 => pos.rule50_counter() as i64,
+            Field::White => (stm == Color::White) as i64,
+            Field::InCheck => pos.is_checked(stm) as i64,
+            Field::IsCapture => {
+                let captured = pos.piece_at(entry.mv.to());
+                let capture = entry.mv.mtype() == MoveType::EnPassant
+                    || (entry.mv.mtype() != MoveType::Castle
+                        && captured.piece_type() != PieceType::None
+                        && captured.color() != stm);
+                capture as i64
+            }
+            Field::IsPromotion => (entry.mv.mtype() == MoveType::Promotion) as i64,
+            Field::IsCastle => (entry.mv.mtype() == MoveType::Castle) as i64,
+            Field::GivesCheck => pos.gives_check(entry.mv) as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    /// Binding power, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne => 3,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+        }
+    }
+
+    /// Operand type and result type
+    fn types(self, operand: Type) -> (Type, Type) {
+        match self {
+            BinaryOp::Or | BinaryOp::And => (Type::Bool, Type::Bool),
+            // equality works on both types as long as the sides agree
+            BinaryOp::Eq | BinaryOp::Ne => (operand, Type::Bool),
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => (Type::Int, Type::Bool),
+            _ => (Type::Int, Type::Int),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    /// Booleans are stored as 0 and 1
+    Literal(i64),
+    Field(Field),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Abs(Box<Node>),
+    Min(Box<Node>, Box<Node>),
+    Max(Box<Node>, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, entry: &TrainingDataEntry) -> i64 {
+        match self {
+            Node::Literal(value) => *value,
+            Node::Field(field) => field.eval(entry),
+            Node::Not(node) => (node.eval(entry) == 0) as i64,
+            Node::Neg(node) => node.eval(entry).wrapping_neg(),
+            Node::Abs(node) => node.eval(entry).wrapping_abs(),
+            Node::Min(a, b) => a.eval(entry).min(b.eval(entry)),
+            Node::Max(a, b) => a.eval(entry).max(b.eval(entry)),
+            Node::Binary(BinaryOp::Or, a, b) => (a.eval(entry) != 0 || b.eval(entry) != 0) as i64,
+            Node::Binary(BinaryOp::And, a, b) => (a.eval(entry) != 0 && b.eval(entry) != 0) as i64,
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.eval(entry), b.eval(entry));
+                match op {
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    // division by zero yields zero instead of aborting the run
+                    BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+/// A parsed, type checked boolean expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Self, ExprError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: text.chars().count(),
+        };
+
+        let (root, ty) = parser.expression(0)?;
+
+        if let Some((token, column)) = parser.tokens.get(parser.pos) {
+            return Err(ExprError {
+                column: *column,
+                message: format!("unexpected {}", token),
+            });
+        }
+
+        if ty != Type::Bool {
+            return Err(ExprError {
+                column: 0,
+                message: format!("the expression must be a boolean, not {}", ty),
+            });
+        }
+
+        Ok(Self { root })
+    }
+
+    pub fn matches(&self, entry: &TrainingDataEntry) -> bool {
+        self.root.eval(entry) != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Int(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(value) => write!(f, "'{}'", value),
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Op(op) => write!(f, "'{}'", op),
+        }
+    }
+}
+
+const OPERATORS: [&str; 17] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", ",",
+];
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ExprError> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+
+        if c.is_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_digit() {
+            let start = idx;
+            while idx < chars.len() && chars[idx].is_ascii_digit() {
+                idx += 1;
+            }
+            let digits = chars[start..idx].iter().collect::<String>();
+            let value = digits.parse().map_err(|_| ExprError {
+                column: start,
+                message: format!("number '{}' is too large", digits),
+            })?;
+            tokens.push((Token::Int(value), start));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_') {
+                idx += 1;
+            }
+            let name = chars[start..idx].iter().collect::<String>();
+            tokens.push((Token::Ident(name), start));
+        } else {
+            let rest = chars[idx..].iter().take(2).collect::<String>();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| ExprError {
+                    column: idx,
+                    message: format!("unexpected character '{}'", c),
+                })?;
+            tokens.push((Token::Op(op), idx));
+            idx += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Column reported for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ExprError {
+        ExprError {
+            column: self.column(),
+            message: message.into(),
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ExprError> {
+        match self.peek() {
+            Some(Token::Op(found)) if *found == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(format!("expected '{}'", op))),
+        }
+    }
+
+    fn peek_binary(&self) -> Option<BinaryOp> {
+        let op = match self.peek()? {
+            Token::Op(op) => *op,
+            Token::Ident(name) if name == "and" => "&&",
+            Token::Ident(name) if name == "or" => "||",
+            _ => return None,
+        };
+
+        Some(match op {
+            "||" => BinaryOp::Or,
+            "&&" => BinaryOp::And,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            _ => return None,
+        })
+    }
+
+    fn check(&self, column: usize, expected: Type, found: Type) -> Result<(), ExprError> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(ExprError {
+                column,
+                message: format!("expected {}, found {}", expected, found),
+            })
+        }
+    }
+
+    /// Precedence climbing over the binary operators
+    fn expression(&mut self, min_precedence: u8) -> Result<(Node, Type), ExprError> {
+        let column = self.column();
+        let (mut lhs, mut lhs_ty) = self.unary()?;
+
+        while let Some(op) = self.peek_binary() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            let op_column = self.column();
+            self.pos += 1;
+
+            let rhs_column = self.column();
+            let (rhs, rhs_ty) = self.expression(op.precedence())?;
+
+            let (operand, result) = op.types(lhs_ty);
+            self.check(column, operand, lhs_ty)?;
+            self.check(rhs_column, operand, rhs_ty).map_err(|err| {
+                if matches!(op, BinaryOp::Eq | BinaryOp::Ne) {
+                    ExprError {
+                        column: op_column,
+                        message: format!("cannot compare {} with {}", lhs_ty, rhs_ty),
+                    }
+                } else {
+                    err
+                }
+            })?;
+
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+            lhs_ty = result;
+        }
+
+        Ok((lhs, lhs_ty))
+    }
+
+    fn unary(&mut self) -> Result<(Node, Type), ExprError> {
+        let column = self.column();
+
+        match self.peek() {
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                let (node, ty) = self.unary()?;
+                self.check(column + 1, Type::Bool, ty)?;
+                Ok((Node::Not(Box::new(node)), Type::Bool))
+            }
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                let (node, ty) = self.unary()?;
+                self.check(column + 1, Type::Int, ty)?;
+                Ok((Node::Neg(Box::new(node)), Type::Int))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<(Node, Type), ExprError> {
+        let column = self.column();
+        let Some((token, _)) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.error("unexpected end of expression"));
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Int(value) => Ok((Node::Literal(value), Type::Int)),
+            Token::Op("(") => {
+                let inner = self.expression(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok((Node::Literal(1), Type::Bool)),
+                "false" => Ok((Node::Literal(0), Type::Bool)),
+                "abs" | "min" | "max" => self.call(&name),
+                _ => {
+                    let field = Field::from_name(&name).ok_or_else(|| ExprError {
+                        column,
+                        message: format!("unknown name '{}'", name),
+                    })?;
+                    Ok((Node::Field(field), field.ty()))
+                }
+            },
+            Token::Op(_) => {
+                self.pos -= 1;
+                Err(self.error(format!("unexpected {}", token)))
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<(Node, Type), ExprError> {
+        self.expect("(")?;
+
+        let arity = if name == "abs" { 1 } else { 2 };
+        let mut args = Vec::with_capacity(arity);
+
+        for idx in 0..arity {
+            if idx > 0 {
+                self.expect(",")?;
+            }
+            let column = self.column();
+            let (node, ty) = self.expression(0)?;
+            self.check(column, Type::Int, ty)?;
+            args.push(Box::new(node));
+        }
+        self.expect(")")?;
+
+        let mut args = args.into_iter();
+        let mut arg = || args.next().unwrap();
+
+        let node = match name {
+            "abs" => Node::Abs(arg()),
+            "min" => Node::Min(arg(), arg()),
+            _ => Node::Max(arg(), arg()),
+        };
+
+        Ok((node, Type::Int))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        // crate::testing is not built for the tools, so spell the entries as EPD
+        let quiet = TrainingDataEntry::from_epd(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - sm e2e4; ce 35; c9 \"1-0\";",
+        )
+        .unwrap();
+        let check = TrainingDataEntry::from_epd(
+            "4k3/8/8/8/8/8/3q4/4K3 w - - fmvn 40; sm e1d2; ce -1500; c9 \"1-0\";",
+        )
+        .unwrap();
+        assert_eq!((quiet.ply, check.ply), (0, 78));
+
+        let cases = [
+            ("abs(score) < 1000 && ply > 20 && !in_check", false, false),
+            ("abs(score) < 1000 || in_check", true, true),
+            ("is_capture", false, true),
+            ("white and pieces == 32", true, false),
+            ("phase == 24 || phase == 4", true, true),
+            ("min(score, 0) == 0 or max(ply, 70) != 70", true, true),
+            ("-score > 1000", false, true),
+            ("1 + 2 * 3 == 7 && (1 + 2) * 3 == 9", true, true),
+            ("score / 0 == 0 && 7 % 3 == 1", true, true),
+            ("in_check == is_capture", true, true),
+            ("!!true && result == 1 && !gives_check", true, true),
+        ];
+
+        for (text, expected_quiet, expected_check) in cases {
+            let expr = Expr::parse(text).unwrap();
+            assert_eq!(expr.matches(&quiet), expected_quiet, "{}", text);
+            assert_eq!(expr.matches(&check), expected_check, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = [
+            (
+                "score",
+                0,
+                "the expression must be a boolean, not an integer",
+            ),
+            (
+                "score && ply > 1",
+                0,
+                "expected a boolean, found an integer",
+            ),
+            ("ply > in_check", 6, "expected an integer, found a boolean"),
+            (
+                "score == true",
+                6,
+                "cannot compare an integer with a boolean",
+            ),
+            (
+                "abs(score < 1) == 1",
+                4,
+                "expected an integer, found a boolean",
+            ),
+            ("foo > 1", 0, "unknown name 'foo'"),
+            ("(ply > 1", 8, "expected ')'"),
+            ("ply > 1 ply", 8, "unexpected 'ply'"),
+            ("ply > ", 6, "unexpected end of expression"),
+            ("ply # 1", 4, "unexpected character '#'"),
+        ];
+
+        for (text, column, message) in cases {
+            let err = Expr::parse(text).unwrap_err();
+            assert_eq!(
+                err,
+                ExprError {
+                    column,
+                    message: message.to_string()
+                },
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! `binpack-tools`, command line utilities for Stockfish binpacks.
+
+mod args;
+mod commands;
+mod error;
+mod expr;
+mod json;
+mod output;
+mod rng;
+
+use std::process::ExitCode;
+
+use args::Args;
+use commands::{
+    compare, convert, diff, filter, grep, head, inspect, interleave, merge, repair, rescore,
+    sample, serve, shuffle, split, stats, validate, verify,
+};
+use error::CliError;
+use output::Output;
+
+const USAGE: &str = "usage: binpack-tools <command> [options]
+
+commands:
+  inspect FILE...      chunk, entry and game counts and byte sizes
+  head [-n N] FILE     print the first N entries
+  convert IN OUT       convert between binpack, plain, bin, pgn, jsonl and epd
+  validate FILE...     check that every entry decodes and is consistent
+  verify FILE...       check the chunk checksums
+  stats FILE...        score, result, ply and piece count distributions
+  filter --where EXPR IN OUT
+                       keep the entries matching an expression
+  merge OUT IN...      concatenate binpacks
+  split -n N IN OUT_PREFIX
+                       split a binpack into files of about N entries
+  shuffle IN OUT       shuffle the games of a binpack, spilling to disk
+  sample (--rate R | --count N) IN OUT
+                       random subset of whole games
+  diff A B             compare the entries of two binpacks
+  compare A B          compare the distributions of two binpacks
+  rescore --engine PATH IN OUT
+                       replace scores with those of a UCI engine
+  interleave [--weights W,...] IN... OUT
+                       mix the games of several binpacks
+  repair IN OUT        salvage the complete chains of a damaged binpack
+  grep --fen FEN FILE...
+                       find the entries with a position and their game
+  serve FILE...        stream filtered, shuffled entries to trainers over TCP
+
+options for every command:
+  --json               print the report as a single JSON document
+  -q, --quiet          no progress bar, notes or text report
+  --progress           draw the progress bar even if stderr is not a terminal
+
+exit status: 0 on success, 1 when a check did not pass (validation errors,
+differing or diverging files, nothing found), 2 for usage errors, 3 for I/O
+errors and 4 for input that is not valid training data.
+
+Run `binpack-tools help <command>` or `binpack-tools <command> --help` for the
+options of a command.";
+
+fn main() -> ExitCode {
+    let mut args = Args::from_env();
+    let out = Output::from_args(&mut args);
+    let command = args.subcommand();
+
+    let result = if args.flag(&["-h", "--help"]) {
+        print_usage(command.as_deref())
+    } else {
+        match command.as_deref() {
+            Some("inspect") => inspect::run(args, &out),
+            Some("head") => head::run(args, &out),
+            Some("convert") => convert::run(args, &out),
+            Some("validate") => validate::run(args, &out),
+            Some("verify") => verify::run(args, &out),
+            Some("stats") => stats::run(args, &out),
+            Some("filter") => filter::run(args, &out),
+            Some("merge") => merge::run(args, &out),
+            Some("split") => split::run(args, &out),
+            Some("shuffle") => shuffle::run(args, &out),
+            Some("sample") => sample::run(args, &out),
+            Some("diff") => diff::run(args, &out),
+            Some("compare") => compare::run(args, &out),
+            Some("rescore") => rescore::run(args, &out),
+            Some("interleave") => interleave::run(args, &out),
+            Some("repair") => repair::run(args, &out),
+            Some("grep") => grep::run(args, &out),
+            Some("serve") => serve::run(args, &out),
+            Some("help") => print_usage(args.subcommand().as_deref()),
+            None => print_usage(None),
+            Some(other) => Err(CliError::UnknownCommand(other.to_string())),
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(usage)) => {
+            eprintln!("{}", usage);
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("binpack-tools: {}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn print_usage(command: Option<&str>) -> Result<(), CliError> {
+    let usage = match command {
+        Some("inspect") => inspect::USAGE,
+        Some("head") => head::USAGE,
+        Some("convert") => convert::USAGE,
+        Some("validate") => validate::USAGE,
+        Some("verify") => verify::USAGE,
+        Some("stats") => stats::USAGE,
+        Some("filter") => filter::USAGE,
+        Some("merge") => merge::USAGE,
+        Some("split") => split::USAGE,
+        Some("shuffle") => shuffle::USAGE,
+        Some("sample") => sample::USAGE,
+        Some("diff") => diff::USAGE,
+        Some("compare") => compare::USAGE,
+        Some("rescore") => rescore::USAGE,
+        Some("interleave") => interleave::USAGE,
+        Some("repair") => repair::USAGE,
+        Some("grep") => grep::USAGE,
+        Some("serve") => serve::USAGE,
+        Some(other) => return Err(CliError::UnknownCommand(other.to_string())),
+        None => USAGE,
+    };
+
+    println!("{}", usage);
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{args::Args, json::Value};
+
+/// Output options shared by every command.
+///
+/// Reports go to stdout, as text or with `--json` as a single JSON document.
+/// Progress bars and notes like the seed of a random run go to stderr, both
+/// are silenced by `--quiet` and so is the text report; JSON is printed
+/// regardless.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Output {
+    pub json: bool,
+    pub quiet: bool,
+    progress: bool,
+}
+
+impl Output {
+    /// Takes `--json`, `-q/--quiet` and `--progress` out of the arguments,
+    /// without `--progress` the progress bar is drawn when stderr is a terminal
+    pub fn from_args(args: &mut Args) -> Self {
+        let json = args.flag(&["--json"]);
+        let quiet = args.flag(&["-q", "--quiet"]);
+        let progress = args.flag(&["--progress"]);
+
+        Self {
+            json,
+            quiet,
+            progress: !quiet && (progress || io::stderr().is_terminal()),
+        }
+    }
+
+    /// Prints the result of a command, `json` is only called with `--json`
+    /// and `text` only without `--json` or `--quiet`
+    pub fn report(&self, json: impl FnOnce() -> Value, text: impl FnOnce()) {
+        if self.json {
+            println!("{}", json());
+        } else if !self.quiet {
+            text();
+        }
+    }
+
+    /// A note on stderr, left out with `--quiet`
+    pub fn info(&self, message: impl Display) {
+        if !self.quiet {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// A progress bar over `total` bytes of input
+    pub fn progress(&self, total: u64) -> Progress {
+        Progress {
+            total,
+            offset: 0,
+            position: 0,
+            entries: 0,
+            next_check: 0,
+            enabled: self.progress,
+            last_draw: Instant::now(),
+        }
+    }
+}
+
+/// Progress bar on stderr, fed with the number of entries processed and the
+/// position in the input they were read from
+#[derive(Debug)]
+pub struct Progress {
+    total: u64,
+    offset: u64,
+    position: u64,
+    entries: u64,
+    next_check: u64,
+    enabled: bool,
+    last_draw: Instant,
+}
+
+impl Progress {
+    const WIDTH: u64 = 30;
+    const INTERVAL: Duration = Duration::from_millis(200);
+    /// Entries between two looks at the clock
+    const CHECK_EVERY: u64 = 4096;
+
+    /// Positions passed afterwards are counted from `offset`, for inputs
+    /// that are read one after the other
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    pub fn entry(&mut self, position: u64) {
+        self.add(1, position);
+    }
+
+    pub fn add(&mut self, entries: u64, position: u64) {
+        self.entries += entries;
+        self.position = self.offset + position;
+
+        if !self.enabled || self.entries < self.next_check {
+            return;
+        }
+
+        self.next_check = self.entries + Self::CHECK_EVERY;
+        if self.last_draw.elapsed() >= Self::INTERVAL {
+            self.draw();
+            self.last_draw = Instant::now();
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.enabled {
+            self.draw();
+            eprintln!();
+        }
+    }
+
+    fn draw(&self) {
+        let position = self.position.min(self.total);
+        let filled = (position * Self::WIDTH)
+            .checked_div(self.total)
+            .unwrap_or(Self::WIDTH);
+        let percent = (position * 100).checked_div(self.total).unwrap_or(100);
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:>3}% {:.1}/{:.1} MB, {} entries",
+            "#".repeat(filled as usize),
+            ".".repeat((Self::WIDTH - filled) as usize),
+            percent,
+            position as f64 / 1e6,
+            self.total as f64 / 1e6,
+            self.entries
+        );
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Args {
+        Args::new(list.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_from_args() {
+        let mut rest = args(&["stats", "--json", "-q", "--progress", "a.binpack"]);
+        let output = Output::from_args(&mut rest);
+
+        assert!(output.json);
+        assert!(output.quiet);
+        // --quiet wins over --progress
+        assert!(!output.progress);
+        assert_eq!(rest.finish().unwrap(), vec!["stats", "a.binpack"]);
+    }
+}
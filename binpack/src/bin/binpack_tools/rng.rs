@@ -0,0 +1,12 @@
+/// SplitMix64: the same seed always produces the same sequence on every
+/// platform, which is what makes `--seed` reproducible.
+pub use sfbinpack::rng::SplitMix64 as Rng;
+
+/// Seed from the clock and process id, for runs without `--seed`
+pub fn seed_from_time() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+
+    Rng::new(nanos ^ ((std::process::id() as u64) << 32)).next_u64()
+}
@@ -0,0 +1,607 @@
+//! Opening books: starting positions for generating games and the book moves
+//! of [`SkipOpening`](crate::filter::SkipOpening).
+//!
+//! A [`PolyglotBook`] is a polyglot `.bin` book, an [`EpdSuite`] a list of
+//! FEN or EPD positions. Both implement [`Book`] for the opening filters and
+//! [`StartPositions`] for picking a random position to start a game from,
+//! weighted like the book says.
+//!
+//! Polyglot books are looked up by the polyglot hash of a position, which is
+//! built from a fixed table of 781 random keys. The crate does not ship that
+//! table: load it with [`PolyglotKeys::read`], 781 big endian `u64`s in the
+//! order of the polyglot sources.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use rand::{rngs::StdRng, SeedableRng};
+//! use sfbinpack::book::{EpdSuite, StartPositions};
+//!
+//! let epd = "\
+//! rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - weight 3;
+//! rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq -
+//! ";
+//! let suite = EpdSuite::read(Cursor::new(epd)).unwrap();
+//!
+//! let mut rng = StdRng::seed_from_u64(1);
+//! let start = suite.sample_position(&mut rng).unwrap();
+//! assert!(suite.positions().contains(&start));
+//! ```
+
+use std::io::{self, BufRead, Read};
+
+use rand::Rng;
+use thiserror::Error;
+
+use crate::{
+    chess::{
+        attacks,
+        castling_rights::CastlingRights,
+        color::Color,
+        coords::Square,
+        piecetype::PieceType,
+        position::Position,
+        r#move::{Move, MoveType},
+    },
+    filter::{parse_fen_or_epd, Book, ExcludePositions},
+};
+
+/// Keys of a polyglot hash table: 768 for pieces on squares, 4 for castling
+/// rights, 8 for en passant files and 1 for white to move
+pub const POLYGLOT_KEY_COUNT: usize = 781;
+
+/// Bytes of a polyglot book entry
+pub const POLYGLOT_ENTRY_SIZE: usize = 16;
+
+const CASTLING_OFFSET: usize = 768;
+const EN_PASSANT_OFFSET: usize = 772;
+const TURN_OFFSET: usize = 780;
+
+#[derive(Debug, Error)]
+pub enum BookError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid FEN on line {line}: {fen}")]
+    InvalidFen { line: usize, fen: String },
+
+    #[error("Invalid weight on line {line}: {weight}")]
+    InvalidWeight { line: usize, weight: String },
+
+    #[error("Polyglot key table has {0} keys, expected 781")]
+    InvalidKeys(usize),
+
+    #[error("Polyglot book size {0} is not a multiple of 16 bytes")]
+    InvalidBook(usize),
+}
+
+type Result<T> = std::result::Result<T, BookError>;
+
+/// Picks starting positions for new games
+pub trait StartPositions {
+    /// A random position, more likely the more weight the book gives it,
+    /// None if the book has nothing to start from
+    fn sample_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Position>;
+}
+
+/// The random keys of the polyglot hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolyglotKeys {
+    keys: Box<[u64; POLYGLOT_KEY_COUNT]>,
+}
+
+impl PolyglotKeys {
+    pub fn new(keys: [u64; POLYGLOT_KEY_COUNT]) -> Self {
+        Self {
+            keys: Box::new(keys),
+        }
+    }
+
+    /// Reads the 781 keys as big endian `u64`s
+    pub fn read(mut reader: impl Read) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() != POLYGLOT_KEY_COUNT * 8 {
+            return Err(BookError::InvalidKeys(data.len() / 8));
+        }
+
+        let mut keys = [0; POLYGLOT_KEY_COUNT];
+        for (key, bytes) in keys.iter_mut().zip(data.chunks_exact(8)) {
+            *key = u64::from_be_bytes(bytes.try_into().unwrap());
+        }
+        Ok(Self::new(keys))
+    }
+
+    /// The polyglot hash of `pos`. The en passant file only counts when a
+    /// pawn of the side to move can capture there, the move counters never.
+    pub fn hash(&self, pos: &Position) -> u64 {
+        let mut hash = 0;
+
+        for sq in pos.occupied().iter() {
+            let piece = pos.piece_at(sq);
+            // black pawn, white pawn, black knight, ...
+            let kind = 2 * piece.piece_type().ordinal() as usize
+                + usize::from(piece.color() == Color::White);
+            hash ^= self.keys[64 * kind + sq.index() as usize];
+        }
+
+        let rights = [
+            CastlingRights::WHITE_KING_SIDE,
+            CastlingRights::WHITE_QUEEN_SIDE,
+            CastlingRights::BLACK_KING_SIDE,
+            CastlingRights::BLACK_QUEEN_SIDE,
+        ];
+        for (idx, right) in rights.into_iter().enumerate() {
+            if pos.castling_rights().contains(right) {
+                hash ^= self.keys[CASTLING_OFFSET + idx];
+            }
+        }
+
+        let stm = pos.side_to_move();
+        let ep = pos.ep_square();
+        if ep != Square::NONE {
+            let capturers = attacks::pawn(!stm, ep) & pos.pieces_bb_color(stm, PieceType::Pawn);
+            if capturers.count() > 0 {
+                hash ^= self.keys[EN_PASSANT_OFFSET + ep.index() as usize % 8];
+            }
+        }
+
+        if stm == Color::White {
+            hash ^= self.keys[TURN_OFFSET];
+        }
+
+        hash
+    }
+}
+
+/// An entry of a polyglot book, as stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolyglotEntry {
+    pub key: u64,
+    /// To square in bits 0 to 5, from square in bits 6 to 11 and the
+    /// promotion piece, 1 for a knight to 4 for a queen, in bits 12 to 14.
+    /// Castling is the king taking its own rook.
+    pub mv: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl PolyglotEntry {
+    pub fn from_bytes(bytes: &[u8; POLYGLOT_ENTRY_SIZE]) -> Self {
+        Self {
+            key: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            mv: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+            learn: u32::from_be_bytes(bytes[12..].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; POLYGLOT_ENTRY_SIZE] {
+        let mut bytes = [0; POLYGLOT_ENTRY_SIZE];
+        bytes[..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+
+    /// The legal move of `pos` the entry stands for, None if there is none,
+    /// e.g. on a hash collision
+    pub fn decode_move(&self, pos: &Position) -> Option<Move> {
+        let to = (self.mv & 63) as u32;
+        let from = ((self.mv >> 6) & 63) as u32;
+        let promotion = ((self.mv >> 12) & 7) as u8;
+
+        attacks::legal_moves(pos).into_iter().find(|mv| {
+            let promoted = match mv.mtype() {
+                MoveType::Promotion => mv.promoted_piece().piece_type().ordinal(),
+                _ => 0,
+            };
+            mv.from().index() == from && mv.to().index() == to && promoted == promotion
+        })
+    }
+
+    /// The polyglot encoding of a move
+    pub fn encode_move(mv: Move) -> u16 {
+        let promotion = match mv.mtype() {
+            MoveType::Promotion => mv.promoted_piece().piece_type().ordinal() as u16,
+            _ => 0,
+        };
+        (promotion << 12) | ((mv.from().index() as u16) << 6) | mv.to().index() as u16
+    }
+}
+
+/// A polyglot opening book
+#[derive(Debug, Clone)]
+pub struct PolyglotBook {
+    /// Sorted by key, like in the file
+    entries: Vec<PolyglotEntry>,
+    keys: PolyglotKeys,
+}
+
+impl PolyglotBook {
+    /// Reads a `.bin` book, whose positions are hashed with `keys`
+    pub fn read(mut reader: impl Read, keys: PolyglotKeys) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() % POLYGLOT_ENTRY_SIZE != 0 {
+            return Err(BookError::InvalidBook(data.len()));
+        }
+
+        let entries = data
+            .chunks_exact(POLYGLOT_ENTRY_SIZE)
+            .map(|bytes| PolyglotEntry::from_bytes(bytes.try_into().unwrap()))
+            .collect();
+        Ok(Self::from_entries(entries, keys))
+    }
+
+    pub fn from_entries(mut entries: Vec<PolyglotEntry>, keys: PolyglotKeys) -> Self {
+        // stable, the moves of a position keep their order
+        entries.sort_by_key(|entry| entry.key);
+        Self { entries, keys }
+    }
+
+    pub fn keys(&self) -> &PolyglotKeys {
+        &self.keys
+    }
+
+    /// Number of entries, moves of all positions
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries of the position with polyglot hash `key`
+    pub fn entries(&self, key: u64) -> &[PolyglotEntry] {
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let end = self.entries.partition_point(|entry| entry.key <= key);
+        &self.entries[start..end]
+    }
+
+    /// The book moves of `pos` with their weights
+    pub fn moves(&self, pos: &Position) -> Vec<(Move, u16)> {
+        self.entries(self.keys.hash(pos))
+            .iter()
+            .filter_map(|entry| Some((entry.decode_move(pos)?, entry.weight)))
+            .collect()
+    }
+
+    /// A book move of `pos`, more likely the more weight it has, None if
+    /// no move of the position has any weight
+    pub fn pick_move<R: Rng + ?Sized>(&self, pos: &Position, rng: &mut R) -> Option<Move> {
+        let moves = self.moves(pos);
+        let total = moves.iter().map(|&(_, weight)| weight as u64).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = rng.gen_range(0..total);
+        moves.into_iter().find_map(|(mv, weight)| {
+            if pick < weight as u64 {
+                Some(mv)
+            } else {
+                pick -= weight as u64;
+                None
+            }
+        })
+    }
+
+    /// Plays weighted book moves from `pos` until the book ends or
+    /// `max_plies` moves were played, and returns where that leads
+    pub fn play_line<R: Rng + ?Sized>(
+        &self,
+        pos: &Position,
+        max_plies: usize,
+        rng: &mut R,
+    ) -> Position {
+        let mut pos = *pos;
+        for _ in 0..max_plies {
+            let Some(mv) = self.pick_move(&pos, rng) else {
+                break;
+            };
+            pos.do_move(mv);
+        }
+        pos
+    }
+}
+
+/// Moves listed for the position, whatever their weight
+impl Book for PolyglotBook {
+    fn is_book_move(&self, pos: &Position, mv: Move) -> bool {
+        self.moves(pos)
+            .iter()
+            .any(|&(book_move, _)| book_move == mv)
+    }
+}
+
+/// Lines from the start position as deep as the book goes
+impl StartPositions for PolyglotBook {
+    fn sample_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Position> {
+        let start = Position::new();
+        self.pick_move(&start, rng)?;
+        Some(self.play_line(&start, usize::MAX, rng))
+    }
+}
+
+/// Positions of an EPD or FEN file, each with a weight
+#[derive(Debug, Clone, Default)]
+pub struct EpdSuite {
+    positions: Vec<Position>,
+    /// Running totals of the weights, for sampling
+    cumulative: Vec<u64>,
+    set: ExcludePositions,
+}
+
+impl EpdSuite {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads one position per line of FEN or EPD. An EPD `weight N;`
+    /// operation sets the weight of a position, 1 otherwise. Empty lines and
+    /// lines starting with `#` are skipped.
+    pub fn read(reader: impl BufRead) -> Result<Self> {
+        let mut suite = Self::new();
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let pos = parse_fen_or_epd(line).ok_or_else(|| BookError::InvalidFen {
+                line: idx + 1,
+                fen: line.to_string(),
+            })?;
+
+            let weight = match epd_weight(line) {
+                Some(weight) => weight.parse().map_err(|_| BookError::InvalidWeight {
+                    line: idx + 1,
+                    weight: weight.to_string(),
+                })?,
+                None => 1,
+            };
+            suite.push(pos, weight);
+        }
+
+        Ok(suite)
+    }
+
+    /// Adds a position, a weight of 0 makes it a book position that is never
+    /// sampled
+    pub fn push(&mut self, pos: Position, weight: u32) {
+        let total = self.cumulative.last().copied().unwrap_or(0);
+        self.positions.push(pos);
+        self.cumulative.push(total + weight as u64);
+        self.set.insert(&pos);
+    }
+
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn contains(&self, pos: &Position) -> bool {
+        self.set.contains(pos)
+    }
+}
+
+/// A move is a book move if it leads to a position of the suite
+impl Book for EpdSuite {
+    fn is_book_move(&self, pos: &Position, mv: Move) -> bool {
+        self.set.contains(&pos.after_move(mv))
+    }
+}
+
+impl StartPositions for EpdSuite {
+    fn sample_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Position> {
+        let total = self.cumulative.last().copied().filter(|&total| total > 0)?;
+        let pick = rng.gen_range(0..total);
+        let idx = self.cumulative.partition_point(|&sum| sum <= pick);
+        Some(self.positions[idx])
+    }
+}
+
+/// The operand of the `weight` operation of an EPD line
+fn epd_weight(line: &str) -> Option<&str> {
+    // the operations start after the four position fields
+    let mut rest = line;
+    for _ in 0..4 {
+        rest = rest.trim_start();
+        rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
+    }
+
+    rest.split(';').find_map(|operation| {
+        let mut tokens = operation.split_whitespace();
+        (tokens.next() == Some("weight")).then(|| tokens.next().unwrap_or(""))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// A made up key table, the real one is not part of the crate
+    fn keys() -> PolyglotKeys {
+        let mut state = 1u64;
+        let mut keys = [0; POLYGLOT_KEY_COUNT];
+        for key in &mut keys {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            *key = state ^ (state >> 29);
+        }
+        PolyglotKeys::new(keys)
+    }
+
+    fn entry(keys: &PolyglotKeys, pos: &Position, uci: &str, weight: u16) -> PolyglotEntry {
+        PolyglotEntry {
+            key: keys.hash(pos),
+            mv: PolyglotEntry::encode_move(Move::from_uci(pos, uci).unwrap()),
+            weight,
+            learn: 0,
+        }
+    }
+
+    #[test]
+    fn test_polyglot_hash() {
+        let keys = keys();
+        let start = Position::new();
+
+        // the en passant square only counts when a capture is possible
+        let e4 = start.after_move(Move::from_uci(&start, "e2e4").unwrap());
+        let no_ep =
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+                .unwrap();
+        assert_eq!(keys.hash(&e4), keys.hash(&no_ep));
+
+        let ep = Position::from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+            .unwrap();
+        let mut ep_gone = ep;
+        ep_gone.set_ep_square_unchecked(Square::NONE);
+        assert_ne!(keys.hash(&ep), keys.hash(&ep_gone));
+
+        let mut black = start;
+        black.set_side_to_move(Color::Black);
+        assert_ne!(keys.hash(&start), keys.hash(&black));
+
+        let data = keys
+            .keys
+            .iter()
+            .flat_map(|key| key.to_be_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(PolyglotKeys::read(Cursor::new(&data)).unwrap(), keys);
+        assert!(matches!(
+            PolyglotKeys::read(Cursor::new(&data[8..])),
+            Err(BookError::InvalidKeys(780))
+        ));
+    }
+
+    #[test]
+    fn test_polyglot_moves() {
+        let castle = Position::from_fen("4k3/8/8/8/8/8/7P/4K2R w K - 0 1").unwrap();
+        let mv = Move::from_uci(&castle, "e1g1").unwrap();
+        // the king takes its own rook
+        let raw = PolyglotEntry::encode_move(mv);
+        assert_eq!(raw, (Square::E1.index() << 6 | Square::H1.index()) as u16);
+
+        let entry = PolyglotEntry {
+            key: 0,
+            mv: raw,
+            weight: 1,
+            learn: 7,
+        };
+        assert_eq!(entry.decode_move(&castle), Some(mv));
+        assert_eq!(PolyglotEntry::from_bytes(&entry.to_bytes()), entry);
+        assert_eq!(entry.decode_move(&Position::new()), None);
+
+        let promotion = Position::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let knight = Move::from_uci(&promotion, "b7b8n").unwrap();
+        let raw = PolyglotEntry::encode_move(knight);
+        assert_eq!(raw >> 12, 1);
+        let entry = PolyglotEntry { mv: raw, ..entry };
+        assert_eq!(entry.decode_move(&promotion), Some(knight));
+    }
+
+    #[test]
+    fn test_polyglot_book() {
+        let keys = keys();
+        let start = Position::new();
+        let e4 = start.after_move(Move::from_uci(&start, "e2e4").unwrap());
+
+        let entries = [
+            entry(&keys, &e4, "e7e5", 1),
+            entry(&keys, &start, "e2e4", 3),
+            entry(&keys, &start, "d2d4", 1),
+            entry(&keys, &start, "g1f3", 0),
+        ];
+        let data = entries
+            .iter()
+            .flat_map(|entry| entry.to_bytes())
+            .collect::<Vec<_>>();
+        let book = PolyglotBook::read(Cursor::new(&data), keys.clone()).unwrap();
+        assert_eq!(book.len(), 4);
+        assert_eq!(book.moves(&start).len(), 3);
+
+        let g1f3 = Move::from_uci(&start, "g1f3").unwrap();
+        assert!(book.is_book_move(&start, g1f3));
+        assert!(!book.is_book_move(&start, Move::from_uci(&start, "a2a3").unwrap()));
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut picks = [0; 2];
+        for _ in 0..400 {
+            let mv = book.pick_move(&start, &mut rng).unwrap();
+            assert_ne!(mv, g1f3, "a move without weight was picked");
+            picks[usize::from(mv.as_uci() == "d2d4")] += 1;
+        }
+        assert!(picks[0] > 2 * picks[1], "{picks:?}");
+
+        // e4 e5 or d4, then out of book
+        let after_e5 = e4.after_move(Move::from_uci(&e4, "e7e5").unwrap());
+        let after_d4 = start.after_move(Move::from_uci(&start, "d2d4").unwrap());
+        for _ in 0..20 {
+            let pos = book.sample_position(&mut rng).unwrap();
+            assert!(pos == after_e5 || pos == after_d4);
+        }
+
+        assert!(matches!(
+            PolyglotBook::read(Cursor::new(&data[1..]), keys.clone()),
+            Err(BookError::InvalidBook(63))
+        ));
+        let empty = PolyglotBook::from_entries(Vec::new(), keys);
+        assert_eq!(empty.sample_position(&mut rng), None);
+    }
+
+    #[test]
+    fn test_epd_suite() {
+        let text = "\
+# openings
+rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - weight 9; id \"e4\";
+
+rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1
+rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - weight 0;
+";
+        let suite = EpdSuite::read(Cursor::new(text)).unwrap();
+        assert_eq!(suite.len(), 3);
+
+        let start = Position::new();
+        assert!(suite.is_book_move(&start, Move::from_uci(&start, "g1f3").unwrap()));
+        assert!(!suite.is_book_move(&start, Move::from_uci(&start, "c2c4").unwrap()));
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut counts = [0; 3];
+        for _ in 0..500 {
+            let pos = suite.sample_position(&mut rng).unwrap();
+            let idx = suite.positions().iter().position(|p| *p == pos).unwrap();
+            counts[idx] += 1;
+        }
+        assert_eq!(counts[2], 0);
+        assert!(counts[0] > 4 * counts[1], "{counts:?}");
+
+        for (text, line) in [("x/y/z w - -\n", 1), ("# x\nnot a fen\n", 2)] {
+            assert!(matches!(
+                EpdSuite::read(Cursor::new(text)),
+                Err(BookError::InvalidFen { line: l, .. }) if l == line
+            ));
+        }
+        assert!(matches!(
+            EpdSuite::read(Cursor::new("4k3/8/8/8/8/8/8/4K3 w - - weight x;")),
+            Err(BookError::InvalidWeight { line: 1, .. })
+        ));
+        assert_eq!(EpdSuite::new().sample_position(&mut rng), None);
+    }
+}
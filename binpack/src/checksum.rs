@@ -0,0 +1,88 @@
+//! Optional CRC32 trailers of chunks.
+//!
+//! With [`WriterOptions::checksums`](crate::WriterOptions::checksums) every
+//! chunk ends with [`TRAILER_SIZE`] bytes: [`TRAILER_MAGIC`] and the CRC32
+//! of the chunk data before it, little endian. The trailer counts towards
+//! the chunk size but is too short to hold a chain, so readers that do not
+//! know about it skip it like any other slack at the end of a chunk. This
+//! crate's reader checks the CRC of every chunk that has a trailer and
+//! reports [`BinpackError::ChecksumMismatch`](crate::BinpackError) when it
+//! is wrong.
+
+/// Marks a chunk trailer, 8 bytes so chain data is all but certain to
+/// never end like it
+pub const TRAILER_MAGIC: [u8; 8] = *b"BINPCRC\x01";
+
+/// Bytes of a trailer, magic and CRC32
+pub const TRAILER_SIZE: usize = TRAILER_MAGIC.len() + 4;
+
+/// What a chunk's trailer says about its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkChecksum {
+    /// The chunk has no trailer
+    Missing,
+    /// The CRC matches the data
+    Valid,
+    Mismatch {
+        stored: u32,
+        computed: u32,
+    },
+}
+
+/// The trailer to append to `data`
+pub fn trailer(data: &[u8]) -> [u8; TRAILER_SIZE] {
+    let mut trailer = [0; TRAILER_SIZE];
+    trailer[..TRAILER_MAGIC.len()].copy_from_slice(&TRAILER_MAGIC);
+    trailer[TRAILER_MAGIC.len()..].copy_from_slice(&crc32fast::hash(data).to_le_bytes());
+    trailer
+}
+
+/// Checks the trailer of a whole chunk, header excluded, and returns the
+/// length of its data without the trailer
+pub fn check(chunk: &[u8]) -> (ChunkChecksum, usize) {
+    let Some(data_len) = chunk.len().checked_sub(TRAILER_SIZE) else {
+        return (ChunkChecksum::Missing, chunk.len());
+    };
+
+    let (data, trailer) = chunk.split_at(data_len);
+    let (magic, stored) = trailer.split_at(TRAILER_MAGIC.len());
+    if magic != TRAILER_MAGIC {
+        return (ChunkChecksum::Missing, chunk.len());
+    }
+
+    let stored = u32::from_le_bytes(stored.try_into().unwrap());
+    let computed = crc32fast::hash(data);
+    let checksum = if stored == computed {
+        ChunkChecksum::Valid
+    } else {
+        ChunkChecksum::Mismatch { stored, computed }
+    };
+
+    (checksum, data_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trailer() {
+        let data = b"some chunk data".to_vec();
+        assert_eq!(check(&data), (ChunkChecksum::Missing, data.len()));
+        assert_eq!(check(b"short"), (ChunkChecksum::Missing, 5));
+
+        let chunk = [&data[..], &trailer(&data)].concat();
+        assert_eq!(chunk.len(), data.len() + TRAILER_SIZE);
+        assert_eq!(check(&chunk), (ChunkChecksum::Valid, data.len()));
+
+        // a trailer of nothing
+        assert_eq!(check(&trailer(&[])), (ChunkChecksum::Valid, 0));
+
+        let mut flipped = chunk.clone();
+        flipped[3] ^= 0x10;
+        assert!(matches!(
+            check(&flipped),
+            (ChunkChecksum::Mismatch { stored, computed }, 15) if stored != computed
+        ));
+    }
+}
@@ -1,6 +1,13 @@
 use crate::chess::{
-    bitboard::Bitboard, castling_rights::CastlingRights, color::Color, coords::Square,
-    hyperbola::HyperbolaQsc, piece::Piece, piecetype::PieceType, position::Position, r#move::Move,
+    bitboard::Bitboard,
+    castling_rights::CastlingRights,
+    color::Color,
+    coords::Square,
+    hyperbola::HyperbolaQsc,
+    piece::Piece,
+    piecetype::PieceType,
+    position::Position,
+    r#move::{Move, MoveType},
 };
 
 use arrayvec::ArrayVec;
@@ -37,6 +44,86 @@ pub fn pseudo_legal_moves(pos: &Position) -> ArrayVec<Move, 256> {
     moves
 }
 
+/// Return every legal move for the current position.
+pub fn legal_moves(pos: &Position) -> ArrayVec<Move, 256> {
+    let side = pos.side_to_move();
+    let mut moves = pseudo_legal_moves(pos);
+    moves.retain(|mv| !pos.after_move(*mv).is_checked(side));
+    moves
+}
+
+/// Whether `mv` is one of the legal moves of the position
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn is_legal(pos: &Position, mv: Move) -> bool {
+    is_pseudo_legal(pos, mv) && !pos.after_move(mv).is_checked(pos.side_to_move())
+}
+
+/// Whether `mv` is one of the moves [`pseudo_legal_moves`] returns, checked
+/// against the board alone without generating them
+pub(crate) fn is_pseudo_legal(pos: &Position, mv: Move) -> bool {
+    let side = pos.side_to_move();
+    let (from, to) = (mv.from(), mv.to());
+    if from.index() >= 64 || to.index() >= 64 {
+        return false;
+    }
+
+    let piece = pos.piece_at(from);
+    if piece == Piece::none() || piece.color() != side {
+        return false;
+    }
+
+    if mv.mtype() == MoveType::Castle {
+        let mut moves = ArrayVec::new();
+        generate_castling_moves(pos, side, &mut moves);
+        return moves.contains(&mv);
+    }
+
+    let target = pos.piece_at(to);
+    if target != Piece::none() && target.color() == side {
+        return false;
+    }
+
+    if piece.piece_type() != PieceType::Pawn {
+        return mv == Move::normal(from, to)
+            && piece_attacks(piece.piece_type(), from, pos.occupied()).sq_set(to);
+    }
+
+    let (direction, start_rank, last_rank) = match side {
+        Color::White => (8, 1, 7),
+        Color::Black => (-8, 6, 0),
+    };
+    let promotes = to.index() / 8 == last_rank;
+    let moved = match mv.mtype() {
+        MoveType::EnPassant => {
+            return mv == Move::en_passant(from, to)
+                && to == pos.ep_square()
+                && pawn(side, from).sq_set(to)
+        }
+        MoveType::Promotion => {
+            promotes
+                && PROMOTION_PIECES
+                    .iter()
+                    .any(|&piece_type| mv.promoted_piece() == Piece::new(piece_type, side))
+        }
+        _ => !promotes && mv.promoted_piece() == Piece::none(),
+    };
+    if !moved {
+        return false;
+    }
+
+    let one_step = from.index() as i32 + direction;
+    if pawn(side, from).sq_set(to) {
+        target != Piece::none()
+    } else if to.index() as i32 == one_step {
+        target == Piece::none()
+    } else {
+        to.index() as i32 == one_step + direction
+            && from.index() / 8 == start_rank
+            && target == Piece::none()
+            && pos.piece_at(Square::new(one_step as u32)) == Piece::none()
+    }
+}
+
 fn generate_pawn_moves(pos: &Position, side: Color, moves: &mut ArrayVec<Move, 256>) {
     let mut pawns = pos.pieces_bb_color(side, PieceType::Pawn).bits();
     let direction = if side == Color::White { 8 } else { -8 };
@@ -425,7 +512,7 @@ mod tests {
 
         let mut nodes = 0;
 
-        let moves = pseudo_legal_moves(&pos);
+        let moves = pseudo_legal_moves(pos);
 
         for mv in moves {
             let new_pos = pos.after_move(mv);
@@ -470,14 +557,14 @@ mod tests {
     #[test]
     fn test_pseudo_moves_startpos() {
         let pos = &Position::from_fen(STARTPOS).unwrap();
-        let moves = pseudo_legal_moves(&pos);
+        let moves = pseudo_legal_moves(pos);
         assert_eq!(moves.len(), 20);
     }
 
     #[test]
     fn test_knight_pseudo_moves() {
         let pos = &Position::from_fen("k7/8/8/3N4/8/8/8/6K1 w - - 0 1").unwrap();
-        let moves = pseudo_legal_moves(&pos);
+        let moves = pseudo_legal_moves(pos);
         let knight_moves = moves
             .iter()
             .filter(|m| pos.piece_at(m.from()).piece_type() == PieceType::Knight)
@@ -488,10 +575,18 @@ mod tests {
     #[test]
     fn test_en_passant_included() {
         let pos = &Position::from_fen("k7/8/8/3pP3/8/8/8/6K1 w - d6 0 1").unwrap();
-        let moves = pseudo_legal_moves(&pos);
+        let moves = pseudo_legal_moves(pos);
         assert!(moves.iter().any(|m| m.mtype() == MoveType::EnPassant));
     }
 
+    #[test]
+    fn test_legal_moves_in_check() {
+        let pos = &Position::from_fen("k7/8/8/8/8/8/8/5q1K w - - 0 1").unwrap();
+        let moves = legal_moves(pos);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].as_uci(), "h1h2");
+    }
+
     #[test]
     fn test_perft_startpos_depth_1() {
         assert_eq!(split_perft(STARTPOS, 1), 20);
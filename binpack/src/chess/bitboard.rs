@@ -1,4 +1,4 @@
-use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+use core::ops::{BitAnd, BitOr, BitOrAssign, Not};
 
 use crate::chess::coords::{File, Rank, Square};
 
@@ -1,4 +1,4 @@
-use std::ops::{BitAndAssign, BitOrAssign, Not};
+use core::ops::{BitAndAssign, BitOrAssign, Not};
 
 use super::color::Color;
 
@@ -51,7 +51,7 @@ impl CastlingRights {
     }
 }
 
-impl std::ops::BitAnd for CastlingRights {
+impl core::ops::BitAnd for CastlingRights {
     type Output = Self;
 
     fn bitand(self, rhs: Self) -> Self {
@@ -1,4 +1,4 @@
-use std::ops::Not;
+use core::ops::Not;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Color {
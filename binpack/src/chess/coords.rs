@@ -1,4 +1,4 @@
-use std::{
+use core::{
     fmt::{self},
     ops::{Add, Sub},
 };
@@ -41,7 +41,7 @@ impl FlatSquareOffset {
     }
 }
 
-impl std::ops::Neg for FlatSquareOffset {
+impl core::ops::Neg for FlatSquareOffset {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
@@ -120,6 +120,14 @@ impl Square {
         Rank::new(self.index >> 3)
     }
 
+    /// The square on the same rank with the file mirrored, a1 <-> h1
+    #[must_use]
+    pub const fn flip_file(self) -> Self {
+        Self {
+            index: self.index ^ 7,
+        }
+    }
+
     #[must_use]
     pub fn offset(self, files: i32, ranks: i32) -> Option<Self> {
         const FILE_CARDINALITY: i32 = 8;
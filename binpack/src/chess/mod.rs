@@ -6,6 +6,9 @@ pub mod castling_rights;
 pub mod color;
 pub mod coords;
 pub mod r#move;
+pub mod pawns;
 pub mod piece;
 pub mod piecetype;
 pub mod position;
+pub mod san;
+pub mod zobrist;
@@ -1,9 +1,13 @@
+use alloc::{format, string::String};
+
 use crate::chess::{
+    attacks,
     castling_rights::CastleType,
     color::Color,
     coords::{File, Rank, Square},
     piece::Piece,
     piecetype::PieceType,
+    position::Position,
 };
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -87,6 +91,23 @@ impl Move {
         self.to
     }
 
+    /// The move mirrored from the a-file to the h-file, as played in the
+    /// position mirrored by [`Position::mirror_files`]. Castling has no
+    /// mirror image, castling moves stay castling moves of the mirrored
+    /// squares.
+    pub const fn mirror_files(&self) -> Self {
+        if self.from.index() >= 64 {
+            return *self;
+        }
+
+        Self {
+            from: self.from.flip_file(),
+            to: self.to.flip_file(),
+            move_type: self.move_type,
+            promoted_piece: self.promoted_piece,
+        }
+    }
+
     pub const fn normal(from: Square, to: Square) -> Self {
         Self {
             from,
@@ -146,6 +167,16 @@ impl Move {
         }
     }
 
+    /// Parse a move in UCI notation, castling may be given as king to
+    /// destination (e1g1) or king captures rook (e1h1).
+    /// Returns None if the move is not legal in the position.
+    pub fn from_uci(pos: &Position, uci: &str) -> Option<Self> {
+        attacks::legal_moves(pos).into_iter().find(|mv| {
+            mv.as_uci() == uci
+                || (mv.mtype() == MoveType::Castle && format!("{}{}", mv.from, mv.to) == uci)
+        })
+    }
+
     pub fn castle_type(&self) -> CastleType {
         if self.to.file() == File::H {
             CastleType::Short
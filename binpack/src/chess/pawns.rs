@@ -0,0 +1,177 @@
+//! Pawn structure, computed for all pawns of a side at once with shifts and
+//! fills of their bitboard.
+//!
+//! ```
+//! use sfbinpack::chess::{color::Color, pawns, position::Position};
+//!
+//! // white: a2 b2 c4 c5 h5, black: b7 c7 f7
+//! let pos = Position::from_fen("4k3/1pp2p2/8/2P4P/2P5/8/PP6/4K3 w - - 0 1").unwrap();
+//!
+//! assert_eq!(pawns::passed(Color::White, &pos).count(), 1); // h5
+//! assert_eq!(pawns::isolated(Color::White, &pos).count(), 1); // h5
+//! assert_eq!(pawns::doubled(Color::White, &pos).count(), 1); // c5
+//! assert_eq!(pawns::isolated(Color::Black, &pos).count(), 1); // f7
+//! ```
+
+use crate::chess::{bitboard::Bitboard, color::Color, piecetype::PieceType, position::Position};
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+const FILE_H: u64 = FILE_A << 7;
+
+fn north(bb: u64) -> u64 {
+    bb << 8
+}
+
+fn south(bb: u64) -> u64 {
+    bb >> 8
+}
+
+fn east(bb: u64) -> u64 {
+    (bb << 1) & !FILE_A
+}
+
+fn west(bb: u64) -> u64 {
+    (bb >> 1) & !FILE_H
+}
+
+fn north_fill(mut bb: u64) -> u64 {
+    bb |= bb << 8;
+    bb |= bb << 16;
+    bb | bb << 32
+}
+
+fn south_fill(mut bb: u64) -> u64 {
+    bb |= bb >> 8;
+    bb |= bb >> 16;
+    bb | bb >> 32
+}
+
+/// One rank towards the other side of `color`
+fn forward(color: Color, bb: u64) -> u64 {
+    match color {
+        Color::White => north(bb),
+        Color::Black => south(bb),
+    }
+}
+
+/// The squares in front of `bb` from the point of view of `color`, not the
+/// squares of `bb` themselves
+fn front_fill(color: Color, bb: u64) -> u64 {
+    match color {
+        Color::White => north_fill(north(bb)),
+        Color::Black => south_fill(south(bb)),
+    }
+}
+
+fn pawn_bits(color: Color, pos: &Position) -> u64 {
+    pos.pieces_bb_color(color, PieceType::Pawn).bits()
+}
+
+/// The squares attacked by the pawns of `color` in `pawns`
+pub fn attacks(color: Color, pawns: Bitboard) -> Bitboard {
+    let front = forward(color, pawns.bits());
+    Bitboard::new(east(front) | west(front))
+}
+
+/// The pawns of `color` without a pawn of the other side in front of them
+/// on their own or an adjacent file
+pub fn passed(color: Color, pos: &Position) -> Bitboard {
+    let front = front_fill(!color, pawn_bits(!color, pos));
+    Bitboard::new(pawn_bits(color, pos) & !(front | east(front) | west(front)))
+}
+
+/// The pawns of `color` without a pawn of theirs on an adjacent file
+pub fn isolated(color: Color, pos: &Position) -> Bitboard {
+    let pawns = pawn_bits(color, pos);
+    let files = north_fill(south_fill(pawns));
+    Bitboard::new(pawns & !(east(files) | west(files)))
+}
+
+/// The pawns of `color` with another pawn of theirs behind them on the same
+/// file. The rearmost pawn of a file is not one, so the count is the number
+/// of pawns beyond one per file.
+pub fn doubled(color: Color, pos: &Position) -> Bitboard {
+    let pawns = pawn_bits(color, pos);
+    Bitboard::new(pawns & front_fill(color, pawns))
+}
+
+/// The pawns of `color` that no pawn of theirs on an adjacent file can
+/// defend on the square in front, while a pawn of the other side attacks
+/// that square, so they cannot advance without being taken
+pub fn backward(color: Color, pos: &Position) -> Bitboard {
+    let pawns = pawn_bits(color, pos);
+    let own_attacks = attacks(color, Bitboard::new(pawns)).bits();
+    let defendable = own_attacks | front_fill(color, own_attacks);
+    let enemy_attacks = attacks(!color, Bitboard::new(pawn_bits(!color, pos))).bits();
+
+    let stops = forward(color, pawns) & enemy_attacks & !defendable;
+    Bitboard::new(pawns & forward(!color, stops))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec::Vec};
+
+    use crate::chess::coords::Square;
+
+    fn squares(bb: Bitboard) -> Vec<alloc::string::String> {
+        bb.iter().map(|sq| sq.to_string()).collect()
+    }
+
+    fn from_fen(fen: &str) -> Position {
+        Position::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn test_passed() {
+        // white: a4 d5 g2, black: b6 e7 h7; a4 and b6 face each other
+        let pos = from_fen("4k3/4p2p/1p6/3P4/P7/8/6P1/4K3 w - - 0 1");
+
+        assert_eq!(squares(passed(Color::White, &pos)), [] as [&str; 0]);
+        assert_eq!(squares(passed(Color::Black, &pos)), [] as [&str; 0]);
+
+        // a pawn beside or behind an enemy pawn on the next file is passed
+        let pos = from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1");
+        assert_eq!(squares(passed(Color::White, &pos)), ["e5"]);
+        assert_eq!(squares(passed(Color::Black, &pos)), ["d5"]);
+    }
+
+    #[test]
+    fn test_isolated_and_doubled() {
+        // white: a2 c2 c3 c4 e4, black: f7 g7 g6
+        let pos = from_fen("4k3/5pp1/6p1/8/2P1P3/2P5/P1P5/4K3 w - - 0 1");
+
+        assert_eq!(
+            squares(isolated(Color::White, &pos)),
+            ["a2", "c2", "c3", "c4", "e4"]
+        );
+        assert_eq!(squares(isolated(Color::Black, &pos)), [] as [&str; 0]);
+        assert_eq!(squares(doubled(Color::White, &pos)), ["c3", "c4"]);
+        // for black the pawn further down the board is the front one
+        assert_eq!(squares(doubled(Color::Black, &pos)), ["g6"]);
+    }
+
+    #[test]
+    fn test_backward() {
+        // d3 cannot be defended from c2 or e2 and its stop square d4 is
+        // attacked by e5, b3 only covers c4 and a4; the same holds for e5
+        // with d3 attacking e4
+        let pos = from_fen("4k3/8/8/4p3/8/1P1P4/8/4K3 w - - 0 1");
+
+        assert_eq!(squares(backward(Color::White, &pos)), ["d3"]);
+        assert_eq!(squares(backward(Color::Black, &pos)), ["e5"]);
+
+        // with a pawn on c2 that can still come up to defend it, it is not
+        let pos = from_fen("4k3/8/8/4p3/8/3P4/2P5/4K3 w - - 0 1");
+        assert_eq!(squares(backward(Color::White, &pos)), [] as [&str; 0]);
+
+        assert_eq!(
+            squares(attacks(
+                Color::Black,
+                Bitboard::from_square(Square::from_string("a7").unwrap())
+            )),
+            ["b6"]
+        );
+    }
+}
@@ -1,3 +1,10 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+
 use crate::chess::{
     attacks,
     bitboard::Bitboard,
@@ -7,34 +14,161 @@ use crate::chess::{
     piece::Piece,
     piecetype::PieceType,
     r#move::{Move, MoveType},
+    zobrist,
 };
 
+/// Byte i of entry b is bit i of b
+const SPREAD: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut bit = 0;
+        while bit < 8 {
+            table[byte] |= ((byte as u64 >> bit) & 1) << (8 * bit);
+            bit += 1;
+        }
+        byte += 1;
+    }
+    table
+};
+
+/// [`Position::game_phase`] of the starting position and of any position
+/// with at least its non-pawn material
+pub const MAX_GAME_PHASE: u32 = 24;
+
+/// Each castling right with the squares its king and rook start on
+const CASTLING_SQUARES: [(CastlingRights, Color, Square, Square); 4] = [
+    (
+        CastlingRights::WHITE_KING_SIDE,
+        Color::White,
+        Square::E1,
+        Square::H1,
+    ),
+    (
+        CastlingRights::WHITE_QUEEN_SIDE,
+        Color::White,
+        Square::E1,
+        Square::A1,
+    ),
+    (
+        CastlingRights::BLACK_KING_SIDE,
+        Color::Black,
+        Square::E8,
+        Square::H8,
+    ),
+    (
+        CastlingRights::BLACK_QUEEN_SIDE,
+        Color::Black,
+        Square::E8,
+        Square::A8,
+    ),
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Position {
     /// Bitboards for each piece type (PNBRQK)
     bb: [u64; 6],
     /// Bitboards for each color (White, Black)
     bb_color: [u64; 2],
-    /// Piece list
-    pieces: [Piece; 64],
     /// Side to move
     stm: Color,
     /// Castling rights
     castling_rights: CastlingRights,
-    /// Halfmove clock for 50-move rule
-    halfm: u8,
+    /// Halfmove clock for 50-move rule, as wide as the packed format stores
+    halfm: u16,
     /// Fullmove number
     fullm: u16,
     /// En passant target square
     enpassant: Square,
 }
 
+/// What changed from one position to another, see [`Position::diff`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PositionDelta {
+    /// Pieces that left a square for another one, from and to. A piece
+    /// that disappears from a square is paired with one of the same kind
+    /// and color that appears on another, so a capture is a moved piece
+    /// and a removed one, a promotion a removed pawn and an added piece.
+    pub moved: Vec<(Piece, Square, Square)>,
+    pub removed: Vec<(Piece, Square)>,
+    pub added: Vec<(Piece, Square)>,
+    /// Each of the state fields, before and after, if it changed
+    pub side_to_move: Option<(Color, Color)>,
+    pub castling_rights: Option<(CastlingRights, CastlingRights)>,
+    pub ep_square: Option<(Square, Square)>,
+    pub rule50_counter: Option<(u16, u16)>,
+    pub ply: Option<(u16, u16)>,
+}
+
+impl PositionDelta {
+    /// Whether the positions are the same
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether only the pieces are the same, the state fields may differ
+    pub fn same_board(&self) -> bool {
+        self.moved.is_empty() && self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+impl fmt::Display for PositionDelta {
+    /// The changes separated by commas, e.g. `Ng1-f3, -pd5, stm w->b`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let piece = |piece: Piece| fen_char(piece).unwrap_or('?');
+        let mut changes = Vec::new();
+
+        for &(pc, from, to) in &self.moved {
+            changes.push(format!("{}{}-{}", piece(pc), from, to));
+        }
+        for &(pc, sq) in &self.removed {
+            changes.push(format!("-{}{}", piece(pc), sq));
+        }
+        for &(pc, sq) in &self.added {
+            changes.push(format!("+{}{}", piece(pc), sq));
+        }
+
+        let color = |color: Color| if color == Color::White { "w" } else { "b" };
+        let square = |sq: Square| match sq {
+            Square::NONE => "-".to_string(),
+            sq => sq.to_string(),
+        };
+        if let Some((from, to)) = self.side_to_move {
+            changes.push(format!("stm {}->{}", color(from), color(to)));
+        }
+        if let Some((from, to)) = self.castling_rights {
+            changes.push(format!(
+                "castling {}->{}",
+                castling_fen(from),
+                castling_fen(to)
+            ));
+        }
+        if let Some((from, to)) = self.ep_square {
+            changes.push(format!("ep {}->{}", square(from), square(to)));
+        }
+        if let Some((from, to)) = self.rule50_counter {
+            changes.push(format!("rule50 {}->{}", from, to));
+        }
+        if let Some((from, to)) = self.ply {
+            changes.push(format!("ply {}->{}", from, to));
+        }
+
+        if changes.is_empty() {
+            f.write_str("no changes")
+        } else {
+            f.write_str(&changes.join(", "))
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PositionError {
     InvalidFEN,
+    /// A counter larger than the packed format stores
+    CounterOutOfRange,
 }
 
-type Result<T> = std::result::Result<T, PositionError>;
+type Result<T> = core::result::Result<T, PositionError>;
 
 impl Default for Position {
     fn default() -> Self {
@@ -54,28 +188,6 @@ impl Position {
                 0x1000_0000_0000_0010,
             ],
             bb_color: [0xffff, 0xffff_0000_0000_0000],
-            pieces: std::array::from_fn(|i| match i {
-                0..=15 => match i {
-                    0 | 7 => Piece::new(PieceType::Rook, Color::White),
-                    1 | 6 => Piece::new(PieceType::Knight, Color::White),
-                    2 | 5 => Piece::new(PieceType::Bishop, Color::White),
-                    3 => Piece::new(PieceType::Queen, Color::White),
-                    4 => Piece::new(PieceType::King, Color::White),
-                    8..=15 => Piece::new(PieceType::Pawn, Color::White),
-                    _ => unreachable!(),
-                },
-                16..=47 => Piece::none(),
-                48..=63 => match i {
-                    48..=55 => Piece::new(PieceType::Pawn, Color::Black),
-                    56 | 63 => Piece::new(PieceType::Rook, Color::Black),
-                    57 | 62 => Piece::new(PieceType::Knight, Color::Black),
-                    58 | 61 => Piece::new(PieceType::Bishop, Color::Black),
-                    59 => Piece::new(PieceType::Queen, Color::Black),
-                    60 => Piece::new(PieceType::King, Color::Black),
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            }),
             stm: Color::White,
             castling_rights: CastlingRights::ALL,
             halfm: 0,
@@ -88,7 +200,6 @@ impl Position {
         Self {
             bb: [0; 6],
             bb_color: [0; 2],
-            pieces: [Piece::none(); 64],
             stm: Color::White,
             castling_rights: CastlingRights::NONE,
             halfm: 0,
@@ -97,6 +208,64 @@ impl Position {
         }
     }
 
+    /// Builds a position from its bitboards, every occupied square must be
+    /// in exactly one piece type bitboard
+    #[cfg_attr(not(feature = "unsafe-opt"), allow(dead_code))]
+    pub(crate) fn from_parts(
+        bb: [u64; 6],
+        bb_color: [u64; 2],
+        stm: Color,
+        castling_rights: CastlingRights,
+        enpassant: Square,
+    ) -> Self {
+        let pos = Self {
+            bb,
+            bb_color,
+            stm,
+            castling_rights,
+            halfm: 0,
+            fullm: 1,
+            enpassant,
+        };
+
+        debug_assert!(bb.iter().fold(0, |all, bb| all | bb) == bb_color[0] | bb_color[1]);
+        debug_assert!(bb.iter().map(|bb| bb.count_ones()).sum::<u32>() == pos.occupied().count());
+
+        pos
+    }
+
+    /// Returns the piece id on every square, indexed by square
+    pub(crate) fn board(&self) -> [u8; 64] {
+        let [p0, p1, p2, p3] = self.id_planes();
+        let spread = |plane: u64, rank: usize| SPREAD[(plane >> (8 * rank)) as u8 as usize];
+
+        let mut board = [0u8; 64];
+        for rank in 0..8 {
+            let ids = spread(p0, rank)
+                | spread(p1, rank) << 1
+                | spread(p2, rank) << 2
+                | spread(p3, rank) << 3;
+            board[8 * rank..8 * rank + 8].copy_from_slice(&ids.to_le_bytes());
+        }
+
+        board
+    }
+
+    /// Bit i of plane j is bit j of the piece id on square i, `Piece::none()`
+    /// on the empty squares
+    #[inline(always)]
+    pub(crate) fn id_planes(&self) -> [u64; 4] {
+        // the id is the piece type times two plus the color, the empty
+        // squares have type 6 (0b110) and color 0
+        let empty = !(self.bb_color[0] | self.bb_color[1]);
+        [
+            self.bb_color[1],
+            self.bb[1] | self.bb[3] | self.bb[5],
+            self.bb[2] | self.bb[3] | empty,
+            self.bb[4] | self.bb[5] | empty,
+        ]
+    }
+
     /// Returns the current side to move's color
     pub fn side_to_move(&self) -> Color {
         self.stm
@@ -127,11 +296,32 @@ impl Position {
         Bitboard::new(self.bb_color[color as usize] & self.bb[pt.ordinal() as usize])
     }
 
+    /// Non-pawn material as in a tapered eval, knights and bishops count 1,
+    /// rooks 2 and queens 4: [`MAX_GAME_PHASE`] at the start, or with more
+    /// material after promotions, down to 0 with only kings and pawns left
+    pub fn game_phase(&self) -> u32 {
+        let count = |pt| self.pieces_bb_type(pt).count();
+        let phase = count(PieceType::Knight)
+            + count(PieceType::Bishop)
+            + 2 * count(PieceType::Rook)
+            + 4 * count(PieceType::Queen);
+
+        phase.min(MAX_GAME_PHASE)
+    }
+
     /// Returns the piece at a given square, Piece::NONE if the square is empty
     pub fn piece_at(&self, square: Square) -> Piece {
         debug_assert!(square != Square::NONE);
 
-        self.pieces[square.index() as usize]
+        // the pieces are only kept in the bitboards
+        let idx = square.index();
+        let id = self
+            .id_planes()
+            .iter()
+            .enumerate()
+            .fold(0, |id, (bit, plane)| id | (plane >> idx & 1) << bit);
+
+        Piece::from_id(id as i32)
     }
 
     /// Returns the castling rights
@@ -191,43 +381,12 @@ impl Position {
         } else if mv.mtype() == MoveType::Normal {
             self.place_piece(self.stm, piece, to);
         } else if mv.mtype() == MoveType::Castle {
-            if mv.castle_type() == CastleType::Short {
-                let rook_to = if self.stm == Color::White {
-                    Square::F1
-                } else {
-                    Square::F8
-                };
+            let (king_to, rook_to) = castled_squares(self.stm, mv.castle_type());
+            let rook = self.piece_at(to);
 
-                let king_to = if self.stm == Color::White {
-                    Square::G1
-                } else {
-                    Square::G8
-                };
-
-                let rook = self.piece_at(to);
-
-                self.remove_piecetype(self.stm, PieceType::Rook, to);
-                self.place_piece(self.stm, rook, rook_to);
-                self.place_piece(self.stm, piece, king_to);
-            } else {
-                let rook_to = if self.stm == Color::White {
-                    Square::D1
-                } else {
-                    Square::D8
-                };
-
-                let king_to = if self.stm == Color::White {
-                    Square::C1
-                } else {
-                    Square::C8
-                };
-
-                let rook = self.piece_at(to);
-
-                self.remove_piecetype(self.stm, PieceType::Rook, to);
-                self.place_piece(self.stm, rook, rook_to);
-                self.place_piece(self.stm, piece, king_to);
-            }
+            self.remove_piecetype(self.stm, PieceType::Rook, to);
+            self.place_piece(self.stm, rook, rook_to);
+            self.place_piece(self.stm, piece, king_to);
         }
 
         // update state
@@ -236,7 +395,7 @@ impl Position {
         if pt == PieceType::Pawn {
             self.halfm = 0;
         } else {
-            self.halfm += 1;
+            self.halfm = self.halfm.saturating_add(1);
         }
 
         // Update fullmove number
@@ -328,11 +487,20 @@ impl Position {
     }
 
     pub fn set_rule50_counter(&mut self, counter: u16) {
-        self.halfm = counter as u8;
+        self.halfm = counter;
+    }
+
+    /// Like set_rule50_counter(), for a counter from a wider integer; fails
+    /// instead of truncating one that does not fit 16 bits
+    pub fn try_set_rule50_counter(&mut self, counter: u32) -> Result<()> {
+        self.halfm = counter
+            .try_into()
+            .map_err(|_| PositionError::CounterOutOfRange)?;
+        Ok(())
     }
 
     pub fn rule50_counter(&self) -> u16 {
-        self.halfm as u16
+        self.halfm
     }
 
     /// Places a piece on the board
@@ -354,7 +522,6 @@ impl Position {
         let mask = 1u64 << (sq.index());
         self.bb_color[side as usize] |= mask;
         self.bb[pc.piece_type().ordinal() as usize] |= mask;
-        self.pieces[sq.index() as usize] = pc;
     }
 
     /// Removes a piece from the board
@@ -367,7 +534,6 @@ impl Position {
         let mask = 1u64 << (sq.index());
         self.bb_color[side as usize] ^= mask;
         self.bb[pc.piece_type().ordinal() as usize] ^= mask;
-        self.pieces[sq.index() as usize] = Piece::none();
     }
 
     #[inline(always)]
@@ -378,7 +544,6 @@ impl Position {
         let mask = 1u64 << (sq.index());
         self.bb_color[side as usize] ^= mask;
         self.bb[pt.ordinal() as usize] ^= mask;
-        self.pieces[sq.index() as usize] = Piece::none();
     }
 
     /// Returns the FEN representation of the position
@@ -401,24 +566,7 @@ impl Position {
                         empty_squares = 0;
                     }
 
-                    let mut c = match piece.piece_type() {
-                        PieceType::Pawn => 'p',
-                        PieceType::Knight => 'n',
-                        PieceType::Bishop => 'b',
-                        PieceType::Rook => 'r',
-                        PieceType::Queen => 'q',
-                        PieceType::King => 'k',
-                        _ => '?',
-                    };
-
-                    if c == '?' {
-                        return Err(PositionError::InvalidFEN);
-                    }
-
-                    if piece.color() == Color::White {
-                        c = c.to_ascii_uppercase();
-                    }
-                    fen.push(c);
+                    fen.push(fen_char(piece).ok_or(PositionError::InvalidFEN)?);
                 }
             }
             if empty_squares > 0 {
@@ -435,23 +583,7 @@ impl Position {
 
         // castling
         fen.push(' ');
-        let castling = self.castling_rights();
-        if castling == CastlingRights::NONE {
-            fen.push('-');
-        } else {
-            if castling.contains(CastlingRights::WHITE_KING_SIDE) {
-                fen.push('K');
-            }
-            if castling.contains(CastlingRights::WHITE_QUEEN_SIDE) {
-                fen.push('Q');
-            }
-            if castling.contains(CastlingRights::BLACK_KING_SIDE) {
-                fen.push('k');
-            }
-            if castling.contains(CastlingRights::BLACK_QUEEN_SIDE) {
-                fen.push('q');
-            }
-        }
+        fen.push_str(&castling_fen(self.castling_rights()));
 
         // ep square
         fen.push(' ');
@@ -544,8 +676,14 @@ impl Position {
             self.enpassant = Square::from_string(ep).unwrap();
         }
 
-        self.halfm = parts.next().unwrap().parse().unwrap();
-        self.fullm = parts.next().unwrap().parse().unwrap();
+        let mut counter = || {
+            parts
+                .next()
+                .and_then(|part| part.parse().ok())
+                .ok_or(PositionError::InvalidFEN)
+        };
+        self.halfm = counter()?;
+        self.fullm = counter()?;
 
         Ok(())
     }
@@ -577,6 +715,120 @@ impl Position {
         self.is_attacked(self.king_sq(c), !c)
     }
 
+    /// Whether `mv` is a pseudo-legal move of the side to move: its piece
+    /// can make it on this board, but it may leave the own king in check.
+    /// Cheaper than looking for it among the generated moves, e.g. to
+    /// validate stored moves.
+    pub fn is_pseudo_legal(&self, mv: Move) -> bool {
+        attacks::is_pseudo_legal(self, mv)
+    }
+
+    /// Whether the pseudo-legal move `mv` checks the other king, directly
+    /// or by uncovering a slider, without making the move
+    pub fn gives_check(&self, mv: Move) -> bool {
+        let us = self.stm;
+        let king = self.king_sq(!us);
+        let (from, to) = (mv.from(), mv.to());
+
+        // the squares of our pieces that leave, the piece that checks
+        // directly and where it lands
+        let mut moved = Bitboard::from_square(from);
+        let mut occupied = self.occupied() & !moved;
+        let (piece_type, sq) = match mv.mtype() {
+            MoveType::Normal => (self.piece_at(from).piece_type(), to),
+            MoveType::Promotion => (mv.promoted_piece().piece_type(), to),
+            MoveType::EnPassant => {
+                occupied = occupied & !Bitboard::from_square(Square::new(to.index() ^ 8));
+                (PieceType::Pawn, to)
+            }
+            MoveType::Castle => {
+                let (king_to, rook_to) = castled_squares(us, mv.castle_type());
+                moved |= Bitboard::from_square(to);
+                occupied = occupied & !moved | Bitboard::from_square(king_to);
+                (PieceType::Rook, rook_to)
+            }
+        };
+        occupied |= Bitboard::from_square(sq);
+
+        let direct = match piece_type {
+            PieceType::Pawn => attacks::pawn(us, sq),
+            piece_type => attacks::piece_attacks(piece_type, sq, occupied),
+        };
+        if direct.sq_set(king) {
+            return true;
+        }
+
+        let pieces = |piece_type| self.pieces_bb_color(us, piece_type) & !moved;
+        let queens = pieces(PieceType::Queen);
+        (attacks::bishop(king, occupied) & (pieces(PieceType::Bishop) | queens)
+            | attacks::rook(king, occupied) & (pieces(PieceType::Rook) | queens))
+            .bits()
+            != 0
+    }
+
+    /// Checks what moves are made on: one king per side, no pawns on the
+    /// first or last rank, the side not to move not in check, and castling
+    /// rights and the en passant square that match the board. Decoded data
+    /// is checked with it before it is trusted.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn check_valid(&self) -> core::result::Result<(), &'static str> {
+        let white = self.bb_color[Color::White as usize];
+        let black = self.bb_color[Color::Black as usize];
+        let typed = self.bb.iter().fold(0, |all, bb| all | bb);
+        let typed_count: u32 = self.bb.iter().map(|bb| bb.count_ones()).sum();
+
+        if white & black != 0 || typed != white | black || typed_count != typed.count_ones() {
+            return Err("a square holds more than one piece");
+        }
+
+        for color in [Color::White, Color::Black] {
+            if self.pieces_bb_color(color, PieceType::King).count() != 1 {
+                return Err("a side does not have exactly one king");
+            }
+        }
+
+        const BACK_RANKS: u64 = 0xFF00_0000_0000_00FF;
+        if self.bb[PieceType::Pawn.ordinal() as usize] & BACK_RANKS != 0 {
+            return Err("a pawn stands on the first or last rank");
+        }
+
+        if self.is_checked(!self.stm) {
+            return Err("the side not to move is in check");
+        }
+
+        for (right, color, king, rook) in CASTLING_SQUARES {
+            if self.castling_rights.contains(right)
+                && (self.piece_at(king) != Piece::new(PieceType::King, color)
+                    || self.piece_at(rook) != Piece::new(PieceType::Rook, color))
+            {
+                return Err("castling rights without king and rook on their squares");
+            }
+        }
+
+        if self.enpassant != Square::NONE {
+            // the square a pawn of the side not to move just skipped, with
+            // that pawn in front of it
+            let (rank, pawn, origin) = match self.stm {
+                Color::White => (5, -8, 8),
+                Color::Black => (2, 8, -8),
+            };
+            let ep = self.enpassant.index() as i32;
+            let pawn_sq = Square::new((ep + pawn).clamp(0, 63) as u32);
+            let origin_sq = Square::new((ep + origin).clamp(0, 63) as u32);
+
+            if ep >= 64
+                || ep / 8 != rank
+                || self.occupied().sq_set(self.enpassant)
+                || self.occupied().sq_set(origin_sq)
+                || self.piece_at(pawn_sq) != Piece::new(PieceType::Pawn, !self.stm)
+            {
+                return Err("en passant square without a pawn that just moved past it");
+            }
+        }
+
+        Ok(())
+    }
+
     fn update_castling_rights_color(&mut self, color: Color, from: Square, to: Square) {
         if color == Color::White {
             if from == Square::E1 || to == Square::E1 {
@@ -601,11 +853,196 @@ impl Position {
         }
     }
 
+    /// The position mirrored from the a-file to the h-file, or `None` if
+    /// either side can still castle, which has no mirror image
+    pub fn mirror_files(&self) -> Option<Self> {
+        if self.castling_rights != CastlingRights::NONE {
+            return None;
+        }
+
+        // reversing the bits mirrors ranks and files, swapping the bytes
+        // mirrors the ranks back
+        let mirror = |bb: u64| bb.reverse_bits().swap_bytes();
+
+        Some(Self {
+            bb: self.bb.map(mirror),
+            bb_color: self.bb_color.map(mirror),
+            enpassant: match self.enpassant {
+                Square::NONE => Square::NONE,
+                sq => sq.flip_file(),
+            },
+            ..*self
+        })
+    }
+
     pub fn after_move(&self, mv: Move) -> Self {
         let mut pos = *self;
         pos.do_move(mv);
         pos
     }
+
+    /// Zobrist key of the pieces, side to move, castling rights and en
+    /// passant square. The move counters are not part of the key, so
+    /// transpositions hash to the same key.
+    pub fn zobrist_key(&self) -> u64 {
+        let mut key = 0;
+
+        for sq in self.occupied().iter() {
+            key ^= zobrist::piece(self.piece_at(sq), sq);
+        }
+
+        key ^= zobrist::castling(self.castling_rights);
+
+        if self.enpassant != Square::NONE {
+            key ^= zobrist::enpassant(self.enpassant);
+        }
+
+        if self.stm == Color::Black {
+            key ^= zobrist::black_to_move();
+        }
+
+        key
+    }
+
+    /// The position without the details that do not change how it can be
+    /// played on: the move counters are reset, the en passant square is
+    /// dropped unless a legal en passant capture exists and castling rights
+    /// are dropped whose king or rook is not on its square. A position read
+    /// from a FEN and the same position reached by moves have the same
+    /// canonical form.
+    pub fn canonical(&self) -> Self {
+        let mut pos = *self;
+        pos.halfm = 0;
+        pos.fullm = 1;
+
+        for (right, color, king, rook) in CASTLING_SQUARES {
+            if pos.piece_at(king) != Piece::new(PieceType::King, color)
+                || pos.piece_at(rook) != Piece::new(PieceType::Rook, color)
+            {
+                pos.castling_rights &= !right;
+            }
+        }
+
+        if pos.enpassant != Square::NONE
+            && !attacks::legal_moves(&pos)
+                .iter()
+                .any(|mv| mv.mtype() == MoveType::EnPassant)
+        {
+            pos.enpassant = Square::NONE;
+        }
+
+        pos
+    }
+
+    /// What changed from this position to `other`: the pieces that moved,
+    /// were removed or added, and the state fields that differ. Between
+    /// the positions of consecutive entries it is what the move did, e.g.
+    /// to update features incrementally, and anything else points at where
+    /// a broken continuation went wrong.
+    /// # Examples
+    ///
+    /// ```
+    /// use sfbinpack::chess::{
+    ///     coords::Square, piece::Piece, position::Position, r#move::Move,
+    /// };
+    ///
+    /// let pos = Position::new();
+    /// let next = pos.after_move(Move::from_uci(&pos, "g1f3").unwrap());
+    /// let delta = pos.diff(&next);
+    /// let f3 = Square::from_string("f3").unwrap();
+    /// assert_eq!(delta.moved, [(Piece::WHITE_KNIGHT, Square::G1, f3)]);
+    /// assert!(delta.removed.is_empty() && delta.added.is_empty());
+    /// assert_eq!(delta.to_string(), "Ng1-f3, stm w->b, rule50 0->1, ply 0->1");
+    /// ```
+    pub fn diff(&self, other: &Position) -> PositionDelta {
+        let mut delta = PositionDelta::default();
+
+        for color in [Color::White, Color::Black] {
+            for pt in 0..6 {
+                let piece_type = PieceType::from_ordinal(pt);
+                let before = self.pieces_bb_color(color, piece_type);
+                let after = other.pieces_bb_color(color, piece_type);
+                let piece = Piece::new(piece_type, color);
+
+                let mut removed = (before & !after).iter();
+                let mut added = (after & !before).iter();
+                loop {
+                    match (removed.next(), added.next()) {
+                        (Some(from), Some(to)) => delta.moved.push((piece, from, to)),
+                        (Some(from), None) => delta.removed.push((piece, from)),
+                        (None, Some(to)) => delta.added.push((piece, to)),
+                        (None, None) => break,
+                    }
+                }
+            }
+        }
+
+        delta.side_to_move = changed(self.stm, other.stm);
+        delta.castling_rights = changed(self.castling_rights, other.castling_rights);
+        delta.ep_square = changed(self.enpassant, other.enpassant);
+        delta.rule50_counter = changed(self.halfm, other.halfm);
+        delta.ply = changed(self.ply(), other.ply());
+        delta
+    }
+
+    /// Zobrist key of the [`canonical`](Self::canonical) position, for
+    /// dedup and position sets. Unlike [`zobrist_key`](Self::zobrist_key)
+    /// it does not tell apart positions that only differ in an en passant
+    /// square or castling rights that can never be used.
+    pub fn canonical_key(&self) -> u64 {
+        self.canonical().zobrist_key()
+    }
+}
+
+/// The squares king and rook of `color` land on when castling
+fn castled_squares(color: Color, castle_type: CastleType) -> (Square, Square) {
+    match (color, castle_type) {
+        (Color::White, CastleType::Short) => (Square::G1, Square::F1),
+        (Color::White, CastleType::Long) => (Square::C1, Square::D1),
+        (Color::Black, CastleType::Short) => (Square::G8, Square::F8),
+        (Color::Black, CastleType::Long) => (Square::C8, Square::D8),
+    }
+}
+
+fn changed<T: PartialEq>(before: T, after: T) -> Option<(T, T)> {
+    (before != after).then_some((before, after))
+}
+
+/// The letter of a piece in a FEN, uppercase for white
+fn fen_char(piece: Piece) -> Option<char> {
+    let c = match piece.piece_type() {
+        PieceType::Pawn => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+        _ => return None,
+    };
+
+    Some(if piece.color() == Color::White {
+        c.to_ascii_uppercase()
+    } else {
+        c
+    })
+}
+
+/// The castling field of a FEN, `-` for none
+fn castling_fen(rights: CastlingRights) -> String {
+    if rights == CastlingRights::NONE {
+        return "-".to_string();
+    }
+
+    [
+        (CastlingRights::WHITE_KING_SIDE, 'K'),
+        (CastlingRights::WHITE_QUEEN_SIDE, 'Q'),
+        (CastlingRights::BLACK_KING_SIDE, 'k'),
+        (CastlingRights::BLACK_QUEEN_SIDE, 'q'),
+    ]
+    .into_iter()
+    .filter(|&(right, _)| rights.contains(right))
+    .map(|(_, c)| c)
+    .collect()
 }
 
 #[cfg(test)]
@@ -620,6 +1057,30 @@ mod tests {
         assert_eq!(pos.fen().unwrap(), STARTPOS);
     }
 
+    #[test]
+    fn test_board_and_piece_at() {
+        let pos = Position::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        let board = pos.board();
+
+        assert_eq!(pos.piece_at(Square::A1), Piece::WHITE_ROOK);
+        assert_eq!(pos.piece_at(Square::E8), Piece::BLACK_KING);
+        assert_eq!(pos.piece_at(Square::new(20)), Piece::none());
+
+        for idx in 0..64 {
+            assert_eq!(board[idx as usize], pos.piece_at(Square::new(idx)).id());
+        }
+    }
+
+    #[test]
+    fn test_size() {
+        // the pieces are only kept in the bitboards, so a copy of a position
+        // is a little more than its eight bitboards
+        assert!(core::mem::size_of::<Position>() <= 80);
+    }
+
     #[test]
     fn test_new() {
         let pos = Position::new();
@@ -631,4 +1092,292 @@ mod tests {
         let pos = Position::new();
         assert_eq!(pos, Position::from_fen(STARTPOS).unwrap());
     }
+
+    #[test]
+    fn test_mirror_files() {
+        assert_eq!(Position::new().mirror_files(), None);
+
+        let pos = Position::from_fen("4k3/8/8/3pP3/8/8/1Q6/4K1N1 w - d6 3 40").unwrap();
+        let mirrored = pos.mirror_files().unwrap();
+        assert_eq!(
+            mirrored.fen().unwrap(),
+            "3k4/8/8/3Pp3/8/8/6Q1/1N1K4 w - e6 3 40"
+        );
+        assert_eq!(mirrored.mirror_files(), Some(pos));
+
+        let mv = Move::from_uci(&pos, "e5d6").unwrap();
+        let mirrored_mv = mv.mirror_files();
+        assert_eq!(mirrored_mv.as_uci(), "d5e6");
+        assert_eq!(mirrored_mv.mtype(), MoveType::EnPassant);
+        assert_eq!(
+            pos.after_move(mv).mirror_files(),
+            Some(mirrored.after_move(mirrored_mv))
+        );
+    }
+
+    #[test]
+    fn test_zobrist_key() {
+        let startpos = Position::new();
+
+        // the same position after 1. Nf3 Nf6 2. Ng1 Ng8, only the counters differ
+        let mut pos = startpos;
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            pos.do_move(Move::from_uci(&pos, uci).unwrap());
+        }
+        assert_ne!(pos, startpos);
+        assert_eq!(pos.zobrist_key(), startpos.zobrist_key());
+
+        let black = Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1");
+        assert_ne!(black.unwrap().zobrist_key(), startpos.zobrist_key());
+
+        let no_castling =
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1");
+        assert_ne!(no_castling.unwrap().zobrist_key(), startpos.zobrist_key());
+
+        let ep = "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3";
+        let no_ep = "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3";
+        assert_ne!(
+            Position::from_fen(ep).unwrap().zobrist_key(),
+            Position::from_fen(no_ep).unwrap().zobrist_key()
+        );
+    }
+
+    #[test]
+    fn test_canonical_key() {
+        // 1. e4 sets no en passant square, black has no pawn to capture with
+        let startpos = Position::new();
+        let e4 = startpos.after_move(Move::from_uci(&startpos, "e2e4").unwrap());
+        let fen = Position::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 5 9")
+            .unwrap();
+        assert_ne!(e4.zobrist_key(), fen.zobrist_key());
+        assert_eq!(e4.canonical_key(), fen.canonical_key());
+        assert_eq!(
+            fen.canonical().fen().unwrap(),
+            e4.canonical().fen().unwrap()
+        );
+        assert_eq!(
+            e4.canonical().fen().unwrap(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+        );
+
+        // a capture that is possible keeps its square
+        let ep = "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3";
+        let no_ep = "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3";
+        assert_ne!(
+            Position::from_fen(ep).unwrap().canonical_key(),
+            Position::from_fen(no_ep).unwrap().canonical_key()
+        );
+
+        // the pawn that could capture is pinned
+        let pinned = Position::from_fen("4k3/8/8/1K1pP2r/8/8/8/8 w - d6 0 1").unwrap();
+        let unpinned = Position::from_fen("4k3/8/8/1K1pP3/8/8/8/8 w - d6 0 1").unwrap();
+        assert_eq!(pinned.canonical().ep_square(), Square::NONE);
+        assert_ne!(unpinned.canonical().ep_square(), Square::NONE);
+
+        // castling rights without the rook to castle with
+        let rights = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w KQ - 0 1").unwrap();
+        let none = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(rights.canonical_key(), none.canonical_key());
+        let rook = Position::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert!(rook
+            .canonical()
+            .castling_rights()
+            .contains(CastlingRights::WHITE_KING_SIDE));
+    }
+
+    #[test]
+    fn test_game_phase() {
+        assert_eq!(Position::new().game_phase(), MAX_GAME_PHASE);
+
+        let pos = Position::from_fen("4k3/pp3ppp/8/8/8/8/PP3PPP/2RQK2R w - - 0 1").unwrap();
+        assert_eq!(pos.game_phase(), 8);
+        let pos = Position::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        assert_eq!(pos.game_phase(), 0);
+
+        // four queens a side after promotions are no more than the start
+        let pos = Position::from_fen("QQQQk3/8/8/8/8/8/8/qqqqK3 w - - 0 1").unwrap();
+        assert_eq!(pos.game_phase(), MAX_GAME_PHASE);
+    }
+
+    #[test]
+    fn test_rule50_counter() {
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 300 200";
+        let mut pos = Position::from_fen(fen).unwrap();
+        assert_eq!(pos.rule50_counter(), 300);
+        assert_eq!(pos.fen().unwrap(), fen);
+
+        pos.do_move(Move::from_uci(&pos, "h1h2").unwrap());
+        assert_eq!(pos.rule50_counter(), 301);
+
+        pos.set_rule50_counter(u16::MAX);
+        pos.do_move(Move::from_uci(&pos, "e8d8").unwrap());
+        assert_eq!(pos.rule50_counter(), u16::MAX);
+
+        assert_eq!(
+            pos.try_set_rule50_counter(70000),
+            Err(PositionError::CounterOutOfRange)
+        );
+        assert_eq!(pos.rule50_counter(), u16::MAX);
+        assert_eq!(pos.try_set_rule50_counter(256), Ok(()));
+        assert_eq!(pos.rule50_counter(), 256);
+
+        assert_eq!(
+            Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 70000 1"),
+            Err(PositionError::InvalidFEN)
+        );
+        assert_eq!(
+            Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - -"),
+            Err(PositionError::InvalidFEN)
+        );
+    }
+
+    #[test]
+    fn test_check_valid() {
+        let valid = [
+            STARTPOS,
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3",
+            "4k3/8/8/8/8/8/8/4K3 b - - 255 300",
+        ];
+        for fen in valid {
+            assert_eq!(
+                Position::from_fen(fen).unwrap().check_valid(),
+                Ok(()),
+                "{}",
+                fen
+            );
+        }
+
+        let invalid = [
+            "4k3/8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/P3K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/r3K3 b - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+            "4k3/8/8/3p4/8/8/8/4K3 w - e6 0 1",
+            "4k3/4R3/8/8/8/8/8/4K3 w - - 0 1",
+        ];
+        for fen in invalid {
+            assert!(
+                Position::from_fen(fen).unwrap().check_valid().is_err(),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_is_pseudo_legal() {
+        use crate::testing::{random_game, GameOptions, Rng};
+
+        let mut rng = Rng::new(11);
+        for _ in 0..10 {
+            for entry in random_game(&mut rng, &GameOptions::default())
+                .iter()
+                .step_by(9)
+            {
+                let pos = entry.pos;
+                let side = pos.side_to_move();
+                let moves = attacks::pseudo_legal_moves(&pos);
+
+                for from in pos.pieces_bb(side).iter() {
+                    for to in (0..64).map(Square::new) {
+                        let promotions = [PieceType::Queen, PieceType::Knight].into_iter();
+                        let promotions = promotions
+                            .flat_map(|pt| [Piece::new(pt, side), Piece::new(pt, !side)])
+                            .map(|piece| Move::promotion(from, to, piece));
+                        let candidates = [
+                            Move::normal(from, to),
+                            Move::en_passant(from, to),
+                            Move::castle(from, to),
+                        ];
+
+                        for mv in candidates.into_iter().chain(promotions) {
+                            assert_eq!(
+                                pos.is_pseudo_legal(mv),
+                                moves.contains(&mv),
+                                "{} {:?}",
+                                pos.fen().unwrap(),
+                                mv
+                            );
+                        }
+                    }
+                }
+
+                for &mv in &moves {
+                    assert_eq!(
+                        pos.gives_check(mv),
+                        pos.after_move(mv).is_checked(!side),
+                        "{} {}",
+                        pos.fen().unwrap(),
+                        mv.as_uci()
+                    );
+                }
+            }
+        }
+
+        // not the side to move, no piece, off the board
+        let pos = Position::new();
+        assert!(!pos.is_pseudo_legal(Move::normal(Square::new(52), Square::new(36))));
+        assert!(!pos.is_pseudo_legal(Move::normal(Square::new(20), Square::new(28))));
+        assert!(!pos.is_pseudo_legal(Move::null()));
+    }
+
+    #[test]
+    fn test_gives_check() {
+        let gives_check = |fen: &str, uci: &str| {
+            let pos = Position::from_fen(fen).unwrap();
+            pos.gives_check(Move::from_uci(&pos, uci).unwrap())
+        };
+
+        // discovered by a knight, by castling, by en passant, by promotion
+        assert!(gives_check("4k3/8/8/8/4N3/8/8/4R1K1 w - - 0 1", "e4c5"));
+        assert!(!gives_check("4k3/8/8/8/4N3/8/8/R5K1 w - - 0 1", "e4c5"));
+        assert!(gives_check("5k2/8/8/8/8/8/8/4K2R w K - 0 1", "e1g1"));
+        assert!(gives_check("8/8/8/k2pP2R/8/8/8/4K3 w - d6 0 1", "e5d6"));
+        assert!(gives_check("k7/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q"));
+        assert!(!gives_check("k7/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8n"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff = |fen: &str, uci: &str| {
+            let pos = Position::from_fen(fen).unwrap();
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            let delta = pos.diff(&pos.after_move(mv));
+            delta.to_string()
+        };
+
+        // capture, castling, promotion with capture, en passant
+        assert_eq!(
+            diff("r3k2r/8/8/3p4/4P3/8/8/R3K2R w KQkq - 3 10", "e4d5"),
+            "Pe4-d5, -pd5, stm w->b, rule50 3->0, ply 18->19"
+        );
+        assert_eq!(
+            diff("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 3 10", "e1g1"),
+            "Rh1-f1, Ke1-g1, stm w->b, castling KQkq->kq, rule50 3->4, ply 18->19"
+        );
+        assert_eq!(
+            diff("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 40", "a7b8q"),
+            "-Pa7, -nb8, +Qb8, stm w->b, ply 78->79"
+        );
+        assert_eq!(
+            diff("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6"),
+            "Pe5-d6, -pd5, stm w->b, ep d6->-, ply 2->3"
+        );
+
+        let pos = Position::new();
+        assert!(pos.diff(&pos).is_empty());
+        assert_eq!(pos.diff(&pos).to_string(), "no changes");
+
+        // a lost castling right alone
+        let mut other = pos;
+        other.set_castling_rights(CastlingRights::WHITE);
+        let delta = pos.diff(&other);
+        assert!(delta.same_board());
+        assert_eq!(
+            delta.castling_rights,
+            Some((CastlingRights::ALL, CastlingRights::WHITE))
+        );
+    }
 }
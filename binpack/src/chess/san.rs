@@ -0,0 +1,263 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::chess::{
+    attacks,
+    castling_rights::CastleType,
+    coords::Square,
+    piece::Piece,
+    piecetype::PieceType,
+    position::Position,
+    r#move::{Move, MoveType},
+};
+
+/// Format a legal move in Standard Algebraic Notation, including the
+/// check (+) and mate (#) suffix.
+pub fn to_san(pos: &Position, mv: Move) -> String {
+    let mut san = san_without_suffix(pos, mv);
+
+    let after = pos.after_move(mv);
+    if after.is_checked(after.side_to_move()) {
+        if attacks::legal_moves(&after).is_empty() {
+            san.push('#');
+        } else {
+            san.push('+');
+        }
+    }
+
+    san
+}
+
+/// Parse a move in Standard Algebraic Notation.
+///
+/// Check/mate markers and annotations (`!`, `?`) are ignored, the capture
+/// marker and the `=` before a promotion piece are optional. Returns None if
+/// the text does not describe exactly one legal move.
+pub fn parse_san(pos: &Position, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal = attacks::legal_moves(pos);
+
+    let castle = match san {
+        "O-O" | "0-0" => Some(CastleType::Short),
+        "O-O-O" | "0-0-0" => Some(CastleType::Long),
+        _ => None,
+    };
+
+    if let Some(castle) = castle {
+        return legal
+            .into_iter()
+            .find(|mv| mv.mtype() == MoveType::Castle && mv.castle_type() == castle);
+    }
+
+    let mut chars = san
+        .chars()
+        .filter(|c| *c != 'x' && *c != '=')
+        .collect::<Vec<_>>();
+
+    let piece_type = match chars.first()? {
+        'N' => PieceType::Knight,
+        'B' => PieceType::Bishop,
+        'R' => PieceType::Rook,
+        'Q' => PieceType::Queen,
+        'K' => PieceType::King,
+        _ => PieceType::Pawn,
+    };
+    if piece_type != PieceType::Pawn {
+        chars.remove(0);
+    }
+
+    let promotion = match chars.last()? {
+        'N' | 'n' => Some(PieceType::Knight),
+        'B' | 'b' if piece_type == PieceType::Pawn && chars.len() > 2 => Some(PieceType::Bishop),
+        'R' | 'r' => Some(PieceType::Rook),
+        'Q' | 'q' => Some(PieceType::Queen),
+        _ => None,
+    };
+    if promotion.is_some() {
+        chars.pop();
+    }
+
+    if chars.len() < 2 {
+        return None;
+    }
+
+    let to_text = chars
+        .split_off(chars.len() - 2)
+        .into_iter()
+        .collect::<String>();
+    let to = Square::from_string(&to_text)?;
+
+    // whatever is left is the disambiguation
+    let from_file = chars.iter().find(|c| c.is_ascii_lowercase());
+    let from_rank = chars.iter().find(|c| c.is_ascii_digit());
+
+    let mut candidates = legal.into_iter().filter(|mv| {
+        let from = mv.from();
+
+        mv.mtype() != MoveType::Castle
+            && mv.to() == to
+            && pos.piece_at(from).piece_type() == piece_type
+            && promotion == promotion_type(*mv)
+            && from_file.is_none_or(|file| from.file().to_string().starts_with(*file))
+            && from_rank.is_none_or(|rank| from.rank().to_string().starts_with(*rank))
+    });
+
+    let mv = candidates.next()?;
+    candidates.next().is_none().then_some(mv)
+}
+
+fn promotion_type(mv: Move) -> Option<PieceType> {
+    (mv.mtype() == MoveType::Promotion).then(|| mv.promoted_piece().piece_type())
+}
+
+fn san_without_suffix(pos: &Position, mv: Move) -> String {
+    if mv.mtype() == MoveType::Castle {
+        return match mv.castle_type() {
+            CastleType::Short => "O-O".to_string(),
+            CastleType::Long => "O-O-O".to_string(),
+        };
+    }
+
+    let from = mv.from();
+    let to = mv.to();
+    let piece_type = pos.piece_at(from).piece_type();
+    let is_capture = pos.piece_at(to) != Piece::none() || mv.mtype() == MoveType::EnPassant;
+
+    let mut san = String::new();
+
+    if piece_type == PieceType::Pawn {
+        if is_capture {
+            san.push_str(&from.file().to_string());
+            san.push('x');
+        }
+        san.push_str(&to.to_string());
+
+        if let Some(promotion) = promotion_type(mv) {
+            san.push('=');
+            san.push(piece_letter(promotion));
+        }
+
+        return san;
+    }
+
+    san.push(piece_letter(piece_type));
+
+    let others = attacks::legal_moves(pos)
+        .into_iter()
+        .filter(|other| {
+            other.mtype() != MoveType::Castle
+                && other.to() == to
+                && other.from() != from
+                && pos.piece_at(other.from()).piece_type() == piece_type
+        })
+        .collect::<Vec<_>>();
+
+    if !others.is_empty() {
+        if others
+            .iter()
+            .all(|other| other.from().file() != from.file())
+        {
+            san.push_str(&from.file().to_string());
+        } else if others
+            .iter()
+            .all(|other| other.from().rank() != from.rank())
+        {
+            san.push_str(&from.rank().to_string());
+        } else {
+            san.push_str(&from.to_string());
+        }
+    }
+
+    if is_capture {
+        san.push('x');
+    }
+    san.push_str(&to.to_string());
+
+    san
+}
+
+fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+        _ => panic!("Invalid SAN piece"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san_of(fen: &str, uci: &str) -> String {
+        let pos = Position::from_fen(fen).unwrap();
+        to_san(&pos, Move::from_uci(&pos, uci).unwrap())
+    }
+
+    #[test]
+    fn test_to_san() {
+        let startpos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san_of(startpos, "e2e4"), "e4");
+        assert_eq!(san_of(startpos, "g1f3"), "Nf3");
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san_of(fen, "e1g1"), "O-O");
+        assert_eq!(san_of(fen, "e1c1"), "O-O-O");
+        assert_eq!(san_of(fen, "a1a8"), "Rxa8+");
+
+        let fen = "1k6/8/8/8/8/8/4K3/R6R w - - 0 1";
+        assert_eq!(san_of(fen, "a1d1"), "Rad1");
+
+        let fen = "7k/8/8/8/R7/8/4K3/R7 w - - 0 1";
+        assert_eq!(san_of(fen, "a1a2"), "R1a2");
+
+        let fen = "k7/4P3/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(san_of(fen, "e5d6"), "exd6");
+        assert_eq!(san_of(fen, "e7e8q"), "e8=Q+");
+
+        let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        assert_eq!(san_of(fen, "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let pos = Position::from_fen("1k6/4P3/8/3pP3/8/8/8/R3K2R w K d6 0 1").unwrap();
+
+        let uci = |san| parse_san(&pos, san).map(|mv| mv.as_uci());
+
+        assert_eq!(uci("exd6").as_deref(), Some("e5d6"));
+        assert_eq!(uci("e8=Q+").as_deref(), Some("e7e8q"));
+        assert_eq!(uci("e8N").as_deref(), Some("e7e8n"));
+        assert_eq!(uci("O-O").as_deref(), Some("e1g1"));
+        assert_eq!(uci("Rd1").as_deref(), Some("a1d1"));
+        assert_eq!(uci("Kd2!?").as_deref(), Some("e1d2"));
+        assert_eq!(uci("Rf1").as_deref(), Some("h1f1"));
+        assert_eq!(uci("O-O-O"), None);
+        assert_eq!(uci("Nf3"), None);
+    }
+
+    #[test]
+    fn test_parse_san_ambiguous() {
+        let pos = Position::from_fen("1k6/8/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+
+        assert_eq!(parse_san(&pos, "Rd1"), None);
+        assert_eq!(
+            parse_san(&pos, "Rhf1").map(|mv| mv.as_uci()).as_deref(),
+            Some("h1f1")
+        );
+    }
+
+    #[test]
+    fn test_from_uci_castling() {
+        let pos = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+
+        let short = Move::from_uci(&pos, "e1g1").unwrap();
+        assert_eq!(short, Move::from_uci(&pos, "e1h1").unwrap());
+        assert_eq!(short.mtype(), MoveType::Castle);
+        assert_eq!(Move::from_uci(&pos, "e1e3"), None);
+    }
+}
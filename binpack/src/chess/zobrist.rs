@@ -0,0 +1,121 @@
+//! Zobrist hashing of positions.
+//!
+//! The keys are generated at compile time from a fixed seed, so a position
+//! hashes to the same key in every build and on every platform and keys can
+//! be stored or compared across runs.
+
+use crate::{
+    chess::{castling_rights::CastlingRights, coords::Square, piece::Piece},
+    rng,
+};
+
+struct Keys {
+    /// Indexed by piece id and square
+    pieces: [[u64; 64]; 12],
+    /// One key per castling right, in the order of `CASTLING_RIGHTS`
+    castling: [u64; 4],
+    /// Indexed by the file of the en passant square
+    enpassant: [u64; 8],
+    black_to_move: u64,
+}
+
+const CASTLING_RIGHTS: [CastlingRights; 4] = [
+    CastlingRights::WHITE_KING_SIDE,
+    CastlingRights::WHITE_QUEEN_SIDE,
+    CastlingRights::BLACK_KING_SIDE,
+    CastlingRights::BLACK_QUEEN_SIDE,
+];
+
+/// SplitMix64 step, returns the new state and the output
+const fn next_key(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(rng::GAMMA);
+    (state, rng::mix(state))
+}
+
+const fn generate_keys() -> Keys {
+    let mut keys = Keys {
+        pieces: [[0; 64]; 12],
+        castling: [0; 4],
+        enpassant: [0; 8],
+        black_to_move: 0,
+    };
+    let mut state = 0x5F5F_B1A9_C0DE_0001;
+    let mut key;
+
+    let mut piece = 0;
+    while piece < 12 {
+        let mut square = 0;
+        while square < 64 {
+            (state, key) = next_key(state);
+            keys.pieces[piece][square] = key;
+            square += 1;
+        }
+        piece += 1;
+    }
+
+    let mut idx = 0;
+    while idx < 4 {
+        (state, key) = next_key(state);
+        keys.castling[idx] = key;
+        idx += 1;
+    }
+
+    let mut file = 0;
+    while file < 8 {
+        (state, key) = next_key(state);
+        keys.enpassant[file] = key;
+        file += 1;
+    }
+
+    (_, keys.black_to_move) = next_key(state);
+    keys
+}
+
+const KEYS: Keys = generate_keys();
+
+/// Key of a piece on a square, `pc` must not be `Piece::NONE`
+#[inline(always)]
+pub fn piece(pc: Piece, sq: Square) -> u64 {
+    KEYS.pieces[pc.id() as usize][sq.index() as usize]
+}
+
+/// Combined key of all castling rights in `rights`
+pub fn castling(rights: CastlingRights) -> u64 {
+    CASTLING_RIGHTS
+        .iter()
+        .zip(KEYS.castling)
+        .filter(|(right, _)| rights.contains(**right))
+        .fold(0, |key, (_, right_key)| key ^ right_key)
+}
+
+/// Key of an en passant square, `sq` must not be `Square::NONE`
+#[inline(always)]
+pub fn enpassant(sq: Square) -> u64 {
+    KEYS.enpassant[(sq.index() % 8) as usize]
+}
+
+/// Key toggled when black is to move
+#[inline(always)]
+pub fn black_to_move() -> u64 {
+    KEYS.black_to_move
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_keys_are_distinct() {
+        let mut keys = HashSet::new();
+
+        keys.extend(KEYS.pieces.iter().flatten().copied());
+        keys.extend(KEYS.castling);
+        keys.extend(KEYS.enpassant);
+        keys.insert(KEYS.black_to_move);
+
+        assert_eq!(keys.len(), 12 * 64 + 4 + 8 + 1);
+        assert!(!keys.contains(&0));
+    }
+}
@@ -0,0 +1,119 @@
+//! The binpack encoding without any I/O: compressed positions and moves, the
+//! packed stem of a chain and the movetext of the plies after it.
+//!
+//! This is what the crate builds without the `std` feature, a `no_std`
+//! library on top of `alloc` for embedded tooling and WASM builds that get
+//! the bytes of a chunk some other way. With `std` the
+//! [`CompressedTrainingDataEntryReader`](crate::CompressedTrainingDataEntryReader)
+//! and writer are layered on top of it.
+//!
+//! A chunk is an 8 byte header, `BINP` and the little endian size of its
+//! data, followed by chains: a 32 byte [`PackedTrainingDataEntry`] stem, the
+//! big endian number of plies after it and the movetext of those plies,
+//! which a [`PackedMoveScoreListReader`] decodes and a
+//! [`PackedMoveScoreList`] encodes. [`ChunkEntries`] decodes the data of a
+//! chunk.
+//!
+//! ```
+//! use sfbinpack::codec::ChunkEntries;
+//!
+//! let bytes = std::fs::read("test/ep1.binpack").unwrap();
+//! assert_eq!(&bytes[..4], b"BINP");
+//! let size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+//!
+//! let entries = ChunkEntries::new(&bytes[8..8 + size]).collect::<Vec<_>>();
+//! assert_eq!(entries.len(), 3);
+//! ```
+
+pub use crate::common::{
+    bitreader::BitReader,
+    bitwriter::BitWriter,
+    compressed_move::CompressedMove,
+    compressed_position::{CompressedPosition, CompressedPositionError},
+    entry::{PackedTrainingDataEntry, TrainingDataEntry},
+    move_score_list::PackedMoveScoreList,
+    move_score_list_reader::PackedMoveScoreListReader,
+};
+
+/// The entries of the data of a chunk, without its header, in the order
+/// they are stored. Like [`next()`](crate::CompressedTrainingDataEntryReader::next)
+/// of the reader it assumes the data is well formed.
+#[derive(Debug)]
+pub struct ChunkEntries<'a> {
+    data: &'a [u8],
+    offset: usize,
+    /// The movetext of the chain being decoded
+    chain: Option<PackedMoveScoreListReader>,
+}
+
+impl<'a> ChunkEntries<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            offset: 0,
+            chain: None,
+        }
+    }
+}
+
+impl Iterator for ChunkEntries<'_> {
+    type Item = TrainingDataEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(chain) = &mut self.chain {
+            let entry = chain.next_entry(&self.data[self.offset..]);
+            if !chain.has_next() {
+                self.offset += chain.num_read_bytes();
+                self.chain = None;
+            }
+            return Some(entry);
+        }
+
+        // the rest of the chunk is padding if no stem fits
+        let size = PackedTrainingDataEntry::byte_size();
+        let stem = self.data.get(self.offset..self.offset + size + 2)?;
+        let entry = PackedTrainingDataEntry::from_slice(&stem[..size]).unpack_entry();
+        let num_plies = u16::from_be_bytes([stem[size], stem[size + 1]]);
+        self.offset += size + 2;
+
+        if num_plies > 0 {
+            self.chain = Some(PackedMoveScoreListReader::new(entry, num_plies));
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{random_game, GameOptions, Rng},
+        CompressedTrainingDataEntryWriter,
+    };
+
+    #[test]
+    fn test_chunk_entries() {
+        let mut rng = Rng::new(5);
+        let entries = (0..20)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.flush_and_end();
+        let bytes = writer.into_inner().unwrap();
+
+        // every chunk on its own
+        let mut decoded = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            assert_eq!(&rest[..4], b"BINP");
+            let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            decoded.extend(ChunkEntries::new(&rest[8..8 + size]));
+            rest = &rest[8 + size..];
+        }
+        assert_eq!(decoded, entries);
+    }
+}
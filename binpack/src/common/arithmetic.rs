@@ -1,7 +1,15 @@
-#[cfg(all(target_arch = "x86_64", any(target_feature = "bmi2", feature = "bmi2")))]
-use std::arch::x86_64::_pdep_u64;
-
-#[cfg(all(target_arch = "x86_64", any(target_feature = "bmi2", feature = "bmi2")))]
+#[cfg(all(
+    target_arch = "x86_64",
+    feature = "unsafe-opt",
+    any(target_feature = "bmi2", feature = "bmi2")
+))]
+use core::arch::x86_64::_pdep_u64;
+
+#[cfg(all(
+    target_arch = "x86_64",
+    feature = "unsafe-opt",
+    any(target_feature = "bmi2", feature = "bmi2")
+))]
 #[target_feature(enable = "bmi2")]
 unsafe fn nth_set_bit_index_bmi2(v: u64, n: u64) -> u32 {
     _pdep_u64(1u64 << n, v).trailing_zeros() as u32
@@ -38,9 +46,24 @@ const NTH_SET_BIT_INDEX: [[u8; 8]; 256] = create_lookup_table();
 #[allow(unreachable_code)]
 #[inline(always)]
 pub fn nth_set_bit_index(v: u64, n: u64) -> u32 {
-    #[cfg(all(target_arch = "x86_64", any(target_feature = "bmi2", feature = "bmi2")))]
-    unsafe {
-        return nth_set_bit_index_bmi2(v, n);
+    // the feature only asks for pdep, the CPU may still lack it; with BMI2
+    // enabled at compile time the check is a constant. Without std there
+    // is no runtime detection and only the target counts.
+    #[cfg(all(
+        target_arch = "x86_64",
+        feature = "unsafe-opt",
+        any(target_feature = "bmi2", feature = "bmi2")
+    ))]
+    {
+        #[cfg(feature = "std")]
+        let bmi2 = std::arch::is_x86_feature_detected!("bmi2");
+        #[cfg(not(feature = "std"))]
+        let bmi2 = cfg!(target_feature = "bmi2");
+
+        if bmi2 {
+            // SAFETY: BMI2 is available, as checked above
+            return unsafe { nth_set_bit_index_bmi2(v, n) };
+        }
     }
 
     let mut value = v;
@@ -7,8 +7,14 @@ pub enum BinpackError {
     Io(#[from] io::Error),
     #[error("Invalid magic bytes")]
     InvalidMagic,
+    #[error("Unexpected end of file in a chunk header")]
+    UnexpectedEof,
+    #[error("Truncated chunk: expected {expected} bytes, got {got}")]
+    TruncatedChunk { expected: u32, got: u32 },
     #[error("Invalid format: {0}")]
     InvalidFormat(String),
+    #[error("Chunk checksum mismatch: stored {stored:08x}, computed {computed:08x}")]
+    ChecksumMismatch { stored: u32, computed: u32 },
 }
 
 pub type Result<T> = std::result::Result<T, BinpackError>;
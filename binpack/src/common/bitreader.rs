@@ -0,0 +1,124 @@
+/// Reads the bits of a movetext, most significant bit first.
+///
+/// The reader only keeps its position, the movetext is passed to every call
+/// so it can stay in the chunk buffer it was read into. Reads past the end of
+/// the movetext return zero bits, a corrupt chain can not read outside of its
+/// chunk and the caller finds the overrun through num_read_bytes().
+#[derive(Debug)]
+pub struct BitReader {
+    read_bits_left: usize,
+    read_offset: usize,
+    /// Set once a variable length number had more blocks than fit 16 bits
+    overlong: bool,
+}
+
+impl Default for BitReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitReader {
+    pub fn new() -> Self {
+        Self {
+            read_bits_left: 8,
+            read_offset: 0,
+            overlong: false,
+        }
+    }
+
+    #[inline(always)]
+    fn byte_at(movetext: &[u8], idx: usize) -> u8 {
+        movetext.get(idx).copied().unwrap_or(0)
+    }
+
+    pub fn extract_bits_le8(&mut self, movetext: &[u8], count: usize) -> u8 {
+        if count == 0 {
+            return 0;
+        }
+
+        if self.read_bits_left == 0 {
+            self.read_offset += 1;
+            self.read_bits_left = 8;
+        }
+
+        let byte = Self::byte_at(movetext, self.read_offset) << (8 - self.read_bits_left);
+
+        let mut bits = byte >> (8 - count);
+
+        if count > self.read_bits_left {
+            let spill_count = count - self.read_bits_left;
+
+            bits |= Self::byte_at(movetext, self.read_offset + 1) >> (8 - spill_count);
+
+            self.read_bits_left += 8;
+            self.read_offset += 1;
+        }
+
+        self.read_bits_left -= count;
+        bits
+    }
+
+    pub fn extract_vle16(&mut self, movetext: &[u8], block_size: usize) -> u16 {
+        let mask = (1 << block_size) - 1;
+        let mut v = 0u16;
+        let mut offset = 0;
+
+        loop {
+            let block = self.extract_bits_le8(movetext, block_size + 1) as u16;
+            v |= (block & mask) << offset;
+            if (block >> block_size) == 0 {
+                break;
+            }
+            offset += block_size;
+
+            if offset >= 16 {
+                self.overlong = true;
+                break;
+            }
+        }
+
+        v
+    }
+
+    /// Whether a variable length number ran past 16 bits, which the writer
+    /// never produces
+    pub fn is_overlong(&self) -> bool {
+        self.overlong
+    }
+
+    pub fn num_read_bytes(&self) -> usize {
+        self.read_offset + (self.read_bits_left != 8) as usize
+    }
+
+    pub fn num_read_bits(&self) -> usize {
+        self.read_offset * 8 + (8 - self.read_bits_left)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_bits() {
+        let movetext = [0b1011_0011, 0b0100_0000];
+        let mut reader = BitReader::new();
+
+        assert_eq!(reader.extract_bits_le8(&movetext, 3), 0b101);
+        assert_eq!(reader.extract_bits_le8(&movetext, 7), 0b1001101);
+        assert_eq!(reader.num_read_bytes(), 2);
+        assert_eq!(reader.num_read_bits(), 10);
+    }
+
+    #[test]
+    fn test_reads_past_the_end_are_zero() {
+        let movetext = [0xFF];
+        let mut reader = BitReader::new();
+
+        assert_eq!(reader.extract_bits_le8(&movetext, 6), 0b111111);
+        assert_eq!(reader.extract_bits_le8(&movetext, 4), 0b1100);
+        assert_eq!(reader.extract_bits_le8(&movetext, 8), 0);
+        assert_eq!(reader.num_read_bytes(), 3);
+    }
+}
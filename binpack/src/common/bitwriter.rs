@@ -1,9 +1,17 @@
+use alloc::vec::Vec;
+
 #[derive(Debug)]
 pub struct BitWriter {
     pub movetext: Vec<u8>,
     bits_left: usize,
 }
 
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BitWriter {
     pub fn new() -> Self {
         Self {
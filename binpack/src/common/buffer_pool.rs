@@ -0,0 +1,214 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+/// Chunk buffers shared between readers.
+///
+/// A reader created with a pool takes its chunk buffer from it and gives it
+/// back when dropped, so cycling through thousands of shard files reuses a
+/// few buffers instead of allocating one per file. With a limit at most that
+/// many buffers are in use at once, which bounds the memory of readers
+/// running in parallel; a reader created while all of them are in use waits
+/// for one to come back, so the limit must be at least the number of readers
+/// alive at the same time on a single thread.
+///
+/// The pool is a handle, clones share the same buffers.
+///
+/// # Examples
+///
+/// ```
+/// use std::fs::File;
+/// use sfbinpack::{BufferPool, CompressedTrainingDataEntryReader, ReaderOptions};
+///
+/// let pool = BufferPool::with_limit(4);
+///
+/// for _ in 0..10 {
+///     let file = File::open("test/ep1.binpack").unwrap();
+///     let options = ReaderOptions {
+///         buffer_pool: Some(pool.clone()),
+///         ..Default::default()
+///     };
+///     let mut reader = CompressedTrainingDataEntryReader::with_options(file, options).unwrap();
+///
+///     while reader.has_next() {
+///         let entry = reader.next();
+///     }
+/// }
+///
+/// assert_eq!(pool.idle(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BufferPool {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    returned: Condvar,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    idle: Vec<Vec<u8>>,
+    in_use: usize,
+}
+
+impl BufferPool {
+    /// A pool without a limit, buffers are only reused
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A pool handing out at most `max_buffers` buffers at once
+    pub fn with_limit(max_buffers: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                limit: Some(max_buffers.max(1)),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Buffers waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.state().idle.len()
+    }
+
+    /// Buffers taken and not given back yet
+    pub fn in_use(&self) -> usize {
+        self.state().in_use
+    }
+
+    /// Takes a buffer, waiting for one to be given back if the limit is
+    /// reached. The buffer is empty but keeps the capacity it grew to.
+    pub(crate) fn take(&self) -> PooledBuffer {
+        let mut state = self.state();
+
+        if let Some(limit) = self.shared.limit {
+            while state.in_use >= limit {
+                state = self
+                    .shared
+                    .returned
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        }
+
+        state.in_use += 1;
+        let buffer = state.idle.pop().unwrap_or_default();
+
+        PooledBuffer {
+            buffer,
+            pool: Some(self.clone()),
+        }
+    }
+
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+
+        let mut state = self.state();
+        state.in_use -= 1;
+        state.idle.push(buffer);
+        drop(state);
+
+        self.shared.returned.notify_one();
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // the state is consistent after every statement, a panic elsewhere
+        // does not leave it broken
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A buffer that goes back to its pool when dropped
+#[derive(Debug)]
+pub(crate) struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// A buffer of no pool, dropped like any other
+    pub(crate) fn unpooled(buffer: Vec<u8>) -> Self {
+        Self { buffer, pool: None }
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give_back(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new();
+
+        let mut buffer = pool.take();
+        buffer.resize(1000, 1);
+        let capacity = buffer.capacity();
+        assert_eq!(pool.in_use(), 1);
+        drop(buffer);
+
+        assert_eq!((pool.in_use(), pool.idle()), (0, 1));
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), capacity);
+
+        // without a limit more buffers are made as needed
+        let others: Vec<_> = (0..5).map(|_| pool.take()).collect();
+        assert_eq!(pool.in_use(), 6);
+        drop(others);
+        drop(buffer);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 6));
+    }
+
+    #[test]
+    fn test_limit_waits_for_a_buffer() {
+        let pool = BufferPool::with_limit(2);
+        let first = pool.take();
+        let _second = pool.take();
+
+        let waiting = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                let _third = pool.take();
+            })
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        assert_eq!(pool.in_use(), 2);
+
+        drop(first);
+        waiting.join().unwrap();
+        assert_eq!(pool.in_use(), 1);
+    }
+}
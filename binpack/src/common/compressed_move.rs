@@ -23,7 +23,7 @@ impl CompressedMove {
     const PROMOTED_PIECE_TYPE_MASK: u16 = 0b11;
 
     pub fn byte_size() -> usize {
-        std::mem::size_of::<CompressedMove>()
+        core::mem::size_of::<CompressedMove>()
     }
 
     pub fn read_from_big_endian(data: &[u8]) -> Self {
@@ -1,3 +1,5 @@
+use thiserror::Error;
+
 use crate::chess::{
     bitboard::Bitboard,
     castling_rights::CastlingRights,
@@ -8,6 +10,21 @@ use crate::chess::{
     position::Position,
 };
 
+#[cfg(feature = "unsafe-opt")]
+use super::{arithmetic::nth_set_bit_index, simd::Simd};
+
+/// Nibbles that no position compresses to, see
+/// [`CompressedPosition::try_decompress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum CompressedPositionError {
+    #[error("{0} occupied squares, the 16 bytes of nibbles hold at most 32 pieces")]
+    TooManyPieces(u32),
+    #[error("en passant pawn on {0}, which is not on the fourth or fifth rank")]
+    EnPassantRank(Square),
+    #[error("castling rook on {0}, which is not a corner of its side")]
+    CastlingRook(Square),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CompressedPosition {
     occupied: Bitboard,
@@ -16,7 +33,7 @@ pub struct CompressedPosition {
 
 impl CompressedPosition {
     pub fn byte_size() -> usize {
-        std::mem::size_of::<CompressedPosition>()
+        core::mem::size_of::<CompressedPosition>()
     }
 
     pub fn read_from_big_endian(data: &[u8]) -> Self {
@@ -55,50 +72,73 @@ impl CompressedPosition {
         data[8..24].copy_from_slice(&self.packed_state[..16]);
     }
 
+    /// Decodes the position. Nibbles no position compresses to do not panic
+    /// but give a position that is not the one meant: pieces past the 32nd
+    /// are left out and a pawn or rook whose nibble carries en passant or
+    /// castling on a square where that is impossible is placed without it.
+    /// [`try_decompress`](Self::try_decompress) reports them instead.
     pub fn decompress(&self) -> Position {
-        let mut pos = Position::empty();
-        pos.set_castling_rights(CastlingRights::NONE);
+        // a corrupt position with more than 32 pieces does not fit the
+        // nibble streams of the SIMD kernels
+        #[cfg(feature = "unsafe-opt")]
+        if let Some(simd) = Simd::detect().filter(|_| self.occupied.count() <= 32) {
+            return self.decompress_simd(simd);
+        }
+
+        self.decompress_scalar()
+    }
+
+    /// Like decompress(), but fails on nibbles no position compresses to
+    pub fn try_decompress(&self) -> Result<Position, CompressedPositionError> {
+        self.validate()?;
+        Ok(self.decompress())
+    }
+
+    /// Checks that every nibble decodes to what it stands for: at most 32
+    /// pieces, en passant pawns on the fourth or fifth rank and castling
+    /// rooks in the corners of their side. Whether the decoded position can
+    /// arise in a game is not checked.
+    pub fn validate(&self) -> Result<(), CompressedPositionError> {
+        let count = self.occupied.count();
+        if count > 32 {
+            return Err(CompressedPositionError::TooManyPieces(count));
+        }
 
-        let mut decompress_piece = |sq: Square, nibble: u8| {
+        for (idx, sq) in self.occupied.iter().enumerate() {
+            let nibble = (self.packed_state[idx / 2] >> (4 * (idx % 2))) & 0xF;
             match nibble {
-                0..=11 => {
-                    pos.place(Piece::from_id(nibble as i32), sq);
-                }
-                12 => {
-                    let rank = sq.rank();
-                    if rank == Rank::FOURTH {
-                        pos.place(Piece::WHITE_PAWN, sq);
-                        pos.set_ep_square_unchecked(sq + FlatSquareOffset::new(0, -1));
-                    } else {
-                        // rank == Rank::FIFTH
-                        pos.place(Piece::BLACK_PAWN, sq);
-                        pos.set_ep_square_unchecked(sq + FlatSquareOffset::new(0, 1));
-                    }
+                12 if sq.rank() != Rank::FOURTH && sq.rank() != Rank::FIFTH => {
+                    return Err(CompressedPositionError::EnPassantRank(sq));
                 }
-                13 => {
-                    pos.place(Piece::WHITE_ROOK, sq);
-                    if sq == Square::A1 {
-                        pos.add_castling_rights(CastlingRights::WHITE_QUEEN_SIDE);
-                    } else {
-                        // sq == Square::H1
-                        pos.add_castling_rights(CastlingRights::WHITE_KING_SIDE);
-                    }
+                13 if sq != Square::A1 && sq != Square::H1 => {
+                    return Err(CompressedPositionError::CastlingRook(sq));
                 }
-                14 => {
-                    pos.place(Piece::BLACK_ROOK, sq);
-                    if sq == Square::A8 {
-                        pos.add_castling_rights(CastlingRights::BLACK_QUEEN_SIDE);
-                    } else {
-                        // sq == Square::H8
-                        pos.add_castling_rights(CastlingRights::BLACK_KING_SIDE);
-                    }
+                14 if sq != Square::A8 && sq != Square::H8 => {
+                    return Err(CompressedPositionError::CastlingRook(sq));
                 }
-                15 => {
-                    pos.place(Piece::BLACK_KING, sq);
-                    pos.set_side_to_move(Color::Black);
-                }
-                _ => unreachable!(),
+                _ => {}
             }
+        }
+
+        Ok(())
+    }
+
+    pub fn compress(pos: &Position) -> Self {
+        #[cfg(feature = "unsafe-opt")]
+        if let Some(simd) = Simd::detect().filter(|_| pos.occupied().count() <= 32) {
+            return Self::compress_simd(pos, simd);
+        }
+
+        Self::compress_scalar(pos)
+    }
+
+    pub(crate) fn decompress_scalar(&self) -> Position {
+        let mut pos = Position::empty();
+        let mut extras = Extras::default();
+
+        let mut decompress_piece = |sq: Square, nibble: u8| match nibble {
+            0..=11 => pos.place(Piece::from_id(nibble as i32), sq),
+            _ => pos.place(special_piece(nibble, sq, &mut extras), sq),
         };
 
         let mut squares_iter = self.occupied.iter();
@@ -116,71 +156,54 @@ impl CompressedPosition {
             }
         }
 
+        pos.set_castling_rights(extras.castling_rights);
+        pos.set_ep_square_unchecked(extras.enpassant);
+        pos.set_side_to_move(extras.stm);
         pos
     }
 
-    pub fn compress(pos: &Position) -> Self {
-        let mut compressed = CompressedPosition {
-            occupied: pos.occupied(),
-            packed_state: [0u8; 16],
-        };
+    #[cfg(feature = "unsafe-opt")]
+    fn decompress_simd(&self, simd: Simd) -> Position {
+        let occupied = self.occupied.bits();
+        let nibbles = simd.unpack_nibbles(&self.packed_state);
+        let mut board = simd.expand(&nibbles, occupied);
+        let mut extras = Extras::default();
 
-        let pack_piece = |sq: Square| -> u8 {
-            let piece = pos.piece_at(sq);
-            let piece_id = piece.id();
-
-            // Special case: pawn with en passant
-            if piece.piece_type() == PieceType::Pawn {
-                let ep_sq = pos.ep_square();
-                if ep_sq != Square::NONE
-                    && ((piece.color() == Color::White
-                        && sq.rank() == Rank::FOURTH
-                        && ep_sq == sq + FlatSquareOffset::new(0, -1))
-                        || (piece.color() == Color::Black
-                            && sq.rank() == Rank::FIFTH
-                            && ep_sq == sq + FlatSquareOffset::new(0, 1)))
-                {
-                    return 12;
-                }
-            }
+        // nibbles past the last piece are not looked at, like in the scalar code
+        let pieces = (1u64 << occupied.count_ones()) - 1;
+        let mut specials = simd.special_nibbles(&nibbles) & pieces as u32;
 
-            // Special case: rooks with castling rights
-            if piece == Piece::WHITE_ROOK
-                && ((sq == Square::A1
-                    && pos
-                        .castling_rights()
-                        .contains(CastlingRights::WHITE_QUEEN_SIDE))
-                    || (sq == Square::H1
-                        && pos
-                            .castling_rights()
-                            .contains(CastlingRights::WHITE_KING_SIDE)))
-            {
-                return 13;
-            }
-            if piece == Piece::BLACK_ROOK
-                && ((sq == Square::A8
-                    && pos
-                        .castling_rights()
-                        .contains(CastlingRights::BLACK_QUEEN_SIDE))
-                    || (sq == Square::H8
-                        && pos
-                            .castling_rights()
-                            .contains(CastlingRights::BLACK_KING_SIDE)))
-            {
-                return 14;
-            }
+        while specials != 0 {
+            let idx = specials.trailing_zeros();
+            specials &= specials - 1;
 
-            // Special case: black king when black to move
-            if piece == Piece::BLACK_KING && pos.side_to_move() == Color::Black {
-                return 15;
-            }
+            let sq = Square::new(nth_set_bit_index(occupied, idx as u64));
+            let pc = special_piece(nibbles[idx as usize], sq, &mut extras);
+            board[sq.index() as usize] = pc.id();
+        }
 
-            piece_id
+        let (bb, bb_color) = simd.bitboards(&board);
+
+        Position::from_parts(
+            bb,
+            bb_color,
+            extras.stm,
+            extras.castling_rights,
+            extras.enpassant,
+        )
+    }
+
+    pub(crate) fn compress_scalar(pos: &Position) -> Self {
+        let mut compressed = CompressedPosition {
+            occupied: pos.occupied(),
+            packed_state: [0u8; 16],
         };
 
+        let board = nibble_board(pos, pos.board());
+
         let mut idx = 0;
         for (nibble_idx, sq) in compressed.occupied.iter().enumerate() {
-            let nibble = pack_piece(sq);
+            let nibble = board[sq.index() as usize];
             if nibble_idx % 2 == 0 {
                 compressed.packed_state[idx] = nibble;
             } else {
@@ -191,10 +214,149 @@ impl CompressedPosition {
 
         compressed
     }
+
+    #[cfg(feature = "unsafe-opt")]
+    fn compress_simd(pos: &Position, simd: Simd) -> Self {
+        let occupied = pos.occupied();
+        let board = simd.board(&pos.id_planes());
+        let nibbles = simd.compact(&nibble_board(pos, board), occupied.bits());
+
+        CompressedPosition {
+            occupied,
+            packed_state: simd.pack_nibbles(&nibbles),
+        }
+    }
+}
+
+/// The state a position encodes in its nibbles besides the pieces
+#[derive(Debug, Clone, Copy)]
+struct Extras {
+    castling_rights: CastlingRights,
+    enpassant: Square,
+    stm: Color,
+}
+
+impl Default for Extras {
+    fn default() -> Self {
+        Self {
+            castling_rights: CastlingRights::NONE,
+            enpassant: Square::NONE,
+            stm: Color::White,
+        }
+    }
+}
+
+/// Decodes one of the nibbles 12 to 15, which stand for a piece with some
+/// state attached:
+/// 12 a pawn that just moved two squares, 13 and 14 a white or black rook
+/// that can still castle, 15 the black king when black is to move.
+/// On a square where the state is impossible, which
+/// [`CompressedPosition::validate`] rejects, the piece is placed without it.
+fn special_piece(nibble: u8, sq: Square, extras: &mut Extras) -> Piece {
+    match nibble {
+        12 => {
+            if sq.rank() == Rank::FOURTH {
+                extras.enpassant = sq + FlatSquareOffset::new(0, -1);
+            } else if sq.rank() == Rank::FIFTH {
+                extras.enpassant = sq + FlatSquareOffset::new(0, 1);
+            }
+
+            // the white half of the board
+            if sq.index() < 32 {
+                Piece::WHITE_PAWN
+            } else {
+                Piece::BLACK_PAWN
+            }
+        }
+        13 => {
+            if sq == Square::A1 {
+                extras.castling_rights |= CastlingRights::WHITE_QUEEN_SIDE;
+            } else if sq == Square::H1 {
+                extras.castling_rights |= CastlingRights::WHITE_KING_SIDE;
+            }
+            Piece::WHITE_ROOK
+        }
+        14 => {
+            if sq == Square::A8 {
+                extras.castling_rights |= CastlingRights::BLACK_QUEEN_SIDE;
+            } else if sq == Square::H8 {
+                extras.castling_rights |= CastlingRights::BLACK_KING_SIDE;
+            }
+            Piece::BLACK_ROOK
+        }
+        // 15, the only nibble left
+        _ => {
+            extras.stm = Color::Black;
+            Piece::BLACK_KING
+        }
+    }
+}
+
+/// The nibble of every square: the piece id, or 12 to 15 for the pieces
+/// that carry en passant, castling or side to move
+fn nibble_board(pos: &Position, mut board: [u8; 64]) -> [u8; 64] {
+    let ep_sq = pos.ep_square();
+    if ep_sq != Square::NONE {
+        let pawn = if ep_sq.rank() == Rank::THIRD {
+            Some((ep_sq + FlatSquareOffset::new(0, 1), Piece::WHITE_PAWN))
+        } else if ep_sq.rank() == Rank::SIXTH {
+            Some((ep_sq + FlatSquareOffset::new(0, -1), Piece::BLACK_PAWN))
+        } else {
+            None
+        };
+
+        if let Some((sq, pc)) = pawn {
+            if pos.piece_at(sq) == pc {
+                board[sq.index() as usize] = 12;
+            }
+        }
+    }
+
+    let rooks = [
+        (
+            CastlingRights::WHITE_QUEEN_SIDE,
+            Square::A1,
+            Piece::WHITE_ROOK,
+            13,
+        ),
+        (
+            CastlingRights::WHITE_KING_SIDE,
+            Square::H1,
+            Piece::WHITE_ROOK,
+            13,
+        ),
+        (
+            CastlingRights::BLACK_QUEEN_SIDE,
+            Square::A8,
+            Piece::BLACK_ROOK,
+            14,
+        ),
+        (
+            CastlingRights::BLACK_KING_SIDE,
+            Square::H8,
+            Piece::BLACK_ROOK,
+            14,
+        ),
+    ];
+    for (right, sq, rook, nibble) in rooks {
+        if pos.castling_rights().contains(right) && pos.piece_at(sq) == rook {
+            board[sq.index() as usize] = nibble;
+        }
+    }
+
+    if pos.side_to_move() == Color::Black {
+        for sq in pos.pieces_bb_color(Color::Black, PieceType::King).iter() {
+            board[sq.index() as usize] = 15;
+        }
+    }
+
+    board
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::chess::attacks;
+
     use super::*;
 
     #[test]
@@ -239,6 +401,53 @@ mod tests {
     //     let _ = CompressedPosition::read_from_big_endian(&data).decompress();
     // }
 
+    #[test]
+    fn test_invalid_nibbles() {
+        let pos = Position::from_fen("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+        let compressed = CompressedPosition::compress(&pos);
+        assert_eq!(compressed.try_decompress(), Ok(compressed.decompress()));
+
+        // the nibble of the n-th piece from a1
+        let with_nibble = |n: usize, nibble: u8| {
+            let mut garbage = compressed;
+            let shift = 4 * (n % 2);
+            garbage.packed_state[n / 2] &= !(0xF << shift);
+            garbage.packed_state[n / 2] |= nibble << shift;
+            garbage
+        };
+
+        // the white king on e1 as an en passant pawn and castling rooks
+        for (nibble, err) in [
+            (12, CompressedPositionError::EnPassantRank(Square::E1)),
+            (13, CompressedPositionError::CastlingRook(Square::E1)),
+            (14, CompressedPositionError::CastlingRook(Square::E1)),
+        ] {
+            let garbage = with_nibble(1, nibble);
+            assert_eq!(garbage.try_decompress(), Err(err));
+
+            // decoded without the state instead of panicking
+            let decompressed = garbage.decompress();
+            assert_eq!(decompressed.ep_square(), pos.ep_square());
+            assert_eq!(decompressed.castling_rights(), pos.castling_rights());
+        }
+
+        // a black castling rook on a white corner
+        assert_eq!(
+            with_nibble(0, 14).validate(),
+            Err(CompressedPositionError::CastlingRook(Square::A1))
+        );
+
+        let crowded = CompressedPosition {
+            occupied: Bitboard::new(0xFFFF_FFFF_0000_0001),
+            packed_state: [0; 16],
+        };
+        assert_eq!(
+            crowded.try_decompress(),
+            Err(CompressedPositionError::TooManyPieces(33))
+        );
+        assert_eq!(crowded.decompress().occupied().count(), 32);
+    }
+
     #[test]
     fn test_write_big_endian() {
         let data = [
@@ -290,4 +499,101 @@ mod tests {
 
         assert_eq!(position_without_fmt, decompressed_pos);
     }
+
+    /// Positions of random games, with en passant squares and castling rights
+    /// along the way
+    fn random_positions() -> Vec<Position> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut positions = Vec::new();
+
+        for _ in 0..100 {
+            let mut pos = Position::new();
+
+            for _ in 0..150 {
+                positions.push(pos);
+
+                let moves = attacks::legal_moves(&pos);
+                if moves.is_empty() {
+                    break;
+                }
+
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                pos.do_move(moves[(state % moves.len() as u64) as usize]);
+            }
+        }
+
+        positions
+    }
+
+    #[test]
+    fn test_round_trip_random_positions() {
+        for mut pos in random_positions() {
+            let decompressed = CompressedPosition::compress(&pos).decompress();
+            pos.set_rule50_counter(0);
+            pos.set_ply(0);
+            assert_eq!(decompressed.fen().unwrap(), pos.fen().unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "unsafe-opt")]
+    fn test_simd_matches_scalar() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        for pos in random_positions() {
+            let compressed = CompressedPosition::compress_scalar(&pos);
+            assert_eq!(CompressedPosition::compress_simd(&pos, simd), compressed);
+
+            let decompressed = compressed.decompress_scalar();
+            assert_eq!(compressed.decompress_simd(simd), decompressed);
+            assert_eq!(decompressed.fen().unwrap(), {
+                let mut pos = pos;
+                pos.set_rule50_counter(0);
+                pos.set_ply(0);
+                pos.fen().unwrap()
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "unsafe-opt")]
+    fn test_simd_matches_scalar_on_garbage() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..10_000 {
+            // at most 32 squares, the scalar code ignores the rest
+            let mut occupied = next() & next();
+            while occupied.count_ones() > 32 {
+                occupied &= occupied - 1;
+            }
+
+            let mut packed_state = [0u8; 16];
+            for byte in packed_state.iter_mut() {
+                *byte = next() as u8;
+            }
+
+            let compressed = CompressedPosition {
+                occupied: Bitboard::new(occupied),
+                packed_state,
+            };
+            assert_eq!(
+                compressed.decompress_simd(simd),
+                compressed.decompress_scalar()
+            );
+        }
+    }
 }
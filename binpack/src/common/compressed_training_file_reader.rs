@@ -1,30 +1,93 @@
 use std::io::{Read, Seek, SeekFrom};
 
-use super::binpack_error::{BinpackError, Result};
+use super::{
+    binpack_error::{BinpackError, Result},
+    compressed_training_file_writer::{CHUNK_HEADER_SIZE, CHUNK_MAGIC, PADDING_MAGIC},
+};
+use crate::annotation;
 
-const HEADER_SIZE: usize = 8;
-const MAX_CHUNK_SIZE: u32 = 100 * 1024 * 1024;
-const MAGIC: &[u8; 4] = b"BINP";
+/// Largest chunk read by default, the writer never gets near it
+pub const MAX_CHUNK_SIZE: u32 = 100 * 1024 * 1024;
+
+/// How chunks are framed in a file.
+///
+/// Stockfish writes `"BINP"` and the chunk size before every chunk, some
+/// older tools only wrote the magic before the first one and just the size
+/// before the rest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FormatDialect {
+    /// Magic and size before every chunk
+    #[default]
+    Standard,
+    /// Magic and size before the first chunk, only the size before the rest
+    MagicOnce,
+    /// Detected from the second chunk header: a header without the magic is
+    /// taken as a size if it is no larger than the chunk size cap and the
+    /// rest of the file, and from then on the file is read as `MagicOnce`.
+    /// A second header with the magic makes it `Standard`.
+    Auto,
+}
 
 #[derive(Debug)]
 struct Header {
     chunk_size: u32,
+    /// An extension chunk of annotations, see [`crate::annotation`]
+    annotations: bool,
 }
 
 #[derive(Debug)]
 pub struct CompressedTrainingDataFileReader<T: Read + Seek> {
     file: T,
+    /// Bytes from the start position to the end of the data, if known
+    len: Option<u64>,
+    dialect: FormatDialect,
+    max_chunk_size: u32,
+    /// Bytes read ahead to find out whether there is another chunk, they
+    /// are read again before anything else
+    peeked: Vec<u8>,
+    /// The error reading ahead, reported when the bytes are needed
+    peek_error: Option<std::io::Error>,
     read_bytes: u64,
+    /// Chunks of entries read, extension chunks are not counted
+    chunks_read: u64,
+    /// Data of the extension chunk in front of the last chunk read, if it
+    /// had one
+    annotations: Option<Vec<u8>>,
 }
 
 impl<T: Read + Seek> CompressedTrainingDataFileReader<T> {
-    pub fn new(file: T) -> std::io::Result<Self> {
+    /// Reads from the current position of `file` to its end. The length is
+    /// taken once here, or from `len_hint` if given; a source that cannot
+    /// seek and comes without a hint is read until a chunk header is missing.
+    pub fn new(mut file: T, len_hint: Option<u64>) -> std::io::Result<Self> {
+        let len = len_hint.or_else(|| remaining_len(&mut file).ok());
+
         Ok(Self {
             file,
+            len,
+            dialect: FormatDialect::Standard,
+            max_chunk_size: MAX_CHUNK_SIZE,
+            peeked: Vec::new(),
+            peek_error: None,
             read_bytes: 0,
+            chunks_read: 0,
+            annotations: None,
         })
     }
 
+    /// Reads chunks framed like `dialect`, larger than `max_chunk_size`
+    /// bytes they are an error
+    pub fn with_dialect(mut self, dialect: FormatDialect, max_chunk_size: u32) -> Self {
+        self.dialect = dialect;
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    /// The dialect chunks are read as, `Auto` until it was detected
+    pub fn dialect(&self) -> FormatDialect {
+        self.dialect
+    }
+
     pub fn into_inner(self) -> std::io::Result<T> {
         Ok(self.file)
     }
@@ -33,55 +96,481 @@ impl<T: Read + Seek> CompressedTrainingDataFileReader<T> {
         self.read_bytes
     }
 
+    pub fn chunks_read(&self) -> u64 {
+        self.chunks_read
+    }
+
     pub fn has_next_chunk(&mut self) -> bool {
-        if let Ok(pos) = self.file.stream_position() {
-            if let Ok(len) = self.file.seek(SeekFrom::End(0)) {
-                if self.file.seek(SeekFrom::Start(pos)).is_ok() {
-                    return pos < len;
-                }
+        if let Some(len) = self.len {
+            return self.read_bytes < len;
+        }
+
+        if !self.peeked.is_empty() || self.peek_error.is_some() {
+            return true;
+        }
+
+        // a partial header or a failed read still counts as a chunk, reading
+        // it reports the damage
+        let mut buf = [0u8; CHUNK_HEADER_SIZE];
+        match read_up_to(&mut self.file, &mut buf) {
+            Ok(0) => false,
+            Ok(n) => {
+                self.peeked.extend_from_slice(&buf[..n]);
+                true
+            }
+            Err(err) => {
+                self.peek_error = Some(err);
+                true
             }
         }
-        false
     }
 
-    pub fn read_next_chunk(&mut self) -> Result<Vec<u8>> {
-        let header = self.read_chunk_header()?;
-        let mut data = vec![0u8; header.chunk_size as usize];
-        self.file.read_exact(&mut data)?;
-        self.read_bytes += header.chunk_size as u64;
-        Ok(data)
+    /// The data of the extension chunk in front of the chunk last read,
+    /// trailer included, None if it had none
+    pub fn annotations(&self) -> Option<&[u8]> {
+        self.annotations.as_deref()
     }
 
+    /// Reads the next chunk of entries, keeping the data of an extension
+    /// chunk in front of it for annotations()
     pub fn read_next_chunk_into(&mut self, buffer: &mut Vec<u8>) -> Result<()> {
-        let header = self.read_chunk_header()?;
-        buffer.resize(header.chunk_size as usize, 0);
-        self.file.read_exact(buffer)?;
-        self.read_bytes += header.chunk_size as u64;
+        trace_span!("read_chunk", offset = self.read_bytes);
+        let mut annotations = self.annotations.take().unwrap_or_default();
+
+        let mut header = self.read_chunk_header()?;
+        if header.annotations {
+            self.read_chunk_data(&mut annotations, header.chunk_size)?;
+            self.annotations = Some(annotations);
+
+            header = self.read_chunk_header()?;
+            if header.annotations {
+                return Err(BinpackError::InvalidFormat(
+                    "Two annotation chunks in a row".to_string(),
+                ));
+            }
+        }
+
+        self.read_chunk_data(buffer, header.chunk_size)?;
+        trace_event!(
+            bytes = buffer.len(),
+            annotations = self.annotations.is_some(),
+            "chunk read"
+        );
         Ok(())
     }
 
-    fn read_chunk_header(&mut self) -> Result<Header> {
-        let mut buf = [0u8; HEADER_SIZE];
+    fn read_chunk_data(&mut self, buffer: &mut Vec<u8>, chunk_size: u32) -> Result<()> {
+        buffer.resize(chunk_size as usize, 0);
+
+        let got = self.read(buffer)?;
 
-        match self.file.read_exact(&mut buf) {
-            Ok(_) => (),
-            Err(_) => return Err(BinpackError::InvalidMagic),
+        if got < buffer.len() {
+            buffer.truncate(got);
+            return Err(BinpackError::TruncatedChunk {
+                expected: chunk_size,
+                got: got as u32,
+            });
         }
 
-        self.read_bytes += HEADER_SIZE as u64;
+        Ok(())
+    }
+
+    /// Passes over `size` bytes of padding
+    fn skip(&mut self, size: u32) -> Result<()> {
+        if size > self.max_chunk_size {
+            return Err(BinpackError::InvalidFormat(
+                "Padding larger than supported. Malformed file?".to_string(),
+            ));
+        }
 
-        if &buf[0..4] != MAGIC {
-            return Err(BinpackError::InvalidMagic);
+        let mut padding = vec![0u8; size as usize];
+        let got = self.read(&mut padding)?;
+        if got < padding.len() {
+            return Err(BinpackError::TruncatedChunk {
+                expected: size,
+                got: got as u32,
+            });
         }
+        Ok(())
+    }
 
-        let chunk_size = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+    /// Fills `buf` with the bytes read ahead and then from the file, unless
+    /// the end comes first
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let peeked = self.peeked.len().min(buf.len());
+        buf[..peeked].copy_from_slice(&self.peeked[..peeked]);
+        self.peeked.drain(..peeked);
 
-        if chunk_size > MAX_CHUNK_SIZE {
+        let mut read = peeked;
+        if read < buf.len() {
+            if let Some(err) = self.peek_error.take() {
+                self.read_bytes += read as u64;
+                return Err(err);
+            }
+            read += read_up_to(&mut self.file, &mut buf[read..])?;
+        }
+
+        self.read_bytes += read as u64;
+        Ok(read)
+    }
+
+    fn read_chunk_header(&mut self) -> Result<Header> {
+        let mut tag = [0u8; 4];
+        if self.read(&mut tag)? < tag.len() {
+            return Err(BinpackError::UnexpectedEof);
+        }
+
+        // padding in front of an aligned chunk
+        while &tag == PADDING_MAGIC {
+            let mut size = [0u8; 4];
+            if self.read(&mut size)? < size.len() {
+                return Err(BinpackError::UnexpectedEof);
+            }
+            self.skip(u32::from_le_bytes(size))?;
+
+            if self.read(&mut tag)? < tag.len() {
+                return Err(BinpackError::UnexpectedEof);
+            }
+        }
+
+        let annotations = &tag == annotation::MAGIC;
+
+        let chunk_size = if &tag == CHUNK_MAGIC || annotations {
+            let mut size = [0u8; 4];
+            if self.read(&mut size)? < size.len() {
+                return Err(BinpackError::UnexpectedEof);
+            }
+
+            if self.chunks_read > 0 && self.dialect == FormatDialect::Auto {
+                self.dialect = FormatDialect::Standard;
+            }
+            u32::from_le_bytes(size)
+        } else {
+            let size = u32::from_le_bytes(tag);
+            let fits = |len| u64::from(size) <= len - self.read_bytes.min(len);
+            let headerless = match self.dialect {
+                FormatDialect::MagicOnce => true,
+                FormatDialect::Auto => size <= self.max_chunk_size && self.len.is_none_or(fits),
+                FormatDialect::Standard => false,
+            };
+
+            if self.chunks_read == 0 || !headerless {
+                return Err(BinpackError::InvalidMagic);
+            }
+
+            self.dialect = FormatDialect::MagicOnce;
+            size
+        };
+
+        if chunk_size > self.max_chunk_size {
             return Err(BinpackError::InvalidFormat(
                 "Chunk size larger than supported. Malformed file?".to_string(),
             ));
         }
 
-        Ok(Header { chunk_size })
+        if !annotations {
+            self.chunks_read += 1;
+        }
+
+        Ok(Header {
+            chunk_size,
+            annotations,
+        })
+    }
+}
+
+/// Bytes from the current position to the end, leaving the position as is
+fn remaining_len(file: &mut impl Seek) -> std::io::Result<u64> {
+    let pos = file.stream_position()?;
+    let end = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(pos))?;
+    Ok(end.saturating_sub(pos))
+}
+
+/// Fills `buf` unless the end comes first, returns how much was read
+fn read_up_to(file: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    fn chunk(payload: &[u8]) -> Vec<u8> {
+        let mut data = CHUNK_MAGIC.to_vec();
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// A chunk with only its size in front, as `MagicOnce` has after the
+    /// first
+    fn headerless_chunk(payload: &[u8]) -> Vec<u8> {
+        chunk(payload)[CHUNK_MAGIC.len()..].to_vec()
+    }
+
+    fn read_all<T: Read + Seek>(
+        reader: &mut CompressedTrainingDataFileReader<T>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut chunks = Vec::new();
+        while reader.has_next_chunk() {
+            let mut buffer = Vec::new();
+            reader.read_next_chunk_into(&mut buffer)?;
+            chunks.push(buffer);
+        }
+        Ok(chunks)
+    }
+
+    /// Reads the data it holds, then fails
+    struct Failing(Cursor<Vec<u8>>);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Seek for Failing {
+        fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let mut data = chunk(b"first");
+        data.extend(chunk(b"second"));
+
+        let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(data), None).unwrap();
+        let mut buffer = Vec::new();
+
+        for expected in [&b"first"[..], b"second"] {
+            assert!(reader.has_next_chunk());
+            reader.read_next_chunk_into(&mut buffer).unwrap();
+            assert_eq!(buffer, expected);
+        }
+
+        assert!(!reader.has_next_chunk());
+        assert_eq!(reader.chunks_read(), 2);
+    }
+
+    #[test]
+    fn test_dialects() {
+        let mut magic_once = chunk(b"first");
+        magic_once.extend(headerless_chunk(b"second"));
+        magic_once.extend(headerless_chunk(b"third"));
+
+        let mut standard = chunk(b"first");
+        standard.extend(chunk(b"second"));
+
+        let expected = [&b"first"[..], b"second", b"third"];
+
+        for len in [None, Some(magic_once.len() as u64)] {
+            for dialect in [FormatDialect::MagicOnce, FormatDialect::Auto] {
+                let mut reader =
+                    CompressedTrainingDataFileReader::new(Cursor::new(magic_once.clone()), len)
+                        .unwrap()
+                        .with_dialect(dialect, MAX_CHUNK_SIZE);
+                assert_eq!(read_all(&mut reader).unwrap(), expected);
+                assert_eq!(reader.dialect(), FormatDialect::MagicOnce);
+                assert_eq!(reader.read_bytes(), magic_once.len() as u64);
+            }
+
+            // the first chunk needs its magic in every dialect
+            let mut reader =
+                CompressedTrainingDataFileReader::new(Cursor::new(magic_once[4..].to_vec()), len)
+                    .unwrap()
+                    .with_dialect(FormatDialect::Auto, MAX_CHUNK_SIZE);
+            assert!(matches!(
+                read_all(&mut reader),
+                Err(BinpackError::InvalidMagic)
+            ));
+        }
+
+        let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(magic_once), None)
+            .unwrap()
+            .with_dialect(FormatDialect::Standard, MAX_CHUNK_SIZE);
+        assert!(matches!(
+            read_all(&mut reader),
+            Err(BinpackError::InvalidMagic)
+        ));
+
+        let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(standard), None)
+            .unwrap()
+            .with_dialect(FormatDialect::Auto, MAX_CHUNK_SIZE);
+        assert_eq!(read_all(&mut reader).unwrap(), expected[..2]);
+        assert_eq!(reader.dialect(), FormatDialect::Standard);
+    }
+
+    #[test]
+    fn test_padding_chunks() {
+        let mut padding = chunk(&[0; 20]);
+        padding[..4].copy_from_slice(PADDING_MAGIC);
+
+        let mut data = chunk(b"first");
+        data.extend(&padding);
+        data.extend(&padding);
+        data.extend(chunk(b"second"));
+
+        let len = data.len() as u64;
+        for len_hint in [None, Some(len)] {
+            let mut reader =
+                CompressedTrainingDataFileReader::new(Cursor::new(data.clone()), len_hint).unwrap();
+            assert_eq!(read_all(&mut reader).unwrap(), [&b"first"[..], b"second"]);
+            assert_eq!(reader.chunks_read(), 2);
+            assert_eq!(reader.read_bytes(), len);
+        }
+
+        // padding cut short
+        let mut reader =
+            CompressedTrainingDataFileReader::new(Cursor::new(data[..30].to_vec()), None).unwrap();
+        assert!(matches!(
+            read_all(&mut reader),
+            Err(BinpackError::TruncatedChunk { .. })
+        ));
+    }
+
+    #[test]
+    fn test_annotation_chunks() {
+        let mut data = chunk(b"first");
+        data.extend(chunk(b"notes"));
+        data[CHUNK_MAGIC.len() + 4 + 5..][..4].copy_from_slice(annotation::MAGIC);
+        data.extend(chunk(b"second"));
+
+        let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(data.clone()), None)
+            .unwrap()
+            .with_dialect(FormatDialect::Auto, MAX_CHUNK_SIZE);
+        let mut buffer = Vec::new();
+
+        reader.read_next_chunk_into(&mut buffer).unwrap();
+        assert_eq!(buffer, b"first");
+        assert_eq!(reader.annotations(), None);
+
+        reader.read_next_chunk_into(&mut buffer).unwrap();
+        assert_eq!(buffer, b"second");
+        assert_eq!(reader.annotations(), Some(&b"notes"[..]));
+        assert_eq!(reader.chunks_read(), 2);
+        assert_eq!(reader.read_bytes(), data.len() as u64);
+        assert!(!reader.has_next_chunk());
+
+        // annotations of nothing
+        let mut reader = CompressedTrainingDataFileReader::new(
+            Cursor::new(data[..data.len() - 14].to_vec()),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            read_all(&mut reader),
+            Err(BinpackError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_auto_rejects_implausible_sizes() {
+        let mut data = chunk(b"first");
+        data.extend_from_slice(b"BINQ\x01\x00\x00\x00\x00");
+
+        // larger than the rest of the file, or than the cap
+        for len in [Some(data.len() as u64), None] {
+            let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(data.clone()), len)
+                .unwrap()
+                .with_dialect(FormatDialect::Auto, 1024);
+            assert!(matches!(
+                read_all(&mut reader),
+                Err(BinpackError::InvalidMagic)
+            ));
+            assert_eq!(reader.dialect(), FormatDialect::Auto);
+        }
+    }
+
+    #[test]
+    fn test_chunk_size_cap() {
+        let data = chunk(&[0; 100]);
+
+        let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(data.clone()), None)
+            .unwrap()
+            .with_dialect(FormatDialect::Standard, 99);
+        assert!(matches!(
+            read_all(&mut reader),
+            Err(BinpackError::InvalidFormat(_))
+        ));
+
+        let mut reader = CompressedTrainingDataFileReader::new(Cursor::new(data), None)
+            .unwrap()
+            .with_dialect(FormatDialect::Standard, 100);
+        assert_eq!(read_all(&mut reader).unwrap(), [[0; 100]]);
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let data = chunk(b"payload");
+
+        for len in [None, Some(5)] {
+            let truncated = Cursor::new(data[..5].to_vec());
+            let mut reader = CompressedTrainingDataFileReader::new(truncated, len).unwrap();
+
+            assert!(reader.has_next_chunk());
+            assert!(matches!(
+                reader.read_next_chunk_into(&mut Vec::new()),
+                Err(BinpackError::UnexpectedEof)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let data = chunk(b"payload");
+        let truncated = Cursor::new(data[..data.len() - 3].to_vec());
+
+        let mut reader = CompressedTrainingDataFileReader::new(truncated, None).unwrap();
+        let mut buffer = Vec::new();
+
+        assert!(matches!(
+            reader.read_next_chunk_into(&mut buffer),
+            Err(BinpackError::TruncatedChunk {
+                expected: 7,
+                got: 4
+            })
+        ));
+        assert_eq!(buffer, b"payl");
+    }
+
+    #[test]
+    fn test_io_error() {
+        // while peeking at the next header
+        let failing = Failing(Cursor::new(chunk(b"payload")));
+        let mut reader = CompressedTrainingDataFileReader::new(failing, None).unwrap();
+        let mut buffer = Vec::new();
+
+        reader.read_next_chunk_into(&mut buffer).unwrap();
+        assert!(reader.has_next_chunk());
+        assert!(matches!(
+            reader.read_next_chunk_into(&mut buffer),
+            Err(BinpackError::Io(e)) if e.kind() == io::ErrorKind::ConnectionReset
+        ));
+
+        // in the middle of a chunk
+        let data = chunk(b"payload");
+        let failing = Failing(Cursor::new(data[..10].to_vec()));
+        let mut reader = CompressedTrainingDataFileReader::new(failing, None).unwrap();
+
+        assert!(matches!(
+            reader.read_next_chunk_into(&mut buffer),
+            Err(BinpackError::Io(e)) if e.kind() == io::ErrorKind::ConnectionReset
+        ));
     }
 }
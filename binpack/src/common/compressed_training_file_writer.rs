@@ -1,6 +1,20 @@
-use std::io::Write;
+use std::io::{self, BufWriter, IoSlice, Write};
 
-const HEADER_SIZE: usize = 8;
+use crate::{annotation, checksum};
+
+/// The magic and the little endian size of the data that follows
+pub const CHUNK_HEADER_SIZE: usize = 8;
+/// Marks a chunk of entries
+pub const CHUNK_MAGIC: &[u8; 4] = b"BINP";
+
+/// Marks a chunk of zeros that only pads the next chunk to an alignment,
+/// see [`WriterOptions::align`](crate::WriterOptions::align). Not a chunk
+/// upstream readers know.
+pub const PADDING_MAGIC: &[u8; 4] = b"BINZ";
+
+/// Chunks up to this size are collected and written together, larger ones go
+/// to the file with a single vectored write of header and data
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Debug)]
 struct Header {
@@ -9,41 +23,243 @@ struct Header {
 
 #[derive(Debug)]
 pub struct CompressedTrainingDataFileWriter<T: Write> {
-    file: T,
+    file: BufWriter<T>,
+    checksums: bool,
+    align: u64,
+    /// Bytes written since the start, to find the next aligned offset
+    written: u64,
+    /// An extension chunk was just written, which stays in front of its
+    /// chunk without padding in between
+    after_annotations: bool,
 }
 
 impl<T: Write> CompressedTrainingDataFileWriter<T> {
-    pub fn new(file: T) -> std::io::Result<Self> {
-        Ok(Self { file })
+    /// Buffers up to `buffer_size` bytes of chunks before writing them to
+    /// `file`, a size of zero writes every chunk right away
+    pub fn with_buffer_size(file: T, buffer_size: usize) -> std::io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::with_capacity(buffer_size, file),
+            checksums: false,
+            align: 0,
+            written: 0,
+            after_annotations: false,
+        })
     }
 
+    /// Ends every chunk with a CRC32 trailer, see [`crate::checksum`]
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Starts every chunk, or the extension chunk in front of it, at a
+    /// multiple of `align` bytes from the start, 0 or 1 for no padding
+    pub fn with_alignment(mut self, align: usize) -> Self {
+        self.align = align as u64;
+        self
+    }
+
+    /// Writes out the buffered chunks and returns the file
     pub fn into_inner(self) -> std::io::Result<T> {
-        Ok(self.file)
+        self.file
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
     }
 
     pub fn append(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if !self.after_annotations {
+            self.pad()?;
+        }
+        self.after_annotations = false;
+        self.append_chunk(CHUNK_MAGIC, data, self.checksums)
+    }
+
+    /// Appends the extension chunk of the annotations of the next chunk,
+    /// see [`crate::annotation`]
+    pub fn append_annotations(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.pad()?;
+        self.after_annotations = true;
+        self.append_chunk(annotation::MAGIC, data, self.checksums)
+    }
+
+    /// Appends a padding chunk up to the next aligned offset, or the one
+    /// after it if the header does not fit in between
+    fn pad(&mut self) -> std::io::Result<()> {
+        if self.align <= 1 || self.written.is_multiple_of(self.align) {
+            return Ok(());
+        }
+
+        let mut padding = self.align - self.written % self.align;
+        while padding < CHUNK_HEADER_SIZE as u64 {
+            padding += self.align;
+        }
+
+        let zeros = vec![0u8; padding as usize - CHUNK_HEADER_SIZE];
+        self.append_chunk(PADDING_MAGIC, &zeros, false)
+    }
+
+    fn append_chunk(
+        &mut self,
+        magic: &[u8; 4],
+        data: &[u8],
+        checksum: bool,
+    ) -> std::io::Result<()> {
+        let trailer = checksum.then(|| checksum::trailer(data));
+        let trailer = trailer.as_ref().map_or(&[][..], |trailer| &trailer[..]);
+
         let header = Header {
-            chunk_size: data.len() as u32,
+            chunk_size: (data.len() + trailer.len()) as u32,
         };
-        self.write_chunk_header(&header)?;
-        self.file.write_all(data)?;
-        Ok(())
+        let header = Self::chunk_header(magic, &header);
+        let chunk_len = CHUNK_HEADER_SIZE + data.len() + trailer.len();
+        self.written += chunk_len as u64;
+
+        if chunk_len > self.file.capacity() - self.file.buffer().len() {
+            self.flush()?;
+        }
+
+        if chunk_len <= self.file.capacity() {
+            self.file.write_all(&header)?;
+            self.file.write_all(data)?;
+            self.file.write_all(trailer)
+        } else {
+            // too large for the buffer, header and data go to the file in
+            // one call
+            write_all_vectored(
+                self.file.get_mut(),
+                &mut [
+                    IoSlice::new(&header),
+                    IoSlice::new(data),
+                    IoSlice::new(trailer),
+                ],
+            )
+        }
     }
 
-    fn write_chunk_header(&mut self, header: &Header) -> std::io::Result<()> {
-        let mut buf = [0u8; HEADER_SIZE];
-        buf[0] = b'B';
-        buf[1] = b'I';
-        buf[2] = b'N';
-        buf[3] = b'P';
+    fn chunk_header(magic: &[u8; 4], header: &Header) -> [u8; CHUNK_HEADER_SIZE] {
+        let mut buf = [0u8; CHUNK_HEADER_SIZE];
+        buf[..4].copy_from_slice(magic);
         buf[4] = (header.chunk_size & 0xFF) as u8;
         buf[5] = ((header.chunk_size >> 8) & 0xFF) as u8;
         buf[6] = ((header.chunk_size >> 16) & 0xFF) as u8;
         buf[7] = ((header.chunk_size >> 24) & 0xFF) as u8;
-        self.file.write_all(&buf)
+        buf
     }
 
     pub fn flush(&mut self) -> std::io::Result<()> {
+        trace_span!("flush", bytes = self.file.buffer().len());
         self.file.flush()
     }
 }
+
+/// `Write::write_all_vectored` is not stable yet
+fn write_all_vectored(file: &mut impl Write, mut bufs: &mut [IoSlice]) -> std::io::Result<()> {
+    while !bufs.is_empty() {
+        match file.write_vectored(bufs) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole chunk",
+                ))
+            }
+            Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts the calls that reach the file
+    #[derive(Debug, Default)]
+    struct CountingFile {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for CountingFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.writes += 1;
+            for buf in bufs {
+                self.data.extend_from_slice(buf);
+            }
+            Ok(bufs.iter().map(|buf| buf.len()).sum())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_small_chunks_are_buffered() {
+        let mut writer = CompressedTrainingDataFileWriter::with_buffer_size(
+            CountingFile::default(),
+            DEFAULT_BUFFER_SIZE,
+        )
+        .unwrap();
+
+        for idx in 0..100u8 {
+            writer.append(&[idx; 10]).unwrap();
+        }
+
+        let file = writer.into_inner().unwrap();
+        assert_eq!(file.writes, 1);
+        assert_eq!(file.data.len(), 100 * (CHUNK_HEADER_SIZE + 10));
+        assert_eq!(&file.data[..CHUNK_HEADER_SIZE], b"BINP\x0A\0\0\0");
+        assert_eq!(
+            &file.data[CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + 10],
+            &[0; 10]
+        );
+    }
+
+    #[test]
+    fn test_alignment() {
+        let mut writer =
+            CompressedTrainingDataFileWriter::with_buffer_size(Vec::new(), DEFAULT_BUFFER_SIZE)
+                .unwrap()
+                .with_alignment(64);
+
+        writer.append(&[1; 10]).unwrap();
+        writer.append(&[2; 52]).unwrap();
+        writer.append_annotations(&[3; 4]).unwrap();
+        writer.append(&[4; 4]).unwrap();
+        let data = writer.into_inner().unwrap();
+
+        // the second chunk leaves no room for a padding header before 128
+        assert_eq!(&data[18..22], PADDING_MAGIC);
+        assert_eq!(&data[22..26], &38u32.to_le_bytes());
+        assert_eq!(&data[64..68], CHUNK_MAGIC);
+        assert_eq!(&data[124..128], PADDING_MAGIC);
+        assert_eq!(&data[192..196], annotation::MAGIC);
+        // no padding between the annotations and their chunk
+        assert_eq!(&data[204..208], CHUNK_MAGIC);
+        assert_eq!(data.len(), 216);
+    }
+
+    #[test]
+    fn test_large_chunk_is_one_write() {
+        let mut writer =
+            CompressedTrainingDataFileWriter::with_buffer_size(CountingFile::default(), 16)
+                .unwrap();
+
+        writer.append(&[1; 3]).unwrap();
+        writer.append(&[2; 100]).unwrap();
+
+        let file = writer.into_inner().unwrap();
+        // the buffered chunk, then header and data of the large one together
+        assert_eq!(file.writes, 2);
+        assert_eq!(&file.data[11..CHUNK_HEADER_SIZE + 11], b"BINP\x64\0\0\0");
+        assert_eq!(file.data.len(), 2 * CHUNK_HEADER_SIZE + 103);
+    }
+}
@@ -1,35 +1,235 @@
-use std::fmt;
+use alloc::{format, string::String};
+use core::fmt;
 
-use crate::chess::{position::Position, r#move::Move};
+use crate::chess::{coords::Square, position::Position, r#move::Move, san};
 
 use super::{
     arithmetic::{signed_to_unsigned, unsigned_to_signed},
     compressed_move::CompressedMove,
-    compressed_position::CompressedPosition,
+    compressed_position::{CompressedPosition, CompressedPositionError},
+    values::{GameResult, Ply, Score, ValueError},
 };
 
+/// Offset of the ply and result in a packed entry, after the position, move
+/// and score
+const PLY_RESULT_OFFSET: usize = 28;
+
 /// A single training data entry.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct TrainingDataEntry {
     /// The position of the board.
     pub pos: Position,
     /// The which will be played on this position.
     pub mv: Move,
     /// The score of the position. Relative to the side to move of the current position.
+    /// [`white_relative_score`](Self::white_relative_score) gives white's point of view.
     pub score: i16,
     /// The game ply of the position.
     pub ply: u16,
     /// The game result of the position.
     /// 1, 0, -1 for win, draw, loss for the side to move (like with score).
+    /// [`game_result`](Self::game_result) reads it as a [`GameResult`].
     pub result: i16,
 }
 
 impl TrainingDataEntry {
+    /// Builds an entry from values the format can store
+    pub fn new(pos: Position, mv: Move, score: Score, ply: Ply, result: GameResult) -> Self {
+        Self {
+            pos,
+            mv,
+            score: score.into(),
+            ply: ply.into(),
+            result: result.into(),
+        }
+    }
+
+    /// Builds an entry from a score and result from white's point of view,
+    /// as most engines outside of Stockfish's tools and PGN results have
+    /// them, converting both to the side to move of `pos`
+    pub fn from_white_relative(
+        pos: Position,
+        mv: Move,
+        score: Score,
+        ply: Ply,
+        result: GameResult,
+    ) -> Self {
+        let stm = pos.side_to_move();
+        Self::new(
+            pos,
+            mv,
+            score.to_white_relative(stm),
+            ply,
+            result.to_white_relative(stm),
+        )
+    }
+
+    /// The result for the side to move, an error if the raw field holds
+    /// anything but -1, 0 or 1
+    pub fn game_result(&self) -> Result<GameResult, ValueError> {
+        GameResult::try_from(self.result)
+    }
+
+    pub fn set_game_result(&mut self, result: GameResult) {
+        self.result = result.into();
+    }
+
+    /// The result for white, see [`GameResult::to_white_relative`]
+    pub fn white_result(&self) -> Result<GameResult, ValueError> {
+        Ok(self
+            .game_result()?
+            .to_white_relative(self.pos.side_to_move()))
+    }
+
+    /// The score from white's point of view, `VALUE_NONE` stays as it is,
+    /// see [`Score::to_white_relative`]
+    pub fn white_relative_score(&self) -> i16 {
+        Score::new(self.score)
+            .to_white_relative(self.pos.side_to_move())
+            .get()
+    }
+
+    /// Checks that the result and ply fit the format, the writer refuses
+    /// entries that do not
+    pub fn validate(&self) -> Result<(), ValueError> {
+        self.game_result()?;
+        Ply::try_from(self.ply)?;
+        Ok(())
+    }
+
+    /// Placeholder for an entry that is about to be overwritten, cheaper to
+    /// build than default() which sets up the start position
+    pub(crate) fn empty() -> Self {
+        Self {
+            pos: Position::empty(),
+            mv: Move::null(),
+            score: 0,
+            ply: 0,
+            result: 0,
+        }
+    }
+
     pub fn is_continuation(&self, &other: &TrainingDataEntry) -> bool {
         self.result == -other.result
             && self.ply + 1 == other.ply
             && self.pos.after_move(self.mv) == other.pos
     }
+
+    /// The fullmove number of the entry's ply, the one a FEN of the
+    /// position at that ply has
+    fn fullmove(&self) -> u16 {
+        let stm = self.pos.side_to_move() as u16;
+        self.ply.saturating_sub(stm) / 2 + 1
+    }
+
+    /// The position as a FEN with the counters of the entry: the rule50
+    /// counter as stored and the fullmove number of `ply`, rather than the
+    /// ones `pos.fen()` has after unpacking
+    pub fn to_extended_fen(&self) -> Option<String> {
+        let fen = self.pos.fen().ok()?;
+        let fields = fen.rsplitn(3, ' ').last()?;
+        Some(format!(
+            "{} {} {}",
+            fields,
+            self.pos.rule50_counter(),
+            self.fullmove()
+        ))
+    }
+
+    /// The entry as an EPD line, the first four fields of the FEN followed by
+    /// the opcodes `hmvc` and `fmvn` for the counters, `sm` for the move in
+    /// SAN, `ce` for the score, relative to the side to move, and `c9` for
+    /// the result from white's point of view as in PGN:
+    ///
+    /// ```text
+    /// 1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - hmvc 0; fmvn 35; sm c4+; ce -201; c9 "1/2-1/2";
+    /// ```
+    ///
+    /// [`from_epd`](Self::from_epd) reads it back into the same entry as long
+    /// as the ply has the parity of the side to move, as it does for every
+    /// position of a game.
+    pub fn to_epd(&self) -> Option<String> {
+        let fen = self.pos.fen().ok()?;
+        let fields = fen.rsplitn(3, ' ').last()?;
+        let result = match self.white_result().ok()? {
+            GameResult::Win => "1-0",
+            GameResult::Loss => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        };
+        let mut epd = format!(
+            "{} hmvc {}; fmvn {};",
+            fields,
+            self.pos.rule50_counter(),
+            self.fullmove()
+        );
+        if self.mv != Move::null() {
+            epd.push_str(&format!(" sm {};", san::to_san(&self.pos, self.mv)));
+        }
+        epd.push_str(&format!(" ce {}; c9 \"{}\";", self.score, result));
+        Some(epd)
+    }
+
+    /// Parses an EPD line like the ones [`to_epd`](Self::to_epd) writes.
+    /// `sm` also takes a move in UCI notation. The counters default to 0
+    /// and 1, the score and the result to 0 and the move to none when their
+    /// opcodes are missing; other opcodes are ignored. Returns None for a
+    /// malformed position, an illegal move or an unknown result.
+    pub fn from_epd(line: &str) -> Option<Self> {
+        let mut fields = line.trim().splitn(5, ' ');
+        let (board, stm, castling, ep) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        if !matches!(stm, "w" | "b") || (ep != "-" && Square::from_string(ep).is_none()) {
+            return None;
+        }
+
+        let mut rule50 = 0u16;
+        let mut fullmove = 1u16;
+        let mut mv = None;
+        let mut score = 0i16;
+        let mut white_result = GameResult::Draw;
+        for operation in fields.next().unwrap_or("").split(';') {
+            let (opcode, operand) = operation.trim().split_once(' ').unwrap_or((operation, ""));
+            let operand = operand.trim();
+            match opcode.trim() {
+                "hmvc" => rule50 = operand.parse().ok()?,
+                "fmvn" => fullmove = operand.parse().ok().filter(|&n| n > 0)?,
+                "sm" => mv = Some(operand),
+                "ce" => score = operand.parse().ok()?,
+                "c9" => {
+                    white_result = match operand.trim_matches('"') {
+                        "1-0" => GameResult::Win,
+                        "0-1" => GameResult::Loss,
+                        "1/2-1/2" => GameResult::Draw,
+                        _ => return None,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut pos =
+            Position::from_fen(&format!("{board} {stm} {castling} {ep} {rule50} 1")).ok()?;
+        let mv = match mv {
+            Some(text) => san::parse_san(&pos, text).or_else(|| Move::from_uci(&pos, text))?,
+            None => Move::null(),
+        };
+        let stm = pos.side_to_move();
+        let ply = (fullmove - 1).checked_mul(2)?.checked_add(stm as u16)?;
+        // the counter the position has after unpacking
+        pos.set_ply(ply);
+
+        Some(Self {
+            pos,
+            mv,
+            score,
+            ply,
+            result: white_result.to_white_relative(stm).into(),
+        })
+    }
 }
 
 impl fmt::Display for TrainingDataEntry {
@@ -46,6 +246,11 @@ impl fmt::Display for TrainingDataEntry {
     }
 }
 
+/// The stem of a chain as it is stored: position, move, score, ply and
+/// result and rule50 counter in 32 bytes.
+///
+/// The fields other than the position are read without unpacking the
+/// entry, which decompresses the position.
 #[derive(Debug, Default, Clone)]
 pub struct PackedTrainingDataEntry {
     pub data: [u8; 32],
@@ -60,50 +265,82 @@ impl PackedTrainingDataEntry {
     }
 
     pub fn byte_size() -> usize {
-        std::mem::size_of::<PackedTrainingDataEntry>()
+        core::mem::size_of::<PackedTrainingDataEntry>()
     }
 
     pub fn unpack_entry(&self) -> TrainingDataEntry {
+        let mut entry = TrainingDataEntry::empty();
+        self.unpack_entry_into(&mut entry);
+        entry
+    }
+
+    /// Like unpack_entry(), but fails on a position whose nibbles
+    /// [`CompressedPosition::validate`] rejects
+    pub fn try_unpack_entry(&self) -> Result<TrainingDataEntry, CompressedPositionError> {
+        self.compressed_position().validate()?;
+        Ok(self.unpack_entry())
+    }
+
+    /// Like unpack_entry(), but decodes into `entry` instead of returning a new one
+    pub fn unpack_entry_into(&self, entry: &mut TrainingDataEntry) {
         let mut offset = 0;
 
         // Read and decompress position
         // EBNF: Position
         let compressed_pos = CompressedPosition::read_from_big_endian(&self.data[offset..]);
-        let mut pos = compressed_pos.decompress();
+        entry.pos = compressed_pos.decompress();
         offset += CompressedPosition::byte_size();
 
         // Read and decompress move
         // EBNF: Move
         let compressed_move = CompressedMove::read_from_big_endian(&self.data[offset..]);
-        let mv = compressed_move.decompress();
+        entry.mv = compressed_move.decompress();
         offset += CompressedMove::byte_size();
 
         // Read score
         // EBNF: Score
-        let score = unsigned_to_signed(self.read_u16_be(offset));
+        entry.score = unsigned_to_signed(self.read_u16_be(offset));
         offset += 2;
 
         // Read ply and result (packed together)
         // EBNF: PlyResult
         let pr = self.read_u16_be(offset);
-        let ply = pr & 0x3FFF;
-        let result = unsigned_to_signed(pr >> 14);
+        entry.ply = pr & 0x3FFF;
+        entry.result = unsigned_to_signed(pr >> 14);
         offset += 2;
 
         // Set position's ply
-        pos.set_ply(ply);
+        entry.pos.set_ply(entry.ply);
 
         // Read and set rule50 counter
         // EBNF: Rule50
-        pos.set_rule50_counter(self.read_u16_be(offset));
+        entry.pos.set_rule50_counter(self.read_u16_be(offset));
+    }
 
-        TrainingDataEntry {
-            pos,
-            mv,
-            score,
-            ply,
-            result,
-        }
+    /// The position, still compressed
+    pub fn compressed_position(&self) -> CompressedPosition {
+        CompressedPosition::read_from_big_endian(&self.data)
+    }
+
+    pub fn mv(&self) -> Move {
+        CompressedMove::read_from_big_endian(&self.data[CompressedPosition::byte_size()..])
+            .decompress()
+    }
+
+    pub fn score(&self) -> i16 {
+        unsigned_to_signed(self.read_u16_be(PLY_RESULT_OFFSET - 2))
+    }
+
+    pub fn ply(&self) -> u16 {
+        self.read_u16_be(PLY_RESULT_OFFSET) & 0x3FFF
+    }
+
+    pub fn result(&self) -> i16 {
+        unsigned_to_signed(self.read_u16_be(PLY_RESULT_OFFSET) >> 14)
+    }
+
+    pub fn rule50_counter(&self) -> u16 {
+        self.read_u16_be(PLY_RESULT_OFFSET + 2)
     }
 
     pub fn from_entry(entry: &TrainingDataEntry) -> Self {
@@ -180,6 +417,148 @@ mod test {
         };
 
         assert_eq!(entry, expected);
+
+        // the fields without unpacking
+        assert_eq!(packed_entry.mv(), expected.mv);
+        assert_eq!(packed_entry.score(), -127);
+        assert_eq!(packed_entry.ply(), 39);
+        assert_eq!(packed_entry.result(), 0);
+        assert_eq!(packed_entry.rule50_counter(), 2);
+        assert_eq!(
+            packed_entry.compressed_position(),
+            CompressedPosition::compress(&expected.pos)
+        );
+    }
+
+    #[test]
+    fn test_large_rule50_counter() {
+        for counter in [99, 255, 256, 1000, u16::MAX] {
+            let mut pos = Position::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 200").unwrap();
+            pos.set_rule50_counter(counter);
+            let entry = TrainingDataEntry {
+                pos,
+                mv: Move::from_uci(&pos, "h1h2").unwrap(),
+                score: 0,
+                ply: pos.ply(),
+                result: 0,
+            };
+
+            let unpacked = PackedTrainingDataEntry::from_entry(&entry).unpack_entry();
+            assert_eq!(unpacked.pos.rule50_counter(), counter);
+            assert_eq!(unpacked, entry);
+        }
+    }
+
+    #[test]
+    fn test_game_result() {
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K2R b - - 0 1").unwrap();
+        let mut entry = TrainingDataEntry {
+            pos,
+            mv: Move::from_uci(&pos, "e8d8").unwrap(),
+            score: 0,
+            ply: 1,
+            result: -1,
+        };
+        assert_eq!(entry.game_result(), Ok(GameResult::Loss));
+        assert_eq!(entry.white_result(), Ok(GameResult::Win));
+
+        entry.set_game_result(GameResult::Win);
+        assert_eq!(entry.result, 1);
+        assert_eq!(entry.white_result(), Ok(GameResult::Loss));
+
+        // the packed bits are the ones of GameResult::to_packed
+        let packed = PackedTrainingDataEntry::from_entry(&entry);
+        let offset = CompressedPosition::byte_size() + CompressedMove::byte_size() + 2;
+        let pr = packed.read_u16_be(offset);
+        assert_eq!(GameResult::from_packed(pr >> 14), Ok(GameResult::Win));
+
+        entry.result = 2;
+        assert_eq!(entry.game_result(), Err(ValueError::Result(2)));
+    }
+
+    #[test]
+    fn test_white_relative() {
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K2R b - - 0 1").unwrap();
+        let mv = Move::from_uci(&pos, "e8d8").unwrap();
+
+        // white is winning by a rook, black to move
+        let entry = TrainingDataEntry::from_white_relative(
+            pos,
+            mv,
+            Score::new(500),
+            Ply::new(1).unwrap(),
+            GameResult::Win,
+        );
+        assert_eq!((entry.score, entry.result), (-500, -1));
+        assert_eq!(entry.white_relative_score(), 500);
+        assert_eq!(entry.white_result(), Ok(GameResult::Win));
+
+        let unscored = TrainingDataEntry {
+            score: crate::common::values::VALUE_NONE,
+            ..entry
+        };
+        assert_eq!(
+            unscored.white_relative_score(),
+            crate::common::values::VALUE_NONE
+        );
+    }
+
+    #[test]
+    fn test_epd() {
+        let pos =
+            Position::from_fen("1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 7 35").unwrap();
+        let entry = TrainingDataEntry {
+            pos,
+            mv: Move::from_uci(&pos, "c2c4").unwrap(),
+            score: -201,
+            ply: 68,
+            result: -1,
+        };
+
+        let epd = entry.to_epd().unwrap();
+        assert_eq!(
+            epd,
+            "1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - hmvc 7; fmvn 35; sm c4+; ce -201; c9 \"0-1\";"
+        );
+        assert_eq!(TrainingDataEntry::from_epd(&epd), Some(entry));
+
+        // the counters come from the entry, not from the unpacked position
+        let unpacked = PackedTrainingDataEntry::from_entry(&entry).unpack_entry();
+        let black = TrainingDataEntry {
+            pos: unpacked.pos.after_move(unpacked.mv),
+            mv: Move::null(),
+            ply: 69,
+            result: 1,
+            ..unpacked
+        };
+        assert_eq!(
+            black.to_extended_fen().unwrap(),
+            "1q5b/1r5k/4p2p/1b2P1pN/2Pp4/6PP/1n4B1/1Q2B1K1 b - - 0 35"
+        );
+        assert_eq!(
+            TrainingDataEntry::from_epd(&black.to_epd().unwrap()),
+            Some(black)
+        );
+
+        // moves in UCI, missing and unknown opcodes
+        let parsed = TrainingDataEntry::from_epd(
+            "1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - sm c2c4; id \"test\";",
+        )
+        .unwrap();
+        assert_eq!((parsed.mv, parsed.ply, parsed.score), (entry.mv, 0, 0));
+
+        assert_eq!(
+            TrainingDataEntry::from_epd("4k3/8/8/8/8/8/8/4K3 x - -"),
+            None
+        );
+        assert_eq!(
+            TrainingDataEntry::from_epd("4k3/8/8/8/8/8/8/4K3 w - - c9 \"*\";"),
+            None
+        );
+        assert_eq!(
+            TrainingDataEntry::from_epd("4k3/8/8/8/8/8/8/4K3 w - - sm e2e4;"),
+            None
+        );
     }
 
     #[test]
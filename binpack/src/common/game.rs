@@ -0,0 +1,108 @@
+use crate::chess::{attacks, color::Color, position::Position, r#move::Move, san};
+
+use super::entry::TrainingDataEntry;
+
+/// The entries of one chain of continuations, as returned by
+/// [`next_game`](crate::CompressedTrainingDataEntryReader::next_game).
+///
+/// A game starts wherever the writer started a chain, which is the start of
+/// the game as played when the whole game was written in order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub entries: Vec<TrainingDataEntry>,
+}
+
+impl Game {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The position the first move is played from
+    pub fn start_pos(&self) -> Option<&Position> {
+        self.entries.first().map(|entry| &entry.pos)
+    }
+
+    pub fn moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.entries.iter().map(|entry| entry.mv)
+    }
+
+    /// The moves in UCI separated by spaces, e.g. `e2e4 e7e5 g1f3`
+    pub fn to_uci_line(&self) -> String {
+        self.moves()
+            .map(|mv| mv.as_uci())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The moves in SAN with move numbers, played from `start_pos`, e.g.
+    /// `1. e4 e5 2. Nf3` or `12... Kd7 13. Re1+`. The numbers come from the
+    /// ply of `start_pos`. Ends before the first move that is not legal
+    /// where it is played, so `start_pos` should be the position of the
+    /// first entry, see [`start_pos`](Self::start_pos).
+    pub fn to_san_line(&self, start_pos: &Position) -> String {
+        let mut pos = *start_pos;
+        let mut tokens = Vec::with_capacity(self.len() * 3 / 2 + 1);
+
+        for (idx, mv) in self.moves().enumerate() {
+            if !attacks::is_legal(&pos, mv) {
+                break;
+            }
+
+            let fullmove = pos.ply() / 2 + 1;
+            if pos.side_to_move() == Color::White {
+                tokens.push(format!("{}.", fullmove));
+            } else if idx == 0 {
+                tokens.push(format!("{}...", fullmove));
+            }
+
+            tokens.push(san::to_san(&pos, mv));
+            pos.do_move(mv);
+        }
+
+        tokens.join(" ")
+    }
+}
+
+impl From<Vec<TrainingDataEntry>> for Game {
+    fn from(entries: Vec<TrainingDataEntry>) -> Self {
+        Self { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_lines() {
+        let mut pos =
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+                .unwrap();
+        let start = pos;
+        let mut game = Game::default();
+        for uci in ["e7e5", "g1f3", "b8c6", "f1b5"] {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            game.entries.push(TrainingDataEntry {
+                pos,
+                mv,
+                ply: pos.ply(),
+                ..Default::default()
+            });
+            pos.do_move(mv);
+        }
+
+        assert_eq!(game.to_uci_line(), "e7e5 g1f3 b8c6 f1b5");
+        assert_eq!(game.to_san_line(&start), "1... e5 2. Nf3 Nc6 3. Bb5");
+
+        // played from the wrong position the line ends where a move is not legal
+        let other =
+            Position::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2")
+                .unwrap();
+        assert_eq!(game.to_san_line(&other), "");
+        assert_eq!(Game::default().to_uci_line(), "");
+    }
+}
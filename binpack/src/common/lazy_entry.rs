@@ -0,0 +1,86 @@
+use std::sync::OnceLock;
+
+use crate::chess::{position::Position, r#move::Move};
+
+use super::entry::{PackedTrainingDataEntry, TrainingDataEntry};
+
+/// An entry whose position is only decompressed when it is first asked
+/// for, see [`next_lazy`](crate::CompressedTrainingDataEntryReader::next_lazy).
+///
+/// The score, ply, result and move of a stem are read straight from its
+/// packed form. Entries in the movetext of a chain are decoded by playing
+/// the moves before them, so they always come with their position.
+#[derive(Debug, Clone)]
+pub struct LazyEntry {
+    /// The stem as stored, None for an entry that was decoded right away
+    packed: Option<PackedTrainingDataEntry>,
+    entry: OnceLock<TrainingDataEntry>,
+}
+
+impl LazyEntry {
+    /// An entry to be unpacked when its position is needed
+    pub fn packed(packed: PackedTrainingDataEntry) -> Self {
+        Self {
+            packed: Some(packed),
+            entry: OnceLock::new(),
+        }
+    }
+
+    /// The packed form if the entry was not unpacked yet
+    fn still_packed(&self) -> Option<&PackedTrainingDataEntry> {
+        self.packed.as_ref().filter(|_| self.entry.get().is_none())
+    }
+
+    pub fn score(&self) -> i16 {
+        self.still_packed()
+            .map_or_else(|| self.entry().score, PackedTrainingDataEntry::score)
+    }
+
+    pub fn ply(&self) -> u16 {
+        self.still_packed()
+            .map_or_else(|| self.entry().ply, PackedTrainingDataEntry::ply)
+    }
+
+    pub fn result(&self) -> i16 {
+        self.still_packed()
+            .map_or_else(|| self.entry().result, PackedTrainingDataEntry::result)
+    }
+
+    pub fn mv(&self) -> Move {
+        self.still_packed()
+            .map_or_else(|| self.entry().mv, PackedTrainingDataEntry::mv)
+    }
+
+    /// The position, decompressed on the first call
+    pub fn pos(&self) -> &Position {
+        &self.entry().pos
+    }
+
+    /// The whole entry, unpacked on the first call
+    pub fn entry(&self) -> &TrainingDataEntry {
+        self.entry.get_or_init(|| {
+            self.packed
+                .as_ref()
+                .expect("a lazy entry is either packed or decoded")
+                .unpack_entry()
+        })
+    }
+
+    pub fn into_entry(self) -> TrainingDataEntry {
+        *self.entry()
+    }
+
+    /// Whether the entry is unpacked, entries from movetext always are
+    pub fn is_decoded(&self) -> bool {
+        self.entry.get().is_some()
+    }
+}
+
+impl From<TrainingDataEntry> for LazyEntry {
+    fn from(entry: TrainingDataEntry) -> Self {
+        Self {
+            packed: None,
+            entry: OnceLock::from(entry),
+        }
+    }
+}
@@ -1,7 +1,23 @@
 pub mod arithmetic;
+#[cfg(feature = "std")]
 pub mod binpack_error;
+pub mod bitreader;
+pub mod bitwriter;
+#[cfg(feature = "std")]
+pub mod buffer_pool;
 pub mod compressed_move;
 pub mod compressed_position;
+#[cfg(feature = "std")]
 pub mod compressed_training_file_reader;
+#[cfg(feature = "std")]
 pub mod compressed_training_file_writer;
 pub mod entry;
+#[cfg(feature = "std")]
+pub mod game;
+#[cfg(feature = "std")]
+pub mod lazy_entry;
+pub mod move_score_list;
+pub mod move_score_list_reader;
+#[cfg(feature = "unsafe-opt")]
+pub mod simd;
+pub mod values;
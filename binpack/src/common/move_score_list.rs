@@ -26,6 +26,12 @@ pub struct PackedMoveScoreList {
     last_score: i16,
 }
 
+impl Default for PackedMoveScoreList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PackedMoveScoreList {
     pub fn new() -> Self {
         Self {
@@ -38,7 +44,7 @@ impl PackedMoveScoreList {
     pub fn clear(&mut self, e: &TrainingDataEntry) {
         self.num_plies = 0;
         self.writer.clear();
-        self.last_score = -e.score;
+        self.last_score = e.score.wrapping_neg();
     }
 
     pub fn movetext(&self) -> &[u8] {
@@ -65,7 +71,7 @@ impl PackedMoveScoreList {
         self.writer
             .add_bits_vle16(score_delta, SCORE_VLE_BLOCK_SIZE);
 
-        self.last_score = -score;
+        self.last_score = score.wrapping_neg();
 
         self.num_plies += 1;
     }
@@ -23,16 +23,20 @@ pub struct PackedMoveScoreListReader {
     num_plies: u16,
     num_read_plies: u16,
     entry: TrainingDataEntry,
+    /// Set once a move index pointed past the pieces or moves it chooses
+    /// from, the movetext is corrupt
+    corrupt: bool,
 }
 
 impl PackedMoveScoreListReader {
-    pub fn new(entry: TrainingDataEntry, movetext: *const u8, num_plies: u16) -> Self {
+    pub fn new(entry: TrainingDataEntry, num_plies: u16) -> Self {
         Self {
-            reader: BitReader::new(movetext),
+            reader: BitReader::new(),
             num_plies,
             entry,
             num_read_plies: 0,
-            last_score: -entry.score,
+            last_score: entry.score.wrapping_neg(),
+            corrupt: false,
         }
     }
 
@@ -40,19 +44,47 @@ impl PackedMoveScoreListReader {
         self.num_read_plies < self.num_plies
     }
 
-    // Get the next TrainingDataEntry from the movetext
-    pub fn next_entry(&mut self) -> TrainingDataEntry {
+    /// Whether the movetext decoded so far is not something the writer
+    /// produces, the entries are then meaningless
+    pub fn is_corrupt(&self) -> bool {
+        self.corrupt || self.reader.is_overlong()
+    }
+
+    /// Number of entries left in the movetext
+    pub fn remaining(&self) -> usize {
+        (self.num_plies - self.num_read_plies) as usize
+    }
+
+    // Get the next TrainingDataEntry from the movetext, which starts right
+    // after the stem and runs at most to the end of the chunk
+    pub fn next_entry(&mut self, movetext: &[u8]) -> TrainingDataEntry {
+        self.advance(movetext);
+        self.entry
+    }
+
+    // Like next_entry(), but copies the entry into `entry`
+    pub fn next_entry_into(&mut self, movetext: &[u8], entry: &mut TrainingDataEntry) {
+        self.advance(movetext);
+        *entry = self.entry;
+    }
+
+    // Like next_entry(), but returns the entry by reference instead of a copy
+    pub fn next_entry_ref(&mut self, movetext: &[u8]) -> &TrainingDataEntry {
+        self.advance(movetext);
+        &self.entry
+    }
+
+    fn advance(&mut self, movetext: &[u8]) {
         self.entry.pos.do_move(self.entry.mv);
-        let (mv, score) = self.next_move_score();
+        let (mv, score) = self.next_move_score(movetext);
         self.entry.mv = mv;
         self.entry.score = score;
-        self.entry.ply += 1;
+        self.entry.ply = self.entry.ply.wrapping_add(1);
         self.entry.result = -self.entry.result;
-        self.entry
     }
 
     // Read a move and score from the movetext
-    pub fn next_move_score(&mut self) -> (Move, i16) {
+    pub fn next_move_score(&mut self, movetext: &[u8]) -> (Move, i16) {
         // if !self.has_next() {
         //     return Ok(None);
         // }
@@ -66,15 +98,15 @@ impl PackedMoveScoreListReader {
 
         let piece_id = self
             .reader
-            .extract_bits_le8(used_bits_safe(our_pieces.count() as u64));
+            .extract_bits_le8(movetext, used_bits_safe(our_pieces.count() as u64));
 
         // Extract the move
-        let move_ = self.decode_move(piece_id, occupied);
+        let move_ = self.decode_move(movetext, piece_id, occupied);
 
         // Extract the score
-        let score = self.decode_score();
+        let score = self.decode_score(movetext);
 
-        self.last_score = -score;
+        self.last_score = score.wrapping_neg();
 
         self.num_read_plies += 1;
 
@@ -82,20 +114,22 @@ impl PackedMoveScoreListReader {
     }
 
     // EBNF: EncodedMove
-    fn decode_score(&mut self) -> i16 {
+    fn decode_score(&mut self, movetext: &[u8]) -> i16 {
         const SCORE_VLE_BLOCK_SIZE: usize = 4;
-        let delta = unsigned_to_signed(self.reader.extract_vle16(SCORE_VLE_BLOCK_SIZE));
+        let delta = unsigned_to_signed(self.reader.extract_vle16(movetext, SCORE_VLE_BLOCK_SIZE));
 
         self.last_score.wrapping_add(delta)
     }
 
     // EBNF: EncodedScore
-    fn decode_move(&mut self, piece_id: u8, occupied: Bitboard) -> Move {
+    fn decode_move(&mut self, movetext: &[u8], piece_id: u8, occupied: Bitboard) -> Move {
         let pos = &self.entry.pos;
 
         let side_to_move = pos.side_to_move();
         let our_pieces = pos.pieces_bb(side_to_move);
-        let idx = nth_set_bit_index(our_pieces.bits(), piece_id as u64);
+        let Some(idx) = checked_nth_set_bit(&mut self.corrupt, our_pieces, piece_id as u64) else {
+            return Move::null();
+        };
 
         let from = Square::new(idx);
 
@@ -134,22 +168,30 @@ impl PackedMoveScoreListReader {
                 let destinations_count = destinations.count();
 
                 if from.rank() == promotion_rank {
-                    let move_id = self
-                        .reader
-                        .extract_bits_le8(used_bits_safe((destinations_count * 4) as u64));
-                    let pt =
-                        PieceType::from_ordinal(PieceType::Knight.ordinal() + (move_id % 4) as u8);
+                    let move_id = self.reader.extract_bits_le8(
+                        movetext,
+                        used_bits_safe((destinations_count * 4) as u64),
+                    );
+                    let pt = PieceType::from_ordinal(PieceType::Knight.ordinal() + (move_id % 4));
                     let promoted_piece = Piece::new(pt, side_to_move);
-                    let to =
-                        Square::new(nth_set_bit_index(destinations.bits(), move_id as u64 / 4));
+                    let Some(to) =
+                        checked_nth_set_bit(&mut self.corrupt, destinations, move_id as u64 / 4)
+                    else {
+                        return Move::null();
+                    };
+                    let to = Square::new(to);
 
                     Move::promotion(from, to, promoted_piece)
                 } else {
                     let move_id = self
                         .reader
-                        .extract_bits_le8(used_bits_safe(destinations_count as u64));
+                        .extract_bits_le8(movetext, used_bits_safe(destinations_count as u64));
 
-                    let idx = nth_set_bit_index(destinations.bits(), move_id as u64);
+                    let Some(idx) =
+                        checked_nth_set_bit(&mut self.corrupt, destinations, move_id as u64)
+                    else {
+                        return Move::null();
+                    };
 
                     let to = Square::new(idx);
 
@@ -173,9 +215,15 @@ impl PackedMoveScoreListReader {
                     (castling_rights & our_castling_rights_mask).count_ones() as usize;
 
                 let offset = attacks_size as usize + num_castlings;
-                let move_id = self.reader.extract_bits_le8(used_bits_safe(offset as u64)) as u32;
+                let move_id = self
+                    .reader
+                    .extract_bits_le8(movetext, used_bits_safe(offset as u64))
+                    as u32;
 
-                if move_id >= attacks_size {
+                if move_id >= offset as u32 {
+                    self.corrupt = true;
+                    Move::null()
+                } else if move_id >= attacks_size {
                     let idx = move_id - attacks_size;
 
                     let castle_type = if idx == 0
@@ -200,10 +248,12 @@ impl PackedMoveScoreListReader {
                 let attacks = attacks::piece_attacks(piece_type, from, occupied) & !our_pieces;
                 let move_id = self
                     .reader
-                    .extract_bits_le8(used_bits_safe(attacks.count() as u64));
-                let idx = nth_set_bit_index(attacks.bits(), move_id as u64);
-                let to = Square::new(idx);
-                Move::normal(from, to)
+                    .extract_bits_le8(movetext, used_bits_safe(attacks.count() as u64));
+                let Some(idx) = checked_nth_set_bit(&mut self.corrupt, attacks, move_id as u64)
+                else {
+                    return Move::null();
+                };
+                Move::normal(from, Square::new(idx))
             }
         }
     }
@@ -211,4 +261,20 @@ impl PackedMoveScoreListReader {
     pub fn num_read_bytes(&self) -> usize {
         self.reader.num_read_bytes()
     }
+
+    pub fn num_read_bits(&self) -> usize {
+        self.reader.num_read_bits()
+    }
+}
+
+/// Index of the n-th set bit, or None and `corrupt` set if there are not
+/// that many
+#[inline(always)]
+fn checked_nth_set_bit(corrupt: &mut bool, bb: Bitboard, n: u64) -> Option<u32> {
+    if n < bb.count() as u64 {
+        Some(nth_set_bit_index(bb.bits(), n))
+    } else {
+        *corrupt = true;
+        None
+    }
 }
@@ -0,0 +1,702 @@
+//! SIMD kernels for compressing and decompressing positions.
+//!
+//! A compressed position stores one nibble per occupied square, in square
+//! order. Decompressing comes down to unpacking the nibbles, spreading them
+//! over the occupied squares of a 64 byte board and deriving the bitboards
+//! from that board, compressing is the same in reverse. The kernels here do
+//! each step 8 to 32 bytes at a time: with SSSE3 and POPCNT (and AVX2 for the
+//! bitboards) on x86_64, picked at runtime, and with NEON on aarch64. Everywhere else
+//! `Simd::detect()` returns None and the scalar code is used.
+//!
+//! Nibble streams are 48 bytes long, the 32 nibbles of a position followed by
+//! zeros, so 16 byte loads and 8 byte stores at any offset stay in bounds.
+
+/// Byte of a board without a piece, the id of `Piece::NONE`
+pub const EMPTY: u8 = 12;
+
+pub type Stream = [u8; 48];
+
+/// Per occupancy byte of a rank: lanes 0..8 pick the rank's pieces out of
+/// the nibble stream (0x80 selects zero), lanes 8..16 are `EMPTY` for the
+/// empty squares and zero for the occupied ones.
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    allow(dead_code)
+)]
+static EXPAND: [[u8; 16]; 256] = expand_table();
+
+/// Per occupancy byte of a rank: the squares of its pieces, in order
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    allow(dead_code)
+)]
+static COMPACT: [[u8; 16]; 256] = compact_table();
+
+const fn expand_table() -> [[u8; 16]; 256] {
+    let mut table = [[0u8; 16]; 256];
+    let mut occ = 0;
+    while occ < 256 {
+        let mut pieces = 0;
+        let mut sq = 0;
+        while sq < 8 {
+            if occ & (1 << sq) != 0 {
+                table[occ][sq] = pieces;
+                pieces += 1;
+            } else {
+                table[occ][sq] = 0x80;
+                table[occ][sq + 8] = EMPTY;
+            }
+            sq += 1;
+        }
+        occ += 1;
+    }
+    table
+}
+
+const fn compact_table() -> [[u8; 16]; 256] {
+    let mut table = [[0x80u8; 16]; 256];
+    let mut occ = 0;
+    while occ < 256 {
+        let mut pieces = 0;
+        let mut sq = 0;
+        while sq < 8 {
+            if occ & (1 << sq) != 0 {
+                table[occ][pieces] = sq as u8;
+                pieces += 1;
+            }
+            sq += 1;
+        }
+        occ += 1;
+    }
+    table
+}
+
+/// Proof that the SIMD kernels can run on this CPU
+#[derive(Debug, Clone, Copy)]
+pub struct Simd {
+    #[cfg(target_arch = "x86_64")]
+    avx2: bool,
+}
+
+impl Simd {
+    /// The kernels if the CPU supports them, the result of the detection is
+    /// cached by the standard library so this is cheap to call per position.
+    /// Without `std` only the features the target enables count.
+    #[inline]
+    pub fn detect() -> Option<Self> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            #[cfg(feature = "std")]
+            let (supported, avx2) = (
+                std::arch::is_x86_feature_detected!("ssse3")
+                    && std::arch::is_x86_feature_detected!("popcnt"),
+                std::arch::is_x86_feature_detected!("avx2"),
+            );
+            #[cfg(not(feature = "std"))]
+            let (supported, avx2) = (
+                cfg!(all(target_feature = "ssse3", target_feature = "popcnt")),
+                cfg!(target_feature = "avx2"),
+            );
+
+            if !supported {
+                return None;
+            }
+            Some(Self { avx2 })
+        }
+
+        // NEON is part of every aarch64 target
+        #[cfg(target_arch = "aarch64")]
+        {
+            Some(Self {})
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            None
+        }
+    }
+
+    /// Splits the 16 packed bytes into 32 nibbles, low nibble first
+    #[inline]
+    pub fn unpack_nibbles(self, packed: &[u8; 16]) -> Stream {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 is available
+        unsafe {
+            x86::unpack_nibbles(packed)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::unpack_nibbles(packed)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = packed;
+            unreachable!()
+        }
+    }
+
+    /// Packs the first 32 nibbles of the stream into 16 bytes, the inverse
+    /// of unpack_nibbles()
+    #[inline]
+    pub fn pack_nibbles(self, stream: &Stream) -> [u8; 16] {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 is available
+        unsafe {
+            x86::pack_nibbles(stream)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::pack_nibbles(stream)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = stream;
+            unreachable!()
+        }
+    }
+
+    /// Puts the bytes of the stream on the occupied squares, in order, and
+    /// `EMPTY` on the others; at most 32 squares may be occupied
+    #[inline]
+    pub fn expand(self, stream: &Stream, occupied: u64) -> [u8; 64] {
+        // the kernels load 16 bytes after the pieces of the ranks below, which
+        // stays inside the stream only for up to 32 pieces
+        assert!(occupied.count_ones() <= 32);
+
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 and POPCNT are available
+        unsafe {
+            x86::expand(stream, occupied)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::expand(stream, occupied)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = (stream, occupied);
+            unreachable!()
+        }
+    }
+
+    /// Collects the bytes of the occupied squares into a stream, the
+    /// inverse of expand(); at most 32 squares may be occupied
+    #[inline]
+    pub fn compact(self, board: &[u8; 64], occupied: u64) -> Stream {
+        // as in expand(), the stores stay inside the stream for up to 32
+        // pieces
+        assert!(occupied.count_ones() <= 32);
+
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 and POPCNT are available
+        unsafe {
+            x86::compact(board, occupied)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::compact(board, occupied)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = (board, occupied);
+            unreachable!()
+        }
+    }
+
+    /// Bit i is set if byte i of the stream is at least 12, a nibble that
+    /// encodes more than just the piece
+    #[inline]
+    pub fn special_nibbles(self, stream: &Stream) -> u32 {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 is available
+        unsafe {
+            x86::special_nibbles(stream)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::special_nibbles(stream)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = stream;
+            unreachable!()
+        }
+    }
+
+    /// The piece type and color bitboards of a board of piece ids
+    #[inline]
+    pub fn bitboards(self, board: &[u8; 64]) -> ([u64; 6], [u64; 2]) {
+        // a piece id is the piece type times two plus the color, so four bit
+        // planes of the board are enough to tell all pieces apart
+        let [color, t0, t1, t2] = self.bit_planes(board);
+
+        // type 6 (0b110) is `EMPTY`, the largest id on a board
+        let occupied = !(t2 & t1);
+        let bb = [
+            !t2 & !t1 & !t0,
+            !t2 & !t1 & t0,
+            !t2 & t1 & !t0,
+            !t2 & t1 & t0,
+            t2 & !t1 & !t0,
+            t2 & !t1 & t0,
+        ];
+
+        (bb, [occupied & !color, color])
+    }
+
+    /// The board whose bit planes are `planes`, the inverse of the bit
+    /// planes behind bitboards()
+    #[inline]
+    pub fn board(self, planes: &[u64; 4]) -> [u8; 64] {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 is available
+        unsafe {
+            x86::board(planes)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::board(planes)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = planes;
+            unreachable!()
+        }
+    }
+
+    /// Bit i of plane j is set if bit j of byte i of the board is set
+    #[inline]
+    fn bit_planes(self, board: &[u8; 64]) -> [u64; 4] {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `self` is only created when SSSE3 is available, and with
+        // `avx2` set when AVX2 is
+        unsafe {
+            if self.avx2 {
+                x86::bit_planes_avx2(board)
+            } else {
+                x86::bit_planes(board)
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON is always available on aarch64
+        unsafe {
+            neon::bit_planes(board)
+        }
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = board;
+            unreachable!()
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use core::arch::x86_64::*;
+
+    use super::{Stream, COMPACT, EXPAND};
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn unpack_nibbles(packed: &[u8; 16]) -> Stream {
+        let bytes = _mm_loadu_si128(packed.as_ptr().cast());
+        let mask = _mm_set1_epi8(0x0F);
+        let low = _mm_and_si128(bytes, mask);
+        let high = _mm_and_si128(_mm_srli_epi16::<4>(bytes), mask);
+
+        let mut stream = [0u8; 48];
+        _mm_storeu_si128(stream.as_mut_ptr().cast(), _mm_unpacklo_epi8(low, high));
+        _mm_storeu_si128(
+            stream.as_mut_ptr().add(16).cast(),
+            _mm_unpackhi_epi8(low, high),
+        );
+        stream
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn pack_nibbles(stream: &Stream) -> [u8; 16] {
+        // every pair of bytes (low, high) becomes low + 16 * high
+        let weights = _mm_set1_epi16(0x1001);
+        let first = _mm_maddubs_epi16(_mm_loadu_si128(stream.as_ptr().cast()), weights);
+        let second = _mm_maddubs_epi16(_mm_loadu_si128(stream.as_ptr().add(16).cast()), weights);
+
+        let mut packed = [0u8; 16];
+        _mm_storeu_si128(packed.as_mut_ptr().cast(), _mm_packus_epi16(first, second));
+        packed
+    }
+
+    #[target_feature(enable = "ssse3,popcnt")]
+    pub unsafe fn expand(stream: &Stream, occupied: u64) -> [u8; 64] {
+        let mut board = [0u8; 64];
+        let mut start = 0;
+
+        for rank in 0..8 {
+            let occ = (occupied >> (8 * rank)) as u8;
+            let table = _mm_loadu_si128(EXPAND[occ as usize].as_ptr().cast());
+            let window = _mm_loadu_si128(stream.as_ptr().add(start).cast());
+
+            let pieces = _mm_shuffle_epi8(window, table);
+            let empty = _mm_unpackhi_epi64(table, table);
+            _mm_storel_epi64(
+                board.as_mut_ptr().add(8 * rank).cast(),
+                _mm_or_si128(pieces, empty),
+            );
+
+            start += occ.count_ones() as usize;
+        }
+
+        board
+    }
+
+    #[target_feature(enable = "ssse3,popcnt")]
+    pub unsafe fn compact(board: &[u8; 64], occupied: u64) -> Stream {
+        let mut stream = [0u8; 48];
+        let mut start = 0;
+
+        for rank in 0..8 {
+            let occ = (occupied >> (8 * rank)) as u8;
+            let table = _mm_loadu_si128(COMPACT[occ as usize].as_ptr().cast());
+            let squares = _mm_loadl_epi64(board.as_ptr().add(8 * rank).cast());
+
+            // the lanes past the rank's pieces are zero and overwritten by
+            // the next rank
+            _mm_storel_epi64(
+                stream.as_mut_ptr().add(start).cast(),
+                _mm_shuffle_epi8(squares, table),
+            );
+
+            start += occ.count_ones() as usize;
+        }
+
+        stream
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn special_nibbles(stream: &Stream) -> u32 {
+        let limit = _mm_set1_epi8(11);
+        let first = _mm_cmpgt_epi8(_mm_loadu_si128(stream.as_ptr().cast()), limit);
+        let second = _mm_cmpgt_epi8(_mm_loadu_si128(stream.as_ptr().add(16).cast()), limit);
+
+        (_mm_movemask_epi8(first) as u32 & 0xFFFF) | ((_mm_movemask_epi8(second) as u32) << 16)
+    }
+
+    /// Bit planes 0 to 3 of a board, `$lanes` bytes at a time: shifting a
+    /// byte left by 7 - j puts bit j on top, where movemask picks it up
+    macro_rules! planes {
+        ($load:ident, $shift:ident, $movemask:ident, $board:expr, $lanes:expr) => {{
+            let mut planes = [0u64; 4];
+
+            for chunk in 0..64 / $lanes {
+                let ids = $load($board.as_ptr().add($lanes * chunk).cast());
+                // the shifts move bits across bytes too, but never into the
+                // top bit of a byte
+                let masks = [
+                    $movemask($shift::<7>(ids)),
+                    $movemask($shift::<6>(ids)),
+                    $movemask($shift::<5>(ids)),
+                    $movemask($shift::<4>(ids)),
+                ];
+
+                for (plane, mask) in planes.iter_mut().zip(masks) {
+                    *plane |= (mask as u32 as u64) << ($lanes * chunk);
+                }
+            }
+
+            planes
+        }};
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn board(planes: &[u64; 4]) -> [u8; 64] {
+        // lanes 0..8 look at the low byte of 16 squares, lanes 8..16 at
+        // the high byte, each at its own bit
+        let halves = _mm_set_epi64x(0x0101_0101_0101_0101, 0);
+        let bits = _mm_set1_epi64x(0x8040_2010_0804_0201u64 as i64);
+        let mut board = [0u8; 64];
+
+        for chunk in 0..4 {
+            let mut ids = _mm_setzero_si128();
+
+            for (bit, plane) in planes.iter().enumerate() {
+                let squares = _mm_set1_epi16((plane >> (16 * chunk)) as u16 as i16);
+                let bytes = _mm_shuffle_epi8(squares, halves);
+                let set = _mm_cmpeq_epi8(_mm_and_si128(bytes, bits), bits);
+                ids = _mm_or_si128(ids, _mm_and_si128(set, _mm_set1_epi8(1 << bit)));
+            }
+
+            _mm_storeu_si128(board.as_mut_ptr().add(16 * chunk).cast(), ids);
+        }
+
+        board
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn bit_planes(board: &[u8; 64]) -> [u64; 4] {
+        planes!(
+            _mm_loadu_si128,
+            _mm_slli_epi16,
+            _mm_movemask_epi8,
+            board,
+            16
+        )
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn bit_planes_avx2(board: &[u8; 64]) -> [u64; 4] {
+        planes!(
+            _mm256_loadu_si256,
+            _mm256_slli_epi16,
+            _mm256_movemask_epi8,
+            board,
+            32
+        )
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use core::arch::aarch64::*;
+
+    use super::{Stream, COMPACT, EXPAND};
+
+    /// Bit i of the result is set if lane i of `mask` is set
+    #[inline(always)]
+    unsafe fn movemask(mask: uint8x16_t) -> u64 {
+        const WEIGHTS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+        let bits = vandq_u8(mask, vld1q_u8(WEIGHTS.as_ptr()));
+        vaddv_u8(vget_low_u8(bits)) as u64 | (vaddv_u8(vget_high_u8(bits)) as u64) << 8
+    }
+
+    pub unsafe fn unpack_nibbles(packed: &[u8; 16]) -> Stream {
+        let bytes = vld1q_u8(packed.as_ptr());
+        let low = vandq_u8(bytes, vdupq_n_u8(0x0F));
+        let high = vshrq_n_u8::<4>(bytes);
+
+        let mut stream = [0u8; 48];
+        vst1q_u8(stream.as_mut_ptr(), vzip1q_u8(low, high));
+        vst1q_u8(stream.as_mut_ptr().add(16), vzip2q_u8(low, high));
+        stream
+    }
+
+    pub unsafe fn pack_nibbles(stream: &Stream) -> [u8; 16] {
+        let first = vld1q_u8(stream.as_ptr());
+        let second = vld1q_u8(stream.as_ptr().add(16));
+        let low = vuzp1q_u8(first, second);
+        let high = vuzp2q_u8(first, second);
+
+        let mut packed = [0u8; 16];
+        vst1q_u8(packed.as_mut_ptr(), vorrq_u8(low, vshlq_n_u8::<4>(high)));
+        packed
+    }
+
+    pub unsafe fn expand(stream: &Stream, occupied: u64) -> [u8; 64] {
+        let mut board = [0u8; 64];
+        let mut start = 0;
+
+        for rank in 0..8 {
+            let occ = (occupied >> (8 * rank)) as u8;
+            let table = vld1q_u8(EXPAND[occ as usize].as_ptr());
+            let window = vld1q_u8(stream.as_ptr().add(start));
+
+            let pieces = vqtbl1q_u8(window, table);
+            vst1_u8(
+                board.as_mut_ptr().add(8 * rank),
+                vorr_u8(vget_low_u8(pieces), vget_high_u8(table)),
+            );
+
+            start += occ.count_ones() as usize;
+        }
+
+        board
+    }
+
+    pub unsafe fn compact(board: &[u8; 64], occupied: u64) -> Stream {
+        let mut stream = [0u8; 48];
+        let mut start = 0;
+
+        for rank in 0..8 {
+            let occ = (occupied >> (8 * rank)) as u8;
+            let table = vld1_u8(COMPACT[occ as usize].as_ptr());
+            let squares = vcombine_u8(vld1_u8(board.as_ptr().add(8 * rank)), vdup_n_u8(0));
+
+            vst1_u8(stream.as_mut_ptr().add(start), vqtbl1_u8(squares, table));
+
+            start += occ.count_ones() as usize;
+        }
+
+        stream
+    }
+
+    pub unsafe fn special_nibbles(stream: &Stream) -> u32 {
+        let limit = vdupq_n_u8(11);
+        let first = vcgtq_u8(vld1q_u8(stream.as_ptr()), limit);
+        let second = vcgtq_u8(vld1q_u8(stream.as_ptr().add(16)), limit);
+
+        (movemask(first) | movemask(second) << 16) as u32
+    }
+
+    pub unsafe fn board(planes: &[u64; 4]) -> [u8; 64] {
+        const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+        let bits = vld1q_u8(BITS.as_ptr());
+        let mut board = [0u8; 64];
+
+        for chunk in 0..4 {
+            let mut ids = vdupq_n_u8(0);
+
+            for (bit, plane) in planes.iter().enumerate() {
+                let squares = (plane >> (16 * chunk)) as u16;
+                let bytes = vcombine_u8(vdup_n_u8(squares as u8), vdup_n_u8((squares >> 8) as u8));
+                let set = vtstq_u8(bytes, bits);
+                ids = vorrq_u8(ids, vandq_u8(set, vdupq_n_u8(1 << bit)));
+            }
+
+            vst1q_u8(board.as_mut_ptr().add(16 * chunk), ids);
+        }
+
+        board
+    }
+
+    pub unsafe fn bit_planes(board: &[u8; 64]) -> [u64; 4] {
+        let mut planes = [0u64; 4];
+
+        for chunk in 0..4 {
+            let ids = vld1q_u8(board.as_ptr().add(16 * chunk));
+
+            for (bit, plane) in planes.iter_mut().enumerate() {
+                let set = vtstq_u8(ids, vdupq_n_u8(1 << bit));
+                *plane |= movemask(set) << (16 * chunk);
+            }
+        }
+
+        planes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(bytes: &[u8]) -> Stream {
+        let mut stream = [0u8; 48];
+        stream[..bytes.len()].copy_from_slice(bytes);
+        stream
+    }
+
+    #[test]
+    fn test_nibbles() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        let packed = core::array::from_fn(|idx| (idx as u8).wrapping_mul(37) ^ 0x5A);
+        let nibbles = simd.unpack_nibbles(&packed);
+
+        for (idx, byte) in packed.iter().enumerate() {
+            assert_eq!(nibbles[2 * idx], byte & 0xF);
+            assert_eq!(nibbles[2 * idx + 1], byte >> 4);
+        }
+        assert_eq!(nibbles[32..], [0; 16]);
+        assert_eq!(simd.pack_nibbles(&nibbles), packed);
+    }
+
+    #[test]
+    fn test_expand_and_compact() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        // a1, h1, e2, a8 and h8
+        let occupied = 1 | 1 << 7 | 1 << 12 | 1 << 56 | 1 << 63;
+        let pieces = stream(&[3, 4, 0, 9, 10]);
+
+        let board = simd.expand(&pieces, occupied);
+        let mut expected = [EMPTY; 64];
+        expected[0] = 3;
+        expected[7] = 4;
+        expected[12] = 0;
+        expected[56] = 9;
+        expected[63] = 10;
+        assert_eq!(board, expected);
+
+        assert_eq!(simd.compact(&board, occupied), pieces);
+
+        // 32 pieces, the most a position can have
+        let occupied = 0xFFFF_0000_0000_FFFF;
+        let pieces = stream(&core::array::from_fn::<u8, 32, _>(|idx| idx as u8 % 16));
+        let board = simd.expand(&pieces, occupied);
+        assert_eq!(simd.compact(&board, occupied), pieces);
+    }
+
+    #[test]
+    fn test_special_nibbles() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        let nibbles = stream(&[0, 12, 11, 15, 1, 13]);
+        assert_eq!(simd.special_nibbles(&nibbles), 0b101010);
+    }
+
+    #[test]
+    fn test_bitboards() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        let mut board = [EMPTY; 64];
+        board[0] = 0; // white pawn
+        board[9] = 7; // black rook
+        board[40] = 11; // black king
+        board[63] = 10; // white king
+
+        let expected = (
+            [1, 0, 0, 1 << 9, 0, 1 << 40 | 1 << 63],
+            [1 | 1 << 63, 1 << 9 | 1 << 40],
+        );
+        assert_eq!(simd.bitboards(&board), expected);
+
+        // the SSSE3 fallback of a CPU with AVX2
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(Simd { avx2: false }.bitboards(&board), expected);
+
+        let planes = simd.bit_planes(&board);
+        assert_eq!(simd.board(&planes), board);
+
+        let (bb, bb_color) = simd.bitboards(&[EMPTY; 64]);
+        assert_eq!((bb, bb_color), ([0; 6], [0; 2]));
+    }
+
+    #[test]
+    fn test_expand_too_many_pieces() {
+        let Some(simd) = Simd::detect() else {
+            return;
+        };
+
+        // more pieces than the stream holds are refused, not read past it
+        let result = std::panic::catch_unwind(|| simd.expand(&[0; 48], u64::MAX));
+        assert!(result.is_err());
+        let result = std::panic::catch_unwind(|| simd.compact(&[0; 64], u64::MAX));
+        assert!(result.is_err());
+    }
+}
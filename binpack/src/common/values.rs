@@ -0,0 +1,302 @@
+//! Checked types for the score, result and ply of an entry.
+//!
+//! The fields of [`TrainingDataEntry`](crate::TrainingDataEntry) are plain
+//! integers, but the format stores the result in 2 bits and the ply in 14.
+//! Building entries from these types rejects what the format cannot
+//! represent instead of silently writing something else.
+
+use core::{fmt, ops::Neg};
+
+use thiserror::Error;
+
+use crate::chess::color::Color;
+
+/// Score used when a format has no evaluation for a position, same as
+/// Stockfish's `VALUE_NONE`.
+pub const VALUE_NONE: i16 = 32002;
+
+/// A value the binpack format cannot represent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ValueError {
+    #[error("score {0} does not fit 16 bits")]
+    Score(i64),
+    #[error("result {0} is not -1, 0 or 1")]
+    Result(i64),
+    #[error("ply {0} does not fit 14 bits")]
+    Ply(i64),
+}
+
+/// Evaluation in centipawns, relative to the side to move like everything
+/// in an entry, see [`Score::to_white_relative`] for tools that use white's
+/// point of view
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Score(i16);
+
+impl Score {
+    pub const fn new(score: i16) -> Self {
+        Self(score)
+    }
+
+    pub const fn get(self) -> i16 {
+        self.0
+    }
+
+    /// The score from white's point of view, given the side to move it is
+    /// relative to. Like [`GameResult::to_white_relative`] this is its own
+    /// inverse. `VALUE_NONE` stays as it is.
+    pub const fn to_white_relative(self, side_to_move: Color) -> Self {
+        match side_to_move {
+            Color::Black if self.0 != VALUE_NONE => Self(self.0.saturating_neg()),
+            _ => self,
+        }
+    }
+}
+
+impl From<i16> for Score {
+    fn from(score: i16) -> Self {
+        Self(score)
+    }
+}
+
+impl From<Score> for i16 {
+    fn from(score: Score) -> Self {
+        score.0
+    }
+}
+
+impl TryFrom<i32> for Score {
+    type Error = ValueError;
+
+    fn try_from(score: i32) -> Result<Self, ValueError> {
+        i16::try_from(score)
+            .map(Self)
+            .map_err(|_| ValueError::Score(score.into()))
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Outcome of the game for the side to move
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    Loss = -1,
+    #[default]
+    Draw = 0,
+    Win = 1,
+}
+
+impl GameResult {
+    /// The result for the other side
+    pub const fn invert(self) -> Self {
+        match self {
+            Self::Loss => Self::Win,
+            Self::Draw => Self::Draw,
+            Self::Win => Self::Loss,
+        }
+    }
+
+    /// The result for white, given the side to move it is relative to. The
+    /// conversion is its own inverse, so this also turns a result for white
+    /// into one for `side_to_move`.
+    pub const fn to_white_relative(self, side_to_move: Color) -> Self {
+        match side_to_move {
+            Color::White => self,
+            Color::Black => self.invert(),
+        }
+    }
+
+    /// The 2 bits the result takes in a packed entry: 0 for a draw, 1 for a
+    /// loss and 2 for a win
+    pub const fn to_packed(self) -> u16 {
+        match self {
+            Self::Draw => 0,
+            Self::Loss => 1,
+            Self::Win => 2,
+        }
+    }
+
+    /// Reads the result from the low 2 bits of `bits`, 3 is not a result
+    pub const fn from_packed(bits: u16) -> Result<Self, ValueError> {
+        match bits & 0b11 {
+            0 => Ok(Self::Draw),
+            1 => Ok(Self::Loss),
+            2 => Ok(Self::Win),
+            // what the raw field reads for it
+            _ => Err(ValueError::Result(-2)),
+        }
+    }
+}
+
+impl Neg for GameResult {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.invert()
+    }
+}
+
+impl From<GameResult> for i16 {
+    fn from(result: GameResult) -> Self {
+        result as i16
+    }
+}
+
+impl TryFrom<i16> for GameResult {
+    type Error = ValueError;
+
+    fn try_from(result: i16) -> Result<Self, ValueError> {
+        match result {
+            -1 => Ok(Self::Loss),
+            0 => Ok(Self::Draw),
+            1 => Ok(Self::Win),
+            _ => Err(ValueError::Result(result.into())),
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        i16::from(*self).fmt(f)
+    }
+}
+
+/// Number of half moves played since the start of the game
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ply(u16);
+
+impl Ply {
+    /// The largest ply the format stores
+    pub const MAX: Ply = Ply(0x3FFF);
+
+    pub const fn new(ply: u16) -> Option<Self> {
+        if ply <= Self::MAX.0 {
+            Some(Self(ply))
+        } else {
+            None
+        }
+    }
+
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl From<Ply> for u16 {
+    fn from(ply: Ply) -> Self {
+        ply.0
+    }
+}
+
+impl TryFrom<u16> for Ply {
+    type Error = ValueError;
+
+    fn try_from(ply: u16) -> Result<Self, ValueError> {
+        Self::new(ply).ok_or(ValueError::Ply(ply.into()))
+    }
+}
+
+impl TryFrom<u32> for Ply {
+    type Error = ValueError;
+
+    fn try_from(ply: u32) -> Result<Self, ValueError> {
+        u16::try_from(ply)
+            .ok()
+            .and_then(Self::new)
+            .ok_or(ValueError::Ply(ply.into()))
+    }
+}
+
+impl fmt::Display for Ply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        assert_eq!(Score::try_from(-300i32), Ok(Score::new(-300)));
+        assert_eq!(Score::try_from(32767i32).map(i16::from), Ok(i16::MAX));
+        assert_eq!(Score::try_from(40000i32), Err(ValueError::Score(40000)));
+        assert_eq!(Score::from(-5i16).get(), -5);
+    }
+
+    #[test]
+    fn test_score_perspective() {
+        let score = Score::new(120);
+        assert_eq!(score.to_white_relative(Color::White), score);
+        assert_eq!(score.to_white_relative(Color::Black), Score::new(-120));
+
+        let none = Score::new(VALUE_NONE);
+        assert_eq!(none.to_white_relative(Color::Black), none);
+        // -32768 has no negation
+        assert_eq!(
+            Score::new(i16::MIN).to_white_relative(Color::Black),
+            Score::new(i16::MAX)
+        );
+    }
+
+    #[test]
+    fn test_game_result() {
+        for (raw, result) in [
+            (-1, GameResult::Loss),
+            (0, GameResult::Draw),
+            (1, GameResult::Win),
+        ] {
+            assert_eq!(GameResult::try_from(raw), Ok(result));
+            assert_eq!(i16::from(result), raw);
+        }
+
+        assert_eq!(GameResult::try_from(2), Err(ValueError::Result(2)));
+        assert_eq!(GameResult::try_from(-2), Err(ValueError::Result(-2)));
+    }
+
+    #[test]
+    fn test_game_result_perspective() {
+        use GameResult::*;
+
+        assert_eq!([Loss, Draw, Win].map(GameResult::invert), [Win, Draw, Loss]);
+        assert_eq!(-Win, Loss);
+        assert_eq!(Win.to_white_relative(Color::White), Win);
+        assert_eq!(Win.to_white_relative(Color::Black), Loss);
+        assert_eq!(
+            Loss.to_white_relative(Color::Black)
+                .to_white_relative(Color::Black),
+            Loss
+        );
+    }
+
+    #[test]
+    fn test_game_result_packed() {
+        use crate::common::arithmetic::{signed_to_unsigned, unsigned_to_signed};
+
+        for result in [GameResult::Loss, GameResult::Draw, GameResult::Win] {
+            let raw = i16::from(result);
+            assert_eq!(result.to_packed(), signed_to_unsigned(raw));
+            assert_eq!(GameResult::from_packed(result.to_packed()), Ok(result));
+        }
+
+        assert_eq!(
+            GameResult::from_packed(3),
+            Err(ValueError::Result(unsigned_to_signed(3).into()))
+        );
+        // only the low bits count
+        assert_eq!(GameResult::from_packed(0xFFF6), Ok(GameResult::Win));
+    }
+
+    #[test]
+    fn test_ply() {
+        assert_eq!(Ply::new(0x3FFF), Some(Ply::MAX));
+        assert_eq!(Ply::new(0x4000), None);
+        assert_eq!(Ply::try_from(0xFFFFu16), Err(ValueError::Ply(0xFFFF)));
+        assert_eq!(Ply::try_from(70000u32), Err(ValueError::Ply(70000)));
+        assert_eq!(Ply::try_from(42u32).map(u16::from), Ok(42));
+    }
+}
@@ -0,0 +1,478 @@
+//! Scoring positions with UCI engines.
+//!
+//! An [`Engine`] is a UCI engine process, an [`EnginePool`] a number of them
+//! sharing the positions of a batch. [`EnginePool::score_entries`] rescores a
+//! stream of entries in batches, all engines searching at once:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use sfbinpack::{
+//!     engine::{EngineOptions, EnginePool, Limit},
+//!     CompressedTrainingDataEntryReader,
+//! };
+//!
+//! let file = File::open("test/ep1.binpack").unwrap();
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//! let entries = std::iter::from_fn(|| reader.has_next().then(|| reader.next()));
+//!
+//! let mut pool = EnginePool::spawn("stockfish", 4, &EngineOptions::default()).unwrap();
+//! for entry in pool.score_entries(entries, Limit::Depth(8)) {
+//!     println!("{}", entry.unwrap().score);
+//! }
+//! ```
+//!
+//! Scores are from the side to move's point of view like in the entries,
+//! converted from centipawns and mates in moves by
+//! [`EngineOptions::score_policy`]: by default a mate in N plies is stored as
+//! [`MATE`](crate::formats::pgn::MATE)` - N`.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    path::Path,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::Mutex,
+    thread,
+};
+
+use crate::{
+    chess::{position::Position, r#move::Move},
+    formats::policy::ScorePolicy,
+    TrainingDataEntry,
+};
+
+/// Entries handed to an engine of a pool at a time
+const JOB_SIZE: usize = 16;
+
+/// Entries per engine that [`EnginePool::score_entries`] scores at a time
+pub const BATCH_PER_ENGINE: usize = 256;
+
+/// How long an engine searches each position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth(u32),
+    Nodes(u64),
+}
+
+impl Limit {
+    fn go(&self) -> String {
+        match self {
+            Limit::Depth(depth) => format!("go depth {}", depth),
+            Limit::Nodes(nodes) => format!("go nodes {}", nodes),
+        }
+    }
+}
+
+/// Options set on every engine of a pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOptions {
+    /// Hash size in MiB
+    pub hash: u64,
+    /// Search threads of each engine, a pool gets its parallelism from the
+    /// number of engines
+    pub threads: u32,
+    /// Any other UCI options, as name and value
+    pub uci_options: Vec<(String, String)>,
+    /// Units and mate encoding of the scores
+    pub score_policy: ScorePolicy,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            hash: 16,
+            threads: 1,
+            uci_options: Vec::new(),
+            score_policy: ScorePolicy::default(),
+        }
+    }
+}
+
+/// What an engine said about a position
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Analysis {
+    /// Score of the deepest exact `info` line
+    pub score: Option<i16>,
+    /// None if the engine's move is not legal in the position
+    pub best_move: Option<Move>,
+}
+
+/// A UCI engine process, told to quit when dropped
+#[derive(Debug)]
+pub struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    line: String,
+    score_policy: ScorePolicy,
+}
+
+impl Engine {
+    /// Starts the engine and waits until it is ready
+    pub fn spawn(path: impl AsRef<Path>, options: &EngineOptions) -> io::Result<Self> {
+        let mut child = Command::new(path.as_ref())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        let mut engine = Self {
+            child,
+            stdin,
+            stdout,
+            line: String::new(),
+            score_policy: options.score_policy,
+        };
+
+        engine.send("uci")?;
+        engine.wait_for("uciok")?;
+        engine.set_option("Threads", &options.threads.to_string())?;
+        engine.set_option("Hash", &options.hash.to_string())?;
+        for (name, value) in &options.uci_options {
+            engine.set_option(name, value)?;
+        }
+        engine.send("isready")?;
+        engine.wait_for("readyok")?;
+
+        Ok(engine)
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.send(&format!("setoption name {} value {}", name, value))
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<&str> {
+        self.line.clear();
+        if self.stdout.read_line(&mut self.line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "engine exited unexpectedly",
+            ));
+        }
+
+        Ok(self.line.trim_end())
+    }
+
+    fn wait_for(&mut self, token: &str) -> io::Result<()> {
+        while self.read_line()? != token {}
+        Ok(())
+    }
+
+    /// Searches `pos` and returns the last exact score and the best move
+    pub fn analyse(&mut self, pos: &Position, limit: Limit) -> io::Result<Analysis> {
+        let fen = pos
+            .fen()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid position"))?;
+
+        self.send(&format!("position fen {}", fen))?;
+        self.send(&limit.go())?;
+
+        let policy = self.score_policy;
+        let mut analysis = Analysis::default();
+        loop {
+            let line = self.read_line()?;
+
+            if let Some(best_move) = line.strip_prefix("bestmove ") {
+                analysis.best_move = best_move
+                    .split_whitespace()
+                    .next()
+                    .and_then(|uci| Move::from_uci(pos, uci));
+                return Ok(analysis);
+            }
+
+            if let Some(score) = parse_info_score(line, &policy) {
+                analysis.score = Some(score);
+            }
+        }
+    }
+
+    /// Replaces the score of `entry` with the engine's, and its move with
+    /// the engine's best move if `best_move` is set. What the engine does
+    /// not tell is left alone.
+    pub fn rescore(
+        &mut self,
+        entry: &mut TrainingDataEntry,
+        limit: Limit,
+        best_move: bool,
+    ) -> io::Result<()> {
+        let analysis = self.analyse(&entry.pos, limit)?;
+
+        if let Some(score) = analysis.score {
+            entry.score = score;
+        }
+
+        if best_move {
+            if let Some(mv) = analysis.best_move {
+                entry.mv = mv;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        if self.send("quit").is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// A number of engine processes searching the positions of a batch together
+#[derive(Debug)]
+pub struct EnginePool {
+    engines: Vec<Engine>,
+    best_move: bool,
+}
+
+impl EnginePool {
+    /// Starts `count` engines, at least one
+    pub fn spawn(
+        path: impl AsRef<Path>,
+        count: usize,
+        options: &EngineOptions,
+    ) -> io::Result<Self> {
+        let engines = (0..count.max(1))
+            .map(|_| Engine::spawn(path.as_ref(), options))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            engines,
+            best_move: false,
+        })
+    }
+
+    /// Also replace the moves of the entries with the engines' best moves;
+    /// games whose moves change are no longer chained by the writer
+    pub fn with_best_move(mut self, best_move: bool) -> Self {
+        self.best_move = best_move;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.engines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.engines.is_empty()
+    }
+
+    /// Rescores `batch` in place, the engines take small jobs off a shared
+    /// queue so a slow position does not hold up the others
+    pub fn score_batch(&mut self, batch: &mut [TrainingDataEntry], limit: Limit) -> io::Result<()> {
+        let jobs = Mutex::new(batch.chunks_mut(JOB_SIZE));
+        let best_move = self.best_move;
+
+        thread::scope(|scope| {
+            let workers = self
+                .engines
+                .iter_mut()
+                .map(|engine| {
+                    let jobs = &jobs;
+                    scope.spawn(move || loop {
+                        let Some(job) = jobs.lock().unwrap().next() else {
+                            return Ok(());
+                        };
+
+                        for entry in job {
+                            engine.rescore(entry, limit, best_move)?;
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("engine worker panicked"))
+        })
+    }
+
+    /// The entries with the engines' scores, in order, scored
+    /// [`BATCH_PER_ENGINE`] entries per engine at a time. The first error
+    /// ends the iterator.
+    pub fn score_entries<I>(&mut self, entries: I, limit: Limit) -> ScoreEntries<'_, I::IntoIter>
+    where
+        I: IntoIterator<Item = TrainingDataEntry>,
+    {
+        let batch_size = self.engines.len() * BATCH_PER_ENGINE;
+        ScoreEntries {
+            pool: self,
+            entries: entries.into_iter(),
+            limit,
+            batch: Vec::with_capacity(batch_size).into_iter(),
+            batch_size,
+            failed: false,
+        }
+    }
+}
+
+/// Iterator of [`EnginePool::score_entries`]
+#[derive(Debug)]
+pub struct ScoreEntries<'a, I> {
+    pool: &'a mut EnginePool,
+    entries: I,
+    limit: Limit,
+    batch: std::vec::IntoIter<TrainingDataEntry>,
+    batch_size: usize,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = TrainingDataEntry>> Iterator for ScoreEntries<'_, I> {
+    type Item = io::Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(entry) = self.batch.next() {
+            return Some(Ok(entry));
+        }
+        if self.failed {
+            return None;
+        }
+
+        // reuse the allocation of the batch that was just handed out
+        let mut batch = mem::take(&mut self.batch).collect::<Vec<_>>();
+        batch.extend(self.entries.by_ref().take(self.batch_size));
+        if batch.is_empty() {
+            return None;
+        }
+
+        if let Err(err) = self.pool.score_batch(&mut batch, self.limit) {
+            self.failed = true;
+            return Some(Err(err));
+        }
+
+        self.batch = batch.into_iter();
+        self.batch.next().map(Ok)
+    }
+}
+
+/// Extracts the exact score of an `info` line, bounds are skipped
+pub fn parse_info_score(line: &str, policy: &ScorePolicy) -> Option<i16> {
+    let mut tokens = line.split_whitespace().peekable();
+    if tokens.next() != Some("info") || tokens.peek() == Some(&"string") {
+        return None;
+    }
+
+    let mut tokens = tokens.skip_while(|&token| token != "score").skip(1);
+    let kind = tokens.next()?;
+    let value = tokens.next()?.parse::<i64>().ok()?;
+
+    if matches!(tokens.next(), Some("lowerbound" | "upperbound")) {
+        return None;
+    }
+
+    // a mate in N moves is 2N - 1 plies for the side that mates and 2N for
+    // the side that gets mated
+    let plies = |plies: i64| plies.clamp(0, u32::MAX.into()) as u32;
+    match kind {
+        "cp" => Some(policy.centipawns(value)),
+        "mate" if value > 0 => Some(policy.mate(plies(2 * value - 1), true)),
+        "mate" => Some(policy.mate(plies(-2 * value), false)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        formats::{pgn::MATE, policy::MateEncoding},
+        pipeline::score::STOCKFISH_INTERNAL,
+    };
+
+    #[test]
+    fn test_parse_info_score() {
+        let parse = |line| parse_info_score(line, &ScorePolicy::default());
+
+        assert_eq!(
+            parse("info depth 8 seldepth 10 score cp 35 nodes 1 pv e2e4"),
+            Some(35)
+        );
+        assert_eq!(parse("info depth 8 score cp -120"), Some(-120));
+        assert_eq!(parse("info depth 20 score mate 3 pv a1a8"), Some(MATE - 5));
+        assert_eq!(parse("info depth 20 score mate -2"), Some(-(MATE - 4)));
+        assert_eq!(parse("info depth 1 score mate 0"), Some(-MATE));
+        assert_eq!(parse("info depth 8 score cp 40 lowerbound"), None);
+        assert_eq!(parse("info string score cp 12"), None);
+        assert_eq!(parse("info depth 8 nodes 100"), None);
+        assert_eq!(parse("bestmove e2e4"), None);
+
+        let policy = ScorePolicy {
+            units: STOCKFISH_INTERNAL,
+            mates: MateEncoding::Clamp(10_000),
+        };
+        assert_eq!(parse_info_score("info score cp 50", &policy), Some(104));
+        assert_eq!(
+            parse_info_score("info score mate -2", &policy),
+            Some(-10_000)
+        );
+        assert_eq!(
+            parse_info_score("info score cp 5000", &policy),
+            Some(10_000)
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(Limit::Depth(8).go(), "go depth 8");
+        assert_eq!(Limit::Nodes(5000).go(), "go nodes 5000");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_score_entries_with_fake_engine() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        use crate::testing::{random_game, GameOptions, Rng};
+
+        // scores every position 7 per word of its `go` command, and plays
+        // e2e4 whether it is legal or not
+        let dir = tempfile::tempdir().unwrap();
+        let engine = dir.path().join("engine.sh");
+        fs::write(
+            &engine,
+            "#!/bin/sh
+while read -r command rest; do
+  case \"$command\" in
+    uci) echo 'id name fake'; echo uciok ;;
+    isready) echo readyok ;;
+    go) set -- $rest; echo \"info depth 1 score cp $((7 * $#))\"; echo 'bestmove e2e4' ;;
+    quit) exit 0 ;;
+  esac
+done
+",
+        )
+        .unwrap();
+        fs::set_permissions(&engine, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut rng = Rng::new(9);
+        let entries = random_game(&mut rng, &GameOptions::default());
+
+        let mut pool = EnginePool::spawn(&engine, 3, &EngineOptions::default())
+            .unwrap()
+            .with_best_move(true);
+        assert_eq!(pool.len(), 3);
+
+        let scored = pool
+            .score_entries(entries.iter().copied(), Limit::Nodes(100))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(scored.len(), entries.len());
+
+        let e2e4 = Move::from_uci(&Position::new(), "e2e4").unwrap();
+        for (before, after) in entries.iter().zip(&scored) {
+            assert_eq!(after.score, 14);
+            assert_eq!(after.pos, before.pos);
+            let legal = Move::from_uci(&before.pos, "e2e4").is_some();
+            assert_eq!(after.mv == e2e4, legal || before.mv == e2e4);
+        }
+    }
+}
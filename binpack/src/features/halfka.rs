@@ -0,0 +1,198 @@
+//! HalfKAv2_hm: every piece, kings included, by the square of the own king,
+//! with the board mirrored so that the king is always on files e-h.
+//!
+//! Squares are flipped vertically for black and additionally mirrored
+//! horizontally whenever the own king stands on files a-d. The 32 remaining
+//! king squares form the buckets of `KingBuckets` in nnue-pytorch and the
+//! Stockfish loader, from 31 for e1 down to 0 for h8. Pieces use 11 planes:
+//! `piece_type * 2 + is_enemy` for pawns to queens and a shared plane 10 for
+//! both kings. A feature is `bucket * 704 + plane * 64 + square`, the same
+//! index as the C++ loader.
+
+use crate::chess::{
+    color::Color, coords::Square, piece::Piece, piecetype::PieceType, position::Position,
+};
+
+use super::FeatureSet;
+
+const PLANES: usize = 11;
+
+/// Pieces by plane and square
+const PIECE_FEATURES: usize = PLANES * 64;
+
+/// Bucket of the oriented king square, files a-d never occur
+#[rustfmt::skip]
+const KING_BUCKETS: [u8; 64] = [
+    0, 0, 0, 0, 31, 30, 29, 28,
+    0, 0, 0, 0, 27, 26, 25, 24,
+    0, 0, 0, 0, 23, 22, 21, 20,
+    0, 0, 0, 0, 19, 18, 17, 16,
+    0, 0, 0, 0, 15, 14, 13, 12,
+    0, 0, 0, 0, 11, 10, 9, 8,
+    0, 0, 0, 0, 7, 6, 5, 4,
+    0, 0, 0, 0, 3, 2, 1, 0,
+];
+
+/// HalfKAv2_hm features, optionally factorized into a virtual A feature per
+/// piece that does not depend on the king bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HalfKAv2Hm {
+    factorized: bool,
+}
+
+impl HalfKAv2Hm {
+    pub const fn new() -> Self {
+        Self { factorized: false }
+    }
+
+    /// Adds the virtual A features
+    pub const fn factorized(self) -> Self {
+        Self { factorized: true }
+    }
+
+    /// The square xor mask for `perspective` with its king on `king_sq`,
+    /// and the bucket of the king
+    fn orient(perspective: Color, king_sq: Square) -> (u32, usize) {
+        let vertical = match perspective {
+            Color::White => 0,
+            Color::Black => 56,
+        };
+        let king_sq = king_sq.index() ^ vertical;
+        let horizontal = if king_sq % 8 < 4 { 7 } else { 0 };
+        let king_sq = king_sq ^ horizontal;
+
+        (
+            vertical ^ horizontal,
+            KING_BUCKETS[king_sq as usize] as usize,
+        )
+    }
+
+    /// The feature of `piece` on `sq` within a bucket
+    fn piece_index(perspective: Color, flip: u32, piece: Piece, sq: Square) -> Option<usize> {
+        let is_enemy = usize::from(piece.color() != perspective);
+        let plane = match piece.piece_type() {
+            PieceType::King => 10,
+            PieceType::None => return None,
+            piece_type => piece_type.ordinal() as usize * 2 + is_enemy,
+        };
+        Some(plane * 64 + (sq.index() ^ flip) as usize)
+    }
+}
+
+impl FeatureSet for HalfKAv2Hm {
+    fn num_features(&self) -> usize {
+        32 * PIECE_FEATURES
+    }
+
+    fn num_virtual_features(&self) -> usize {
+        if self.factorized {
+            PIECE_FEATURES
+        } else {
+            0
+        }
+    }
+
+    fn max_active_features(&self) -> usize {
+        if self.factorized {
+            64
+        } else {
+            32
+        }
+    }
+
+    fn index(
+        &self,
+        perspective: Color,
+        king_sq: Square,
+        piece: Piece,
+        sq: Square,
+    ) -> Option<usize> {
+        let (flip, bucket) = Self::orient(perspective, king_sq);
+        Some(bucket * PIECE_FEATURES + Self::piece_index(perspective, flip, piece, sq)?)
+    }
+
+    fn factor_indices(&self, pos: &Position, perspective: Color, f: &mut dyn FnMut(usize)) {
+        if !self.factorized {
+            return;
+        }
+
+        let (flip, _) = Self::orient(perspective, pos.king_sq(perspective));
+        for sq in pos.occupied().iter() {
+            if let Some(index) = Self::piece_index(perspective, flip, pos.piece_at(sq), sq) {
+                f(self.num_features() + index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sq(name: &str) -> Square {
+        Square::from_string(name).unwrap()
+    }
+
+    #[test]
+    fn test_halfka_indices() {
+        let set = HalfKAv2Hm::new();
+        assert_eq!(set.num_features(), 22528);
+
+        // indices as computed by halfka_idx of nnue-pytorch's
+        // halfka_v2_hm.py; king on e1, bucket 31 and no mirroring
+        let e1 = sq("e1");
+        assert_eq!(
+            set.index(Color::White, e1, Piece::WHITE_PAWN, sq("a2")),
+            Some(21832)
+        );
+        assert_eq!(
+            set.index(Color::White, e1, Piece::WHITE_KING, e1),
+            Some(22468)
+        );
+        assert_eq!(
+            set.index(Color::White, e1, Piece::BLACK_KING, sq("e8")),
+            Some(22524)
+        );
+
+        // king on d1 mirrors a2 to h2, the king to e1
+        assert_eq!(
+            set.index(Color::White, sq("d1"), Piece::WHITE_PAWN, sq("a2")),
+            Some(21839)
+        );
+
+        // king on h8 is bucket 0
+        assert_eq!(
+            set.index(Color::White, sq("h8"), Piece::BLACK_QUEEN, sq("d8")),
+            Some(9 * 64 + 59)
+        );
+
+        // black sees its a7 pawn on a2 and the white a2 pawn on a7, king g8 is
+        // g1, bucket 29
+        let g8 = sq("g8");
+        assert_eq!(
+            set.index(Color::Black, g8, Piece::BLACK_PAWN, sq("a7")),
+            Some(20424)
+        );
+        assert_eq!(
+            set.index(Color::Black, g8, Piece::WHITE_PAWN, sq("a2")),
+            Some(20528)
+        );
+        assert_eq!(set.index(Color::Black, g8, Piece::NONE, sq("a2")), None);
+    }
+
+    #[test]
+    fn test_halfka_factorized() {
+        let set = HalfKAv2Hm::new().factorized();
+        let pos = Position::from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+        assert_eq!(set.num_virtual_features(), 704);
+
+        let mut indices = [-1; 64];
+        let mut values = [0.0; 64];
+        let count = set.fill_features(&pos, Color::White, &mut indices, &mut values);
+        assert_eq!(
+            indices[..count],
+            [22468, 21832, 22524, 22528 + 644, 22528 + 8, 22528 + 700]
+        );
+        assert_eq!(indices[count], -1);
+    }
+}
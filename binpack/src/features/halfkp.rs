@@ -0,0 +1,315 @@
+//! HalfKP: every piece but the kings, by the square of the own king.
+//!
+//! Squares are flipped vertically for black, so both perspectives see their
+//! own pieces from the first rank. A feature is
+//! `bucket * 640 + is_enemy * 320 + piece_type * 64 + square` with the piece
+//! types pawn to queen, where the bucket is that of the own king. With the
+//! default [`KingBuckets`] every king square is a bucket of its own;
+//! [`KingBuckets::new`] groups squares to share weights.
+//!
+//! This is the layout `halfkp/train.py` and the pybinpack loader have always
+//! used, not Stockfish's: Stockfish numbers a HalfKP feature
+//! `1 + square + piece * 64 + king_square * 641`, with the pieces
+//! interleaved by colour and an unused first slot per king square, so its
+//! networks cannot be loaded with these indices.
+//!
+//! ```
+//! use sfbinpack::{
+//!     chess::{color::Color, coords::Square, piece::Piece, position::Position},
+//!     features::{halfkp::HalfKP, FeatureSet},
+//! };
+//!
+//! let halfkp = HalfKP::new();
+//! let pos = Position::new();
+//! let a2 = Square::from_string("a2").unwrap();
+//!
+//! // own king on e1, bucket 4
+//! let index = halfkp.index(Color::White, pos.king_sq(Color::White), Piece::WHITE_PAWN, a2);
+//! assert_eq!(index, Some(4 * 640 + 8));
+//!
+//! let mut indices = [-1; 32];
+//! let mut values = [0.0; 32];
+//! assert_eq!(halfkp.fill_features(&pos, Color::White, &mut indices, &mut values), 30);
+//! ```
+
+use crate::chess::{
+    color::Color, coords::Square, piece::Piece, piecetype::PieceType, position::Position,
+};
+
+use super::FeatureSet;
+
+/// Pieces other than kings, by side, type and square
+const PIECE_FEATURES: usize = 2 * 5 * 64;
+
+/// Which bucket of weights each square of the own king selects, seen from
+/// the first rank of its side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KingBuckets {
+    map: [u8; 64],
+    count: usize,
+}
+
+impl Default for KingBuckets {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl KingBuckets {
+    /// A bucket per square, bucket `n` for square `n`
+    pub const fn identity() -> Self {
+        let mut map = [0; 64];
+        let mut sq = 0;
+        while sq < 64 {
+            map[sq] = sq as u8;
+            sq += 1;
+        }
+        Self { map, count: 64 }
+    }
+
+    /// Buckets `0..=max` of `map` by square. Numbers no square maps to
+    /// still count, their weights just never train.
+    pub const fn new(map: [u8; 64]) -> Self {
+        let mut max = 0;
+        let mut sq = 0;
+        while sq < 64 {
+            if map[sq] > max {
+                max = map[sq];
+            }
+            sq += 1;
+        }
+        Self {
+            map,
+            count: max as usize + 1,
+        }
+    }
+
+    /// 32 buckets, a king on files a-d shares the bucket of the square
+    /// mirrored to files e-h: `rank * 4 + file - 4`
+    pub const fn mirrored() -> Self {
+        let mut map = [0; 64];
+        let mut sq = 0;
+        while sq < 64 {
+            let file = sq % 8;
+            let file = if file < 4 { 7 - file } else { file };
+            map[sq] = ((sq / 8) * 4 + file - 4) as u8;
+            sq += 1;
+        }
+        Self::new(map)
+    }
+
+    pub const fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The bucket of the own king on `sq`, oriented as seen by its side
+    pub const fn bucket(&self, sq: usize) -> usize {
+        self.map[sq] as usize
+    }
+}
+
+/// HalfKP features, optionally factorized into a virtual K feature for the
+/// bucket of the own king and a virtual P feature per piece that does not
+/// depend on the king
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HalfKP {
+    buckets: KingBuckets,
+    factorized: bool,
+}
+
+impl Default for HalfKP {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HalfKP {
+    /// A bucket per king square, not factorized
+    pub const fn new() -> Self {
+        Self::with_buckets(KingBuckets::identity())
+    }
+
+    pub const fn with_buckets(buckets: KingBuckets) -> Self {
+        Self {
+            buckets,
+            factorized: false,
+        }
+    }
+
+    /// Adds the virtual K and P features
+    pub const fn factorized(self) -> Self {
+        Self {
+            factorized: true,
+            ..self
+        }
+    }
+
+    pub const fn buckets(&self) -> &KingBuckets {
+        &self.buckets
+    }
+
+    /// The feature of `piece` on `sq` independent of the king, None for
+    /// kings
+    fn piece_index(perspective: Color, piece: Piece, sq: Square) -> Option<usize> {
+        let piece_type = match piece.piece_type() {
+            PieceType::King | PieceType::None => return None,
+            piece_type => piece_type.ordinal() as usize,
+        };
+        let is_enemy = usize::from(piece.color() != perspective);
+        Some(is_enemy * 320 + piece_type * 64 + orient(perspective, sq))
+    }
+}
+
+impl FeatureSet for HalfKP {
+    fn num_features(&self) -> usize {
+        self.buckets.count() * PIECE_FEATURES
+    }
+
+    fn num_virtual_features(&self) -> usize {
+        if self.factorized {
+            self.buckets.count() + PIECE_FEATURES
+        } else {
+            0
+        }
+    }
+
+    fn max_active_features(&self) -> usize {
+        if self.factorized {
+            64
+        } else {
+            32
+        }
+    }
+
+    fn index(
+        &self,
+        perspective: Color,
+        king_sq: Square,
+        piece: Piece,
+        sq: Square,
+    ) -> Option<usize> {
+        let bucket = self.buckets.bucket(orient(perspective, king_sq));
+        Some(bucket * PIECE_FEATURES + Self::piece_index(perspective, piece, sq)?)
+    }
+
+    fn factor_indices(&self, pos: &Position, perspective: Color, f: &mut dyn FnMut(usize)) {
+        if !self.factorized {
+            return;
+        }
+
+        let offset = self.num_features();
+        let king_sq = orient(perspective, pos.king_sq(perspective));
+        f(offset + self.buckets.bucket(king_sq));
+
+        let offset = offset + self.buckets.count();
+        for sq in pos.occupied().iter() {
+            if let Some(index) = Self::piece_index(perspective, pos.piece_at(sq), sq) {
+                f(offset + index);
+            }
+        }
+    }
+}
+
+/// `sq` as seen by `perspective`, flipped vertically for black
+fn orient(perspective: Color, sq: Square) -> usize {
+    match perspective {
+        Color::White => sq.index() as usize,
+        Color::Black => (sq.index() ^ 56) as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(set: &HalfKP, pos: &Position, perspective: Color) -> Vec<i32> {
+        let mut indices = vec![-1; set.max_active_features()];
+        let mut values = vec![0.0; set.max_active_features()];
+        let count = set.fill_features(pos, perspective, &mut indices, &mut values);
+        assert!(values[..count].iter().all(|&v| v == 1.0));
+        indices.truncate(count);
+        indices
+    }
+
+    fn sq(name: &str) -> Square {
+        Square::from_string(name).unwrap()
+    }
+
+    #[test]
+    fn test_halfkp_indices() {
+        let set = HalfKP::new();
+        let pos = Position::new();
+        assert_eq!(set.num_features(), 40960);
+        assert_eq!(set.num_virtual_features(), 0);
+
+        // own king e1 and e8 are both square 4 for their side
+        let white = features(&set, &pos, Color::White);
+        assert_eq!(white.len(), 30);
+        assert_eq!(white[0], 4 * 640 + 3 * 64);
+        assert!(white.contains(&(4 * 640 + 8)));
+
+        let black = features(&set, &pos, Color::Black);
+        // the white a2 pawn is an enemy on a7 for black
+        assert!(black.contains(&(4 * 640 + 320 + 48)));
+        // the black b8 knight is an own knight on b1
+        assert!(black.contains(&(4 * 640 + 64 + 1)));
+
+        assert_eq!(
+            set.index(Color::White, sq("g1"), Piece::BLACK_QUEEN, sq("d8")),
+            Some(6 * 640 + 320 + 4 * 64 + 59)
+        );
+        assert_eq!(
+            set.index(Color::White, sq("g1"), Piece::BLACK_KING, sq("g8")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_halfkp_factorized() {
+        let set = HalfKP::new().factorized();
+        let pos = Position::from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+        assert_eq!(set.num_virtual_features(), 64 + 640);
+        assert_eq!(set.max_active_features(), 64);
+
+        // the real feature, K for e1 and P for a2
+        assert_eq!(
+            features(&set, &pos, Color::White),
+            [4 * 640 + 8, 40960 + 4, 40960 + 64 + 8]
+        );
+        assert_eq!(
+            features(&set, &pos, Color::Black),
+            [4 * 640 + 320 + 48, 40960 + 4, 40960 + 64 + 320 + 48]
+        );
+    }
+
+    #[test]
+    fn test_king_buckets() {
+        let buckets = KingBuckets::mirrored();
+        assert_eq!(buckets.count(), 32);
+        assert_eq!(buckets.bucket(sq("e1").index() as usize), 0);
+        assert_eq!(buckets.bucket(sq("d1").index() as usize), 0);
+        assert_eq!(buckets.bucket(sq("a8").index() as usize), 31);
+
+        let set = HalfKP::with_buckets(buckets).factorized();
+        assert_eq!(set.num_features(), 32 * 640);
+        assert_eq!(set.num_virtual_features(), 32 + 640);
+
+        // kings on d1 and e1 share the weights of the pawn on a2
+        let a2 = sq("a2");
+        let e1 = set.index(Color::White, sq("e1"), Piece::WHITE_PAWN, a2);
+        assert_eq!(e1, set.index(Color::White, sq("d1"), Piece::WHITE_PAWN, a2));
+        assert_eq!(e1, Some(8));
+
+        // as seen by black, the black king on h8 is on h1, bucket 3
+        let pos = Position::from_fen("7k/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+        assert_eq!(
+            features(&set, &pos, Color::Black),
+            [3 * 640 + 320 + 48, 32 * 640 + 3, 32 * 640 + 32 + 320 + 48]
+        );
+
+        // a gap in the numbers still counts as a bucket
+        let mut map = [0; 64];
+        map[63] = 2;
+        assert_eq!(KingBuckets::new(map).count(), 3);
+    }
+}
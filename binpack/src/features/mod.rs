@@ -0,0 +1,360 @@
+//! Scalar features of a position, for auxiliary training targets and
+//! analysis reports.
+//!
+//! Each feature is a function of a [`Position`] and a side, mobility per
+//! piece type, attackers of the king ring and pawn structure masks, and
+//! [`Features::of`] computes all of them for both sides:
+//!
+//! ```
+//! use std::fs::File;
+//! use sfbinpack::{chess::color::Color, features::Features, CompressedTrainingDataEntryReader};
+//!
+//! let file = File::open("test/ep1.binpack").unwrap();
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//!
+//! while reader.has_next() {
+//!     let entry = reader.next();
+//!     let features = Features::of(&entry.pos);
+//!     let white = features.side(Color::White);
+//!     println!(
+//!         "mobility {} passed pawns {}",
+//!         white.mobility.total(),
+//!         white.passed_pawns.count()
+//!     );
+//! }
+//! ```
+//!
+//! The definitions are the simple textbook ones, not those of any engine's
+//! evaluation.
+//!
+//! The sparse inputs of NNUE networks are [`FeatureSet`]s: [`halfkp::HalfKP`]
+//! and [`halfka::HalfKAv2Hm`], optionally with virtual factor features, so
+//! trainers written in Rust and the Python loader index positions the same
+//! way.
+
+pub mod halfka;
+pub mod halfkp;
+
+use crate::chess::{
+    attacks, bitboard::Bitboard, color::Color, coords::Square, piece::Piece, piecetype::PieceType,
+    position::Position,
+};
+
+/// Sparse input features of a network, seen from one side.
+///
+/// Every piece on the board is at most one real feature, whose index depends
+/// on the piece, its square and the king of the perspective. Factorized sets
+/// add virtual features, indexed after the real ones, that share weights
+/// between real features during training and are folded into them when the
+/// network is exported.
+pub trait FeatureSet {
+    /// Real features, indices `0..num_features()`
+    fn num_features(&self) -> usize;
+
+    /// Virtual factor features, indices from `num_features()` on
+    fn num_virtual_features(&self) -> usize {
+        0
+    }
+
+    /// Most features, real and virtual, active for one perspective
+    fn max_active_features(&self) -> usize;
+
+    /// The real feature of `piece` on `sq` seen by `perspective` with its
+    /// king on `king_sq`, None if the piece is not an input
+    fn index(&self, perspective: Color, king_sq: Square, piece: Piece, sq: Square)
+        -> Option<usize>;
+
+    /// Calls `f` with the virtual features active in `pos` for
+    /// `perspective`, none unless the set is factorized
+    fn factor_indices(&self, pos: &Position, perspective: Color, f: &mut dyn FnMut(usize)) {
+        let _ = (pos, perspective, f);
+    }
+
+    /// Writes the active features of `pos` for `perspective`, real ones by
+    /// square and then the virtual ones, into `indices` with a value of 1
+    /// each, up to the length of `indices`. Returns how many were written,
+    /// the rest of the slices is left as it is.
+    fn fill_features(
+        &self,
+        pos: &Position,
+        perspective: Color,
+        indices: &mut [i32],
+        values: &mut [f32],
+    ) -> usize {
+        let mut count = 0;
+        let mut push = |index: usize| {
+            if count < indices.len() {
+                indices[count] = index as i32;
+                values[count] = 1.0;
+                count += 1;
+            }
+        };
+
+        let king_sq = pos.king_sq(perspective);
+        for sq in pos.occupied().iter() {
+            if let Some(index) = self.index(perspective, king_sq, pos.piece_at(sq), sq) {
+                push(index);
+            }
+        }
+        self.factor_indices(pos, perspective, &mut push);
+        count
+    }
+}
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+
+/// Squares each piece type of a side can move to, summed over its pieces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mobility {
+    pub knight: u32,
+    pub bishop: u32,
+    pub rook: u32,
+    pub queen: u32,
+    pub king: u32,
+}
+
+impl Mobility {
+    pub fn total(&self) -> u32 {
+        self.knight + self.bishop + self.rook + self.queen + self.king
+    }
+}
+
+/// The features of one side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SideFeatures {
+    pub mobility: Mobility,
+    /// Pieces of the other side that attack the king ring of this one
+    pub king_ring_attackers: u32,
+    /// Squares of the king ring the other side attacks, 0 to 9
+    pub king_ring_attacks: u32,
+    pub passed_pawns: Bitboard,
+    pub isolated_pawns: Bitboard,
+    pub doubled_pawns: Bitboard,
+    pub pawn_attacks: Bitboard,
+}
+
+impl SideFeatures {
+    pub fn of(pos: &Position, color: Color) -> Self {
+        let ring = king_ring(pos, color);
+        let attacked = attacked_squares(pos, !color);
+
+        Self {
+            mobility: mobility(pos, color),
+            king_ring_attackers: king_ring_attackers(pos, color),
+            king_ring_attacks: (ring & attacked).count(),
+            passed_pawns: passed_pawns(pos, color),
+            isolated_pawns: isolated_pawns(pos, color),
+            doubled_pawns: doubled_pawns(pos, color),
+            pawn_attacks: pawn_attacks(pos, color),
+        }
+    }
+}
+
+/// The features of both sides of a position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    pub white: SideFeatures,
+    pub black: SideFeatures,
+}
+
+impl Features {
+    pub fn of(pos: &Position) -> Self {
+        Self {
+            white: SideFeatures::of(pos, Color::White),
+            black: SideFeatures::of(pos, Color::Black),
+        }
+    }
+
+    pub fn side(&self, color: Color) -> &SideFeatures {
+        match color {
+            Color::White => &self.white,
+            Color::Black => &self.black,
+        }
+    }
+}
+
+/// Squares the pieces of `color` attack, not counting squares held by its
+/// own pieces or attacked by pawns of the other side, as moving there loses
+/// the piece to a pawn. Pins and checks are ignored.
+pub fn mobility(pos: &Position, color: Color) -> Mobility {
+    let area = !(pos.pieces_bb(color) | pawn_attacks(pos, !color));
+    let occupied = pos.occupied();
+    let count = |pt| {
+        pos.pieces_bb_color(color, pt)
+            .iter()
+            .map(|sq| (attacks::piece_attacks(pt, sq, occupied) & area).count())
+            .sum()
+    };
+
+    Mobility {
+        knight: count(PieceType::Knight),
+        bishop: count(PieceType::Bishop),
+        rook: count(PieceType::Rook),
+        queen: count(PieceType::Queen),
+        king: count(PieceType::King),
+    }
+}
+
+/// The king of `color` and the squares next to it
+pub fn king_ring(pos: &Position, color: Color) -> Bitboard {
+    let king = pos.king_sq(color);
+    attacks::king(king) | Bitboard::from_square(king)
+}
+
+/// Pieces of the other side that attack a square of the king ring of
+/// `color`, each counted once however many squares it attacks
+pub fn king_ring_attackers(pos: &Position, color: Color) -> u32 {
+    let ring = king_ring(pos, color);
+    let occupied = pos.occupied();
+
+    let mut attackers = 0;
+    for (sq, pt) in pieces(pos, !color) {
+        let attacks = match pt {
+            PieceType::Pawn => attacks::pawn(!color, sq),
+            pt => attacks::piece_attacks(pt, sq, occupied),
+        };
+        attackers += ((attacks & ring).count() > 0) as u32;
+    }
+    attackers
+}
+
+/// Squares the pawns of `color` attack
+pub fn pawn_attacks(pos: &Position, color: Color) -> Bitboard {
+    let pawns = pos.pieces_bb_color(color, PieceType::Pawn).bits();
+    let west = pawns & !FILE_A;
+    let east = pawns & !(FILE_A << 7);
+
+    Bitboard::new(match color {
+        Color::White => (west << 7) | (east << 9),
+        Color::Black => (west >> 9) | (east >> 7),
+    })
+}
+
+/// Pawns of `color` without a pawn of the other side in front of them on
+/// their own or a neighbouring file
+pub fn passed_pawns(pos: &Position, color: Color) -> Bitboard {
+    let theirs = pos.pieces_bb_color(!color, PieceType::Pawn).bits();
+
+    let passed = pos
+        .pieces_bb_color(color, PieceType::Pawn)
+        .iter()
+        .filter(|&sq| front_span(color, sq) & adjacent_files(sq, true) & theirs == 0)
+        .fold(0, |passed, sq| passed | 1 << sq.index());
+    Bitboard::new(passed)
+}
+
+/// Pawns of `color` without a pawn of their own on a neighbouring file
+pub fn isolated_pawns(pos: &Position, color: Color) -> Bitboard {
+    let ours = pos.pieces_bb_color(color, PieceType::Pawn);
+
+    let isolated = ours
+        .iter()
+        .filter(|&sq| adjacent_files(sq, false) & ours.bits() == 0)
+        .fold(0, |isolated, sq| isolated | 1 << sq.index());
+    Bitboard::new(isolated)
+}
+
+/// Pawns of `color` with a pawn of their own in front of them on the same
+/// file, so a file of two pawns has one doubled pawn, the rear one
+pub fn doubled_pawns(pos: &Position, color: Color) -> Bitboard {
+    let ours = pos.pieces_bb_color(color, PieceType::Pawn);
+    let file = |sq: Square| FILE_A << (sq.index() & 7);
+
+    let doubled = ours
+        .iter()
+        .filter(|&sq| front_span(color, sq) & file(sq) & ours.bits() != 0)
+        .fold(0, |doubled, sq| doubled | 1 << sq.index());
+    Bitboard::new(doubled)
+}
+
+/// Every square attacked by a piece of `color`
+fn attacked_squares(pos: &Position, color: Color) -> Bitboard {
+    let occupied = pos.occupied();
+    pieces(pos, color).fold(pawn_attacks(pos, color), |attacked, (sq, pt)| match pt {
+        PieceType::Pawn => attacked,
+        pt => attacked | attacks::piece_attacks(pt, sq, occupied),
+    })
+}
+
+/// The pieces of `color` with their types
+fn pieces(pos: &Position, color: Color) -> impl Iterator<Item = (Square, PieceType)> + '_ {
+    pos.pieces_bb(color)
+        .iter()
+        .map(|sq| (sq, pos.piece_at(sq).piece_type()))
+}
+
+/// The ranks in front of `sq` as seen by `color`
+fn front_span(color: Color, sq: Square) -> u64 {
+    let rank = sq.index() >> 3;
+    match color {
+        Color::White if rank < 7 => u64::MAX << (8 * (rank + 1)),
+        Color::Black => (1u64 << (8 * rank)) - 1,
+        Color::White => 0,
+    }
+}
+
+/// The files next to the file of `sq`, and its own file with `own`
+fn adjacent_files(sq: Square, own: bool) -> u64 {
+    let file = FILE_A << (sq.index() & 7);
+    let sides = ((file & !(FILE_A << 7)) << 1) | ((file & !FILE_A) >> 1);
+    if own {
+        sides | file
+    } else {
+        sides
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn squares(bb: Bitboard) -> Vec<String> {
+        bb.iter().map(|sq| sq.to_string()).collect()
+    }
+
+    #[test]
+    fn test_startpos() {
+        let pos = Position::new();
+        let features = Features::of(&pos);
+        assert_eq!(features.white.mobility.knight, 4);
+        assert_eq!(features.white.mobility.total(), 4);
+        assert_eq!(features.black.mobility, features.white.mobility);
+        assert_eq!(features.white.king_ring_attackers, 0);
+        assert_eq!(features.white.passed_pawns.count(), 0);
+        assert_eq!(features.white.isolated_pawns.count(), 0);
+        assert_eq!(features.white.doubled_pawns.count(), 0);
+        assert_eq!(squares(features.white.pawn_attacks).len(), 8);
+        assert_eq!(squares(pawn_attacks(&pos, Color::Black))[0], "a6");
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        // white: a-pawn passed and isolated, doubled c-pawns; black: the
+        // e-pawn blocks d4 from being passed
+        let pos = Position::from_fen("4k3/8/4p3/8/3P4/2P5/P1P5/4K3 w - - 0 1").unwrap();
+
+        assert_eq!(
+            squares(passed_pawns(&pos, Color::White)),
+            ["a2", "c2", "c3"]
+        );
+        assert_eq!(squares(isolated_pawns(&pos, Color::White)), ["a2"]);
+        assert_eq!(squares(doubled_pawns(&pos, Color::White)), ["c2"]);
+        assert_eq!(squares(passed_pawns(&pos, Color::Black)), [] as [&str; 0]);
+        assert_eq!(squares(isolated_pawns(&pos, Color::Black)), ["e6"]);
+    }
+
+    #[test]
+    fn test_king_safety_and_mobility() {
+        // the knight and the queen aim at the black king, the rook does not
+        let pos = Position::from_fen("6k1/5ppp/8/4N2Q/8/8/8/R3K3 b - - 0 1").unwrap();
+        let black = SideFeatures::of(&pos, Color::Black);
+        assert_eq!(black.king_ring_attackers, 2);
+        // f7 and h7
+        assert_eq!(black.king_ring_attacks, 2);
+
+        // g6 is covered by black pawns
+        let white = mobility(&pos, Color::White);
+        assert_eq!(white.knight, 7);
+        assert_eq!(Features::of(&pos).side(Color::White).mobility, white);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, Read, Seek},
+};
+
+use thiserror::Error;
+
+use crate::{
+    chess::{coords::Square, position::Position},
+    CompressedReaderError, CompressedTrainingDataEntryReader, TrainingDataEntry,
+};
+
+use super::EntryFilter;
+
+#[derive(Debug, Error)]
+pub enum ExcludeError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid FEN on line {line}: {fen}")]
+    InvalidFen { line: usize, fen: String },
+
+    #[error("Reading failed: {0}")]
+    Read(#[from] CompressedReaderError),
+}
+
+/// Skips entries whose position is in a set, e.g. the positions of a test
+/// set that must not leak into the training data.
+///
+/// Positions are compared by [`Position::canonical_key`], so the move
+/// counters and en passant squares without a legal capture do not matter. The set holds the 64 bit keys only, a few hundred million
+/// positions fit in a few gigabytes.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludePositions {
+    keys: HashSet<u64>,
+}
+
+impl ExcludePositions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, pos: &Position) {
+        self.keys.insert(pos.canonical_key());
+    }
+
+    pub fn contains(&self, pos: &Position) -> bool {
+        self.keys.contains(&pos.canonical_key())
+    }
+
+    /// Number of distinct positions in the set
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Adds one position per line of FEN or EPD, the operations after the
+    /// first four fields of an EPD line are ignored. Empty lines and lines
+    /// starting with `#` are skipped. Returns the number of positions read.
+    pub fn load_fens(&mut self, reader: impl BufRead) -> Result<usize, ExcludeError> {
+        let mut count = 0;
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let pos = parse_fen_or_epd(line).ok_or_else(|| ExcludeError::InvalidFen {
+                line: idx + 1,
+                fen: line.to_string(),
+            })?;
+            self.insert(&pos);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Adds the position of every entry of a binpack. Returns the number of
+    /// entries read.
+    pub fn load_binpack<T: Read + Seek>(
+        &mut self,
+        reader: &mut CompressedTrainingDataEntryReader<T>,
+    ) -> Result<usize, ExcludeError> {
+        let mut count = 0;
+
+        while reader.has_next() {
+            self.insert(&reader.try_next()?.pos);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl Extend<Position> for ExcludePositions {
+    fn extend<I: IntoIterator<Item = Position>>(&mut self, positions: I) {
+        self.keys
+            .extend(positions.into_iter().map(|pos| pos.canonical_key()));
+    }
+}
+
+impl FromIterator<Position> for ExcludePositions {
+    fn from_iter<I: IntoIterator<Item = Position>>(positions: I) -> Self {
+        let mut set = Self::new();
+        set.extend(positions);
+        set
+    }
+}
+
+impl EntryFilter for ExcludePositions {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        !self.contains(&entry.pos)
+    }
+}
+
+/// The position of a FEN, or of the first four fields of an EPD line
+pub(crate) fn parse_fen_or_epd(line: &str) -> Option<Position> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [board, stm, castling, ep, ..] = fields[..] else {
+        return None;
+    };
+
+    if !matches!(stm, "w" | "b") || (ep != "-" && Square::from_string(ep).is_none()) {
+        return None;
+    }
+
+    Position::from_fen(&format!("{board} {stm} {castling} {ep} 0 1")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Cursor};
+
+    use super::*;
+
+    #[test]
+    fn test_load_fens() {
+        let text = "\
+# test positions
+rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1
+
+4k3/8/8/8/8/8/3P4/3QK3 w - - bm Qd8+; id \"mate\";
+";
+        let mut set = ExcludePositions::new();
+        assert_eq!(set.load_fens(Cursor::new(text)).unwrap(), 2);
+        assert_eq!(set.len(), 2);
+
+        let fen = |fen| Position::from_fen(fen).unwrap();
+        // the move counters do not matter
+        assert!(set.contains(&fen(
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 5 30"
+        )));
+        assert!(set.contains(&fen("4k3/8/8/8/8/8/3P4/3QK3 w - - 0 1")));
+        // nor an en passant square black can not capture on
+        assert!(set.contains(&fen(
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        )));
+        assert!(!set.contains(&fen("4k3/8/8/8/8/8/3P4/3QK3 b - - 0 1")));
+        assert!(!set.contains(&Position::new()));
+    }
+
+    #[test]
+    fn test_load_fens_invalid() {
+        for text in [
+            "8/8/8 w",
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - z9",
+        ] {
+            let mut set = ExcludePositions::new();
+            let err = set.load_fens(Cursor::new(format!("\n{text}"))).unwrap_err();
+            assert!(
+                matches!(err, ExcludeError::InvalidFen { line: 2, .. }),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_exclude_binpack() {
+        let open = || {
+            let file = File::open("./test/ep1.binpack").unwrap();
+            CompressedTrainingDataEntryReader::new(file).unwrap()
+        };
+
+        let mut set = ExcludePositions::new();
+        let count = set.load_binpack(&mut open()).unwrap();
+        assert!(count > 0);
+
+        let mut reader = open();
+        while reader.has_next() {
+            assert!(!set.keep(&reader.next()));
+        }
+
+        let mut set: ExcludePositions = [Position::new()].into_iter().collect();
+        let entry = TrainingDataEntry {
+            pos: Position::new(),
+            ..Default::default()
+        };
+        assert!(!set.keep(&entry));
+    }
+}
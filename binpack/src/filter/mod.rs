@@ -0,0 +1,685 @@
+//! Deciding which training entries to keep.
+//!
+//! An [`EntryFilter`] says whether an entry is kept. Filters combine with
+//! [`and`](EntryFilter::and), [`or`](EntryFilter::or) and
+//! [`not`](EntryFilter::not), and [`from_fn`] turns a closure into a
+//! filter. The built-ins are the skipping rules of the nnue-pytorch
+//! data loader, each keeps every entry except the ones it is named after.
+//!
+//! ```
+//! use sfbinpack::filter::{
+//!     from_fn, EntryFilter, SkipCaptures, SkipEarlyPlies, SkipInCheck, SkipScoreNone,
+//! };
+//! use sfbinpack::chess::{position::Position, r#move::Move};
+//! use sfbinpack::TrainingDataEntry;
+//!
+//! let mut filter = SkipScoreNone
+//!     .and(SkipEarlyPlies::new(16))
+//!     .and(SkipCaptures)
+//!     .and(SkipInCheck)
+//!     .and(from_fn(|entry: &TrainingDataEntry| entry.score.abs() < 3000));
+//!
+//! let pos = Position::new();
+//! let mv = Move::from_uci(&pos, "e2e4").unwrap();
+//! let entry = TrainingDataEntry { pos, mv, score: 30, ply: 40, result: 0 };
+//! assert!(filter.keep(&entry));
+//! ```
+//!
+//! [`PieceCountBalancer`] skips entries so that the piece counts of the kept
+//! ones follow a target distribution, [`ExcludePositions`] skips the
+//! positions of a held out set and [`SkipOpening`] skips the opening of
+//! every game.
+//!
+//! Filters are called in order and the combinators stop at the first filter
+//! that decides the outcome, so a stateful filter only sees the entries that
+//! reach it.
+//!
+//! Instead of skipping entries, a filter can weigh them with the
+//! probability it keeps them, see [`EntryFilter::weighted`] and
+//! [`EntryWeight`], for a trainer that scales the loss of every entry.
+//!
+//! The random filters draw from a generator seeded from the OS unless they
+//! are built with `seeded` or `with_rng`; with a seed a run over the same
+//! input keeps the same entries every time.
+
+mod exclude;
+mod opening;
+mod piece_count;
+mod weight;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    chess::{
+        color::Color, coords::Square, piece::Piece, piecetype::PieceType, position::Position,
+        r#move::MoveType,
+    },
+    TrainingDataEntry,
+};
+
+pub(crate) use exclude::parse_fen_or_epd;
+pub use exclude::{ExcludeError, ExcludePositions};
+pub use opening::{Book, NoBook, OpeningLabel, OpeningTracker, SkipOpening};
+pub use piece_count::{quadratic_weights, PieceCountBalancer, PieceCountStats, PIECE_COUNTS};
+pub use weight::{weight_fn, EntryWeight, PhaseWeight, Times, WeightFn, Weighted};
+
+pub use crate::formats::VALUE_NONE;
+
+/// Decides whether an entry is kept.
+pub trait EntryFilter {
+    /// Whether `entry` is kept. Takes `&mut self` so filters can keep state,
+    /// like a random number generator or statistics of what they have seen.
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool;
+
+    /// The probability that `entry` is kept, for weighing entries instead of
+    /// skipping them, see [`weighted`](Self::weighted). Random filters give
+    /// the probability they skip with and draw nothing, the others 0 or 1
+    /// by calling [`keep`](Self::keep).
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        f64::from(u8::from(self.keep(entry)))
+    }
+
+    /// Removes every entry of `entries` that is not kept, in order
+    fn retain(&mut self, entries: &mut Vec<TrainingDataEntry>) {
+        entries.retain(|entry| self.keep(entry));
+    }
+
+    /// Keeps entries both filters keep, `other` only sees entries this one keeps
+    fn and<F: EntryFilter>(self, other: F) -> And<Self, F>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Keeps entries either filter keeps, `other` only sees entries this one drops
+    fn or<F: EntryFilter>(self, other: F) -> Or<Self, F>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// Keeps the entries this filter drops
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+
+    /// Weighs every entry by the probability this filter keeps it instead
+    /// of skipping any
+    fn weighted(self) -> Weighted<Self>
+    where
+        Self: Sized,
+    {
+        Weighted(self)
+    }
+}
+
+impl<F: EntryFilter + ?Sized> EntryFilter for Box<F> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        (**self).keep(entry)
+    }
+
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        (**self).keep_probability(entry)
+    }
+}
+
+impl<F: EntryFilter + ?Sized> EntryFilter for &mut F {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        (**self).keep(entry)
+    }
+
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        (**self).keep_probability(entry)
+    }
+}
+
+/// A filter keeping the entries for which `keep` returns true
+pub fn from_fn<F: FnMut(&TrainingDataEntry) -> bool>(keep: F) -> FromFn<F> {
+    FromFn(keep)
+}
+
+/// See [`from_fn`]
+#[derive(Debug, Clone)]
+pub struct FromFn<F>(F);
+
+impl<F: FnMut(&TrainingDataEntry) -> bool> EntryFilter for FromFn<F> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        (self.0)(entry)
+    }
+}
+
+/// See [`EntryFilter::and`]
+#[derive(Debug, Clone)]
+pub struct And<A, B>(A, B);
+
+impl<A: EntryFilter, B: EntryFilter> EntryFilter for And<A, B> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        self.0.keep(entry) && self.1.keep(entry)
+    }
+
+    /// The product, both filters see every entry
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        self.0.keep_probability(entry) * self.1.keep_probability(entry)
+    }
+}
+
+/// See [`EntryFilter::or`]
+#[derive(Debug, Clone)]
+pub struct Or<A, B>(A, B);
+
+impl<A: EntryFilter, B: EntryFilter> EntryFilter for Or<A, B> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        self.0.keep(entry) || self.1.keep(entry)
+    }
+
+    /// Both filters see every entry
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        let skip = 1.0 - self.0.keep_probability(entry);
+        1.0 - skip * (1.0 - self.1.keep_probability(entry))
+    }
+}
+
+/// See [`EntryFilter::not`]
+#[derive(Debug, Clone)]
+pub struct Not<A>(A);
+
+impl<A: EntryFilter> EntryFilter for Not<A> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        !self.0.keep(entry)
+    }
+
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        1.0 - self.0.keep_probability(entry)
+    }
+}
+
+/// Skips entries whose score is [`VALUE_NONE`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkipScoreNone;
+
+impl EntryFilter for SkipScoreNone {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        entry.score != VALUE_NONE
+    }
+}
+
+/// Skips entries up to and including the given ply
+#[derive(Debug, Clone, Copy)]
+pub struct SkipEarlyPlies {
+    last_skipped: u16,
+}
+
+impl SkipEarlyPlies {
+    pub fn new(last_skipped: u16) -> Self {
+        Self { last_skipped }
+    }
+}
+
+impl EntryFilter for SkipEarlyPlies {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        entry.ply > self.last_skipped
+    }
+}
+
+/// Skips entries whose move captures a piece, en passant included
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkipCaptures;
+
+impl EntryFilter for SkipCaptures {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        !is_capture(entry)
+    }
+}
+
+/// Skips entries whose side to move is in check
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkipInCheck;
+
+impl EntryFilter for SkipInCheck {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        !entry.pos.is_checked(entry.pos.side_to_move())
+    }
+}
+
+/// Skips each entry with a fixed probability
+#[derive(Debug, Clone)]
+pub struct SkipRandom<R = StdRng> {
+    probability: f64,
+    rng: R,
+}
+
+impl SkipRandom {
+    /// `probability` is clamped to `0.0..=1.0`
+    pub fn new(probability: f64) -> Self {
+        Self::with_rng(probability, StdRng::from_entropy())
+    }
+
+    /// Skips the same entries of the same input every time for the same
+    /// `seed`
+    pub fn seeded(probability: f64, seed: u64) -> Self {
+        Self::with_rng(probability, StdRng::seed_from_u64(seed))
+    }
+}
+
+impl<R: Rng> SkipRandom<R> {
+    pub fn with_rng(probability: f64, rng: R) -> Self {
+        Self {
+            probability: probability.clamp(0.0, 1.0),
+            rng,
+        }
+    }
+}
+
+impl<R: Rng> EntryFilter for SkipRandom<R> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        let _ = entry;
+        !self.rng.gen_bool(self.probability)
+    }
+
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        let _ = entry;
+        1.0 - self.probability
+    }
+}
+
+/// Skips entries whose score disagrees with the game result.
+///
+/// An entry is kept with the probability the win/draw/loss model of the
+/// nnue-pytorch loader gives its result for its score and ply, so a winning
+/// score in a lost game is mostly dropped.
+#[derive(Debug, Clone)]
+pub struct SkipWld<R = StdRng> {
+    rng: R,
+}
+
+impl SkipWld {
+    pub fn new() -> Self {
+        Self::with_rng(StdRng::from_entropy())
+    }
+
+    /// Skips the same entries of the same input every time for the same
+    /// `seed`
+    pub fn seeded(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for SkipWld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Rng> SkipWld<R> {
+    pub fn with_rng(rng: R) -> Self {
+        Self { rng }
+    }
+}
+
+impl<R: Rng> EntryFilter for SkipWld<R> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        let skip = (1.0 - result_probability(entry)).clamp(0.0, 1.0);
+        !self.rng.gen_bool(skip)
+    }
+
+    /// How likely the result of the entry is at its score, the confidence
+    /// of the win/draw/loss model
+    fn keep_probability(&mut self, entry: &TrainingDataEntry) -> f64 {
+        result_probability(entry).clamp(0.0, 1.0)
+    }
+}
+
+/// Skips entries whose score confidently predicts a different game result.
+///
+/// Unlike [`SkipWld`] this is deterministic and only drops clear cases: a
+/// won game is dropped where the model of [`wdl`] gives a loss at least
+/// `confidence` for the score and ply, a lost game where it gives a win at
+/// least `confidence`, and a drawn game where it gives either. Entries
+/// without a score are kept.
+#[derive(Debug, Clone, Copy)]
+pub struct SkipResultDisagreement {
+    confidence: f64,
+}
+
+impl SkipResultDisagreement {
+    /// `confidence` is a probability, e.g. 0.9
+    pub fn new(confidence: f64) -> Self {
+        Self { confidence }
+    }
+}
+
+impl EntryFilter for SkipResultDisagreement {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        if entry.score == VALUE_NONE {
+            return true;
+        }
+
+        let wdl = wdl(entry.score, entry.ply);
+        let against = match entry.result.signum() {
+            1 => wdl.loss,
+            -1 => wdl.win,
+            _ => wdl.win.max(wdl.loss),
+        };
+
+        against < self.confidence
+    }
+}
+
+/// Skips entries whose material balance by [`simple_eval`] is smaller
+/// than the threshold, in absolute value
+#[derive(Debug, Clone, Copy)]
+pub struct SkipSimpleEval {
+    threshold: i32,
+}
+
+impl SkipSimpleEval {
+    pub fn new(threshold: i32) -> Self {
+        Self { threshold }
+    }
+}
+
+impl EntryFilter for SkipSimpleEval {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        simple_eval(&entry.pos).abs() >= self.threshold
+    }
+}
+
+/// Whether the move of the entry captures a piece, en passant included
+pub fn is_capture(entry: &TrainingDataEntry) -> bool {
+    let mv = entry.mv;
+    if mv.mtype() == MoveType::EnPassant {
+        return true;
+    }
+
+    let from_piece = entry.pos.piece_at(mv.from());
+    let to_piece = entry.pos.piece_at(mv.to());
+    to_piece != Piece::none() && to_piece.color() != from_piece.color()
+}
+
+/// Material balance in centipawns from white's point of view
+pub fn simple_eval(pos: &Position) -> i32 {
+    let mut score = 0i32;
+    for idx in 0..64u32 {
+        let square = Square::new(idx);
+        let piece = pos.piece_at(square);
+        if piece == Piece::none() {
+            continue;
+        }
+
+        let value = match piece.piece_type() {
+            PieceType::Pawn => 100,
+            PieceType::Knight => 320,
+            PieceType::Bishop => 330,
+            PieceType::Rook => 500,
+            PieceType::Queen => 900,
+            PieceType::King | PieceType::None => 0,
+        };
+
+        if piece.color() == Color::White {
+            score += value;
+        } else {
+            score -= value;
+        }
+    }
+
+    score
+}
+
+/// Win, draw and loss probabilities for the side to move, summing to one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wdl {
+    pub win: f64,
+    pub draw: f64,
+    pub loss: f64,
+}
+
+impl Wdl {
+    /// The certain outcome of a result, 1 for a win, 0 for a draw and -1 for
+    /// a loss
+    pub fn from_result(result: i16) -> Self {
+        let (win, draw, loss) = match result.signum() {
+            1 => (1.0, 0.0, 0.0),
+            -1 => (0.0, 0.0, 1.0),
+            _ => (0.0, 1.0, 0.0),
+        };
+        Self { win, draw, loss }
+    }
+
+    /// Probability of `result`
+    pub fn probability(&self, result: i16) -> f64 {
+        match result.signum() {
+            1 => self.win,
+            -1 => self.loss,
+            _ => self.draw,
+        }
+    }
+
+    /// Expected score of the game, from 0 for a certain loss to 1 for a
+    /// certain win
+    pub fn expected_outcome(&self) -> f64 {
+        self.win + self.draw / 2.0
+    }
+
+    /// `lambda * self + (1 - lambda) * other`
+    pub fn lerp(&self, other: &Wdl, lambda: f64) -> Wdl {
+        let mix = |a: f64, b: f64| lambda * a + (1.0 - lambda) * b;
+        Wdl {
+            win: mix(self.win, other.win),
+            draw: mix(self.draw, other.draw),
+            loss: mix(self.loss, other.loss),
+        }
+    }
+}
+
+/// Outcome probabilities for the side to move given a score in Stockfish's
+/// internal units and the ply, by the win/draw/loss model of the
+/// nnue-pytorch loader
+pub fn wdl(score: i16, ply: u16) -> Wdl {
+    let ply = (ply.min(240) as f64) / 64.0;
+    let as_coeffs = [-3.683_893_04, 30.070_659_21, -60.528_787_23, 149.533_785_57];
+    let bs_coeffs = [-2.018_185_7, 15.856_850_38, -29.834_520_23, 47.590_788_27];
+
+    let a = ((as_coeffs[0] * ply + as_coeffs[1]) * ply + as_coeffs[2]) * ply + as_coeffs[3];
+    let mut b = ((bs_coeffs[0] * ply + bs_coeffs[1]) * ply + bs_coeffs[2]) * ply + bs_coeffs[3];
+    b *= 1.5;
+    if b.abs() < 1e-9 {
+        b = 1e-9;
+    }
+
+    let x = ((score as f64) * 100.0 / 208.0).clamp(-2000.0, 2000.0);
+    let win = 1.0 / (1.0 + ((a - x) / b).exp());
+    let loss = 1.0 / (1.0 + ((a + x) / b).exp());
+
+    Wdl {
+        win,
+        draw: 1.0 - win - loss,
+        loss,
+    }
+}
+
+/// Probability of the result of the entry given its score and ply, by the
+/// win/draw/loss model of the nnue-pytorch loader
+pub fn result_probability(entry: &TrainingDataEntry) -> f64 {
+    wdl(entry.score, entry.ply).probability(entry.result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::entry_from_fen;
+
+    #[test]
+    fn test_skip_score_none_and_early_plies() {
+        let mut entry = TrainingDataEntry {
+            score: VALUE_NONE,
+            ..Default::default()
+        };
+
+        assert!(!SkipScoreNone.keep(&entry));
+        entry.score = -VALUE_NONE;
+        assert!(SkipScoreNone.keep(&entry));
+
+        let mut filter = SkipEarlyPlies::new(16);
+        entry.ply = 16;
+        assert!(!filter.keep(&entry));
+        entry.ply = 17;
+        assert!(filter.keep(&entry));
+    }
+
+    #[test]
+    fn test_skip_captures_and_checks() {
+        let fen = "4k2r/8/8/3pP3/8/8/8/4K2R w - d6 0 1";
+        assert!(SkipCaptures.keep(&entry_from_fen(fen, "h1h7", 0, 0)));
+        assert!(!SkipCaptures.keep(&entry_from_fen(fen, "h1h8", 0, 0)));
+        assert!(!SkipCaptures.keep(&entry_from_fen(fen, "e5d6", 0, 0)));
+        assert!(SkipInCheck.keep(&entry_from_fen(fen, "h1h7", 0, 0)));
+
+        let fen = "4k3/8/8/8/8/8/8/4K2r w - - 0 1";
+        assert!(!SkipInCheck.keep(&entry_from_fen(fen, "e1e2", 0, 0)));
+    }
+
+    #[test]
+    fn test_skip_simple_eval() {
+        let entry = entry_from_fen("4k3/8/8/8/8/8/3P4/3QK3 w - - 0 1", "e1e2", 0, 0);
+        assert_eq!(simple_eval(&entry.pos), 1000);
+        assert_eq!(simple_eval(&Position::new()), 0);
+
+        assert!(SkipSimpleEval::new(1000).keep(&entry));
+        assert!(!SkipSimpleEval::new(1001).keep(&entry));
+    }
+
+    #[test]
+    fn test_result_probability() {
+        let mut entry = TrainingDataEntry::default();
+
+        for ply in [0, 60, 240, 400] {
+            for score in [-20000, -300, 0, 150, 20000] {
+                entry.ply = ply;
+                entry.score = score;
+
+                let total = [-1, 0, 1]
+                    .map(|result| result_probability(&TrainingDataEntry { result, ..entry }))
+                    .iter()
+                    .sum::<f64>();
+                assert!((total - 1.0).abs() < 1e-9);
+            }
+        }
+
+        entry.ply = 60;
+        entry.score = 1000;
+        entry.result = 1;
+        assert!(result_probability(&entry) > 0.9);
+        entry.result = -1;
+        assert!(result_probability(&entry) < 0.01);
+    }
+
+    #[test]
+    fn test_skip_result_disagreement() {
+        let mut filter = SkipResultDisagreement::new(0.9);
+        let keep = |filter: &mut SkipResultDisagreement, score, result| {
+            filter.keep(&TrainingDataEntry {
+                score,
+                ply: 60,
+                result,
+                ..Default::default()
+            })
+        };
+
+        // a big eval agrees with a win and disagrees with a loss or a draw
+        assert!(keep(&mut filter, 1500, 1));
+        assert!(!keep(&mut filter, 1500, -1));
+        assert!(!keep(&mut filter, 1500, 0));
+        assert!(!keep(&mut filter, -1500, 1));
+        assert!(!keep(&mut filter, -1500, 0));
+
+        // a small one is not confident enough either way
+        for result in [-1, 0, 1] {
+            assert!(keep(&mut filter, 100, result));
+        }
+        assert!(keep(&mut filter, VALUE_NONE, -1));
+
+        // lower confidence drops more
+        assert!(!keep(&mut SkipResultDisagreement::new(0.05), 300, -1));
+    }
+
+    #[test]
+    fn test_random_filters_with_rng() {
+        let entry = TrainingDataEntry::default();
+
+        assert!(SkipRandom::new(0.0).keep(&entry));
+        assert!(!SkipRandom::new(1.0).keep(&entry));
+
+        let kept = |seed| {
+            let mut filter = SkipRandom::seeded(0.5, seed);
+            (0..100).map(|_| filter.keep(&entry)).collect::<Vec<_>>()
+        };
+        assert_eq!(kept(1), kept(1));
+        assert_ne!(kept(1), kept(2));
+
+        let kept = (0..1000)
+            .filter(|_| SkipRandom::new(0.75).keep(&entry))
+            .count();
+        assert!((150..350).contains(&kept));
+
+        // a draw at a balanced score is likely, a loss at a winning score is not
+        let mut wld = SkipWld::with_rng(StdRng::seed_from_u64(3));
+        let kept = (0..1000).map(|_| wld.keep(&entry)).collect::<Vec<_>>();
+        assert!(kept.iter().filter(|&&keep| keep).count() > 500);
+        let mut seeded = SkipWld::seeded(3);
+        assert_eq!(
+            (0..1000).map(|_| seeded.keep(&entry)).collect::<Vec<_>>(),
+            kept
+        );
+
+        let lost = TrainingDataEntry {
+            score: 2000,
+            result: -1,
+            ..entry
+        };
+        assert_eq!((0..1000).filter(|_| wld.keep(&lost)).count(), 0);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let mut entry = TrainingDataEntry {
+            ply: 10,
+            ..Default::default()
+        };
+
+        let positive = || from_fn(|entry: &TrainingDataEntry| entry.score > 0);
+
+        let mut filter = SkipEarlyPlies::new(5).and(positive());
+        assert!(!filter.keep(&entry));
+        entry.score = 1;
+        assert!(filter.keep(&entry));
+
+        let mut filter = SkipEarlyPlies::new(20).or(positive());
+        assert!(filter.keep(&entry));
+        entry.score = 0;
+        assert!(!filter.keep(&entry));
+        assert!(filter.not().keep(&entry));
+
+        // the second filter only sees what the first one keeps
+        let mut seen = 0;
+        let mut entries = (0..30)
+            .map(|ply| TrainingDataEntry { ply, ..entry })
+            .collect::<Vec<_>>();
+        SkipEarlyPlies::new(15)
+            .and(from_fn(|_: &TrainingDataEntry| {
+                seen += 1;
+                true
+            }))
+            .retain(&mut entries);
+
+        assert_eq!(entries.len(), 14);
+        assert_eq!(seen, 14);
+
+        let mut boxed: Box<dyn EntryFilter> = Box::new(SkipScoreNone.not());
+        assert!(!boxed.keep(&entry));
+    }
+}
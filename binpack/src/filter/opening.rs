@@ -0,0 +1,196 @@
+use crate::{
+    chess::{position::Position, r#move::Move},
+    TrainingDataEntry,
+};
+
+use super::{EntryFilter, ExcludePositions};
+
+/// Tells book moves from the rest
+pub trait Book {
+    /// Whether playing `mv` in `pos` stays in the book
+    fn is_book_move(&self, pos: &Position, mv: Move) -> bool;
+}
+
+/// No move is a book move
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoBook;
+
+impl Book for NoBook {
+    fn is_book_move(&self, _pos: &Position, _mv: Move) -> bool {
+        false
+    }
+}
+
+/// A set of book positions: a move is a book move if it leads to one of
+/// them
+impl Book for ExcludePositions {
+    fn is_book_move(&self, pos: &Position, mv: Move) -> bool {
+        self.contains(&pos.after_move(mv))
+    }
+}
+
+impl<F: Fn(&Position, Move) -> bool> Book for F {
+    fn is_book_move(&self, pos: &Position, mv: Move) -> bool {
+        self(pos, mv)
+    }
+}
+
+/// Where an entry is in its game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpeningLabel {
+    /// Entries of the same game before this one, counted from the start of
+    /// its chain
+    pub game_ply: u32,
+    /// Whether this move and every earlier move of the game are book moves
+    pub in_book: bool,
+}
+
+/// Follows the game chains of a stream of entries and labels each entry
+/// with its place in the opening.
+///
+/// Entries continue a game when they follow from the previous entry by
+/// [`TrainingDataEntry::is_continuation`], anything else starts a new one.
+/// The tracker must see every entry of a game, in order.
+#[derive(Debug, Clone)]
+pub struct OpeningTracker<B = NoBook> {
+    book: B,
+    prev: Option<(TrainingDataEntry, OpeningLabel)>,
+}
+
+impl OpeningTracker {
+    pub fn new() -> Self {
+        Self::with_book(NoBook)
+    }
+}
+
+impl Default for OpeningTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Book> OpeningTracker<B> {
+    pub fn with_book(book: B) -> Self {
+        Self { book, prev: None }
+    }
+
+    pub fn book(&self) -> &B {
+        &self.book
+    }
+
+    pub fn label(&mut self, entry: &TrainingDataEntry) -> OpeningLabel {
+        let (game_ply, was_in_book) = match &self.prev {
+            Some((prev, label)) if prev.is_continuation(entry) => {
+                (label.game_ply + 1, label.in_book)
+            }
+            _ => (0, true),
+        };
+
+        let label = OpeningLabel {
+            game_ply,
+            in_book: was_in_book && self.book.is_book_move(&entry.pos, entry.mv),
+        };
+        self.prev = Some((*entry, label));
+        label
+    }
+}
+
+/// Skips the opening of every game: its first `plies` entries and, with a
+/// book, every entry up to the first move that leaves the book.
+///
+/// Unlike [`SkipEarlyPlies`](super::SkipEarlyPlies), which looks at the
+/// stored ply, this counts from the start of each game chain, so games that
+/// start from book positions lose their first moves too. It has to see
+/// every entry of a game, so put it before filters that skip entries.
+#[derive(Debug, Clone)]
+pub struct SkipOpening<B = NoBook> {
+    tracker: OpeningTracker<B>,
+    plies: u32,
+}
+
+impl SkipOpening {
+    pub fn new(plies: u32) -> Self {
+        Self::with_book(plies, NoBook)
+    }
+}
+
+impl<B: Book> SkipOpening<B> {
+    pub fn with_book(plies: u32, book: B) -> Self {
+        Self {
+            tracker: OpeningTracker::with_book(book),
+            plies,
+        }
+    }
+}
+
+impl<B: Book> EntryFilter for SkipOpening<B> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        let label = self.tracker.label(entry);
+        label.game_ply >= self.plies && !label.in_book
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::game_from_uci;
+
+    const MOVES: [&str; 6] = ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6"];
+
+    #[test]
+    fn test_game_plies() {
+        let mut entries = game_from_uci(&MOVES);
+        entries.extend(game_from_uci(&MOVES[..3]));
+
+        let mut tracker = OpeningTracker::new();
+        let plies = entries
+            .iter()
+            .map(|entry| tracker.label(entry).game_ply)
+            .collect::<Vec<_>>();
+        assert_eq!(plies, [0, 1, 2, 3, 4, 5, 0, 1, 2]);
+
+        let mut filter = SkipOpening::new(4);
+        filter.retain(&mut entries);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].ply, 4);
+    }
+
+    #[test]
+    fn test_book_exit() {
+        let entries = game_from_uci(&MOVES);
+
+        // the book knows 1. e4 e5 2. Nf3 and 1. d4
+        let d4 = game_from_uci(&["d2d4"])[0];
+        let book: ExcludePositions = entries[1..4]
+            .iter()
+            .map(|entry| entry.pos)
+            .chain([d4.pos.after_move(d4.mv)])
+            .collect();
+
+        let mut tracker = OpeningTracker::with_book(book.clone());
+        let in_book = entries
+            .iter()
+            .map(|entry| tracker.label(entry).in_book)
+            .collect::<Vec<_>>();
+        assert_eq!(in_book, [true, true, true, false, false, false]);
+
+        let mut kept = entries.clone();
+        SkipOpening::with_book(0, book.clone()).retain(&mut kept);
+        assert_eq!(kept, entries[3..]);
+
+        let mut kept = entries.clone();
+        SkipOpening::with_book(5, book).retain(&mut kept);
+        assert_eq!(kept, entries[5..]);
+    }
+
+    #[test]
+    fn test_leaving_the_book_is_final() {
+        let entries = game_from_uci(&MOVES);
+
+        // every move is a book move except the second
+        let second = entries[1].mv;
+        let mut filter = SkipOpening::with_book(0, |_: &Position, mv| mv != second);
+        let kept = entries.iter().filter(|entry| filter.keep(entry)).count();
+        assert_eq!(kept, 5);
+    }
+}
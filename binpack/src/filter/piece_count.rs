@@ -0,0 +1,263 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::TrainingDataEntry;
+
+use super::EntryFilter;
+
+/// Number of piece counts, 0 to 32 pieces on the board
+pub const PIECE_COUNTS: usize = 33;
+
+/// Entries seen between two updates of the skipping rate
+const UPDATE_INTERVAL: u64 = 10000;
+
+/// Weights of the quadratic through `(0, at_0)`, `(16, at_16)` and
+/// `(32, at_32)`, like the `pc_y1/pc_y2/pc_y3` parameters of the
+/// nnue-pytorch loader
+pub fn quadratic_weights(at_0: f64, at_16: f64, at_32: f64) -> [f64; PIECE_COUNTS] {
+    let mut weights = [0.0; PIECE_COUNTS];
+    for (pc, weight) in weights.iter_mut().enumerate() {
+        // Lagrange interpolation through the three control points
+        let x = pc as f64;
+        let l1 = (x - 16.0) * (x - 32.0) / 512.0;
+        let l2 = x * (x - 32.0) / -256.0;
+        let l3 = x * (x - 16.0) / 512.0;
+        *weight = at_0 * l1 + at_16 * l2 + at_32 * l3;
+    }
+    weights
+}
+
+/// How many entries of each piece count a [`PieceCountBalancer`] has seen
+/// and kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceCountStats {
+    pub seen: [u64; PIECE_COUNTS],
+    pub kept: [u64; PIECE_COUNTS],
+}
+
+impl PieceCountStats {
+    pub fn seen_total(&self) -> u64 {
+        self.seen.iter().sum()
+    }
+
+    pub fn kept_total(&self) -> u64 {
+        self.kept.iter().sum()
+    }
+
+    /// Share of the kept entries with each piece count, all zero if nothing
+    /// was kept
+    pub fn kept_distribution(&self) -> [f64; PIECE_COUNTS] {
+        let total = self.kept_total().max(1) as f64;
+        self.kept.map(|kept| kept as f64 / total)
+    }
+}
+
+/// Skips entries so the piece counts of the kept ones follow target weights.
+///
+/// This is the piece count sampler of the nnue-pytorch loader: the skipping
+/// probability of each piece count follows from how often it has been seen
+/// so far, and every 10000 entries the overall rate is adjusted so that no
+/// more than `max_skipping_rate` entries are seen per entry kept, roughly.
+/// Piece counts with a weight of zero are always skipped.
+#[derive(Debug, Clone)]
+pub struct PieceCountBalancer<R = StdRng> {
+    weights: [f64; PIECE_COUNTS],
+    weights_total: f64,
+    max_skipping_rate: f64,
+    alpha: f64,
+    stats: PieceCountStats,
+    seen_total: u64,
+    rng: R,
+}
+
+impl PieceCountBalancer {
+    /// Negative weights count as zero
+    pub fn new(weights: [f64; PIECE_COUNTS]) -> Self {
+        Self::with_rng(weights, StdRng::from_entropy())
+    }
+
+    /// Skips the same entries of the same input every time for the same
+    /// `seed`
+    pub fn seeded(weights: [f64; PIECE_COUNTS], seed: u64) -> Self {
+        Self::with_rng(weights, StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for PieceCountBalancer {
+    /// The default distribution of the nnue-pytorch loader, peaking at 16
+    /// pieces
+    fn default() -> Self {
+        Self::new(quadratic_weights(1.0, 2.0, 1.0))
+    }
+}
+
+impl<R: Rng> PieceCountBalancer<R> {
+    pub fn with_rng(weights: [f64; PIECE_COUNTS], rng: R) -> Self {
+        let weights = weights.map(|weight| weight.max(0.0));
+
+        Self {
+            weights,
+            weights_total: weights.iter().sum(),
+            max_skipping_rate: 10.0,
+            alpha: 1.0,
+            stats: PieceCountStats {
+                seen: [0; PIECE_COUNTS],
+                kept: [0; PIECE_COUNTS],
+            },
+            seen_total: 0,
+            rng,
+        }
+    }
+
+    /// Sets how many entries may be seen per entry kept, 10 by default
+    pub fn with_max_skipping_rate(mut self, rate: f64) -> Self {
+        self.max_skipping_rate = rate;
+        self
+    }
+
+    pub fn weights(&self) -> &[f64; PIECE_COUNTS] {
+        &self.weights
+    }
+
+    pub fn stats(&self) -> PieceCountStats {
+        self.stats
+    }
+
+    /// Whether an entry with `piece_count` pieces is kept, counts above 32
+    /// count as 32
+    pub fn keep_piece_count(&mut self, piece_count: usize) -> bool {
+        let piece_count = piece_count.min(PIECE_COUNTS - 1);
+
+        self.stats.seen[piece_count] += 1;
+        self.seen_total += 1;
+
+        let total = self.seen_total as f64;
+
+        if self.seen_total.is_multiple_of(UPDATE_INTERVAL) {
+            let mut pass = total * self.weights_total;
+            for (weight, &seen) in self.weights.iter().zip(&self.stats.seen) {
+                if *weight <= 0.0 || seen == 0 {
+                    continue;
+                }
+                pass = pass.min(total * weight / (self.weights_total * seen as f64));
+            }
+            self.alpha = 1.0 / (pass * self.max_skipping_rate).max(1e-9);
+        }
+
+        let seen = self.stats.seen[piece_count] as f64;
+        let keep = (self.alpha * total * self.weights[piece_count] / (self.weights_total * seen))
+            .clamp(0.0, 1.0);
+
+        // all weights zero gives NaN, which skips everything
+        if keep.is_nan() || !self.rng.gen_bool(keep) {
+            return false;
+        }
+
+        self.stats.kept[piece_count] += 1;
+        true
+    }
+}
+
+impl<R: Rng> EntryFilter for PieceCountBalancer<R> {
+    fn keep(&mut self, entry: &TrainingDataEntry) -> bool {
+        self.keep_piece_count(entry.pos.occupied().count() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quadratic_weights() {
+        let weights = quadratic_weights(1.0, 2.0, 1.0);
+        assert!((weights[0] - 1.0).abs() < 1e-12);
+        assert!((weights[16] - 2.0).abs() < 1e-12);
+        assert!((weights[32] - 1.0).abs() < 1e-12);
+        assert!((weights[8] - weights[24]).abs() < 1e-12);
+
+        let flat = quadratic_weights(1.0, 1.0, 1.0);
+        assert!(flat.iter().all(|weight| (weight - 1.0).abs() < 1e-12));
+    }
+
+    /// Piece counts where every count from 2 to 32 is equally common
+    fn uniform_counts(n: usize) -> impl Iterator<Item = usize> {
+        (0..n).map(|idx| 2 + idx % 31)
+    }
+
+    #[test]
+    fn test_balances_towards_weights() {
+        let mut weights = [0.0; PIECE_COUNTS];
+        weights[10] = 1.0;
+        weights[20] = 3.0;
+
+        let mut balancer = PieceCountBalancer::with_rng(weights, StdRng::seed_from_u64(1));
+        for pc in uniform_counts(200_000) {
+            balancer.keep_piece_count(pc);
+        }
+
+        let stats = balancer.stats();
+        assert_eq!(stats.seen_total(), 200_000);
+        assert_eq!(stats.kept_total(), stats.kept[10] + stats.kept[20]);
+
+        let distribution = stats.kept_distribution();
+        // the first entries are kept before the rates are adjusted
+        assert!((distribution[20] - 0.75).abs() < 0.1, "{distribution:?}");
+    }
+
+    #[test]
+    fn test_max_skipping_rate() {
+        let rate = |max_skipping_rate| {
+            let mut balancer = PieceCountBalancer::with_rng(
+                quadratic_weights(1.0, 2.0, 1.0),
+                StdRng::seed_from_u64(2),
+            )
+            .with_max_skipping_rate(max_skipping_rate);
+
+            for pc in uniform_counts(100_000) {
+                balancer.keep_piece_count(pc);
+            }
+
+            let stats = balancer.stats();
+            stats.seen_total() as f64 / stats.kept_total() as f64
+        };
+
+        let (low, high) = (rate(4.0), rate(10.0));
+        assert!(low <= 4.0, "{low}");
+        assert!(high <= 10.0 && high > low, "{high}");
+    }
+
+    #[test]
+    fn test_deterministic_with_rng() {
+        let run = |seed| {
+            let mut balancer = PieceCountBalancer::with_rng(
+                quadratic_weights(2.0, 1.5, 1.0),
+                StdRng::seed_from_u64(seed),
+            );
+            uniform_counts(20_000)
+                .map(|pc| balancer.keep_piece_count(pc))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+
+        let mut seeded = PieceCountBalancer::seeded(quadratic_weights(2.0, 1.5, 1.0), 3);
+        let seeded = uniform_counts(20_000)
+            .map(|pc| seeded.keep_piece_count(pc))
+            .collect::<Vec<_>>();
+        assert_eq!(seeded, run(3));
+    }
+
+    #[test]
+    fn test_zero_weights() {
+        let mut balancer =
+            PieceCountBalancer::with_rng([0.0; PIECE_COUNTS], StdRng::seed_from_u64(5));
+        assert!(!(0..100).any(|pc| balancer.keep_piece_count(pc)));
+        assert_eq!(balancer.stats().seen[32], 68);
+
+        let entry = TrainingDataEntry::default();
+        let mut balancer = PieceCountBalancer::default();
+        balancer.keep(&entry);
+        assert_eq!(balancer.stats().seen[32], 1);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{sample::Phase, TrainingDataEntry};
+
+use super::EntryFilter;
+
+/// Weighs entries for the loss of a trainer instead of skipping them.
+pub trait EntryWeight {
+    /// The weight of `entry`, 1 for an entry that counts fully
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32;
+
+    /// Weighs entries by the product of both weights
+    fn times<W: EntryWeight>(self, other: W) -> Times<Self, W>
+    where
+        Self: Sized,
+    {
+        Times(self, other)
+    }
+}
+
+impl<W: EntryWeight + ?Sized> EntryWeight for Box<W> {
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32 {
+        (**self).weight(entry)
+    }
+}
+
+impl<W: EntryWeight + ?Sized> EntryWeight for &mut W {
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32 {
+        (**self).weight(entry)
+    }
+}
+
+/// Weighs every entry with what `weight` returns for it
+pub fn weight_fn<F: FnMut(&TrainingDataEntry) -> f32>(weight: F) -> WeightFn<F> {
+    WeightFn(weight)
+}
+
+/// See [`weight_fn`]
+#[derive(Debug, Clone)]
+pub struct WeightFn<F>(F);
+
+impl<F: FnMut(&TrainingDataEntry) -> f32> EntryWeight for WeightFn<F> {
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32 {
+        (self.0)(entry)
+    }
+}
+
+/// See [`EntryWeight::times`]
+#[derive(Debug, Clone)]
+pub struct Times<A, B>(A, B);
+
+impl<A: EntryWeight, B: EntryWeight> EntryWeight for Times<A, B> {
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32 {
+        self.0.weight(entry) * self.1.weight(entry)
+    }
+}
+
+/// See [`EntryFilter::weighted`]
+#[derive(Debug, Clone)]
+pub struct Weighted<F>(pub(super) F);
+
+impl<F: EntryFilter> EntryWeight for Weighted<F> {
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32 {
+        self.0.keep_probability(entry) as f32
+    }
+}
+
+/// A weight per game phase, see [`Phase::of`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaseWeight {
+    pub opening: f32,
+    pub middlegame: f32,
+    pub endgame: f32,
+}
+
+impl EntryWeight for PhaseWeight {
+    fn weight(&mut self, entry: &TrainingDataEntry) -> f32 {
+        match Phase::of(&entry.pos) {
+            Phase::Opening => self.opening,
+            Phase::Middlegame => self.middlegame,
+            Phase::Endgame => self.endgame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::position::Position,
+        filter::{result_probability, SkipEarlyPlies, SkipRandom, SkipWld},
+    };
+
+    #[test]
+    fn test_filter_weights() {
+        let entry = TrainingDataEntry {
+            score: 400,
+            ply: 40,
+            result: 0,
+            ..Default::default()
+        };
+
+        // the confidence of the model instead of a draw
+        let mut wld = SkipWld::seeded(1).weighted();
+        let weight = wld.weight(&entry);
+        assert_eq!(weight, result_probability(&entry) as f32);
+        assert!(weight > 0.0 && weight < 1.0);
+
+        // deterministic filters weigh 0 or 1, combinators combine
+        assert_eq!(SkipEarlyPlies::new(50).weighted().weight(&entry), 0.0);
+        let mut both = SkipEarlyPlies::new(10)
+            .and(SkipRandom::seeded(0.75, 1))
+            .weighted();
+        assert_eq!(both.weight(&entry), 0.25);
+        let mut either = SkipEarlyPlies::new(50)
+            .or(SkipRandom::seeded(0.75, 1))
+            .weighted();
+        assert_eq!(either.weight(&entry), 0.25);
+        assert_eq!(
+            SkipRandom::seeded(0.75, 1).not().weighted().weight(&entry),
+            0.75
+        );
+    }
+
+    #[test]
+    fn test_phase_weight() {
+        let mut phase = PhaseWeight {
+            opening: 0.5,
+            middlegame: 1.0,
+            endgame: 2.0,
+        };
+        let endgame = TrainingDataEntry {
+            pos: Position::from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(phase.weight(&TrainingDataEntry::default()), 0.5);
+        assert_eq!(phase.weight(&endgame), 2.0);
+
+        let mut halved = phase.times(weight_fn(|_| 0.5));
+        assert_eq!(halved.weight(&endgame), 1.0);
+    }
+}
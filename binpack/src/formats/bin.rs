@@ -0,0 +1,431 @@
+//! The `.bin` format of the Stockfish tools, a flat array of 40 byte
+//! `PackedSfenValue` records:
+//!
+//! ```text
+//! sfen         32 bytes  huffman coded position
+//! score        i16 le    relative to the side to move
+//! move         u16 le    Stockfish move encoding
+//! game ply     u16 le
+//! game result  i8        1, 0, -1 for win, draw, loss of the side to move
+//! padding      u8
+//! ```
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    chess::{
+        castling_rights::CastlingRights,
+        color::Color,
+        coords::Square,
+        piece::Piece,
+        piecetype::PieceType,
+        position::Position,
+        r#move::{Move, MoveType},
+    },
+    TrainingDataEntry,
+};
+
+use super::{policy::ScorePolicy, EntryWrite, FormatError, Result};
+
+pub const RECORD_SIZE: usize = 40;
+const SFEN_SIZE: usize = 32;
+
+/// (code, bits) for empty squares and pawn to queen, codes are written lsb first
+const HUFFMAN: [(u8, usize); 6] = [
+    (0b0000, 1),
+    (0b0001, 4),
+    (0b0011, 4),
+    (0b0101, 4),
+    (0b0111, 4),
+    (0b1001, 4),
+];
+
+const CASTLING: [CastlingRights; 4] = [
+    CastlingRights::WHITE_KING_SIDE,
+    CastlingRights::WHITE_QUEEN_SIDE,
+    CastlingRights::BLACK_KING_SIDE,
+    CastlingRights::BLACK_QUEEN_SIDE,
+];
+
+pub struct BinReader<R: Read> {
+    input: R,
+    score_policy: ScorePolicy,
+    record: u64,
+}
+
+impl<R: Read> BinReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            score_policy: ScorePolicy::default(),
+            record: 0,
+        }
+    }
+
+    /// Reads scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    fn read_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        let mut data = [0u8; RECORD_SIZE];
+        let mut filled = 0;
+
+        while filled < RECORD_SIZE {
+            match self.input.read(&mut data[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(FormatError::InvalidEntry(format!(
+                        "record {} is truncated",
+                        self.record
+                    )))
+                }
+                Ok(read) => filled += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let mut entry = unpack_record(&data).ok_or_else(|| {
+            FormatError::InvalidEntry(format!("record {} is malformed", self.record))
+        })?;
+        self.record += 1;
+
+        entry.score = self.score_policy.score(entry.score.into());
+        Ok(Some(entry))
+    }
+}
+
+impl<R: Read> Iterator for BinReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+pub struct BinWriter<W: Write> {
+    output: W,
+    score_policy: ScorePolicy,
+}
+
+impl<W: Write> BinWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            score_policy: ScorePolicy::default(),
+        }
+    }
+
+    /// Writes scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> EntryWrite for BinWriter<W> {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        let entry = TrainingDataEntry {
+            score: self.score_policy.score(entry.score.into()),
+            ..*entry
+        };
+        self.output.write_all(&pack_record(&entry))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+pub fn pack_record(entry: &TrainingDataEntry) -> [u8; RECORD_SIZE] {
+    let mut data = [0u8; RECORD_SIZE];
+
+    pack_sfen(&entry.pos, &mut data[..SFEN_SIZE]);
+    data[32..34].copy_from_slice(&entry.score.to_le_bytes());
+    data[34..36].copy_from_slice(&to_sf_move(entry.mv).to_le_bytes());
+    data[36..38].copy_from_slice(&entry.ply.to_le_bytes());
+    data[38] = entry.result as i8 as u8;
+
+    data
+}
+
+/// Decodes a record, None if the position or move is malformed
+pub fn unpack_record(data: &[u8; RECORD_SIZE]) -> Option<TrainingDataEntry> {
+    let mut pos = unpack_sfen(&data[..SFEN_SIZE])?;
+    let score = i16::from_le_bytes([data[32], data[33]]);
+    let mv = from_sf_move(&pos, u16::from_le_bytes([data[34], data[35]]))?;
+    let ply = u16::from_le_bytes([data[36], data[37]]);
+    let result = data[38] as i8 as i16;
+
+    pos.set_ply(ply);
+
+    Some(TrainingDataEntry {
+        pos,
+        mv,
+        score,
+        ply,
+        result,
+    })
+}
+
+/// Stockfish move: to in bits 0-5, from in bits 6-11, promotion piece type
+/// minus knight in bits 12-13 and the move type in bits 14-15. Castling is
+/// encoded as king captures rook, like `Move`.
+fn to_sf_move(mv: Move) -> u16 {
+    if mv.from() == Square::NONE || mv.to() == Square::NONE {
+        return 0;
+    }
+
+    let mut packed = (mv.from().index() << 6 | mv.to().index()) as u16;
+
+    match mv.mtype() {
+        MoveType::Normal => {}
+        MoveType::Promotion => {
+            let promotion =
+                mv.promoted_piece().piece_type().ordinal() - PieceType::Knight.ordinal();
+            packed |= 1 << 14 | (promotion as u16) << 12;
+        }
+        MoveType::EnPassant => packed |= 2 << 14,
+        MoveType::Castle => packed |= 3 << 14,
+    }
+
+    packed
+}
+
+fn from_sf_move(pos: &Position, packed: u16) -> Option<Move> {
+    let from = Square::new(((packed >> 6) & 63) as u32);
+    let to = Square::new((packed & 63) as u32);
+
+    let mv = match packed >> 14 {
+        0 => Move::normal(from, to),
+        1 => {
+            let piece_type = PieceType::from_ordinal(((packed >> 12) & 3) as u8 + 1);
+            Move::promotion(from, to, Piece::new(piece_type, pos.side_to_move()))
+        }
+        2 => Move::en_passant(from, to),
+        _ => Move::castle(from, to),
+    };
+
+    let piece = pos.piece_at(from);
+    (piece != Piece::none() && piece.color() == pos.side_to_move()).then_some(mv)
+}
+
+struct BitWriter<'a> {
+    data: &'a mut [u8],
+    cursor: usize,
+}
+
+impl BitWriter<'_> {
+    fn write(&mut self, value: u32, bits: usize) {
+        for i in 0..bits {
+            if value & (1 << i) != 0 {
+                self.data[self.cursor / 8] |= 1 << (self.cursor % 8);
+            }
+            self.cursor += 1;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..bits {
+            let byte = *self.data.get(self.cursor / 8)?;
+            value |= (((byte >> (self.cursor % 8)) & 1) as u32) << i;
+            self.cursor += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Squares in sfen order, rank 8 to rank 1 and file a to h within a rank
+fn sfen_squares() -> impl Iterator<Item = Square> {
+    (0..8u32)
+        .rev()
+        .flat_map(|rank| (0..8u32).map(move |file| Square::new(rank * 8 + file)))
+}
+
+fn pack_sfen(pos: &Position, data: &mut [u8]) {
+    let mut writer = BitWriter { data, cursor: 0 };
+
+    writer.write(pos.side_to_move().ordinal() as u32, 1);
+    writer.write(pos.king_sq(Color::White).index(), 7);
+    writer.write(pos.king_sq(Color::Black).index(), 7);
+
+    for sq in sfen_squares() {
+        let piece = pos.piece_at(sq);
+        let piece_type = piece.piece_type();
+
+        if piece_type == PieceType::King {
+            continue;
+        }
+
+        if piece == Piece::none() {
+            let (code, bits) = HUFFMAN[0];
+            writer.write(code as u32, bits);
+        } else {
+            let (code, bits) = HUFFMAN[piece_type.ordinal() as usize + 1];
+            writer.write(code as u32, bits);
+            writer.write(piece.color().ordinal() as u32, 1);
+        }
+    }
+
+    for rights in CASTLING {
+        writer.write(pos.castling_rights().contains(rights) as u32, 1);
+    }
+
+    if pos.ep_square() == Square::NONE {
+        writer.write(0, 1);
+    } else {
+        writer.write(1, 1);
+        writer.write(pos.ep_square().index(), 6);
+    }
+
+    // the format has 7 bits for the counter, larger ones are stored as the
+    // largest it holds, which keeps a draw by the 50-move rule claimable
+    let rule50 = pos.rule50_counter().min(0x7F) as u32;
+    let fullmove = pos.ply() as u32 / 2 + 1;
+
+    writer.write(rule50, 6);
+    writer.write(fullmove, 8);
+    writer.write(fullmove >> 8, 8);
+    writer.write(rule50 >> 6, 1);
+}
+
+fn unpack_sfen(data: &[u8]) -> Option<Position> {
+    let mut reader = BitReader { data, cursor: 0 };
+    let mut pos = Position::empty();
+
+    pos.set_side_to_move(Color::from_ordinal(reader.read(1)? as u8));
+
+    let white_king = Square::new(reader.read(7)?);
+    let black_king = Square::new(reader.read(7)?);
+    if white_king.index() >= 64 || black_king.index() >= 64 || white_king == black_king {
+        return None;
+    }
+
+    pos.place(Piece::WHITE_KING, white_king);
+    pos.place(Piece::BLACK_KING, black_king);
+
+    for sq in sfen_squares() {
+        if sq == white_king || sq == black_king {
+            continue;
+        }
+
+        if reader.read(1)? == 0 {
+            continue;
+        }
+
+        let code = 1 | reader.read(3)? << 1;
+        let idx = HUFFMAN
+            .iter()
+            .position(|(c, bits)| *bits == 4 && *c as u32 == code)?;
+        let color = Color::from_ordinal(reader.read(1)? as u8);
+
+        pos.place(
+            Piece::new(PieceType::from_ordinal(idx as u8 - 1), color),
+            sq,
+        );
+    }
+
+    let mut castling = CastlingRights::NONE;
+    for rights in CASTLING {
+        if reader.read(1)? == 1 {
+            castling |= rights;
+        }
+    }
+    pos.set_castling_rights(castling);
+
+    if reader.read(1)? == 1 {
+        pos.set_ep_square_unchecked(Square::new(reader.read(6)?));
+    }
+
+    let rule50_low = reader.read(6)?;
+    let fullmove = reader.read(8)? | reader.read(8)? << 8;
+    let rule50 = rule50_low | reader.read(1)? << 6;
+
+    pos.set_rule50_counter(rule50 as u16);
+    pos.set_ply((fullmove.max(1) as u16 - 1) * 2 + pos.side_to_move().ordinal() as u16);
+
+    Some(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::entry_from_fen;
+
+    #[test]
+    fn test_bin_round_trip() {
+        let entries = [
+            entry_from_fen(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "e2e4",
+                35,
+                1,
+            ),
+            entry_from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 3 20", "e8c8", -12, -1),
+            entry_from_fen("k7/8/8/3pP3/8/8/8/6K1 w - d6 0 40", "e5d6", 250, 0),
+            entry_from_fen("k7/4P3/8/8/8/8/8/6K1 w - - 70 90", "e7e8n", 31000, 1),
+        ];
+
+        let mut writer = BinWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        let data = writer.into_inner();
+        assert_eq!(data.len(), entries.len() * RECORD_SIZE);
+
+        let decoded = BinReader::new(data.as_slice())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(decoded, entries);
+    }
+
+    #[test]
+    fn test_large_rule50_counter() {
+        // 7 bits for the counter, larger ones saturate
+        for (counter, stored) in [(127, 127), (128, 127), (300, 127)] {
+            let mut entry = entry_from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 200", "h1h2", 0, 0);
+            entry.pos.set_rule50_counter(counter);
+
+            let decoded = unpack_record(&pack_record(&entry)).unwrap();
+            assert_eq!(decoded.pos.rule50_counter(), stored);
+        }
+    }
+
+    #[test]
+    fn test_sf_move_encoding() {
+        let pos = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let castle = Move::from_uci(&pos, "e1g1").unwrap();
+
+        // e1 = 4, h1 = 7
+        assert_eq!(to_sf_move(castle), 3 << 14 | 4 << 6 | 7);
+        assert_eq!(from_sf_move(&pos, 3 << 14 | 4 << 6 | 7), Some(castle));
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let data = [0u8; RECORD_SIZE + 5];
+        let mut reader = BinReader::new(&data[..]);
+
+        assert!(matches!(
+            reader.nth(1),
+            Some(Err(FormatError::InvalidEntry(_)))
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+//! EPD lines with the counters, move, score and result as opcodes, one
+//! entry per line:
+//!
+//! ```text
+//! 1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - hmvc 0; fmvn 35; sm c4+; ce -201; c9 "1/2-1/2";
+//! ```
+//!
+//! `ce` is relative to the side to move and `c9` is the result for white,
+//! see [`TrainingDataEntry::to_epd`]. Unlike `pos.fen()` of an unpacked
+//! entry the counters are exact, so the ply survives a round trip.
+
+use std::io::{BufRead, Write};
+
+use crate::TrainingDataEntry;
+
+use super::{policy::ScorePolicy, EntryWrite, FormatError, Result};
+
+pub struct EpdReader<R: BufRead> {
+    input: R,
+    score_policy: ScorePolicy,
+    line: u64,
+    buffer: String,
+}
+
+impl<R: BufRead> EpdReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            score_policy: ScorePolicy::default(),
+            line: 0,
+            buffer: String::new(),
+        }
+    }
+
+    /// Reads scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    fn read_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        loop {
+            self.buffer.clear();
+            if self.input.read_line(&mut self.buffer)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+
+            let line = self.buffer.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut entry =
+                TrainingDataEntry::from_epd(line).ok_or_else(|| FormatError::Parse {
+                    line: self.line,
+                    message: format!("invalid EPD '{}'", line),
+                })?;
+            entry.score = self.score_policy.score(entry.score.into());
+            return Ok(Some(entry));
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for EpdReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+pub struct EpdWriter<W: Write> {
+    output: W,
+    score_policy: ScorePolicy,
+}
+
+impl<W: Write> EpdWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            score_policy: ScorePolicy::default(),
+        }
+    }
+
+    /// Writes scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> EntryWrite for EpdWriter<W> {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        let entry = TrainingDataEntry {
+            score: self.score_policy.score(entry.score.into()),
+            ..*entry
+        };
+        let epd = entry.to_epd().ok_or_else(|| {
+            FormatError::InvalidEntry("position has no FEN or result is invalid".to_string())
+        })?;
+        writeln!(self.output, "{}", epd)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const TEXT: &str = "\
+1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - hmvc 0; fmvn 35; sm c4+; ce -201; c9 \"1/2-1/2\";
+1q5b/1r5k/4p2p/1b2P1pN/2Pp4/6PP/1n4B1/1Q2B1K1 b - - hmvc 0; fmvn 35; sm d3; ce 254; c9 \"1/2-1/2\";
+";
+
+    #[test]
+    fn test_epd_round_trip() {
+        let entries = EpdReader::new(Cursor::new(TEXT))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].ply, entries[1].ply), (68, 69));
+        assert_eq!(entries[1].mv.as_uci(), "d4d3");
+        assert!(entries[0].is_continuation(&entries[1]));
+
+        let mut writer = EpdWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn test_epd_errors() {
+        let text = "4k3/8/8/8/8/8/8/4K3 w - - ce 0;\n\n4k3/8/8/8/8/8/8/4K3 w - - sm e2e4;\n";
+        let mut reader = EpdReader::new(Cursor::new(text));
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap();
+        assert!(matches!(err, Err(FormatError::Parse { line: 3, .. })));
+    }
+}
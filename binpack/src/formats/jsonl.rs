@@ -0,0 +1,328 @@
+//! One JSON object per line:
+//!
+//! ```text
+//! {"fen":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1","move":"e2e4","score":35,"ply":0,"result":0}
+//! ```
+//!
+//! `score` and `result` are relative to the side to move. Only flat objects
+//! are supported, unknown keys with string, number, boolean or null values
+//! are ignored.
+
+use std::io::{BufRead, Write};
+
+use crate::{
+    chess::{position::Position, r#move::Move},
+    TrainingDataEntry,
+};
+
+use super::{policy::ScorePolicy, EntryWrite, FormatError, Result};
+
+pub struct JsonlReader<R: BufRead> {
+    input: R,
+    score_policy: ScorePolicy,
+    line: u64,
+    buffer: String,
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            score_policy: ScorePolicy::default(),
+            line: 0,
+            buffer: String::new(),
+        }
+    }
+
+    /// Reads scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    fn error(&self, message: impl Into<String>) -> FormatError {
+        FormatError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        loop {
+            self.buffer.clear();
+            if self.input.read_line(&mut self.buffer)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+
+            if !self.buffer.trim().is_empty() {
+                break;
+            }
+        }
+
+        let fields = parse_object(self.buffer.trim()).map_err(|message| self.error(message))?;
+
+        let mut fen = None;
+        let mut uci = None;
+        let mut score = None;
+        let mut ply = None;
+        let mut result = None;
+
+        for (key, value) in fields {
+            match (key.as_str(), value) {
+                ("fen", Value::String(value)) => fen = Some(value),
+                ("move", Value::String(value)) => uci = Some(value),
+                ("score", Value::Number(value)) => score = Some(self.score_policy.score(value)),
+                ("ply", Value::Number(value)) => ply = Some(self.number(&key, value)?),
+                ("result", Value::Number(value)) => result = Some(self.number(&key, value)?),
+                ("fen" | "move" | "score" | "ply" | "result", _) => {
+                    return Err(self.error(format!("unexpected type for '{}'", key)))
+                }
+                _ => {}
+            }
+        }
+
+        let fen = fen.ok_or_else(|| self.error("missing 'fen'"))?;
+        let pos = Position::from_fen(&fen).map_err(|_| self.error("invalid fen"))?;
+
+        let uci = uci.ok_or_else(|| self.error("missing 'move'"))?;
+        let mv = Move::from_uci(&pos, &uci)
+            .ok_or_else(|| self.error(format!("illegal move '{}'", uci)))?;
+
+        Ok(Some(TrainingDataEntry {
+            pos,
+            mv,
+            score: score.ok_or_else(|| self.error("missing 'score'"))?,
+            ply: ply.unwrap_or_else(|| pos.ply()),
+            result: result.ok_or_else(|| self.error("missing 'result'"))?,
+        }))
+    }
+
+    fn number<T: TryFrom<i64>>(&self, key: &str, value: i64) -> Result<T> {
+        T::try_from(value).map_err(|_| self.error(format!("'{}' out of range: {}", key, value)))
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+pub struct JsonlWriter<W: Write> {
+    output: W,
+    score_policy: ScorePolicy,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            score_policy: ScorePolicy::default(),
+        }
+    }
+
+    /// Writes scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> EntryWrite for JsonlWriter<W> {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        let fen = entry
+            .pos
+            .fen()
+            .map_err(|_| FormatError::InvalidEntry("position has no FEN".to_string()))?;
+
+        // FENs and UCI moves never contain characters that need escaping
+        writeln!(
+            self.output,
+            r#"{{"fen":"{}","move":"{}","score":{},"ply":{},"result":{}}}"#,
+            fen,
+            entry.mv.as_uci(),
+            self.score_policy.score(entry.score.into()),
+            entry.ply,
+            entry.result
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) enum Value {
+    String(String),
+    Number(i64),
+    Other,
+}
+
+/// Parses a flat JSON object into its key value pairs, also used for
+/// [`crate::meta`] sidecars
+pub(crate) fn parse_object(text: &str) -> std::result::Result<Vec<(String, Value)>, String> {
+    let mut chars = text.chars().peekable();
+    let mut fields = Vec::new();
+
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+
+    if chars.next() != Some('{') {
+        return Err("expected '{'".to_string());
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(fields);
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next() != Some('"') {
+            return Err("expected a key".to_string());
+        }
+        let key = parse_string(&mut chars)?;
+
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(format!("expected ':' after '{}'", key));
+        }
+        skip_whitespace(&mut chars);
+
+        let value = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                Value::String(parse_string(&mut chars)?)
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = chars.next_if(|c| *c == '-' || c.is_ascii_digit()) {
+                    number.push(c);
+                }
+                if chars.peek().is_some_and(|c| matches!(c, '.' | 'e' | 'E')) {
+                    return Err(format!("'{}' must be an integer", key));
+                }
+                Value::Number(
+                    number
+                        .parse()
+                        .map_err(|_| format!("invalid number for '{}'", key))?,
+                )
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                    word.push(c);
+                }
+                if !matches!(word.as_str(), "true" | "false" | "null") {
+                    return Err(format!("unsupported value for '{}'", key));
+                }
+                Value::Other
+            }
+            None => return Err("unexpected end of line".to_string()),
+        };
+
+        fields.push((key, value));
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => break,
+            _ => return Err("expected ',' or '}'".to_string()),
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err("trailing characters after object".to_string());
+    }
+
+    Ok(fields)
+}
+
+fn parse_string(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> std::result::Result<String, String> {
+    let mut value = String::new();
+
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some(c @ ('"' | '\\' | '/')) => value.push(c),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                _ => return Err("unsupported escape sequence".to_string()),
+            },
+            Some(c) => value.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_jsonl_round_trip() {
+        let text = concat!(
+            r#"{"fen":"1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 0 35","move":"c2c4","score":-201,"ply":68,"result":0}"#,
+            "\n",
+            r#"{"fen":"1q5b/1r5k/4p2p/1b2P1pN/2Pp4/6PP/1n4B1/1Q2B1K1 b - - 0 35","move":"d4d3","score":254,"ply":69,"result":0}"#,
+            "\n",
+        );
+
+        let entries = JsonlReader::new(Cursor::new(text))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].score, -201);
+
+        let mut writer = JsonlWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), text);
+    }
+
+    #[test]
+    fn test_jsonl_lenient_input() {
+        let text = r#" { "move" : "e2e4", "extra": null, "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "score": 12, "result": -1, "tag": "a\"b" } "#;
+
+        let entry = JsonlReader::new(Cursor::new(text)).next().unwrap().unwrap();
+
+        assert_eq!(entry.mv.as_uci(), "e2e4");
+        assert_eq!(entry.ply, 0);
+        assert_eq!(entry.result, -1);
+    }
+
+    #[test]
+    fn test_jsonl_errors() {
+        for text in [
+            r#"{"fen": 1}"#,
+            r#"{"score": 1.5}"#,
+            r#"{"score": 1"#,
+            r#"{"fen":"8/8/8/8/8/8/8/K6k w - - 0 1","move":"a1a2","score":0}"#,
+        ] {
+            let result = JsonlReader::new(Cursor::new(text)).next().unwrap();
+            assert!(
+                matches!(result, Err(FormatError::Parse { line: 1, .. })),
+                "{}",
+                text
+            );
+        }
+    }
+}
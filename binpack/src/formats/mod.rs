@@ -0,0 +1,95 @@
+//! Readers and writers for the other common training data formats.
+//!
+//! Every reader is an iterator over `Result<TrainingDataEntry, FormatError>`
+//! and every writer implements [`EntryWrite`], so conversions are a simple
+//! loop over the entries of one format into the writer of another.
+//!
+//! Scores and results are relative to the side to move in every format but
+//! PGN and the result of EPD, like in the entries themselves. The PGN reader
+//! and writer convert the `Result` tag, and the EPD ones the `c9` opcode,
+//! from and to white's point of view, and the scored reader takes either
+//! point of view, see [`scored::ScoredOptions`]; anything importing
+//! white-relative data should go through
+//! [`TrainingDataEntry::from_white_relative`] rather than negate by hand.
+//!
+//! Mate scores and evals out of range are read and written by a
+//! [`policy::ScorePolicy`], set with `with_score_policy` on any reader or
+//! writer.
+
+use std::io::{self, Write};
+
+use thiserror::Error;
+
+use crate::{CompressedTrainingDataEntryWriter, CompressedWriterError, TrainingDataEntry};
+
+pub mod bin;
+pub mod epd;
+pub mod jsonl;
+pub mod pgn;
+pub mod plain;
+pub mod policy;
+pub mod scored;
+
+pub use crate::common::values::VALUE_NONE;
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {message}")]
+    Parse { line: u64, message: String },
+    #[error("Invalid entry: {0}")]
+    InvalidEntry(String),
+    #[error("Binpack writer error: {0}")]
+    Writer(#[from] CompressedWriterError),
+}
+
+pub type Result<T> = std::result::Result<T, FormatError>;
+
+/// A sink for training data entries.
+pub trait EntryWrite {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()>;
+
+    /// Write out everything that is still buffered, call once after the last entry
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl<T: Write> EntryWrite for CompressedTrainingDataEntryWriter<T> {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        CompressedTrainingDataEntryWriter::write_entry(self, entry)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush_and_end();
+        Ok(())
+    }
+}
+
+/// Formats a score in pawns with two decimals and an explicit sign, e.g. `+0.35`
+pub(crate) fn format_pawns(score: i32) -> String {
+    let sign = if score < 0 { '-' } else { '+' };
+    let abs = score.unsigned_abs();
+    format!("{}{}.{:02}", sign, abs / 100, abs % 100)
+}
+
+/// Parses a score in pawns like `+0.35` or `-1` into centipawns
+pub(crate) fn parse_pawns(text: &str) -> Option<i32> {
+    let value = text.parse::<f64>().ok()?;
+    value.is_finite().then(|| (value * 100.0).round() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pawns_round_trip() {
+        for score in [0, 5, -5, 35, -100, 1234, -31999, 32767] {
+            assert_eq!(parse_pawns(&format_pawns(score)), Some(score));
+        }
+
+        assert_eq!(format_pawns(-5), "-0.05");
+        assert_eq!(format_pawns(120), "+1.20");
+    }
+}
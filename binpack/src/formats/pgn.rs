@@ -0,0 +1,915 @@
+//! Portable Game Notation.
+//!
+//! Every chain of continuation entries is written as one game, its `Result`
+//! tag is white's result and converted from and to the side to move of each
+//! entry. Scores are stored as move comments in the cutechess convention:
+//! the comment after a move holds the score of the position the move was
+//! played from, from the point of view of the side that played it, in pawns
+//! (`{+0.35}`) or as a mate in plies (`{+M3}`), converted by the
+//! [`ScorePolicy`]. Entries scored `VALUE_NONE` get no comment.
+//!
+//! The reader accepts the same comments with an optional `/depth` and trailing
+//! text (`{+0.35/12 0.51s}`) as well as mate scores (`{-M4}`), moves without
+//! a score comment get `VALUE_NONE`. NAGs and other comments are skipped,
+//! and so are variations unless [`PgnOptions::variation_depth`] asks for
+//! them: then every variation is a chain of its own after the mainline,
+//! starting from the position of the move it replaces, in the order the
+//! variations open. Its entries get the result of the game, the only one
+//! there is.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Write},
+    mem,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    chess::{color::Color, position::Position, san},
+    GameResult, TrainingDataEntry,
+};
+
+use super::{
+    format_pawns, parse_pawns,
+    policy::{mate_plies, ScorePolicy},
+    EntryWrite, FormatError, Result, VALUE_NONE,
+};
+
+/// Score of a mate in zero plies, `M3` is read as `MATE - 3`
+pub const MATE: i16 = 32000;
+
+const MAX_LINE_LENGTH: usize = 80;
+
+/// Games a [`ParallelPgnReader`] reads ahead of the entries it hands out,
+/// per thread
+const GAMES_IN_FLIGHT_PER_THREAD: usize = 32;
+
+/// Which variations [`PgnReader`] imports besides the mainline
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PgnOptions {
+    /// How deep nested variations are imported: 0 for the mainline only,
+    /// the default, 1 for the variations of the mainline, 2 for theirs too
+    /// and so on. Deeper variations are skipped unchecked.
+    pub variation_depth: usize,
+    /// Only imports variations with at least one score comment, e.g. the
+    /// principal variations of an engine analysis
+    pub scored_variations_only: bool,
+}
+
+pub struct PgnReader<R: BufRead> {
+    input: R,
+    options: PgnOptions,
+    score_policy: ScorePolicy,
+    line: u64,
+    peeked: Option<String>,
+    entries: std::vec::IntoIter<TrainingDataEntry>,
+}
+
+/// A line of moves being read, the mainline or a variation
+struct Line {
+    entries: Vec<TrainingDataEntry>,
+    pos: Position,
+    /// The last move already has its score comment
+    scored_last: bool,
+    depth: usize,
+    /// Where the finished variation goes in the output, None for the
+    /// mainline and variations that are skipped
+    slot: Option<usize>,
+}
+
+impl Line {
+    fn new(pos: Position, depth: usize, slot: Option<usize>) -> Self {
+        Self {
+            entries: Vec::new(),
+            pos,
+            scored_last: false,
+            depth,
+            slot,
+        }
+    }
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_options(input, PgnOptions::default())
+    }
+
+    pub fn with_options(input: R, options: PgnOptions) -> Self {
+        Self {
+            input,
+            options,
+            score_policy: ScorePolicy::default(),
+            line: 0,
+            peeked: None,
+            entries: Vec::new().into_iter(),
+        }
+    }
+
+    /// Reads score comments with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    fn error(&self, message: impl Into<String>) -> FormatError {
+        FormatError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+
+        Ok(Some(line))
+    }
+
+    /// Reads the tags and movetext of the next game
+    fn read_game_text(&mut self) -> Result<Option<GameText>> {
+        let mut tags = HashMap::new();
+        let mut movetext = String::new();
+
+        while let Some(line) = self.read_line()? {
+            let trimmed = line.trim();
+
+            // escape mechanism of the PGN standard
+            if trimmed.starts_with('%') {
+                continue;
+            }
+
+            if trimmed.starts_with('[') && !in_comment(&movetext) {
+                if !movetext.trim().is_empty() {
+                    // the tags of the next game, the previous one had no result
+                    self.peeked = Some(line);
+                    break;
+                }
+
+                let (key, value) = parse_tag(trimmed).ok_or_else(|| self.error("invalid tag"))?;
+                tags.insert(key, value);
+                continue;
+            }
+
+            movetext.push_str(&line);
+
+            if ends_with_result(&movetext) {
+                break;
+            }
+        }
+
+        if tags.is_empty() && movetext.trim().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(GameText {
+            tags,
+            movetext,
+            line: self.line,
+        }))
+    }
+
+    fn read_game(&mut self) -> Result<Option<Vec<TrainingDataEntry>>> {
+        let Some(game) = self.read_game_text()? else {
+            return Ok(None);
+        };
+
+        game.parse(&self.options, &self.score_policy).map(Some)
+    }
+
+    /// Reads the text of the games on this thread and parses them on
+    /// `threads` others, see [`ParallelPgnReader`]
+    pub fn parallel(self, threads: usize) -> ParallelPgnReader
+    where
+        R: Send + 'static,
+    {
+        ParallelPgnReader::new(self, threads)
+    }
+}
+
+/// The tags and movetext of a game, and the line of the input it ends on
+struct GameText {
+    tags: HashMap<String, String>,
+    movetext: String,
+    line: u64,
+}
+
+impl GameText {
+    fn error(&self, message: impl Into<String>) -> FormatError {
+        FormatError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn parse(&self, options: &PgnOptions, policy: &ScorePolicy) -> Result<Vec<TrainingDataEntry>> {
+        let tags = &self.tags;
+
+        let pos = match tags.get("FEN") {
+            Some(fen) => Position::from_fen(fen).map_err(|_| self.error("invalid FEN tag"))?,
+            None => Position::new(),
+        };
+
+        let mut white_result = parse_result(tags.get("Result").map(String::as_str));
+
+        // the mainline at the bottom, the variations being read above it
+        let mut lines = vec![Line::new(pos, 0, None)];
+        let mut variations: Vec<Vec<TrainingDataEntry>> = Vec::new();
+
+        for token in tokenize(&self.movetext).map_err(|message| self.error(message))? {
+            let line = lines.last_mut().unwrap();
+            let skipped = line.depth > 0 && line.slot.is_none();
+
+            match token {
+                Token::VariationStart => {
+                    let depth = line.depth + 1;
+                    if skipped || depth > options.variation_depth {
+                        let pos = line.pos;
+                        lines.push(Line::new(pos, depth, None));
+                        continue;
+                    }
+
+                    // the variation replaces the last move of its parent
+                    let pos = line
+                        .entries
+                        .last()
+                        .ok_or_else(|| self.error("variation before the first move"))?
+                        .pos;
+                    variations.push(Vec::new());
+                    lines.push(Line::new(pos, depth, Some(variations.len() - 1)));
+                }
+                Token::VariationEnd => {
+                    let line = lines.pop().unwrap();
+                    let scored = line.entries.iter().any(|entry| entry.score != VALUE_NONE);
+                    if let Some(slot) = line.slot {
+                        if scored || !options.scored_variations_only {
+                            variations[slot] = line.entries;
+                        }
+                    }
+                }
+                _ if skipped => {}
+                Token::Comment(comment) => {
+                    // only the first comment directly after a move carries its score
+                    if let (Some(last), false) = (line.entries.last_mut(), line.scored_last) {
+                        if let Some(score) = parse_score(comment, policy) {
+                            last.score = score;
+                        }
+                    }
+                    line.scored_last = true;
+                }
+                Token::Result(result) => {
+                    if !tags.contains_key("Result") {
+                        white_result = parse_result(Some(result));
+                    }
+                    break;
+                }
+                Token::Move(text) => {
+                    let pos = line.pos;
+                    let mv = san::parse_san(&pos, text)
+                        .ok_or_else(|| self.error(format!("illegal move '{}'", text)))?;
+
+                    line.entries.push(TrainingDataEntry {
+                        pos,
+                        mv,
+                        score: VALUE_NONE,
+                        ply: pos.ply(),
+                        result: 0,
+                    });
+
+                    line.pos.do_move(mv);
+                    line.scored_last = false;
+                }
+            }
+        }
+
+        // variations still open at the end of the game are dropped
+        let mut entries = lines.swap_remove(0).entries;
+        entries.extend(variations.into_iter().flatten());
+        for entry in &mut entries {
+            let stm = entry.pos.side_to_move();
+            entry.set_game_result(white_result.to_white_relative(stm));
+        }
+
+        Ok(entries)
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+
+            match self.read_game() {
+                Ok(Some(game)) => self.entries = game.into_iter(),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// A game read by the reading thread of a [`ParallelPgnReader`], numbered in
+/// the order of the input
+type Task = (u64, Result<GameText>);
+
+/// A [`PgnReader`] that parses its games on several threads, one game per
+/// task, and hands out the entries in the order of the input, the same ones
+/// `PgnReader` does. One more thread reads the text of the games.
+///
+/// After an error reading the input there are no more entries, after an
+/// illegal move in a game the entries go on with the next game.
+pub struct ParallelPgnReader {
+    results: mpsc::Receiver<(u64, Result<Vec<TrainingDataEntry>>)>,
+    /// Gives the reading thread room for one more game
+    permits: mpsc::SyncSender<()>,
+    /// Parsed games that come after the next one
+    parsed: BTreeMap<u64, Result<Vec<TrainingDataEntry>>>,
+    next_game: u64,
+    entries: std::vec::IntoIter<TrainingDataEntry>,
+}
+
+impl ParallelPgnReader {
+    fn new<R: BufRead + Send + 'static>(mut reader: PgnReader<R>, threads: usize) -> Self {
+        let threads = threads.max(1);
+        let in_flight = threads * GAMES_IN_FLIGHT_PER_THREAD;
+
+        let (permits, permit_rx) = mpsc::sync_channel(in_flight);
+        for _ in 0..in_flight {
+            permits.send(()).unwrap();
+        }
+
+        let (task_tx, task_rx) = mpsc::sync_channel::<Task>(in_flight);
+        let (result_tx, results) = mpsc::channel();
+        let (options, score_policy) = (reader.options, reader.score_policy);
+
+        thread::spawn(move || {
+            for index in 0.. {
+                // gone once the entries are no longer wanted
+                if permit_rx.recv().is_err() {
+                    return;
+                }
+
+                let task = match reader.read_game_text() {
+                    Ok(Some(game)) => Ok(game),
+                    Ok(None) => return,
+                    Err(err) => Err(err),
+                };
+                let last = task.is_err();
+                if task_tx.send((index, task)).is_err() || last {
+                    return;
+                }
+            }
+        });
+
+        let task_rx = Arc::new(Mutex::new(task_rx));
+        for _ in 0..threads {
+            let (task_rx, result_tx) = (task_rx.clone(), result_tx.clone());
+
+            thread::spawn(move || loop {
+                let task = task_rx.lock().unwrap().recv();
+                let Ok((index, game)) = task else {
+                    return;
+                };
+
+                let entries = game.and_then(|game| game.parse(&options, &score_policy));
+                if result_tx.send((index, entries)).is_err() {
+                    return;
+                }
+            });
+        }
+
+        Self {
+            results,
+            permits,
+            parsed: BTreeMap::new(),
+            next_game: 0,
+            entries: Vec::new().into_iter(),
+        }
+    }
+
+    /// The next game in the order of the input, None at the end
+    fn next_game(&mut self) -> Option<Result<Vec<TrainingDataEntry>>> {
+        while !self.parsed.contains_key(&self.next_game) {
+            // all threads are done once every game is parsed
+            let (index, game) = self.results.recv().ok()?;
+            self.parsed.insert(index, game);
+        }
+
+        let game = self.parsed.remove(&self.next_game);
+        self.next_game += 1;
+        let _ = self.permits.try_send(());
+        game
+    }
+}
+
+impl Iterator for ParallelPgnReader {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+
+            match self.next_game()? {
+                Ok(game) => self.entries = game.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+pub struct PgnWriter<W: Write> {
+    output: W,
+    score_policy: ScorePolicy,
+    game: Vec<TrainingDataEntry>,
+}
+
+impl<W: Write> PgnWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            score_policy: ScorePolicy::default(),
+            game: Vec::new(),
+        }
+    }
+
+    /// Writes score comments with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    pub fn into_inner(mut self) -> Result<W> {
+        self.write_game()?;
+        Ok(self.output)
+    }
+
+    fn write_game(&mut self) -> Result<()> {
+        let game = mem::take(&mut self.game);
+        let (Some(first), Some(last)) = (game.first(), game.last()) else {
+            return Ok(());
+        };
+
+        let result = match last.white_result() {
+            Ok(GameResult::Win) => "1-0",
+            Ok(GameResult::Loss) => "0-1",
+            _ => "1/2-1/2",
+        };
+
+        for (key, value) in [
+            ("Event", "?"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "?"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", result),
+        ] {
+            writeln!(self.output, "[{} \"{}\"]", key, value)?;
+        }
+
+        if first.pos != Position::new() {
+            let fen = first
+                .pos
+                .fen()
+                .map_err(|_| FormatError::InvalidEntry("position has no FEN".to_string()))?;
+            writeln!(self.output, "[FEN \"{}\"]", fen)?;
+            writeln!(self.output, "[SetUp \"1\"]")?;
+        }
+        writeln!(self.output)?;
+
+        let mut tokens = Vec::with_capacity(game.len() * 3 + 1);
+        for (idx, entry) in game.iter().enumerate() {
+            let fullmove = entry.pos.ply() / 2 + 1;
+
+            if entry.pos.side_to_move() == Color::White {
+                tokens.push(format!("{}.", fullmove));
+            } else if idx == 0 {
+                tokens.push(format!("{}...", fullmove));
+            }
+
+            tokens.push(san::to_san(&entry.pos, entry.mv));
+
+            if let Some(comment) = format_score(entry.score, &self.score_policy) {
+                tokens.push(format!("{{{}}}", comment));
+            }
+        }
+        tokens.push(result.to_string());
+
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + 1 + token.len() > MAX_LINE_LENGTH {
+                writeln!(self.output)?;
+                line_length = 0;
+            }
+            if line_length > 0 {
+                write!(self.output, " ")?;
+                line_length += 1;
+            }
+            write!(self.output, "{}", token)?;
+            line_length += token.len();
+        }
+        writeln!(self.output)?;
+        writeln!(self.output)?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> EntryWrite for PgnWriter<W> {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        if let Some(last) = self.game.last() {
+            if !last.is_continuation(entry) {
+                self.write_game()?;
+            }
+        }
+
+        self.game.push(*entry);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.write_game()?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// The result of a `Result` tag or game termination marker for white,
+/// unfinished games count as draws
+fn parse_result(text: Option<&str>) -> GameResult {
+    match text {
+        Some("1-0") => GameResult::Win,
+        Some("0-1") => GameResult::Loss,
+        _ => GameResult::Draw,
+    }
+}
+
+/// Parses a cutechess style score comment like `+0.35/12 0.5s` or `-M4`
+fn parse_score(comment: &str, policy: &ScorePolicy) -> Option<i16> {
+    let text = comment.split_whitespace().next()?;
+    let text = text.split('/').next()?;
+
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    if let Some(plies) = unsigned.strip_prefix('M') {
+        let plies = plies.parse::<u32>().ok()?;
+        return Some(policy.mate(plies, sign > 0));
+    }
+
+    let score = parse_pawns(unsigned)?;
+    Some(policy.centipawns(i64::from(sign * score)))
+}
+
+/// The score comment of an entry, None for `VALUE_NONE`
+fn format_score(score: i16, policy: &ScorePolicy) -> Option<String> {
+    let score = policy.score(score.into());
+    if score == VALUE_NONE {
+        return None;
+    }
+
+    match mate_plies(score) {
+        Some(plies) if score > 0 => Some(format!("+M{}", plies)),
+        Some(plies) => Some(format!("-M{}", plies)),
+        None => Some(format_pawns(policy.to_centipawns(score))),
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let (key, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    Some((
+        key.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+/// True if the text stops inside an unterminated `{` comment
+fn in_comment(movetext: &str) -> bool {
+    match (movetext.rfind('{'), movetext.rfind('}')) {
+        (Some(open), Some(close)) => open > close,
+        (open, _) => open.is_some(),
+    }
+}
+
+fn ends_with_result(movetext: &str) -> bool {
+    if in_comment(movetext) {
+        return false;
+    }
+
+    movetext
+        .split_whitespace()
+        .last()
+        .is_some_and(|token| RESULTS.contains(&token))
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+enum Token<'a> {
+    Move(&'a str),
+    Comment(&'a str),
+    Result(&'a str),
+    VariationStart,
+    VariationEnd,
+}
+
+fn tokenize(movetext: &str) -> std::result::Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = movetext;
+    let mut variation_depth = 0usize;
+
+    loop {
+        rest = rest.trim_start();
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+
+        match c {
+            '{' => {
+                let end = rest.find('}').ok_or("unterminated comment")?;
+                tokens.push(Token::Comment(rest[1..end].trim()));
+                rest = &rest[end + 1..];
+            }
+            ';' => {
+                let end = rest.find('\n').unwrap_or(rest.len());
+                rest = &rest[end..];
+            }
+            '(' => {
+                variation_depth += 1;
+                tokens.push(Token::VariationStart);
+                rest = &rest[1..];
+            }
+            ')' => {
+                variation_depth = variation_depth
+                    .checked_sub(1)
+                    .ok_or("unbalanced ')' in movetext")?;
+                tokens.push(Token::VariationEnd);
+                rest = &rest[1..];
+            }
+            _ => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '{' | '(' | ')' | ';'))
+                    .unwrap_or(rest.len());
+                let word = &rest[..end];
+                rest = &rest[end..];
+
+                if word.starts_with('$') {
+                    continue;
+                }
+
+                // a variation does not end the game
+                if RESULTS.contains(&word) {
+                    if variation_depth == 0 {
+                        tokens.push(Token::Result(word));
+                    }
+                    continue;
+                }
+
+                // move numbers, possibly glued to the move: "12.", "12...", "12.e4"
+                let word = word.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                if !word.is_empty() {
+                    tokens.push(Token::Move(word));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        formats::policy::MateEncoding,
+        testing::{random_game, GameOptions, Rng},
+    };
+
+    const GAME: &str = r#"[Event "?"]
+[Site "?"]
+[Date "????.??.??"]
+[Round "?"]
+[White "?"]
+[Black "?"]
+[Result "0-1"]
+[FEN "rnbqkbnr/pppppppp/8/8/8/5P2/PPPPP1PP/RNBQKBNR b KQkq - 0 1"]
+[SetUp "1"]
+
+1... e5 {-0.20} 2. g4 {-3.10} Qh4# 0-1
+
+"#;
+
+    #[test]
+    fn test_pgn_round_trip() {
+        let entries = PgnReader::new(Cursor::new(GAME))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].score, -20);
+        assert_eq!(entries[0].result, 1);
+        assert_eq!(entries[1].result, -1);
+        assert_eq!(entries[1].mv.as_uci(), "g2g4");
+        assert_eq!(entries[2].score, VALUE_NONE);
+        assert!(entries[0].is_continuation(&entries[1]));
+
+        let mut writer = PgnWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(text, GAME);
+    }
+
+    #[test]
+    fn test_pgn_score_policy() {
+        let game = GAME.replace("{-3.10}", "{-M1}");
+        let round_trip = |read: ScorePolicy, write: ScorePolicy| {
+            let entries = PgnReader::new(Cursor::new(&game))
+                .with_score_policy(read)
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            let mut writer = PgnWriter::new(Vec::new()).with_score_policy(write);
+            for entry in &entries {
+                writer.write_entry(entry).unwrap();
+            }
+            let text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+            (entries, text)
+        };
+
+        let (entries, text) = round_trip(ScorePolicy::default(), ScorePolicy::default());
+        assert_eq!(entries[1].score, -(MATE - 1));
+        assert_eq!(text, game);
+
+        let internal = ScorePolicy {
+            units: crate::pipeline::score::STOCKFISH_INTERNAL,
+            ..ScorePolicy::default()
+        };
+        let (entries, text) = round_trip(internal, internal);
+        assert_eq!(entries[0].score, -42);
+        assert_eq!(text, game);
+
+        let clamp = ScorePolicy {
+            mates: MateEncoding::Clamp(1000),
+            ..ScorePolicy::default()
+        };
+        let (entries, text) = round_trip(clamp, ScorePolicy::default());
+        assert_eq!(entries[1].score, -1000);
+        assert!(text.contains("g4 {-10.00}"));
+
+        let none = ScorePolicy {
+            mates: MateEncoding::None,
+            ..ScorePolicy::default()
+        };
+        let (_, text) = round_trip(ScorePolicy::default(), none);
+        assert!(text.contains("2. g4 Qh4#"));
+    }
+
+    #[test]
+    fn test_pgn_reader_skips_annotations() {
+        let text = r#"[Result "1-0"]
+
+1. e4 {+0.35/12 0.51s} e5 $1 {book} (1... c5 {sicilian} 2. Nf3) 2. Nf3 ; comment
+{-M4} 2... Nc6 {
+multi line } 1-0
+
+1.d4 *
+"#;
+
+        let entries = PgnReader::new(Cursor::new(text))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        let moves = entries.iter().map(|e| e.mv.as_uci()).collect::<Vec<_>>();
+        assert_eq!(moves, ["e2e4", "e7e5", "g1f3", "b8c6", "d2d4"]);
+
+        assert_eq!(entries[0].score, 35);
+        assert_eq!(entries[1].score, VALUE_NONE);
+        assert_eq!(entries[2].score, -(MATE - 4));
+        assert_eq!(entries[0].result, 1);
+        assert_eq!(entries[1].result, -1);
+        assert_eq!(entries[4].result, 0);
+    }
+
+    #[test]
+    fn test_pgn_variations() {
+        let text = r#"[Result "1-0"]
+
+1. e4 {+0.30} e5 (1... c5 {+0.40} 2. Nf3 {+0.35} (2. c3 d5) d6) (1... e6 2. d4)
+2. Nf3 {+0.25} 1-0
+"#;
+        let read = |variation_depth, scored_variations_only| {
+            let options = PgnOptions {
+                variation_depth,
+                scored_variations_only,
+            };
+            PgnReader::with_options(Cursor::new(text), options)
+                .collect::<Result<Vec<_>>>()
+                .unwrap()
+        };
+        let moves = |entries: &[TrainingDataEntry]| {
+            entries.iter().map(|e| e.mv.as_uci()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(moves(&read(0, false)), ["e2e4", "e7e5", "g1f3"]);
+
+        let entries = read(1, false);
+        assert_eq!(
+            moves(&entries),
+            ["e2e4", "e7e5", "g1f3", "c7c5", "g1f3", "d7d6", "e7e6", "d2d4"]
+        );
+        // the variation starts from the position of the move it replaces,
+        // as a chain of its own
+        assert_eq!(entries[3].pos, entries[1].pos);
+        assert_eq!(
+            (entries[3].ply, entries[3].score, entries[3].result),
+            (1, 40, -1)
+        );
+        assert!(!entries[2].is_continuation(&entries[3]));
+        assert!(entries[3].is_continuation(&entries[4]));
+        assert_eq!(entries[5].score, VALUE_NONE);
+
+        let entries = read(2, false);
+        assert_eq!(
+            moves(&entries),
+            ["e2e4", "e7e5", "g1f3", "c7c5", "g1f3", "d7d6", "c2c3", "d7d5", "e7e6", "d2d4"]
+        );
+        assert_eq!(entries[6].pos, entries[4].pos);
+
+        assert_eq!(
+            moves(&read(2, true)),
+            ["e2e4", "e7e5", "g1f3", "c7c5", "g1f3", "d7d6"]
+        );
+
+        let err = PgnReader::with_options(
+            Cursor::new("(1. d4) 1. e4 *\n"),
+            PgnOptions {
+                variation_depth: 1,
+                ..PgnOptions::default()
+            },
+        )
+        .next()
+        .unwrap();
+        assert!(matches!(err, Err(FormatError::Parse { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_pgn_parallel_same_entries() {
+        let mut rng = Rng::new(5);
+        let mut writer = PgnWriter::new(Vec::new());
+        for _ in 0..100 {
+            for entry in random_game(&mut rng, &GameOptions::default()) {
+                writer.write_entry(&entry).unwrap();
+            }
+        }
+        let mut text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        // a game with an illegal move in the middle of the others
+        let middle = text.len() / 2;
+        let middle = middle + text[middle..].find("\n[Event").unwrap() + 1;
+        text.insert_str(middle, "1. e4 e5 2. Ke3 1-0\n\n");
+
+        let sequential = PgnReader::new(Cursor::new(text.clone())).collect::<Vec<_>>();
+        let parallel = PgnReader::new(Cursor::new(text))
+            .parallel(4)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sequential.len(), parallel.len());
+        assert_eq!(sequential.iter().filter(|entry| entry.is_err()).count(), 1);
+        for (sequential, parallel) in sequential.iter().zip(&parallel) {
+            match (sequential, parallel) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b),
+                (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+                _ => panic!("{sequential:?} {parallel:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pgn_illegal_move() {
+        let text = "1. e4 e5 2. Ke3 1-0\n";
+        let err = PgnReader::new(Cursor::new(text)).next().unwrap();
+
+        assert!(matches!(err, Err(FormatError::Parse { line: 1, .. })));
+    }
+}
@@ -0,0 +1,226 @@
+//! The plain text format of the Stockfish tools.
+//!
+//! Each entry is a block of `key value` lines terminated by a line with `e`:
+//!
+//! ```text
+//! fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1
+//! move e2e4
+//! score 35
+//! ply 0
+//! result 0
+//! e
+//! ```
+//!
+//! `score` and `result` are relative to the side to move.
+
+use std::io::{BufRead, Write};
+
+use crate::{
+    chess::{position::Position, r#move::Move},
+    TrainingDataEntry,
+};
+
+use super::{policy::ScorePolicy, EntryWrite, FormatError, Result};
+
+pub struct PlainReader<R: BufRead> {
+    input: R,
+    score_policy: ScorePolicy,
+    line: u64,
+    buffer: String,
+}
+
+impl<R: BufRead> PlainReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            score_policy: ScorePolicy::default(),
+            line: 0,
+            buffer: String::new(),
+        }
+    }
+
+    /// Reads scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    fn error(&self, message: impl Into<String>) -> FormatError {
+        FormatError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        let mut fen = None;
+        let mut uci = None;
+        let mut score = None;
+        let mut ply = None;
+        let mut result = None;
+        let mut started = false;
+
+        loop {
+            self.buffer.clear();
+            if self.input.read_line(&mut self.buffer)? == 0 {
+                if started {
+                    return Err(self.error("unexpected end of input, missing 'e'"));
+                }
+                return Ok(None);
+            }
+            self.line += 1;
+
+            let line = self.buffer.trim();
+            if line.is_empty() {
+                continue;
+            }
+            started = true;
+
+            if line == "e" {
+                break;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+
+            match key {
+                "fen" => fen = Some(value.to_string()),
+                "move" => uci = Some(value.to_string()),
+                "score" => score = Some(self.parse_number::<i64>(key, value)?),
+                "ply" => ply = Some(self.parse_number::<u16>(key, value)?),
+                "result" => result = Some(self.parse_number::<i16>(key, value)?),
+                _ => return Err(self.error(format!("unknown key '{}'", key))),
+            }
+        }
+
+        let fen = fen.ok_or_else(|| self.error("entry without fen"))?;
+        let pos = Position::from_fen(&fen).map_err(|_| self.error("invalid fen"))?;
+
+        let uci = uci.ok_or_else(|| self.error("entry without move"))?;
+        let mv = Move::from_uci(&pos, &uci)
+            .ok_or_else(|| self.error(format!("illegal move '{}'", uci)))?;
+
+        Ok(Some(TrainingDataEntry {
+            pos,
+            mv,
+            score: self
+                .score_policy
+                .score(score.ok_or_else(|| self.error("entry without score"))?),
+            ply: ply.unwrap_or_else(|| pos.ply()),
+            result: result.ok_or_else(|| self.error("entry without result"))?,
+        }))
+    }
+
+    fn parse_number<T: std::str::FromStr>(&self, key: &str, value: &str) -> Result<T> {
+        value
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} '{}'", key, value)))
+    }
+}
+
+impl<R: BufRead> Iterator for PlainReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+pub struct PlainWriter<W: Write> {
+    output: W,
+    score_policy: ScorePolicy,
+}
+
+impl<W: Write> PlainWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            score_policy: ScorePolicy::default(),
+        }
+    }
+
+    /// Writes scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> EntryWrite for PlainWriter<W> {
+    fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        let fen = entry
+            .pos
+            .fen()
+            .map_err(|_| FormatError::InvalidEntry("position has no FEN".to_string()))?;
+
+        writeln!(self.output, "fen {}", fen)?;
+        writeln!(self.output, "move {}", entry.mv.as_uci())?;
+        let score = self.score_policy.score(entry.score.into());
+        writeln!(self.output, "score {}", score)?;
+        writeln!(self.output, "ply {}", entry.ply)?;
+        writeln!(self.output, "result {}", entry.result)?;
+        writeln!(self.output, "e")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const TEXT: &str = "fen 1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 0 35
+move c2c4
+score -201
+ply 68
+result 0
+e
+fen 1q5b/1r5k/4p2p/1b2P1pN/2Pp4/6PP/1n4B1/1Q2B1K1 b - - 0 35
+move d4d3
+score 254
+ply 69
+result 0
+e
+";
+
+    #[test]
+    fn test_plain_round_trip() {
+        let entries = PlainReader::new(Cursor::new(TEXT))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].mv.as_uci(), "d4d3");
+        assert_eq!(entries[1].score, 254);
+        assert!(entries[0].is_continuation(&entries[1]));
+
+        let mut writer = PlainWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn test_plain_errors() {
+        let missing_end = "fen 8/8/8/8/8/8/8/K6k w - - 0 1\nmove a1a2\n";
+        let err = PlainReader::new(Cursor::new(missing_end)).next().unwrap();
+        assert!(matches!(err, Err(FormatError::Parse { line: 2, .. })));
+
+        let illegal = "fen 8/8/8/8/8/8/8/K6k w - - 0 1\nmove a1a3\nscore 0\nresult 0\ne\n";
+        let err = PlainReader::new(Cursor::new(illegal)).next().unwrap();
+        assert!(matches!(err, Err(FormatError::Parse { line: 5, .. })));
+    }
+}
@@ -0,0 +1,236 @@
+//! How scores are encoded on import and export.
+//!
+//! Entries hold evals in [`ScorePolicy::units`] per pawn, centipawns by
+//! default, and a mate in N plies as [`MATE`]` - N` for the side that mates
+//! and its negation for the side that gets mated. Other data rarely agrees:
+//! engines report mates in moves, PGN comments in pawns, some datasets cap
+//! mates at a few thousand or drop them, and evals past the mate scores turn
+//! up too. A [`ScorePolicy`] says what becomes of them. Every reader and
+//! writer of [`formats`](super) applies one, the default if not told
+//! otherwise, so a round trip through any format keeps the scores.
+//!
+//! ```
+//! use sfbinpack::formats::{
+//!     pgn::MATE,
+//!     policy::{MateEncoding, ScorePolicy},
+//!     VALUE_NONE,
+//! };
+//!
+//! let offset = ScorePolicy::default();
+//! assert_eq!(offset.mate(3, true), MATE - 3);
+//! assert_eq!(offset.score(40_000), MATE - 247);
+//!
+//! let clamp = ScorePolicy { mates: MateEncoding::Clamp(3000), ..ScorePolicy::default() };
+//! assert_eq!(clamp.score(i64::from(-(MATE - 3))), -3000);
+//! assert_eq!(clamp.score(4000), 3000);
+//!
+//! let none = ScorePolicy { mates: MateEncoding::None, ..ScorePolicy::default() };
+//! assert_eq!(none.mate(3, true), VALUE_NONE);
+//! ```
+
+use std::str::FromStr;
+
+use crate::pipeline::score::{CENTIPAWNS, MATE_IN_MAX_PLY, MAX_MATE_PLY};
+
+use super::{pgn::MATE, VALUE_NONE};
+
+/// What mate scores and evals out of range become
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MateEncoding {
+    /// A mate in N plies is `MATE - N`, evals are clamped to just below the
+    /// mate scores
+    #[default]
+    Offset,
+    /// Mates and evals beyond the cap become `±cap`, the cap should be below
+    /// [`MATE_IN_MAX_PLY`]
+    Clamp(i16),
+    /// Mates and evals beyond the mate scores become [`VALUE_NONE`]
+    None,
+}
+
+impl FromStr for MateEncoding {
+    type Err = ();
+
+    /// `offset`, `clamp:CAP` or `none`
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        match text {
+            "offset" => Ok(MateEncoding::Offset),
+            "none" => Ok(MateEncoding::None),
+            _ => {
+                let cap = text.strip_prefix("clamp:").ok_or(())?;
+                match cap.parse::<i16>() {
+                    Ok(cap) if (0..MATE_IN_MAX_PLY).contains(&cap) => Ok(MateEncoding::Clamp(cap)),
+                    _ => Err(()),
+                }
+            }
+        }
+    }
+}
+
+/// Units and mate encoding of the scores of entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScorePolicy {
+    /// Units per pawn of the scores of entries, positive, e.g.
+    /// [`STOCKFISH_INTERNAL`](crate::pipeline::score::STOCKFISH_INTERNAL).
+    /// Formats that write pawns or centipawns, and engines, are converted
+    /// from and to it; formats that store the score of the entry as a
+    /// number keep it as it is.
+    pub units: i32,
+    pub mates: MateEncoding,
+}
+
+impl Default for ScorePolicy {
+    fn default() -> Self {
+        Self {
+            units: CENTIPAWNS,
+            mates: MateEncoding::Offset,
+        }
+    }
+}
+
+impl ScorePolicy {
+    /// Largest eval that is not out of range
+    pub fn max_eval(&self) -> i16 {
+        match self.mates {
+            MateEncoding::Clamp(cap) => cap,
+            MateEncoding::Offset | MateEncoding::None => MATE_IN_MAX_PLY - 1,
+        }
+    }
+
+    /// An eval in the units of the entries
+    pub fn eval(&self, eval: i64) -> i16 {
+        let max = i64::from(self.max_eval());
+        if (-max..=max).contains(&eval) {
+            return eval as i16;
+        }
+
+        match self.mates {
+            MateEncoding::None => VALUE_NONE,
+            MateEncoding::Offset | MateEncoding::Clamp(_) => (max * eval.signum()) as i16,
+        }
+    }
+
+    /// An eval in centipawns, rounded half away from zero into the units of
+    /// the entries
+    pub fn centipawns(&self, centipawns: i64) -> i16 {
+        let (units, cp) = (i128::from(self.units), i128::from(CENTIPAWNS));
+        let scaled = i128::from(centipawns) * units;
+        let rounded = (2 * scaled + scaled.signum() * cp) / (2 * cp);
+        self.eval(rounded.clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+    }
+
+    /// An eval in pawns, [`VALUE_NONE`] if it is not finite
+    pub fn pawns(&self, pawns: f64) -> i16 {
+        match pawns.is_finite() {
+            true => self.eval((pawns * f64::from(self.units)).round() as i64),
+            false => VALUE_NONE,
+        }
+    }
+
+    /// A mate in `plies` plies, for the side to move if `mating` and
+    /// against it otherwise. Mates further away than [`MAX_MATE_PLY`] count
+    /// as that far.
+    pub fn mate(&self, plies: u32, mating: bool) -> i16 {
+        let sign = if mating { 1 } else { -1 };
+        match self.mates {
+            MateEncoding::Offset => sign * (MATE - plies.min(MAX_MATE_PLY as u32) as i16),
+            MateEncoding::Clamp(cap) => sign * cap,
+            MateEncoding::None => VALUE_NONE,
+        }
+    }
+
+    /// A score as stored in the entries, read from a format that stores it
+    /// as a number or about to be written: mate scores are encoded again and
+    /// evals out of range handled, [`VALUE_NONE`] stays. Applying it twice
+    /// changes nothing.
+    pub fn score(&self, score: i64) -> i16 {
+        if score == i64::from(VALUE_NONE) {
+            return VALUE_NONE;
+        }
+
+        match i16::try_from(score).ok().and_then(mate_plies) {
+            Some(plies) => self.mate(plies, score > 0),
+            None => self.eval(score),
+        }
+    }
+
+    /// An eval of the entries in centipawns, rounded half away from zero
+    pub fn to_centipawns(&self, score: i16) -> i32 {
+        let (score, units, cp) = (score as i32, self.units, CENTIPAWNS);
+        (2 * score * cp + score.signum() * units) / (2 * units)
+    }
+}
+
+/// Plies to the mate of a mate score encoded as `MATE - N`, None for any
+/// other score
+pub fn mate_plies(score: i16) -> Option<u32> {
+    let abs = score.checked_abs()?;
+    (MATE_IN_MAX_PLY..=MATE)
+        .contains(&abs)
+        .then(|| (MATE - abs) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::score::STOCKFISH_INTERNAL;
+
+    #[test]
+    fn test_score_policy() {
+        let offset = ScorePolicy::default();
+        assert_eq!(offset.score(35), 35);
+        assert_eq!(offset.score(i64::from(MATE - 4)), MATE - 4);
+        assert_eq!(offset.score(-40_000), -(MATE_IN_MAX_PLY - 1));
+        assert_eq!(offset.score(i64::from(VALUE_NONE)), VALUE_NONE);
+        assert_eq!(offset.mate(0, false), -MATE);
+        assert_eq!(offset.mate(u32::MAX, true), MATE_IN_MAX_PLY);
+
+        let clamp = ScorePolicy {
+            mates: MateEncoding::Clamp(2000),
+            ..ScorePolicy::default()
+        };
+        assert_eq!(clamp.score(i64::from(MATE - 4)), 2000);
+        assert_eq!(clamp.score(-2500), -2000);
+        assert_eq!(clamp.score(1999), 1999);
+
+        let none = ScorePolicy {
+            mates: MateEncoding::None,
+            ..ScorePolicy::default()
+        };
+        assert_eq!(none.score(i64::from(-(MATE - 1))), VALUE_NONE);
+        assert_eq!(none.score(32_500), VALUE_NONE);
+        assert_eq!(none.score(-300), -300);
+
+        for policy in [offset, clamp, none] {
+            for score in [0, 35, -(MATE - 4), MATE, 30_000, -32_768, VALUE_NONE] {
+                let once = policy.score(score.into());
+                assert_eq!(policy.score(once.into()), once, "{policy:?} {score}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_score_units() {
+        let internal = ScorePolicy {
+            units: STOCKFISH_INTERNAL,
+            ..ScorePolicy::default()
+        };
+        assert_eq!(internal.centipawns(100), 208);
+        assert_eq!(internal.centipawns(-50), -104);
+        assert_eq!(internal.pawns(1.5), 312);
+        assert_eq!(internal.to_centipawns(312), 150);
+        assert_eq!(internal.to_centipawns(-104), -50);
+        assert_eq!(internal.pawns(f64::NAN), VALUE_NONE);
+        assert_eq!(internal.centipawns(i64::MAX), MATE_IN_MAX_PLY - 1);
+    }
+
+    #[test]
+    fn test_mate_encoding_from_str() {
+        assert_eq!("offset".parse(), Ok(MateEncoding::Offset));
+        assert_eq!("clamp:3000".parse(), Ok(MateEncoding::Clamp(3000)));
+        assert_eq!("none".parse(), Ok(MateEncoding::None));
+        assert_eq!("clamp:-1".parse::<MateEncoding>(), Err(()));
+        assert_eq!("clamp:31900".parse::<MateEncoding>(), Err(()));
+        assert_eq!("cap".parse::<MateEncoding>(), Err(()));
+    }
+}
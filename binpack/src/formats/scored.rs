@@ -0,0 +1,327 @@
+//! Text dumps with a FEN, a move, a score and a result per line, as
+//! written by the usual rescoring scripts:
+//!
+//! ```text
+//! rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | e2e4 | 35 | 0
+//! ```
+//!
+//! The order of the columns and the delimiter are set by [`ScoredOptions`],
+//! extra columns can be skipped. The FEN may leave out its counters, the
+//! move is UCI or SAN, `0000` or `-` for none. The result is -1, 0 or 1,
+//! or `1-0`, `0-1` and `1/2-1/2`, which are always white's. Score and
+//! numeric result are relative to the side to move, or to white with
+//! [`ScoredOptions::white_relative`]. The ply comes from the fullmove
+//! number of the FEN. Blank lines and lines starting with `#` are skipped.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use sfbinpack::formats::scored::{Column, ScoredOptions, ScoredReader};
+//!
+//! let text = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1,120,e2e4,1\n";
+//! let options = ScoredOptions {
+//!     columns: vec![Column::Fen, Column::Score, Column::Move, Column::Result],
+//!     delimiter: ',',
+//!     ..ScoredOptions::default()
+//! };
+//!
+//! let entry = ScoredReader::with_options(Cursor::new(text), options)
+//!     .next()
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!((entry.mv.as_uci().as_str(), entry.score), ("e2e4", 120));
+//! ```
+
+use std::{io::BufRead, str::FromStr};
+
+use crate::{
+    chess::{position::Position, r#move::Move, san},
+    GameResult, Score, TrainingDataEntry,
+};
+
+use super::{policy::ScorePolicy, FormatError, Result};
+
+/// What a column of a line holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Fen,
+    Move,
+    Score,
+    Result,
+    /// Anything else, ignored
+    Skip,
+}
+
+impl FromStr for Column {
+    type Err = ();
+
+    /// `fen`, `move` or `bestmove`, `score`, `result`, and `skip` or `-`
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "fen" => Ok(Column::Fen),
+            "move" | "bestmove" => Ok(Column::Move),
+            "score" => Ok(Column::Score),
+            "result" => Ok(Column::Result),
+            "skip" | "-" => Ok(Column::Skip),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Layout of the lines of [`ScoredReader`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoredOptions {
+    /// The columns in order, `fen | bestmove | score | result` by default.
+    /// The FEN is required, without a move column the entries get none,
+    /// without a score or result column a score of 0 and a draw.
+    pub columns: Vec<Column>,
+    /// Between the columns, `|` by default. A space does not work, the FEN
+    /// has spaces of its own.
+    pub delimiter: char,
+    /// Score and numeric result are white's rather than the side to move's
+    pub white_relative: bool,
+}
+
+impl Default for ScoredOptions {
+    fn default() -> Self {
+        Self {
+            columns: vec![Column::Fen, Column::Move, Column::Score, Column::Result],
+            delimiter: '|',
+            white_relative: false,
+        }
+    }
+}
+
+pub struct ScoredReader<R: BufRead> {
+    input: R,
+    options: ScoredOptions,
+    score_policy: ScorePolicy,
+    line: u64,
+    buffer: String,
+}
+
+impl<R: BufRead> ScoredReader<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_options(input, ScoredOptions::default())
+    }
+
+    pub fn with_options(input: R, options: ScoredOptions) -> Self {
+        Self {
+            input,
+            options,
+            score_policy: ScorePolicy::default(),
+            line: 0,
+            buffer: String::new(),
+        }
+    }
+
+    /// Reads scores with `policy` rather than the default one
+    pub fn with_score_policy(mut self, policy: ScorePolicy) -> Self {
+        self.score_policy = policy;
+        self
+    }
+
+    fn error(&self, message: impl Into<String>) -> FormatError {
+        FormatError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        loop {
+            self.buffer.clear();
+            if self.input.read_line(&mut self.buffer)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+
+            let line = self.buffer.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            return self.parse_line(line).map(Some);
+        }
+    }
+
+    fn parse_line(&self, line: &str) -> Result<TrainingDataEntry> {
+        let fields = line.split(self.options.delimiter).collect::<Vec<_>>();
+        if fields.len() != self.options.columns.len() {
+            return Err(self.error(format!(
+                "expected {} columns, found {}",
+                self.options.columns.len(),
+                fields.len()
+            )));
+        }
+
+        let column = |column| {
+            let index = self.options.columns.iter().position(|&c| c == column)?;
+            Some(fields[index].trim())
+        };
+
+        let fen = column(Column::Fen).ok_or_else(|| self.error("no fen column"))?;
+        let pos = parse_fen(fen).ok_or_else(|| self.error(format!("invalid fen '{}'", fen)))?;
+        // moves are only generated on boards that could come up in a game
+        pos.check_valid()
+            .map_err(|reason| self.error(format!("invalid position '{}': {}", fen, reason)))?;
+        let stm = pos.side_to_move();
+
+        let mv = match column(Column::Move) {
+            None | Some("0000" | "-" | "") => Move::null(),
+            Some(text) => san::parse_san(&pos, text)
+                .or_else(|| Move::from_uci(&pos, text))
+                .ok_or_else(|| self.error(format!("illegal move '{}'", text)))?,
+        };
+
+        let score = match column(Column::Score) {
+            None => 0,
+            Some(text) => text
+                .parse::<i64>()
+                .map(|score| self.score_policy.score(score))
+                .map_err(|_| self.error(format!("invalid score '{}'", text)))?,
+        };
+
+        let (result, white) = match column(Column::Result) {
+            None => (GameResult::Draw, false),
+            Some(text) => parse_result(text)
+                .ok_or_else(|| self.error(format!("invalid result '{}'", text)))?,
+        };
+
+        let (score, result) = match (self.options.white_relative, white) {
+            (true, _) => (
+                Score::new(score).to_white_relative(stm).get(),
+                result.to_white_relative(stm),
+            ),
+            (false, true) => (score, result.to_white_relative(stm)),
+            (false, false) => (score, result),
+        };
+
+        Ok(TrainingDataEntry {
+            pos,
+            mv,
+            score,
+            ply: pos.ply(),
+            result: result.into(),
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for ScoredReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+/// A FEN with or without its two counters
+fn parse_fen(fen: &str) -> Option<Position> {
+    match fen.split_whitespace().count() {
+        4 => Position::from_fen(&format!("{} 0 1", fen)).ok(),
+        6 => Position::from_fen(fen).ok(),
+        _ => None,
+    }
+}
+
+/// The result and whether it is white's regardless of the options
+fn parse_result(text: &str) -> Option<(GameResult, bool)> {
+    match text {
+        "1-0" => Some((GameResult::Win, true)),
+        "0-1" => Some((GameResult::Loss, true)),
+        "1/2-1/2" => Some((GameResult::Draw, true)),
+        _ => {
+            let value = text.parse::<i16>().ok()?;
+            Some((GameResult::try_from(value).ok()?, false))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read(text: &str, options: ScoredOptions) -> Result<Vec<TrainingDataEntry>> {
+        ScoredReader::with_options(Cursor::new(text), options).collect()
+    }
+
+    #[test]
+    fn test_scored_default_columns() {
+        let text = "\
+# fen | bestmove | score | result
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | e2e4 | 35 | 1
+
+rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1 | e5 | -20 | 1-0
+8/8/8/8/8/4k3/8/4K2R w K - | O-O | 900 | 1
+";
+        let entries = read(text, ScoredOptions::default()).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].mv.as_uci(), "e2e4");
+        assert_eq!(
+            (entries[0].score, entries[0].result, entries[0].ply),
+            (35, 1, 0)
+        );
+        assert_eq!(entries[1].mv.as_uci(), "e7e5");
+        assert_eq!(
+            (entries[1].score, entries[1].result, entries[1].ply),
+            (-20, -1, 1)
+        );
+        assert!(entries[0].is_continuation(&entries[1]));
+        assert_eq!(entries[2].mv.as_uci(), "e1g1");
+        assert_eq!((entries[2].score, entries[2].result), (900, 1));
+    }
+
+    #[test]
+    fn test_scored_options() {
+        let text = "0.7\t4k3/8/8/8/8/8/4P3/4K3 b - - 3 20\t-150\t-1\n";
+        let options = ScoredOptions {
+            columns: vec![Column::Skip, Column::Fen, Column::Score, Column::Result],
+            delimiter: '\t',
+            white_relative: true,
+        };
+        let entries = read(text, options).unwrap();
+
+        assert_eq!(entries[0].mv, Move::null());
+        assert_eq!(
+            (entries[0].score, entries[0].result, entries[0].ply),
+            (150, 1, 39)
+        );
+        assert_eq!(entries[0].pos.rule50_counter(), 3);
+    }
+
+    #[test]
+    fn test_scored_errors() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        for (line, message) in [
+            (format!("{fen} | e2e4 | 35"), "expected 4 columns, found 3"),
+            (format!("{fen} | e2e5 | 35 | 0"), "illegal move 'e2e5'"),
+            (format!("{fen} | e2e4 | 35cp | 0"), "invalid score '35cp'"),
+            (format!("{fen} | e2e4 | 35 | 2"), "invalid result '2'"),
+            (
+                "4k3/8/8/8 w | e2e4 | 35 | 0".to_string(),
+                "invalid fen '4k3/8/8/8 w'",
+            ),
+            (
+                "8/8/8/8/8/8/8/8 w - - | e2e4 | 35 | 0".to_string(),
+                "invalid position '8/8/8/8/8/8/8/8 w - -': a side does not have exactly one king",
+            ),
+        ] {
+            let text = format!("{fen} | e2e4 | 35 | 0\n{line}\n");
+            match read(&text, ScoredOptions::default()) {
+                Err(FormatError::Parse {
+                    line: 2,
+                    message: m,
+                }) => assert_eq!(m, message),
+                other => panic!("{line}: {other:?}"),
+            }
+        }
+
+        let options = ScoredOptions {
+            columns: vec![Column::Move, Column::Score],
+            ..ScoredOptions::default()
+        };
+        assert!(read("e2e4 | 35\n", options).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+//! Internals the fuzz targets in `fuzz/` drive directly, not part of the API.
+
+use crate::chess::{attacks, position::Position, r#move::Move};
+
+pub use crate::common::bitreader::BitReader;
+pub use crate::common::entry::PackedTrainingDataEntry;
+pub use crate::common::move_score_list_reader::PackedMoveScoreListReader;
+
+/// Whether the position can arise in a game, the check the checked reader
+/// applies to every stem
+pub fn check_position(pos: &Position) -> Result<(), &'static str> {
+    pos.check_valid()
+}
+
+/// Whether `mv` is legal in `pos`, which must pass check_position()
+pub fn is_legal(pos: &Position, mv: Move) -> bool {
+    attacks::is_legal(pos, mv)
+}
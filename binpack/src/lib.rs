@@ -1,14 +1,88 @@
+#![cfg_attr(not(feature = "unsafe-opt"), forbid(unsafe_code))]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+#[macro_use]
+mod telemetry;
+
 mod common;
+#[cfg(feature = "std")]
 mod reader;
+#[cfg(feature = "std")]
 mod writer;
 
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod annotation;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod book;
+#[cfg(feature = "std")]
+pub mod checksum;
 pub mod chess;
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod features;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod formats;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod meta;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod resume;
+pub mod rng;
+#[cfg(feature = "std")]
+pub mod sample;
+#[cfg(feature = "zstd")]
+pub mod seekable;
+#[cfg(feature = "std")]
+pub mod shard;
+#[cfg(feature = "std")]
+pub mod stats;
+
+#[cfg(any(all(test, feature = "std"), feature = "testing"))]
+pub mod testing;
+
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
 
+#[cfg(feature = "std")]
 pub use common::binpack_error::BinpackError;
+#[cfg(feature = "std")]
+pub use common::buffer_pool::BufferPool;
+pub use common::compressed_position::{CompressedPosition, CompressedPositionError};
+pub use common::entry::PackedTrainingDataEntry;
 pub use common::entry::TrainingDataEntry;
+#[cfg(feature = "std")]
+pub use common::game::Game;
+#[cfg(feature = "std")]
+pub use common::lazy_entry::LazyEntry;
+pub use common::values::{GameResult, Ply, Score, ValueError};
 
-pub use reader::CompressedReaderError;
-pub use reader::CompressedTrainingDataEntryReader;
+#[cfg(feature = "remote")]
+pub use reader::remote;
+#[cfg(all(feature = "std", unix))]
+pub use reader::PreadFile;
+#[cfg(feature = "std")]
+pub use reader::{
+    ChainLocation, ChainRecord, CompressedReaderError, CompressedTrainingDataEntryReader,
+    FormatDialect, Games, LazyEntries, ReaderOptions, ScoreRecord,
+};
 
-pub use writer::CompressedTrainingDataEntryWriter;
-pub use writer::CompressedWriterError;
+#[cfg(feature = "std")]
+pub use writer::{
+    BrokenChain, CompressedTrainingDataEntryWriter, CompressedWriterError, WriterOptions,
+    WriterStats,
+};
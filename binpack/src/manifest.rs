@@ -0,0 +1,624 @@
+//! Dataset manifests: the shards of a dataset and which chunks of them each
+//! process of a distributed training run reads in an epoch.
+//!
+//! A manifest lists shard files with their entry, game and chunk counts,
+//! size and CRC32, one flat JSON object per line:
+//!
+//! ```text
+//! {"path": "shard-0000.binpack", "entries": 1048576, "games": 9731, "chunks": 12, "size": 12345678, "crc32": 305419896}
+//! ```
+//!
+//! Relative paths are relative to the manifest. Chunks never split a game,
+//! so they are the unit of work: [`DatasetManifest::assign`] shuffles the
+//! shards with a seed and the epoch, lays their chunks end to end and gives
+//! every rank a contiguous, equally long run of them. Every rank computes
+//! the same order on its own, there is nothing to coordinate, and the
+//! shuffle does not depend on the version of `rand`.
+//!
+//! ```
+//! use sfbinpack::manifest::{DatasetManifest, Partition};
+//!
+//! let manifest = DatasetManifest::scan(["test/ep1.binpack", "test/ep1.binpack"]).unwrap();
+//! assert_eq!(manifest.entries(), 6);
+//!
+//! // two ranks, one chunk each
+//! let mut entries = 0;
+//! for rank in 0..2 {
+//!     let partition = Partition { rank, world_size: 2, epoch: 0, seed: 7 };
+//!     for entry in manifest.reader(&partition).unwrap() {
+//!         entry.unwrap();
+//!         entries += 1;
+//!     }
+//! }
+//! assert_eq!(entries, 6);
+//! ```
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    vec,
+};
+
+use thiserror::Error;
+
+use crate::{
+    common::compressed_training_file_writer::{self, PADDING_MAGIC},
+    formats::jsonl::{parse_object, Value},
+    meta::{quote, DatasetMeta, MetaError},
+    rng::SplitMix64,
+    CompressedReaderError, CompressedTrainingDataEntryReader, ReaderOptions, TrainingDataEntry,
+};
+
+const CHUNK_HEADER_SIZE: u64 = compressed_training_file_writer::CHUNK_HEADER_SIZE as u64;
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Reader error: {0}")]
+    Reader(#[from] CompressedReaderError),
+
+    #[error("Sidecar error: {0}")]
+    Meta(#[from] MetaError),
+
+    #[error("Invalid manifest line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("Rank {rank} is not below the world size {world_size}")]
+    InvalidPartition { rank: usize, world_size: usize },
+
+    #[error("{path} changed since the manifest was written")]
+    Changed { path: PathBuf },
+}
+
+type Result<T> = std::result::Result<T, ManifestError>;
+
+/// A shard file of a dataset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestShard {
+    pub path: PathBuf,
+    pub entries: u64,
+    pub games: u64,
+    pub chunks: u64,
+    /// Bytes of the file
+    pub size: u64,
+    /// CRC32 of the whole file
+    pub crc32: u32,
+}
+
+impl ManifestShard {
+    /// Counts and hashes the binpack at `path`. The entry and game counts of
+    /// a fresh `.binpack.meta` sidecar are used instead of decoding the
+    /// file.
+    pub fn scan(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+
+        let chunks = chunk_offsets(&mut file, u64::MAX)?.len() as u64;
+
+        file.seek(SeekFrom::Start(0))?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut file = BufReader::with_capacity(1 << 20, file);
+        loop {
+            let data = file.fill_buf()?;
+            if data.is_empty() {
+                break;
+            }
+            hasher.update(data);
+            let len = data.len();
+            file.consume(len);
+        }
+
+        let (entries, games) = match DatasetMeta::read(&path)? {
+            Some(meta) => (meta.entries, meta.games),
+            None => count_entries(File::open(&path)?)?,
+        };
+
+        Ok(Self {
+            path,
+            entries,
+            games,
+            chunks,
+            size,
+            crc32: hasher.finalize(),
+        })
+    }
+
+    fn to_json(&self, path: &Path) -> String {
+        format!(
+            "{{\"path\": {}, \"entries\": {}, \"games\": {}, \"chunks\": {}, \"size\": {}, \"crc32\": {}}}",
+            quote(&path.to_string_lossy()),
+            self.entries,
+            self.games,
+            self.chunks,
+            self.size,
+            self.crc32
+        )
+    }
+
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut path = None;
+        let mut numbers = [None; 5];
+        const NUMBERS: [&str; 5] = ["entries", "games", "chunks", "size", "crc32"];
+
+        for (key, value) in parse_object(text)? {
+            match (key.as_str(), value) {
+                ("path", Value::String(value)) => path = Some(PathBuf::from(value)),
+                ("path", _) => return Err("unexpected type for 'path'".to_string()),
+                (key, value) => {
+                    let Some(idx) = NUMBERS.iter().position(|&name| name == key) else {
+                        continue;
+                    };
+                    numbers[idx] = match value {
+                        Value::Number(value) if value >= 0 => Some(value as u64),
+                        _ => return Err(format!("invalid '{}'", key)),
+                    };
+                }
+            }
+        }
+
+        let path = path.ok_or("missing 'path'")?;
+        let mut numbers = numbers.into_iter().zip(NUMBERS);
+        let mut number = || {
+            let (value, key) = numbers.next().unwrap();
+            value.ok_or_else(|| format!("missing '{}'", key))
+        };
+
+        Ok(Self {
+            path,
+            entries: number()?,
+            games: number()?,
+            chunks: number()?,
+            size: number()?,
+            crc32: u32::try_from(number()?).map_err(|_| "invalid 'crc32'".to_string())?,
+        })
+    }
+}
+
+/// The shards of a dataset
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatasetManifest {
+    pub shards: Vec<ManifestShard>,
+}
+
+/// Which process of how many reads, in which epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Partition {
+    /// Zero based index of the process
+    pub rank: usize,
+    pub world_size: usize,
+    pub epoch: u64,
+    /// Same for all ranks of a run, a different one reshuffles every epoch
+    pub seed: u64,
+}
+
+/// A run of chunks of one shard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkRange {
+    /// Index into [`DatasetManifest::shards`]
+    pub shard: usize,
+    pub chunks: Range<u64>,
+}
+
+impl DatasetManifest {
+    pub fn new(shards: Vec<ManifestShard>) -> Self {
+        Self { shards }
+    }
+
+    /// A manifest of the binpacks at `paths`, see [`ManifestShard::scan`]
+    pub fn scan<P: Into<PathBuf>>(paths: impl IntoIterator<Item = P>) -> Result<Self> {
+        let shards = paths
+            .into_iter()
+            .map(ManifestShard::scan)
+            .collect::<Result<_>>()?;
+        Ok(Self::new(shards))
+    }
+
+    /// Reads the manifest at `path`, relative shard paths are resolved
+    /// against its directory
+    pub fn read(path: &Path) -> Result<Self> {
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut manifest = Self::parse(BufReader::new(File::open(path)?))?;
+        for shard in &mut manifest.shards {
+            shard.path = base.join(&shard.path);
+        }
+        Ok(manifest)
+    }
+
+    /// Reads a manifest, one shard per line, empty lines are skipped
+    pub fn parse(reader: impl BufRead) -> Result<Self> {
+        let mut shards = Vec::new();
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let shard =
+                ManifestShard::parse(line.trim()).map_err(|message| ManifestError::Parse {
+                    line: idx + 1,
+                    message,
+                })?;
+            shards.push(shard);
+        }
+        Ok(Self::new(shards))
+    }
+
+    /// Writes the manifest to `path`, shard paths below its directory are
+    /// written relative to it
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut text = String::new();
+        for shard in &self.shards {
+            let relative = shard.path.strip_prefix(base).unwrap_or(&shard.path);
+            text.push_str(&shard.to_json(relative));
+            text.push('\n');
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.entries).sum()
+    }
+
+    pub fn games(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.games).sum()
+    }
+
+    pub fn chunks(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.chunks).sum()
+    }
+
+    /// Indices of the shards whose file no longer has the size and CRC32 of
+    /// the manifest
+    pub fn verify(&self) -> Result<Vec<usize>> {
+        let mut changed = Vec::new();
+        for (idx, shard) in self.shards.iter().enumerate() {
+            let scanned = ManifestShard::scan(&shard.path)?;
+            if (scanned.size, scanned.crc32) != (shard.size, shard.crc32) {
+                changed.push(idx);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// The chunks `partition` reads, in order. The ranks of one epoch read
+    /// every chunk exactly once, and their shares differ by at most one
+    /// chunk.
+    pub fn assign(&self, partition: &Partition) -> Result<Vec<ChunkRange>> {
+        let Partition {
+            rank,
+            world_size,
+            epoch,
+            seed,
+        } = *partition;
+        if rank >= world_size {
+            return Err(ManifestError::InvalidPartition { rank, world_size });
+        }
+
+        let total = self.chunks() as u128;
+        let start = (total * rank as u128 / world_size as u128) as u64;
+        let end = (total * (rank + 1) as u128 / world_size as u128) as u64;
+
+        let mut ranges = Vec::new();
+        let mut offset = 0;
+        for shard in shard_order(self.shards.len(), seed, epoch) {
+            let chunks = self.shards[shard].chunks;
+            let from = start.max(offset);
+            let to = end.min(offset + chunks);
+            if from < to {
+                ranges.push(ChunkRange {
+                    shard,
+                    chunks: from - offset..to - offset,
+                });
+            }
+            offset += chunks;
+        }
+
+        Ok(ranges)
+    }
+
+    /// A reader of the chunks of `range`
+    pub fn open(&self, range: &ChunkRange) -> Result<CompressedTrainingDataEntryReader<File>> {
+        let shard = &self.shards[range.shard];
+        let mut file = File::open(&shard.path)?;
+        if file.metadata()?.len() != shard.size {
+            return Err(ManifestError::Changed {
+                path: shard.path.clone(),
+            });
+        }
+
+        let offsets = chunk_offsets(&mut file, range.chunks.end)?;
+        if (offsets.len() as u64) < range.chunks.end {
+            return Err(ManifestError::Changed {
+                path: shard.path.clone(),
+            });
+        }
+
+        let start = offsets
+            .get(range.chunks.start as usize)
+            .copied()
+            .unwrap_or(shard.size);
+        let end = match offsets.get(range.chunks.end as usize) {
+            Some(&end) => end,
+            None => shard.size,
+        };
+
+        file.seek(SeekFrom::Start(start))?;
+        let options = ReaderOptions {
+            len_hint: Some(end - start),
+            ..Default::default()
+        };
+        Ok(CompressedTrainingDataEntryReader::with_options(
+            file, options,
+        )?)
+    }
+
+    /// Reads the entries `partition` is assigned, see
+    /// [`assign`](Self::assign)
+    pub fn reader(&self, partition: &Partition) -> Result<EpochReader<'_>> {
+        Ok(EpochReader {
+            manifest: self,
+            ranges: self.assign(partition)?.into_iter(),
+            current: None,
+        })
+    }
+}
+
+/// The entries of the chunk ranges of one rank in one epoch, from
+/// [`DatasetManifest::reader`]
+#[derive(Debug)]
+pub struct EpochReader<'a> {
+    manifest: &'a DatasetManifest,
+    ranges: vec::IntoIter<ChunkRange>,
+    current: Option<CompressedTrainingDataEntryReader<File>>,
+}
+
+impl Iterator for EpochReader<'_> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(reader) = &mut self.current {
+                if reader.has_next() {
+                    return Some(reader.try_next().map_err(ManifestError::from));
+                }
+            }
+
+            let range = self.ranges.next()?;
+            match self.manifest.open(&range) {
+                Ok(reader) => self.current = Some(reader),
+                Err(err) => {
+                    self.current = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Start offsets of the first `limit` chunks of `file`, walking the chunk
+/// headers from the start of the file and passing over padding chunks
+fn chunk_offsets(file: &mut File, limit: u64) -> io::Result<Vec<u64>> {
+    let len = file.metadata()?.len();
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    let mut header = [0u8; CHUNK_HEADER_SIZE as usize];
+
+    while offset + CHUNK_HEADER_SIZE <= len && (offsets.len() as u64) <= limit {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        if &header[..4] != PADDING_MAGIC {
+            offsets.push(offset);
+        }
+
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap());
+        offset += CHUNK_HEADER_SIZE + size as u64;
+    }
+
+    Ok(offsets)
+}
+
+fn count_entries(file: File) -> Result<(u64, u64)> {
+    let mut reader = CompressedTrainingDataEntryReader::new(file)?;
+    let (mut entries, mut games) = (0, 0);
+    while reader.has_next() {
+        if !reader.is_next_entry_continuation() {
+            games += 1;
+        }
+        reader.try_next()?;
+        entries += 1;
+    }
+    Ok((entries, games))
+}
+
+/// The shards in the order of `epoch`, shuffled by SplitMix64
+fn shard_order(shards: usize, seed: u64, epoch: u64) -> Vec<usize> {
+    let mut rng = SplitMix64::new(seed ^ epoch.wrapping_mul(0xD1B5_4A32_D192_ED03));
+    let mut order = (0..shards).collect::<Vec<_>>();
+    rng.shuffle(&mut order);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A shard of `copies` chunks, each a copy of ep1.binpack
+    fn shard(dir: &Path, name: &str, copies: usize) -> PathBuf {
+        let data = fs::read("test/ep1.binpack").unwrap();
+        let path = dir.join(name);
+        fs::write(&path, data.repeat(copies)).unwrap();
+        path
+    }
+
+    fn dataset(dir: &Path) -> DatasetManifest {
+        let paths = [
+            shard(dir, "a.binpack", 3),
+            shard(dir, "b.binpack", 1),
+            shard(dir, "c.binpack", 5),
+        ];
+        DatasetManifest::scan(paths).unwrap()
+    }
+
+    #[test]
+    fn test_manifest_scan_and_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dataset(dir.path());
+        assert_eq!(manifest.chunks(), 9);
+        assert_eq!(manifest.entries(), 27);
+        assert_eq!(manifest.games(), 9);
+        assert_eq!(
+            manifest.shards[0].crc32,
+            crc32fast::hash(&fs::read(&manifest.shards[0].path).unwrap())
+        );
+
+        let path = dir.path().join("dataset.manifest");
+        manifest.write(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("{\"path\": \"a.binpack\""), "{text}");
+        assert_eq!(DatasetManifest::read(&path).unwrap(), manifest);
+
+        assert_eq!(manifest.verify().unwrap(), Vec::<usize>::new());
+        shard(dir.path(), "b.binpack", 2);
+        assert_eq!(manifest.verify().unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_manifest_parse() {
+        let text = "{\"path\": \"x\", \"entries\": 1, \"games\": 1, \"chunks\": 1, \"size\": 2, \"crc32\": 3, \"new\": \"ok\"}\n\n";
+        let manifest = DatasetManifest::parse(Cursor::new(text)).unwrap();
+        assert_eq!(manifest.shards[0].crc32, 3);
+
+        for text in [
+            "{\"entries\": 1, \"games\": 1, \"chunks\": 1, \"size\": 2, \"crc32\": 3}",
+            "{\"path\": \"x\", \"entries\": 1, \"games\": 1, \"chunks\": 1, \"size\": 2}",
+            "{\"path\": \"x\", \"entries\": -1, \"games\": 1, \"chunks\": 1, \"size\": 2, \"crc32\": 3}",
+            "{\"path\": \"x\", \"entries\": 1, \"games\": 1, \"chunks\": 1, \"size\": 2, \"crc32\": 4294967296}",
+            "not json",
+        ] {
+            let text = format!("\n{}\n", text);
+            assert!(
+                matches!(
+                    DatasetManifest::parse(Cursor::new(&text)),
+                    Err(ManifestError::Parse { line: 2, .. })
+                ),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_manifest_assign() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dataset(dir.path());
+
+        for world_size in 1..=10 {
+            let mut seen = vec![vec![false; 5]; 3];
+            for rank in 0..world_size {
+                let partition = Partition {
+                    rank,
+                    world_size,
+                    epoch: 2,
+                    seed: 11,
+                };
+                let ranges = manifest.assign(&partition).unwrap();
+                assert_eq!(ranges, manifest.assign(&partition).unwrap());
+
+                let chunks = ranges
+                    .iter()
+                    .map(|range| range.chunks.end - range.chunks.start)
+                    .sum::<u64>();
+                assert!(
+                    chunks == 9 / world_size as u64 || chunks == 9_u64.div_ceil(world_size as u64)
+                );
+
+                for range in ranges {
+                    for chunk in range.chunks {
+                        assert!(!seen[range.shard][chunk as usize]);
+                        seen[range.shard][chunk as usize] = true;
+                    }
+                }
+            }
+            for (shard, seen) in manifest.shards.iter().zip(&seen) {
+                assert_eq!(
+                    seen.iter().filter(|&&seen| seen).count() as u64,
+                    shard.chunks
+                );
+            }
+        }
+
+        // the shard order changes with the epoch
+        let orders = (0..8)
+            .map(|epoch| shard_order(3, 11, epoch))
+            .collect::<Vec<_>>();
+        assert!(orders.iter().any(|order| *order != orders[0]));
+
+        let partition = Partition {
+            rank: 2,
+            world_size: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            manifest.assign(&partition),
+            Err(ManifestError::InvalidPartition {
+                rank: 2,
+                world_size: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_epoch_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dataset(dir.path());
+
+        let mut ep1 =
+            CompressedTrainingDataEntryReader::new(File::open("test/ep1.binpack").unwrap())
+                .unwrap();
+        let mut expected = Vec::new();
+        while ep1.has_next() {
+            expected.push(ep1.next());
+        }
+
+        let mut all = Vec::new();
+        for rank in 0..4 {
+            let partition = Partition {
+                rank,
+                world_size: 4,
+                epoch: 0,
+                seed: 1,
+            };
+            let entries = manifest
+                .reader(&partition)
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            // whole chunks, so whole copies of ep1
+            assert_eq!(entries.len() % 3, 0);
+            for copy in entries.chunks(3) {
+                assert_eq!(copy, expected);
+            }
+            all.extend(entries);
+        }
+        assert_eq!(all.len(), 27);
+
+        // a shard that changed size is refused
+        fs::write(&manifest.shards[2].path, b"").unwrap();
+        let partition = Partition {
+            rank: 0,
+            world_size: 1,
+            ..Default::default()
+        };
+        let results = manifest.reader(&partition).unwrap().collect::<Vec<_>>();
+        assert!(results
+            .iter()
+            .any(|result| matches!(result, Err(ManifestError::Changed { .. }))));
+    }
+}
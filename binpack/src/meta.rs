@@ -0,0 +1,409 @@
+//! `.binpack.meta` sidecars, summaries of a binpack stored next to it.
+//!
+//! A sidecar is a flat JSON object in `data.binpack.meta` next to
+//! `data.binpack`:
+//!
+//! ```text
+//! {
+//!   "entries": 3,
+//!   "games": 1,
+//!   "file_size": 46,
+//!   "generator": "binpack-tools",
+//!   "generator_version": "0.1.0",
+//!   "created": 1760486400,
+//!   "filter": "score < 0",
+//!   "score_sources": "search:d9=2,tablebase=1",
+//!   "result_sources": "played=3"
+//! }
+//! ```
+//!
+//! `score_sources` and `result_sources` count the entries by where their
+//! scores and results come from, with the provenance tags of
+//! [`ScoreSource`] and [`ResultSource`], so a dataset mixed from several
+//! sources stays auditable. Entries can carry the same tags one by one in
+//! their [annotations](crate::annotation).
+//!
+//! Only `entries` and `games` are required. `file_size` is the size of the
+//! binpack when the sidecar was written, [`DatasetMeta::read`] ignores a
+//! sidecar whose binpack has since changed size. Unknown keys with string,
+//! number, boolean or null values are ignored, so later versions can add to
+//! the format.
+//!
+//! ```
+//! use sfbinpack::{meta::DatasetMeta, CompressedTrainingDataEntryWriter, TrainingDataEntry};
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let path = dir.path().join("data.binpack");
+//!
+//! let file = std::fs::File::create(&path).unwrap();
+//! let mut writer = CompressedTrainingDataEntryWriter::new(file).unwrap();
+//! writer.write_entry(&TrainingDataEntry::default()).unwrap();
+//! writer.flush_and_end();
+//!
+//! DatasetMeta::from_stats(&writer.stats())
+//!     .with_generator("my-generator", "1.2")
+//!     .write(&path)
+//!     .unwrap();
+//!
+//! let meta = DatasetMeta::read(&path).unwrap().unwrap();
+//! assert_eq!((meta.entries, meta.games), (1, 1));
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write as _},
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
+use crate::{
+    annotation::{Annotation, ResultSource, ScoreSource},
+    formats::jsonl::{parse_object, Value},
+    WriterStats,
+};
+
+/// Appended to the name of a binpack for the name of its sidecar
+pub const EXTENSION: &str = "meta";
+
+#[derive(Debug, Error)]
+pub enum MetaError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid sidecar: {0}")]
+    Parse(String),
+}
+
+type Result<T> = std::result::Result<T, MetaError>;
+
+/// What a sidecar says about its binpack
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatasetMeta {
+    pub entries: u64,
+    /// Chains of the binpack, every game starts one
+    pub games: u64,
+    /// Bytes of the binpack, filled in by [`DatasetMeta::write`]
+    pub file_size: Option<u64>,
+    /// Name of the program that wrote the binpack
+    pub generator: Option<String>,
+    pub generator_version: Option<String>,
+    /// Seconds since the Unix epoch
+    pub created: Option<u64>,
+    /// How the entries were filtered, in the generator's own words
+    pub filter: Option<String>,
+    /// Entries by where their score comes from
+    pub score_sources: BTreeMap<ScoreSource, u64>,
+    /// Entries by how the result of their game was decided
+    pub result_sources: BTreeMap<ResultSource, u64>,
+}
+
+/// The sidecar of the binpack at `path`, `path` with `.meta` appended
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+impl DatasetMeta {
+    /// Counts of a binpack created now
+    pub fn new(entries: u64, games: u64) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .ok();
+
+        Self {
+            entries,
+            games,
+            created,
+            ..Default::default()
+        }
+    }
+
+    /// Counts of what a writer wrote, see
+    /// [`CompressedTrainingDataEntryWriter::stats`](crate::CompressedTrainingDataEntryWriter::stats)
+    pub fn from_stats(stats: &WriterStats) -> Self {
+        Self::new(stats.entries, stats.chains)
+    }
+
+    pub fn with_generator(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.generator = Some(name.into());
+        self.generator_version = Some(version.into());
+        self
+    }
+
+    pub fn with_filter(mut self, filter: impl Into<String>) -> Self {
+        self.filter = Some(filter.into());
+        self
+    }
+
+    /// Counts `entries` more entries scored by `source`
+    pub fn with_score_source(mut self, source: ScoreSource, entries: u64) -> Self {
+        *self.score_sources.entry(source).or_default() += entries;
+        self
+    }
+
+    /// Counts `entries` more entries whose results were decided by `source`
+    pub fn with_result_source(mut self, source: ResultSource, entries: u64) -> Self {
+        *self.result_sources.entry(source).or_default() += entries;
+        self
+    }
+
+    /// Counts the provenance of an entry from its annotation, an entry
+    /// without provenance is not counted
+    pub fn add_provenance(&mut self, annotation: &Annotation) {
+        if let Some(source) = annotation.score_source {
+            *self.score_sources.entry(source).or_default() += 1;
+        }
+        if let Some(source) = annotation.result_source {
+            *self.result_sources.entry(source).or_default() += 1;
+        }
+    }
+
+    /// Writes the sidecar of the binpack at `path`, which must be complete
+    /// as its size goes into the sidecar
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let meta = Self {
+            file_size: Some(fs::metadata(path)?.len()),
+            ..self.clone()
+        };
+        fs::write(sidecar_path(path), meta.to_json())?;
+        Ok(())
+    }
+
+    /// Reads the sidecar of the binpack at `path`, None if there is none or
+    /// the binpack no longer has the size the sidecar was written for
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let text = match fs::read_to_string(sidecar_path(path)) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let meta = Self::parse(&text)?;
+        let size = fs::metadata(path)?.len();
+        Ok(meta
+            .file_size
+            .is_none_or(|expected| expected == size)
+            .then_some(meta))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let fields = parse_object(text.trim()).map_err(MetaError::Parse)?;
+
+        let mut entries = None;
+        let mut games = None;
+        let mut meta = Self::default();
+
+        for (key, value) in fields {
+            let number = |value| {
+                u64::try_from(value)
+                    .map_err(|_| MetaError::Parse(format!("'{}' out of range: {}", key, value)))
+            };
+
+            match (key.as_str(), value) {
+                ("entries", Value::Number(value)) => entries = Some(number(value)?),
+                ("games", Value::Number(value)) => games = Some(number(value)?),
+                ("file_size", Value::Number(value)) => meta.file_size = Some(number(value)?),
+                ("created", Value::Number(value)) => meta.created = Some(number(value)?),
+                ("generator", Value::String(value)) => meta.generator = Some(value),
+                ("generator_version", Value::String(value)) => meta.generator_version = Some(value),
+                ("filter", Value::String(value)) => meta.filter = Some(value),
+                ("score_sources", Value::String(value)) => {
+                    meta.score_sources = parse_counts(&key, &value)?
+                }
+                ("result_sources", Value::String(value)) => {
+                    meta.result_sources = parse_counts(&key, &value)?
+                }
+                (
+                    "entries" | "games" | "file_size" | "created" | "generator"
+                    | "generator_version" | "filter" | "score_sources" | "result_sources",
+                    _,
+                ) => return Err(MetaError::Parse(format!("unexpected type for '{}'", key))),
+                _ => {}
+            }
+        }
+
+        meta.entries = entries.ok_or_else(|| MetaError::Parse("missing 'entries'".to_string()))?;
+        meta.games = games.ok_or_else(|| MetaError::Parse("missing 'games'".to_string()))?;
+        Ok(meta)
+    }
+
+    /// The sidecar as a JSON object, one key per line, keys without a value
+    /// left out
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![
+            ("entries", self.entries.to_string()),
+            ("games", self.games.to_string()),
+        ];
+
+        let numbers = [("file_size", self.file_size), ("created", self.created)];
+        fields.extend(
+            numbers
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, value?.to_string()))),
+        );
+
+        let strings = [
+            ("generator", &self.generator),
+            ("generator_version", &self.generator_version),
+            ("filter", &self.filter),
+        ];
+        fields.extend(
+            strings
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, quote(value.as_ref()?)))),
+        );
+
+        let counts = [
+            ("score_sources", format_counts(&self.score_sources)),
+            ("result_sources", format_counts(&self.result_sources)),
+        ];
+        fields.extend(
+            counts
+                .into_iter()
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(key, value)| (key, quote(&value))),
+        );
+
+        let mut json = String::from("{\n");
+        for (idx, (key, value)) in fields.iter().enumerate() {
+            let comma = if idx + 1 < fields.len() { "," } else { "" };
+            let _ = writeln!(json, "  \"{}\": {}{}", key, value, comma);
+        }
+        json.push_str("}\n");
+        json
+    }
+}
+
+/// Counts by provenance tag, `tag=count` separated by commas
+fn format_counts<T: Display>(counts: &BTreeMap<T, u64>) -> String {
+    let counts = counts
+        .iter()
+        .map(|(tag, count)| format!("{tag}={count}"))
+        .collect::<Vec<_>>();
+    counts.join(",")
+}
+
+fn parse_counts<T: FromStr + Ord>(key: &str, value: &str) -> Result<BTreeMap<T, u64>> {
+    let invalid = |part: &str| MetaError::Parse(format!("invalid '{}' count: '{}'", key, part));
+
+    let mut counts = BTreeMap::new();
+    for part in value.split(',').filter(|part| !part.is_empty()) {
+        let (tag, count) = part.split_once('=').ok_or_else(|| invalid(part))?;
+        let tag = tag.parse().map_err(|_| invalid(part))?;
+        let count = count.parse::<u64>().map_err(|_| invalid(part))?;
+        *counts.entry(tag).or_default() += count;
+    }
+    Ok(counts)
+}
+
+/// A JSON string, escaped as far as [`parse_object`] reads it back
+pub(crate) fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_round_trip() {
+        let meta = DatasetMeta {
+            entries: 1200,
+            games: 14,
+            file_size: Some(4096),
+            generator: Some("gen \"quoted\"\\".to_string()),
+            generator_version: Some("2.0".to_string()),
+            created: Some(1760486400),
+            filter: Some("score < 0\n&& !in_check".to_string()),
+            ..Default::default()
+        }
+        .with_score_source(ScoreSource::Search { depth: Some(9) }, 1000)
+        .with_score_source(ScoreSource::Tablebase, 200)
+        .with_result_source(ResultSource::Adjudicated, 1200);
+        assert_eq!(DatasetMeta::parse(&meta.to_json()).unwrap(), meta);
+
+        let bare = DatasetMeta::new(3, 1);
+        assert!(bare.created.is_some());
+        assert_eq!(DatasetMeta::parse(&bare.to_json()).unwrap(), bare);
+    }
+
+    #[test]
+    fn test_meta_parse() {
+        let meta =
+            DatasetMeta::parse(r#"{"games": 2, "entries": 10, "future": "x", "other": true}"#)
+                .unwrap();
+        assert_eq!((meta.entries, meta.games, meta.generator), (10, 2, None));
+
+        for text in [
+            r#"{"games": 2}"#,
+            r#"{"games": 2, "entries": -1}"#,
+            r#"{"games": "2", "entries": 1}"#,
+            r#"{"games": 2, "entries": 1, "nested": [1]}"#,
+        ] {
+            assert!(DatasetMeta::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_meta_provenance() {
+        let mut meta = DatasetMeta::new(3, 1).with_score_source(ScoreSource::Tablebase, 1);
+        for depth in [Some(12), Some(12), None] {
+            meta.add_provenance(&Annotation {
+                score_source: Some(ScoreSource::Search { depth }),
+                result_source: Some(ResultSource::Played),
+                ..Default::default()
+            });
+        }
+        meta.add_provenance(&Annotation::default());
+
+        let json = meta.to_json();
+        assert!(json.contains(r#""score_sources": "search=1,search:d12=2,tablebase=1""#));
+        assert!(json.contains(r#""result_sources": "played=3""#));
+        assert_eq!(DatasetMeta::parse(&json).unwrap(), meta);
+
+        for counts in ["search", "search=x", "nnue=3", "search:d=1"] {
+            let text = format!(r#"{{"games": 1, "entries": 1, "score_sources": "{counts}"}}"#);
+            assert!(DatasetMeta::parse(&text).is_err(), "{counts}");
+        }
+    }
+
+    #[test]
+    fn test_meta_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.binpack");
+        assert_eq!(sidecar_path(&path), dir.path().join("data.binpack.meta"));
+
+        fs::write(&path, [0u8; 46]).unwrap();
+        assert_eq!(DatasetMeta::read(&path).unwrap(), None);
+
+        let meta = DatasetMeta::new(3, 1).with_filter("all");
+        meta.write(&path).unwrap();
+        let read = DatasetMeta::read(&path).unwrap().unwrap();
+        assert_eq!(read.file_size, Some(46));
+        assert_eq!(read.filter.as_deref(), Some("all"));
+
+        // the binpack changed since, the sidecar is stale
+        fs::write(&path, [0u8; 50]).unwrap();
+        assert_eq!(DatasetMeta::read(&path).unwrap(), None);
+    }
+}
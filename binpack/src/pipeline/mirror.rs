@@ -0,0 +1,180 @@
+//! Augmenting the data with mirrored positions.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::TrainingDataEntry;
+
+use super::Transform;
+
+/// Adds a copy of every entry mirrored from the a-file to the h-file, with
+/// the move mirrored too and the score and result as they are.
+///
+/// Positions where either side can still castle have no mirror image and
+/// get no copy. The copies of a game are held back until the game ends and
+/// written after it, so they still form a chain and compress as well as the
+/// original.
+#[derive(Debug, Clone)]
+pub struct MirrorGames<R = StdRng> {
+    probability: f64,
+    rng: R,
+    mirror_game: bool,
+    last: Option<TrainingDataEntry>,
+    mirrored: Vec<TrainingDataEntry>,
+}
+
+impl MirrorGames {
+    /// Mirrors every game
+    pub fn new() -> Self {
+        Self::with_rng(1.0, StdRng::from_entropy())
+    }
+
+    /// Mirrors each game with the given probability
+    pub fn sometimes(probability: f64) -> Self {
+        Self::with_rng(probability, StdRng::from_entropy())
+    }
+}
+
+impl Default for MirrorGames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Rng> MirrorGames<R> {
+    pub fn with_rng(probability: f64, rng: R) -> Self {
+        Self {
+            probability: probability.clamp(0.0, 1.0),
+            rng,
+            mirror_game: false,
+            last: None,
+            mirrored: Vec::new(),
+        }
+    }
+}
+
+/// The entry mirrored from the a-file to the h-file, `None` if its position
+/// has castling rights
+pub fn mirror_entry(entry: &TrainingDataEntry) -> Option<TrainingDataEntry> {
+    Some(TrainingDataEntry {
+        pos: entry.pos.mirror_files()?,
+        mv: entry.mv.mirror_files(),
+        ..*entry
+    })
+}
+
+impl<R: Rng + Send> Transform for MirrorGames<R> {
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        if !self.last.is_some_and(|last| last.is_continuation(&entry)) {
+            out.append(&mut self.mirrored);
+            self.mirror_game = self.rng.gen_bool(self.probability);
+        }
+
+        if self.mirror_game {
+            self.mirrored.extend(mirror_entry(&entry));
+        }
+
+        self.last = Some(entry);
+        out.push(entry);
+    }
+
+    fn finish(&mut self, out: &mut Vec<TrainingDataEntry>) {
+        out.append(&mut self.mirrored);
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        chess::{position::Position, r#move::Move},
+        pipeline::Pipeline,
+        testing::{random_game, GameOptions, Rng as TestRng},
+        CompressedTrainingDataEntryWriter,
+    };
+
+    #[test]
+    fn test_mirror_entry() {
+        let pos = Position::from_fen("4k3/8/8/8/8/8/1P6/4K2R w - - 0 30").unwrap();
+        let entry = TrainingDataEntry {
+            pos,
+            mv: Move::from_uci(&pos, "h1h8").unwrap(),
+            score: 700,
+            ply: 58,
+            result: 1,
+        };
+
+        let mirrored = mirror_entry(&entry).unwrap();
+        assert_eq!(
+            mirrored.pos.fen().unwrap(),
+            "3k4/8/8/8/8/8/6P1/R2K4 w - - 0 30"
+        );
+        assert_eq!(mirrored.mv.as_uci(), "a1a8");
+        assert_eq!(
+            (mirrored.score, mirrored.ply, mirrored.result),
+            (700, 58, 1)
+        );
+
+        let start = TrainingDataEntry {
+            pos: Position::new(),
+            ..entry
+        };
+        assert_eq!(mirror_entry(&start), None);
+    }
+
+    fn games() -> Vec<TrainingDataEntry> {
+        let mut rng = TestRng::new(11);
+        (0..10)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_mirror_games() {
+        let input = games();
+        let mut pipeline = Pipeline::new().transform(MirrorGames::new());
+
+        let mut output = Vec::new();
+        for &entry in &input {
+            output.extend(pipeline.apply(entry));
+        }
+        output.extend(pipeline.finish());
+
+        let expected = input.iter().filter_map(mirror_entry).count();
+        assert!(expected > 0);
+        assert_eq!(output.len(), input.len() + expected);
+
+        // every original is there, in order
+        let originals = output
+            .iter()
+            .filter(|entry| input.contains(entry))
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(originals, input);
+
+        // the copies chain like the originals do
+        let mut writer = CompressedTrainingDataEntryWriter::new(Cursor::new(Vec::new())).unwrap();
+        for entry in &output {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.flush_and_end();
+        let stats = writer.stats();
+        // a chain per game and one per mirrored game
+        assert!(stats.chains <= 20, "{stats:?}");
+    }
+
+    #[test]
+    fn test_mirror_none() {
+        let input = games();
+        let mut pipeline = Pipeline::new().transform(MirrorGames::sometimes(0.0));
+
+        let mut output = Vec::new();
+        for &entry in &input {
+            output.extend(pipeline.apply(entry));
+        }
+        output.extend(pipeline.finish());
+        assert_eq!(output, input);
+    }
+}
@@ -0,0 +1,831 @@
+//! Streaming entries from a reader through transforms into a writer.
+//!
+//! A [`Pipeline`] is a list of [`Transform`]s, each of which turns an entry
+//! into any number of entries: one for a map, none or one for a filter, more
+//! for an augmentation. [`Pipeline::run`] reads the entries in batches,
+//! passes them through the transforms in order and writes what comes out.
+//!
+//! ```
+//! use std::{fs::File, io::Cursor};
+//! use sfbinpack::{
+//!     filter::SkipCaptures, pipeline::Pipeline, CompressedTrainingDataEntryReader,
+//!     CompressedTrainingDataEntryWriter,
+//! };
+//!
+//! let file = File::open("test/ep1.binpack").unwrap();
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//! let mut writer = CompressedTrainingDataEntryWriter::new(Cursor::new(Vec::new())).unwrap();
+//!
+//! let stats = Pipeline::new()
+//!     .filter(SkipCaptures)
+//!     .map_score(|entry| entry.score.clamp(-1000, 1000))
+//!     .run(&mut reader, &mut writer)
+//!     .unwrap();
+//! writer.flush_and_end();
+//!
+//! assert_eq!(stats.read, 3);
+//! ```
+//!
+//! With [`Pipeline::copy_chunks`] the entries are read a chunk at a time,
+//! and a chunk the transforms leave exactly as it was, e.g. because a
+//! filter kept all of it, is copied to the output as it is instead of being
+//! encoded again.
+//!
+//! With [`Pipeline::validation`] a [`split::ValidationSplit`] of the games
+//! goes to a second writer instead, so a conversion writes a training and a
+//! validation file that share no game.
+
+pub mod mirror;
+pub mod perspective;
+pub mod quality;
+pub mod relink;
+pub mod result;
+pub mod score;
+pub mod split;
+pub mod tablebase;
+
+use std::{
+    io::{Read, Seek, Write},
+    sync::mpsc,
+    thread,
+};
+
+use thiserror::Error;
+
+use self::split::ValidationSplit;
+use crate::{
+    filter::EntryFilter, CompressedReaderError, CompressedTrainingDataEntryReader,
+    CompressedTrainingDataEntryWriter, CompressedWriterError, TrainingDataEntry,
+};
+
+/// Entries read and passed through the transforms at a time
+const DEFAULT_BATCH_SIZE: usize = 4096;
+
+/// Batches that can be on their way between two threads of a parallel run
+const BATCHES_IN_FLIGHT: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("Reading failed: {0}")]
+    Read(#[from] CompressedReaderError),
+
+    #[error("Writing failed: {0}")]
+    Write(#[from] CompressedWriterError),
+}
+
+type Result<T> = std::result::Result<T, PipelineError>;
+
+/// Turns an entry into any number of entries.
+pub trait Transform: Send {
+    /// Pushes what `entry` becomes onto `out`, nothing to drop it
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>);
+
+    /// Called once after the last entry, pushes the entries the transform
+    /// still holds back onto `out`
+    fn finish(&mut self, _out: &mut Vec<TrainingDataEntry>) {}
+
+    /// What the transform is called in the spans of the `tracing` feature,
+    /// its type name by default
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Counts of a [`Pipeline::run`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Entries read from the reader
+    pub read: u64,
+    /// Entries handed to the writer
+    pub written: u64,
+    /// Chunks copied to the writer without encoding their entries again,
+    /// see [`Pipeline::copy_chunks`]
+    pub chunks_copied: u64,
+    /// Entries handed to the validation writer, not counted in `written`,
+    /// see [`Pipeline::validation`]
+    pub validation: u64,
+}
+
+/// The data of a chunk read whole and the entries decoded from it
+struct Chunk {
+    data: Vec<u8>,
+    entries: Vec<TrainingDataEntry>,
+    chains: u64,
+}
+
+struct Map<F>(F);
+
+impl<F: FnMut(&mut TrainingDataEntry) + Send> Transform for Map<F> {
+    fn apply(&mut self, mut entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        (self.0)(&mut entry);
+        out.push(entry);
+    }
+}
+
+struct Filter<F>(F);
+
+impl<F: EntryFilter + Send> Transform for Filter<F> {
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        if self.0.keep(&entry) {
+            out.push(entry);
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        std::any::type_name::<F>()
+    }
+}
+
+struct Augment<F>(F);
+
+impl<F, I> Transform for Augment<F>
+where
+    F: FnMut(&TrainingDataEntry) -> I + Send,
+    I: IntoIterator<Item = TrainingDataEntry>,
+{
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        out.push(entry);
+        out.extend((self.0)(&entry));
+    }
+}
+
+type Progress<'a> = Box<dyn FnMut(&PipelineStats, u64) + 'a>;
+
+/// The split and the writer of the validation games
+type Validation<'a> = (
+    ValidationSplit,
+    Box<dyn FnMut(&TrainingDataEntry) -> std::result::Result<(), CompressedWriterError> + 'a>,
+);
+
+/// Transforms to run on every entry, see the [module docs](self).
+pub struct Pipeline<'a> {
+    transforms: Vec<Box<dyn Transform + 'a>>,
+    progress: Option<Progress<'a>>,
+    validation: Option<Validation<'a>>,
+    batch_size: usize,
+    parallel: bool,
+    checked: bool,
+    copy_chunks: bool,
+}
+
+impl Default for Pipeline<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Self {
+            transforms: Vec::new(),
+            progress: None,
+            validation: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            parallel: false,
+            checked: false,
+            copy_chunks: false,
+        }
+    }
+
+    /// Adds a transform
+    pub fn transform(mut self, transform: impl Transform + 'a) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Changes every entry in place
+    pub fn map(self, f: impl FnMut(&mut TrainingDataEntry) + Send + 'a) -> Self {
+        self.transform(Map(f))
+    }
+
+    /// Replaces the score of every entry
+    pub fn map_score(self, mut f: impl FnMut(&TrainingDataEntry) -> i16 + Send + 'a) -> Self {
+        self.map(move |entry| entry.score = f(entry))
+    }
+
+    /// Replaces the result of every entry, which is from the point of view
+    /// of its side to move like the stored one
+    pub fn relabel_result(self, mut f: impl FnMut(&TrainingDataEntry) -> i16 + Send + 'a) -> Self {
+        self.map(move |entry| entry.result = f(entry))
+    }
+
+    /// Drops the entries the filter does not keep
+    pub fn filter(self, filter: impl EntryFilter + Send + 'a) -> Self {
+        self.transform(Filter(filter))
+    }
+
+    /// Keeps every entry and adds the entries `f` returns for it after it
+    pub fn augment<I>(self, f: impl FnMut(&TrainingDataEntry) -> I + Send + 'a) -> Self
+    where
+        I: IntoIterator<Item = TrainingDataEntry>,
+    {
+        self.transform(Augment(f))
+    }
+
+    /// Calls `f` after every batch with the counts so far and the bytes the
+    /// reader has read
+    pub fn progress(mut self, f: impl FnMut(&PipelineStats, u64) + 'a) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Entries read and transformed at a time, 4096 by default
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Decodes, transforms and encodes on three threads instead of one. The
+    /// output is the same, in the same order.
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Reads with try_next() instead of next(), so corrupt input is an error
+    /// instead of garbage or a panic
+    pub fn checked(mut self, checked: bool) -> Self {
+        self.checked = checked;
+        self
+    }
+
+    /// Reads a chunk at a time instead of `batch_size` entries, and copies
+    /// the chunks whose entries come out of the transforms unchanged and in
+    /// the same order straight to the writer, saving the encoding. Only
+    /// chunks read from their start are copied, and the writer's options
+    /// such as verify do not apply to them.
+    pub fn copy_chunks(mut self, copy_chunks: bool) -> Self {
+        self.copy_chunks = copy_chunks;
+        self
+    }
+
+    /// Writes the games `split` picks to `writer` instead of the writer of
+    /// [`run`](Self::run), after the transforms. Like that one, `writer` is
+    /// not flushed. Chunks are not copied with a split, as they may hold
+    /// games of both sides.
+    pub fn validation<W: Write>(
+        mut self,
+        split: ValidationSplit,
+        writer: &'a mut CompressedTrainingDataEntryWriter<W>,
+    ) -> Self {
+        self.validation = Some((split, Box::new(|entry| writer.write_entry(entry))));
+        self
+    }
+
+    /// Runs the transforms on `entry` alone and returns what comes out
+    pub fn apply(&mut self, entry: TrainingDataEntry) -> Vec<TrainingDataEntry> {
+        let mut batch = vec![entry];
+        apply_all(&mut self.transforms, &mut batch, &mut Vec::new());
+        batch
+    }
+
+    /// Ends the input of [`apply`](Self::apply) and returns the entries the
+    /// transforms held back, [`run`](Self::run) does this itself
+    pub fn finish(&mut self) -> Vec<TrainingDataEntry> {
+        let mut batch = Vec::new();
+        finish_all(&mut self.transforms, &mut batch, &mut Vec::new());
+        batch
+    }
+
+    /// Passes every entry left in `reader` through the transforms to
+    /// `writer`. The writer is not flushed, call `flush_and_end` once done
+    /// with it.
+    pub fn run<R, W>(
+        &mut self,
+        reader: &mut CompressedTrainingDataEntryReader<R>,
+        writer: &mut CompressedTrainingDataEntryWriter<W>,
+    ) -> Result<PipelineStats>
+    where
+        R: Read + Seek + Send,
+        W: Write,
+    {
+        if self.parallel {
+            self.run_parallel(reader, writer)
+        } else {
+            self.run_serial(reader, writer)
+        }
+    }
+
+    fn run_serial<R: Read + Seek, W: Write>(
+        &mut self,
+        reader: &mut CompressedTrainingDataEntryReader<R>,
+        writer: &mut CompressedTrainingDataEntryWriter<W>,
+    ) -> Result<PipelineStats> {
+        let mut stats = PipelineStats::default();
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut scratch = Vec::new();
+
+        loop {
+            let chunk = read_input(
+                reader,
+                &mut batch,
+                self.batch_size,
+                self.checked,
+                self.copy_chunks,
+            )?;
+            let read = batch.len();
+            stats.read += read as u64;
+
+            apply_all(&mut self.transforms, &mut batch, &mut scratch);
+            if read == 0 {
+                finish_all(&mut self.transforms, &mut batch, &mut scratch);
+            }
+            write_output(writer, self.validation.as_mut(), &batch, chunk, &mut stats)?;
+
+            if read == 0 {
+                return Ok(stats);
+            }
+
+            if let Some(progress) = self.progress.as_mut() {
+                progress(&stats, reader.read_bytes());
+            }
+        }
+    }
+
+    fn run_parallel<R: Read + Seek + Send, W: Write>(
+        &mut self,
+        reader: &mut CompressedTrainingDataEntryReader<R>,
+        writer: &mut CompressedTrainingDataEntryWriter<W>,
+    ) -> Result<PipelineStats> {
+        let (batch_size, checked, copy_chunks) = (self.batch_size, self.checked, self.copy_chunks);
+        let transforms = &mut self.transforms;
+
+        // batches, the chunk they are if it was read whole and the reader
+        // position after them, or the error ending the input
+        let (read_tx, read_rx) =
+            mpsc::sync_channel::<Result<(Vec<_>, Option<Chunk>, u64)>>(BATCHES_IN_FLIGHT);
+        let (done_tx, done_rx) =
+            mpsc::sync_channel::<Result<(Vec<_>, Option<Chunk>, u64, u64)>>(BATCHES_IN_FLIGHT);
+
+        thread::scope(|scope| {
+            scope.spawn(move || loop {
+                let mut batch = Vec::with_capacity(batch_size);
+                let read = read_input(reader, &mut batch, batch_size, checked, copy_chunks)
+                    .map(|chunk| (batch, chunk, reader.read_bytes()));
+
+                let last = !matches!(read, Ok((ref batch, _, _)) if !batch.is_empty());
+                // the receiver is gone only if writing failed
+                if read_tx.send(read).is_err() || last {
+                    return;
+                }
+            });
+
+            scope.spawn(move || {
+                let mut scratch = Vec::new();
+
+                for read in read_rx {
+                    let done = read.map(|(mut batch, chunk, position)| {
+                        let read = batch.len() as u64;
+                        apply_all(transforms, &mut batch, &mut scratch);
+                        if read == 0 {
+                            finish_all(transforms, &mut batch, &mut scratch);
+                        }
+                        (batch, chunk, read, position)
+                    });
+
+                    if done_tx.send(done).is_err() {
+                        return;
+                    }
+                }
+            });
+
+            let mut stats = PipelineStats::default();
+
+            for done in done_rx {
+                let (batch, chunk, read, position) = done?;
+
+                stats.read += read;
+                write_output(writer, self.validation.as_mut(), &batch, chunk, &mut stats)?;
+
+                // the empty batch at the end of the input
+                if read == 0 {
+                    break;
+                }
+
+                if let Some(progress) = self.progress.as_mut() {
+                    progress(&stats, position);
+                }
+            }
+
+            Ok(stats)
+        })
+    }
+}
+
+/// Reads the next batch or chunk into `batch`, and returns the chunk if it
+/// was read whole
+fn read_input<R: Read + Seek>(
+    reader: &mut CompressedTrainingDataEntryReader<R>,
+    batch: &mut Vec<TrainingDataEntry>,
+    batch_size: usize,
+    checked: bool,
+    copy_chunks: bool,
+) -> Result<Option<Chunk>> {
+    if copy_chunks {
+        read_chunk(reader, batch, checked)
+    } else {
+        read_batch(reader, batch, batch_size, checked).map(|_| None)
+    }
+}
+
+/// Reads the rest of the current chunk into `batch`
+fn read_chunk<R: Read + Seek>(
+    reader: &mut CompressedTrainingDataEntryReader<R>,
+    batch: &mut Vec<TrainingDataEntry>,
+    checked: bool,
+) -> Result<Option<Chunk>> {
+    batch.clear();
+
+    // the reader loads the next chunk with the last entry of this one
+    let data = reader.chunk_data().map(<[u8]>::to_vec);
+    let chunk = reader.chunks_read();
+    let mut chains = 0;
+
+    while reader.has_next() && reader.chunks_read() == chunk {
+        chains += !reader.is_next_entry_continuation() as u64;
+        batch.push(if checked {
+            reader.try_next()?
+        } else {
+            reader.next()
+        });
+    }
+
+    Ok(data.filter(|_| !batch.is_empty()).map(|data| Chunk {
+        data,
+        entries: batch.clone(),
+        chains,
+    }))
+}
+
+fn read_batch<R: Read + Seek>(
+    reader: &mut CompressedTrainingDataEntryReader<R>,
+    batch: &mut Vec<TrainingDataEntry>,
+    batch_size: usize,
+    checked: bool,
+) -> Result<usize> {
+    if !checked {
+        return Ok(reader.next_batch(batch, batch_size));
+    }
+
+    batch.clear();
+    while batch.len() < batch_size && reader.has_next() {
+        batch.push(reader.try_next()?);
+    }
+
+    Ok(batch.len())
+}
+
+/// Replaces `batch` with what the transforms make of it, `scratch` is
+/// room for the output of one transform
+fn apply_all(
+    transforms: &mut [Box<dyn Transform + '_>],
+    batch: &mut Vec<TrainingDataEntry>,
+    scratch: &mut Vec<TrainingDataEntry>,
+) {
+    for transform in transforms {
+        trace_span!("transform", name = transform.name(), entries = batch.len());
+        scratch.clear();
+        for entry in batch.drain(..) {
+            transform.apply(entry, scratch);
+        }
+        std::mem::swap(batch, scratch);
+        trace_event!(entries = batch.len(), "batch transformed");
+    }
+}
+
+/// Finishes the transforms in order, passing what each one holds back
+/// through the ones after it. `batch` must come in empty.
+fn finish_all(
+    transforms: &mut [Box<dyn Transform + '_>],
+    batch: &mut Vec<TrainingDataEntry>,
+    scratch: &mut Vec<TrainingDataEntry>,
+) {
+    for transform in transforms {
+        trace_span!("transform", name = transform.name(), finish = true);
+        scratch.clear();
+        for entry in batch.drain(..) {
+            transform.apply(entry, scratch);
+        }
+        transform.finish(scratch);
+        std::mem::swap(batch, scratch);
+    }
+}
+
+/// Copies `chunk` if `batch` is what it holds, writes `batch` otherwise,
+/// the picked games to the validation writer if there is one
+fn write_output<W: Write>(
+    writer: &mut CompressedTrainingDataEntryWriter<W>,
+    validation: Option<&mut Validation>,
+    batch: &[TrainingDataEntry],
+    chunk: Option<Chunk>,
+    stats: &mut PipelineStats,
+) -> Result<()> {
+    if let Some((split, validation)) = validation {
+        for entry in batch {
+            if split.is_validation(entry) {
+                validation(entry)?;
+                stats.validation += 1;
+            } else {
+                writer.write_entry(entry)?;
+                stats.written += 1;
+            }
+        }
+        return Ok(());
+    }
+
+    match chunk {
+        Some(chunk) if chunk.entries == batch => {
+            let entries = batch.len() as u64;
+            writer.write_chunk(&chunk.data, entries, chunk.chains)?;
+            stats.written += entries;
+            stats.chunks_copied += 1;
+            Ok(())
+        }
+        _ => write_batch(writer, batch, stats),
+    }
+}
+
+fn write_batch<W: Write>(
+    writer: &mut CompressedTrainingDataEntryWriter<W>,
+    batch: &[TrainingDataEntry],
+    stats: &mut PipelineStats,
+) -> Result<()> {
+    for entry in batch {
+        writer.write_entry(entry)?;
+        stats.written += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        filter::from_fn,
+        testing::{random_game, write_binpack, GameOptions, Rng},
+        WriterOptions,
+    };
+
+    fn games() -> Vec<TrainingDataEntry> {
+        let mut rng = Rng::new(1);
+        (0..20)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect()
+    }
+
+    fn read_all(data: Vec<u8>) -> Vec<TrainingDataEntry> {
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    fn run(pipeline: &mut Pipeline, input: &[TrainingDataEntry]) -> (PipelineStats, Vec<u8>) {
+        let mut reader =
+            CompressedTrainingDataEntryReader::new(Cursor::new(write_binpack(input))).unwrap();
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+
+        let stats = pipeline.run(&mut reader, &mut writer).unwrap();
+        writer.flush_and_end();
+        (stats, writer.into_inner().unwrap())
+    }
+
+    fn pipeline<'a>() -> Pipeline<'a> {
+        let mut seen = 0u64;
+
+        Pipeline::new()
+            .filter(from_fn(|entry: &TrainingDataEntry| entry.ply % 7 != 3))
+            // stateful, the order of the entries must not change
+            .map_score(move |entry| {
+                seen += 1;
+                entry.score.wrapping_add(seen as i16)
+            })
+            .relabel_result(|entry| -entry.result)
+            .augment(|entry| {
+                entry
+                    .ply
+                    .is_multiple_of(10)
+                    .then_some(TrainingDataEntry { score: 0, ..*entry })
+            })
+    }
+
+    #[test]
+    fn test_pipeline_transforms() {
+        let input = games();
+
+        let mut expected = Vec::new();
+        let mut seen = 0i16;
+        for entry in input.iter().filter(|entry| entry.ply % 7 != 3) {
+            seen += 1;
+            let entry = TrainingDataEntry {
+                score: entry.score.wrapping_add(seen),
+                result: -entry.result,
+                ..*entry
+            };
+            expected.push(entry);
+            if entry.ply.is_multiple_of(10) {
+                expected.push(TrainingDataEntry { score: 0, ..entry });
+            }
+        }
+
+        let (stats, output) = run(&mut pipeline().batch_size(100), &input);
+        assert_eq!(
+            stats,
+            PipelineStats {
+                read: input.len() as u64,
+                written: expected.len() as u64,
+                chunks_copied: 0,
+                validation: 0,
+            }
+        );
+
+        let output = read_all(output);
+        assert_eq!(output.len(), expected.len());
+        for (output, expected) in output.iter().zip(&expected) {
+            assert_eq!(output.pos.fen(), expected.pos.fen());
+            assert_eq!(
+                (output.mv, output.score, output.ply, output.result),
+                (expected.mv, expected.score, expected.ply, expected.result)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pipeline_parallel_same_output() {
+        let input = games();
+
+        let (serial_stats, serial) = run(&mut pipeline().batch_size(64), &input);
+        let (parallel_stats, parallel) = run(&mut pipeline().batch_size(64).parallel(true), &input);
+
+        assert_eq!(serial_stats, parallel_stats);
+        assert_eq!(serial, parallel);
+
+        // and with nothing to read
+        let (stats, _) = run(&mut pipeline().parallel(true), &[]);
+        assert_eq!(stats, PipelineStats::default());
+    }
+
+    #[test]
+    fn test_pipeline_progress_and_apply() {
+        let input = games();
+        let mut calls = Vec::new();
+
+        let mut pipeline = Pipeline::new()
+            .batch_size(500)
+            .checked(true)
+            .progress(|stats, position| calls.push((stats.read, position)));
+        run(&mut pipeline, &input);
+        drop(pipeline);
+
+        assert_eq!(calls.len(), input.len().div_ceil(500));
+        assert_eq!(calls.last().unwrap().0, input.len() as u64);
+        assert!(calls.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        let mut pipeline = Pipeline::new().augment(|entry| [*entry, *entry]);
+        assert_eq!(pipeline.apply(input[0]).len(), 3);
+    }
+
+    #[test]
+    fn test_pipeline_copy_chunks() {
+        let games = games();
+        let parts = [&games[..300], &games[300..600], &games[600..]];
+        let data = parts.map(write_binpack).concat();
+        // games cut in two by the end of a chunk count twice
+        let chains = parts
+            .iter()
+            .map(|part| {
+                let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+                part.iter()
+                    .for_each(|entry| writer.write_entry(entry).unwrap());
+                writer.stats().chains
+            })
+            .sum::<u64>();
+
+        for parallel in [false, true] {
+            let run = |pipeline: Pipeline, options| {
+                let mut reader =
+                    CompressedTrainingDataEntryReader::new(Cursor::new(data.clone())).unwrap();
+                let mut writer =
+                    CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+                let stats = pipeline
+                    .copy_chunks(true)
+                    .parallel(parallel)
+                    .run(&mut reader, &mut writer)
+                    .unwrap();
+                writer.flush_and_end();
+                (stats, writer.stats(), writer.into_inner().unwrap())
+            };
+
+            // everything kept, the file is copied chunk by chunk
+            let keep_all = Pipeline::new().filter(from_fn(|_: &TrainingDataEntry| true));
+            let (stats, writer_stats, output) = run(keep_all, WriterOptions::default());
+            assert_eq!(stats.read, games.len() as u64);
+            assert_eq!(stats.written, games.len() as u64);
+            assert_eq!(stats.chunks_copied, 3);
+            assert_eq!(writer_stats.entries, games.len() as u64);
+            assert_eq!(writer_stats.chains, chains);
+            assert_eq!(output, data);
+
+            // an entry dropped from the second chunk, only it is encoded
+            let dropped = games[400];
+            let drop_one =
+                Pipeline::new().filter(from_fn(move |e: &TrainingDataEntry| *e != dropped));
+            let (stats, _, output) = run(drop_one, WriterOptions::default());
+            assert_eq!(stats.chunks_copied, 2);
+            let mut expected = games.clone();
+            expected.remove(400);
+            assert_eq!(read_all(output), expected);
+
+            // the writer adds its own trailers to copied chunks
+            let checksums = WriterOptions {
+                checksums: true,
+                ..Default::default()
+            };
+            let (stats, _, output) = run(Pipeline::new(), checksums);
+            assert_eq!(stats.chunks_copied, 3);
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(output)).unwrap();
+            let mut read = Vec::new();
+            while reader.has_next() {
+                read.push(reader.try_next().unwrap());
+            }
+            assert_eq!(read, games);
+            assert_eq!(reader.checksummed_chunks(), 3);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_validation() {
+        let mut rng = Rng::new(4);
+        let games = (0..40)
+            .map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+        let input = games.concat();
+
+        for parallel in [false, true] {
+            let mut reader =
+                CompressedTrainingDataEntryReader::new(Cursor::new(write_binpack(&input))).unwrap();
+            let mut train = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+            let mut val = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+
+            // a gap in every game must not split it
+            let stats = Pipeline::new()
+                .filter(from_fn(|entry: &TrainingDataEntry| entry.ply != 5))
+                .validation(split::ValidationSplit::new(4, 9), &mut val)
+                .copy_chunks(true)
+                .parallel(parallel)
+                .run(&mut reader, &mut train)
+                .unwrap();
+            train.flush_and_end();
+            val.flush_and_end();
+            let (train, val) = (
+                read_all(train.into_inner().unwrap()),
+                read_all(val.into_inner().unwrap()),
+            );
+
+            assert_eq!(stats.written, train.len() as u64);
+            assert_eq!(stats.validation, val.len() as u64);
+            assert_eq!(stats.chunks_copied, 0);
+
+            // every game is whole on one side
+            let mut val_games = 0;
+            for game in &games {
+                let game = game
+                    .iter()
+                    .filter(|entry| entry.ply != 5)
+                    .copied()
+                    .collect::<Vec<_>>();
+                let in_val = val.contains(&game[0]);
+                let side = if in_val { &val } else { &train };
+                assert!(game.iter().all(|entry| side.contains(entry)));
+                val_games += in_val as usize;
+            }
+            assert!(val_games > 0 && val_games < games.len() / 2, "{val_games}");
+            assert_eq!(train.len() + val.len(), stats.read as usize - games.len());
+        }
+    }
+
+    #[test]
+    fn test_pipeline_read_error() {
+        // the second chunk is cut short
+        let chunk = write_binpack(&games());
+        let mut data = chunk.clone();
+        data.extend_from_slice(&chunk[..chunk.len() - 100]);
+
+        for parallel in [false, true] {
+            let mut reader =
+                CompressedTrainingDataEntryReader::new(Cursor::new(data.clone())).unwrap();
+            let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+
+            let result = Pipeline::new()
+                .checked(true)
+                .parallel(parallel)
+                .run(&mut reader, &mut writer);
+            assert!(matches!(result, Err(PipelineError::Read(_))));
+        }
+    }
+}
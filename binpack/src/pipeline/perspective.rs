@@ -0,0 +1,271 @@
+//! Scores and results from white's point of view.
+//!
+//! Entries hold the score and the result for the side to move, many other
+//! datasets hold them for white. [`FlipPerspective`] turns the one into the
+//! other by negating them for the entries with black to move; that is its
+//! own inverse, so it also turns entries into white's point of view for an
+//! export.
+//!
+//! Which of the two a dataset uses is rarely written down. [`detect`] tells
+//! it from a sample of entries: with black to move and one side well ahead
+//! in material, scores and results for the side to move have the opposite
+//! sign of white's material balance and scores and results for white the
+//! same one.
+//!
+//! ```
+//! use sfbinpack::{
+//!     chess::position::Position,
+//!     pipeline::perspective::{detect, FlipPerspective, Perspective},
+//!     TrainingDataEntry,
+//! };
+//!
+//! // black to move a queen down, scored and decided for white
+//! let pos = Position::from_fen("3qk3/8/8/8/8/8/8/QQ2K3 b - - 0 1").unwrap();
+//! let entry = TrainingDataEntry { pos, score: 900, result: 1, ..Default::default() };
+//! let sample = vec![entry; 20];
+//!
+//! let report = detect(&sample);
+//! assert_eq!(report.scores.perspective(), Some(Perspective::White));
+//! assert_eq!(report.results.perspective(), Some(Perspective::White));
+//!
+//! let flipped = FlipPerspective::new().entry(&entry);
+//! assert_eq!((flipped.score, flipped.result), (-900, -1));
+//! ```
+
+use crate::{
+    chess::color::Color,
+    filter::{simple_eval, VALUE_NONE},
+    Score, TrainingDataEntry,
+};
+
+use super::Transform;
+
+/// Material balance by [`simple_eval`], in centipawns, from which the side
+/// ahead is expected to be the one with the better score and result
+pub const DETECT_MATERIAL: i32 = 300;
+
+/// Entries that have to vote before [`Votes::perspective`] decides
+pub const MIN_VOTES: u64 = 16;
+
+/// Whose point of view scores and results are from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Perspective {
+    SideToMove,
+    White,
+}
+
+/// Negates the scores and results of the entries with black to move,
+/// turning white's point of view into the side to move's and back.
+/// Scores of [`VALUE_NONE`] stay as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlipPerspective {
+    scores: bool,
+    results: bool,
+}
+
+impl Default for FlipPerspective {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlipPerspective {
+    /// Flips scores and results
+    pub fn new() -> Self {
+        Self {
+            scores: true,
+            results: true,
+        }
+    }
+
+    /// Flips the scores and leaves the results, for datasets that mix both
+    /// points of view
+    pub fn scores_only() -> Self {
+        Self {
+            scores: true,
+            results: false,
+        }
+    }
+
+    /// Flips the results and leaves the scores
+    pub fn results_only() -> Self {
+        Self {
+            scores: false,
+            results: true,
+        }
+    }
+
+    pub fn entry(&self, entry: &TrainingDataEntry) -> TrainingDataEntry {
+        let mut entry = *entry;
+        let stm = entry.pos.side_to_move();
+
+        if self.scores {
+            entry.score = Score::new(entry.score).to_white_relative(stm).get();
+        }
+        if self.results && stm == Color::Black {
+            entry.result = -entry.result;
+        }
+
+        entry
+    }
+}
+
+impl Transform for FlipPerspective {
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        out.push(self.entry(&entry));
+    }
+}
+
+/// Entries of a sample that agree with either point of view
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Votes {
+    pub side_to_move: u64,
+    pub white: u64,
+}
+
+impl Votes {
+    /// The point of view at least three quarters of at least [`MIN_VOTES`]
+    /// votes agree on, None if the sample does not tell
+    pub fn perspective(&self) -> Option<Perspective> {
+        let total = self.side_to_move + self.white;
+        if total < MIN_VOTES {
+            return None;
+        }
+
+        if self.side_to_move * 4 >= total * 3 {
+            Some(Perspective::SideToMove)
+        } else if self.white * 4 >= total * 3 {
+            Some(Perspective::White)
+        } else {
+            None
+        }
+    }
+
+    fn add(&mut self, value: i32, white_material: i32) {
+        // with black to move the two points of view have opposite signs
+        if value.signum() == white_material.signum() {
+            self.white += 1;
+        } else if value != 0 {
+            self.side_to_move += 1;
+        }
+    }
+}
+
+/// What [`detect`] found for the scores and for the results
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PerspectiveReport {
+    pub scores: Votes,
+    pub results: Votes,
+}
+
+/// Counts the entries of `sample` whose scores and results agree with
+/// either point of view. Only entries with black to move and at least
+/// [`DETECT_MATERIAL`] ahead for one side vote; scores of zero or none and
+/// draws do not.
+pub fn detect<'a>(sample: impl IntoIterator<Item = &'a TrainingDataEntry>) -> PerspectiveReport {
+    let mut report = PerspectiveReport::default();
+
+    for entry in sample {
+        if entry.pos.side_to_move() != Color::Black {
+            continue;
+        }
+
+        let material = simple_eval(&entry.pos);
+        if material.abs() < DETECT_MATERIAL {
+            continue;
+        }
+
+        if entry.score != VALUE_NONE && entry.score != 0 {
+            report.scores.add(entry.score.into(), material);
+        }
+        if entry.result != 0 {
+            report.results.add(entry.result.into(), material);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::Pipeline, testing::entry_from_fen};
+
+    #[test]
+    fn test_flip_perspective() {
+        let white = entry_from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "", 50, 1);
+        let black = entry_from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1", "", 50, 1);
+        let none = entry_from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1", "", VALUE_NONE, 0);
+
+        let flip = FlipPerspective::new();
+        assert_eq!(flip.entry(&white), white);
+        assert_eq!(
+            (flip.entry(&black).score, flip.entry(&black).result),
+            (-50, -1)
+        );
+        assert_eq!(flip.entry(&none).score, VALUE_NONE);
+        assert_eq!(flip.entry(&flip.entry(&black)), black);
+
+        let scores = FlipPerspective::scores_only().entry(&black);
+        let results = FlipPerspective::results_only().entry(&black);
+        assert_eq!((scores.score, scores.result), (-50, 1));
+        assert_eq!((results.score, results.result), (50, -1));
+    }
+
+    #[test]
+    fn test_detect() {
+        // black to move, white a rook ahead or black a rook ahead
+        let ahead = "3k4/8/8/8/8/8/8/R3K3 b - - 0 1";
+        let behind = "r3k3/8/8/8/8/8/8/4K3 b - - 0 1";
+
+        let mut sample = Vec::new();
+        for _ in 0..10 {
+            sample.push(entry_from_fen(ahead, "", -400, -1));
+            sample.push(entry_from_fen(behind, "", 400, 1));
+        }
+        // white to move, a small material difference, draws and no scores
+        // do not vote
+        sample.push(entry_from_fen(
+            "3k4/8/8/8/8/8/8/R3K3 w - - 0 1",
+            "",
+            -400,
+            -1,
+        ));
+        sample.push(entry_from_fen("3k4/8/8/8/8/8/P7/4K3 b - - 0 1", "", 100, 1));
+        sample.push(entry_from_fen(ahead, "", VALUE_NONE, 0));
+
+        let report = detect(&sample);
+        assert_eq!(
+            report.scores,
+            Votes {
+                side_to_move: 20,
+                white: 0
+            }
+        );
+        assert_eq!(
+            report.results,
+            Votes {
+                side_to_move: 20,
+                white: 0
+            }
+        );
+        assert_eq!(report.scores.perspective(), Some(Perspective::SideToMove));
+
+        let mut pipeline = Pipeline::new().transform(FlipPerspective::scores_only());
+        let flipped = sample
+            .iter()
+            .flat_map(|entry| pipeline.apply(*entry))
+            .collect::<Vec<_>>();
+        let report = detect(&flipped);
+        assert_eq!(report.scores.perspective(), Some(Perspective::White));
+        assert_eq!(report.results.perspective(), Some(Perspective::SideToMove));
+
+        // too few votes, or no clear majority
+        assert_eq!(detect(&sample[..10]).scores.perspective(), None);
+        let mixed = Votes {
+            side_to_move: 60,
+            white: 40,
+        };
+        assert_eq!(mixed.perspective(), None);
+    }
+}
@@ -0,0 +1,297 @@
+//! Measuring the quality of whole games and dropping the bad ones.
+//!
+//! [`GameQuality::analyze`] looks at the scores and material of a game,
+//! [`PruneGames`] splits the entries into games and keeps only the games a
+//! predicate accepts, so the survivors are still complete chains.
+
+use crate::{
+    chess::color::Color,
+    filter::{simple_eval, VALUE_NONE},
+    TrainingDataEntry,
+};
+
+use super::Transform;
+
+/// Limits used by [`GameQuality::analyze`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualityThresholds {
+    /// Loss of score, for the side that moved, from one entry to the next
+    /// that counts as a blunder
+    pub blunder: i32,
+    /// Material balance by [`simple_eval`], in centipawns, that decides a
+    /// game when it lasts until the end
+    pub decisive_material: i32,
+    /// Scores are clamped to this before they are compared, so mate scores
+    /// count like big evals
+    pub score_cap: i16,
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        Self {
+            blunder: 300,
+            decisive_material: 900,
+            score_cap: 2000,
+        }
+    }
+}
+
+/// Metrics of one game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameQuality {
+    /// Entries in the game
+    pub plies: usize,
+    /// Mean absolute change of the score from white's point of view between
+    /// consecutive entries
+    pub score_volatility: f64,
+    /// Moves that lose more than the blunder threshold
+    pub blunders: usize,
+    /// Index of the first entry from which one side is at least the
+    /// decisive material ahead for the rest of the game, when there is one
+    pub decided_at: Option<usize>,
+}
+
+impl GameQuality {
+    /// Analyzes the entries of one game, in order. Entries without a score
+    /// are left out of the score metrics.
+    pub fn analyze(game: &[TrainingDataEntry], thresholds: &QualityThresholds) -> Self {
+        let cap = thresholds.score_cap.max(0);
+        let score = |entry: &TrainingDataEntry| {
+            (entry.score != VALUE_NONE).then(|| i32::from(entry.score.clamp(-cap, cap)))
+        };
+
+        let mut deltas = 0;
+        let mut total_delta = 0i64;
+        let mut blunders = 0;
+
+        for pair in game.windows(2) {
+            let (Some(before), Some(after)) = (score(&pair[0]), score(&pair[1])) else {
+                continue;
+            };
+
+            // the scores are for the side to move, which changes between
+            // the two entries
+            let white = |entry: &TrainingDataEntry, score: i32| match entry.pos.side_to_move() {
+                Color::White => score,
+                Color::Black => -score,
+            };
+            total_delta += i64::from((white(&pair[1], after) - white(&pair[0], before)).abs());
+            deltas += 1;
+
+            if before + after > thresholds.blunder {
+                blunders += 1;
+            }
+        }
+
+        let decisive = |entry: &TrainingDataEntry| {
+            simple_eval(&entry.pos).abs() >= thresholds.decisive_material
+        };
+        let decided_at = game
+            .iter()
+            .rposition(|entry| !decisive(entry))
+            .map_or(0, |idx| idx + 1);
+
+        Self {
+            plies: game.len(),
+            score_volatility: match deltas {
+                0 => 0.0,
+                _ => total_delta as f64 / deltas as f64,
+            },
+            blunders,
+            decided_at: (decided_at < game.len()).then_some(decided_at),
+        }
+    }
+}
+
+/// Keeps only the games whose [`GameQuality`] the predicate accepts.
+///
+/// Entries are split into games by
+/// [`TrainingDataEntry::is_continuation`] and held back until their game
+/// ends, so a game is kept or dropped as a whole.
+pub struct PruneGames<F> {
+    thresholds: QualityThresholds,
+    keep: F,
+    game: Vec<TrainingDataEntry>,
+    games: u64,
+    dropped: u64,
+}
+
+impl<F: FnMut(&GameQuality) -> bool> PruneGames<F> {
+    pub fn new(thresholds: QualityThresholds, keep: F) -> Self {
+        Self {
+            thresholds,
+            keep,
+            game: Vec::new(),
+            games: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Games seen so far, the one in progress not included
+    pub fn games(&self) -> u64 {
+        self.games
+    }
+
+    /// Games dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn end_game(&mut self, out: &mut Vec<TrainingDataEntry>) {
+        if self.game.is_empty() {
+            return;
+        }
+
+        self.games += 1;
+        if (self.keep)(&GameQuality::analyze(&self.game, &self.thresholds)) {
+            out.append(&mut self.game);
+        } else {
+            self.dropped += 1;
+            self.game.clear();
+        }
+    }
+}
+
+impl<F: FnMut(&GameQuality) -> bool + Send> Transform for PruneGames<F> {
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        if self
+            .game
+            .last()
+            .is_some_and(|last| !last.is_continuation(&entry))
+        {
+            self.end_game(out);
+        }
+
+        self.game.push(entry);
+    }
+
+    fn finish(&mut self, out: &mut Vec<TrainingDataEntry>) {
+        self.end_game(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        pipeline::Pipeline,
+        testing::{game_from_uci, random_game, write_binpack, GameOptions, Rng},
+        CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    };
+
+    /// A game from the start position with the given scores
+    fn game(moves: &[&str], scores: &[i16]) -> Vec<TrainingDataEntry> {
+        let mut entries = game_from_uci(moves);
+        for (entry, &score) in entries.iter_mut().zip(scores) {
+            entry.score = score;
+        }
+        entries
+    }
+
+    const MOVES: [&str; 5] = ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"];
+
+    #[test]
+    fn test_steady_game() {
+        let quality = GameQuality::analyze(
+            &game(&MOVES, &[20, -20, 30, -30, 20]),
+            &QualityThresholds::default(),
+        );
+
+        assert_eq!(quality.plies, 5);
+        assert_eq!(quality.blunders, 0);
+        assert_eq!(quality.decided_at, None);
+        // white's view: 20 20 30 30 20
+        assert!((quality.score_volatility - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_blunders() {
+        // black's e5 throws away 500, white's Nf3 gives it back and more
+        let entries = game(&MOVES, &[0, 0, 500, 300, -300]);
+        let quality = GameQuality::analyze(&entries, &QualityThresholds::default());
+        assert_eq!(quality.blunders, 2);
+
+        // mate scores are capped
+        let entries = game(&MOVES[..2], &[0, 31000]);
+        let quality = GameQuality::analyze(&entries, &QualityThresholds::default());
+        assert_eq!(quality.blunders, 1);
+        assert!((quality.score_volatility - 2000.0).abs() < 1e-9);
+
+        // no score, no delta
+        let entries = game(&MOVES[..2], &[0, VALUE_NONE]);
+        let quality = GameQuality::analyze(&entries, &QualityThresholds::default());
+        assert_eq!(quality.blunders, 0);
+        assert_eq!(quality.score_volatility, 0.0);
+    }
+
+    #[test]
+    fn test_decided_by_material() {
+        let thresholds = QualityThresholds {
+            decisive_material: 200,
+            ..Default::default()
+        };
+
+        // white wins a knight for a pawn and keeps it
+        let moves = ["e2e4", "g8f6", "d2d3", "f6e4", "d3e4", "d7d6"];
+        let entries = game(&moves, &[0; 6]);
+        let quality = GameQuality::analyze(&entries, &thresholds);
+        assert_eq!(quality.decided_at, Some(5));
+
+        let quality = GameQuality::analyze(&entries[..5], &thresholds);
+        assert_eq!(quality.decided_at, None);
+    }
+
+    #[test]
+    fn test_prune_games() {
+        let steady = game(&MOVES, &[20, -20, 30, -30, 20]);
+        let wild = game(&MOVES[..4], &[0, 0, 500, 300]);
+        let input = [steady.clone(), wild, steady.clone()].concat();
+
+        let prune = PruneGames::new(QualityThresholds::default(), |quality: &GameQuality| {
+            quality.blunders == 0
+        });
+        let mut pipeline = Pipeline::new().transform(prune);
+
+        let mut output = input
+            .iter()
+            .flat_map(|&entry| pipeline.apply(entry))
+            .collect::<Vec<_>>();
+        // the last game is held back until the end
+        assert_eq!(output.len(), steady.len());
+        output.extend(pipeline.finish());
+        assert_eq!(output, [steady.clone(), steady].concat());
+    }
+
+    #[test]
+    fn test_prune_games_in_run() {
+        let mut rng = Rng::new(7);
+        let input = (0..30)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+
+        let run = |parallel| {
+            let data = write_binpack(&input);
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+            let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+            let prune = PruneGames::new(QualityThresholds::default(), |quality: &GameQuality| {
+                quality.plies.is_multiple_of(2)
+            });
+
+            let stats = Pipeline::new()
+                .transform(prune)
+                .batch_size(100)
+                .parallel(parallel)
+                .run(&mut reader, &mut writer)
+                .unwrap();
+            writer.flush_and_end();
+            (stats, writer.into_inner().unwrap())
+        };
+
+        let (stats, serial) = run(false);
+        assert_eq!(stats.read, input.len() as u64);
+        assert!(stats.written > 0 && stats.written < stats.read);
+        assert_eq!(run(true), (stats, serial));
+    }
+}
@@ -0,0 +1,308 @@
+//! Putting the plies of a game back in order before they are written.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{chess::attacks, TrainingDataEntry};
+
+use super::Transform;
+
+/// Window of [`Relink::new`] when none is given, large enough for games that
+/// are spread over a few batches of a parallel run
+pub const DEFAULT_WINDOW: usize = 1 << 16;
+
+/// Reorders entries so that the plies of a game follow each other again,
+/// whatever order they arrive in.
+///
+/// The writer only stores an entry as a continuation, a byte or two instead
+/// of a 34 byte stem, if it directly follows the entry whose move leads to
+/// it. Plies that a filter dropped are gaps the writer has to start a new
+/// stem after anyway, but entries that are merely out of order, e.g. after
+/// interleaving sources or transforms that hold entries back, would break
+/// chains that could be kept. `Relink` holds up to `window` entries, and
+/// whenever the window is full writes out the chain of the oldest one:
+/// from the first entry in the window that leads to it, through every
+/// entry that continues it. Gaps stay gaps, a chain is only as long as the
+/// plies that survived in a row.
+///
+/// Entries that continue one another are linked even if they come from
+/// different games, which is what the writer would do with them too.
+///
+/// ```
+/// use sfbinpack::{
+///     chess::{position::Position, r#move::Move},
+///     pipeline::{relink::Relink, Pipeline},
+///     TrainingDataEntry,
+/// };
+///
+/// let pos = Position::new();
+/// let start = TrainingDataEntry {
+///     mv: Move::from_uci(&pos, "e2e4").unwrap(),
+///     ..Default::default()
+/// };
+/// let second = TrainingDataEntry {
+///     pos: pos.after_move(start.mv),
+///     mv: Move::null(),
+///     ply: 1,
+///     ..start
+/// };
+///
+/// // the second ply first, the relinked stream starts with its stem
+/// let mut pipeline = Pipeline::new().transform(Relink::new(16));
+/// let mut out = pipeline.apply(second);
+/// out.extend(pipeline.apply(start));
+/// out.extend(pipeline.finish());
+/// assert_eq!(out, [start, second]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Relink {
+    window: usize,
+    /// Entries by the id they got on arrival, until they are written
+    entries: HashMap<u64, TrainingDataEntry>,
+    /// Ids in order of arrival, written ones are skipped when popped
+    order: VecDeque<u64>,
+    /// Ids by the zobrist key and ply of their position
+    by_position: HashMap<(u64, u16), Vec<u64>>,
+    /// Ids by the zobrist key and ply of the position after their move
+    by_next: HashMap<(u64, u16), Vec<u64>>,
+    next_id: u64,
+}
+
+impl Default for Relink {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+impl Relink {
+    /// Holds up to `window` entries, at least one
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            by_position: HashMap::new(),
+            by_next: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn insert(&mut self, entry: TrainingDataEntry) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.by_position
+            .entry(position_key(&entry))
+            .or_default()
+            .push(id);
+        if let Some(key) = next_key(&entry) {
+            self.by_next.entry(key).or_default().push(id);
+        }
+        self.entries.insert(id, entry);
+        self.order.push_back(id);
+    }
+
+    fn remove(&mut self, id: u64) -> TrainingDataEntry {
+        let entry = self.entries.remove(&id).unwrap();
+        unlink(&mut self.by_position, position_key(&entry), id);
+        if let Some(key) = next_key(&entry) {
+            unlink(&mut self.by_next, key, id);
+        }
+        entry
+    }
+
+    /// The oldest entry in the window that `entry` continues
+    fn predecessor(&self, entry: &TrainingDataEntry) -> Option<u64> {
+        self.by_next
+            .get(&position_key(entry))?
+            .iter()
+            .copied()
+            .find(|id| self.entries[id].is_continuation(entry))
+    }
+
+    /// The oldest entry in the window that continues `entry`
+    fn successor(&self, entry: &TrainingDataEntry) -> Option<u64> {
+        self.by_position
+            .get(&next_key(entry)?)?
+            .iter()
+            .copied()
+            .find(|id| entry.is_continuation(&self.entries[id]))
+    }
+
+    /// Writes the chain of the oldest entry still held
+    fn emit_oldest(&mut self, out: &mut Vec<TrainingDataEntry>) {
+        let Some(oldest) = self.pop_oldest() else {
+            return;
+        };
+
+        // plies only go up along a chain, so this ends
+        let mut head = oldest;
+        while let Some(id) = self.predecessor(&self.entries[&head]) {
+            head = id;
+        }
+
+        let mut id = Some(head);
+        while let Some(current) = id {
+            let entry = self.remove(current);
+            out.push(entry);
+            id = self.successor(&entry);
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<u64> {
+        while let Some(id) = self.order.pop_front() {
+            if self.entries.contains_key(&id) {
+                return Some(id);
+            }
+        }
+        None
+    }
+}
+
+impl Transform for Relink {
+    fn apply(&mut self, entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        self.insert(entry);
+        while self.entries.len() > self.window {
+            self.emit_oldest(out);
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<TrainingDataEntry>) {
+        while !self.entries.is_empty() {
+            self.emit_oldest(out);
+        }
+        self.order.clear();
+    }
+}
+
+fn position_key(entry: &TrainingDataEntry) -> (u64, u16) {
+    (entry.pos.zobrist_key(), entry.ply)
+}
+
+/// None if the move of `entry` is not legal, as the last move of a game
+/// need not be, then nothing can continue it
+fn next_key(entry: &TrainingDataEntry) -> Option<(u64, u16)> {
+    attacks::is_legal(&entry.pos, entry.mv).then(|| {
+        (
+            entry.pos.after_move(entry.mv).zobrist_key(),
+            entry.ply.wrapping_add(1),
+        )
+    })
+}
+
+fn unlink(index: &mut HashMap<(u64, u16), Vec<u64>>, key: (u64, u16), id: u64) {
+    if let Some(ids) = index.get_mut(&key) {
+        ids.retain(|&other| other != id);
+        if ids.is_empty() {
+            index.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        pipeline::Pipeline,
+        testing::{random_game, GameOptions, Rng},
+        CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    };
+
+    fn games(seed: u64, count: usize) -> Vec<Vec<TrainingDataEntry>> {
+        let mut rng = Rng::new(seed);
+        (0..count)
+            .map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect()
+    }
+
+    fn relink(entries: &[TrainingDataEntry], window: usize) -> Vec<TrainingDataEntry> {
+        let mut pipeline = Pipeline::new().transform(Relink::new(window));
+        let mut out = Vec::new();
+        for &entry in entries {
+            out.extend(pipeline.apply(entry));
+        }
+        out.extend(pipeline.finish());
+        out
+    }
+
+    fn chains(entries: &[TrainingDataEntry]) -> u64 {
+        let mut writer = CompressedTrainingDataEntryWriter::new(Cursor::new(Vec::new())).unwrap();
+        for entry in entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.flush_and_end();
+        let chains = writer.stats().chains;
+
+        // and the file reads back as written
+        let data = writer.into_inner().unwrap().into_inner();
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        let mut read = Vec::new();
+        while reader.has_next() {
+            read.push(reader.next());
+        }
+        assert_eq!(read, entries);
+
+        chains
+    }
+
+    fn sorted(entries: &[TrainingDataEntry]) -> Vec<String> {
+        let mut entries = entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_relink_interleaved_games() {
+        let games = games(5, 6);
+
+        // round robin over the games, no two plies of a game are adjacent
+        let longest = games.iter().map(Vec::len).max().unwrap();
+        let interleaved = (0..longest)
+            .flat_map(|ply| games.iter().filter_map(move |game| game.get(ply).copied()))
+            .collect::<Vec<_>>();
+        assert!(chains(&interleaved) > 6);
+
+        let relinked = relink(&interleaved, 1 << 12);
+        assert_eq!(sorted(&relinked), sorted(&interleaved));
+        assert_eq!(chains(&relinked), 6);
+    }
+
+    #[test]
+    fn test_relink_reversed_and_gaps() {
+        let games = games(9, 3);
+
+        // every game backwards
+        let reversed = games
+            .iter()
+            .flat_map(|game| game.iter().rev().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(chains(&relink(&reversed, 1 << 12)), 3);
+
+        // a dropped ply splits its game in two, and no more
+        let mut gapped = games.concat();
+        let dropped = games[0].len() / 2;
+        gapped.remove(dropped);
+        gapped.reverse();
+        assert_eq!(chains(&relink(&gapped, 1 << 12)), 4);
+    }
+
+    #[test]
+    fn test_relink_window() {
+        let games = games(13, 4);
+        let entries = games
+            .iter()
+            .flat_map(|game| game.iter().rev().copied())
+            .collect::<Vec<_>>();
+
+        // games in order come out as they went in, whatever the window
+        let in_order = games.concat();
+        assert_eq!(relink(&in_order, 0), in_order);
+        assert_eq!(relink(&in_order, 1), in_order);
+
+        // a small window still keeps every entry
+        let relinked = relink(&entries, 8);
+        assert_eq!(sorted(&relinked), sorted(&entries));
+        assert!(chains(&relinked) < chains(&entries));
+    }
+}
@@ -0,0 +1,141 @@
+//! Relabeling game results with the outcome the score predicts.
+//!
+//! Trainers commonly learn from `lambda * wdl(score) + (1 - lambda) * result`
+//! instead of the bare game result. [`BlendResult`] does that ahead of time:
+//! since a stored result is a win, a draw or a loss, it draws the new result
+//! from the blended distribution, so over many entries the results follow
+//! the blend.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    filter::{wdl, Wdl, VALUE_NONE},
+    TrainingDataEntry,
+};
+
+use super::Transform;
+
+/// Replaces the result of each entry with one drawn from
+/// `lambda * wdl(score, ply) + (1 - lambda) * result`, by the model of
+/// [`wdl`].
+///
+/// A `lambda` of 1 replaces the results with the model's, 0 keeps them.
+/// Entries without a score keep their result.
+#[derive(Debug, Clone)]
+pub struct BlendResult<R = StdRng> {
+    lambda: f64,
+    rng: R,
+}
+
+impl BlendResult {
+    /// `lambda` is clamped to `0.0..=1.0`
+    pub fn new(lambda: f64) -> Self {
+        Self::with_rng(lambda, StdRng::from_entropy())
+    }
+}
+
+impl<R: Rng> BlendResult<R> {
+    pub fn with_rng(lambda: f64, rng: R) -> Self {
+        Self {
+            lambda: lambda.clamp(0.0, 1.0),
+            rng,
+        }
+    }
+
+    pub fn lambda(&self) -> f64 {
+        self.lambda
+    }
+
+    /// The blended outcome probabilities of the entry, for the side to move
+    pub fn target(&self, entry: &TrainingDataEntry) -> Wdl {
+        let stored = Wdl::from_result(entry.result);
+        if entry.score == VALUE_NONE {
+            return stored;
+        }
+
+        wdl(entry.score, entry.ply).lerp(&stored, self.lambda)
+    }
+
+    /// A result drawn from [`target`](Self::target)
+    pub fn result(&mut self, entry: &TrainingDataEntry) -> i16 {
+        let target = self.target(entry);
+
+        let x = self.rng.gen::<f64>();
+        if x < target.win {
+            1
+        } else if x < target.win + target.draw {
+            0
+        } else {
+            -1
+        }
+    }
+}
+
+impl<R: Rng + Send> Transform for BlendResult<R> {
+    fn apply(&mut self, mut entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        entry.result = self.result(&entry);
+        out.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::Pipeline, testing::entry_from_fen};
+
+    /// A position 60 plies into the game
+    const PLY_60: &str = "4k3/8/8/8/8/8/8/4K3 w - - 0 31";
+
+    #[test]
+    fn test_lambda_zero_keeps_results() {
+        let mut blend = BlendResult::with_rng(0.0, StdRng::seed_from_u64(1));
+        for (score, result) in [(3000, -1), (-3000, 1), (0, 1), (500, 0)] {
+            assert_eq!(
+                blend.result(&entry_from_fen(PLY_60, "", score, result)),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_score_none_keeps_results() {
+        let mut blend = BlendResult::with_rng(1.0, StdRng::seed_from_u64(2));
+        for result in [-1, 0, 1] {
+            assert_eq!(
+                blend.result(&entry_from_fen(PLY_60, "", VALUE_NONE, result)),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_target() {
+        let blend = BlendResult::new(0.25);
+        let target = blend.target(&entry_from_fen(PLY_60, "", 400, -1));
+        let model = wdl(400, 60);
+
+        assert!((target.win - 0.25 * model.win).abs() < 1e-12);
+        assert!((target.draw - 0.25 * model.draw).abs() < 1e-12);
+        assert!((target.loss - (0.25 * model.loss + 0.75)).abs() < 1e-12);
+        assert!((target.win + target.draw + target.loss - 1.0).abs() < 1e-12);
+
+        assert_eq!(BlendResult::new(7.0).lambda(), 1.0);
+        assert_eq!(BlendResult::new(-1.0).lambda(), 0.0);
+    }
+
+    #[test]
+    fn test_results_follow_blend() {
+        let mut pipeline =
+            Pipeline::new().transform(BlendResult::with_rng(0.5, StdRng::seed_from_u64(3)));
+
+        let n = 20_000;
+        let total: i64 = (0..n)
+            .map(|_| i64::from(pipeline.apply(entry_from_fen(PLY_60, "", 300, -1))[0].result))
+            .sum();
+
+        // mean result is 2 * expected outcome - 1
+        let expected = 0.5 * (2.0 * wdl(300, 60).expected_outcome() - 1.0) - 0.5;
+        let mean = total as f64 / n as f64;
+        assert!((mean - expected).abs() < 0.03, "{mean} {expected}");
+    }
+}
@@ -0,0 +1,215 @@
+//! Transforms of the scores of entries.
+//!
+//! None of them change a score of [`VALUE_NONE`], so entries without a score
+//! can still be told apart afterwards.
+
+use crate::{
+    formats::{pgn::MATE, VALUE_NONE},
+    TrainingDataEntry,
+};
+
+use super::Transform;
+
+/// Plies a mate can be away and still be scored as one, like Stockfish's
+/// `MAX_PLY`
+pub const MAX_MATE_PLY: i16 = 246;
+
+/// Smallest score of a mate, `MATE - MAX_MATE_PLY`
+pub const MATE_IN_MAX_PLY: i16 = MATE - MAX_MATE_PLY;
+
+/// Centipawns per pawn
+pub const CENTIPAWNS: i32 = 100;
+
+/// Internal units per pawn of Stockfish's NNUE evaluation, the scale of
+/// scores in Stockfish generated training data
+pub const STOCKFISH_INTERNAL: i32 = 208;
+
+/// Whether `score` is a mate score, for either side
+pub fn is_mate_score(score: i16) -> bool {
+    score != VALUE_NONE && (MATE_IN_MAX_PLY..=MATE).contains(&score.saturating_abs())
+}
+
+/// Clamps scores to `min..=max`, mate scores included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClampScore {
+    min: i16,
+    max: i16,
+}
+
+impl ClampScore {
+    /// `min` must not be greater than `max`
+    pub fn new(min: i16, max: i16) -> Self {
+        assert!(min <= max, "empty score range {min}..={max}");
+        Self { min, max }
+    }
+
+    pub fn score(&self, score: i16) -> i16 {
+        if score == VALUE_NONE {
+            return score;
+        }
+
+        score.clamp(self.min, self.max)
+    }
+}
+
+impl Transform for ClampScore {
+    fn apply(&mut self, mut entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        entry.score = self.score(entry.score);
+        out.push(entry);
+    }
+}
+
+/// Converts scores from one unit to another, `from` units become `to` units.
+///
+/// Mate scores are not in either unit and stay as they are; a converted
+/// score that would look like a mate is capped just below the mate scores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaleScore {
+    from: i32,
+    to: i32,
+}
+
+impl ScaleScore {
+    /// `from` must be positive and `to` must not be negative, e.g.
+    /// `ScaleScore::new(STOCKFISH_INTERNAL, CENTIPAWNS)`
+    pub fn new(from: i32, to: i32) -> Self {
+        assert!(from > 0 && to >= 0, "invalid score scale {from}:{to}");
+        Self { from, to }
+    }
+
+    pub fn score(&self, score: i16) -> i16 {
+        if score == VALUE_NONE || is_mate_score(score) {
+            return score;
+        }
+
+        let (score, from, to) = (i64::from(score), i64::from(self.from), i64::from(self.to));
+        // rounds half away from zero
+        let scaled = (2 * score * to + score.signum() * from) / (2 * from);
+
+        let max = i64::from(MATE_IN_MAX_PLY) - 1;
+        scaled.clamp(-max, max) as i16
+    }
+}
+
+impl Transform for ScaleScore {
+    fn apply(&mut self, mut entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        entry.score = self.score(entry.score);
+        out.push(entry);
+    }
+}
+
+/// Replaces mate scores with `cap` for the side that mates and `-cap` for
+/// the side that gets mated, however far away the mate is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapMateScores {
+    cap: i16,
+}
+
+impl CapMateScores {
+    /// `cap` must not be negative
+    pub fn new(cap: i16) -> Self {
+        assert!(cap >= 0, "negative mate score cap {cap}");
+        Self { cap }
+    }
+
+    pub fn score(&self, score: i16) -> i16 {
+        match is_mate_score(score) {
+            true => self.cap * score.signum(),
+            false => score,
+        }
+    }
+}
+
+impl Transform for CapMateScores {
+    fn apply(&mut self, mut entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        entry.score = self.score(entry.score);
+        out.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn test_is_mate_score() {
+        for score in [MATE, MATE - 1, MATE_IN_MAX_PLY, -MATE, -MATE_IN_MAX_PLY] {
+            assert!(is_mate_score(score), "{score}");
+        }
+        for score in [
+            0,
+            3000,
+            MATE_IN_MAX_PLY - 1,
+            VALUE_NONE,
+            -VALUE_NONE,
+            i16::MIN,
+        ] {
+            assert!(!is_mate_score(score), "{score}");
+        }
+    }
+
+    #[test]
+    fn test_clamp_score() {
+        let clamp = ClampScore::new(-3000, 2000);
+        assert_eq!(clamp.score(-5000), -3000);
+        assert_eq!(clamp.score(150), 150);
+        assert_eq!(clamp.score(MATE - 3), 2000);
+        assert_eq!(clamp.score(VALUE_NONE), VALUE_NONE);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty score range")]
+    fn test_clamp_score_empty_range() {
+        ClampScore::new(1, 0);
+    }
+
+    #[test]
+    fn test_scale_score() {
+        let to_cp = ScaleScore::new(STOCKFISH_INTERNAL, CENTIPAWNS);
+        assert_eq!(to_cp.score(208), 100);
+        assert_eq!(to_cp.score(-208), -100);
+        assert_eq!(to_cp.score(1), 0);
+        // 3 * 100 / 208 = 1.44, 5 * 100 / 208 = 2.4, 7 * 100 / 208 = 3.37
+        assert_eq!(to_cp.score(-3), -1);
+        assert_eq!(to_cp.score(5), 2);
+        assert_eq!(to_cp.score(7), 3);
+        assert_eq!(ScaleScore::new(2, 1).score(-3), -2);
+
+        let to_internal = ScaleScore::new(CENTIPAWNS, STOCKFISH_INTERNAL);
+        assert_eq!(to_internal.score(100), 208);
+        assert_eq!(to_internal.score(30000), MATE_IN_MAX_PLY - 1);
+        assert_eq!(to_internal.score(-30000), -(MATE_IN_MAX_PLY - 1));
+
+        // not evals, left alone
+        assert_eq!(to_internal.score(MATE - 5), MATE - 5);
+        assert_eq!(to_internal.score(VALUE_NONE), VALUE_NONE);
+    }
+
+    #[test]
+    fn test_cap_mate_scores() {
+        let cap = CapMateScores::new(4000);
+        assert_eq!(cap.score(MATE - 10), 4000);
+        assert_eq!(cap.score(-(MATE - 1)), -4000);
+        assert_eq!(cap.score(31000), 31000);
+        assert_eq!(cap.score(VALUE_NONE), VALUE_NONE);
+    }
+
+    #[test]
+    fn test_score_transforms_in_pipeline() {
+        let mut pipeline = Pipeline::new()
+            .transform(CapMateScores::new(5000))
+            .transform(ScaleScore::new(STOCKFISH_INTERNAL, CENTIPAWNS))
+            .transform(ClampScore::new(-1000, 1000));
+
+        let entry = |score| TrainingDataEntry {
+            score,
+            ..Default::default()
+        };
+
+        // a mate scaled like an eval of 5000
+        assert_eq!(pipeline.apply(entry(MATE - 4))[0].score, 1000);
+        assert_eq!(pipeline.apply(entry(-416))[0].score, -200);
+        assert_eq!(pipeline.apply(entry(VALUE_NONE))[0].score, VALUE_NONE);
+    }
+}
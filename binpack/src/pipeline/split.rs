@@ -0,0 +1,114 @@
+//! Splitting off a validation set by game while converting.
+
+use crate::{rng, TrainingDataEntry};
+
+/// Picks a pseudo-random one in `every` games of a stream of entries, for
+/// [`Pipeline::validation`](super::Pipeline::validation).
+///
+/// A game starts wherever the ply goes down. Plies dropped by a filter or
+/// positions added next to their original by an augmentation do not split a
+/// game, so every game goes to one side only. Two games where the second
+/// starts at a higher ply than the first ended count as one, which keeps
+/// the split disjoint as well. Whether a game is picked depends only on the
+/// seed and how many games came before it, so the same input and seed give
+/// the same split every time.
+///
+/// ```
+/// use sfbinpack::{pipeline::split::ValidationSplit, TrainingDataEntry};
+///
+/// let mut split = ValidationSplit::new(1, 0);
+/// let entry = TrainingDataEntry::default();
+/// assert!(split.is_validation(&entry));
+/// assert_eq!(split.games(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ValidationSplit {
+    every: u64,
+    seed: u64,
+    games: u64,
+    last_ply: Option<u16>,
+    current: bool,
+}
+
+impl ValidationSplit {
+    /// Picks one in `every` games, at least 1, which picks all of them
+    pub fn new(every: u64, seed: u64) -> Self {
+        Self {
+            every: every.max(1),
+            seed,
+            games: 0,
+            last_ply: None,
+            current: false,
+        }
+    }
+
+    /// Whether `entry` belongs to a picked game, entries have to come in
+    /// the order they are written
+    pub fn is_validation(&mut self, entry: &TrainingDataEntry) -> bool {
+        if self.last_ply.is_none_or(|ply| entry.ply < ply) {
+            self.current = mix(self.seed, self.games).is_multiple_of(self.every);
+            self.games += 1;
+        }
+        self.last_ply = Some(entry.ply);
+        self.current
+    }
+
+    /// Games seen so far, picked or not
+    pub fn games(&self) -> u64 {
+        self.games
+    }
+}
+
+/// SplitMix64 of game `game` under `seed`
+fn mix(seed: u64, game: u64) -> u64 {
+    rng::mix(seed.wrapping_add(game.wrapping_add(1).wrapping_mul(rng::GAMMA)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(split: &mut ValidationSplit, plies: &[u16]) -> Vec<bool> {
+        plies
+            .iter()
+            .map(|&ply| {
+                split.is_validation(&TrainingDataEntry {
+                    ply,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_by_game() {
+        // three games, the second with a gap and a repeated ply
+        let plies = [0, 1, 2, 3, 2, 4, 4, 5, 1, 2];
+        let mut split = ValidationSplit::new(2, 7);
+        let picked = picks(&mut split, &plies);
+        assert_eq!(split.games(), 3);
+        assert!(picked[..4].iter().all(|&p| p == picked[0]));
+        assert!(picked[4..8].iter().all(|&p| p == picked[4]));
+        assert_eq!(picked[8], picked[9]);
+
+        // the same seed picks the same games
+        assert_eq!(picks(&mut ValidationSplit::new(2, 7), &plies), picked);
+    }
+
+    #[test]
+    fn test_split_rate() {
+        let plies = (0..10_000).flat_map(|_| [3, 4]).collect::<Vec<_>>();
+        let count = |every, seed| {
+            let picked = picks(&mut ValidationSplit::new(every, seed), &plies);
+            picked.iter().filter(|&&p| p).count() / 2
+        };
+
+        assert!((900..1100).contains(&count(10, 1)));
+        assert_ne!(
+            picks(&mut ValidationSplit::new(10, 1), &plies),
+            picks(&mut ValidationSplit::new(10, 2), &plies)
+        );
+        assert_eq!(count(1, 5), 10_000);
+        assert_eq!(count(0, 5), 10_000);
+    }
+}
@@ -0,0 +1,232 @@
+//! Relabeling endgames with exact values from tablebases.
+//!
+//! The probing itself is left to an implementation of [`TablebaseProber`],
+//! e.g. a wrapper around a Syzygy library; [`TablebaseRelabel`] overwrites
+//! the results and scores of the entries it knows.
+
+use crate::{
+    chess::{castling_rights::CastlingRights, position::Position},
+    TrainingDataEntry,
+};
+
+use super::{
+    score::{is_mate_score, MATE_IN_MAX_PLY, MAX_MATE_PLY},
+    Transform,
+};
+
+/// Tablebase outcome for the side to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TbWdl {
+    Loss,
+    /// A loss that the fifty-move rule turns into a draw
+    BlessedLoss,
+    Draw,
+    /// A win that the fifty-move rule turns into a draw
+    CursedWin,
+    Win,
+}
+
+impl TbWdl {
+    /// The game result under the fifty-move rule, 1 for a win, 0 for a draw
+    /// and -1 for a loss
+    pub fn result(self) -> i16 {
+        match self {
+            TbWdl::Win => 1,
+            TbWdl::Loss => -1,
+            TbWdl::BlessedLoss | TbWdl::Draw | TbWdl::CursedWin => 0,
+        }
+    }
+}
+
+/// What a tablebase knows about a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TbProbe {
+    pub wdl: TbWdl,
+    /// Plies to the next zeroing move with best play, when known
+    pub dtz: Option<u32>,
+}
+
+/// Looks positions up in an endgame tablebase
+pub trait TablebaseProber: Send {
+    /// Most pieces, kings included, the tablebase has positions for
+    fn max_pieces(&self) -> u32;
+
+    /// The value of `pos`, `None` when it is not in the tablebase. Only
+    /// called for positions without castling rights and with at most
+    /// [`max_pieces`](Self::max_pieces) pieces.
+    fn probe(&self, pos: &Position) -> Option<TbProbe>;
+}
+
+/// Score given to tablebase wins, just below the mate scores
+pub const TB_WIN: i16 = MATE_IN_MAX_PLY - 1;
+
+/// Lowest score given to a tablebase win, however long the conversion
+pub const TB_WIN_IN_MAX_PLY: i16 = TB_WIN - MAX_MATE_PLY;
+
+/// Overwrites the result, and unless disabled the score, of every entry the
+/// tablebase has.
+///
+/// Wins score [`TB_WIN`] less the distance to zeroing, losses the negation,
+/// draws 0, like Stockfish scores tablebase hits. Mate scores that agree
+/// with the tablebase are more precise and are kept.
+pub struct TablebaseRelabel<P> {
+    prober: P,
+    scores: bool,
+    relabeled: u64,
+}
+
+impl<P: TablebaseProber> TablebaseRelabel<P> {
+    pub fn new(prober: P) -> Self {
+        Self {
+            prober,
+            scores: true,
+            relabeled: 0,
+        }
+    }
+
+    /// Whether scores are overwritten too, on by default
+    pub fn scores(mut self, scores: bool) -> Self {
+        self.scores = scores;
+        self
+    }
+
+    /// Entries found in the tablebase so far
+    pub fn relabeled(&self) -> u64 {
+        self.relabeled
+    }
+
+    /// Relabels `entry` in place, returns whether the tablebase had it
+    pub fn relabel(&mut self, entry: &mut TrainingDataEntry) -> bool {
+        let pos = &entry.pos;
+        if pos.castling_rights() != CastlingRights::NONE
+            || pos.occupied().count() > self.prober.max_pieces()
+        {
+            return false;
+        }
+
+        let Some(probe) = self.prober.probe(pos) else {
+            return false;
+        };
+
+        let result = probe.wdl.result();
+        entry.result = result;
+
+        let agrees = is_mate_score(entry.score) && entry.score.signum() == result;
+        if self.scores && !agrees {
+            let distance = probe.dtz.unwrap_or(0).min(MAX_MATE_PLY as u32) as i16;
+            entry.score = match result {
+                1 => TB_WIN - distance,
+                -1 => -(TB_WIN - distance),
+                _ => 0,
+            };
+        }
+
+        self.relabeled += 1;
+        true
+    }
+}
+
+impl<P: TablebaseProber> Transform for TablebaseRelabel<P> {
+    fn apply(&mut self, mut entry: TrainingDataEntry, out: &mut Vec<TrainingDataEntry>) {
+        self.relabel(&mut entry);
+        out.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::piecetype::PieceType, formats::pgn::MATE, pipeline::Pipeline,
+        testing::entry_from_fen,
+    };
+
+    /// Knows that a lone queen wins, and that bare kings draw
+    struct Queens;
+
+    impl TablebaseProber for Queens {
+        fn max_pieces(&self) -> u32 {
+            3
+        }
+
+        fn probe(&self, pos: &Position) -> Option<TbProbe> {
+            let queens = |color| pos.pieces_bb_color(color, PieceType::Queen).count();
+            let stm = pos.side_to_move();
+
+            let wdl = match (queens(stm), queens(!stm), pos.occupied().count()) {
+                (_, _, 2) => TbWdl::Draw,
+                (1, 0, 3) => TbWdl::Win,
+                (0, 1, 3) => TbWdl::Loss,
+                _ => return None,
+            };
+            Some(TbProbe { wdl, dtz: Some(9) })
+        }
+    }
+
+    #[test]
+    fn test_relabel() {
+        let mut relabel = TablebaseRelabel::new(Queens);
+
+        let mut win = entry_from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1d7", 300, 0);
+        assert!(relabel.relabel(&mut win));
+        assert_eq!((win.score, win.result), (TB_WIN - 9, 1));
+
+        let mut loss = entry_from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1", "e8f8", 0, 1);
+        assert!(relabel.relabel(&mut loss));
+        assert_eq!((loss.score, loss.result), (-(TB_WIN - 9), -1));
+
+        // the mate score is kept
+        let mut mate = entry_from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1d7", MATE - 7, 0);
+        assert!(relabel.relabel(&mut mate));
+        assert_eq!((mate.score, mate.result), (MATE - 7, 1));
+
+        let mut draw = entry_from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "e1e2", 80, 1);
+        assert!(relabel.relabel(&mut draw));
+        assert_eq!((draw.score, draw.result), (0, 0));
+        assert_eq!(relabel.relabeled(), 4);
+
+        // too many pieces, or not in the tablebase
+        let mut rook = entry_from_fen("4k3/8/8/8/8/8/8/3RK3 w - - 0 1", "d1d7", 300, 0);
+        assert!(!relabel.relabel(&mut rook));
+        let mut many = entry_from_fen("4k3/8/8/8/8/8/3P4/3QK3 w - - 0 1", "d1a4", 300, 0);
+        assert!(!relabel.relabel(&mut many));
+        assert_eq!((many.score, many.result), (300, 0));
+    }
+
+    #[test]
+    fn test_relabel_results_only() {
+        let mut pipeline = Pipeline::new().transform(TablebaseRelabel::new(Queens).scores(false));
+        let win = entry_from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1d7", 300, 0);
+        let out = pipeline.apply(win);
+        assert_eq!((out[0].score, out[0].result), (300, 1));
+    }
+
+    /// Has every position, as a draw
+    struct Draws;
+
+    impl TablebaseProber for Draws {
+        fn max_pieces(&self) -> u32 {
+            32
+        }
+
+        fn probe(&self, _pos: &Position) -> Option<TbProbe> {
+            Some(TbProbe {
+                wdl: TbWdl::Draw,
+                dtz: None,
+            })
+        }
+    }
+
+    #[test]
+    fn test_castling_rights_not_probed() {
+        let mut relabel = TablebaseRelabel::new(Draws);
+        let mut entry = entry_from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1", "a1a7", 300, 0);
+        assert!(!relabel.relabel(&mut entry));
+
+        let mut entry = TrainingDataEntry {
+            pos: entry.pos.after_move(entry.mv),
+            ..entry
+        };
+        assert!(relabel.relabel(&mut entry));
+    }
+}
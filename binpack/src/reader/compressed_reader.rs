@@ -2,13 +2,23 @@ use std::io::{self};
 use std::io::{Read, Seek};
 use thiserror::Error;
 
+use crate::annotation::{self, Annotation};
+use crate::checksum::{self, ChunkChecksum};
+use crate::chess::attacks;
 use crate::common::{
-    binpack_error::BinpackError, compressed_training_file_reader::CompressedTrainingDataFileReader,
-    entry::PackedTrainingDataEntry, entry::TrainingDataEntry,
+    binpack_error::BinpackError,
+    buffer_pool::{BufferPool, PooledBuffer},
+    compressed_position::CompressedPositionError,
+    compressed_training_file_reader::{
+        CompressedTrainingDataFileReader, FormatDialect, MAX_CHUNK_SIZE,
+    },
+    entry::{PackedTrainingDataEntry, TrainingDataEntry},
+    game::Game,
+    lazy_entry::LazyEntry,
+    move_score_list_reader::PackedMoveScoreListReader,
+    values::Ply,
 };
 
-use super::move_score_list_reader::PackedMoveScoreListReader;
-
 const SUGGESTED_CHUNK_SIZE: usize = 8192;
 
 #[derive(Debug, Error)]
@@ -21,19 +31,72 @@ pub enum CompressedReaderError {
     EndOfFile,
     #[error("Binpack error: {0}")]
     BinpackError(#[from] BinpackError),
+    #[error("Invalid position: {0}")]
+    InvalidPosition(#[from] CompressedPositionError),
 }
 
 type Result<T> = std::result::Result<T, CompressedReaderError>;
 
+/// Where the chain of an entry is stored in the file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainLocation {
+    /// Zero based index of the chunk
+    pub chunk: u64,
+    /// Byte offset of the chain's stem from the start of the file
+    pub offset: u64,
+}
+
+/// Options for [`CompressedTrainingDataEntryReader::with_options`]
+#[derive(Debug, Clone, Default)]
+pub struct ReaderOptions {
+    /// Bytes of binpack data from the current position of the file.
+    ///
+    /// Without it the length is measured once, with a seek to the end and
+    /// back, and a source whose `seek` fails is read until a chunk header is
+    /// missing. The hint saves that seek, and stops the reader at the end of
+    /// a binpack embedded in a larger stream.
+    pub len_hint: Option<u64>,
+    /// Pool to take the chunk buffer from, it goes back when the reader is
+    /// dropped
+    pub buffer_pool: Option<BufferPool>,
+    /// Fail with `EndOfFile` on input without a single chunk, as the reader
+    /// used to. By default such a reader is created and has no entries.
+    pub error_on_empty: bool,
+    /// How the chunks are framed, [`FormatDialect::Auto`] to read files of
+    /// older tools without knowing which they came from
+    pub dialect: FormatDialect,
+    /// Largest chunk that is read instead of reported as malformed, 100 MiB
+    /// by default
+    pub max_chunk_size: Option<u32>,
+}
+
 /// Reads Stockfish binpacks and returns a TrainingDataEntry
 /// for each encoded entry.
+///
+/// The reader is `Send` and `Sync` whenever `T` is, so it can be handed to
+/// another thread, e.g. one reader per file in a pipeline. Reading takes
+/// `&mut self`, a reader shared between threads needs a lock.
 #[derive(Debug)]
 pub struct CompressedTrainingDataEntryReader<T: Read + Seek> {
-    chunk: Vec<u8>,
+    chunk: PooledBuffer,
+    chunk_len: usize,
+    chunk_start: u64,
     movelist_reader: Option<PackedMoveScoreListReader>,
+    /// Stem and plies of the chain whose stem next_packed() returned, until
+    /// its movetext is read
+    packed_chain: Option<(PackedTrainingDataEntry, u16)>,
     input_file: Option<CompressedTrainingDataFileReader<T>>,
     offset: usize,
+    chain: ChainLocation,
+    pending_error: Option<CompressedReaderError>,
     is_end: bool,
+    checksummed_chunks: u64,
+    /// Annotations of the entries of the current chunk, empty if it has
+    /// none
+    annotations: Vec<Annotation>,
+    /// Entries of the current chunk up to the end of the last chain whose
+    /// stem was read
+    chunk_entries: usize,
 }
 
 /*
@@ -45,6 +108,13 @@ ChunkHeader  = Magic ChunkSize
 Magic        = "BINP"
 ChunkSize    = UINT32LE               (* 4 bytes, little endian *)
 
+(* with annotations, see crate::annotation *)
+Block        = (AnnotationHeader Annotations)? ChunkHeader Chain*
+AnnotationHeader = "BINA" ChunkSize
+
+(* FormatDialect::MagicOnce, written by some older tools *)
+File         = ChunkHeader Chain* (ChunkSize Chain*)*
+
 Chain        = Stem Count MoveText
 Stem         = Position Move Score PlyResult Rule50
 Count        = UINT16BE               (* 2 bytes, big endian *)
@@ -69,6 +139,7 @@ EncodedScore = VARLEN_INT             (* Variable length encoding *)
 impl<T: Read + Seek> CompressedTrainingDataEntryReader<T> {
     /// Create a new CompressedTrainingDataEntryReader,
     /// reading from the file at the given path.
+    /// An empty file gives a reader without entries.
     /// # Examples
     ///
     /// ```
@@ -83,25 +154,62 @@ impl<T: Read + Seek> CompressedTrainingDataEntryReader<T> {
     /// }
     /// ```
     pub fn new(file: T) -> Result<Self> {
-        let chunk = Vec::with_capacity(SUGGESTED_CHUNK_SIZE);
+        Self::with_options(file, ReaderOptions::default())
+    }
+
+    /// Like [`new`](Self::new), with options for how the file is read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::{CompressedTrainingDataEntryReader, ReaderOptions};
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let len = file.metadata().unwrap().len();
+    /// let options = ReaderOptions {
+    ///     len_hint: Some(len),
+    ///     ..Default::default()
+    /// };
+    /// let mut reader = CompressedTrainingDataEntryReader::with_options(file, options).unwrap();
+    ///
+    /// while reader.has_next() {
+    ///     let entry = reader.next();
+    /// }
+    /// ```
+    pub fn with_options(file: T, options: ReaderOptions) -> Result<Self> {
+        let chunk = match &options.buffer_pool {
+            Some(pool) => pool.take(),
+            None => PooledBuffer::unpooled(Vec::with_capacity(SUGGESTED_CHUNK_SIZE)),
+        };
 
         let mut reader = Self {
             chunk,
+            chunk_len: 0,
+            chunk_start: 0,
             movelist_reader: None,
-            input_file: Some(CompressedTrainingDataFileReader::new(file)?),
+            packed_chain: None,
+            input_file: Some(
+                CompressedTrainingDataFileReader::new(file, options.len_hint)?.with_dialect(
+                    options.dialect,
+                    options.max_chunk_size.unwrap_or(MAX_CHUNK_SIZE),
+                ),
+            ),
             offset: 0,
+            chain: ChainLocation::default(),
+            pending_error: None,
             is_end: false,
+            checksummed_chunks: 0,
+            annotations: Vec::new(),
+            chunk_entries: 0,
         };
 
-        if !reader.input_file.as_mut().unwrap().has_next_chunk() {
-            reader.is_end = true;
+        if reader.input_file.as_mut().unwrap().has_next_chunk() {
+            reader.load_next_chunk()?;
+        } else if options.error_on_empty {
             return Err(CompressedReaderError::EndOfFile);
         } else {
-            reader
-                .input_file
-                .as_mut()
-                .unwrap()
-                .read_next_chunk_into(&mut reader.chunk)?;
+            reader.is_end = true;
         }
 
         Ok(reader)
@@ -116,13 +224,53 @@ impl<T: Read + Seek> CompressedTrainingDataEntryReader<T> {
         self.input_file.as_ref().unwrap().read_bytes()
     }
 
+    /// Get how many chunks have been loaded so far, including the current one
+    pub fn chunks_read(&self) -> u64 {
+        self.input_file.as_ref().unwrap().chunks_read()
+    }
+
+    /// Chunks loaded so far that had a checksum trailer, all of them
+    /// matched: a mismatch is an error like any other broken chunk
+    pub fn checksummed_chunks(&self) -> u64 {
+        self.checksummed_chunks
+    }
+
+    /// The dialect the chunks are read as, with [`FormatDialect::Auto`] the
+    /// detected one once a second chunk was loaded
+    pub fn dialect(&self) -> FormatDialect {
+        self.input_file.as_ref().unwrap().dialect()
+    }
+
+    /// Get the location of the chain the last entry returned by next() or
+    /// try_next() belongs to, after an error from try_next() the location
+    /// of the offending chain or chunk header
+    pub fn chain_location(&self) -> ChainLocation {
+        self.chain
+    }
+
+    /// The data of the current chunk without its header and checksum
+    /// trailer, as long as nothing of it has been read yet: right after the
+    /// last entry of the chunk before. A chunk copied with
+    /// [`write_chunk`](crate::CompressedTrainingDataEntryWriter::write_chunk)
+    /// holds the same entries as its decoded entries written one by one,
+    /// their annotations are not part of it.
+    pub fn chunk_data(&self) -> Option<&[u8]> {
+        let untouched = self.offset == 0 && self.movelist_reader.is_none();
+        (untouched && !self.is_end && self.pending_error.is_none() && self.chunk_len > 0)
+            .then(|| &self.chunk[..self.chunk_len])
+    }
+
     /// Check if there are more TrainingDataEntry to read
     pub fn has_next(&self) -> bool {
-        !self.is_end
+        !self.is_end || self.pending_error.is_some()
     }
 
     /// Check if the next entry is a continuation of the last returned entry from next()
     pub fn is_next_entry_continuation(&self) -> bool {
+        if self.packed_chain.is_some() {
+            return true;
+        }
+
         if let Some(ref reader) = self.movelist_reader {
             return reader.has_next();
         }
@@ -133,8 +281,34 @@ impl<T: Read + Seek> CompressedTrainingDataEntryReader<T> {
     /// Get the next TrainingDataEntry
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> TrainingDataEntry {
+        let mut entry = TrainingDataEntry::empty();
+        self.next_into(&mut entry);
+        entry
+    }
+
+    /// Decode the next TrainingDataEntry into `entry`, overwriting it.
+    ///
+    /// Same as next(), but a caller that keeps reusing one entry, or decodes
+    /// straight into a slot of a batch, saves copying the entry around.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::{CompressedTrainingDataEntryReader, TrainingDataEntry};
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+    /// let mut entry = TrainingDataEntry::default();
+    ///
+    /// while reader.has_next() {
+    ///     reader.next_into(&mut entry);
+    /// }
+    /// ```
+    pub fn next_into(&mut self, entry: &mut TrainingDataEntry) {
+        let _ = self.start_packed_chain();
+
         if let Some(ref mut reader) = self.movelist_reader {
-            let entry = reader.next_entry();
+            reader.next_entry_into(&self.chunk[self.offset..self.chunk_len], entry);
 
             if !reader.has_next() {
                 self.offset += reader.num_read_bytes();
@@ -142,75 +316,668 @@ impl<T: Read + Seek> CompressedTrainingDataEntryReader<T> {
                 self.fetch_next_chunk_if_needed();
             }
 
-            return entry;
+            return;
         }
 
         // We don't have a movelist reader, so we first need to extract the "stem" information
 
+        self.chain = self.current_location();
+
         // EBNF: Stem
-        let entry = self.read_entry();
+        self.read_packed_entry().unpack_entry_into(entry);
 
         // EBNF: Count
         let num_plies = self.read_plies();
 
         if num_plies > 0 {
             // EBNF: MoveText
-            let chunk_ref = &self.chunk[self.offset..];
+            self.movelist_reader = Some(PackedMoveScoreListReader::new(*entry, num_plies));
+        } else {
+            self.fetch_next_chunk_if_needed();
+        }
+    }
+
+    /// Decode up to `n` entries into `batch`, replacing its contents, and
+    /// return how many were decoded; fewer than `n` only at the end of the
+    /// file.
+    ///
+    /// The entries of a chain are decoded in one loop straight into the
+    /// batch, without going through next() and its checks for each of them.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::CompressedTrainingDataEntryReader;
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+    /// let mut batch = Vec::new();
+    ///
+    /// while reader.next_batch(&mut batch, 1024) > 0 {
+    ///     for entry in &batch {
+    ///         // ...
+    ///     }
+    /// }
+    /// ```
+    pub fn next_batch(&mut self, batch: &mut Vec<TrainingDataEntry>, n: usize) -> usize {
+        trace_span!("decode_batch", max = n);
+        batch.clear();
+        batch.reserve(n);
+        let _ = self.start_packed_chain();
+
+        while batch.len() < n && !self.is_end {
+            let Some(ref mut reader) = self.movelist_reader else {
+                let mut entry = TrainingDataEntry::empty();
+                self.next_into(&mut entry);
+                batch.push(entry);
+                continue;
+            };
+
+            // the rest of the chain, or as much of it as fits the batch
+            let movetext = &self.chunk[self.offset..self.chunk_len];
+            let start = batch.len();
+            let end = n.min(start + reader.remaining());
+            batch.resize(end, TrainingDataEntry::empty());
+
+            for entry in &mut batch[start..end] {
+                reader.next_entry_into(movetext, entry);
+            }
+
+            if !reader.has_next() {
+                self.offset += reader.num_read_bytes();
+                self.movelist_reader = None;
+                self.fetch_next_chunk_if_needed();
+            }
+        }
+
+        trace_event!(entries = batch.len(), "batch decoded");
+        batch.len()
+    }
+
+    /// Get the next TrainingDataEntry, checking that the data is well formed.
+    ///
+    /// Unlike next(), a chain that runs past the end of its chunk or a broken
+    /// chunk header is reported as an error instead of being decoded from
+    /// whatever bytes follow. A chain has no length prefix, so after an error
+    /// inside a chunk the rest of that chunk is skipped. After a broken chunk
+    /// header nothing more can be read.
+    ///
+    /// Positions that cannot arise in a game and moves that are not legal are
+    /// errors too, so this never panics whatever the input; use it for data
+    /// that is not trusted. next() assumes well formed data.
+    pub fn try_next(&mut self) -> Result<TrainingDataEntry> {
+        if let Some(err) = self.pending_error.take() {
+            return Err(err);
+        }
+
+        if self.is_end {
+            return Err(CompressedReaderError::EndOfFile);
+        }
+
+        if let Err(err) = self.start_packed_chain() {
+            self.skip_chunk();
+            return Err(err);
+        }
+
+        if let Some(ref mut reader) = self.movelist_reader {
+            let entry = reader.next_entry(&self.chunk[self.offset..self.chunk_len]);
+            let end = self.offset + reader.num_read_bytes();
+            let has_next = reader.has_next();
+
+            if end > self.chunk_len {
+                self.skip_chunk();
+                return Err(CompressedReaderError::InvalidFormat(
+                    "movetext runs past the end of the chunk".to_string(),
+                ));
+            }
+
+            // the next entry is decoded by playing this move, so it has to be
+            // legal
+            if reader.is_corrupt() || (has_next && !attacks::is_legal(&entry.pos, entry.mv)) {
+                self.skip_chunk();
+                return Err(CompressedReaderError::InvalidFormat(format!(
+                    "movetext decodes to an illegal move in {}",
+                    entry.pos.fen().unwrap_or_default()
+                )));
+            }
+
+            if !has_next {
+                self.offset = end;
+                self.movelist_reader = None;
+                self.fetch_next_chunk_checked();
+            }
+
+            return Ok(entry);
+        }
+
+        self.chain = self.current_location();
+
+        if self.offset + PackedTrainingDataEntry::byte_size() + 2 > self.chunk_len {
+            // chains only end early like this when the chunk itself is too short
+            self.skip_chunk();
+            return Err(CompressedReaderError::InvalidFormat(format!(
+                "chunk of {} bytes is too small for a chain",
+                self.chunk_len
+            )));
+        }
+
+        let packed = self.read_packed_entry();
+        let num_plies = self.read_plies();
+
+        let entry = match packed.try_unpack_entry() {
+            Ok(entry) => entry,
+            Err(err) => {
+                self.skip_chunk();
+                return Err(err.into());
+            }
+        };
+
+        if let Err(err) = check_stem(&entry, num_plies) {
+            self.skip_chunk();
+            return Err(CompressedReaderError::InvalidFormat(err.to_string()));
+        }
+
+        if num_plies > 0 {
+            self.movelist_reader = Some(PackedMoveScoreListReader::new(entry, num_plies));
+        } else {
+            self.fetch_next_chunk_checked();
+        }
+
+        Ok(entry)
+    }
+
+    /// The annotation of the entry the next read returns, None if it has
+    /// none; see [`crate::annotation`]
+    pub fn next_annotation(&self) -> Option<&Annotation> {
+        if self.is_end {
+            return None;
+        }
 
-            self.movelist_reader = Some(PackedMoveScoreListReader::new(
-                entry,
-                chunk_ref.as_ptr(),
-                num_plies,
-            ));
+        let remaining = match (&self.movelist_reader, &self.packed_chain) {
+            (Some(reader), _) => reader.remaining(),
+            (None, Some((_, plies))) => *plies as usize,
+            (None, None) => 0,
+        };
+        self.annotations.get(self.chunk_entries - remaining)
+    }
+
+    /// Get the next entry and its annotation, empty if it has none
+    pub fn next_annotated(&mut self) -> (TrainingDataEntry, Annotation) {
+        let annotation = self.next_annotation().cloned().unwrap_or_default();
+        (self.next(), annotation)
+    }
+
+    /// Like next_annotated(), checking the data like try_next()
+    pub fn try_next_annotated(&mut self) -> Result<(TrainingDataEntry, Annotation)> {
+        let annotation = self.next_annotation().cloned().unwrap_or_default();
+        Ok((self.try_next()?, annotation))
+    }
+
+    /// Get the stem of the next chain as it is stored, without decompressing
+    /// its position, for decoding only the fields that are needed.
+    ///
+    /// The movetext after the stem is left as it is, see movetext(). Its
+    /// entries are read with next() as usual, which decompresses the stem
+    /// then, or passed over with skip_chain(). Must not be called in the
+    /// middle of a chain, check is_next_entry_continuation() first.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::CompressedTrainingDataEntryReader;
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+    /// let mut plies = Vec::new();
+    ///
+    /// while reader.has_next() {
+    ///     let stem = reader.next_packed();
+    ///     plies.push((stem.ply(), stem.score()));
+    ///     reader.skip_chain();
+    /// }
+    /// assert_eq!(plies.len(), 1);
+    /// ```
+    pub fn next_packed(&mut self) -> PackedTrainingDataEntry {
+        assert!(
+            !self.is_next_entry_continuation(),
+            "next_packed() in the middle of a chain"
+        );
+
+        self.chain = self.current_location();
+
+        // EBNF: Stem
+        let stem = self.read_packed_entry();
+
+        // EBNF: Count
+        let num_plies = self.read_plies();
+
+        if num_plies > 0 {
+            self.packed_chain = Some((stem.clone(), num_plies));
         } else {
             self.fetch_next_chunk_if_needed();
         }
 
-        entry
+        stem
+    }
+
+    /// The entries left in the chain whose stem next_packed() just returned,
+    /// and the bytes of the chunk from the start of its movetext. The format
+    /// stores no length for the movetext, its end is only known once it is
+    /// decoded. None once the movetext is being read.
+    pub fn movetext(&self) -> Option<(u16, &[u8])> {
+        self.packed_chain
+            .as_ref()
+            .map(|&(_, plies)| (plies, &self.chunk[self.offset..self.chunk_len]))
+    }
+
+    /// Get the next entry, leaving the position of a stem compressed until
+    /// it is asked for.
+    ///
+    /// Only a chain of one entry, a stem without movetext, is left packed:
+    /// the entries after a stem are decoded from its position, so it is
+    /// decompressed anyway. Files of positions that do not follow each
+    /// other, e.g. filtered or shuffled ones, are mostly such stems.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::CompressedTrainingDataEntryReader;
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+    ///
+    /// let quiet = reader
+    ///     .lazy_entries()
+    ///     .filter(|entry| entry.score().abs() < 250)
+    ///     .map(|entry| entry.into_entry())
+    ///     .count();
+    /// assert_eq!(quiet, 2);
+    /// ```
+    pub fn next_lazy(&mut self) -> LazyEntry {
+        let count = self.offset + PackedTrainingDataEntry::byte_size();
+        let single = self.movelist_reader.is_none()
+            && self.packed_chain.is_none()
+            && count + 2 <= self.chunk_len
+            && self.chunk[count] == 0
+            && self.chunk[count + 1] == 0;
+
+        if single {
+            LazyEntry::packed(self.next_packed())
+        } else {
+            self.next().into()
+        }
+    }
+
+    /// Iterate over the rest of the entries with next_lazy()
+    pub fn lazy_entries(&mut self) -> LazyEntries<'_, T> {
+        LazyEntries { reader: self }
+    }
+
+    /// Reads the next entry and the entries continuing it, None once there
+    /// are no more entries.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::CompressedTrainingDataEntryReader;
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+    ///
+    /// let game = reader.next_game().unwrap();
+    /// assert_eq!(game.to_uci_line(), "c2c4 d4d3 g2b7");
+    /// assert_eq!(game.to_san_line(game.start_pos().unwrap()), "35. c4+ d3 36. Bxb7");
+    /// assert!(reader.next_game().is_none());
+    /// ```
+    pub fn next_game(&mut self) -> Option<Game> {
+        if !self.has_next() {
+            return None;
+        }
+
+        let mut entries = vec![self.next()];
+        while self.has_next() && self.is_next_entry_continuation() {
+            entries.push(self.next());
+        }
+
+        Some(Game { entries })
+    }
+
+    /// Iterate over the rest of the games with next_game()
+    pub fn games(&mut self) -> Games<'_, T> {
+        Games { reader: self }
+    }
+
+    /// Calls `f` with the score, ply and result of every entry left, without
+    /// returning whole entries.
+    ///
+    /// A stem without movetext is read without decompressing its position.
+    /// The moves of a chain still have to be played to decode its scores,
+    /// as the bits spent on each move depend on the position, but no entry
+    /// is copied out. Assumes well formed data like next().
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use sfbinpack::CompressedTrainingDataEntryReader;
+    ///
+    /// let file = File::open("test/ep1.binpack").unwrap();
+    /// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+    ///
+    /// let mut scores = Vec::new();
+    /// reader.scan_scores(|record| scores.push(record.score));
+    /// assert_eq!(scores, [-201, 254, -220]);
+    /// ```
+    pub fn scan_scores(&mut self, mut f: impl FnMut(ScoreRecord)) {
+        let _ = self.start_packed_chain();
+
+        // the rest of a chain already started
+        if let Some(reader) = self.movelist_reader.take() {
+            self.scan_movetext(reader, &mut f);
+        }
+
+        while !self.is_end {
+            self.chain = self.current_location();
+            let stem = self.read_packed_entry();
+            let num_plies = self.read_plies();
+            f(ScoreRecord {
+                score: stem.score(),
+                ply: stem.ply(),
+                result: stem.result(),
+            });
+
+            if num_plies > 0 {
+                let reader = PackedMoveScoreListReader::new(stem.unpack_entry(), num_plies);
+                self.scan_movetext(reader, &mut f);
+            } else {
+                self.fetch_next_chunk_if_needed();
+            }
+        }
+    }
+
+    /// Calls `f` with the stem and length of every chain left, skipping
+    /// the chain already started.
+    ///
+    /// The entries of a chain follow from its stem: their plies count up
+    /// and their results alternate. Its movetext is still decoded to find
+    /// where the next chain starts, except for stems without movetext,
+    /// whose positions are not decompressed. Assumes well formed data like
+    /// next().
+    pub fn scan_metadata(&mut self, mut f: impl FnMut(ChainRecord)) {
+        self.skip_chain();
+
+        while !self.is_end {
+            self.chain = self.current_location();
+            let stem = self.read_packed_entry();
+            let num_plies = self.read_plies();
+            f(ChainRecord {
+                score: stem.score(),
+                ply: stem.ply(),
+                result: stem.result(),
+                plies: num_plies,
+            });
+
+            if num_plies > 0 {
+                let reader = PackedMoveScoreListReader::new(stem.unpack_entry(), num_plies);
+                self.scan_movetext(reader, &mut |_| {});
+            } else {
+                self.fetch_next_chunk_if_needed();
+            }
+        }
+    }
+
+    /// Decodes the rest of the movetext of `reader`, moving on to the next
+    /// chain
+    fn scan_movetext(
+        &mut self,
+        mut reader: PackedMoveScoreListReader,
+        f: &mut impl FnMut(ScoreRecord),
+    ) {
+        let movetext = &self.chunk[self.offset..self.chunk_len];
+        while reader.has_next() {
+            let entry = reader.next_entry_ref(movetext);
+            f(ScoreRecord {
+                score: entry.score,
+                ply: entry.ply,
+                result: entry.result,
+            });
+        }
+
+        self.offset += reader.num_read_bytes();
+        self.fetch_next_chunk_if_needed();
+    }
+
+    /// Passes over the rest of the current chain, so the next entry is the
+    /// stem of the next one. The movetext still has to be decoded to find
+    /// its end, but no entries are returned.
+    pub fn skip_chain(&mut self) {
+        let _ = self.start_packed_chain();
+
+        let Some(mut reader) = self.movelist_reader.take() else {
+            return;
+        };
+
+        let movetext = &self.chunk[self.offset..self.chunk_len];
+        let mut entry = TrainingDataEntry::empty();
+        while reader.has_next() {
+            reader.next_entry_into(movetext, &mut entry);
+        }
+
+        self.offset += reader.num_read_bytes();
+        self.fetch_next_chunk_if_needed();
+    }
+
+    /// Starts reading the movetext of the chain whose stem next_packed()
+    /// returned, checking the stem like try_next() does
+    fn start_packed_chain(&mut self) -> Result<()> {
+        let Some((stem, num_plies)) = self.packed_chain.take() else {
+            return Ok(());
+        };
+
+        let valid = stem.compressed_position().validate();
+        let stem = stem.unpack_entry();
+        let checked = match valid {
+            Ok(()) => check_stem(&stem, num_plies)
+                .map_err(|err| CompressedReaderError::InvalidFormat(err.to_string())),
+            Err(err) => Err(err.into()),
+        };
+        self.movelist_reader = Some(PackedMoveScoreListReader::new(stem, num_plies));
+        checked
     }
 
-    fn read_entry(&mut self) -> TrainingDataEntry {
+    fn current_location(&self) -> ChainLocation {
+        ChainLocation {
+            chunk: self.chunks_read() - 1,
+            offset: self.chunk_start + self.offset as u64,
+        }
+    }
+
+    fn read_packed_entry(&mut self) -> PackedTrainingDataEntry {
         let size = PackedTrainingDataEntry::byte_size();
 
-        debug_assert!(self.offset + size <= self.chunk.len());
+        debug_assert!(self.offset + size <= self.chunk_len);
 
         let packed =
             PackedTrainingDataEntry::from_slice(&self.chunk[self.offset..self.offset + size]);
 
         self.offset += size;
 
-        packed.unpack_entry()
+        packed
     }
 
     fn read_plies(&mut self) -> u16 {
         let ply = ((self.chunk[self.offset] as u16) << 8) | (self.chunk[self.offset + 1] as u16);
         self.offset += 2;
+        self.chunk_entries += 1 + ply as usize;
         ply
     }
 
     // EBNF: BLOCK
     fn fetch_next_chunk_if_needed(&mut self) {
-        if self.offset + PackedTrainingDataEntry::byte_size() + 2 > self.chunk.len() {
+        self.try_fetch_next_chunk_if_needed().unwrap();
+    }
+
+    fn try_fetch_next_chunk_if_needed(&mut self) -> Result<()> {
+        if self.offset + PackedTrainingDataEntry::byte_size() + 2 > self.chunk_len {
             if self.input_file.as_mut().unwrap().has_next_chunk() {
-                let chunk = self.input_file.as_mut().unwrap().read_next_chunk().unwrap();
-                self.chunk = chunk;
-                self.offset = 0;
+                let location = ChainLocation {
+                    chunk: self.chunks_read(),
+                    offset: self.chunk_start + self.chunk.len() as u64,
+                };
+
+                if let Err(err) = self.load_next_chunk() {
+                    self.is_end = true;
+                    self.chain = location;
+                    return Err(err);
+                }
             } else {
                 self.is_end = true;
             }
         }
+
+        Ok(())
+    }
+
+    /// Like fetch_next_chunk_if_needed(), but keeps the error for the next
+    /// call to try_next() so the entry that was just read is not lost
+    fn fetch_next_chunk_checked(&mut self) {
+        if let Err(err) = self.try_fetch_next_chunk_if_needed() {
+            self.pending_error = Some(err);
+        }
+    }
+
+    fn skip_chunk(&mut self) {
+        self.movelist_reader = None;
+        self.offset = self.chunk_len;
+        self.fetch_next_chunk_checked();
+    }
+
+    fn load_next_chunk(&mut self) -> Result<()> {
+        let input_file = self.input_file.as_mut().unwrap();
+        input_file.read_next_chunk_into(&mut self.chunk)?;
+
+        self.chunk_start = input_file.read_bytes() - self.chunk.len() as u64;
+        self.offset = 0;
+        self.chunk_entries = 0;
+        self.annotations.clear();
+        let annotations = input_file.annotations();
+
+        let (checksum, data_len) = checksum::check(&self.chunk);
+        self.chunk_len = data_len;
+        match checksum {
+            ChunkChecksum::Missing => {}
+            ChunkChecksum::Valid => self.checksummed_chunks += 1,
+            ChunkChecksum::Mismatch { stored, computed } => {
+                self.chunk_len = 0;
+                return Err(BinpackError::ChecksumMismatch { stored, computed }.into());
+            }
+        }
+
+        if let Some(data) = annotations {
+            let (checksum, data_len) = checksum::check(data);
+            let decoded = match checksum {
+                ChunkChecksum::Mismatch { stored, computed } => {
+                    Err(BinpackError::ChecksumMismatch { stored, computed }.into())
+                }
+                _ => annotation::decode_chunk(&data[..data_len]).map_err(|err| {
+                    CompressedReaderError::InvalidFormat(format!("annotations: {err}"))
+                }),
+            };
+
+            match decoded {
+                Ok(annotations) => self.annotations = annotations,
+                Err(err) => {
+                    self.chunk_len = 0;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Score, ply and result of an entry, see
+/// [`CompressedTrainingDataEntryReader::scan_scores`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScoreRecord {
+    pub score: i16,
+    pub ply: u16,
+    pub result: i16,
+}
+
+/// The stem of a chain without its position, and how many entries follow
+/// it, see [`CompressedTrainingDataEntryReader::scan_metadata`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainRecord {
+    pub score: i16,
+    pub ply: u16,
+    pub result: i16,
+    /// Entries in the movetext after the stem
+    pub plies: u16,
+}
+
+/// Iterator over the entries of a reader, see
+/// [`CompressedTrainingDataEntryReader::lazy_entries`]
+#[derive(Debug)]
+pub struct LazyEntries<'a, T: Read + Seek> {
+    reader: &'a mut CompressedTrainingDataEntryReader<T>,
+}
+
+impl<T: Read + Seek> Iterator for LazyEntries<'_, T> {
+    type Item = LazyEntry;
+
+    fn next(&mut self) -> Option<LazyEntry> {
+        self.reader.has_next().then(|| self.reader.next_lazy())
+    }
+}
+
+/// Iterator over the games of a reader, see
+/// [`CompressedTrainingDataEntryReader::games`]
+#[derive(Debug)]
+pub struct Games<'a, T: Read + Seek> {
+    reader: &'a mut CompressedTrainingDataEntryReader<T>,
+}
+
+impl<T: Read + Seek> Iterator for Games<'_, T> {
+    type Item = Game;
+
+    fn next(&mut self) -> Option<Game> {
+        self.reader.next_game()
     }
 }
 
+/// Checks a stem before anything is decoded from it. Its move is only played
+/// if the chain goes on, a stem of its own may carry any move.
+fn check_stem(entry: &TrainingDataEntry, num_plies: u16) -> std::result::Result<(), &'static str> {
+    entry.pos.check_valid()?;
+
+    if num_plies > 0 && !attacks::is_legal(&entry.pos, entry.mv) {
+        return Err("the stem move is not legal but the chain goes on");
+    }
+
+    if entry.ply as u32 + num_plies as u32 > Ply::MAX.get() as u32 {
+        return Err("the chain runs past the largest ply");
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{fs::OpenOptions, io::Cursor};
+    use std::{
+        fs::{File, OpenOptions},
+        io::Cursor,
+    };
 
-    use crate::chess::{
-        coords::Square,
-        piece::Piece,
-        position::Position,
-        r#move::{Move, MoveType},
+    use crate::{
+        chess::{
+            attacks,
+            coords::Square,
+            piece::Piece,
+            position::Position,
+            r#move::{Move, MoveType},
+        },
+        CompressedTrainingDataEntryWriter, WriterOptions,
     };
 
     use super::*;
@@ -281,6 +1048,370 @@ mod tests {
         assert_eq!(entries, expected);
     }
 
+    #[test]
+    fn test_reader_next_into() {
+        let expected = {
+            let file = File::open("./test/ep1.binpack").unwrap();
+            let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+            let mut entries = Vec::new();
+            while reader.has_next() {
+                entries.push(reader.next());
+            }
+            entries
+        };
+
+        let file = File::open("./test/ep1.binpack").unwrap();
+        let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+
+        // whatever the entry held before is overwritten
+        let mut entry = TrainingDataEntry {
+            pos: Position::from_fen("8/8/8/8/8/8/8/K1k5 b - - 12 40").unwrap(),
+            mv: Move::null(),
+            score: 1234,
+            ply: 77,
+            result: 1,
+        };
+        let mut entries = Vec::new();
+
+        while reader.has_next() {
+            reader.next_into(&mut entry);
+            entries.push(entry);
+        }
+
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_reader_next_batch() {
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        let mut expected = Vec::new();
+
+        // two games of 5 plies, so batches end inside and between chains
+        for _ in 0..2 {
+            let mut pos = Position::new();
+            for ply in 0..5 {
+                let mv = attacks::legal_moves(&pos)[0];
+                let entry = TrainingDataEntry {
+                    pos,
+                    mv,
+                    score: ply * 10,
+                    ply: ply as u16,
+                    result: if ply % 2 == 0 { 1 } else { -1 },
+                };
+                writer.write_entry(&entry).unwrap();
+                expected.push(entry);
+                pos.do_move(mv);
+            }
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        for n in [1, 3, 5, 7, 10, 100] {
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+            let mut batch = Vec::new();
+            let mut entries = Vec::new();
+
+            while reader.next_batch(&mut batch, n) > 0 {
+                assert!(batch.len() <= n);
+                entries.extend_from_slice(&batch);
+            }
+
+            assert_eq!(entries, expected, "batches of {}", n);
+            assert!(!reader.has_next());
+        }
+    }
+
+    #[test]
+    fn test_reader_next_packed() {
+        let mut rng = crate::testing::Rng::new(7);
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        let mut expected = Vec::new();
+        for _ in 0..5 {
+            let game = crate::testing::random_game(&mut rng, &Default::default());
+            for entry in &game {
+                writer.write_entry(entry).unwrap();
+            }
+            expected.push(game);
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        // only the stems, one per game
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        for game in &expected {
+            let stem = reader.next_packed();
+            assert_eq!((stem.ply(), stem.score()), (game[0].ply, game[0].score));
+            assert_eq!(stem.result(), game[0].result);
+            assert_eq!(stem.unpack_entry(), game[0]);
+
+            let (plies, movetext) = reader.movetext().unwrap();
+            assert_eq!(plies as usize, game.len() - 1);
+            assert!(!movetext.is_empty());
+            reader.skip_chain();
+            assert!(reader.movetext().is_none());
+        }
+        assert!(!reader.has_next());
+
+        // the rest of a chain read as usual after its stem, checked or not
+        for checked in [false, true] {
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+            for game in &expected {
+                assert_eq!(reader.next_packed().unpack_entry(), game[0]);
+                for entry in &game[1..] {
+                    assert!(reader.is_next_entry_continuation());
+                    let read = if checked {
+                        reader.try_next().unwrap()
+                    } else {
+                        reader.next()
+                    };
+                    assert_eq!(read, *entry);
+                }
+            }
+            assert!(!reader.has_next());
+        }
+    }
+
+    #[test]
+    fn test_reader_lazy_entries() {
+        let mut rng = crate::testing::Rng::new(11);
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        let mut expected = Vec::new();
+        for sparse in [true, false, true] {
+            let game = crate::testing::random_game(&mut rng, &Default::default());
+            // every other ply dropped, each entry is a stem of its own
+            for entry in game.iter().step_by(1 + sparse as usize) {
+                writer.write_entry(entry).unwrap();
+                expected.push((*entry, sparse));
+            }
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let lazy = reader.lazy_entries().collect::<Vec<_>>();
+        assert_eq!(lazy.len(), expected.len());
+
+        for (lazy, &(entry, sparse)) in lazy.iter().zip(&expected) {
+            assert_eq!(lazy.is_decoded(), !sparse);
+            assert_eq!(
+                (lazy.score(), lazy.ply(), lazy.result(), lazy.mv()),
+                (entry.score, entry.ply, entry.result, entry.mv)
+            );
+            assert_eq!(lazy.is_decoded(), !sparse);
+
+            assert_eq!(*lazy.pos(), entry.pos);
+            assert!(lazy.is_decoded());
+            assert_eq!(lazy.clone().into_entry(), entry);
+        }
+    }
+
+    #[test]
+    fn test_reader_scan() {
+        let mut rng = crate::testing::Rng::new(17);
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        for sparse in [false, true, false] {
+            let game = crate::testing::random_game(&mut rng, &Default::default());
+            for entry in game.iter().step_by(1 + sparse as usize) {
+                writer.write_entry(entry).unwrap();
+            }
+        }
+        writer.flush_and_end();
+        let stats = writer.stats();
+        let data = writer.into_inner().unwrap();
+        let reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let records = read_all(reader)
+            .0
+            .iter()
+            .map(|entry| ScoreRecord {
+                score: entry.score,
+                ply: entry.ply,
+                result: entry.result,
+            })
+            .collect::<Vec<_>>();
+
+        // from the start, and from the middle of the first chain
+        for skip in [0, 3] {
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+            for _ in 0..skip {
+                reader.next();
+            }
+            let mut scanned = Vec::new();
+            reader.scan_scores(|record| scanned.push(record));
+            assert_eq!(scanned, records[skip..]);
+            assert!(!reader.has_next());
+        }
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let mut chains = Vec::new();
+        reader.scan_metadata(|chain| chains.push(chain));
+        assert_eq!(chains.len() as u64, stats.chains);
+        let entries = chains.iter().map(|c| 1 + c.plies as u64).sum::<u64>();
+        assert_eq!(entries, stats.entries);
+        assert_eq!(chains[0].ply, records[0].ply);
+
+        // a chain that was started is skipped
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        reader.next();
+        let mut rest = 0;
+        reader.scan_metadata(|_| rest += 1);
+        assert_eq!(rest, chains.len() - 1);
+    }
+
+    /// A source counting its seeks, or failing them like a pipe
+    struct Source {
+        inner: Cursor<Vec<u8>>,
+        seeks: usize,
+        seekable: bool,
+    }
+
+    impl Source {
+        fn new(data: Vec<u8>, seekable: bool) -> Self {
+            Self {
+                inner: Cursor::new(data),
+                seeks: 0,
+                seekable,
+            }
+        }
+    }
+
+    impl std::io::Read for Source {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl std::io::Seek for Source {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.seeks += 1;
+            if !self.seekable {
+                return Err(std::io::ErrorKind::Unsupported.into());
+            }
+            self.inner.seek(pos)
+        }
+    }
+
+    fn read_all<T: std::io::Read + std::io::Seek>(
+        mut reader: CompressedTrainingDataEntryReader<T>,
+    ) -> (Vec<TrainingDataEntry>, T) {
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.try_next().unwrap());
+        }
+        (entries, reader.into_inner().unwrap())
+    }
+
+    #[test]
+    fn test_reader_seeks_once() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap().repeat(3);
+        let (expected, _) =
+            read_all(CompressedTrainingDataEntryReader::new(Cursor::new(data.clone())).unwrap());
+        assert_eq!(expected.len(), 9);
+
+        let reader =
+            CompressedTrainingDataEntryReader::new(Source::new(data.clone(), true)).unwrap();
+        let (entries, source) = read_all(reader);
+        assert_eq!(entries, expected);
+        // the length is measured once, not for every chunk
+        assert!(source.seeks <= 3, "{} seeks", source.seeks);
+
+        let options = ReaderOptions {
+            len_hint: Some(data.len() as u64),
+            ..Default::default()
+        };
+        let reader =
+            CompressedTrainingDataEntryReader::with_options(Source::new(data, true), options)
+                .unwrap();
+        let (entries, source) = read_all(reader);
+        assert_eq!(entries, expected);
+        assert_eq!(source.seeks, 0);
+    }
+
+    #[test]
+    fn test_reader_without_seek() {
+        let one = std::fs::read("./test/ep1.binpack").unwrap();
+        let data = one.repeat(3);
+        let (expected, _) =
+            read_all(CompressedTrainingDataEntryReader::new(Cursor::new(data.clone())).unwrap());
+
+        // the end is found from the missing chunk header
+        let (entries, _) = read_all(
+            CompressedTrainingDataEntryReader::new(Source::new(data.clone(), false)).unwrap(),
+        );
+        assert_eq!(entries, expected);
+
+        // a hint stops the reader before whatever follows the binpack
+        let mut embedded = data.clone();
+        embedded.extend_from_slice(b"trailing data");
+        let options = ReaderOptions {
+            len_hint: Some(data.len() as u64),
+            ..Default::default()
+        };
+        let reader = CompressedTrainingDataEntryReader::with_options(
+            Source::new(embedded.clone(), false),
+            options,
+        )
+        .unwrap();
+        let (entries, _) = read_all(reader);
+        assert_eq!(entries, expected);
+
+        // without it, that data is a damaged chunk
+        let mut reader =
+            CompressedTrainingDataEntryReader::new(Source::new(embedded, false)).unwrap();
+        let mut errors = 0;
+        while reader.has_next() {
+            errors += reader.try_next().is_err() as usize;
+        }
+        assert_eq!(errors, 1);
+
+        // an empty source has no chunk
+        let reader = CompressedTrainingDataEntryReader::new(Source::new(Vec::new(), false));
+        assert!(!reader.unwrap().has_next());
+    }
+
+    #[test]
+    fn test_reader_empty() {
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(Vec::new())).unwrap();
+        assert!(!reader.has_next());
+        assert!(!reader.is_next_entry_continuation());
+        assert_eq!(reader.next_batch(&mut Vec::new(), 10), 0);
+        assert_eq!(reader.chunks_read(), 0);
+        assert!(matches!(
+            reader.try_next(),
+            Err(CompressedReaderError::EndOfFile)
+        ));
+
+        let options = ReaderOptions {
+            error_on_empty: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            CompressedTrainingDataEntryReader::with_options(Cursor::new(Vec::new()), options),
+            Err(CompressedReaderError::EndOfFile)
+        ));
+    }
+
+    #[test]
+    fn test_reader_moved_to_thread() {
+        let file = File::open("./test/ep1.binpack").unwrap();
+        let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+        let first = reader.next();
+
+        // the rest of the file is read on another thread
+        let rest = std::thread::spawn(move || {
+            let mut entries = Vec::new();
+            while reader.has_next() {
+                entries.push(reader.next());
+            }
+            entries
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(first.ply, 68);
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0].ply, 69);
+    }
+
     #[test]
     fn test_reader_big_score_diff() {
         let cursor: Cursor<Vec<u8>> = Cursor::new(Vec::from([
@@ -297,6 +1428,8 @@ mod tests {
             entries.push(entry);
         }
 
+        assert_eq!(reader.chunks_read(), 1);
+
         let expected = vec![
             TrainingDataEntry {
                 pos: Position::from_fen("1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 0 35")
@@ -328,4 +1461,384 @@ mod tests {
 
         assert_eq!(entries, expected);
     }
+
+    fn read_all_checked<T: Read + Seek>(
+        reader: &mut CompressedTrainingDataEntryReader<T>,
+    ) -> Vec<Result<TrainingDataEntry>> {
+        let mut results = Vec::new();
+        while reader.has_next() {
+            results.push(reader.try_next());
+        }
+        results
+    }
+
+    #[test]
+    fn test_reader_try_next() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let mut expected = Vec::new();
+        while reader.has_next() {
+            expected.push(reader.next());
+        }
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let entries = read_all_checked(&mut reader)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(entries, expected);
+        assert_eq!(
+            reader.chain_location(),
+            ChainLocation {
+                chunk: 0,
+                offset: 8
+            }
+        );
+        assert!(matches!(
+            reader.try_next(),
+            Err(CompressedReaderError::EndOfFile)
+        ));
+    }
+
+    #[test]
+    fn test_reader_aligned_chunks() {
+        let mut rng = crate::testing::Rng::new(3);
+        let entries = (0..50)
+            .flat_map(|_| crate::testing::random_game(&mut rng, &Default::default()))
+            .collect::<Vec<_>>();
+
+        let options = WriterOptions {
+            checksums: true,
+            align: 4096,
+            ..Default::default()
+        };
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+        for (idx, entry) in entries.iter().enumerate() {
+            writer.write_entry(entry).unwrap();
+            if idx % 700 == 0 {
+                writer.end_chunk().unwrap();
+            }
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let mut read = Vec::new();
+        while reader.has_next() {
+            let entry = reader.try_next().unwrap();
+            // the header of every chunk is at an aligned offset
+            assert!((reader.chunk_start - 8).is_multiple_of(4096));
+            read.push(entry);
+        }
+        assert_eq!(read, entries);
+        assert!(reader.chunks_read() > 1);
+
+        // the padding is no BINP chunk, upstream readers stop at the first
+        let stop = crate::testing::binp_chunks(&data).unwrap_err();
+        assert_eq!(&data[stop..stop + 4], b"BINZ");
+        assert!(stop < 4096);
+        assert!(crate::testing::binp_chunks(&crate::testing::write_binpack(&entries)).is_ok());
+    }
+
+    #[test]
+    fn test_reader_try_next_invalid_position() {
+        let mut data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        // the queen on b1, the first piece of the stem, becomes a castling rook
+        data[16] = (data[16] & 0xF0) | 13;
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        assert!(matches!(
+            reader.try_next(),
+            Err(CompressedReaderError::InvalidPosition(
+                CompressedPositionError::CastlingRook(Square::B1)
+            ))
+        ));
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        reader.next_packed();
+        assert!(matches!(
+            reader.try_next(),
+            Err(CompressedReaderError::InvalidPosition(_))
+        ));
+    }
+
+    #[test]
+    fn test_reader_try_next_truncated_movetext() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        // cut the movetext of the first chunk short, the second chunk is intact
+        let mut truncated = data[..data.len() - 2].to_vec();
+        truncated[4..8].copy_from_slice(&((data.len() - 10) as u32).to_le_bytes());
+        truncated.extend_from_slice(&data);
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(truncated)).unwrap();
+        let results = read_all_checked(&mut reader);
+
+        let errors = results.iter().filter(|result| result.is_err()).count();
+        let entries = results.iter().filter(|result| result.is_ok()).count();
+
+        // the stem and first move of the truncated chain, then the intact chunk
+        assert_eq!(errors, 1);
+        assert_eq!(entries, 5);
+        assert_eq!(
+            reader.chain_location(),
+            ChainLocation {
+                chunk: 1,
+                offset: data.len() as u64 - 2 + 8
+            }
+        );
+    }
+
+    #[test]
+    fn test_reader_magic_once_dialect() {
+        let one = std::fs::read("./test/ep1.binpack").unwrap();
+        // the same chunk again, without its magic
+        let data = [one.clone(), one[4..].to_vec(), one[4..].to_vec()].concat();
+
+        let options = ReaderOptions {
+            dialect: FormatDialect::Auto,
+            ..Default::default()
+        };
+        let mut reader =
+            CompressedTrainingDataEntryReader::with_options(Cursor::new(data.clone()), options)
+                .unwrap();
+        let results = read_all_checked(&mut reader);
+        assert_eq!(results.len(), 9);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(reader.dialect(), FormatDialect::MagicOnce);
+        assert_eq!(reader.chunks_read(), 3);
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        let results = read_all_checked(&mut reader);
+        assert_eq!(results.len(), 4);
+        assert!(matches!(
+            results[3],
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::InvalidMagic
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut reader =
+            CompressedTrainingDataEntryReader::new(File::open("./test/ep1.binpack").unwrap())
+                .unwrap();
+        let entries = read_all_checked(&mut reader)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        let options = WriterOptions {
+            checksums: true,
+            buffer_size: 0,
+            ..Default::default()
+        };
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+        let plain = std::fs::read("./test/ep1.binpack").unwrap();
+        assert_eq!(data.len(), plain.len() + checksum::TRAILER_SIZE);
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let read = read_all_checked(&mut reader)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, entries);
+        assert_eq!(reader.checksummed_chunks(), 1);
+
+        // a reader that knows nothing of the trailer sees the same chains
+        let mut stripped = data[..data.len() - checksum::TRAILER_SIZE].to_vec();
+        stripped[4..8].copy_from_slice(&((plain.len() - 8) as u32).to_le_bytes());
+        assert_eq!(stripped, plain);
+
+        let mut damaged = data.clone();
+        damaged[40] ^= 0x01;
+        assert!(matches!(
+            CompressedTrainingDataEntryReader::new(Cursor::new(damaged)),
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::ChecksumMismatch { .. }
+            ))
+        ));
+
+        // only the broken chunk fails
+        let mut damaged = [data.clone(), data.clone()].concat();
+        damaged[data.len() + 40] ^= 0x01;
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(damaged)).unwrap();
+        let results = read_all_checked(&mut reader);
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|result| result.is_ok()));
+        assert!(matches!(
+            results[3],
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::ChecksumMismatch { .. }
+            ))
+        ));
+        assert_eq!(reader.chain_location().chunk, 1);
+    }
+
+    #[test]
+    fn test_annotations() {
+        use crate::{
+            analysis::CompressionStats,
+            testing::{binp_chunks, random_game, GameOptions, Rng},
+        };
+
+        let mut rng = Rng::new(21);
+        let games = (0..4)
+            .map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+        let annotated = |game: usize, ply: usize| {
+            game.is_multiple_of(3).then(|| Annotation {
+                depth: Some(ply as u32),
+                nodes: Some(1000 * ply as u64),
+                ..Default::default()
+            })
+        };
+
+        // games 0 and 1 in one chunk, only 0 annotated, 2 in a chunk
+        // without annotations and 3 in one of its own
+        let options = WriterOptions {
+            checksums: true,
+            ..Default::default()
+        };
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+        let mut plain =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+        let mut expected = Vec::new();
+        for (idx, game) in games.iter().enumerate() {
+            for (ply, entry) in game.iter().enumerate() {
+                match annotated(idx, ply) {
+                    Some(annotation) => writer.write_annotated(entry, &annotation).unwrap(),
+                    None => writer.write_entry(entry).unwrap(),
+                }
+                plain.write_entry(entry).unwrap();
+                expected.push((*entry, annotated(idx, ply).unwrap_or_default()));
+            }
+            if idx > 0 {
+                writer.end_chunk().unwrap();
+                plain.end_chunk().unwrap();
+            }
+        }
+        writer.flush_and_end();
+        plain.flush_and_end();
+        let data = writer.into_inner().unwrap();
+        let plain = plain.into_inner().unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let mut read = Vec::new();
+        while reader.has_next() {
+            read.push(reader.try_next_annotated().unwrap());
+        }
+        assert_eq!(read, expected);
+        assert_eq!(reader.chunks_read(), 3);
+        assert_eq!(reader.next_annotation(), None);
+
+        // whatever reads the entries before
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        let mut batch = Vec::new();
+        reader.next_batch(&mut batch, 5);
+        assert_eq!(reader.next_annotated(), expected[5]);
+        reader.skip_chain();
+        let stem = reader.next_packed();
+        assert_eq!(stem.ply(), 0);
+        assert!(reader.next_annotation().unwrap().is_empty());
+        reader.scan_metadata(|_| {});
+        assert!(!reader.has_next());
+
+        // the annotations are the only difference to the plain file
+        let stats = CompressionStats::analyze(Cursor::new(&data)).unwrap();
+        let plain_stats = CompressionStats::analyze(Cursor::new(&plain)).unwrap();
+        assert_eq!(
+            stats.file_bytes - stats.annotation_bytes,
+            plain_stats.file_bytes
+        );
+        assert_eq!(stats.entries, expected.len() as u64);
+        assert_eq!(plain_stats.annotation_bytes, 0);
+
+        // a reader that only knows BINP chunks stops at the extension chunk
+        // in front of the first chunk, the plain file it reads through
+        assert_eq!(binp_chunks(&data), Err(0));
+        assert_eq!(&data[..4], annotation::MAGIC);
+        assert_eq!(binp_chunks(&plain).unwrap().len(), 3);
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&plain)).unwrap();
+        assert_eq!(reader.next_annotation(), None);
+        assert!(reader.next_annotated().1.is_empty());
+
+        // a damaged extension chunk fails its chunk
+        let mut damaged = data.clone();
+        damaged[10] ^= 0x01;
+        assert!(matches!(
+            CompressedTrainingDataEntryReader::new(Cursor::new(damaged)),
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::ChecksumMismatch { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_reader_try_next_broken_header() {
+        let mut data = std::fs::read("./test/ep1.binpack").unwrap();
+        let len = data.len() as u64;
+        data.extend_from_slice(b"BINQ\x01\x00\x00\x00\x00");
+
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        let results = read_all_checked(&mut reader);
+
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|result| result.is_ok()));
+        assert!(matches!(
+            results[3],
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::InvalidMagic
+            ))
+        ));
+        assert_eq!(
+            reader.chain_location(),
+            ChainLocation {
+                chunk: 1,
+                offset: len
+            }
+        );
+        assert!(!reader.has_next());
+    }
+
+    #[test]
+    fn test_reader_try_next_corrupt_input() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        // xorshift, the same corruptions on every run
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+
+        for _ in 0..500 {
+            let mut corrupt = data.clone();
+            for _ in 0..1 + random() % 4 {
+                let i = 8 + random() % (corrupt.len() - 8);
+                corrupt[i] = random() as u8;
+            }
+
+            // anything but a panic, and every entry that is returned is sound
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(corrupt)).unwrap();
+            for entry in read_all_checked(&mut reader).into_iter().flatten() {
+                assert_eq!(entry.pos.check_valid(), Ok(()));
+            }
+        }
+    }
 }
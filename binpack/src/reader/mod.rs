@@ -1,6 +1,38 @@
-mod bitreader;
 mod compressed_reader;
-mod move_score_list_reader;
+#[cfg(unix)]
+mod pread;
+#[cfg(feature = "remote")]
+pub mod remote;
 
+pub use crate::common::compressed_training_file_reader::FormatDialect;
+pub use compressed_reader::ChainLocation;
+pub use compressed_reader::ChainRecord;
 pub use compressed_reader::CompressedReaderError;
 pub use compressed_reader::CompressedTrainingDataEntryReader;
+pub use compressed_reader::Games;
+pub use compressed_reader::LazyEntries;
+pub use compressed_reader::ReaderOptions;
+pub use compressed_reader::ScoreRecord;
+#[cfg(unix)]
+pub use pread::PreadFile;
+
+// The reader owns its chunk buffer and decodes movetext through slices of it,
+// so it can be moved to or shared with another thread whenever its file can
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<CompressedTrainingDataEntryReader<std::fs::File>>();
+    assert_send_sync::<CompressedTrainingDataEntryReader<std::io::Cursor<Vec<u8>>>>();
+    assert_send_sync::<crate::common::move_score_list_reader::PackedMoveScoreListReader>();
+    assert_send_sync::<CompressedReaderError>();
+    assert_send_sync::<crate::LazyEntry>();
+    assert_send_sync::<crate::BufferPool>();
+    #[cfg(unix)]
+    assert_send_sync::<CompressedTrainingDataEntryReader<PreadFile>>();
+    #[cfg(feature = "remote")]
+    assert_send_sync::<CompressedTrainingDataEntryReader<remote::RemoteFile>>();
+    #[cfg(feature = "zstd")]
+    assert_send_sync::<
+        CompressedTrainingDataEntryReader<crate::seekable::SeekableReader<std::fs::File>>,
+    >();
+};
@@ -0,0 +1,343 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Bytes read ahead by default, a few chunks of the reference writer
+pub const DEFAULT_READ_AHEAD: usize = 4 * 1024 * 1024;
+
+/// A file read with positioned reads (`pread`) instead of `read` and `seek`.
+///
+/// The length is taken once when the file is opened, so seeking to the end
+/// costs no system call.
+/// While the reader decodes one window of the file a background thread
+/// already reads the next one, so I/O and decoding overlap; this pays off
+/// most on network file systems with high latency per read. The thread is
+/// started on the first read and serves every window until the file is
+/// dropped.
+///
+/// Reading is sequential in windows of the read-ahead size, a seek
+/// elsewhere drops the window read ahead. The file must not change while
+/// it is read.
+///
+/// # Examples
+///
+/// ```
+/// use sfbinpack::{CompressedTrainingDataEntryReader, PreadFile};
+///
+/// let file = PreadFile::open("test/ep1.binpack").unwrap();
+/// let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+///
+/// while reader.has_next() {
+///     let entry = reader.next();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PreadFile {
+    file: Arc<File>,
+    len: u64,
+    pos: u64,
+    /// The part of the file that was read last
+    window: Vec<u8>,
+    window_start: u64,
+    window_size: usize,
+    read_ahead: Option<ReadAhead>,
+}
+
+/// The thread reading windows ahead, one at a time as they are requested
+#[derive(Debug)]
+struct ReadAhead {
+    /// Start of a window and the buffer to read it into
+    requests: Sender<(u64, Vec<u8>)>,
+    /// Only used through `&mut self`, the lock keeps the file `Sync`
+    windows: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    /// Start of the window requested last, if it is still wanted
+    pending: Option<u64>,
+    /// Windows requested and not yet received, the ones before the last
+    /// were dropped by seeks
+    outstanding: usize,
+}
+
+impl PreadFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    pub fn new(file: File) -> io::Result<Self> {
+        Self::with_read_ahead(file, DEFAULT_READ_AHEAD)
+    }
+
+    /// Reads the file in windows of `window_size` bytes, the next one on a
+    /// background thread; a size of zero reads synchronously, straight into
+    /// the buffers of the caller
+    pub fn with_read_ahead(file: File, window_size: usize) -> io::Result<Self> {
+        Ok(Self {
+            len: file.metadata()?.len(),
+            file: Arc::new(file),
+            pos: 0,
+            window: Vec::new(),
+            window_start: 0,
+            window_size,
+            read_ahead: None,
+        })
+    }
+
+    /// Length of the file when it was opened
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn window_end(&self) -> u64 {
+        self.window_start + self.window.len() as u64
+    }
+
+    /// Makes the window cover `self.pos`, which is before the end of the
+    /// file, and starts reading the window after it
+    fn load_window(&mut self) -> io::Result<()> {
+        let mut spent = std::mem::take(&mut self.window);
+        let (file, len, size) = (&self.file, self.len, self.window_size);
+        let read_ahead = self
+            .read_ahead
+            .get_or_insert_with(|| ReadAhead::spawn(Arc::clone(file), len, size));
+
+        self.window = match read_ahead.receive(self.pos) {
+            Some(window) => window?,
+            // a seek, or the first read: whatever was read ahead is of no
+            // use, this window is read right away
+            None => read_window(file, len, self.pos, std::mem::take(&mut spent), size)?,
+        };
+        self.window_start = self.pos;
+
+        let next = self.window_start + self.window.len() as u64;
+        if next < len {
+            read_ahead.request(next, spent);
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for PreadFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        if self.window_size == 0 {
+            let read = self.file.read_at(buf, self.pos)?;
+            self.pos += read as u64;
+            return Ok(read);
+        }
+
+        if !(self.window_start..self.window_end()).contains(&self.pos) {
+            self.load_window()?;
+        }
+
+        let from = (self.pos - self.window_start) as usize;
+        let read = buf.len().min(self.window.len() - from);
+        buf[..read].copy_from_slice(&self.window[from..from + read]);
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for PreadFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+impl ReadAhead {
+    /// Starts the thread, it stops once the file is dropped
+    fn spawn(file: Arc<File>, len: u64, size: usize) -> Self {
+        let (requests, jobs) = mpsc::channel::<(u64, Vec<u8>)>();
+        let (done, windows) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (start, buffer) in jobs {
+                if done
+                    .send(read_window(&file, len, start, buffer, size))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            windows: Mutex::new(windows),
+            pending: None,
+            outstanding: 0,
+        }
+    }
+
+    /// Starts reading the window at `start` into `buffer`
+    fn request(&mut self, start: u64, buffer: Vec<u8>) {
+        // if the thread is gone, receive reports it
+        let _ = self.requests.send((start, buffer));
+        self.pending = Some(start);
+        self.outstanding += 1;
+    }
+
+    /// Waits for the window at `start` if it was requested last, without
+    /// waiting for windows no longer wanted
+    fn receive(&mut self, start: u64) -> Option<io::Result<Vec<u8>>> {
+        if self.pending.take() != Some(start) {
+            return None;
+        }
+
+        let windows = self.windows.get_mut().unwrap_or_else(|e| e.into_inner());
+        let mut window = Err(io::Error::other("read ahead thread panicked"));
+        while self.outstanding > 0 {
+            self.outstanding -= 1;
+            match windows.recv() {
+                Ok(received) => window = received,
+                Err(_) => {
+                    self.outstanding = 0;
+                    break;
+                }
+            }
+        }
+        Some(window)
+    }
+}
+
+/// Reads up to `size` bytes from `start`, fewer only at the end of the file
+fn read_window(
+    file: &File,
+    len: u64,
+    start: u64,
+    mut buffer: Vec<u8>,
+    size: usize,
+) -> io::Result<Vec<u8>> {
+    let size = size.min((len - start) as usize);
+    buffer.clear();
+    buffer.resize(size, 0);
+    file.read_exact_at(&mut buffer, start)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+    use crate::{
+        chess::{attacks, position::Position},
+        CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter, TrainingDataEntry,
+    };
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_and_seek() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let path = temp_file("pread-seek", &data);
+
+        for window in [0, 1, 7, 64, 4096] {
+            let mut file = PreadFile::with_read_ahead(File::open(&path).unwrap(), window).unwrap();
+            assert_eq!(file.len(), 1000);
+
+            let mut read = Vec::new();
+            file.read_to_end(&mut read).unwrap();
+            assert_eq!(read, data, "window of {}", window);
+
+            let mut buf = [0u8; 10];
+            assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 990);
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[990..]);
+
+            file.seek(SeekFrom::Start(100)).unwrap();
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[100..110]);
+
+            assert_eq!(file.seek(SeekFrom::Current(-5)).unwrap(), 105);
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[105..115]);
+
+            assert!(file.seek(SeekFrom::Current(-200)).is_err());
+            assert_eq!(file.stream_position().unwrap(), 115);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reader_over_pread_file() {
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        for game in 0..50 {
+            let mut pos = Position::new();
+            for ply in 0..40 {
+                let moves = attacks::legal_moves(&pos);
+                if moves.is_empty() {
+                    break;
+                }
+                let mv = moves[(game + ply) % moves.len()];
+                let entry = TrainingDataEntry {
+                    pos,
+                    mv,
+                    score: ply as i16,
+                    ply: ply as u16,
+                    result: 0,
+                };
+                writer.write_entry(&entry).unwrap();
+                pos.do_move(mv);
+            }
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+        let path = temp_file("pread-reader", &data);
+
+        let mut expected = Vec::new();
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        while reader.has_next() {
+            expected.push(reader.next());
+        }
+
+        // windows smaller than a chunk, so chunks span several of them
+        for window in [0, 1000, DEFAULT_READ_AHEAD] {
+            let file = PreadFile::with_read_ahead(File::open(&path).unwrap(), window).unwrap();
+            let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+            let mut entries = Vec::new();
+            while reader.has_next() {
+                entries.push(reader.next());
+            }
+            assert_eq!(entries, expected, "window of {}", window);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,919 @@
+//! Reading binpacks over HTTP and HTTPS, so a dataset on a web server or in
+//! an object store is streamed into training without a local copy first.
+//!
+//! [`RemoteFile`] is `Read + Seek` over HTTP range requests. Like
+//! [`PreadFile`](crate::PreadFile) it reads windows of the file and fetches
+//! the next one on a background thread while the current one is decoded.
+//! Windows are rounded up to whole chunks: a chunk is never split across two
+//! requests, so the reader never waits for two round trips to get one.
+//!
+//! `http://` URLs are fetched with requests of this crate's own over TCP.
+//! The crate has no TLS, `https://` URLs are fetched by running the `curl`
+//! program, which has to be on the `PATH`, once per request. S3 and other
+//! object stores work with a presigned `https://` URL, or with credentials
+//! in [`RemoteOptions::headers`]. Those are passed to curl on its standard
+//! input, so they do not show in the process list, and over plain http are
+//! only sent to a loopback host, such as a local proxy to the store;
+//! credentials never leave the machine unencrypted.
+//!
+//! ```no_run
+//! use sfbinpack::{remote::RemoteFile, CompressedTrainingDataEntryReader};
+//!
+//! let file = RemoteFile::open("https://data.example.org/test80.binpack").unwrap();
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//!
+//! while reader.has_next() {
+//!     let entry = reader.next();
+//! }
+//! ```
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::{TcpStream, ToSocketAddrs},
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::common::compressed_training_file_writer::{
+    CHUNK_HEADER_SIZE, CHUNK_MAGIC, PADDING_MAGIC,
+};
+
+/// Bytes fetched per request by default, a few chunks of the reference writer
+pub const DEFAULT_WINDOW_SIZE: usize = 4 * 1024 * 1024;
+
+/// Options for [`RemoteFile::with_options`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteOptions {
+    /// Bytes fetched per range request, rounded up to the end of the chunk
+    /// the window ends in
+    pub window_size: usize,
+    /// Sent with every request, e.g. `Authorization`. Opening fails for a
+    /// name that is not a token or a value with CR, LF or NUL, and for an
+    /// `http://` URL whose host is not `localhost` or a loopback address
+    /// when there are any, they would cross the network in cleartext.
+    pub headers: Vec<(String, String)>,
+    /// Limit on connecting and on every read from the server, none by default
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        Self {
+            window_size: DEFAULT_WINDOW_SIZE,
+            headers: Vec::new(),
+            timeout: None,
+        }
+    }
+}
+
+/// A binpack on an HTTP or HTTPS server, read with range requests.
+///
+/// The length is taken from the first response, the file must not change
+/// while it is read. Reading is sequential in windows, a seek elsewhere
+/// drops the window read ahead.
+#[derive(Debug)]
+pub struct RemoteFile {
+    source: Arc<Source>,
+    len: u64,
+    pos: u64,
+    /// The part of the file that was fetched last
+    window: Vec<u8>,
+    window_start: u64,
+    read_ahead: Option<ReadAhead>,
+}
+
+/// The window being fetched on a background thread, and where it starts
+#[derive(Debug)]
+struct ReadAhead {
+    start: u64,
+    thread: JoinHandle<io::Result<Vec<u8>>>,
+}
+
+/// Where the file is and how to ask for it
+#[derive(Debug)]
+struct Source {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+    transport: Transport,
+    options: RemoteOptions,
+}
+
+/// How requests reach the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    /// Requests of our own over a TCP connection, for http
+    Tcp,
+    /// The `curl` program, for https
+    Curl,
+}
+
+/// The response curl writes to its standard output. curl is stopped when
+/// it is dropped.
+struct Curl {
+    child: Child,
+    output: BufReader<ChildStdout>,
+}
+
+impl RemoteFile {
+    pub fn open(url: &str) -> io::Result<Self> {
+        Self::with_options(url, RemoteOptions::default())
+    }
+
+    /// Fetches the first window right away, which also tells the length of
+    /// the file
+    pub fn with_options(url: &str, mut options: RemoteOptions) -> io::Result<Self> {
+        options.window_size = options.window_size.max(CHUNK_HEADER_SIZE);
+        Self::with_source(Source::new(url, options)?)
+    }
+
+    fn with_source(source: Source) -> io::Result<Self> {
+        let source = Arc::new(source);
+        let (window, len) = source.get(0, source.options.window_size)?;
+        let mut file = Self {
+            source,
+            len,
+            pos: 0,
+            window,
+            window_start: 0,
+            read_ahead: None,
+        };
+        file.complete_chunks(0)?;
+        file.start_read_ahead();
+
+        Ok(file)
+    }
+
+    /// Length of the file when it was opened
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn window_end(&self) -> u64 {
+        self.window_start + self.window.len() as u64
+    }
+
+    /// Makes the window cover `self.pos`, which is before the end of the
+    /// file, and starts fetching the window after it
+    fn load_window(&mut self) -> io::Result<()> {
+        self.window = match self.read_ahead.take() {
+            Some(read_ahead) if read_ahead.start == self.pos => read_ahead.join()?,
+            // a seek: whatever was read ahead is of no use, this window is
+            // fetched right away
+            _ => self.source.window(self.pos, self.len)?,
+        };
+        self.window_start = self.pos;
+        self.start_read_ahead();
+
+        Ok(())
+    }
+
+    fn start_read_ahead(&mut self) {
+        let next = self.window_end();
+        if next < self.len {
+            let source = Arc::clone(&self.source);
+            let len = self.len;
+            self.read_ahead = Some(ReadAhead {
+                start: next,
+                thread: thread::spawn(move || source.window(next, len)),
+            });
+        }
+    }
+
+    /// Extends the first window to the end of its last chunk
+    fn complete_chunks(&mut self, start: u64) -> io::Result<()> {
+        let window = std::mem::take(&mut self.window);
+        self.window = self.source.extend(window, start, self.len)?;
+        Ok(())
+    }
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        if !(self.window_start..self.window_end()).contains(&self.pos) {
+            self.load_window()?;
+        }
+
+        let from = (self.pos - self.window_start) as usize;
+        let read = buf.len().min(self.window.len() - from);
+        buf[..read].copy_from_slice(&self.window[from..from + read]);
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+impl ReadAhead {
+    fn join(self) -> io::Result<Vec<u8>> {
+        self.thread
+            .join()
+            .map_err(|_| io::Error::other("read ahead thread panicked"))?
+    }
+}
+
+impl Source {
+    fn new(url: &str, options: RemoteOptions) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", url, message))
+        };
+
+        // everything goes into the request as it is, a line break would end
+        // a header early and smuggle in others
+        if url.chars().any(|c| c.is_ascii_control() || c == ' ') {
+            return Err(invalid("control characters or spaces in the URL"));
+        }
+        for (name, value) in &options.headers {
+            if name.is_empty() || name.contains(|c: char| c == ':' || !c.is_ascii_graphic()) {
+                return Err(invalid(&format!("invalid header name {:?}", name)));
+            }
+            if value.contains(['\r', '\n', '\0']) {
+                return Err(invalid(&format!("line break or NUL in header {}", name)));
+            }
+        }
+
+        let (rest, transport, default_port) = match url.split_once("://") {
+            Some(("http", rest)) => (rest, Transport::Tcp, 80),
+            Some(("https", rest)) => (rest, Transport::Curl, 443),
+            _ => return Err(invalid("expected an http:// or https:// URL")),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid("invalid port"))?),
+            None => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        if transport == Transport::Tcp && !options.headers.is_empty() && !is_loopback(host) {
+            return Err(invalid(
+                "headers are only sent over https or to a loopback host, plain http would expose them",
+            ));
+        }
+
+        Ok(Self {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            transport,
+            options,
+        })
+    }
+
+    /// Fetches a window from `start`, which is a chunk boundary, to the end
+    /// of the chunk `window_size` bytes on ends in
+    fn window(&self, start: u64, len: u64) -> io::Result<Vec<u8>> {
+        let (window, _) = self.get(start, self.options.window_size)?;
+        self.extend(window, start, len)
+    }
+
+    /// Appends to a window starting at `start` until it ends on a chunk
+    /// boundary or at the end of the file. A window that does not start
+    /// with a chunk header is left alone.
+    fn extend(&self, mut window: Vec<u8>, start: u64, len: u64) -> io::Result<Vec<u8>> {
+        loop {
+            let end = start + window.len() as u64;
+            let missing = missing_tail(&window).min(len - end);
+            if missing == 0 {
+                return Ok(window);
+            }
+
+            let (tail, _) = self.get(end, missing as usize)?;
+            if tail.is_empty() {
+                return Ok(window);
+            }
+            window.extend_from_slice(&tail);
+        }
+    }
+
+    /// Fetches up to `size` bytes from `start` and returns them with the
+    /// length of the file
+    fn get(&self, start: u64, size: usize) -> io::Result<(Vec<u8>, u64)> {
+        let end = start + size as u64 - 1;
+        let mut response: Box<dyn BufRead> = match self.transport {
+            Transport::Tcp => Box::new(BufReader::new(self.request(start, end)?)),
+            Transport::Curl => Box::new(self.curl(start, end)?),
+        };
+        let (status, headers) = read_head(&mut response)?;
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+
+        let body = || -> io::Result<Vec<u8>> {
+            let mut body = Vec::new();
+            if header("transfer-encoding").is_some_and(|value| value.contains("chunked")) {
+                read_chunked(&mut response, &mut body)?;
+            } else if let Some(length) = header("content-length") {
+                let length = length.parse().map_err(|_| invalid_data("content-length"))?;
+                response.take(length).read_to_end(&mut body)?;
+            } else {
+                response.read_to_end(&mut body)?;
+            }
+            Ok(body)
+        };
+
+        match status {
+            206 => {
+                let total = header("content-range")
+                    .and_then(range_total)
+                    .ok_or_else(|| invalid_data("content-range"))?;
+                Ok((body()?, total))
+            }
+            // the server ignores the range and sends the whole file
+            200 if start == 0 => {
+                let mut body = body()?;
+                let total = body.len() as u64;
+                body.truncate(size);
+                Ok((body, total))
+            }
+            200 => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{}: the server does not support range requests", self.host),
+            )),
+            // a range from the start of an empty file
+            416 => {
+                let total = header("content-range").and_then(range_total).unwrap_or(0);
+                Ok((Vec::new(), total))
+            }
+            status => Err(io::Error::other(format!(
+                "{}{}: HTTP status {}",
+                self.host, self.path, status
+            ))),
+        }
+    }
+
+    /// Sends the request for the bytes from `start` to `end` on a new
+    /// connection
+    fn request(&self, start: u64, end: u64) -> io::Result<TcpStream> {
+        let mut stream = self.connect()?;
+
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: close\r\n",
+            self.path, self.host, start, end
+        );
+        for (name, value) in &self.options.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        Ok(stream)
+    }
+
+    /// Runs curl for the bytes from `start` to `end`. The options go to
+    /// curl on its standard input, not in its arguments, so headers do not
+    /// show in the process list. `raw` leaves the body as the server sent
+    /// it and `include` puts the headers in front, so the response is read
+    /// like one over TCP.
+    fn curl(&self, start: u64, end: u64) -> io::Result<Curl> {
+        let mut config = format!(
+            "url = {}\nrange = \"{}-{}\"\nhttp1.1\nraw\ninclude\nsuppress-connect-headers\nsilent\nshow-error\n",
+            curl_quote(&self.url),
+            start,
+            end
+        );
+        if let Some(timeout) = self.options.timeout {
+            // no read timeout in curl, a transfer that stalls for as long
+            // comes closest
+            config.push_str(&format!(
+                "connect-timeout = {}\nspeed-limit = 1\nspeed-time = {}\n",
+                timeout.as_secs_f64(),
+                timeout.as_secs().max(1)
+            ));
+        }
+        for (name, value) in &self.options.headers {
+            config.push_str(&format!(
+                "header = {}\n",
+                curl_quote(&format!("{}: {}", name, value))
+            ));
+        }
+
+        let mut child = Command::new("curl")
+            .args(["--config", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("https needs curl: {}", err)))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        let curl = Curl { child, output };
+        stdin.write_all(config.as_bytes())?;
+        drop(stdin);
+
+        Ok(curl)
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let timeout = self.options.timeout;
+        let addrs = (self.host.as_str(), self.port).to_socket_addrs()?;
+
+        let mut last_error = None;
+        for addr in addrs {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(timeout)?;
+                    stream.set_write_timeout(timeout)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no address", self.host),
+            )
+        }))
+    }
+}
+
+/// Bytes missing at the end of `window` for its last chunk to be complete,
+/// zero if it ends on a chunk boundary or is not made of chunks
+fn missing_tail(window: &[u8]) -> u64 {
+    let mut pos = 0;
+
+    loop {
+        let rest = &window[pos..];
+        if rest.is_empty() {
+            return 0;
+        }
+        if rest.len() < CHUNK_HEADER_SIZE {
+            return (CHUNK_HEADER_SIZE - rest.len()) as u64;
+        }
+        if &rest[..4] != CHUNK_MAGIC && &rest[..4] != PADDING_MAGIC {
+            return 0;
+        }
+
+        let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let chunk = CHUNK_HEADER_SIZE + size;
+        if chunk > rest.len() {
+            return (chunk - rest.len()) as u64;
+        }
+        pos += chunk;
+    }
+}
+
+impl Curl {
+    /// Waits for curl to end and turns a failure into an error with what
+    /// curl printed
+    fn exited(&mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut message = String::new();
+        if let Some(stderr) = &mut self.child.stderr {
+            let _ = stderr.read_to_string(&mut message);
+        }
+        Err(io::Error::other(format!(
+            "curl failed, {}: {}",
+            status,
+            message.trim()
+        )))
+    }
+}
+
+impl BufRead for Curl {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // the end of the output, a failed transfer has to be told from the
+        // end of the response
+        if self.output.fill_buf()?.is_empty() {
+            self.exited()?;
+        }
+        self.output.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.output.consume(amt);
+    }
+}
+
+impl Read for Curl {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl Drop for Curl {
+    fn drop(&mut self) {
+        // the rest of a response that is not read any further
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// `text` as a quoted string of a curl config file
+fn curl_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid HTTP response: {}", what),
+    )
+}
+
+/// Whether `host` is this machine, so a request to it stays off the network
+fn is_loopback(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|addr| addr.is_loopback())
+}
+
+/// Reads the status line and the headers of a response
+fn read_head(response: &mut impl BufRead) -> io::Result<(u16, Vec<(String, String)>)> {
+    let mut line = String::new();
+    response.read_line(&mut line)?;
+
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid_data("status line"))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if response.read_line(&mut line)? == 0 {
+            return Err(invalid_data("end of headers"));
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok((status, headers));
+        }
+
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid_data(line))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+/// Reads a body sent with `Transfer-Encoding: chunked`
+fn read_chunked(response: &mut impl BufRead, body: &mut Vec<u8>) -> io::Result<()> {
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        response.read_line(&mut line)?;
+        let size = line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid_data("chunk size"))?;
+        if size == 0 {
+            return Ok(());
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        response.read_exact(&mut body[start..])?;
+
+        // the line break after the data
+        line.clear();
+        response.read_line(&mut line)?;
+    }
+}
+
+/// The total length of `bytes 0-99/1234` or `bytes */1234`
+fn range_total(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::{
+        testing::{random_game, write_binpack, GameOptions, Rng},
+        CompressedTrainingDataEntryReader,
+    };
+
+    /// Serves `data` with range requests on a local port, until the test
+    /// ends, and counts the requests. A request with an `X-Forbid` header
+    /// is refused.
+    fn serve(data: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/data.binpack", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut range = None;
+                let mut forbid = false;
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    forbid |= line.starts_with("X-Forbid:");
+                    if let Some(value) = line.trim_end().strip_prefix("Range: bytes=") {
+                        let (start, end) = value.split_once('-').unwrap();
+                        range = Some((
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        ));
+                    }
+                    line.clear();
+                }
+
+                let response = match range {
+                    _ if forbid => b"HTTP/1.1 403 Forbidden\r\n\r\n".to_vec(),
+                    _ if data.is_empty() => {
+                        b"HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */0\r\n\r\n"
+                            .to_vec()
+                    }
+                    Some((start, end)) => {
+                        let end = (end + 1).min(data.len());
+                        let head = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                            start,
+                            end - 1,
+                            data.len(),
+                            end - start
+                        );
+                        [head.as_bytes(), &data[start..end]].concat()
+                    }
+                    None => b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec(),
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn binpack() -> Vec<u8> {
+        let mut rng = Rng::new(3);
+        let entries = (0..200)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+        write_binpack(&entries)
+    }
+
+    #[test]
+    fn test_url() {
+        let source = Source::new("http://localhost:8080/a/b.binpack", Default::default()).unwrap();
+        assert_eq!(
+            (source.host.as_str(), source.port, source.path.as_str()),
+            ("localhost", 8080, "/a/b.binpack")
+        );
+
+        let source = Source::new("http://example.org", Default::default()).unwrap();
+        assert_eq!((source.port, source.path.as_str()), (80, "/"));
+        assert_eq!(source.transport, Transport::Tcp);
+
+        let source = Source::new("https://example.org/x?a=b", Default::default()).unwrap();
+        assert_eq!((source.port, source.path.as_str()), (443, "/x?a=b"));
+        assert_eq!(source.transport, Transport::Curl);
+
+        for url in ["ftp://example.org/x", "example.org/x"] {
+            assert!(Source::new(url, Default::default()).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_headers_only_to_loopback() {
+        let options = RemoteOptions {
+            headers: vec![("Authorization".into(), "Bearer secret".into())],
+            ..Default::default()
+        };
+
+        for url in [
+            "http://localhost:9000/a.binpack",
+            "http://127.0.0.1/a.binpack",
+            "http://127.1.2.3:8080/a.binpack",
+        ] {
+            assert!(Source::new(url, options.clone()).is_ok(), "{url}");
+        }
+
+        for url in [
+            "http://example.org/a.binpack",
+            "http://10.0.0.1:9000/a.binpack",
+            "http://localhost.example.org/a.binpack",
+        ] {
+            let err = Source::new(url, options.clone()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{url}");
+        }
+
+        // without headers any host will do, with https any host gets them
+        assert!(Source::new("http://example.org/a.binpack", Default::default()).is_ok());
+        assert!(Source::new("https://example.org/a.binpack", options).is_ok());
+    }
+
+    #[test]
+    fn test_curl() {
+        if Command::new("curl").arg("--version").output().is_err() {
+            return;
+        }
+
+        let data = binpack();
+        let (url, requests) = serve(data.clone());
+        // curl with the http url of the local server, as it would be run
+        // for an https one
+        let curl = |headers: Vec<(String, String)>| {
+            let options = RemoteOptions {
+                window_size: 1000,
+                headers,
+                timeout: Some(Duration::from_secs(10)),
+            };
+            let mut source = Source::new(&url, options).unwrap();
+            source.transport = Transport::Curl;
+            RemoteFile::with_source(source)
+        };
+
+        let mut file = curl(vec![("X-Token".into(), "a \"quoted\" \\ value".into())]).unwrap();
+        assert_eq!(file.len(), data.len() as u64);
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        assert!(requests.load(Ordering::SeqCst) >= 2);
+
+        // the headers reach the server
+        let err = curl(vec![("X-Forbid".into(), "1".into())]).unwrap_err();
+        assert!(err.to_string().contains("403"), "{err}");
+
+        // a failed transfer is an error with curl's message
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/a.binpack", closed.local_addr().unwrap());
+        drop(closed);
+        let mut source = Source::new(&url, Default::default()).unwrap();
+        source.transport = Transport::Curl;
+        let err = RemoteFile::with_source(source).unwrap_err();
+        assert!(err.to_string().starts_with("curl failed"), "{err}");
+    }
+
+    #[test]
+    fn test_header_injection() {
+        let url = "http://localhost:9000/a.binpack";
+        let with = |name: &str, value: &str| RemoteOptions {
+            headers: vec![(name.into(), value.into())],
+            ..Default::default()
+        };
+
+        assert!(Source::new(url, with("X-Token", "a b:c")).is_ok());
+        for (name, value) in [
+            ("X-Token", "a\r\nHost: evil.example.org"),
+            ("X-Token", "a\nb"),
+            ("X-Token", "a\0b"),
+            ("X-Token\r\nHost", "evil"),
+            ("X-Token: a", "b"),
+            ("X Token", "b"),
+            ("", "b"),
+        ] {
+            let err = Source::new(url, with(name, value)).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                io::ErrorKind::InvalidInput,
+                "{name:?}: {value:?}"
+            );
+        }
+
+        for url in [
+            "http://localhost/a HTTP/1.1\r\nHost: evil\r\n\r\nGET /b",
+            "http://localhost/a\nb",
+        ] {
+            assert!(Source::new(url, Default::default()).is_err(), "{url:?}");
+        }
+    }
+
+    #[test]
+    fn test_missing_tail() {
+        let chunk = |size: u32| {
+            [
+                &CHUNK_MAGIC[..],
+                &size.to_le_bytes(),
+                &vec![0; size as usize],
+            ]
+            .concat()
+        };
+        let two = [chunk(5), chunk(10)].concat();
+
+        assert_eq!(missing_tail(&two), 0);
+        // in the first chunk, then in the header and the data of the second
+        assert_eq!(missing_tail(&two[..10]), 3);
+        assert_eq!(missing_tail(&two[..15]), 6);
+        assert_eq!(missing_tail(&two[..21]), 10);
+        assert_eq!(missing_tail(&two[..13]), 0);
+        assert_eq!(missing_tail(b"not a binpack"), 0);
+    }
+
+    #[test]
+    fn test_reader_over_remote_file() {
+        let data = binpack();
+        let (url, requests) = serve(data.clone());
+
+        let mut expected = Vec::new();
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(&data)).unwrap();
+        while reader.has_next() {
+            expected.push(reader.next());
+        }
+
+        // windows smaller than a chunk are rounded up to it
+        for window_size in [1000, DEFAULT_WINDOW_SIZE] {
+            let options = RemoteOptions {
+                window_size,
+                ..Default::default()
+            };
+            let file = RemoteFile::with_options(&url, options).unwrap();
+            assert_eq!(file.len(), data.len() as u64);
+
+            let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+            let mut entries = Vec::new();
+            while reader.has_next() {
+                entries.push(reader.next());
+            }
+            assert_eq!(entries, expected, "window of {}", window_size);
+        }
+        assert!(requests.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_remote_read_and_seek() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let (url, _) = serve(data.clone());
+
+        let options = RemoteOptions {
+            window_size: 64,
+            ..Default::default()
+        };
+        let mut file = RemoteFile::with_options(&url, options).unwrap();
+
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 990);
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[990..]);
+
+        file.seek(SeekFrom::Start(100)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[100..110]);
+        assert!(file.seek(SeekFrom::Current(-200)).is_err());
+
+        let (url, _) = serve(Vec::new());
+        let mut file = RemoteFile::open(&url).unwrap();
+        assert!(file.is_empty());
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+    }
+}
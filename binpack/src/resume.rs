@@ -0,0 +1,504 @@
+//! Picking up an interrupted conversion where it left off.
+//!
+//! A job that turns one binpack into another, such as rescoring it with an
+//! engine for days, can record how far it got after every chunk of its
+//! input. [`Resume::read_batch`] stops at the end of an input chunk, and
+//! [`Resume::checkpoint`] then ends the output chunk being written and
+//! stores a [`Checkpoint`] in a `.progress` file next to the output: where
+//! the next input chunk starts, how long the output is and the CRC32 of
+//! those bytes. A restarted job opened with [`Resume::open`] checks that the
+//! output still starts with them, cuts off whatever was written after the
+//! checkpoint and reads on from the recorded input chunk.
+//!
+//! ```
+//! use std::fs::File;
+//! use sfbinpack::{resume::Resume, CompressedTrainingDataEntryWriter, ReaderOptions};
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let (input, output) = ("test/ep1.binpack".as_ref(), dir.path().join("out.binpack"));
+//!
+//! let (mut resume, mut reader, file) =
+//!     Resume::open(input, &output, ReaderOptions::default()).unwrap();
+//! let mut writer = CompressedTrainingDataEntryWriter::new(file).unwrap();
+//! let mut batch = Vec::new();
+//!
+//! while reader.has_next() {
+//!     resume.read_batch(&mut reader, &mut batch, 1024);
+//!     for entry in &mut batch {
+//!         entry.score /= 2;
+//!         writer.write_entry(entry).unwrap();
+//!     }
+//!     resume.checkpoint(&mut writer).unwrap();
+//! }
+//!
+//! writer.flush_and_end();
+//! assert_eq!(resume.stats(&writer.stats()).entries, 3);
+//! resume.finish().unwrap();
+//! ```
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    formats::jsonl::{parse_object, Value},
+    CompressedReaderError, CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    CompressedWriterError, FormatDialect, ReaderOptions, TrainingDataEntry, WriterStats,
+};
+
+/// Appended to the name of the output for the name of its progress file
+pub const EXTENSION: &str = "progress";
+
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Reading failed: {0}")]
+    Read(#[from] CompressedReaderError),
+    #[error("Writing failed: {0}")]
+    Write(#[from] CompressedWriterError),
+    #[error("Invalid progress file: {0}")]
+    Parse(String),
+    #[error("{} changed since the last checkpoint", .0.display())]
+    Changed(PathBuf),
+}
+
+type Result<T> = std::result::Result<T, ResumeError>;
+
+/// The progress file of the output at `path`, `path` with `.progress`
+/// appended
+pub fn progress_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+/// How far a job got, everything before it is in the output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Bytes of the input, a different size means a different input
+    pub input_size: u64,
+    /// Offset of the first input chunk not yet converted
+    pub input_offset: u64,
+    /// Entries read from the input before it
+    pub read: u64,
+    /// Bytes of the output, ending with a whole chunk
+    pub output_size: u64,
+    /// CRC32 of those bytes
+    pub output_crc32: u32,
+    /// Entries and chains in the output
+    pub entries: u64,
+    pub chains: u64,
+}
+
+impl Checkpoint {
+    /// Reads the progress file of the output at `path`, None if there is none
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(progress_path(path)) {
+            Ok(text) => Self::parse(&text).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the progress file of the output at `path`, through a
+    /// temporary file so that it is never left half written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let progress = progress_path(path);
+        let mut temporary = progress.clone().into_os_string();
+        temporary.push(".tmp");
+
+        fs::write(&temporary, self.to_json())?;
+        fs::rename(&temporary, &progress)?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut checkpoint = Self::default();
+        let mut seen = 0;
+
+        for (key, value) in parse_object(text.trim()).map_err(ResumeError::Parse)? {
+            let field = match key.as_str() {
+                "input_size" => &mut checkpoint.input_size,
+                "input_offset" => &mut checkpoint.input_offset,
+                "read" => &mut checkpoint.read,
+                "output_size" => &mut checkpoint.output_size,
+                "entries" => &mut checkpoint.entries,
+                "chains" => &mut checkpoint.chains,
+                "output_crc32" => {
+                    checkpoint.output_crc32 = match value {
+                        Value::Number(crc) => u32::try_from(crc).ok(),
+                        _ => None,
+                    }
+                    .ok_or_else(|| ResumeError::Parse("invalid 'output_crc32'".to_string()))?;
+                    seen += 1;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            *field = match value {
+                Value::Number(number) => u64::try_from(number).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| ResumeError::Parse(format!("invalid '{}'", key)))?;
+            seen += 1;
+        }
+
+        if seen < 7 {
+            return Err(ResumeError::Parse("missing fields".to_string()));
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// The checkpoint as a JSON object, one key per line
+    pub fn to_json(&self) -> String {
+        let fields = [
+            ("input_size", self.input_size),
+            ("input_offset", self.input_offset),
+            ("read", self.read),
+            ("output_size", self.output_size),
+            ("output_crc32", self.output_crc32 as u64),
+            ("entries", self.entries),
+            ("chains", self.chains),
+        ];
+
+        let fields = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect::<Vec<_>>();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+}
+
+/// Converts a binpack into another with checkpoints, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct Resume {
+    output: PathBuf,
+    checkpoint: Checkpoint,
+    /// CRC32 of the output up to the checkpoint
+    crc: crc32fast::Hasher,
+    /// Offset of the reader's start in the input
+    base: u64,
+    /// Entries and chains in the output before the job went on
+    written: (u64, u64),
+    /// Entries read so far
+    read: u64,
+    /// Where the next input chunk starts, once read_batch() read to the
+    /// end of one
+    boundary: Option<u64>,
+    /// Whether the input can be read from a chunk other than the first
+    resumable: bool,
+}
+
+impl Resume {
+    /// Starts converting `input` into `output`, from the checkpoint in the
+    /// progress file of `output` if there is one. Returns the reader of
+    /// the input left to convert and the output file to write it to, which
+    /// is created, or cut back to the checkpoint.
+    ///
+    /// Fails with [`ResumeError::Changed`] if the input no longer has the
+    /// size it had or the output no longer starts with the bytes it had at
+    /// the checkpoint.
+    pub fn open(
+        input: &Path,
+        output: &Path,
+        options: ReaderOptions,
+    ) -> Result<(Self, CompressedTrainingDataEntryReader<File>, File)> {
+        let checkpoint = Checkpoint::read(output)?;
+        let mut input_file = File::open(input)?;
+        let input_size = input_file.metadata()?.len();
+
+        let (checkpoint, out) = match checkpoint {
+            Some(checkpoint) => {
+                if checkpoint.input_size != input_size || checkpoint.input_offset > input_size {
+                    return Err(ResumeError::Changed(input.to_path_buf()));
+                }
+
+                let mut out = OpenOptions::new().read(true).write(true).open(output)?;
+                if crc_of(&mut out, checkpoint.output_size)? != Some(checkpoint.output_crc32) {
+                    return Err(ResumeError::Changed(output.to_path_buf()));
+                }
+
+                out.set_len(checkpoint.output_size)?;
+                out.seek(SeekFrom::End(0))?;
+                (checkpoint, out)
+            }
+            None => (
+                Checkpoint {
+                    input_size,
+                    ..Default::default()
+                },
+                File::create(output)?,
+            ),
+        };
+
+        input_file.seek(SeekFrom::Start(checkpoint.input_offset))?;
+        let options = ReaderOptions {
+            len_hint: Some(input_size - checkpoint.input_offset),
+            ..options
+        };
+        let reader = CompressedTrainingDataEntryReader::with_options(input_file, options)?;
+
+        let resume = Self {
+            output: output.to_path_buf(),
+            crc: crc32fast::Hasher::new_with_initial_len(
+                checkpoint.output_crc32,
+                checkpoint.output_size,
+            ),
+            base: checkpoint.input_offset,
+            written: (checkpoint.entries, checkpoint.chains),
+            read: checkpoint.read,
+            boundary: None,
+            resumable: true,
+            checkpoint,
+        };
+        Ok((resume, reader, out))
+    }
+
+    /// The checkpoint the job went on from, or the last one it recorded
+    pub fn last_checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+
+    /// Entries read from the input, including those before the checkpoint
+    /// the job went on from
+    pub fn read(&self) -> u64 {
+        self.read
+    }
+
+    /// Counts of the whole output, `stats` being those of the writer
+    /// since the job went on
+    pub fn stats(&self, stats: &WriterStats) -> WriterStats {
+        WriterStats {
+            entries: self.written.0 + stats.entries,
+            chains: self.written.1 + stats.chains,
+            broken_chains: stats.broken_chains,
+            split_chains: stats.split_chains,
+        }
+    }
+
+    /// Reads up to `n` entries into `batch`, replacing its contents, but
+    /// not past the end of the current input chunk. Returns whether the
+    /// chunk ended, [`checkpoint`](Self::checkpoint) then records the
+    /// progress once the batch is written.
+    pub fn read_batch<T: Read + Seek>(
+        &mut self,
+        reader: &mut CompressedTrainingDataEntryReader<T>,
+        batch: &mut Vec<TrainingDataEntry>,
+        n: usize,
+    ) -> bool {
+        batch.clear();
+        let chunk = reader.chunks_read();
+
+        while batch.len() < n && reader.has_next() {
+            // the next chunk is loaded with the last entry of this one
+            let end = reader.read_bytes();
+            batch.push(reader.next());
+            self.read += 1;
+
+            if reader.chunks_read() != chunk || !reader.has_next() {
+                // a headerless chunk cannot be read on its own
+                self.resumable &= reader.dialect() != FormatDialect::MagicOnce;
+                self.boundary = Some(self.base + end);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Records a checkpoint if the last batch ended an input chunk: ends
+    /// the output chunk `writer` is writing, which must write to the output
+    /// file from [`open`](Self::open), and replaces the progress file.
+    pub fn checkpoint<W: Write>(
+        &mut self,
+        writer: &mut CompressedTrainingDataEntryWriter<W>,
+    ) -> Result<()> {
+        let Some(input_offset) = self.boundary.take() else {
+            return Ok(());
+        };
+        if !self.resumable {
+            return Ok(());
+        }
+
+        writer.end_chunk()?;
+
+        // the CRC of the new bytes, as they are in the file
+        let mut out = File::open(&self.output)?;
+        let size = out.metadata()?.len();
+        let start = self.checkpoint.output_size;
+        out.seek(SeekFrom::Start(start))?;
+        if !update_crc(&mut self.crc, &mut out, size - start)? {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let stats = self.stats(&writer.stats());
+        self.checkpoint = Checkpoint {
+            input_size: self.checkpoint.input_size,
+            input_offset,
+            read: self.read,
+            output_size: size,
+            output_crc32: self.crc.clone().finalize(),
+            entries: stats.entries,
+            chains: stats.chains,
+        };
+        self.checkpoint.write(&self.output)
+    }
+
+    /// Removes the progress file once the job is done
+    pub fn finish(self) -> Result<()> {
+        match fs::remove_file(progress_path(&self.output)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// CRC32 of the first `len` bytes of `file`, None if it is shorter
+fn crc_of(file: &mut File, len: u64) -> io::Result<Option<u32>> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut crc = crc32fast::Hasher::new();
+    Ok(update_crc(&mut crc, file, len)?.then(|| crc.finalize()))
+}
+
+/// Adds the next `len` bytes of `file` to `crc`, false if there are fewer
+fn update_crc(crc: &mut crc32fast::Hasher, file: &mut File, len: u64) -> io::Result<bool> {
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut left = len;
+
+    while left > 0 {
+        let len = left.min(buffer.len() as u64) as usize;
+        let read = file.read(&mut buffer[..len])?;
+        if read == 0 {
+            return Ok(false);
+        }
+        crc.update(&buffer[..read]);
+        left -= read as u64;
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{random_game, write_binpack, GameOptions, Rng};
+
+    /// Halves the scores of `input` into `output`, and when `crash` is
+    /// given stops without finishing after that many checkpoints and a few
+    /// entries more
+    fn halve(input: &Path, output: &Path, crash: Option<usize>) -> Result<WriterStats> {
+        let (mut resume, mut reader, file) = Resume::open(input, output, Default::default())?;
+        let mut writer = CompressedTrainingDataEntryWriter::new(file)?;
+        let mut batch = Vec::new();
+        let mut checkpoints = 0;
+
+        while reader.has_next() {
+            let chunk_ended = resume.read_batch(&mut reader, &mut batch, 100);
+            for entry in &mut batch {
+                entry.score /= 2;
+                writer.write_entry(entry)?;
+            }
+
+            if crash == Some(checkpoints) {
+                writer.flush_and_end();
+                return Ok(resume.stats(&writer.stats()));
+            }
+
+            resume.checkpoint(&mut writer)?;
+            checkpoints += chunk_ended as usize;
+        }
+
+        writer.flush_and_end();
+        let stats = resume.stats(&writer.stats());
+        resume.finish()?;
+        Ok(stats)
+    }
+
+    fn setup() -> (tempfile::TempDir, PathBuf, Vec<TrainingDataEntry>) {
+        let mut rng = Rng::new(21);
+        let games = (0..12)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.binpack");
+        let thirds = games.len() / 3;
+        let data = games
+            .chunks(thirds)
+            .map(write_binpack)
+            .collect::<Vec<_>>()
+            .concat();
+        fs::write(&input, data).unwrap();
+        (dir, input, games)
+    }
+
+    #[test]
+    fn test_resume_after_crash() {
+        let (dir, input, games) = setup();
+
+        let whole = dir.path().join("whole.binpack");
+        let stats = halve(&input, &whole, None).unwrap();
+        assert_eq!(stats.entries, games.len() as u64);
+        assert!(!progress_path(&whole).exists());
+
+        for crash in [0, 1, 2] {
+            let output = dir.path().join(format!("crash{crash}.binpack"));
+            halve(&input, &output, Some(crash)).unwrap();
+            assert_eq!(Checkpoint::read(&output).unwrap().is_some(), crash > 0);
+
+            // twice, the second time from a later checkpoint
+            halve(&input, &output, Some(crash + 1)).unwrap();
+            assert_eq!(halve(&input, &output, None).unwrap(), stats);
+            assert_eq!(fs::read(&output).unwrap(), fs::read(&whole).unwrap());
+            assert!(!progress_path(&output).exists());
+        }
+    }
+
+    #[test]
+    fn test_resume_changed_files() {
+        let (dir, input, _) = setup();
+        let output = dir.path().join("out.binpack");
+
+        halve(&input, &output, Some(2)).unwrap();
+        let checkpoint = Checkpoint::read(&output).unwrap().unwrap();
+        assert_eq!(
+            Checkpoint::parse(&checkpoint.to_json()).unwrap(),
+            checkpoint
+        );
+        assert!(checkpoint.input_offset > 0 && checkpoint.read > 0);
+
+        // a byte of the output before the checkpoint changed
+        let mut data = fs::read(&output).unwrap();
+        data[20] ^= 1;
+        fs::write(&output, &data).unwrap();
+        assert!(matches!(
+            Resume::open(&input, &output, Default::default()),
+            Err(ResumeError::Changed(path)) if path == output
+        ));
+
+        // another input
+        data[20] ^= 1;
+        fs::write(&output, &data).unwrap();
+        let mut other = fs::read(&input).unwrap();
+        other.truncate(other.len() - 1);
+        fs::write(&input, other).unwrap();
+        assert!(matches!(
+            Resume::open(&input, &output, Default::default()),
+            Err(ResumeError::Changed(path)) if path == input
+        ));
+
+        assert!(matches!(
+            Checkpoint::parse("{\"read\": 1}"),
+            Err(ResumeError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+//! SplitMix64, the one generator of the crate where a seed has to give the
+//! same numbers on every platform and in every version: Zobrist keys, splits,
+//! shard orders, the shuffles and samples of binpack-tools and generated test
+//! games.
+
+/// Added to the state at every step
+pub(crate) const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// The output of SplitMix64 for the state `z`
+pub(crate) const fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// SplitMix64, small and fast enough for shuffling and sampling
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GAMMA);
+        mix(self.state)
+    }
+
+    /// Uniform in `0..bound`, `bound` must not be zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // reject the top values that would make the modulo biased
+        let zone = u64::MAX - u64::MAX % bound;
+
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True with the given probability
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+
+    /// Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for idx in (1..items.len()).rev() {
+            let other = self.below(idx as u64 + 1) as usize;
+            items.swap(idx, other);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reproducible() {
+        let mut a = SplitMix64::new(42);
+        let mut b = SplitMix64::new(42);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+
+        // reference value of SplitMix64 seeded with 0
+        assert_eq!(SplitMix64::new(0).next_u64(), 0xE220_A839_7B1D_CDAF);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = SplitMix64::new(7);
+        let mut seen = [false; 5];
+
+        for _ in 0..1000 {
+            let value = rng.below(5);
+            seen[value as usize] = true;
+
+            let float = rng.next_f64();
+            assert!((0.0..1.0).contains(&float));
+        }
+        assert!(seen.iter().all(|seen| *seen));
+
+        let mut items = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        rng.shuffle(&mut items);
+        items.sort();
+        assert_eq!(items, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+}
@@ -0,0 +1,313 @@
+//! Stratified sampling of entries.
+//!
+//! A [`Bucketing`] sorts entries into [`Bucket`]s by game phase, material
+//! balance and king safety, and a [`StratifiedSampler`] keeps a uniform
+//! random sample of up to a fixed number of entries of each bucket, e.g. to
+//! build an evaluation suite where endgames are as common as openings.
+//!
+//! ```
+//! use std::fs::File;
+//! use sfbinpack::{
+//!     sample::{Bucketing, StratifiedSampler},
+//!     CompressedTrainingDataEntryReader,
+//! };
+//!
+//! let file = File::open("test/ep1.binpack").unwrap();
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//!
+//! let mut sampler = StratifiedSampler::new(Bucketing::all(), 1000);
+//! while reader.has_next() {
+//!     sampler.add(reader.next());
+//! }
+//!
+//! for (bucket, stratum) in sampler.strata() {
+//!     println!("{bucket:?}: {} of {}", stratum.entries().len(), stratum.seen());
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    chess::{attacks, bitboard::Bitboard, color::Color, position::Position},
+    filter::simple_eval,
+    TrainingDataEntry,
+};
+
+/// Game phase by [`Position::game_phase`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+impl Phase {
+    /// 18 and more out of
+    /// [`MAX_GAME_PHASE`](crate::chess::position::MAX_GAME_PHASE) is the
+    /// opening, less than 8 the endgame
+    pub fn of(pos: &Position) -> Self {
+        match pos.game_phase() {
+            18.. => Phase::Opening,
+            8.. => Phase::Middlegame,
+            _ => Phase::Endgame,
+        }
+    }
+}
+
+/// Largest material balance, in pawns, with its own bucket
+pub const MAX_MATERIAL_BUCKET: i8 = 3;
+
+/// Material balance by [`simple_eval`] for the side to move, in pawns,
+/// rounded and clamped to `-MAX_MATERIAL_BUCKET..=MAX_MATERIAL_BUCKET`
+pub fn material_bucket(pos: &Position) -> i8 {
+    let eval = match pos.side_to_move() {
+        Color::White => simple_eval(pos),
+        Color::Black => -simple_eval(pos),
+    };
+    let pawns = (eval as f64 / 100.0).round() as i32;
+    pawns.clamp(-MAX_MATERIAL_BUCKET as i32, MAX_MATERIAL_BUCKET as i32) as i8
+}
+
+/// Squares next to or under the king of the side to move that the other
+/// side attacks, 0 to 9
+pub fn king_danger(pos: &Position) -> u32 {
+    let stm = pos.side_to_move();
+    let king = pos.king_sq(stm);
+    let zone = attacks::king(king) | Bitboard::from_square(king);
+
+    zone.iter().filter(|&sq| pos.is_attacked(sq, !stm)).count() as u32
+}
+
+/// King safety buckets of [`king_danger`]: safe, pressed and exposed
+fn king_safety_bucket(pos: &Position) -> u8 {
+    match king_danger(pos) {
+        0 => 0,
+        1..=2 => 1,
+        _ => 2,
+    }
+}
+
+/// What an entry is classified by, a feature that is switched off is the
+/// same for every entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bucket {
+    pub phase: Option<Phase>,
+    /// See [`material_bucket`]
+    pub material: Option<i8>,
+    /// 0 when no square around the king is attacked, 1 for one or two and
+    /// 2 for more, see [`king_danger`]
+    pub king_safety: Option<u8>,
+}
+
+/// Which features entries are bucketed by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bucketing {
+    pub phase: bool,
+    pub material: bool,
+    pub king_safety: bool,
+}
+
+impl Bucketing {
+    /// Buckets by every feature
+    pub fn all() -> Self {
+        Self {
+            phase: true,
+            material: true,
+            king_safety: true,
+        }
+    }
+
+    pub fn bucket(&self, entry: &TrainingDataEntry) -> Bucket {
+        let pos = &entry.pos;
+        Bucket {
+            phase: self.phase.then(|| Phase::of(pos)),
+            material: self.material.then(|| material_bucket(pos)),
+            king_safety: self.king_safety.then(|| king_safety_bucket(pos)),
+        }
+    }
+}
+
+/// The entries of one bucket a [`StratifiedSampler`] has kept
+#[derive(Debug, Clone, Default)]
+pub struct Stratum {
+    entries: Vec<TrainingDataEntry>,
+    seen: u64,
+}
+
+impl Stratum {
+    /// A uniform sample of the entries of the bucket
+    pub fn entries(&self) -> &[TrainingDataEntry] {
+        &self.entries
+    }
+
+    /// Entries of the bucket seen in total
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+}
+
+/// Keeps a uniform random sample of up to `per_bucket` entries of every
+/// bucket, by reservoir sampling, so the input is read once and memory
+/// stays bounded by the number of buckets times `per_bucket`.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler<R = StdRng> {
+    bucketing: Bucketing,
+    per_bucket: usize,
+    quotas: BTreeMap<Bucket, usize>,
+    strata: BTreeMap<Bucket, Stratum>,
+    rng: R,
+}
+
+impl StratifiedSampler {
+    pub fn new(bucketing: Bucketing, per_bucket: usize) -> Self {
+        Self::with_rng(bucketing, per_bucket, StdRng::from_entropy())
+    }
+}
+
+impl<R: Rng> StratifiedSampler<R> {
+    pub fn with_rng(bucketing: Bucketing, per_bucket: usize, rng: R) -> Self {
+        Self {
+            bucketing,
+            per_bucket,
+            quotas: BTreeMap::new(),
+            strata: BTreeMap::new(),
+            rng,
+        }
+    }
+
+    /// Keeps `count` entries of `bucket` instead of `per_bucket`
+    pub fn with_quota(mut self, bucket: Bucket, count: usize) -> Self {
+        self.quotas.insert(bucket, count);
+        self
+    }
+
+    pub fn bucketing(&self) -> &Bucketing {
+        &self.bucketing
+    }
+
+    pub fn add(&mut self, entry: TrainingDataEntry) {
+        let bucket = self.bucketing.bucket(&entry);
+        let quota = self.quotas.get(&bucket).copied().unwrap_or(self.per_bucket);
+        let stratum = self.strata.entry(bucket).or_default();
+
+        stratum.seen += 1;
+        if stratum.entries.len() < quota {
+            stratum.entries.push(entry);
+        } else {
+            let idx = self.rng.gen_range(0..stratum.seen);
+            if let Some(slot) = stratum.entries.get_mut(idx as usize) {
+                *slot = entry;
+            }
+        }
+    }
+
+    /// The buckets seen so far, in order, with what was kept of them
+    pub fn strata(&self) -> impl Iterator<Item = (&Bucket, &Stratum)> {
+        self.strata.iter()
+    }
+
+    /// The kept entries, bucket by bucket
+    pub fn into_entries(self) -> Vec<TrainingDataEntry> {
+        self.strata
+            .into_values()
+            .flat_map(|stratum| stratum.entries)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry_from_fen, random_game, GameOptions, Rng as TestRng};
+
+    #[test]
+    fn test_features() {
+        let start = Position::new();
+        assert_eq!(Phase::of(&start), Phase::Opening);
+        assert_eq!(material_bucket(&start), 0);
+        assert_eq!(king_danger(&start), 0);
+
+        // white is a queen up, black to move, the queen on e1 gives check
+        // and covers e7
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4QK2 b - - 0 1").unwrap();
+        assert_eq!(Phase::of(&pos), Phase::Endgame);
+        assert_eq!(material_bucket(&pos), -MAX_MATERIAL_BUCKET);
+        assert_eq!(king_danger(&pos), 2);
+
+        let pos = Position::from_fen(
+            "r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/3P1N2/PPP2PPP/RNBQK2R w KQkq - 0 1",
+        )
+        .unwrap();
+        assert_eq!(Phase::of(&pos), Phase::Opening);
+        let pos = Position::from_fen("r2qk3/pp3ppp/8/8/8/8/PP3PPP/2RQK2R w - - 0 1").unwrap();
+        assert_eq!(Phase::of(&pos), Phase::Middlegame);
+    }
+
+    #[test]
+    fn test_bucketing() {
+        let queen_up = entry_from_fen("4k3/8/8/8/8/8/8/4QK2 w - - 0 1", "", 0, 0);
+
+        let bucket = Bucketing::all().bucket(&queen_up);
+        assert_eq!(bucket.phase, Some(Phase::Endgame));
+        assert_eq!(bucket.material, Some(MAX_MATERIAL_BUCKET));
+        assert_eq!(bucket.king_safety, Some(0));
+
+        let phase_only = Bucketing {
+            phase: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            phase_only.bucket(&queen_up),
+            Bucket {
+                phase: Some(Phase::Endgame),
+                material: None,
+                king_safety: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stratified_sampler() {
+        let mut rng = TestRng::new(5);
+        let input = (0..40)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect::<Vec<_>>();
+
+        let phases = Bucketing {
+            phase: true,
+            ..Default::default()
+        };
+        let opening = Bucket {
+            phase: Some(Phase::Opening),
+            material: None,
+            king_safety: None,
+        };
+
+        let mut sampler = StratifiedSampler::with_rng(phases, 50, StdRng::seed_from_u64(1))
+            .with_quota(opening, 10);
+        for &entry in &input {
+            sampler.add(entry);
+        }
+
+        let mut seen = 0;
+        for (bucket, stratum) in sampler.strata() {
+            seen += stratum.seen();
+            let quota = if *bucket == opening { 10 } else { 50 };
+            assert_eq!(
+                stratum.entries().len() as u64,
+                stratum.seen().min(quota),
+                "{bucket:?}"
+            );
+            assert!(stratum
+                .entries()
+                .iter()
+                .all(|entry| phases.bucket(entry) == *bucket));
+        }
+        assert_eq!(seen, input.len() as u64);
+
+        let entries = sampler.into_entries();
+        assert!(entries.iter().all(|entry| input.contains(entry)));
+    }
+}
@@ -0,0 +1,546 @@
+//! Binpacks compressed in the zstd seekable format.
+//!
+//! A plain `.zst` stream has to be decompressed from the start, so readers
+//! that jump between chunks, like shuffling and sharding, cannot use it. The
+//! [seekable format] splits the data into independent zstd frames and
+//! appends a seek table in a skippable frame, which any zstd tool still
+//! decompresses as a whole.
+//!
+//! [`SeekableWriter`] cuts its frames at chunk boundaries, so a chunk is
+//! never split across two frames, and [`SeekableReader`] is `Read + Seek`
+//! over the decompressed binpack, decompressing only the frame a read falls
+//! into. Over a [`RemoteFile`](crate::remote::RemoteFile) that fetches just
+//! the compressed frames that are read.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use sfbinpack::{
+//!     seekable::{SeekableReader, SeekableWriter},
+//!     CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+//! };
+//!
+//! let binpack = std::fs::read("test/ep1.binpack").unwrap();
+//!
+//! let mut writer = SeekableWriter::new(Vec::new());
+//! std::io::copy(&mut binpack.as_slice(), &mut writer).unwrap();
+//! let compressed = writer.finish().unwrap();
+//!
+//! let file = SeekableReader::new(Cursor::new(compressed)).unwrap();
+//! assert_eq!(file.len(), binpack.len() as u64);
+//!
+//! let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+//! let mut entries = 0;
+//! while reader.has_next() {
+//!     reader.next();
+//!     entries += 1;
+//! }
+//! assert_eq!(entries, 3);
+//! ```
+//!
+//! [seekable format]: https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::common::compressed_training_file_writer::{
+    CHUNK_HEADER_SIZE, CHUNK_MAGIC, PADDING_MAGIC,
+};
+
+/// Decompressed bytes after which the writer ends a frame at the next chunk
+/// boundary by default
+pub const DEFAULT_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Compression level of the writer by default, zstd's own default
+pub const DEFAULT_LEVEL: i32 = 3;
+
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+/// Number of frames, descriptor and magic at the end of the seek table
+const FOOTER_SIZE: usize = 9;
+const CHECKSUM_FLAG: u8 = 0x80;
+/// Bits of the descriptor that must be zero
+const RESERVED_BITS: u8 = 0x7C;
+
+/// Options for [`SeekableWriter::with_options`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeekableOptions {
+    /// zstd compression level
+    pub level: i32,
+    /// Decompressed bytes after which a frame ends at the next chunk
+    /// boundary. A chunk larger than that gets a frame of its own.
+    pub frame_size: usize,
+}
+
+impl Default for SeekableOptions {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LEVEL,
+            frame_size: DEFAULT_FRAME_SIZE,
+        }
+    }
+}
+
+/// Compresses what is written to it into seekable zstd.
+///
+/// The data is expected to be a binpack and frames end between its chunks.
+/// Anything that does not start with a chunk header is cut into frames of
+/// [`SeekableOptions::frame_size`] instead. Call [`finish`](Self::finish)
+/// once done, it writes the last frame and the seek table.
+pub struct SeekableWriter<W: Write> {
+    inner: W,
+    options: SeekableOptions,
+    compressor: zstd::bulk::Compressor<'static>,
+    buffer: Vec<u8>,
+    /// End of the whole chunks in `buffer`
+    boundary: usize,
+    /// The data is not a binpack, frames end anywhere
+    raw: bool,
+    /// Compressed and decompressed size of the frames written
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_options(inner, SeekableOptions::default())
+    }
+
+    pub fn with_options(inner: W, mut options: SeekableOptions) -> Self {
+        options.frame_size = options.frame_size.max(1);
+        Self {
+            inner,
+            options,
+            compressor: zstd::bulk::Compressor::new(options.level)
+                .expect("zstd compression context"),
+            buffer: Vec::new(),
+            boundary: 0,
+            raw: false,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Frames written so far
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Writes the buffered data as the last frame, then the seek table, and
+    /// returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            let len = self.buffer.len();
+            self.write_frame(len)?;
+        }
+
+        let mut table = Vec::with_capacity(8 + self.frames.len() * 8 + FOOTER_SIZE);
+        let size = self.frames.len() * 8 + FOOTER_SIZE;
+        table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend_from_slice(&(size as u32).to_le_bytes());
+        for &(compressed, decompressed) in &self.frames {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&decompressed.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        self.inner.write_all(&table)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Moves `boundary` past the chunks buffered completely and writes
+    /// frames while there is enough for one
+    fn cut_frames(&mut self) -> io::Result<()> {
+        loop {
+            if self.raw {
+                self.boundary = self.buffer.len();
+            } else {
+                let rest = &self.buffer[self.boundary..];
+                if rest.len() < CHUNK_HEADER_SIZE {
+                    break;
+                }
+                if &rest[..4] != CHUNK_MAGIC && &rest[..4] != PADDING_MAGIC {
+                    self.raw = true;
+                    continue;
+                }
+                let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+                if rest.len() < CHUNK_HEADER_SIZE + size {
+                    break;
+                }
+                self.boundary += CHUNK_HEADER_SIZE + size;
+            }
+
+            if self.boundary >= self.options.frame_size {
+                let len = if self.raw {
+                    self.options.frame_size
+                } else {
+                    self.boundary
+                };
+                self.write_frame(len)?;
+            } else if self.raw {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compresses the first `len` bytes of the buffer into a frame
+    fn write_frame(&mut self, len: usize) -> io::Result<()> {
+        let decompressed = u32::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame over 4 GiB"))?;
+        let frame = self.compressor.compress(&self.buffer[..len])?;
+        self.inner.write_all(&frame)?;
+        self.frames.push((frame.len() as u32, decompressed));
+
+        self.buffer.drain(..len);
+        self.boundary -= len;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.cut_frames()?;
+        Ok(buf.len())
+    }
+
+    /// Flushes the inner writer. Buffered data stays buffered, a frame only
+    /// ends at a chunk boundary.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where a frame is in the compressed and in the decompressed data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    compressed_offset: u64,
+    compressed_size: u32,
+    offset: u64,
+    size: u32,
+}
+
+/// The decompressed data of seekable zstd, `Read + Seek` as if it was the
+/// file itself.
+///
+/// The seek table is read when it is opened, afterwards a read decompresses
+/// the frame it falls into and keeps it until a read leaves it. Checksums
+/// in the seek table are not verified, zstd checks the frames themselves.
+pub struct SeekableReader<R: Read + Seek> {
+    inner: R,
+    decompressor: zstd::bulk::Decompressor<'static>,
+    frames: Vec<Frame>,
+    len: u64,
+    pos: u64,
+    /// Index of the frame in `data`, if any
+    current: Option<usize>,
+    data: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Reads the seek table at the end of `inner`, fails with
+    /// [`io::ErrorKind::InvalidData`] if there is none
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let frames = read_seek_table(&mut inner)?;
+        let len = frames
+            .last()
+            .map_or(0, |frame| frame.offset + frame.size as u64);
+
+        Ok(Self {
+            inner,
+            decompressor: zstd::bulk::Decompressor::new()?,
+            frames,
+            len,
+            pos: 0,
+            current: None,
+            data: Vec::new(),
+            compressed: Vec::new(),
+        })
+    }
+
+    /// Length of the decompressed data
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn load_frame(&mut self, index: usize) -> io::Result<()> {
+        let frame = self.frames[index];
+        self.current = None;
+
+        self.compressed.resize(frame.compressed_size as usize, 0);
+        self.inner.seek(SeekFrom::Start(frame.compressed_offset))?;
+        self.inner.read_exact(&mut self.compressed)?;
+
+        self.data.clear();
+        self.data.reserve(frame.size as usize);
+        self.decompressor
+            .decompress_to_buffer(&self.compressed, &mut self.data)?;
+        if self.data.len() != frame.size as usize {
+            return Err(invalid_data("frame size differs from the seek table"));
+        }
+
+        self.current = Some(index);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let index = match self.current {
+            Some(index)
+                if self.frames[index].offset <= self.pos
+                    && self.pos < self.frames[index].offset + self.frames[index].size as u64 =>
+            {
+                index
+            }
+            _ => {
+                // the last frame starting at or before pos, empty frames
+                // are skipped as they start where the next one does
+                let index = self
+                    .frames
+                    .partition_point(|frame| frame.offset <= self.pos)
+                    - 1;
+                self.load_frame(index)?;
+                index
+            }
+        };
+
+        let from = (self.pos - self.frames[index].offset) as usize;
+        let read = buf.len().min(self.data.len() - from);
+        buf[..read].copy_from_slice(&self.data[from..from + read]);
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.pos)
+    }
+}
+
+/// Whether `inner` ends in a seek table, leaves it at an unspecified
+/// position
+pub fn is_seekable<R: Read + Seek>(inner: &mut R) -> io::Result<bool> {
+    match read_footer(inner) {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// The number of frames and whether the entries have checksums
+fn read_footer<R: Read + Seek>(inner: &mut R) -> io::Result<(u32, bool)> {
+    let len = inner.seek(SeekFrom::End(0))?;
+    if len < (8 + FOOTER_SIZE) as u64 {
+        return Err(invalid_data("too short for a seek table"));
+    }
+
+    let mut footer = [0; FOOTER_SIZE];
+    inner.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+    inner.read_exact(&mut footer)?;
+
+    if u32::from_le_bytes(footer[5..9].try_into().unwrap()) != SEEKABLE_MAGIC {
+        return Err(invalid_data("no seek table"));
+    }
+    let descriptor = footer[4];
+    if descriptor & RESERVED_BITS != 0 {
+        return Err(invalid_data("reserved bits set in the seek table"));
+    }
+
+    let frames = u32::from_le_bytes(footer[..4].try_into().unwrap());
+    Ok((frames, descriptor & CHECKSUM_FLAG != 0))
+}
+
+fn read_seek_table<R: Read + Seek>(inner: &mut R) -> io::Result<Vec<Frame>> {
+    let (count, checksums) = read_footer(inner)?;
+    let entry_size = if checksums { 12 } else { 8 };
+    let table_size = count as u64 * entry_size as u64 + FOOTER_SIZE as u64;
+
+    let len = inner.seek(SeekFrom::End(0))?;
+    let table_start = len
+        .checked_sub(table_size + 8)
+        .ok_or_else(|| invalid_data("seek table larger than the file"))?;
+
+    let mut header = [0; 8];
+    inner.seek(SeekFrom::Start(table_start))?;
+    inner.read_exact(&mut header)?;
+    if u32::from_le_bytes(header[..4].try_into().unwrap()) != SKIPPABLE_MAGIC
+        || u32::from_le_bytes(header[4..].try_into().unwrap()) as u64 != table_size
+    {
+        return Err(invalid_data("seek table not in a skippable frame"));
+    }
+
+    let mut entries = vec![0; table_size as usize - FOOTER_SIZE];
+    inner.read_exact(&mut entries)?;
+
+    let mut frames = Vec::with_capacity(count as usize);
+    let (mut compressed_offset, mut offset) = (0, 0);
+    for entry in entries.chunks_exact(entry_size) {
+        let compressed_size = u32::from_le_bytes(entry[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+        frames.push(Frame {
+            compressed_offset,
+            compressed_size,
+            offset,
+            size,
+        });
+        compressed_offset += compressed_size as u64;
+        offset += size as u64;
+    }
+
+    if compressed_offset != table_start {
+        return Err(invalid_data(
+            "frames do not end where the seek table starts",
+        ));
+    }
+
+    Ok(frames)
+}
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid seekable zstd: {}", what),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        testing::{random_game, write_binpack, GameOptions, Rng},
+        CompressedTrainingDataEntryReader, TrainingDataEntry,
+    };
+
+    /// A binpack of `chunks` chunks of 50 games each
+    fn binpack(chunks: usize) -> (Vec<u8>, Vec<TrainingDataEntry>) {
+        let mut rng = Rng::new(7);
+        let mut data = Vec::new();
+        let mut entries = Vec::new();
+        for _ in 0..chunks {
+            let chunk = (0..50)
+                .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+                .collect::<Vec<_>>();
+            data.extend(write_binpack(&chunk));
+            entries.extend(chunk);
+        }
+        (data, entries)
+    }
+
+    fn compress(data: &[u8], frame_size: usize) -> Vec<u8> {
+        let options = SeekableOptions {
+            frame_size,
+            ..SeekableOptions::default()
+        };
+        let mut writer = SeekableWriter::with_options(Vec::new(), options);
+        // odd writes, so chunk headers are split between them
+        for part in data.chunks(1000) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_seekable_round_trip() {
+        let (data, entries) = binpack(4);
+        let compressed = compress(&data, 1);
+
+        // one frame per chunk, readable as a plain zstd stream too
+        let mut file = SeekableReader::new(Cursor::new(&compressed)).unwrap();
+        assert_eq!(file.frame_count(), 4);
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+        assert_eq!(file.len(), data.len() as u64);
+
+        let mut frame_starts = file.frames.iter().map(|frame| frame.offset as usize);
+        let mut pos = 0;
+        while pos < data.len() {
+            assert_eq!(frame_starts.next(), Some(pos));
+            assert_eq!(&data[pos..pos + 4], CHUNK_MAGIC);
+            pos += 8 + u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        }
+
+        let mut tail = vec![0; 100];
+        file.seek(SeekFrom::End(-100)).unwrap();
+        file.read_exact(&mut tail).unwrap();
+        assert_eq!(tail, data[data.len() - 100..]);
+
+        file.rewind().unwrap();
+        let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+        let mut read = Vec::new();
+        while reader.has_next() {
+            read.push(reader.next());
+        }
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn test_seekable_raw_data() {
+        let data = (0..10_000u32)
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>();
+        let compressed = compress(&data, 4096);
+
+        let mut file = SeekableReader::new(Cursor::new(&compressed)).unwrap();
+        assert_eq!(file.frame_count(), 10);
+
+        let mut middle = vec![0; 8192];
+        file.seek(SeekFrom::Start(3000)).unwrap();
+        file.read_exact(&mut middle).unwrap();
+        assert_eq!(middle, data[3000..3000 + 8192]);
+    }
+
+    #[test]
+    fn test_not_seekable() {
+        let plain = zstd::encode_all(&b"not seekable"[..], 3).unwrap();
+        assert!(!is_seekable(&mut Cursor::new(&plain)).unwrap());
+        assert_eq!(
+            SeekableReader::new(Cursor::new(&plain))
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let empty = SeekableWriter::new(Vec::new()).finish().unwrap();
+        let mut cursor = Cursor::new(&empty);
+        assert!(is_seekable(&mut cursor).unwrap());
+        let mut file = SeekableReader::new(cursor).unwrap();
+        assert!(file.is_empty());
+        assert_eq!(file.read(&mut [0; 8]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,224 @@
+//! Merging binpacks and splitting them into shards.
+//!
+//! Both keep games intact: the writer links consecutive entries into chains,
+//! and a shard only ends where the next entry does not continue the game of
+//! the previous one.
+
+use std::io::{self, Read, Seek, Write};
+
+use crate::{
+    CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter, CompressedWriterError,
+    TrainingDataEntry,
+};
+
+type Result<T> = std::result::Result<T, CompressedWriterError>;
+
+/// Writes all entries of `readers`, one after the other, to `writer` and
+/// returns the number of entries written.
+///
+/// The writer is not flushed, call `flush_and_end` once done with it.
+///
+/// # Examples
+///
+/// ```
+/// use std::{fs::File, io::Cursor};
+/// use sfbinpack::{shard, CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter};
+///
+/// let readers = (0..2).map(|_| {
+///     let file = File::open("test/ep1.binpack").unwrap();
+///     CompressedTrainingDataEntryReader::new(file).unwrap()
+/// });
+///
+/// let mut writer = CompressedTrainingDataEntryWriter::new(Cursor::new(Vec::new())).unwrap();
+/// assert_eq!(shard::merge(readers, &mut writer).unwrap(), 6);
+/// writer.flush_and_end();
+/// ```
+pub fn merge<R, W>(
+    readers: impl IntoIterator<Item = CompressedTrainingDataEntryReader<R>>,
+    writer: &mut CompressedTrainingDataEntryWriter<W>,
+) -> Result<u64>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let mut written = 0;
+
+    for mut reader in readers {
+        while reader.has_next() {
+            writer.write_entry(&reader.next())?;
+            written += 1;
+        }
+    }
+
+    Ok(written)
+}
+
+/// Size of a finished shard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardInfo {
+    pub entries: u64,
+    pub games: u64,
+}
+
+/// Distributes entries over shards of about `entries_per_shard` entries.
+///
+/// A shard is closed once it holds at least `entries_per_shard` entries and
+/// the next entry starts a new game, so shards can be slightly larger than
+/// requested but never cut a game in two. `create` is called with the zero
+/// based index of each new shard.
+pub struct ShardWriter<W: Write, F: FnMut(usize) -> io::Result<W>> {
+    create: F,
+    entries_per_shard: u64,
+    writer: Option<CompressedTrainingDataEntryWriter<W>>,
+    last: Option<TrainingDataEntry>,
+    current: ShardInfo,
+    shards: Vec<ShardInfo>,
+}
+
+impl<W: Write, F: FnMut(usize) -> io::Result<W>> ShardWriter<W, F> {
+    pub fn new(entries_per_shard: u64, create: F) -> Self {
+        Self {
+            create,
+            entries_per_shard: entries_per_shard.max(1),
+            writer: None,
+            last: None,
+            current: ShardInfo::default(),
+            shards: Vec::new(),
+        }
+    }
+
+    pub fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        let new_game = !self.last.is_some_and(|last| last.is_continuation(entry));
+
+        if new_game && self.current.entries >= self.entries_per_shard {
+            self.close_shard();
+        }
+
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => {
+                let output = (self.create)(self.shards.len())?;
+                self.writer
+                    .insert(CompressedTrainingDataEntryWriter::new(output)?)
+            }
+        };
+
+        writer.write_entry(entry)?;
+
+        self.current.entries += 1;
+        if new_game {
+            self.current.games += 1;
+        }
+        self.last = Some(*entry);
+
+        Ok(())
+    }
+
+    /// Closes the last shard and returns the sizes of all shards
+    pub fn finish(mut self) -> Vec<ShardInfo> {
+        self.close_shard();
+        self.shards
+    }
+
+    fn close_shard(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush_and_end();
+            self.shards.push(self.current);
+        }
+
+        self.current = ShardInfo::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs::File, io::Cursor, rc::Rc};
+
+    use super::*;
+
+    fn ep1_entries() -> Vec<TrainingDataEntry> {
+        let file = File::open("./test/ep1.binpack").unwrap();
+        let mut reader = CompressedTrainingDataEntryReader::new(file).unwrap();
+
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    fn read_all(data: Vec<u8>) -> Vec<TrainingDataEntry> {
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    #[test]
+    fn test_merge() {
+        let readers = (0..3).map(|_| {
+            let file = File::open("./test/ep1.binpack").unwrap();
+            CompressedTrainingDataEntryReader::new(file).unwrap()
+        });
+
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        assert_eq!(merge(readers, &mut writer).unwrap(), 9);
+        writer.flush_and_end();
+
+        let entries = read_all(writer.into_inner().unwrap());
+        assert_eq!(entries, ep1_entries().repeat(3));
+    }
+
+    #[test]
+    fn test_shard_writer_keeps_games() {
+        type Buffer = Rc<RefCell<Vec<u8>>>;
+
+        struct Shared(Buffer);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let shards: Rc<RefCell<Vec<Buffer>>> = Rc::default();
+        let created = shards.clone();
+        let mut writer = ShardWriter::new(2, move |idx| {
+            assert_eq!(idx, created.borrow().len());
+            let buffer = Rc::new(RefCell::new(Vec::new()));
+            created.borrow_mut().push(buffer.clone());
+            Ok(Shared(buffer))
+        });
+
+        // three games of three entries, a shard of 2 has to take a whole game
+        let game = ep1_entries();
+        for _ in 0..3 {
+            for entry in &game {
+                writer.write_entry(entry).unwrap();
+            }
+        }
+
+        let info = writer.finish();
+        assert_eq!(
+            info,
+            vec![
+                ShardInfo {
+                    entries: 3,
+                    games: 1
+                };
+                3
+            ]
+        );
+
+        for shard in shards.borrow().iter() {
+            assert_eq!(read_all(shard.borrow().clone()), game);
+        }
+    }
+}
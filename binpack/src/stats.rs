@@ -0,0 +1,454 @@
+//! Summary statistics of datasets and how far two datasets are apart.
+//!
+//! [`DatasetStats`] collects the score and piece count distributions, the
+//! result balance and the positions games pass through in the opening.
+//! [`DatasetStats::compare`] puts two of them side by side with the
+//! Jensen-Shannon divergence and total variation distance of every
+//! distribution, e.g. to check that a filter did not skew the data:
+//!
+//! ```
+//! use std::fs::File;
+//! use sfbinpack::{stats::DatasetStats, CompressedTrainingDataEntryReader};
+//!
+//! let collect = |path| {
+//!     let mut reader = CompressedTrainingDataEntryReader::new(File::open(path).unwrap()).unwrap();
+//!     let mut stats = DatasetStats::new();
+//!     while reader.has_next() {
+//!         stats.add(&reader.next());
+//!     }
+//!     stats
+//! };
+//!
+//! let before = collect("test/ep1.binpack");
+//! let after = collect("test/ep1.binpack");
+//! let comparison = before.compare(&after);
+//! assert_eq!(comparison.scores.jensen_shannon, 0.0);
+//! println!("{comparison}");
+//! ```
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::TrainingDataEntry;
+
+/// Counts of values in buckets of a fixed width
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    width: i64,
+    min: i64,
+    max: i64,
+    counts: BTreeMap<i64, u64>,
+}
+
+impl Histogram {
+    /// Values outside of `min..=max` are counted in the first or last bucket
+    pub fn new(width: i64, min: i64, max: i64) -> Self {
+        Self {
+            width,
+            min,
+            max,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, value: i64) {
+        let bucket = value.clamp(self.min, self.max).div_euclid(self.width) * self.width;
+        *self.counts.entry(bucket).or_default() += 1;
+    }
+
+    /// The non-empty buckets by their lowest value, in order
+    pub fn counts(&self) -> &BTreeMap<i64, u64> {
+        &self.counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// `a..b` for a bucket, `< b` and `>= a` for the ones that take the
+    /// values out of range, the value itself for buckets of width 1
+    pub fn label(&self, bucket: i64) -> String {
+        if self.width == 1 {
+            return bucket.to_string();
+        }
+
+        let end = bucket + self.width;
+        if bucket <= self.min {
+            format!("< {}", end)
+        } else if end > self.max {
+            format!(">= {}", bucket)
+        } else {
+            format!("{}..{}", bucket, end)
+        }
+    }
+}
+
+/// Plies into the game at which positions count towards opening diversity
+pub const OPENING_PLY: u16 = 12;
+
+/// Summary statistics of a dataset, fed entry by entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetStats {
+    pub entries: u64,
+    /// Scores in buckets of 100 from -3000 to 3000
+    pub scores: Histogram,
+    /// Pieces on the board, kings included
+    pub piece_counts: Histogram,
+    /// Losses, draws and wins for the side to move
+    pub results: [u64; 3],
+    /// How often each position, by Zobrist key, occurs at [`OPENING_PLY`]
+    pub openings: BTreeMap<u64, u64>,
+}
+
+impl DatasetStats {
+    pub fn new() -> Self {
+        Self {
+            entries: 0,
+            scores: Histogram::new(100, -3000, 3000),
+            piece_counts: Histogram::new(1, 2, 32),
+            results: [0; 3],
+            openings: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, entry: &TrainingDataEntry) {
+        let pos = &entry.pos;
+        self.entries += 1;
+
+        self.scores.add(entry.score as i64);
+        self.piece_counts.add(pos.occupied().count() as i64);
+        self.results[(entry.result.clamp(-1, 1) + 1) as usize] += 1;
+
+        if entry.ply == OPENING_PLY {
+            *self.openings.entry(pos.zobrist_key()).or_default() += 1;
+        }
+    }
+
+    /// Fraction of the entries the side to move lost, drew and won
+    pub fn result_balance(&self) -> [f64; 3] {
+        self.results.map(|count| fraction(count, self.entries))
+    }
+
+    /// Entropy, in bits, of the positions at [`OPENING_PLY`]: 0 when every
+    /// game goes through the same position, `log2(n)` when `n` positions
+    /// are equally common
+    pub fn opening_entropy(&self) -> f64 {
+        let total = self.openings.values().sum();
+        self.openings
+            .values()
+            .map(|&count| fraction(count, total))
+            .fold(0.0, |entropy, p| entropy - p * p.log2())
+    }
+
+    /// Distinct positions at [`OPENING_PLY`]
+    pub fn distinct_openings(&self) -> usize {
+        self.openings.len()
+    }
+
+    /// Compares the distributions of `self`, the reference, to `other`
+    pub fn compare(&self, other: &Self) -> Comparison {
+        let results = |stats: &Self| (-1..=1).zip(stats.results).collect::<BTreeMap<i64, u64>>();
+
+        let shared = self
+            .openings
+            .iter()
+            .filter(|(key, _)| other.openings.contains_key(key))
+            .map(|(_, count)| count)
+            .sum();
+
+        Comparison {
+            entries: [self.entries, other.entries],
+            scores: Divergence::between(self.scores.counts(), other.scores.counts()),
+            piece_counts: Divergence::between(
+                self.piece_counts.counts(),
+                other.piece_counts.counts(),
+            ),
+            results: Divergence::between(&results(self), &results(other)),
+            openings: Divergence::between(&self.openings, &other.openings),
+            result_balance: [self.result_balance(), other.result_balance()],
+            opening_entropy: [self.opening_entropy(), other.opening_entropy()],
+            distinct_openings: [self.distinct_openings(), other.distinct_openings()],
+            shared_openings: fraction(shared, self.openings.values().sum()),
+        }
+    }
+}
+
+impl Default for DatasetStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extend<TrainingDataEntry> for DatasetStats {
+    fn extend<I: IntoIterator<Item = TrainingDataEntry>>(&mut self, iter: I) {
+        for entry in iter {
+            self.add(&entry);
+        }
+    }
+}
+
+impl FromIterator<TrainingDataEntry> for DatasetStats {
+    fn from_iter<I: IntoIterator<Item = TrainingDataEntry>>(iter: I) -> Self {
+        let mut stats = Self::new();
+        stats.extend(iter);
+        stats
+    }
+}
+
+fn fraction(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// How far apart two distributions are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Divergence {
+    /// Jensen-Shannon divergence in bits, 0 for equal distributions and 1
+    /// for distributions that share no bucket
+    pub jensen_shannon: f64,
+    /// Largest difference in probability that any set of buckets gets,
+    /// from 0 to 1
+    pub total_variation: f64,
+}
+
+impl Divergence {
+    /// Compares two histograms given as counts per bucket. An empty
+    /// histogram is as far from every other one as can be, and equal to
+    /// another empty one.
+    pub fn between<K: Ord>(a: &BTreeMap<K, u64>, b: &BTreeMap<K, u64>) -> Self {
+        let (total_a, total_b) = (a.values().sum::<u64>(), b.values().sum::<u64>());
+        if total_a == 0 || total_b == 0 {
+            let distance = if total_a == total_b { 0.0 } else { 1.0 };
+            return Self {
+                jensen_shannon: distance,
+                total_variation: distance,
+            };
+        }
+
+        let probabilities = a
+            .iter()
+            .map(|(key, &count)| (count, b.get(key).copied().unwrap_or(0)))
+            .chain(
+                b.iter()
+                    .filter(|(key, _)| !a.contains_key(key))
+                    .map(|(_, &count)| (0, count)),
+            )
+            .map(|(count_a, count_b)| (fraction(count_a, total_a), fraction(count_b, total_b)));
+
+        // p * log2(p / m), 0 for p = 0
+        let relative = |p: f64, m: f64| if p > 0.0 { p * (p / m).log2() } else { 0.0 };
+
+        let (mut jensen_shannon, mut total_variation) = (0.0, 0.0);
+        for (p, q) in probabilities {
+            let m = (p + q) / 2.0;
+            jensen_shannon += (relative(p, m) + relative(q, m)) / 2.0;
+            total_variation += (p - q).abs() / 2.0;
+        }
+
+        Self {
+            // rounding can push either a hair out of range
+            jensen_shannon: jensen_shannon.clamp(0.0, 1.0),
+            total_variation: total_variation.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Two datasets side by side, made by [`DatasetStats::compare`]. Pairs are
+/// the reference first and the other dataset second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub entries: [u64; 2],
+    pub scores: Divergence,
+    pub piece_counts: Divergence,
+    pub results: Divergence,
+    /// Of the positions at [`OPENING_PLY`]
+    pub openings: Divergence,
+    /// See [`DatasetStats::result_balance`]
+    pub result_balance: [[f64; 3]; 2],
+    /// See [`DatasetStats::opening_entropy`]
+    pub opening_entropy: [f64; 2],
+    pub distinct_openings: [usize; 2],
+    /// Fraction of the reference's opening positions that the other
+    /// dataset has too
+    pub shared_openings: f64,
+}
+
+impl Comparison {
+    /// The largest Jensen-Shannon divergence of any of the distributions
+    pub fn max_divergence(&self) -> f64 {
+        [self.scores, self.piece_counts, self.results, self.openings]
+            .iter()
+            .map(|divergence| divergence.jensen_shannon)
+            .fold(0.0, f64::max)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b] = self.entries;
+        writeln!(f, "{:<18} {:>12} {:>12}", "", "a", "b")?;
+        writeln!(f, "{:<18} {:>12} {:>12}", "entries", a, b)?;
+
+        for (idx, name) in ["losses", "draws", "wins"].iter().enumerate() {
+            writeln!(
+                f,
+                "{:<18} {:>11.2}% {:>11.2}%",
+                name,
+                100.0 * self.result_balance[0][idx],
+                100.0 * self.result_balance[1][idx]
+            )?;
+        }
+
+        let [a, b] = self.distinct_openings;
+        writeln!(f, "{:<18} {:>12} {:>12}", "distinct openings", a, b)?;
+        let [a, b] = self.opening_entropy;
+        writeln!(f, "{:<18} {:>12.3} {:>12.3}", "opening entropy", a, b)?;
+        writeln!(
+            f,
+            "{:<18} {:>12} {:>11.2}%",
+            "shared openings",
+            "",
+            100.0 * self.shared_openings
+        )?;
+
+        writeln!(f)?;
+        writeln!(f, "{:<18} {:>12} {:>12}", "", "js div", "tv dist")?;
+        for (name, divergence) in [
+            ("score", self.scores),
+            ("pieces", self.piece_counts),
+            ("result", self.results),
+            ("openings", self.openings),
+        ] {
+            writeln!(
+                f,
+                "{:<18} {:>12.4} {:>12.4}",
+                name, divergence.jensen_shannon, divergence.total_variation
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::{position::Position, r#move::Move},
+        testing::{random_game, GameOptions, Rng},
+    };
+
+    #[test]
+    fn test_histogram_tails() {
+        let mut histogram = Histogram::new(100, -3000, 3000);
+        for value in [-32000, -3000, -2950, 0, 99, 3000, 32000] {
+            histogram.add(value);
+        }
+
+        let labels = histogram
+            .counts()
+            .iter()
+            .map(|(bucket, count)| (histogram.label(*bucket), *count))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            labels,
+            [
+                ("< -2900".to_string(), 3),
+                ("0..100".to_string(), 2),
+                (">= 3000".to_string(), 2)
+            ]
+        );
+        assert_eq!(histogram.total(), 7);
+    }
+
+    #[test]
+    fn test_divergence() {
+        let counts = |pairs: &[(i64, u64)]| pairs.iter().copied().collect::<BTreeMap<_, _>>();
+
+        let a = counts(&[(0, 10), (1, 30)]);
+        let same = Divergence::between(&a, &counts(&[(0, 1), (1, 3)]));
+        assert_eq!(same.jensen_shannon, 0.0);
+        assert_eq!(same.total_variation, 0.0);
+
+        let disjoint = Divergence::between(&a, &counts(&[(2, 5)]));
+        assert!((disjoint.jensen_shannon - 1.0).abs() < 1e-12);
+        assert!((disjoint.total_variation - 1.0).abs() < 1e-12);
+
+        // half of the mass moves
+        let half = Divergence::between(&counts(&[(0, 1)]), &counts(&[(0, 1), (1, 1)]));
+        assert!((half.total_variation - 0.5).abs() < 1e-12);
+        assert!(half.jensen_shannon > 0.0 && half.jensen_shannon < 0.5);
+
+        let empty = BTreeMap::new();
+        assert_eq!(Divergence::between(&a, &empty).jensen_shannon, 1.0);
+        assert_eq!(Divergence::between(&empty, &empty).jensen_shannon, 0.0);
+    }
+
+    fn dataset(seed: u64, games: usize) -> Vec<TrainingDataEntry> {
+        let mut rng = Rng::new(seed);
+        (0..games)
+            .flat_map(|_| random_game(&mut rng, &GameOptions::default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare_with_itself() {
+        let stats = dataset(3, 30).into_iter().collect::<DatasetStats>();
+        let comparison = stats.compare(&stats);
+
+        assert_eq!(comparison.max_divergence(), 0.0);
+        assert_eq!(comparison.shared_openings, 1.0);
+        assert_eq!(comparison.entries[0], comparison.entries[1]);
+        assert!(comparison.to_string().contains("js div"));
+    }
+
+    #[test]
+    fn test_compare_skewed() {
+        let input = dataset(3, 30);
+        let all = input.iter().copied().collect::<DatasetStats>();
+        // a filter that drops every decided game
+        let draws = input
+            .iter()
+            .filter(|entry| entry.result == 0)
+            .copied()
+            .collect::<DatasetStats>();
+
+        let comparison = all.compare(&draws);
+        assert_eq!(comparison.result_balance[1], [0.0, 1.0, 0.0]);
+        assert!(comparison.results.total_variation > 0.0);
+        assert!(comparison.max_divergence() >= comparison.results.jensen_shannon);
+    }
+
+    #[test]
+    fn test_openings() {
+        let mut stats = DatasetStats::new();
+        let pos = Position::new();
+        let entry = TrainingDataEntry {
+            pos,
+            mv: Move::from_uci(&pos, "e2e4").unwrap(),
+            score: 0,
+            ply: OPENING_PLY,
+            result: 0,
+        };
+        stats.add(&entry);
+        stats.add(&entry);
+        assert_eq!(stats.distinct_openings(), 1);
+        assert_eq!(stats.opening_entropy(), 0.0);
+
+        stats.add(&TrainingDataEntry {
+            pos: pos.after_move(entry.mv),
+            ..entry
+        });
+        stats.add(&TrainingDataEntry {
+            ply: OPENING_PLY + 1,
+            ..entry
+        });
+        assert_eq!(stats.distinct_openings(), 2);
+        // 2 of 3 and 1 of 3
+        let entropy = -(2.0 / 3.0 * (2.0f64 / 3.0).log2() + 1.0 / 3.0 * (1.0f64 / 3.0).log2());
+        assert!((stats.opening_entropy() - entropy).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,121 @@
+//! Spans and events for the `tracing` feature.
+//!
+//! With the feature the reader, writer and pipeline report chunk reads,
+//! decoded batches, transform stages and written chunks to whatever
+//! `tracing` subscriber the application installs, all at debug level. The
+//! span names are
+//!
+//! - `read_chunk`: a chunk read from the file, with its `offset`
+//! - `decode_batch`: [`next_batch`](crate::CompressedTrainingDataEntryReader::next_batch)
+//!   decoding up to `max` entries
+//! - `transform`: a [`Transform`](crate::pipeline::Transform) of a pipeline
+//!   applied to a batch, with its `name`
+//! - `write_chunk`: a chunk appended by the writer, with its `entries` and
+//!   `bytes`, or `copied` as it was read
+//! - `flush`: buffered chunks written to the file
+//!
+//! Without the feature the macros expand to nothing, the fields are not even
+//! evaluated.
+
+/// Enters a debug span until the end of the enclosing block
+macro_rules! trace_span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name $(, $($fields)*)?).entered();
+    };
+}
+
+/// Records a debug event in the current span
+macro_rules! trace_event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($args)*);
+    };
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        io::Cursor,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use tracing::{span, Event, Metadata, Subscriber};
+
+    use crate::{
+        filter::SkipCaptures,
+        pipeline::Pipeline,
+        testing::{random_game, GameOptions, Rng},
+        CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    };
+
+    /// Collects the names of the spans created
+    struct Names {
+        spans: Arc<Mutex<Vec<&'static str>>>,
+        next_id: AtomicU64,
+    }
+
+    impl Subscriber for Names {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            self.spans.lock().unwrap().push(span.metadata().name());
+            span::Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_pipeline_spans() {
+        let mut rng = Rng::new(1);
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        for _ in 0..10 {
+            for entry in random_game(&mut rng, &GameOptions::default()) {
+                writer.write_entry(&entry).unwrap();
+            }
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = Names {
+            spans: spans.clone(),
+            next_id: AtomicU64::new(0),
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+            let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+            Pipeline::new()
+                .filter(SkipCaptures)
+                .run(&mut reader, &mut writer)
+                .unwrap();
+            writer.flush_and_end();
+        });
+
+        let spans = spans.lock().unwrap();
+        for name in [
+            "read_chunk",
+            "decode_batch",
+            "transform",
+            "write_chunk",
+            "flush",
+        ] {
+            assert!(spans.contains(&name), "no {name} span in {spans:?}");
+        }
+    }
+}
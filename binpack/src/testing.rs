@@ -0,0 +1,432 @@
+//! Random legal games and a writer/reader round trip, for property tests of
+//! code built on the format.
+//!
+//! Games come from the move generator, so every chain is one the writer
+//! encodes as continuations. Everything is driven by a seeded [`Rng`], a
+//! failing case is reproduced by running it again with the seed [`check`]
+//! reports.
+//!
+//! ```
+//! use sfbinpack::testing::{check, random_game, round_trip, GameOptions};
+//!
+//! check(10, 0x5EED, |rng| {
+//!     let game = random_game(rng, &GameOptions::default());
+//!     round_trip(&[game])
+//! });
+//! ```
+
+use std::{fmt, io::Cursor};
+
+use thiserror::Error;
+
+use crate::{
+    chess::{
+        attacks,
+        position::Position,
+        r#move::{Move, MoveType},
+    },
+    CompressedReaderError, CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter,
+    CompressedWriterError, Ply, TrainingDataEntry,
+};
+
+/// The same seed gives the same games on every platform
+pub use crate::rng::SplitMix64 as Rng;
+
+/// How random_game() plays
+#[derive(Debug, Clone)]
+pub struct GameOptions {
+    /// Position the game starts from
+    pub start: Position,
+    /// Ply of the first entry
+    pub start_ply: u16,
+    /// The game stops after this many entries if it is not over before,
+    /// or when the ply would pass [`Ply::MAX`]
+    pub max_plies: usize,
+    /// Chance of playing a promotion, en passant capture or castling move
+    /// when the position has one, instead of a uniformly chosen move
+    pub special_moves: f64,
+    /// Chance of a score from the edges of the range instead of a typical
+    /// evaluation
+    pub extreme_scores: f64,
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self {
+            start: Position::new(),
+            start_ply: 0,
+            max_plies: 300,
+            special_moves: 0.5,
+            extreme_scores: 0.05,
+        }
+    }
+}
+
+/// Entries of a random legal game, each one the continuation of the one
+/// before. The game ends at checkmate or stalemate or at the limits of
+/// `options`, so it can be empty.
+pub fn random_game(rng: &mut Rng, options: &GameOptions) -> Vec<TrainingDataEntry> {
+    let mut pos = options.start;
+    let max_plies = options
+        .max_plies
+        .min((Ply::MAX.get() as usize + 1).saturating_sub(options.start_ply as usize));
+
+    // the result of the side to move in the first position
+    let mut result = rng.below(3) as i16 - 1;
+    let mut entries = Vec::new();
+
+    while entries.len() < max_plies {
+        let moves = attacks::legal_moves(&pos);
+        if moves.is_empty() {
+            break;
+        }
+
+        let special = moves
+            .iter()
+            .filter(|mv| mv.mtype() != MoveType::Normal)
+            .copied()
+            .collect::<Vec<Move>>();
+
+        let mv = if !special.is_empty() && rng.chance(options.special_moves) {
+            special[rng.below(special.len() as u64) as usize]
+        } else {
+            moves[rng.below(moves.len() as u64) as usize]
+        };
+
+        let score = if rng.chance(options.extreme_scores) {
+            extreme_score(rng)
+        } else {
+            rng.below(2001) as i16 - 1000
+        };
+
+        entries.push(TrainingDataEntry {
+            pos,
+            mv,
+            score,
+            ply: options.start_ply + entries.len() as u16,
+            result,
+        });
+
+        pos.do_move(mv);
+        result = -result;
+    }
+
+    entries
+}
+
+/// A score at or near the ends of the range, or one of the values engines
+/// use for mates and for "no score"
+pub fn extreme_score(rng: &mut Rng) -> i16 {
+    const SCORES: [i16; 8] = [
+        i16::MIN,
+        i16::MIN + 1,
+        -32002,
+        -32000,
+        32000,
+        32002,
+        32001,
+        32767,
+    ];
+
+    match rng.below(3) {
+        0 => SCORES[rng.below(SCORES.len() as u64) as usize],
+        1 => i16::MIN.wrapping_add(rng.below(64) as i16),
+        _ => i16::MAX.wrapping_sub(rng.below(64) as i16),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RoundTripError {
+    #[error("Writing entry {index} failed: {source}")]
+    Write {
+        index: usize,
+        source: CompressedWriterError,
+    },
+
+    #[error("Reading entry {index} failed: {source}")]
+    Read {
+        index: usize,
+        source: CompressedReaderError,
+    },
+
+    #[error("Entry {index} came back different:\n  written: {written}\n  read:    {read}")]
+    Mismatch {
+        index: usize,
+        written: Box<TrainingDataEntry>,
+        read: Box<TrainingDataEntry>,
+    },
+
+    #[error("{written} entries were written but {read} read back")]
+    Count { written: usize, read: usize },
+}
+
+/// The entries of a game from the start position, one per move in UCI,
+/// scored 0 and won by white. Panics on a move that is not legal.
+pub fn game_from_uci(moves: &[&str]) -> Vec<TrainingDataEntry> {
+    let mut pos = Position::new();
+    let mut entries = Vec::with_capacity(moves.len());
+
+    for (ply, uci) in moves.iter().enumerate() {
+        let mv = Move::from_uci(&pos, uci)
+            .unwrap_or_else(|| panic!("{uci} is not a legal move at ply {ply}"));
+        entries.push(TrainingDataEntry {
+            pos,
+            mv,
+            score: 0,
+            ply: ply as u16,
+            result: if ply % 2 == 0 { 1 } else { -1 },
+        });
+        pos.do_move(mv);
+    }
+
+    entries
+}
+
+/// The entry of the position in `fen` with the move in UCI, none if `uci`
+/// is empty, and the ply of the FEN's move number. Panics on a FEN or a
+/// move that does not parse.
+pub fn entry_from_fen(fen: &str, uci: &str, score: i16, result: i16) -> TrainingDataEntry {
+    let pos = Position::from_fen(fen).unwrap_or_else(|err| panic!("{fen}: {err:?}"));
+    let mv = match uci {
+        "" => Move::null(),
+        uci => Move::from_uci(&pos, uci)
+            .unwrap_or_else(|| panic!("{uci} is not a legal move in {fen}")),
+    };
+
+    TrainingDataEntry {
+        pos,
+        mv,
+        score,
+        ply: pos.ply(),
+        result,
+    }
+}
+
+/// The entries written to a binpack in memory. Panics on an entry the
+/// writer refuses.
+pub fn write_binpack(entries: &[TrainingDataEntry]) -> Vec<u8> {
+    let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+    for entry in entries {
+        writer.write_entry(entry).unwrap();
+    }
+    writer.flush_and_end();
+    writer.into_inner().unwrap()
+}
+
+/// The data of every chunk, as a reader that only knows `"BINP"` chunks
+/// sees them, Stockfish's and nnue-pytorch's among them, or the offset of
+/// the first header with another magic, where such a reader stops
+pub fn binp_chunks(data: &[u8]) -> Result<Vec<&[u8]>, usize> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let header = data.get(offset..offset + 8).ok_or(offset)?;
+        if &header[..4] != b"BINP" {
+            return Err(offset);
+        }
+
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        chunks.push(data.get(offset + 8..offset + 8 + size).ok_or(offset)?);
+        offset += 8 + size;
+    }
+
+    Ok(chunks)
+}
+
+/// Writes the games one after the other, reads them back with try_next()
+/// and checks that every entry comes back as it was written
+pub fn round_trip(games: &[Vec<TrainingDataEntry>]) -> Result<(), RoundTripError> {
+    let entries = games.iter().flatten().collect::<Vec<_>>();
+
+    let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new())
+        .map_err(|source| RoundTripError::Write { index: 0, source })?;
+
+    for (index, entry) in entries.iter().enumerate() {
+        writer
+            .write_entry(entry)
+            .map_err(|source| RoundTripError::Write { index, source })?;
+    }
+
+    writer.flush_and_end();
+    let data = writer.into_inner().map_err(|e| RoundTripError::Write {
+        index: entries.len(),
+        source: e.into(),
+    })?;
+
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(data))
+        .map_err(|source| RoundTripError::Read { index: 0, source })?;
+
+    let mut read = 0;
+    while reader.has_next() {
+        let entry = reader.try_next().map_err(|source| RoundTripError::Read {
+            index: read,
+            source,
+        })?;
+
+        if let Some(written) = entries.get(read) {
+            if !same_entry(written, &entry) {
+                return Err(RoundTripError::Mismatch {
+                    index: read,
+                    written: Box::new(**written),
+                    read: Box::new(entry),
+                });
+            }
+        }
+
+        read += 1;
+    }
+
+    if read != entries.len() {
+        return Err(RoundTripError::Count {
+            written: entries.len(),
+            read,
+        });
+    }
+
+    Ok(())
+}
+
+/// The fullmove number is not stored, the reader derives it from the ply
+fn same_entry(written: &TrainingDataEntry, read: &TrainingDataEntry) -> bool {
+    let mut written = *written;
+    written.pos.set_ply(read.pos.ply());
+    written == *read
+}
+
+/// Runs `property` for `cases` cases, each with an [`Rng`] seeded from
+/// `seed` and the case number, and panics with the seed of the first case
+/// that fails
+pub fn check<E: fmt::Display>(
+    cases: u64,
+    seed: u64,
+    mut property: impl FnMut(&mut Rng) -> Result<(), E>,
+) {
+    for case in 0..cases {
+        let case_seed = Rng::new(seed ^ case).next_u64();
+
+        if let Err(err) = property(&mut Rng::new(case_seed)) {
+            panic!("case {case} failed, run it again with Rng::new({case_seed:#x}): {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_moves(games: &[Vec<TrainingDataEntry>], mtype: MoveType) -> usize {
+        games
+            .iter()
+            .flatten()
+            .filter(|entry| entry.mv.mtype() == mtype)
+            .count()
+    }
+
+    #[test]
+    fn test_random_game_is_a_chain() {
+        check(50, 1, |rng| {
+            let game = random_game(rng, &GameOptions::default());
+
+            match game.windows(2).position(|w| !w[0].is_continuation(&w[1])) {
+                Some(idx) => Err(format!("entry {} does not continue the game", idx + 1)),
+                None => Ok(()),
+            }
+        });
+    }
+
+    #[test]
+    fn test_round_trip_random_games() {
+        check(100, 2, |rng| {
+            let games = (0..1 + rng.below(8))
+                .map(|_| random_game(rng, &GameOptions::default()))
+                .collect::<Vec<_>>();
+            round_trip(&games)
+        });
+    }
+
+    #[test]
+    fn test_round_trip_special_moves() {
+        let options = GameOptions {
+            special_moves: 1.0,
+            ..Default::default()
+        };
+
+        let mut rng = Rng::new(3);
+        let games = (0..200)
+            .map(|_| random_game(&mut rng, &options))
+            .collect::<Vec<_>>();
+
+        // the games are worth something only if they do play these
+        assert!(count_moves(&games, MoveType::Promotion) > 100);
+        assert!(count_moves(&games, MoveType::EnPassant) > 50);
+        assert!(count_moves(&games, MoveType::Castle) > 50);
+
+        round_trip(&games).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_from_positions() {
+        // promotions with and without captures, en passant and castling
+        // from the first move on
+        let fens = [
+            "r3k2r/1P4P1/8/3pP3/8/8/1p4p1/R3K2R w KQkq d6 0 1",
+            "r3k2r/1P4P1/8/8/4pP2/8/1p4p1/R3K2R b KQkq f3 0 1",
+            "n1n1k3/1P6/8/8/8/8/8/4K3 w - - 0 1",
+        ];
+
+        check(50, 4, |rng| {
+            let options = GameOptions {
+                start: Position::from_fen(fens[rng.below(3) as usize]).unwrap(),
+                start_ply: rng.below(200) as u16,
+                ..Default::default()
+            };
+            round_trip(&[random_game(rng, &options)])
+        });
+    }
+
+    #[test]
+    fn test_round_trip_extreme_scores() {
+        let options = GameOptions {
+            extreme_scores: 1.0,
+            ..Default::default()
+        };
+
+        check(50, 5, |rng| round_trip(&[random_game(rng, &options)]));
+
+        // every score and every difference between neighbouring scores
+        let mut rng = Rng::new(5);
+        let mut games = vec![random_game(&mut rng, &options)];
+        for (entry, score) in games[0]
+            .iter_mut()
+            .zip([i16::MIN, i16::MAX, i16::MIN, 0, -1])
+        {
+            entry.score = score;
+        }
+        round_trip(&games).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_max_length() {
+        // kings and rooks keep moving, a game from here rarely ends early
+        let options = GameOptions {
+            start: Position::from_fen("r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1").unwrap(),
+            max_plies: usize::MAX,
+            ..Default::default()
+        };
+
+        let game = (0..)
+            .map(|seed| random_game(&mut Rng::new(seed), &options))
+            .find(|game| game.len() == Ply::MAX.get() as usize + 1)
+            .unwrap();
+
+        assert_eq!(game.last().unwrap().ply, Ply::MAX.get());
+        round_trip(&[game.clone(), game]).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "case 0 failed")]
+    fn test_check_reports_failure() {
+        check(10, 7, |_| Err("always"));
+    }
+}
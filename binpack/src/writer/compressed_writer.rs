@@ -1,24 +1,32 @@
+use core::mem::size_of;
 use std::io::Write;
 use std::io::{self};
 use thiserror::Error;
-use core::mem::size_of;
 
 use crate::{
-    chess::{position::Position, r#move::Move},
+    annotation::{self, Annotation},
+    chess::{attacks, position::Position, r#move::Move},
     common::{
-        compressed_training_file_writer::CompressedTrainingDataFileWriter,
-        entry::PackedTrainingDataEntry, entry::TrainingDataEntry,
+        compressed_training_file_writer::{CompressedTrainingDataFileWriter, DEFAULT_BUFFER_SIZE},
+        entry::PackedTrainingDataEntry,
+        entry::TrainingDataEntry,
+        move_score_list::PackedMoveScoreList,
+        move_score_list_reader::PackedMoveScoreListReader,
+        values::ValueError,
     },
 };
 
-use super::move_score_list::PackedMoveScoreList;
-
 const KI_B: usize = 1024;
 const MI_B: usize = 1024 * KI_B;
 
 const SUGGESTED_CHUNK_SIZE: usize = MI_B;
 const MAX_MOVELIST_SIZE: usize = 10 * KI_B;
 
+/// Most bytes one ply adds to the movetext: at most 6 + 8 bits for the
+/// piece and move however crowded the board, and 4 blocks of 5 bits for the
+/// score
+const MAX_PLY_BYTES: usize = 5;
+
 #[derive(Debug, Error)]
 pub enum CompressedWriterError {
     #[error("IO error: {0}")]
@@ -27,12 +35,117 @@ pub enum CompressedWriterError {
     InvalidFormat(String),
     #[error("End of file reached")]
     EndOfFile,
+    #[error("Invalid entry: {0}")]
+    InvalidEntry(#[from] ValueError),
+    #[error("Entry {expected} was decoded as {decoded}")]
+    VerificationFailed {
+        expected: Box<TrainingDataEntry>,
+        decoded: Box<TrainingDataEntry>,
+    },
+    #[error("Entry does not continue the chain: {0}")]
+    BrokenChain(#[from] BrokenChain),
+    #[error("Chain of {plies} plies is at the movetext limit of {max} bytes")]
+    ChainTooLong { plies: u16, max: usize },
+}
+
+/// Why an entry whose position follows the move of the last entry does not
+/// continue its chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum BrokenChain {
+    #[error("result {got} is not the last result {last} negated")]
+    Result { last: i16, got: i16 },
+    #[error("ply {got} does not follow ply {last}")]
+    Ply { last: u16, got: u16 },
+    #[error("the move counters do not follow the last position")]
+    Counters,
+}
+
+/// Counts of what a writer wrote so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriterStats {
+    pub entries: u64,
+    /// Chains started, each costs a full stem
+    pub chains: u64,
+    /// Entries that follow the move of the last entry but started a new
+    /// chain, see [`BrokenChain`]. Only counted with
+    /// [`WriterOptions::count_broken_chains`] or
+    /// [`WriterOptions::strict_continuations`], 0 otherwise.
+    pub broken_chains: u64,
+    /// Entries that continue the last entry but started a new chain because
+    /// its movetext was at [`WriterOptions::max_movetext`]
+    pub split_chains: u64,
 }
 
 type Result<T> = std::result::Result<T, CompressedWriterError>;
 
+/// Options for [`CompressedTrainingDataEntryWriter::with_options`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriterOptions {
+    /// Bytes of chunks collected before they are written to the file,
+    /// larger chunks are written with a single vectored write and zero
+    /// writes every chunk right away
+    pub buffer_size: usize,
+    /// Decode every entry right after encoding it and fail with
+    /// [`CompressedWriterError::VerificationFailed`] if it does not come
+    /// back the same, a safety net when changing the codec or writing
+    /// unusual positions. The move counter is not compared, the format
+    /// derives it from the ply.
+    pub verify: bool,
+    /// Refuse an entry whose position follows the move of the last entry
+    /// but whose result, ply or move counters do not, with
+    /// [`CompressedWriterError::BrokenChain`]. Such an entry is otherwise
+    /// written as the stem of a new chain, which costs space.
+    pub strict_continuations: bool,
+    /// Count the entries [`strict_continuations`](Self::strict_continuations)
+    /// would refuse in [`WriterStats::broken_chains`]. Either option checks
+    /// every stem against the last entry, which takes a legality check, a
+    /// move and two Zobrist keys; for shuffled data, where every entry is a
+    /// stem, that slows writing down noticeably, see the `write/stems`
+    /// benches.
+    pub count_broken_chains: bool,
+    /// End every chunk with a CRC32 of its data, which readers verify; see
+    /// [`crate::checksum`]. Older readers skip the trailer.
+    pub checksums: bool,
+    /// Largest movetext of a chain in bytes, 10 KiB by default. An entry
+    /// that could take its chain past it starts a new chain, which keeps
+    /// games of any length within the buffers of the writer.
+    pub max_movetext: usize,
+    /// Refuse an entry that continues a chain already at `max_movetext`
+    /// with [`CompressedWriterError::ChainTooLong`] instead of starting a
+    /// new chain with it.
+    pub strict_chain_length: bool,
+    /// Start every chunk at a multiple of this many bytes from where the
+    /// writer started, e.g. 4096 for pages or the stripe size of an object
+    /// store, so memory maps and range requests of whole chunks are
+    /// aligned. 0 or 1, the default, writes no padding.
+    ///
+    /// The gaps are filled with padding chunks, marked by their `BINZ`
+    /// magic, which only this crate's reader and tools skip. Readers that
+    /// only know `BINP` chunks, Stockfish's and nnue-pytorch's, stop at the
+    /// first one with an invalid magic error, so an aligned file is for this
+    /// crate alone: write it again without `align` to hand it to them.
+    pub align: usize,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            verify: false,
+            strict_continuations: false,
+            count_broken_chains: false,
+            checksums: false,
+            max_movetext: MAX_MOVELIST_SIZE,
+            strict_chain_length: false,
+            align: 0,
+        }
+    }
+}
+
 /// Write Stockfish binpacks from TrainingDataEntry's
 /// to a file.
+///
+/// The writer is `Send` and `Sync` whenever `T` is.
 #[derive(Debug)]
 pub struct CompressedTrainingDataEntryWriter<T: Write> {
     output_file: Option<CompressedTrainingDataFileWriter<T>>,
@@ -41,6 +154,20 @@ pub struct CompressedTrainingDataEntryWriter<T: Write> {
     packed_size: usize,
     packed_entries: Vec<u8>,
     is_first: bool,
+    verify: bool,
+    /// Decodes the chain being written when verifying
+    verifier: Option<PackedMoveScoreListReader>,
+    strict_continuations: bool,
+    /// Checks stems for broken chains, for strict_continuations or the stats
+    check_chains: bool,
+    max_movetext: usize,
+    strict_chain_length: bool,
+    stats: WriterStats,
+    /// Encoded annotations of the entries of the chunk being collected, up
+    /// to the last annotated one
+    annotations: Vec<u8>,
+    annotated_entries: u64,
+    chunk_entries: u64,
 }
 
 impl<T: Write> CompressedTrainingDataEntryWriter<T> {
@@ -58,8 +185,57 @@ impl<T: Write> CompressedTrainingDataEntryWriter<T> {
     /// let mut writer = CompressedTrainingDataEntryWriter::new(file).unwrap();
     /// ```
     pub fn new(file: T) -> Result<Self> {
+        Self::with_options(file, WriterOptions::default())
+    }
+
+    /// Create a new CompressedTrainingDataEntryWriter which collects up to
+    /// `buffer_size` bytes of chunks before writing them to the file.
+    /// Chunks larger than that are written with a single vectored write,
+    /// a size of zero writes every chunk right away.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sfbinpack::CompressedTrainingDataEntryWriter;
+    ///
+    /// let mut writer =
+    ///     CompressedTrainingDataEntryWriter::with_buffer_size(Cursor::new(Vec::new()), 1 << 20)
+    ///         .unwrap();
+    /// ```
+    pub fn with_buffer_size(file: T, buffer_size: usize) -> Result<Self> {
+        Self::with_options(
+            file,
+            WriterOptions {
+                buffer_size,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Create a new CompressedTrainingDataEntryWriter with the given options.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sfbinpack::{CompressedTrainingDataEntryWriter, WriterOptions};
+    ///
+    /// let options = WriterOptions {
+    ///     verify: true,
+    ///     ..Default::default()
+    /// };
+    /// let mut writer =
+    ///     CompressedTrainingDataEntryWriter::with_options(Cursor::new(Vec::new()), options)
+    ///         .unwrap();
+    /// ```
+    pub fn with_options(file: T, options: WriterOptions) -> Result<Self> {
         let writer = Self {
-            output_file: Some(CompressedTrainingDataFileWriter::new(file)?),
+            output_file: Some(
+                CompressedTrainingDataFileWriter::with_buffer_size(file, options.buffer_size)?
+                    .with_checksums(options.checksums)
+                    .with_alignment(options.align),
+            ),
             last_entry: TrainingDataEntry {
                 ply: 0xFFFF, // never a continuation
                 result: 0x7FFF,
@@ -69,8 +245,26 @@ impl<T: Write> CompressedTrainingDataEntryWriter<T> {
             },
             movelist: PackedMoveScoreList::new(),
             packed_size: 0,
-            packed_entries: vec![0u8; SUGGESTED_CHUNK_SIZE + MAX_MOVELIST_SIZE],
+            // a chain is started below the suggested size and ends within
+            // the movetext limit
+            packed_entries: vec![
+                0u8;
+                SUGGESTED_CHUNK_SIZE
+                    + PackedTrainingDataEntry::byte_size()
+                    + 2
+                    + options.max_movetext
+            ],
             is_first: true,
+            verify: options.verify,
+            verifier: None,
+            strict_continuations: options.strict_continuations,
+            check_chains: options.strict_continuations || options.count_broken_chains,
+            max_movetext: options.max_movetext,
+            strict_chain_length: options.strict_chain_length,
+            stats: WriterStats::default(),
+            annotations: Vec::new(),
+            annotated_entries: 0,
+            chunk_entries: 0,
         };
         Ok(writer)
     }
@@ -79,31 +273,64 @@ impl<T: Write> CompressedTrainingDataEntryWriter<T> {
         self.output_file.take().unwrap().into_inner()
     }
 
-    /// Write a single entry to the file
+    /// Get the counts of what was written so far
+    pub fn stats(&self) -> WriterStats {
+        self.stats
+    }
+
+    /// Write a single entry to the file, an entry whose result or ply the
+    /// format cannot store is refused
     pub fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
-        let is_cont = self.last_entry.is_continuation(entry);
+        entry.validate()?;
+
+        let mut is_cont = self.last_entry.is_continuation(entry);
+
+        if !is_cont && !self.is_first && self.check_chains {
+            if let Some(broken) = self.broken_chain(entry) {
+                self.stats.broken_chains += 1;
+
+                if self.strict_continuations {
+                    return Err(broken.into());
+                }
+            }
+        }
+
+        if is_cont && self.movelist.movetext().len() + MAX_PLY_BYTES > self.max_movetext {
+            if self.strict_chain_length {
+                return Err(CompressedWriterError::ChainTooLong {
+                    plies: self.movelist.num_plies,
+                    max: self.max_movetext,
+                });
+            }
+
+            self.stats.split_chains += 1;
+            is_cont = false;
+        }
+
+        self.stats.entries += 1;
+        self.stats.chains += !is_cont as u64;
+
+        let mut decoded = None;
 
         if is_cont {
             self.movelist
                 .add_move_score(&entry.pos, entry.mv, entry.score);
+
+            if let Some(verifier) = self.verifier.as_mut() {
+                decoded = Some(verifier.next_entry(self.movelist.movetext()));
+            }
         } else {
             if !self.is_first {
                 self.write_movelist();
             }
 
             if self.packed_size >= SUGGESTED_CHUNK_SIZE {
-                match self
-                    .output_file
-                    .as_mut()
-                    .unwrap()
-                    .append(&self.packed_entries[..self.packed_size])
-                {
+                match self.append_packed() {
                     Ok(_) => {}
                     Err(e) => {
                         return Err(CompressedWriterError::Io(e));
                     }
                 }
-                self.packed_size = 0;
             }
 
             let packed = PackedTrainingDataEntry::from_entry(entry);
@@ -117,9 +344,137 @@ impl<T: Write> CompressedTrainingDataEntryWriter<T> {
 
             self.movelist.clear(entry);
             self.is_first = false;
+
+            if self.verify {
+                let stem = packed.unpack_entry();
+                // the movetext has no room for more plies than this
+                self.verifier = Some(PackedMoveScoreListReader::new(stem, u16::MAX));
+                decoded = Some(stem);
+            }
         }
 
+        // the entry is part of the output even if it does not come back
+        // the same, the writer stays usable
         self.last_entry = *entry;
+        self.chunk_entries += 1;
+
+        match decoded {
+            Some(decoded) if !same_entry(entry, &decoded) => {
+                Err(CompressedWriterError::VerificationFailed {
+                    expected: Box::new(*entry),
+                    decoded: Box::new(decoded),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write an entry like write_entry() and store `annotation` with it, see
+    /// [`crate::annotation`]. Entries written without one get an empty
+    /// annotation if another entry of their chunk has one.
+    pub fn write_annotated(
+        &mut self,
+        entry: &TrainingDataEntry,
+        annotation: &Annotation,
+    ) -> Result<()> {
+        let written = self.stats.entries;
+        let result = self.write_entry(entry);
+
+        // refused entries are not written, a failed verification is
+        if self.stats.entries > written {
+            self.pad_annotations(self.chunk_entries - 1);
+            annotation.encode(&mut self.annotations);
+            self.annotated_entries += 1;
+        }
+
+        result
+    }
+
+    /// Gives the entries written without an annotation since the last
+    /// annotated one an empty one, up to `entries` of the chunk
+    fn pad_annotations(&mut self, entries: u64) {
+        while self.annotated_entries < entries {
+            Annotation::default().encode(&mut self.annotations);
+            self.annotated_entries += 1;
+        }
+    }
+
+    /// Appends the chunk being collected, after the extension chunk of its
+    /// annotations if it has any
+    fn append_packed(&mut self) -> io::Result<()> {
+        trace_span!(
+            "write_chunk",
+            entries = self.chunk_entries,
+            bytes = self.packed_size
+        );
+        if self.annotated_entries > 0 {
+            self.pad_annotations(self.chunk_entries);
+        }
+
+        let file = self.output_file.as_mut().unwrap();
+        if self.annotated_entries > 0 {
+            file.append_annotations(&annotation::encode_chunk(
+                self.annotated_entries,
+                &self.annotations,
+            ))?;
+        }
+        file.append(&self.packed_entries[..self.packed_size])?;
+
+        self.packed_size = 0;
+        self.annotations.clear();
+        self.annotated_entries = 0;
+        self.chunk_entries = 0;
+        Ok(())
+    }
+
+    /// Write the data of a chunk as it is, e.g. copied from a reader with
+    /// [`chunk_data`](crate::CompressedTrainingDataEntryReader::chunk_data),
+    /// instead of encoding its entries again. The chunk being collected is
+    /// written first, the next entry starts a new chain. `entries` and
+    /// `chains` are what the chunk holds, for the stats; the data is not
+    /// checked or verified.
+    pub fn write_chunk(&mut self, data: &[u8], entries: u64, chains: u64) -> Result<()> {
+        self.end_packed()?;
+        trace_span!("write_chunk", entries, bytes = data.len(), copied = true);
+        self.output_file.as_mut().unwrap().append(data)?;
+
+        self.stats.entries += entries;
+        self.stats.chains += chains;
+
+        Ok(())
+    }
+
+    /// Write the entries collected so far as a chunk and flush the file, so
+    /// that everything written so far is in the file and ends on a chunk
+    /// boundary, e.g. before recording how far a long job got. The next
+    /// entry starts a new chain.
+    pub fn end_chunk(&mut self) -> Result<()> {
+        self.end_packed()?;
+        self.output_file.as_mut().unwrap().flush()?;
+        Ok(())
+    }
+
+    /// Writes the chunk being collected, if any, and starts over with an
+    /// empty one
+    fn end_packed(&mut self) -> Result<()> {
+        if self.packed_size > 0 {
+            if !self.is_first {
+                self.write_movelist();
+            }
+
+            self.append_packed()?;
+        }
+
+        self.is_first = true;
+        self.verifier = None;
+        self.last_entry = TrainingDataEntry {
+            ply: 0xFFFF,
+            result: 0x7FFF,
+            pos: Position::default(),
+            mv: Move::default(),
+            score: 0,
+        };
+
         Ok(())
     }
 
@@ -140,18 +495,12 @@ impl<T: Write> CompressedTrainingDataEntryWriter<T> {
                 self.write_movelist();
             }
 
-            match self
-                .output_file
-                .as_mut()
-                .unwrap()
-                .append(&self.packed_entries[..self.packed_size])
-            {
+            match self.append_packed() {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(CompressedWriterError::Io(e));
                 }
             }
-            self.packed_size = 0;
         }
 
         if let Some(file) = self.output_file.as_mut() {
@@ -161,6 +510,36 @@ impl<T: Write> CompressedTrainingDataEntryWriter<T> {
         Ok(())
     }
 
+    /// Why `entry` does not continue the last chain although its position
+    /// follows the last move, None if it does not follow it
+    fn broken_chain(&self, entry: &TrainingDataEntry) -> Option<BrokenChain> {
+        let last = &self.last_entry;
+
+        // whatever the last entry holds, only a legal move is played
+        if last.pos.check_valid().is_err() || !attacks::is_legal(&last.pos, last.mv) {
+            return None;
+        }
+
+        let expected = last.pos.after_move(last.mv);
+        if expected.zobrist_key() != entry.pos.zobrist_key() {
+            return None;
+        }
+
+        if last.result != -entry.result {
+            Some(BrokenChain::Result {
+                last: last.result,
+                got: entry.result,
+            })
+        } else if last.ply.wrapping_add(1) != entry.ply {
+            Some(BrokenChain::Ply {
+                last: last.ply,
+                got: entry.ply,
+            })
+        } else {
+            Some(BrokenChain::Counters)
+        }
+    }
+
     fn write_movelist(&mut self) {
         self.packed_entries[self.packed_size] = (self.movelist.num_plies >> 8) as u8;
         self.packed_entries[self.packed_size + 1] = self.movelist.num_plies as u8;
@@ -183,6 +562,14 @@ impl<T: Write> Drop for CompressedTrainingDataEntryWriter<T> {
     }
 }
 
+/// Compares everything the format stores, the move counter is derived from
+/// the ply
+fn same_entry(source: &TrainingDataEntry, decoded: &TrainingDataEntry) -> bool {
+    let mut source = *source;
+    source.pos.set_ply(decoded.pos.ply());
+    source == *decoded
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -198,10 +585,11 @@ mod tests {
         position::Position,
         r#move::{Move, MoveType},
     };
+    use crate::testing::game_from_uci;
 
     #[test]
     fn test_compressed_writer() {
-        let entries = vec![
+        let entries = [
             TrainingDataEntry {
                 pos: Position::from_fen("1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 0 35")
                     .unwrap(),
@@ -251,6 +639,7 @@ mod tests {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .append(false)
                 .open("test/ep_new1.binpack")
                 .unwrap();
@@ -273,7 +662,7 @@ mod tests {
 
     #[test]
     fn test_compressed_writer_in_memory_file() {
-        let entries = vec![
+        let entries = [
             TrainingDataEntry {
                 pos: Position::from_fen("1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 0 35")
                     .unwrap(),
@@ -338,7 +727,7 @@ mod tests {
 
     #[test]
     fn test_compressed_writer_big_score_diff() {
-        let entries = vec![
+        let entries = [
             TrainingDataEntry {
                 pos: Position::from_fen("1q5b/1r5k/4p2p/1b2P1pN/3p4/6PP/1nP3B1/1Q2B1K1 w - - 0 35")
                     .unwrap(),
@@ -388,4 +777,316 @@ mod tests {
         ];
         assert_eq!(read_bytes, expected_bytes);
     }
+
+    #[test]
+    fn test_compressed_writer_refuses_unrepresentable_entries() {
+        use crate::{GameResult, Ply, Score};
+
+        let pos = Position::new();
+        let mv = crate::chess::attacks::legal_moves(&pos)[0];
+        let valid = TrainingDataEntry::new(pos, mv, Score::new(20), Ply::MAX, GameResult::Loss);
+
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+
+        let bad_result = TrainingDataEntry { result: 2, ..valid };
+        assert!(matches!(
+            writer.write_entry(&bad_result),
+            Err(CompressedWriterError::InvalidEntry(ValueError::Result(2)))
+        ));
+
+        let bad_ply = TrainingDataEntry {
+            ply: 0x4000,
+            ..valid
+        };
+        assert!(matches!(
+            writer.write_entry(&bad_ply),
+            Err(CompressedWriterError::InvalidEntry(ValueError::Ply(0x4000)))
+        ));
+
+        // nothing was written for them
+        writer.write_entry(&valid).unwrap();
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        let mut reader = crate::CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        // the position takes its move counter from the ply
+        let read = reader.next();
+        assert_eq!(
+            (read.mv, read.score, read.ply, read.result),
+            (valid.mv, valid.score, valid.ply, valid.result)
+        );
+        assert!(!reader.has_next());
+    }
+
+    #[test]
+    fn test_compressed_writer_large_rule50_counter() {
+        // a chain that starts below 256 and runs past it
+        let mut pos = Position::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 250 300").unwrap();
+        let mut entries = Vec::new();
+        for (ply, uci) in ["a1a2", "e8d8", "a2a1", "d8e8"]
+            .iter()
+            .cycle()
+            .take(12)
+            .enumerate()
+        {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            entries.push(TrainingDataEntry {
+                pos,
+                mv,
+                score: 0,
+                ply: pos.ply(),
+                result: if ply % 2 == 0 { 1 } else { -1 },
+            });
+            pos.do_move(mv);
+        }
+
+        let mut writer = verifying_writer();
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+
+        let mut reader = crate::CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        for entry in &entries {
+            assert_eq!(reader.next(), *entry);
+        }
+        assert_eq!(entries.last().unwrap().pos.rule50_counter(), 261);
+        assert!(!reader.has_next());
+    }
+
+    #[test]
+    fn test_compressed_writer_stats() {
+        let moves = ["e2e4", "e7e5", "g1f3", "b8c6"];
+        let mut broken = game_from_uci(&moves);
+        broken[2].result = 0;
+        broken[3].result = 0;
+
+        for count_broken_chains in [false, true] {
+            let options = WriterOptions {
+                count_broken_chains,
+                ..Default::default()
+            };
+            let mut writer =
+                CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+            for entry in game_from_uci(&moves).iter().chain(&broken) {
+                writer.write_entry(entry).unwrap();
+            }
+
+            // the second game breaks at its third entry, and goes on from there
+            assert_eq!(
+                writer.stats(),
+                WriterStats {
+                    entries: 8,
+                    chains: 3,
+                    broken_chains: count_broken_chains as u64,
+                    split_chains: 0
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_compressed_writer_strict_continuations() {
+        let options = WriterOptions {
+            strict_continuations: true,
+            ..Default::default()
+        };
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+
+        let entries = game_from_uci(&["e2e4", "e7e5", "g1f3"]);
+        writer.write_entry(&entries[0]).unwrap();
+
+        let mut wrong_result = entries[1];
+        wrong_result.result = 1;
+        let mut wrong_ply = entries[1];
+        wrong_ply.ply = 5;
+        let mut wrong_counters = entries[1];
+        wrong_counters.pos.set_rule50_counter(7);
+
+        for (entry, expected) in [
+            (wrong_result, BrokenChain::Result { last: 1, got: 1 }),
+            (wrong_ply, BrokenChain::Ply { last: 0, got: 5 }),
+            (wrong_counters, BrokenChain::Counters),
+        ] {
+            assert!(matches!(
+                writer.write_entry(&entry),
+                Err(CompressedWriterError::BrokenChain(broken)) if broken == expected
+            ));
+        }
+
+        // refused entries are not written, the chain goes on
+        writer.write_entry(&entries[1]).unwrap();
+        writer.write_entry(&entries[2]).unwrap();
+
+        // a new game is not a broken chain
+        writer.write_entry(&game_from_uci(&["d2d4"])[0]).unwrap();
+
+        assert_eq!(
+            writer.stats(),
+            WriterStats {
+                entries: 4,
+                chains: 2,
+                broken_chains: 3,
+                split_chains: 0
+            }
+        );
+    }
+
+    /// Rooks shuffling back and forth for `plies` plies, with scores that
+    /// swing by the most the movetext encodes, so every ply takes many bits
+    fn long_game(plies: usize) -> Vec<TrainingDataEntry> {
+        let mut pos = Position::from_fen("r3k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let mut entries = Vec::new();
+        for (ply, uci) in ["a1a2", "a8a7", "a2a1", "a7a8"]
+            .iter()
+            .cycle()
+            .take(plies)
+            .enumerate()
+        {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            entries.push(TrainingDataEntry {
+                pos,
+                mv,
+                score: 16000,
+                ply: pos.ply(),
+                result: if ply % 2 == 0 { 1 } else { -1 },
+            });
+            pos.do_move(mv);
+        }
+        entries
+    }
+
+    #[test]
+    fn test_compressed_writer_splits_long_games() {
+        let entries = long_game(4000);
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        let stats = writer.stats();
+        assert_eq!((stats.chains, stats.split_chains), (2, 1));
+        assert_eq!(stats.broken_chains, 0);
+
+        writer.flush_and_end();
+        let data = writer.into_inner().unwrap();
+        let mut reader = crate::CompressedTrainingDataEntryReader::new(Cursor::new(data)).unwrap();
+        for entry in &entries {
+            assert!(same_entry(entry, &reader.next()));
+        }
+        assert!(!reader.has_next());
+    }
+
+    #[test]
+    fn test_compressed_writer_strict_chain_length() {
+        let options = WriterOptions {
+            max_movetext: 64,
+            strict_chain_length: true,
+            ..Default::default()
+        };
+        let mut writer =
+            CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap();
+
+        let entries = long_game(100);
+        let written = entries
+            .iter()
+            .take_while(|entry| writer.write_entry(entry).is_ok())
+            .count();
+        assert!(written > 1 && written < entries.len());
+        assert!(matches!(
+            writer.write_entry(&entries[written]),
+            Err(CompressedWriterError::ChainTooLong { plies, max: 64 }) if plies as usize == written - 1
+        ));
+
+        // the writer stays usable, a new game starts a new chain
+        writer.write_entry(&game_from_uci(&["e2e4"])[0]).unwrap();
+        assert_eq!(writer.stats().chains, 2);
+        assert_eq!(writer.stats().split_chains, 0);
+    }
+
+    fn verifying_writer() -> CompressedTrainingDataEntryWriter<Vec<u8>> {
+        let options = WriterOptions {
+            verify: true,
+            ..Default::default()
+        };
+        CompressedTrainingDataEntryWriter::with_options(Vec::new(), options).unwrap()
+    }
+
+    #[test]
+    fn test_compressed_writer_verify() {
+        use crate::chess::attacks;
+
+        let mut writer = verifying_writer();
+
+        let file = fs::File::open("./test/ep1.binpack").unwrap();
+        let mut reader = crate::CompressedTrainingDataEntryReader::new(file).unwrap();
+        while reader.has_next() {
+            writer.write_entry(&reader.next()).unwrap();
+        }
+
+        // long games with captures, castling and promotions, and a move
+        // counter that does not match the ply
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        for _ in 0..20 {
+            let mut pos = Position::from_fen(
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 40",
+            )
+            .unwrap();
+            for ply in 0..200 {
+                let moves = attacks::legal_moves(&pos);
+                if moves.is_empty() {
+                    break;
+                }
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+
+                let mv = moves[seed as usize % moves.len()];
+                let entry = TrainingDataEntry {
+                    pos,
+                    mv,
+                    score: (seed >> 32) as i16,
+                    ply,
+                    result: if ply % 2 == 0 { 1 } else { -1 },
+                };
+                writer.write_entry(&entry).unwrap();
+                pos.do_move(mv);
+            }
+        }
+
+        writer.flush_and_end();
+    }
+
+    #[test]
+    fn test_compressed_writer_verify_mismatch() {
+        // castling rights without a rook to castle with cannot be stored,
+        // the compressed position marks the rooks that may castle
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w KQ - 0 1").unwrap();
+        let entry = TrainingDataEntry {
+            pos,
+            mv: Move::new(
+                Square::new(4),
+                Square::new(3),
+                MoveType::Normal,
+                Piece::none(),
+            ),
+            score: 0,
+            ply: 0,
+            result: 0,
+        };
+
+        let mut writer = verifying_writer();
+        match writer.write_entry(&entry) {
+            Err(CompressedWriterError::VerificationFailed { expected, decoded }) => {
+                assert_eq!(*expected, entry);
+                assert_ne!(decoded.pos, entry.pos);
+            }
+            other => panic!("expected a verification failure, got {:?}", other),
+        }
+
+        // without verification the entry is written as it decodes
+        let mut writer = CompressedTrainingDataEntryWriter::new(Vec::new()).unwrap();
+        writer.write_entry(&entry).unwrap();
+    }
 }
@@ -1,8 +1,19 @@
 #![allow(dead_code)]
 
-mod bitwriter;
 mod compressed_writer;
-mod move_score_list;
 
+pub use compressed_writer::BrokenChain;
 pub use compressed_writer::CompressedTrainingDataEntryWriter;
 pub use compressed_writer::CompressedWriterError;
+pub use compressed_writer::WriterOptions;
+pub use compressed_writer::WriterStats;
+
+// Same for the writer, it only holds its buffers and the file
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<CompressedTrainingDataEntryWriter<std::fs::File>>();
+    assert_send_sync::<CompressedTrainingDataEntryWriter<Vec<u8>>>();
+    assert_send_sync::<crate::common::move_score_list::PackedMoveScoreList>();
+    assert_send_sync::<CompressedWriterError>();
+};
@@ -91,9 +91,8 @@ class BatchLoader:
             self._feature_set_name,
             self._files,
             self._batch_size,
-            None,  # skip_config
-            False,  # cyclic
-            1,  # num_workers
+            cyclic=False,
+            num_workers=1,
         )
 
     def read_batch(self, device: torch.device) -> tuple[bool, Batch]: